@@ -0,0 +1,223 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Search the programmes of XMLTV listings from the command line, for scripting
+//! recordings with me-tv-schedule as much as for seeing what is on.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::process;
+
+use clap::{Arg, App};
+use chrono::{Duration, Local, NaiveDateTime, TimeZone, Utc};
+use serde_derive::Serialize;
+use xdg;
+
+use me_tv::epg::Programme;
+use me_tv::epg_search::{search, Query};
+use me_tv::genres::Category;
+use me_tv::name_matching::fold;
+use me_tv::xmltv::{programmes_by_service, read_xmltv, Listings};
+
+/// A programme found, as output as JSON. Times are local, in the form me-tv-schedule takes.
+#[derive(Debug, Serialize)]
+struct Found {
+    channel: String,
+    start: Option<String>,
+    end: Option<String>,
+    duration: u32,  // Minutes.
+    title: String,
+    description: String,
+    genres: Vec<String>,
+}
+
+impl Found {
+    fn new(channel: &str, programme: &Programme) -> Found {
+        let local = |time: NaiveDateTime| Local.from_utc_datetime(&time).format("%Y-%m-%dT%H:%M").to_string();
+        Found {
+            channel: channel.to_string(),
+            start: programme.start.map(local),
+            end: programme.end().map(local),
+            duration: (programme.duration_seconds + 59) / 60,
+            title: programme.title.clone(),
+            description: programme.description.clone(),
+            genres: programme.genres.clone(),
+        }
+    }
+}
+
+/// The XMLTV listings file of the Me TV preferences, if one is set.
+fn preferences_xmltv_file() -> Option<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").ok()?;
+    let file = File::open(xdg_dirs.get_config_home().join("preferences.yml")).ok()?;
+    let preferences: serde_yaml::Value = serde_yaml::from_reader(file).ok()?;
+    preferences.get("xmltv_file")?.as_str().filter(|path| !path.is_empty()).map(PathBuf::from)
+}
+
+/// A number for each channel of `listings` to search by, and the name of each channel by
+/// number, the first display name or else the id. Only the channels with `names` as a
+/// display name, ignoring case and accents, are numbered, unless `names` is empty.
+fn number_channels(listings: &Listings, names: &[&str]) -> (HashMap<String, u16>, HashMap<u16, String>) {
+    let names = names.iter().map(|name| fold(name)).collect::<Vec<_>>();
+    let mut numbers = HashMap::new();
+    let mut channels = HashMap::new();
+    for (number, channel) in listings.channels.iter().enumerate() {
+        if names.is_empty() || channel.display_names.iter().any(|display_name| names.contains(&fold(display_name))) {
+            numbers.insert(channel.id.clone(), number as u16);
+            channels.insert(number as u16, channel.display_names.first().unwrap_or(&channel.id).clone());
+        }
+    }
+    (numbers, channels)
+}
+
+fn main() {
+    let matches = App::new("me-tv-epg")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("Russel Winder <russel@winder.org.uk>")
+        .about("Search the programmes of XMLTV listings.
+
+The titles and descriptions of the programmes on in the next few days are
+searched for the text given, ignoring case and accents. The listings file is
+that of the Me TV preferences unless one is given.
+")
+        .arg(Arg::with_name("search")
+            .short("s")
+            .long("search")
+            .value_name("TEXT")
+            .help("Sets the text to look for in the titles and descriptions, everything is found if it is empty.")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("whole_words")
+            .short("w")
+            .long("whole-words")
+            .help("Only finds the text as whole words."))
+        .arg(Arg::with_name("channel")
+            .short("c")
+            .long("channel")
+            .value_name("CHANNEL")
+            .help("Only searches the channel with this display name, can be given more than once.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("genre")
+            .short("g")
+            .long("genre")
+            .value_name("GENRE")
+            .help("Only finds programmes of this genre, as the listings categorise them.")
+            .takes_value(true))
+        .arg(Arg::with_name("category")
+            .long("category")
+            .value_name("CATEGORY")
+            .help("Only finds programmes of this category of genres, e.g. Sports or Movie/Drama.")
+            .takes_value(true))
+        .arg(Arg::with_name("days")
+            .short("d")
+            .long("days")
+            .value_name("DAYS")
+            .help("Sets how many days ahead to search.")
+            .takes_value(true)
+            .default_value("7"))
+        .arg(Arg::with_name("xmltv")
+            .short("x")
+            .long("xmltv")
+            .value_name("PATH")
+            .help("Sets the XMLTV listings file to search.")
+            .takes_value(true))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Output the programmes found as a JSON array."))
+        .get_matches();
+    let path = match matches.value_of("xmltv").map(PathBuf::from).or_else(preferences_xmltv_file) {
+        Some(path) => path,
+        None => {
+            eprintln!("No XMLTV listings file given, and none in the Me TV preferences.");
+            process::exit(exitcode::USAGE);
+        },
+    };
+    let listings = match read_xmltv(&path) {
+        Ok(listings) => listings,
+        Err(e) => {
+            eprintln!("Could not read the XMLTV listings file {}: {}", path.display(), e);
+            process::exit(exitcode::NOINPUT);
+        },
+    };
+    let days = matches.value_of("days").unwrap().parse::<i64>().expect("Couldn't parse the number of days as an integer.");
+    let (numbers, channels) = number_channels(&listings, &matches.values_of("channel").map(|names| names.collect::<Vec<_>>()).unwrap_or_default());
+    let by_channel = programmes_by_service(&listings, &numbers);
+    let category = matches.value_of("category").map(|name| {
+        Category::ALL.iter().copied().find(|category| fold(category.name()) == fold(name)).unwrap_or_else(|| {
+            let names = Category::ALL.iter().map(|category| category.name()).collect::<Vec<_>>();
+            eprintln!("There is no category {}, the categories are: {}.", name, names.join(", "));
+            process::exit(exitcode::USAGE);
+        })
+    });
+    let now = Utc::now().naive_utc();
+    let query = Query {
+        text: matches.value_of("search").unwrap().to_string(),
+        is_whole_words: matches.is_present("whole_words"),
+        service_ids: None,
+        genre: matches.value_of("genre").map(String::from),
+        category,
+        from: Some(now),
+        to: Some(now + Duration::days(days)),
+    };
+    let found = search(by_channel.iter().flat_map(|(number, programmes)| programmes.iter().map(move |programme| (*number, programme))), &query)
+        .iter()
+        .map(|(number, programme)| Found::new(&channels[number], programme))
+        .collect::<Vec<_>>();
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&found).expect("Could not serialise the programmes found."));
+    } else {
+        for programme in found {
+            println!(
+                "{}–{}  {}  {}",
+                programme.start.unwrap_or_default(), programme.end.map(|end| end[11..].to_string()).unwrap_or_default(), programme.channel, programme.title,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use me_tv::xmltv::parse_xmltv;
+
+    const LISTINGS: &str = r#"<tv>
+  <channel id="bbc1.freeview.uk"><display-name>BBC One</display-name></channel>
+  <channel id="bbc2.freeview.uk"><display-name>BBC Two</display-name><display-name>102</display-name></channel>
+  <channel id="film4.freeview.uk"/>
+</tv>"#;
+
+    #[test]
+    fn channels_are_numbered_and_named() {
+        let listings = parse_xmltv(LISTINGS).unwrap();
+        let (numbers, channels) = number_channels(&listings, &[]);
+        assert_eq!(numbers.len(), 3);
+        assert_eq!(channels[&numbers["bbc2.freeview.uk"]], "BBC Two");
+        assert_eq!(channels[&numbers["film4.freeview.uk"]], "film4.freeview.uk");
+        let (numbers, channels) = number_channels(&listings, &["bbc two", "bbc one"]);
+        assert_eq!(numbers.len(), 2);
+        assert!(!numbers.contains_key("film4.freeview.uk"));
+        assert_eq!(channels.len(), 2);
+    }
+}
@@ -19,223 +19,2991 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::{thread, time};
+use std::{env, fs, io, process, thread, time};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
 
-use clap::{Arg, App};
+use clap::{Arg, ArgGroup, ArgMatches, App};
+
+use dbus::blocking::Connection;
+use dbus_crossroads::Crossroads;
+
+use log::{debug, error, info, warn};
+
+use libc::SIGRTMIN;
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
 
 use gst::{gst_element_error, gst_element_warning};
 use gst::prelude::*;
 
-fn main() {
-    let matches = App::new("me-tv-record")
-        .version(env!("CARGO_PKG_VERSION"))
-        .author("Russel Winder <russel@winder.org.uk>")
-        .about("Record a channel from now for a duration to create an MPEG4 file.
+use me_tv::channels_file::{
+    are_same_frequency, channels_file_path, import_channels, preferred_copies_file_path, read_channel_names, read_channels, read_delivery_system, read_is_radio,
+    read_scan, read_service_id, read_vdr, Channel, Channels, Conflicts, Import, TuningParameters, CHANNELS_FILE_VARIABLE, DELIVERY_SYSTEMS, MODULATIONS,
+};
+use me_tv::chapters::{write_chapters, ChapterMarks};
+use me_tv::desktop_notification::{recording_notification_body, send_notification};
+use me_tv::device_history::{history_path, DeviceHistory};
+use me_tv::eit::{parse_eit_section, EitSection, RunningStatus, SectionAssembler, ACTUAL_PRESENT_FOLLOWING, EIT_PID};
+use me_tv::epg::Slippage;
+use me_tv::frontend_info::{availability, delivery_systems, inaccessibility_reason, incompatibility_reason, read_display_name, Availability, DeliverySystem};
+use me_tv::frontend_lease::{Lease, Purpose, Reservations};
+use me_tv::frontend_lock::{lock_directory, FrontendLock, LockHolder};
+use me_tv::frontends::{installed_frontends, set_dvb_devices, DvbDevices, FrontendId};
+use me_tv::handover::{acquire_frontend_from_gui, Handover};
+use me_tv::logging::init_logging;
+use me_tv::multiplex_signals::{multiplex_signals_path, record_multiplex_signals, scanned_signals, TransponderSignal};
+use me_tv::recording_event::RecordingEvent;
+use me_tv::scan::{
+    known_transponders, read_announcements, read_initial_tuning, scan, unknown_transponders, DvbsrcNetworkReader, Dvbv5Scanner, ScanEvent, ScanResults, Transponder,
+};
+use me_tv::sd_notify::{watchdog_interval, Notifier};
+use me_tv::sidecar::{write_sidecar, RecordingMetadata, SidecarFormat};
+use me_tv::signal_monitor::{SignalMonitor, SignalStatus};
+use me_tv::thumbnail::{thumbnail_path, write_thumbnail};
+
+/// The time after a first termination signal during which a second one forces an immediate quit.
+const FORCE_QUIT_GRACE_PERIOD: time::Duration = time::Duration::from_secs(10);
+
+/// Is a signal received at `now` a request to quit without waiting for the file to be finalised?
+fn is_forced_quit(last_signal_time: Option<time::Instant>, now: time::Instant) -> bool {
+    match last_signal_time {
+        Some(t) => now.duration_since(t) < FORCE_QUIT_GRACE_PERIOD,
+        None => false,
+    }
+}
+
+/// The longest the duration thread waits before checking whether the recording is
+/// complete, it is woken early when the duration changes.
+const TIMER_POLL_INTERVAL: time::Duration = time::Duration::from_millis(250);
+
+/// How often, at the least, a progress line is logged at info level, and a progress
+/// event and systemd status sent.
+const PROGRESS_REPORT_INTERVAL: time::Duration = time::Duration::from_secs(10);
+
+/// Keep track of how much of the requested duration has been recorded. Time spent paused
+/// does not count against the duration.
+#[derive(Debug)]
+struct RecordingTimer {
+    duration: time::Duration,
+    recorded_before_pause: time::Duration,
+    running_since: Option<time::Instant>,  // None when paused.
+}
+
+impl RecordingTimer {
+    fn new(duration: time::Duration, now: time::Instant) -> RecordingTimer {
+        RecordingTimer { duration, recorded_before_pause: time::Duration::from_secs(0), running_since: Some(now) }
+    }
+
+    fn is_paused(&self) -> bool { self.running_since.is_none() }
+
+    fn pause(&mut self, now: time::Instant) {
+        if let Some(t) = self.running_since.take() {
+            self.recorded_before_pause += now.duration_since(t);
+        }
+    }
+
+    fn resume(&mut self, now: time::Instant) {
+        if self.running_since.is_none() {
+            self.running_since = Some(now);
+        }
+    }
+
+    fn recorded(&self, now: time::Instant) -> time::Duration {
+        match self.running_since {
+            Some(t) => self.recorded_before_pause + now.duration_since(t),
+            None => self.recorded_before_pause,
+        }
+    }
+
+    fn remaining(&self, now: time::Instant) -> time::Duration {
+        let recorded = self.recorded(now);
+        if recorded < self.duration { self.duration - recorded } else { time::Duration::from_secs(0) }
+    }
+
+    fn extend(&mut self, by: time::Duration) {
+        self.duration += by;
+    }
+
+    fn progress_line(&self, now: time::Instant) -> String {
+        format!(
+            "Recorded {} of {}{}.",
+            format_minutes_seconds(self.recorded(now)),
+            format_minutes_seconds(self.duration),
+            if self.is_paused() { " (paused)" } else { "" },
+        )
+    }
+}
+
+/// Render a `Duration` as minutes and seconds, mm:ss.
+fn format_minutes_seconds(duration: time::Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// How long to wait for the Me TV GUI to release a frontend the recording needs.
+const HANDOVER_GRACE_PERIOD: time::Duration = time::Duration::from_secs(30);
+
+/// The element factories the recording pipeline needs.
+const REQUIRED_ELEMENT_FACTORIES: [&str; 7] = ["uridecodebin", "decodebin", "dvbbasebin", "queue", "avenc_ac3", "mp4mux", "filesink"];
+
+/// The further element factories recording video, rather than radio, needs.
+const VIDEO_ELEMENT_FACTORIES: [&str; 1] = ["x264enc"];
+
+/// The further element factories a network stream needs.
+const STREAM_ELEMENT_FACTORIES: [&str; 5] = ["tee", "mpegtsmux", "rtpmp2tpay", "udpsink", "tcpserversink"];
+
+/// The further element factories HLS output needs.
+const HLS_ELEMENT_FACTORIES: [&str; 2] = ["tee", "hlssink2"];
+
+/// The further element factories streaming or HLS output of video needs.
+const PARSED_VIDEO_ELEMENT_FACTORIES: [&str; 1] = ["h264parse"];
+
+/// Return a gst-launch style description of the pipeline that would be built. For a
+/// radio channel there is only sound to encode.
+fn pipeline_description(channel: &str, tuning: &Option<TuningParameters>, adapter: u8, frontend: u8, outputs: &Outputs, is_radio: bool) -> String {
+    let source = match tuning {
+        None => format!("uridecodebin uri=\"dvb://{}\" source::adapter={} source::frontend={} name=d", channel, adapter, frontend),
+        Some(tuning) => {
+            let mut source = format!("dvbbasebin adapter={} frontend={} delsys={} frequency={}", adapter, frontend, tuning.delsys_nick(), tuning.frequency);
+            if let Some(bandwidth_hz) = tuning.bandwidth_hz {
+                source.push_str(&format!(" bandwidth-hz={}", bandwidth_hz));
+            }
+            if let Some(modulation) = tuning.modulation_nick() {
+                source.push_str(&format!(" modulation=\"{}\"", modulation));
+            }
+            source.push_str(&format!(" program-numbers={} ! decodebin name=d", tuning.service_id));
+            source
+        },
+    };
+    // Each branch is the description of its muxer and sink, the name of the muxer, and
+    // whether the video needs parsing.
+    let mut branches = Vec::new();
+    if let Some(output_path) = &outputs.file {
+        branches.push((format!("mp4mux name=m ! filesink location=\"{}\"", output_path), "m", false));
+    }
+    if let Some(target) = &outputs.stream {
+        branches.push((format!("mpegtsmux name=t ! {}", target.sink_description()), "t", true));
+    }
+    if let Some(hls) = &outputs.hls {
+        branches.push((hls.sink_description(), "h", true));
+    }
+    if is_radio {
+        let audio = if branches.len() == 1 {
+            branches[0].0.clone()
+        } else {
+            let audio = branches.iter().map(|(muxer, _, _)| format!("queue ! {}", muxer)).collect::<Vec<_>>().join(" at. ! ");
+            format!("tee name=at ! {}", audio)
+        };
+        return format!("{} ! queue ! avenc_ac3 ! {}", source, audio);
+    }
+    let parser = |needs_parsed_video| if needs_parsed_video { "h264parse config-interval=-1 ! " } else { "" };
+    let (video, audio) = if branches.len() == 1 {
+        let (muxer, name, needs_parsed_video) = &branches[0];
+        (format!("{}{}", parser(*needs_parsed_video), muxer), format!("{}.", name))
+    } else {
+        let video = branches.iter()
+            .map(|(muxer, _, needs_parsed_video)| format!("queue ! {}{}", parser(*needs_parsed_video), muxer))
+            .collect::<Vec<_>>()
+            .join(" vt. ! ");
+        let audio = branches.iter().map(|(_, name, _)| format!("queue ! {}.", name)).collect::<Vec<_>>().join(" at. ! ");
+        (format!("tee name=vt ! {}", video), format!("tee name=at ! {}", audio))
+    };
+    format!("{} ! queue ! x264enc ! {} d. ! queue ! avenc_ac3 ! {}", source, video, audio)
+}
+
+/// Parse a comma separated list of adapter numbers.
+fn parse_adapter_list(value: &str) -> Option<Vec<u8>> {
+    value.split(',').map(|a| a.trim().parse::<u8>().ok()).collect()
+}
+
+/// Validate a command line value as a list of adapter numbers, or auto.
+fn is_adapter_list(value: String) -> Result<(), String> {
+    if value == "auto" { return Ok(()); }
+    parse_adapter_list(&value).map(|_| ()).ok_or_else(|| format!("'{}' is not a comma separated list of adapter numbers or auto.", value))
+}
+
+/// Order the frontends for `--adapter auto` so that those not in use by another process
+/// are tried first. Frontends in use are still tried, last, as they may be freed.
+fn order_by_availability<T: Clone>(frontends: &[(T, Availability)]) -> Vec<T> {
+    let mut ordered = frontends.to_vec();
+    ordered.sort_by_key(|(_, availability)| *availability == Availability::InUse);
+    ordered.into_iter().map(|(frontend, _)| frontend).collect()
+}
+
+/// The frontends to try for `--adapter auto`: all those installed, or only those with
+/// frontend number `frontend` if given.
+fn auto_frontends(frontend: Option<u8>) -> Vec<FrontendId> {
+    let frontends = installed_frontends().into_iter()
+        .filter(|fei| frontend.map_or(true, |frontend| fei.frontend == frontend))
+        .map(|fei| { let availability = availability(&fei); (fei, availability) })
+        .collect::<Vec<_>>();
+    order_by_availability(&frontends)
+}
+
+/// Split the frontends into those that can tune a channel using delivery system
+/// `required` and the reasons the others cannot, according to `capabilities`. All can if
+/// the delivery system is not known.
+fn split_by_compatibility(
+    frontends: Vec<FrontendId>,
+    required: Option<DeliverySystem>,
+    capabilities: &HashMap<FrontendId, Vec<DeliverySystem>>,
+) -> (Vec<FrontendId>, Vec<String>) {
+    let mut compatible = Vec::new();
+    let mut reasons = Vec::new();
+    for fei in frontends {
+        match required.and_then(|required| incompatibility_reason(&fei, required, capabilities)) {
+            Some(reason) => reasons.push(reason),
+            None => compatible.push(fei),
+        }
+    }
+    (compatible, reasons)
+}
+
+/// The display name of a frontend for the log, from those read before recording
+/// started, the adapter and frontend numbers if it could not be read.
+fn frontend_name(display_names: &HashMap<FrontendId, String>, fei: &FrontendId) -> String {
+    display_names.get(fei).cloned().unwrap_or_else(|| fei.to_string())
+}
+
+/// Render a list of frontends for the log.
+fn frontend_list(frontends: &[FrontendId], display_names: &HashMap<FrontendId, String>) -> String {
+    frontends.iter().map(|fei| frontend_name(display_names, fei)).collect::<Vec<_>>().join(", ")
+}
+
+/// The lines describing the frontends appearing and disappearing since `since`, for
+/// the log when a recording fails.
+fn device_history_report(history: &DeviceHistory, since: chrono::DateTime<chrono::Local>) -> Vec<String> {
+    let events = history.since(since);
+    if events.is_empty() {
+        vec!["No frontends appeared or disappeared during the recording.".to_string()]
+    } else {
+        let mut lines = vec!["Frontends appearing and disappearing during the recording:".to_string()];
+        lines.extend(events.iter().map(|event| format!("    {}", event.line())));
+        lines
+    }
+}
+
+/// Log the device history the GUI keeps, so that a tuner dropping out can be seen as
+/// the cause of a failed recording. Without the GUI running there is no history.
+fn log_device_history(since: chrono::DateTime<chrono::Local>) {
+    let path = history_path();
+    if !path.exists() {
+        debug!("There is no device history, {} does not exist.", path.display());
+        return;
+    }
+    match DeviceHistory::read(&path) {
+        Ok(history) => for line in device_history_report(&history, since) {
+            error!("{}", line);
+        },
+        Err(e) => debug!("Could not read the device history {}: {}", path.display(), e),
+    }
+}
+
+/// The lines saying what importing channels into `channels_file` did.
+fn import_report(import: &Import, channels_file: &Path) -> Vec<String> {
+    let mut lines = vec![format!("{} channels added to {}, {} updated, {} skipped.", import.added.len(), channels_file.display(), import.updated.len(), import.skipped.len())];
+    lines.extend(import.added.iter().map(|name| format!("'{}' is added.", name)));
+    lines.extend(import.updated.iter().map(|name| format!("'{}' is now tuned as the imported channel is.", name)));
+    lines.extend(import.skipped.iter().map(|name| format!("'{}' is already in the channels file, it is left as it is.", name)));
+    lines.extend(import.already_present.iter().map(|name| format!("'{}' is already the name of another channel in the channels file, it is not added.", name)));
+    lines.extend(import.not_zap.iter().map(|name| format!("'{}' cannot be given in the zap format of the channels file, it is not added.", name)));
+    lines
+}
+
+/// Add the channels read from the file at `path` to the channels file, those that are
+/// already in it dealt with as `conflicts` says, and exit.
+fn import(path: &Path, channels: io::Result<Channels>, conflicts: Conflicts) -> ! {
+    let channels = match channels {
+        Ok(channels) => channels,
+        Err(e) => {
+            error!("Could not read {}: {}", path.display(), e);
+            process::exit(exitcode::NOINPUT);
+        },
+    };
+    for warning in &channels.warnings {
+        warn!("{}: {}, the channel is not added.", path.display(), warning);
+    }
+    let channels_file = channels_file_path();
+    match import_channels(&channels_file, &channels.channels, conflicts) {
+        Ok(import) => {
+            for line in import_report(&import, &channels_file) {
+                println!("{}", line);
+            }
+            process::exit(exitcode::OK);
+        },
+        Err(e) => {
+            error!("Could not write the channels file {}: {}", channels_file.display(), e);
+            process::exit(exitcode::CANTCREAT);
+        },
+    }
+}
+
+/// The end of a line of the progress of a scan giving the signal on the transponder, if
+/// it is known.
+fn signal_suffix(signal: &Option<TransponderSignal>) -> String {
+    signal.map_or(String::new(), |signal| format!(", {}", signal))
+}
+
+/// The line of the progress of a scan saying what a frontend has done, or is doing.
+fn scan_event_line(event: &ScanEvent, transponders: &[Transponder]) -> String {
+    let transponder = |index: usize| format!("{} ({} of {})", transponders[index], index + 1, transponders.len());
+    match event {
+        ScanEvent::Scanning{fei, transponder: index} => format!("{}: scanning {}", fei, transponder(*index)),
+        ScanEvent::Signal{transponder: index, status} => format!("{} whilst scanning {}", status.line().trim_end_matches('.'), transponder(*index)),
+        ScanEvent::Scanned{fei, transponder: index, services, signal} => format!("{}: {} services on {}{}", fei, services, transponder(*index), signal_suffix(signal)),
+        ScanEvent::ReadingNit{fei, transponder: index} => format!("{}: reading the NIT on {}", fei, transponder(*index)),
+        ScanEvent::ReadNit{fei, transponder: index, transport_streams} => format!("{}: the NIT on {} announces {} transport streams", fei, transponder(*index), transport_streams),
+        ScanEvent::Failed{fei, transponder: index, reason, signal} => format!("{}: nothing found on {}: {}{}", fei, transponder(*index), reason, signal_suffix(signal)),
+        ScanEvent::FrontendFailed{fei, transponder: index, reason} => format!("{}: failed, leaving {} to the other frontends: {}", fei, transponder(*index), reason),
+    }
+}
+
+/// The frontends to scan with, as --frontend-id or --adapter and --frontend give them.
+fn scanning_frontends(matches: &ArgMatches) -> Vec<FrontendId> {
+    let frontend = matches.value_of("frontend").unwrap().parse::<u8>().expect("Couldn't parse frontend value as a positive integer.");
+    match (matches.value_of("frontend_id"), matches.value_of("adapter").unwrap()) {
+        (Some(fei), _) => vec![fei.parse::<FrontendId>().unwrap()],
+        (None, "auto") => auto_frontends(Some(frontend)),
+        (None, list) => parse_adapter_list(list).unwrap().into_iter().map(|adapter| FrontendId { adapter, frontend }).collect(),
+    }
+}
+
+/// The frontends of `frontends` locked against recordings, and their locks. Exits if none
+/// of them can be locked.
+fn lock_for_scanning(frontends: Vec<FrontendId>) -> (Vec<FrontendId>, Vec<FrontendLock>) {
+    let holder = LockHolder { pid: process::id(), channel: "scanning".to_string(), end: String::new() };
+    let mut locks = Vec::new();
+    let frontends = frontends.into_iter().filter(|fei| match FrontendLock::acquire(&lock_directory(), fei, &holder) {
+        Ok(lock) => {
+            locks.push(lock);
+            true
+        },
+        Err(e) => {
+            error!("Not scanning with {}: {}", fei, e);
+            false
+        },
+    }).collect::<Vec<_>>();
+    if frontends.is_empty() {
+        error!("There are no frontends free to scan with.");
+        process::exit(exitcode::UNAVAILABLE);
+    }
+    (frontends, locks)
+}
+
+/// The flag interrupting a scan sets.
+fn scan_cancellation() -> Arc<AtomicBool> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    for signal in &[SIGINT, SIGTERM] {
+        signal_hook::flag::register(*signal, cancelled.clone()).expect("Error setting signal handlers.");
+    }
+    cancelled
+}
+
+/// Exit if the scan has been interrupted, the channels file being left as it is.
+fn exit_if_cancelled(cancelled: &AtomicBool) {
+    if cancelled.load(Ordering::SeqCst) {
+        println!("The scan was stopped, the channels file is as it was.");
+        process::exit(exitcode::TEMPFAIL);
+    }
+}
+
+/// Scan `transponders` using the locked `frontends` at the same time, printing the
+/// progress, for the channels on them.
+fn scan_transponders(transponders: &[Transponder], frontends: &[FrontendId], lnb: Option<&str>, cancelled: &Arc<AtomicBool>) -> ScanResults {
+    let (to, from) = mpsc::channel();
+    let scanning = thread::spawn({
+        let scanner = Arc::new(Dvbv5Scanner { lnb: lnb.map(String::from) });
+        let (transponders, frontends, cancelled) = (transponders.to_vec(), frontends.to_vec(), cancelled.clone());
+        move || scan(scanner, &frontends, &transponders, cancelled, to)
+    });
+    for event in from {
+        match event {
+            ScanEvent::Signal{..} => debug!("{}", scan_event_line(&event, transponders)),
+            _ => println!("{}", scan_event_line(&event, transponders)),
+        }
+    }
+    let results = scanning.join().expect("The scan failed.");
+    exit_if_cancelled(cancelled);
+    if !results.unscanned.is_empty() {
+        warn!("{} transponders were not scanned, every frontend having failed.", results.unscanned.len());
+    }
+    for line in signal_table(transponders, &results) {
+        println!("{}", line);
+    }
+    let path = multiplex_signals_path();
+    if let Err(e) = record_multiplex_signals(&path, &scanned_signals(transponders, &results.signals, chrono::Local::now())) {
+        warn!("Could not write the multiplex signals file {}: {}", path.display(), e);
+    }
+    results
+}
+
+/// The lines of the table of what a scan of `transponders` found: on each transponder the
+/// signal, and how many services there are or why there are none.
+fn signal_table(transponders: &[Transponder], results: &ScanResults) -> Vec<String> {
+    let mut lines = vec![format!("{:<24} {:<32} {}", "Multiplex", "Signal", "Services")];
+    for (index, transponder) in transponders.iter().enumerate() {
+        let signal = results.signals[index].map_or("unknown".to_string(), |signal| signal.to_string());
+        let services = match results.failed.iter().find(|(i, _)| *i == index) {
+            Some((_, reason)) => format!("none: {}", reason),
+            None if results.unscanned.contains(&index) => "not scanned".to_string(),
+            None => results.channels.iter().filter(|channel| Transponder::of(channel).is_same_as(transponder)).count().to_string(),
+        };
+        lines.push(format!("{:<24} {:<32} {}", transponder.to_string(), signal, services));
+    }
+    lines
+}
+
+/// Scan `transponders` using `frontends` at the same time, add the channels found to the
+/// channels file as importing does, and exit. Interrupting the scan stops it without
+/// changing the channels file.
+fn scan_transponders_and_import(transponders: &[Transponder], frontends: Vec<FrontendId>, lnb: Option<&str>, conflicts: Conflicts) -> ! {
+    // The locks keep recordings off the frontends until the scan is finished.
+    let (frontends, locks) = lock_for_scanning(frontends);
+    let cancelled = scan_cancellation();
+    let results = scan_transponders(transponders, &frontends, lnb, &cancelled);
+    drop(locks);
+    import(&channels_file_path(), Ok(Channels { channels: results.channels, warnings: Vec::new() }), conflicts);
+}
+
+/// Scan the transponders of the initial tuning file at `path` using `frontends` at the
+/// same time, add the channels found to the channels file as importing does, and exit.
+/// Interrupting the scan stops it without changing the channels file.
+fn scan_and_import(path: &Path, frontends: Vec<FrontendId>, lnb: Option<&str>, conflicts: Conflicts) -> ! {
+    let transponders = match read_initial_tuning(path) {
+        Ok((transponders, warnings)) => {
+            for warning in &warnings {
+                warn!("{}: {}, the transponder is not scanned.", path.display(), warning);
+            }
+            transponders
+        },
+        Err(e) => {
+            error!("Could not read {}: {}", path.display(), e);
+            process::exit(exitcode::NOINPUT);
+        },
+    };
+    if transponders.is_empty() {
+        error!("There are no transponders to scan in {}.", path.display());
+        process::exit(exitcode::DATAERR);
+    }
+    scan_transponders_and_import(&transponders, frontends, lnb, conflicts);
+}
+
+/// The channels of the channels file, there being none if there is no channels file.
+fn channels_of_channels_file() -> Vec<Channel> {
+    let path = channels_file_path();
+    match read_channels(&path) {
+        Ok(channels) => channels.channels,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            error!("Could not read the channels file {}: {}", path.display(), e);
+            process::exit(exitcode::NOINPUT);
+        },
+    }
+}
+
+/// The lines listing the multiplexes `channels` are on, and how many of them are on each.
+fn multiplex_list(channels: &[Channel]) -> Vec<String> {
+    known_transponders(channels).iter().map(|transponder| {
+        let count = channels.iter().filter(|channel| Transponder::of(channel).is_same_as(transponder)).count();
+        format!("{}: {} channels", transponder, count)
+    }).collect()
+}
+
+/// The multiplexes of `channels` at `frequency`, more than one only if there are satellite
+/// transponders of both polarisations there.
+fn multiplexes_at(frequency: u32, channels: &[Channel]) -> Result<Vec<Transponder>, String> {
+    let transponders = known_transponders(channels).into_iter()
+        .filter(|transponder| are_same_frequency(transponder.frequency, frequency))
+        .collect::<Vec<_>>();
+    if transponders.is_empty() {
+        Err(format!("There is no multiplex at {} in the channels file, --delivery-system is needed to scan it.", frequency))
+    } else {
+        Ok(transponders)
+    }
+}
+
+/// Read the NIT on the multiplexes of the channels file for the transponders the
+/// networks announce, scan only those not in the channels file using `frontends`, add the
+/// channels found to the channels file as importing does, and exit.
+fn update_and_import(frontends: Vec<FrontendId>, lnb: Option<&str>, conflicts: Conflicts) -> ! {
+    let channels = channels_of_channels_file();
+    let known = known_transponders(&channels);
+    if known.is_empty() {
+        error!("There are no multiplexes in the channels file {} to read the NIT on, do a full scan.", channels_file_path().display());
+        process::exit(exitcode::DATAERR);
+    }
+    let (frontends, locks) = lock_for_scanning(frontends);
+    let cancelled = scan_cancellation();
+    let (to, from) = mpsc::channel();
+    let reading = thread::spawn({
+        let (known, frontends, cancelled) = (known.clone(), frontends.clone(), cancelled.clone());
+        move || read_announcements(&DvbsrcNetworkReader, &frontends, &known, &cancelled, &to)
+    });
+    for event in from {
+        println!("{}", scan_event_line(&event, &known));
+    }
+    let announced = reading.join().expect("Reading the NITs failed.");
+    exit_if_cancelled(&cancelled);
+    let unknown = unknown_transponders(&channels, &announced);
+    if unknown.is_empty() {
+        println!("No transponders are announced that are not in the channels file, it is as it was.");
+        process::exit(exitcode::OK);
+    }
+    println!("{} transponders are announced that are not in the channels file: {}", unknown.len(), unknown.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", "));
+    let results = scan_transponders(&unknown, &frontends, lnb, &cancelled);
+    drop(locks);
+    import(&channels_file_path(), Ok(Channels { channels: results.channels, warnings: Vec::new() }), conflicts);
+}
+
+/// Validate a command line value as a u32.
+fn is_u32(value: String) -> Result<(), String> {
+    value.parse::<u32>().map(|_| ()).map_err(|_| format!("'{}' is not a positive integer.", value))
+}
+
+/// Validate a command line value as a u16.
+fn is_u16(value: String) -> Result<(), String> {
+    value.parse::<u16>().map(|_| ()).map_err(|_| format!("'{}' is not a positive integer less than 65536.", value))
+}
+
+/// Return the names of the required element factories that are not available.
+fn missing_element_factories(names: &[&'static str]) -> Vec<&'static str> {
+    names.iter().filter(|name| gst::ElementFactory::find(name).is_none()).cloned().collect()
+}
+
+/// Check everything that can be checked without tuning, print the pipeline, and exit.
+fn dry_run(channel: &str, tuning: &Option<TuningParameters>, adapter: u8, frontend: u8, outputs: &Outputs, is_radio: bool) -> ! {
+    let mut is_ok = true;
+    if tuning.is_none() {
+        let channels_file = channels_file_path();
+        match read_channel_names(&channels_file) {
+            Some(names) => if !names.iter().any(|name| name == channel) {
+                error!("Channel '{}' is not in {}.", channel, channels_file.display());
+                is_ok = false;
+            },
+            None => {
+                error!("Could not read the channels file {}.", channels_file.display());
+                is_ok = false;
+            },
+        }
+    }
+    let mut missing = missing_element_factories(&REQUIRED_ELEMENT_FACTORIES);
+    if outputs.stream.is_some() {
+        missing.extend(missing_element_factories(&STREAM_ELEMENT_FACTORIES));
+    }
+    if outputs.hls.is_some() {
+        missing.extend(missing_element_factories(&HLS_ELEMENT_FACTORIES));
+    }
+    if !is_radio {
+        missing.extend(missing_element_factories(&VIDEO_ELEMENT_FACTORIES));
+        if outputs.stream.is_some() || outputs.hls.is_some() {
+            missing.extend(missing_element_factories(&PARSED_VIDEO_ELEMENT_FACTORIES));
+        }
+    }
+    for name in missing {
+        error!("The GStreamer element factory {} is not available, is the plugin installed?", name);
+        is_ok = false;
+    }
+    println!("{}", pipeline_description(channel, tuning, adapter, frontend, outputs, is_radio));
+    process::exit(if is_ok { exitcode::OK } else { exitcode::UNAVAILABLE });
+}
+
+/// In EIT mode without a duration, the longest time to wait for and record the event.
+const EIT_TIME_LIMIT: time::Duration = time::Duration::from_secs(12 * 60 * 60);
+
+/// Where a recording following the EIT is in the lifecycle of the event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum EventState {
+    Waiting,
+    Recording,
+    Finished,
+}
+
+/// Follow the running status of an event in the present/following table of the actual
+/// transport stream to decide when to write to the file.
+#[derive(Debug)]
+struct EventFollower {
+    service_id: u16,
+    event_id: Option<u16>,  // None until, when following, the present event is known.
+    state: EventState,
+    scheduled_start: Option<chrono::NaiveDateTime>,  // UTC.
+    scheduled_end: Option<chrono::NaiveDateTime>,  // UTC.
+    title: Option<String>,
+    description: Option<String>,
+}
+
+impl EventFollower {
+    fn new(service_id: u16, event_id: Option<u16>) -> EventFollower {
+        EventFollower { service_id, event_id, state: EventState::Waiting, scheduled_start: None, scheduled_end: None, title: None, description: None }
+    }
+
+    fn is_writing(&self) -> bool { self.state == EventState::Recording }
+
+    /// Update from an EIT section, returning the new state if it changed.
+    ///
+    /// Section 0 of the table describes the present event and section 1 the following one.
+    /// Recording starts when the event is present and running. Broadcasters that do not
+    /// set the running status leave it undefined, so being present counts as running.
+    /// Recording finishes when the event stops running or another event is present.
+    fn update(&mut self, section: &EitSection) -> Option<EventState> {
+        if section.table_id != ACTUAL_PRESENT_FOLLOWING || section.service_id != self.service_id {
+            return None;
+        }
+        let is_present = section.section_number == 0;
+        if self.event_id.is_none() {
+            if !is_present { return None; }
+            self.event_id = Some(section.events.first()?.event_id);
+        }
+        let event = section.events.iter().find(|e| Some(e.event_id) == self.event_id);
+        if let Some(e) = event {
+            if let Some(start_time) = e.start_time {
+                self.scheduled_start = Some(start_time);
+                self.scheduled_end = Some(start_time + chrono::Duration::seconds(e.duration_seconds.into()));
+            }
+            if e.title.is_some() {
+                self.title = e.title.clone();
+                self.description = e.description.clone();
+            }
+        }
+        let new_state = match (self.state, event) {
+            (EventState::Waiting, Some(e)) if is_present => match e.running_status {
+                RunningStatus::Running | RunningStatus::Undefined => EventState::Recording,
+                _ => EventState::Waiting,
+            },
+            (EventState::Recording, Some(e)) => match e.running_status {
+                RunningStatus::NotRunning | RunningStatus::ServiceOffAir => EventState::Finished,
+                _ => EventState::Recording,
+            },
+            (EventState::Recording, None) if is_present => EventState::Finished,
+            (state, _) => state,
+        };
+        if new_state != self.state {
+            self.state = new_state;
+            Some(new_state)
+        } else {
+            None
+        }
+    }
+
+    /// How the start of the recording of the event at `now`, UTC, differs from the start
+    /// the EIT has for it, None if it is on time or the start is not known.
+    fn start_slippage(&self, now: chrono::NaiveDateTime) -> Option<Slippage> {
+        Slippage::of_start(self.scheduled_start?, now)
+    }
+
+    /// Has the event run on beyond its scheduled end by more than `max_overrun`?
+    fn is_overrun(&self, now: chrono::NaiveDateTime, max_overrun: chrono::Duration) -> bool {
+        match self.scheduled_end {
+            Some(end) => self.state == EventState::Recording && now > end + max_overrun,
+            None => false,
+        }
+    }
+}
+
+/// How long to wait for an injected EOS to reach the end of the pipeline.
+const EOS_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// How long to wait for the pipeline to reach the NULL state at the end.
+const STATE_CHANGE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// The frontend statistics dvbsrc posts on the bus as dvb-frontend-stats element
+/// messages. Using these rather than querying the frontend keeps clear of the data path.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FrontendStats {
+    signal: i32,
+    snr: i32,
+    ber: i32,
+    unc: i32,
+    lock: bool,
+}
+
+impl FrontendStats {
+    fn from_structure(structure: &gst::StructureRef) -> Option<FrontendStats> {
+        if structure.get_name() != "dvb-frontend-stats" {
+            return None;
+        }
+        let get_i32 = |name| structure.get::<i32>(name).ok().flatten().unwrap_or(0);
+        Some(FrontendStats {
+            signal: get_i32("signal"),
+            snr: get_i32("snr"),
+            ber: get_i32("ber"),
+            unc: get_i32("unc"),
+            lock: structure.get::<bool>("lock").ok().flatten().unwrap_or(false),
+        })
+    }
+
+    fn line(&self) -> String {
+        format!("Signal {}, SNR {}, BER {}, uncorrected blocks {}, {}.",
+                self.signal, self.snr, self.ber, self.unc, if self.lock { "locked" } else { "no lock" })
+    }
+}
+
+/// The state shared between the threads controlling a recording.
+#[derive(Debug)]
+struct RecordingControl {
+    timer: Mutex<RecordingTimer>,
+    timer_changed: Condvar,
+    eos_requested_at: Mutex<Option<time::Instant>>,
+    last_stream_at: Mutex<Option<time::Instant>>,  // Written, or dropped waiting for the event.
+    event_follower: Mutex<Option<EventFollower>>,
+    pipeline: Mutex<Option<gst::Pipeline>>,  // None between segments.
+    segments: Mutex<Vec<String>>,
+    segment_data_since: Mutex<Option<time::Instant>>,
+    frontend_stats: Mutex<Option<FrontendStats>>,  // The most recent.
+    chapter_marks: Mutex<Option<ChapterMarks>>,
+}
+
+impl RecordingControl {
+    fn new(duration: time::Duration) -> RecordingControl {
+        RecordingControl {
+            timer: Mutex::new(RecordingTimer::new(duration, time::Instant::now())),
+            timer_changed: Condvar::new(),
+            eos_requested_at: Mutex::new(None),
+            last_stream_at: Mutex::new(None),
+            event_follower: Mutex::new(None),
+            pipeline: Mutex::new(None),
+            segments: Mutex::new(Vec::new()),
+            segment_data_since: Mutex::new(None),
+            frontend_stats: Mutex::new(None),
+            chapter_marks: Mutex::new(None),
+        }
+    }
+
+    /// Extend the duration of the recording, waking the duration thread so that the
+    /// progress shows the new end straight away. Returns the new progress line.
+    fn extend(&self, by: time::Duration) -> String {
+        let mut timer = self.timer.lock().unwrap();
+        timer.extend(by);
+        self.timer_changed.notify_all();
+        timer.progress_line(time::Instant::now())
+    }
+
+    /// Is anything interested in the EIT?
+    fn is_watching_eit(&self) -> bool {
+        self.event_follower.lock().unwrap().is_some() || self.chapter_marks.lock().unwrap().is_some()
+    }
+
+    /// The pipeline of the segment currently being recorded, if there is one.
+    fn pipeline(&self) -> Option<gst::Pipeline> {
+        self.pipeline.lock().unwrap().clone()
+    }
+
+    /// Has the recording been asked to finish?
+    fn is_stop_requested(&self) -> bool {
+        self.eos_requested_at.lock().unwrap().is_some()
+    }
+
+    /// The total size of the segment files written so far.
+    fn bytes_written(&self) -> u64 {
+        self.segments.lock().unwrap().iter().map(|path| output_file_size(path)).sum()
+    }
+
+    /// Has an EOS been injected and not got to the end of the pipeline in a reasonable time?
+    fn is_eos_overdue(&self, now: time::Instant) -> bool {
+        match *self.eos_requested_at.lock().unwrap() {
+            Some(t) => now.duration_since(t) > EOS_TIMEOUT,
+            None => false,
+        }
+    }
+
+    /// Record that the transport stream arrived at `now`, whether or not it is written,
+    /// returning whether this is the first of it.
+    fn stream_arrived(&self, now: time::Instant) -> bool {
+        let mut last_stream_at = self.last_stream_at.lock().unwrap();
+        let is_first = last_stream_at.is_none();
+        *last_stream_at = Some(now);
+        is_first
+    }
+
+    /// Record that data reached the file at `now`, returning whether this is the first data.
+    fn data_arrived(&self, now: time::Instant) -> bool {
+        let mut segment_data_since = self.segment_data_since.lock().unwrap();
+        if segment_data_since.is_none() {
+            *segment_data_since = Some(now);
+        }
+        self.stream_arrived(now)
+    }
+
+    /// Has the transport stream arrived within `period` before `now`?
+    fn is_stream_flowing(&self, now: time::Instant, period: time::Duration) -> bool {
+        match *self.last_stream_at.lock().unwrap() {
+            Some(t) => now.duration_since(t) <= period,
+            None => false,
+        }
+    }
+
+    /// Has the transport stream arrived since `t`, so the frontend must have a lock?
+    fn has_stream_since(&self, t: time::Instant) -> bool {
+        match *self.last_stream_at.lock().unwrap() {
+            Some(last) => last >= t,
+            None => false,
+        }
+    }
+}
+
+/// Inject an EOS into the pipeline so that the muxer writes out its index and the file
+/// is finalised. The bus loop stops when the EOS reaches the end of the pipeline.
+fn request_eos(control: &RecordingControl) {
+    let now = time::Instant::now();
+    let pipeline = control.pipeline();
+    {
+        // A paused live source does not push data, so it would never push the EOS.
+        let mut timer = control.timer.lock().unwrap();
+        if timer.is_paused() {
+            if let Some(pipeline) = &pipeline {
+                pipeline.set_state(gst::State::Playing).expect("Could not resume the pipeline.");
+            }
+            timer.resume(now);
+        }
+    }
+    {
+        let mut eos_requested_at = control.eos_requested_at.lock().unwrap();
+        if eos_requested_at.is_none() {
+            *eos_requested_at = Some(now);
+        }
+    }
+    if let Some(pipeline) = pipeline {
+        pipeline.send_event(gst::event::Eos::new());
+    }
+}
+
+/// Act on a signal sent to the process.
+///
+/// SIGINT (Ctrl-C), SIGTERM (systemd, job schedulers) and SIGHUP (closing terminal) all
+/// work the same way: inject an EOS so that the bus loop can finalise the file. A second
+/// signal within the grace period means the user really wants out, now.
+///
+/// SIGUSR1 pauses the recording and SIGUSR2 resumes it. Pausing the pipeline rather than
+/// dropping buffers means the running time stops, so the muxer timeline stays continuous.
+///
+/// SIGRTMIN extends the recording by `extend_step` and SIGRTMIN+1 finishes it now.
+fn handle_signal(signal: i32, control: &RecordingControl, extend_step: time::Duration, last_signal_time: &mut Option<time::Instant>) {
+    let now = time::Instant::now();
+    match signal {
+        s if s == SIGRTMIN() => {
+            let progress_line = control.extend(extend_step);
+            info!("Duration extended by {} minutes. {}", extend_step.as_secs() / 60, progress_line);
+        },
+        s if s == SIGRTMIN() + 1 => {
+            info!("Signal {} received, finishing the recording now.", signal);
+            request_eos(control);
+        },
+        SIGUSR1 => {
+            let mut timer = control.timer.lock().unwrap();
+            if !timer.is_paused() {
+                if let Some(pipeline) = control.pipeline() {
+                    pipeline.set_state(gst::State::Paused).expect("Could not pause the pipeline.");
+                }
+                timer.pause(now);
+                info!("Recording paused. {}", timer.progress_line(now));
+            }
+        },
+        SIGUSR2 => {
+            let mut timer = control.timer.lock().unwrap();
+            if timer.is_paused() {
+                if let Some(pipeline) = control.pipeline() {
+                    pipeline.set_state(gst::State::Playing).expect("Could not resume the pipeline.");
+                }
+                timer.resume(now);
+                info!("Recording resumed. {}", timer.progress_line(now));
+            }
+        },
+        _ => {
+            if is_forced_quit(*last_signal_time, now) {
+                warn!("Second signal {} received during finalisation, quitting without finalising the file.", signal);
+                process::exit(exitcode::TEMPFAIL);
+            }
+            *last_signal_time = Some(now);
+            info!("Signal {} received, finalising the recording.", signal);
+            request_eos(control);
+        },
+    }
+}
+
+/// Take the pipeline to the NULL state, waiting for the state change to complete so that
+/// the filesink has flushed and closed the file before the process terminates.
+fn shut_down_pipeline(pipeline: &gst::Pipeline) {
+    if let Err(e) = pipeline.set_state(gst::State::Null) {
+        error!("Could not set the pipeline to the NULL state: {:?}", e);
+        return;
+    }
+    let (result, state, _pending) = pipeline.get_state(gst::ClockTime::from_seconds(STATE_CHANGE_TIMEOUT.as_secs()));
+    match result {
+        Ok(_) if state == gst::State::Null => debug!("Pipeline is in the NULL state."),
+        _ => warn!("The pipeline did not reach the NULL state within {} seconds, the file may be incomplete.", STATE_CHANGE_TIMEOUT.as_secs()),
+    }
+}
+
+/// The stem of the D-Bus bus name and object path of each recording.
+const DBUS_NAME_STEM: &str = "uk.org.winder.MeTV.Record";
+
+/// Offer control of the recording on the D-Bus session bus: Stop(), GetStatus() and
+/// ExtendDuration(minutes).
+///
+/// Each recording process has its own bus name and object, uk.org.winder.MeTV.Record.Pid<pid>
+/// and /uk/org/winder/MeTV/Record/Pid<pid>. Without a session bus, for example when run from
+/// cron or as a system service, there is just a warning.
+fn start_dbus_service(control: &Arc<RecordingControl>, channel: &str) {
+    let connection = match Connection::new_session() {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!("No D-Bus session bus, the recording cannot be controlled over D-Bus: {}", e);
+            return;
+        },
+    };
+    let pid = process::id();
+    let bus_name = format!("{}.Pid{}", DBUS_NAME_STEM, pid);
+    if let Err(e) = connection.request_name(bus_name.as_str(), false, true, false) {
+        warn!("Could not acquire the D-Bus name {}: {}", bus_name, e);
+        return;
+    }
+    let mut crossroads = Crossroads::new();
+    let interface = crossroads.register(DBUS_NAME_STEM, {
+        let control = control.clone();
+        let channel = channel.to_string();
+        move |builder| {
+            builder.method("Stop", (), (), {
+                let control = control.clone();
+                move |_, _, ()| {
+                    info!("Stop requested over D-Bus, finalising the recording.");
+                    request_eos(&control);
+                    Ok(())
+                }
+            });
+            builder.method("GetStatus", (), ("channel", "elapsed_seconds", "remaining_seconds", "bytes"), {
+                let control = control.clone();
+                move |_, _, ()| {
+                    let now = time::Instant::now();
+                    let timer = control.timer.lock().unwrap();
+                    Ok((channel.clone(), timer.recorded(now).as_secs(), timer.remaining(now).as_secs(), control.bytes_written()))
+                }
+            });
+            builder.method("ExtendDuration", ("minutes",), (), {
+                let control = control.clone();
+                move |_, _, (minutes,): (u32,)| {
+                    let progress_line = control.extend(time::Duration::from_secs(u64::from(minutes) * 60));
+                    info!("Duration extended by {} minutes over D-Bus. {}", minutes, progress_line);
+                    Ok(())
+                }
+            });
+        }
+    });
+    crossroads.insert(format!("/{}/Pid{}", DBUS_NAME_STEM.replace('.', "/"), pid), &[interface], ());
+    thread::spawn(move || {
+        if let Err(e) = crossroads.serve(&connection) {
+            warn!("The D-Bus service stopped: {}", e);
+        }
+    });
+    info!("Recording can be controlled over D-Bus as {}.", bus_name);
+}
+
+/// Watch the EIT sections in the transport stream coming out of dvbbasebin, marking
+/// chapters at programme boundaries, and when following an event only letting the
+/// stream through while the event is on. The stream arriving, even when it is then
+/// dropped, is what readiness, liveness and the lock mean while waiting for the event.
+fn add_eit_probe(dvbbasebin: &gst::Element, control: &Arc<RecordingControl>, notifier: &Option<Arc<Notifier>>) {
+    let assembler = Mutex::new(SectionAssembler::new(EIT_PID));
+    let control = control.clone();
+    let notifier = notifier.clone();
+    let src_pad = dvbbasebin.get_static_pad("src").expect("dvbbasebin has no src pad");
+    src_pad.add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
+        if control.stream_arrived(time::Instant::now()) {
+            notify_systemd(&notifier, "READY=1");
+        }
+        let position = control.timer.lock().unwrap().recorded(time::Instant::now());
+        let mut follower = control.event_follower.lock().unwrap();
+        let mut chapter_marks = control.chapter_marks.lock().unwrap();
+        if let Some(gst::PadProbeData::Buffer(ref buffer)) = probe_info.data {
+            if let Ok(map) = buffer.map_readable() {
+                for section in assembler.lock().unwrap().push_buffer(map.as_slice()) {
+                    if let Ok(eit) = parse_eit_section(&section) {
+                        if let Some(follower) = follower.as_mut() {
+                            match follower.update(&eit) {
+                                Some(EventState::Recording) => match follower.start_slippage(chrono::Utc::now().naive_utc()) {
+                                    Some(slippage) => info!("Event {:?} has {}, recording.", follower.event_id, slippage),
+                                    None => info!("Event {:?} has started, recording.", follower.event_id),
+                                },
+                                Some(EventState::Finished) => info!("Event {:?} has finished.", follower.event_id),
+                                _ => {},
+                            }
+                        }
+                        if let Some(chapter_marks) = chapter_marks.as_mut() {
+                            if let Some(chapter) = chapter_marks.update(&eit, position) {
+                                info!("Chapter '{}' starts at {}.", chapter.title, format_minutes_seconds(chapter.start));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        match follower.as_ref() {
+            Some(follower) if !follower.is_writing() => gst::PadProbeReturn::Drop,
+            _ => gst::PadProbeReturn::Ok,
+        }
+    });
+}
+
+/// The uridecodebin that tunes using the channels file, with the adapter and frontend
+/// set as the dvbbasebin source is created.
+fn uridecodebin(channel: &str, adapter: u8, frontend: u8, control: &Arc<RecordingControl>, notifier: &Option<Arc<Notifier>>) -> gst::Element {
+    let element = gst::ElementFactory::make("uridecodebin", None).expect("cannot make uridecodebin");
+    element.set_property("uri", &format!("dvb://{}", channel)).expect("cannot set uri property on uridecodebin");
+    element.connect("source-setup",  false, {
+        let adapter_number = adapter;
+        let frontend_number = frontend;
+        let control = control.clone();
+        let notifier = notifier.clone();
+        move |values| {
+            // values[0] .get::<gst::Element>() is an Option on the uridecodebin itself.
+            let element = values[1].get::<gst::Element>()
+                .expect("Failed to get a handle on the Element being created")
+                .expect("Option on Element was None");
+            if let Some(element_factory) = element.get_factory() {
+                if element_factory.get_name() == "dvbbasebin" {
+                    let current_adapter_number = element
+                        .get_property("adapter")
+                        .expect("Could not retrieve adapter number Value")
+                        .get::<i32>()
+                        .expect("Could not get the i32 value from the adapter number Value")
+                        .expect("Option on u32 returned None") as u8;
+                    let current_frontend_number = element
+                        .get_property("frontend")
+                        .expect("Could not retrieve frontend number Value.")
+                        .get::<i32>()
+                        .expect("Could not get the i32 value from the frontend number Value")
+                        .expect ("Option on u32 returned None") as u8;
+                    if current_adapter_number != adapter_number {
+                        element.set_property("adapter", &(adapter_number as i32)).expect("Could not set adapter number on dvbsrc element");
+                    }
+                    if current_frontend_number != adapter_number {
+                        element.set_property("frontend", &(frontend_number as i32)).expect("Could not set frontend number of dvbsrc element");
+                    }
+                    if control.is_watching_eit() {
+                        add_eit_probe(&element, &control, &notifier);
+                    }
+                }
+            }
+            None
+        }
+    }).expect("Could not connect a handler to the source-setup signal.");
+    element
+}
+
+/// A dvbbasebin tuned by explicit parameters rather than by the channels file.
+fn tuned_dvbbasebin(tuning: &TuningParameters, adapter: u8, frontend: u8) -> gst::Element {
+    let element = gst::ElementFactory::make("dvbbasebin", None).expect("cannot make dvbbasebin");
+    element.set_property("adapter", &(adapter as i32)).expect("Could not set adapter number on dvbbasebin element");
+    element.set_property("frontend", &(frontend as i32)).expect("Could not set frontend number on dvbbasebin element");
+    element.set_property_from_str("delsys", &tuning.delsys_nick());
+    element.set_property("frequency", &tuning.frequency).expect("Could not set frequency on dvbbasebin element");
+    if let Some(bandwidth_hz) = tuning.bandwidth_hz {
+        element.set_property("bandwidth-hz", &bandwidth_hz).expect("Could not set bandwidth on dvbbasebin element");
+    }
+    if let Some(modulation) = tuning.modulation_nick() {
+        element.set_property_from_str("modulation", &modulation);
+    }
+    element.set_property("program-numbers", &tuning.service_id.to_string()).expect("Could not set program numbers on dvbbasebin element");
+    element
+}
+
+/// The limits of the queues in the pipeline. Writing HD to slow storage needs a few
+/// seconds of buffering, more than the queue defaults.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct QueueSettings {
+    max_bytes: u32,
+    max_time: time::Duration,
+    leaky: String,  // The nick of the queue leaky property value.
+}
+
+/// Make a queue with the given limits, warning when it overruns.
+fn make_queue(settings: &QueueSettings) -> gst::Element {
+    let queue = gst::ElementFactory::make("queue", None).expect("cannot make a queue");
+    queue.set_property("max-size-buffers", &0u32).expect("cannot set max-size-buffers on queue");
+    queue.set_property("max-size-bytes", &settings.max_bytes).expect("cannot set max-size-bytes on queue");
+    queue.set_property("max-size-time", &(settings.max_time.as_nanos() as u64)).expect("cannot set max-size-time on queue");
+    queue.set_property_from_str("leaky", &settings.leaky);
+    queue.connect("overrun", false, |values| {
+        if let Ok(Some(queue)) = values[0].get::<gst::Element>() {
+            warn!("Queue {} is full, data will be lost or the pipeline stall, try a larger --buffer-size or --buffer-time.", queue.get_name());
+        }
+        None
+    }).expect("Could not connect a handler to the overrun signal.");
+    // A queue in a live pipeline empties all the time, so underruns are only of interest
+    // when debugging.
+    queue.connect("underrun", false, |values| {
+        if let Ok(Some(queue)) = values[0].get::<gst::Element>() {
+            debug!("Queue {} is empty.", queue.get_name());
+        }
+        None
+    }).expect("Could not connect a handler to the underrun signal.");
+    queue
+}
+
+/// The name of the muxer in the pipeline.
+const MUXER_NAME: &str = "mux";
+
+/// The name of the muxer of the network stream in the pipeline.
+const STREAM_MUXER_NAME: &str = "streammux";
+
+/// Where a network stream of the recording is sent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum StreamTarget {
+    Udp { host: String, port: u16 },
+    Rtp { host: String, port: u16 },
+    TcpListen { host: String, port: u16 },
+}
+
+impl StreamTarget {
+    fn parse(url: &str) -> Result<StreamTarget, String> {
+        let error = || format!("'{}' is not a udp://host:port, rtp://host:port or tcp-listen://[host]:port URL.", url);
+        let separator = url.find("://").ok_or_else(error)?;
+        let (scheme, address) = (&url[..separator], &url[separator + 3..]);
+        let colon = address.rfind(':').ok_or_else(error)?;
+        let host = address[..colon].to_string();
+        let port = address[colon + 1..].parse::<u16>().map_err(|_| error())?;
+        match scheme {
+            "udp" if !host.is_empty() => Ok(StreamTarget::Udp { host, port }),
+            "rtp" if !host.is_empty() => Ok(StreamTarget::Rtp { host, port }),
+            "tcp-listen" => Ok(StreamTarget::TcpListen { host: if host.is_empty() { "0.0.0.0".to_string() } else { host }, port }),
+            _ => Err(error()),
+        }
+    }
+
+    /// The gst-launch style description of the elements after the MPEG-TS muxer.
+    fn sink_description(&self) -> String {
+        match self {
+            StreamTarget::Udp { host, port } => format!("udpsink host={} port={}", host, port),
+            StreamTarget::Rtp { host, port } => format!("rtpmp2tpay ! udpsink host={} port={}", host, port),
+            StreamTarget::TcpListen { host, port } => format!("tcpserversink host={} port={}", host, port),
+        }
+    }
+
+    /// The elements after the MPEG-TS muxer, in order.
+    fn sink_elements(&self) -> Vec<gst::Element> {
+        let network_sink = |factory, host: &str, port: u16| {
+            let element = gst::ElementFactory::make(factory, None).expect("cannot make network sink");
+            element.set_property("host", &host).expect("cannot set host on network sink");
+            element.set_property("port", &(port as i32)).expect("cannot set port on network sink");
+            element
+        };
+        match self {
+            StreamTarget::Udp { host, port } => vec![network_sink("udpsink", host, *port)],
+            StreamTarget::Rtp { host, port } => vec![
+                gst::ElementFactory::make("rtpmp2tpay", None).expect("cannot make rtpmp2tpay"),
+                network_sink("udpsink", host, *port),
+            ],
+            StreamTarget::TcpListen { host, port } => vec![network_sink("tcpserversink", host, *port)],
+        }
+    }
+}
+
+/// Validate a command line value as a stream URL.
+fn is_stream_target(value: String) -> Result<(), String> {
+    StreamTarget::parse(&value).map(|_| ())
+}
+
+/// The name of the HLS sink in the pipeline.
+const HLS_SINK_NAME: &str = "hls";
+
+/// A rolling HLS playlist and its segments in a directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct HlsOutput {
+    directory: String,
+    segment_seconds: u32,
+    playlist_length: u32,
+    keep_all: bool,  // Keep every segment, and list them all, so the event is available as VOD.
+}
+
+impl HlsOutput {
+    fn segment_location(&self) -> String {
+        Path::new(&self.directory).join("segment%05d.ts").to_string_lossy().to_string()
+    }
+
+    fn playlist_location(&self) -> String {
+        Path::new(&self.directory).join("playlist.m3u8").to_string_lossy().to_string()
+    }
+
+    /// The playlist-length and max-files property values for hlssink2, zero meaning
+    /// no limit.
+    fn limits(&self) -> (u32, u32) {
+        if self.keep_all { (0, 0) } else { (self.playlist_length, self.playlist_length) }
+    }
+
+    fn sink_description(&self) -> String {
+        let (playlist_length, max_files) = self.limits();
+        format!(
+            "hlssink2 name=h location=\"{}\" playlist-location=\"{}\" target-duration={} playlist-length={} max-files={}",
+            self.segment_location(), self.playlist_location(), self.segment_seconds, playlist_length, max_files,
+        )
+    }
+
+    fn sink_element(&self) -> gst::Element {
+        let (playlist_length, max_files) = self.limits();
+        let element = gst::ElementFactory::make("hlssink2", Some(HLS_SINK_NAME)).expect("cannot make hlssink2");
+        element.set_property("location", &self.segment_location()).expect("cannot set location on hlssink2");
+        element.set_property("playlist-location", &self.playlist_location()).expect("cannot set playlist-location on hlssink2");
+        element.set_property("target-duration", &self.segment_seconds).expect("cannot set target-duration on hlssink2");
+        element.set_property("playlist-length", &playlist_length).expect("cannot set playlist-length on hlssink2");
+        element.set_property("max-files", &max_files).expect("cannot set max-files on hlssink2");
+        element
+    }
+}
+
+/// The outputs of a recording, at least one of which is present.
+#[derive(Clone, Debug, Default)]
+struct Outputs {
+    file: Option<String>,
+    stream: Option<StreamTarget>,
+    hls: Option<HlsOutput>,
+}
+
+/// An output branch of a pipeline: a muxer with its request pad templates, whether
+/// the video must be parsed into byte-stream form for it, and the sink pad at the end
+/// through which the muxed data passes, if there is one outside the muxer.
+struct OutputBranch {
+    muxer: gst::Element,
+    video_pad_template: &'static str,
+    audio_pad_template: &'static str,
+    needs_parsed_video: bool,
+    data_pad: Option<gst::Pad>,
+}
+
+/// Add the muxers and sinks for the outputs to the pipeline.
+fn output_branches(pipeline: &gst::Pipeline, outputs: &Outputs) -> Vec<OutputBranch> {
+    let mut branches = Vec::new();
+    if let Some(output_path) = &outputs.file {
+        let mp4mux = gst::ElementFactory::make("mp4mux", Some(MUXER_NAME)).expect("cannot make mp4mux");
+        let filesink = {
+            let element = gst::ElementFactory::make("filesink", None).expect("cannot make filesrc");
+            element.set_property("location", output_path).expect("cannot set location for filesrc");
+            element
+        };
+        pipeline.add_many(&[&mp4mux, &filesink]).expect("could not add elements to pipeline");
+        gst::Element::link_many(&[&mp4mux, &filesink]).expect("could not link elements in pipeline");
+        let data_pad = filesink.get_static_pad("sink");
+        branches.push(OutputBranch { muxer: mp4mux, video_pad_template: "video_%u", audio_pad_template: "audio_%u", needs_parsed_video: false, data_pad });
+    }
+    if let Some(target) = &outputs.stream {
+        let mpegtsmux = gst::ElementFactory::make("mpegtsmux", Some(STREAM_MUXER_NAME)).expect("cannot make mpegtsmux");
+        let mut elements = vec![mpegtsmux.clone()];
+        elements.extend(target.sink_elements());
+        let element_refs = elements.iter().collect::<Vec<_>>();
+        pipeline.add_many(&element_refs).expect("could not add elements to pipeline");
+        gst::Element::link_many(&element_refs).expect("could not link elements in pipeline");
+        let data_pad = elements.last().unwrap().get_static_pad("sink");
+        branches.push(OutputBranch { muxer: mpegtsmux, video_pad_template: "sink_%d", audio_pad_template: "sink_%d", needs_parsed_video: true, data_pad });
+    }
+    if let Some(hls) = &outputs.hls {
+        // hlssink2 muxes internally so it takes the elementary streams itself.
+        let hlssink2 = hls.sink_element();
+        pipeline.add(&hlssink2).expect("could not add hlssink2 to pipeline");
+        branches.push(OutputBranch { muxer: hlssink2, video_pad_template: "video", audio_pad_template: "audio", needs_parsed_video: true, data_pad: None });
+    }
+    branches
+}
+
+/// Construct the GStreamer graph described by:
+///
+///    gst-launch-1.0 -e uridecodebin uri=dvb://<channel> name=d ! queue ! x264enc ! mp4mux name=m ! filesink location=<output-path> d. ! queue ! avenc_ac3 ! m.
+///
+/// or, with explicit tuning parameters, with dvbbasebin ! decodebin as the source. When
+/// there are several outputs each encoder feeds a tee with a branch for each output. For
+/// a radio channel only the sound is recorded, so there is no video encoder.
+fn build_pipeline(channel: &str, tuning: &Option<TuningParameters>, adapter: u8, frontend: u8, outputs: &Outputs, is_radio: bool, queue_settings: &QueueSettings, control: &Arc<RecordingControl>, notifier: &Option<Arc<Notifier>>) -> gst::Pipeline {
+    let pipeline = gst::Pipeline::new(None);
+    let decoder = match tuning {
+        Some(tuning) => {
+            let dvbbasebin = tuned_dvbbasebin(tuning, adapter, frontend);
+            if control.is_watching_eit() {
+                add_eit_probe(&dvbbasebin, control, notifier);
+            }
+            let decodebin = gst::ElementFactory::make("decodebin", None).expect("cannot make decodebin");
+            pipeline.add_many(&[&dvbbasebin, &decodebin]).expect("could not add elements to pipeline");
+            dvbbasebin.link(&decodebin).expect("could not link dvbbasebin to decodebin");
+            decodebin
+        },
+        None => {
+            let uridecodebin = uridecodebin(channel, adapter, frontend, control, notifier);
+            pipeline.add(&uridecodebin).expect("could not add uridecodebin to pipeline");
+            uridecodebin
+        },
+    };
+    let branches = output_branches(&pipeline, outputs);
+    // Without a sink outside a muxer, the data going into the muxer has to do.
+    let data_pad = branches.iter().find_map(|branch| branch.data_pad.clone());
+    let is_probing_muxer = data_pad.is_none();
+    if let Some(pad) = data_pad {
+        add_data_probe(&pad, control, notifier);
+    }
+    let control = control.clone();
+    let notifier = notifier.clone();
+    let queue_settings = queue_settings.clone();
+    // Heed the warnings about strong references, circular references and memory leaks.
+    let pipeline_weak_ref = pipeline.downgrade();
+    decoder.connect_pad_added(move |d_b, src_pad| {
+        let pipeline = match pipeline_weak_ref.upgrade() {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let (is_audio, is_video) = {
+            let media_type = src_pad.get_current_caps().and_then(|caps| {
+                caps.get_structure(0).map(|s| {
+                    let name = s.get_name();
+                    (name.starts_with("audio/"), name.starts_with("video/"))
+                })
+            });
+            match media_type {
+                Some(media_type) => media_type,
+                None => {
+                    gst_element_warning!(d_b, gst::CoreError::Negotiation, ("Failed to get media type from pad {}", src_pad.get_name()));
+                    return;
+                },
+            }
+        };
+        let insert_sink = |is_audio, is_video| -> Result<(), ()> {
+            if is_audio && is_video { panic!("sink is both audio and video at the same time"); }
+            if ! is_audio && ! is_video { return Ok(()); }
+            if is_radio && is_video { return Ok(()); }
+            let queue = make_queue(&queue_settings);
+            let new_element = if is_audio {
+                gst::ElementFactory::make("avenc_ac3", None).expect("cannot make a avenc_ac3")
+            } else {
+                gst::ElementFactory::make("x264enc", None).expect("cannot make a x264enc")
+            };
+            let elements = &[&queue, &new_element];
+            pipeline.add_many(elements).expect("could not add elements to pipeline");
+            gst::Element::link_many(elements).expect("could not link elements in pipeline");
+            for e in elements {
+                e.sync_state_with_parent().expect("could not sync state of elements with parent");
+            }
+            let sink_pad = queue.get_static_pad("sink").expect("video queue has no sink pad");
+            src_pad.link(&sink_pad).expect("linking src_pad to sink_pad of new queue failed");
+            let new_element_src_pad = new_element.get_static_pad("src").expect("new element has no src pad");
+            let branch_src_pads = if branches.len() == 1 {
+                vec![new_element_src_pad]
+            } else {
+                let tee = gst::ElementFactory::make("tee", None).expect("cannot make a tee");
+                pipeline.add(&tee).expect("could not add tee to pipeline");
+                new_element.link(&tee).expect("could not link encoder to tee");
+                tee.sync_state_with_parent().expect("could not sync state of tee with parent");
+                branches.iter().map(|_| tee.get_request_pad("src_%u").expect("tee has no src pad")).collect()
+            };
+            for (branch, branch_pad) in branches.iter().zip(branch_src_pads) {
+                let mut chain = Vec::new();
+                if branches.len() > 1 {
+                    chain.push(make_queue(&queue_settings));
+                }
+                if is_video && branch.needs_parsed_video {
+                    let parser = gst::ElementFactory::make("h264parse", None).expect("cannot make a h264parse");
+                    // Repeat the codec data so that viewers can join a stream at any time.
+                    parser.set_property("config-interval", &(-1i32)).expect("cannot set config-interval on h264parse");
+                    chain.push(parser);
+                }
+                let chain_refs = chain.iter().collect::<Vec<_>>();
+                if !chain.is_empty() {
+                    pipeline.add_many(&chain_refs).expect("could not add elements to pipeline");
+                    gst::Element::link_many(&chain_refs).expect("could not link elements in pipeline");
+                    for e in &chain {
+                        e.sync_state_with_parent().expect("could not sync state of elements with parent");
+                    }
+                }
+                let branch_src_pad = match (chain.first(), chain.last()) {
+                    (Some(first), Some(last)) => {
+                        branch_pad.link(&first.get_static_pad("sink").expect("no sink pad")).expect("linking to the branch failed");
+                        last.get_static_pad("src").expect("no src pad")
+                    },
+                    _ => branch_pad,
+                };
+                let sink_pad_template = if is_audio { branch.audio_pad_template } else { branch.video_pad_template };
+                let muxer_sink_pad = branch.muxer.get_request_pad(sink_pad_template).expect(&format!("muxer has no {} sink pad", sink_pad_template));
+                branch_src_pad.link(&muxer_sink_pad).expect("linking new element to the muxer failed.");
+                // What is written is the video, or for a radio channel the sound.
+                if is_probing_muxer && (is_video || is_radio) {
+                    add_data_probe(&muxer_sink_pad, &control, &notifier);
+                }
+            }
+            Ok(())
+        };
+        if let Err(err) = insert_sink(is_audio, is_video) {
+            //  TODO why are the parentheses needed around the string?
+            gst_element_error!(d_b, gst::LibraryError::Failed, ("Failed to insert sink"), ["{:?}", err]);
+        }
+    });
+    pipeline
+}
+
+/// Data reaching the sink means the tuning worked and the muxer is producing output:
+/// this is what readiness and liveness mean to systemd.
+fn add_data_probe(pad: &gst::Pad, control: &Arc<RecordingControl>, notifier: &Option<Arc<Notifier>>) {
+    pad.add_probe(gst::PadProbeType::BUFFER, {
+        let control = control.clone();
+        let notifier = notifier.clone();
+        move |_, _| {
+            if control.data_arrived(time::Instant::now()) {
+                notify_systemd(&notifier, "READY=1");
+            }
+            gst::PadProbeReturn::Ok
+        }
+    });
+}
+
+/// How a segment of the recording ended.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SegmentEnd {
+    Eos,
+    EosTimedOut,
+    Error,
+    NotTuned,  // Failed, or no lock, before any data was recorded.
+}
+
+/// How long to wait for the frontend to lock before trying the next adapter.
+const LOCK_TIMEOUT: time::Duration = time::Duration::from_secs(20);
+
+/// After an error, push an EOS into the muxer directly so that it finalises the file
+/// even though the source side of the pipeline has failed.
+fn finalise_after_error(pipeline: &gst::Pipeline) {
+    for muxer in [MUXER_NAME, STREAM_MUXER_NAME, HLS_SINK_NAME].iter().filter_map(|name| pipeline.get_by_name(name)) {
+        for pad in muxer.get_sink_pads() {
+            pad.send_event(gst::event::Eos::new());
+        }
+    }
+    let bus = pipeline.get_bus().expect("Pipeline without bus. Shouldn't happen!");
+    if bus.timed_pop_filtered(gst::ClockTime::from_seconds(EOS_TIMEOUT.as_secs()), &[gst::MessageType::Eos]).is_none() {
+        warn!("The muxer did not finish within {} seconds of the error, the file may be incomplete.", EOS_TIMEOUT.as_secs());
+    }
+}
+
+/// Process the messages on the bus of a segment's pipeline until the segment ends.
+fn run_bus_loop(pipeline: &gst::Pipeline, control: &RecordingControl, json_output: bool, channel: &str, adapter: u8, frontend: u8) -> SegmentEnd {
+    let bus = pipeline.get_bus().expect("Pipeline without bus. Shouldn't happen!");
+    let started_at = time::Instant::now();
+    let mut is_tuned = false;
+    // Some drivers do not post frontend statistics, the stream arriving, whether or not it
+    // is written, also shows the tuning worked.
+    let has_tuned = |is_tuned: bool| is_tuned || control.has_stream_since(started_at);
+    loop {
+        let msg = match bus.timed_pop(gst::ClockTime::from_seconds(1)) {
+            Some(msg) => msg,
+            None => {
+                let now = time::Instant::now();
+                if control.is_eos_overdue(now) {
+                    warn!("The end of stream did not reach the end of the pipeline within {} seconds, the file may be incomplete.", EOS_TIMEOUT.as_secs());
+                    return SegmentEnd::EosTimedOut;
+                }
+                if !has_tuned(is_tuned) && !control.is_stop_requested() && !control.timer.lock().unwrap().is_paused()
+                    && now.duration_since(started_at) > LOCK_TIMEOUT {
+                    warn!("No lock on adapter {} frontend {} within {} seconds.", adapter, frontend, LOCK_TIMEOUT.as_secs());
+                    return SegmentEnd::NotTuned;
+                }
+                continue;
+            },
+        };
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                debug!("End of stream reached the end of the pipeline.");
+                return SegmentEnd::Eos;
+            },
+            MessageView::Error(err) => {
+                let message = format!("{}: {} ({})",
+                                      err.get_src().map(|s| s.get_path_string()).unwrap_or_else(|| glib::GString::from("None")),
+                                      err.get_error(),
+                                      err.get_debug().unwrap_or_else(|| String::from("None")),
+                );
+                error!("{}", message);
+                emit(json_output, &RecordingEvent::Error { message });
+                if !has_tuned(is_tuned) {
+                    return SegmentEnd::NotTuned;
+                }
+                finalise_after_error(pipeline);
+                return SegmentEnd::Error;
+            },
+            MessageView::Warning(w) => {
+                let message = format!("{}: {} ({})",
+                                      w.get_src().map(|s| s.get_path_string()).unwrap_or_else(|| glib::GString::from("None")),
+                                      w.get_error(),
+                                      w.get_debug().unwrap_or_else(|| String::from("None")),
+                );
+                warn!("{}", message);
+                emit(json_output, &RecordingEvent::Warning { message });
+            },
+            MessageView::Element(element) => {
+                if let Some(structure) = element.get_structure() {
+                    if let Some(stats) = FrontendStats::from_structure(structure) {
+                        *control.frontend_stats.lock().unwrap() = Some(stats);
+                    }
+                    // dvbsrc posts frontend statistics regularly, the first one with a
+                    // lock tells us the tuning succeeded.
+                    if !is_tuned && structure.get_name() == "dvb-frontend-stats" {
+                        if let Ok(Some(true)) = structure.get::<bool>("lock") {
+                            is_tuned = true;
+                            info!("Tuned to channel '{}'.", channel);
+                            emit(json_output, &RecordingEvent::Tuned { adapter, frontend });
+                        }
+                    }
+                }
+            },
+            MessageView::StateChanged(s) => {
+                debug!(
+                    "State changed from {:?}: {:?} -> {:?} ({:?})",
+                    s.get_src().map(|s| s.get_path_string()),
+                    s.get_old(),
+                    s.get_current(),
+                    s.get_pending()
+                );
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The path of a numbered segment of a recording: the output path itself for the first
+/// segment, then with -2, -3, etc. inserted before the extension.
+fn segment_output_path(output_path: &str, segment_number: usize) -> String {
+    if segment_number <= 1 {
+        return output_path.to_string();
+    }
+    let path = Path::new(output_path);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => path
+            .with_file_name(format!("{}-{}.{}", stem.to_string_lossy(), segment_number, extension.to_string_lossy()))
+            .to_string_lossy()
+            .to_string(),
+        _ => format!("{}-{}", output_path, segment_number),
+    }
+}
+
+/// How long to wait after an error before rebuilding the pipeline.
+const RESTART_DELAY: time::Duration = time::Duration::from_secs(2);
+
+/// When run by systemd as a notify service, tell systemd about the state of the recording.
+fn notify_systemd(notifier: &Option<Arc<Notifier>>, state: &str) {
+    if let Some(notifier) = notifier {
+        if let Err(e) = notifier.notify(state) {
+            warn!("Could not send '{}' to systemd: {}", state, e);
+        }
+    }
+}
+
+/// In JSON mode, write the event to stdout as a single line.
+///
+/// All the human-readable output goes to stderr via the logging system so that stdout
+/// is just the stream of events.
+fn emit(json_output: bool, event: &RecordingEvent) {
+    if json_output {
+        println!("{}", event.to_json_line());
+    }
+}
+
+/// Send a desktop notification, if asked to. Not being able to is not an error.
+fn notify_desktop(is_notifying: bool, summary: &str, body: &str) {
+    if is_notifying && !send_notification(summary, body) {
+        debug!("Could not send a desktop notification.");
+    }
+}
+
+/// The current size of the output file, zero if it cannot be determined.
+fn output_file_size(output_path: &str) -> u64 {
+    fs::metadata(output_path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// The command line of me-tv-record.
+fn command_line() -> App<'static, 'static> {
+    App::new("me-tv-record")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("Russel Winder <russel@winder.org.uk>")
+        .about("Record a channel from now for a duration to create an MPEG4 file.
+
+A channel name and either a duration or an EIT event to follow must be provided.
+")
+        .arg(Arg::with_name("adapter")
+            .short("a")
+            .long("adapter")
+            .value_name("NUMBER[,NUMBER…]|auto")
+            .help("Sets the adapter number to use, or a comma separated list of adapter numbers to try in order if tuning fails, or auto to try all installed adapters, those not in use by another program first.")
+            .takes_value(true)
+            .validator(is_adapter_list)
+            .default_value("0"))
+        .arg(Arg::with_name("frontend")
+            .short("f")
+            .long("frontend")
+            .value_name("NUMBER")
+            .help("Sets the frontend number to use. With --adapter auto and not given, any frontend that supports the delivery system of the channel is used.")
+            .takes_value(true)
+            .default_value("0"))
+        .arg(Arg::with_name("frontend_id")
+            .short("F")
+            .long("frontend-id")
+            .value_name("ADAPTER:FRONTEND")
+            .help("Sets the adapter and frontend to use, e.g. 2:0, overriding --adapter and --frontend.")
+            .takes_value(true)
+            .validator(|value| value.parse::<FrontendId>().map(|_| ())))
+        .arg(Arg::with_name("dvb_path")
+            .long("dvb-path")
+            .value_name("DIRECTORY")
+            .help("Sets the directory of the DVB adapter directories, overriding ME_TV_DVB_PATH, default /dev/dvb.")
+            .takes_value(true))
+        .arg(Arg::with_name("channel")
+            .short("c")
+            .long("channel")
+            .value_name("CHANNEL")
+            .help("Sets the channel name, must be specified unless tuning explicitly, no default.")
+            .takes_value(true)
+            .required_unless_one(&["frequency", "import_vdr", "import", "scan", "scan_multiplex", "scan_update", "list_multiplexes"])
+            .conflicts_with("frequency"))
+        .arg(Arg::with_name("frequency")
+            .long("frequency")
+            .value_name("FREQUENCY")
+            .help("Tune explicitly to this frequency, as written in the channels file, rather than using the channels file.")
+            .takes_value(true)
+            .validator(is_u32)
+            .requires_all(&["delivery_system", "service_id"]))
+        .arg(Arg::with_name("delivery_system")
+            .long("delivery-system")
+            .value_name("SYSTEM")
+            .help("Sets the delivery system when tuning explicitly, or scanning a multiplex not in the channels file.")
+            .takes_value(true)
+            .possible_values(&DELIVERY_SYSTEMS)
+            .requires("explicit_frequency"))
+        .arg(Arg::with_name("bandwidth")
+            .long("bandwidth")
+            .value_name("HZ")
+            .help("Sets the bandwidth in Hz when tuning explicitly, or scanning a multiplex not in the channels file.")
+            .takes_value(true)
+            .validator(is_u32)
+            .requires("explicit_frequency"))
+        .arg(Arg::with_name("modulation")
+            .long("modulation")
+            .value_name("MODULATION")
+            .help("Sets the modulation when tuning explicitly, or scanning a multiplex not in the channels file.")
+            .takes_value(true)
+            .possible_values(&MODULATIONS)
+            .requires("explicit_frequency"))
+        .arg(Arg::with_name("service_id")
+            .long("service-id")
+            .alias("program-number")
+            .value_name("ID")
+            .help("Sets the service id, aka program number, to record when tuning explicitly.")
+            .takes_value(true)
+            .validator(is_u16)
+            .requires("frequency"))
+        .arg(Arg::with_name("name")
+            .long("name")
+            .value_name("NAME")
+            .help("Sets the name of the service when tuning explicitly.")
+            .takes_value(true)
+            .requires("frequency"))
+        .arg(Arg::with_name("radio")
+            .long("radio")
+            .help("Record the service as a radio service, sound only, when tuning explicitly.")
+            .requires("frequency"))
+        .arg(Arg::with_name("emit_channels_line")
+            .long("emit-channels-line")
+            .help("Print the channels file entry equivalent to the explicit tuning parameters, and exit.")
+            .requires("frequency"))
+        .arg(Arg::with_name("import_vdr")
+            .long("import-vdr")
+            .value_name("PATH")
+            .help("Add the channels of a VDR channels.conf file to the channels file, those not already in it, and exit.")
+            .takes_value(true)
+            .conflicts_with_all(&["channel", "frequency"]))
+        .arg(Arg::with_name("import")
+            .long("import")
+            .value_name("PATH")
+            .help("Add the channels of the scan results w_scan2 or dvbv5-scan wrote, in VDR, zap, or DVBv5 format, to the channels file, and exit.")
+            .takes_value(true)
+            .conflicts_with_all(&["channel", "frequency", "import_vdr"]))
+        .arg(Arg::with_name("scan")
+            .long("scan")
+            .value_name("PATH")
+            .help("Scan the transponders of a DVBv5 initial tuning file with dvbv5-scan, using all the adapters given at the same time, add the channels found to the channels file, and exit.")
+            .takes_value(true)
+            .conflicts_with_all(&["channel", "frequency", "import_vdr", "import"]))
+        .arg(Arg::with_name("scan_multiplex")
+            .long("scan-multiplex")
+            .value_name("FREQUENCY")
+            .help("Scan the one multiplex at this frequency, as written in the channels file, with dvbv5-scan, add the channels found to the channels file, and exit. A multiplex not in the channels file needs --delivery-system.")
+            .takes_value(true)
+            .validator(is_u32)
+            .conflicts_with_all(&["channel", "frequency", "import_vdr", "import", "scan"]))
+        .arg(Arg::with_name("scan_update")
+            .long("scan-update")
+            .help("Read the NIT on the multiplexes in the channels file for those the networks announce, scan only the ones not in the channels file with dvbv5-scan, add the channels found to the channels file, and exit.")
+            .conflicts_with_all(&["channel", "frequency", "import_vdr", "import", "scan", "scan_multiplex"]))
+        .arg(Arg::with_name("list_multiplexes")
+            .long("list-multiplexes")
+            .help("List the multiplexes in the channels file, by the frequencies --scan-multiplex takes, and exit.")
+            .conflicts_with_all(&["channel", "frequency", "import_vdr", "import", "scan", "scan_multiplex", "scan_update"]))
+        .group(ArgGroup::with_name("explicit_frequency")
+            .args(&["frequency", "scan_multiplex"]))
+        .group(ArgGroup::with_name("scanning")
+            .args(&["scan", "scan_multiplex", "scan_update"]))
+        .arg(Arg::with_name("lnb")
+            .long("lnb")
+            .value_name("LNB")
+            .help("Sets the LNB of the satellite dish to scan with, as dvbv5-scan names it, e.g. UNIVERSAL.")
+            .takes_value(true)
+            .requires("scanning"))
+        .arg(Arg::with_name("replace")
+            .long("replace")
+            .help("When importing, tune the channels already in the channels file as the imported ones are, rather than leaving them as they are."))
+        .arg(Arg::with_name("duration")
+            .short("d")
+            .long("duration")
+            .value_name("TIME")
+            .help("Sets the duration of recording in minutes, must be specified unless following the EIT, no default.")
+            .takes_value(true)
+            .required_unless_one(&["event_id", "follow_eit", "emit_channels_line", "import_vdr", "import", "scan", "scan_multiplex", "scan_update", "list_multiplexes"]))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("PATH")
+            .help("Path to output file, must be specified unless streaming, no default.")
+            .takes_value(true)
+            .required_unless_one(&["emit_channels_line", "import_vdr", "import", "scan", "scan_multiplex", "scan_update", "list_multiplexes", "stream", "hls"]))
+        .arg(Arg::with_name("stream")
+            .long("stream")
+            .value_name("URL")
+            .help("Stream the recording as MPEG-TS to udp://host:port, rtp://host:port or tcp-listen://[host]:port, as well as writing any output file.")
+            .takes_value(true)
+            .validator(is_stream_target))
+        .arg(Arg::with_name("hls")
+            .long("hls")
+            .value_name("DIRECTORY")
+            .help("Write an HLS playlist and segments into the directory, as well as writing any output file.")
+            .takes_value(true))
+        .arg(Arg::with_name("hls_segment_duration")
+            .long("hls-segment-duration")
+            .value_name("SECONDS")
+            .help("Sets the target duration of the HLS segments.")
+            .takes_value(true)
+            .validator(is_u32)
+            .default_value("6"))
+        .arg(Arg::with_name("hls_playlist_length")
+            .long("hls-playlist-length")
+            .value_name("NUMBER")
+            .help("Sets the number of segments in the HLS playlist, older segments are deleted.")
+            .takes_value(true)
+            .validator(is_u32)
+            .default_value("5"))
+        .arg(Arg::with_name("hls_keep_all")
+            .long("hls-keep-all")
+            .help("Keep all the HLS segments in the playlist, so the whole recording is available afterwards.")
+            .requires("hls"))
+        .arg(Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .help("sets verbose mode, the same as --log-level=debug"))
+        .arg(Arg::with_name("log_level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .help("Sets the level of logging output, overrides --verbose and RUST_LOG.")
+            .takes_value(true)
+            .possible_values(&["off", "error", "warn", "info", "debug", "trace"]))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Output each significant event as a line of JSON on stdout, all other output goes to stderr."))
+        .arg(Arg::with_name("dry_run")
+            .long("dry-run")
+            .help("Check the channel and the needed GStreamer elements, print the pipeline that would be used, and exit without tuning."))
+        .arg(Arg::with_name("event_id")
+            .long("event-id")
+            .value_name("ID")
+            .help("Record the EPG event with this DVB event id, starting and stopping as the EIT says the event does.")
+            .takes_value(true))
+        .arg(Arg::with_name("follow_eit")
+            .long("follow-eit")
+            .help("Record the EPG event on air at the start, stopping when the EIT says the event ends.")
+            .conflicts_with("event_id"))
+        .arg(Arg::with_name("max_overrun")
+            .long("max-overrun")
+            .value_name("TIME")
+            .help("Sets the number of minutes an event followed in the EIT may run beyond its scheduled end.")
+            .takes_value(true)
+            .default_value("30"))
+        .arg(Arg::with_name("sidecar")
+            .long("sidecar")
+            .value_name("FORMAT")
+            .help("Sets the format of the metadata file written alongside the recording, may be given more than once.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .possible_values(&["nfo", "json", "none"])
+            .default_value("nfo"))
+        .arg(Arg::with_name("notify")
+            .long("notify")
+            .help("Show a desktop notification when the recording finishes or fails."))
+        .arg(Arg::with_name("stats")
+            .long("stats")
+            .value_name("SECONDS")
+            .help("Report the signal statistics of the frontend, every second or at the given interval.")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .validator(is_u32))
+        .arg(Arg::with_name("chapters")
+            .long("chapters")
+            .help("Mark a chapter at each programme boundary, using the EIT, in an .ffmetadata file alongside the recording."))
+        .arg(Arg::with_name("thumbnail")
+            .long("thumbnail")
+            .help("Write a JPEG thumbnail alongside the recording once it is finished."))
+        .arg(Arg::with_name("thumbnail_position")
+            .long("thumbnail-position")
+            .value_name("SECONDS")
+            .help("Sets how far into the recording the thumbnail frame is taken from.")
+            .takes_value(true)
+            .validator(is_u32)
+            .default_value("60"))
+        .arg(Arg::with_name("extend_step")
+            .long("extend-step")
+            .value_name("MINUTES")
+            .help("Sets how much SIGRTMIN extends the recording by.")
+            .takes_value(true)
+            .validator(is_u32)
+            .default_value("15"))
+        .arg(Arg::with_name("buffer_size")
+            .long("buffer-size")
+            .value_name("MB")
+            .help("Sets the maximum size of each queue in the pipeline.")
+            .takes_value(true)
+            .validator(is_u32)
+            .default_value("64"))
+        .arg(Arg::with_name("buffer_time")
+            .long("buffer-time")
+            .value_name("SECONDS")
+            .help("Sets the maximum time each queue in the pipeline can hold.")
+            .takes_value(true)
+            .validator(is_u32)
+            .default_value("5"))
+        .arg(Arg::with_name("buffer_leaky")
+            .long("buffer-leaky")
+            .value_name("WHERE")
+            .help("Sets whether a full queue drops new data (upstream), old data (downstream), or blocks (no).")
+            .takes_value(true)
+            .possible_values(&["no", "upstream", "downstream"])
+            .default_value("no"))
+        .arg(Arg::with_name("resume_on_error")
+            .long("resume-on-error")
+            .help("After an error, finalise the file and carry on recording into a new numbered segment file."))
+        .arg(Arg::with_name("max_restarts")
+            .long("max-restarts")
+            .value_name("NUMBER")
+            .help("Sets the maximum number of times the recording is restarted with --resume-on-error.")
+            .takes_value(true)
+            .default_value("5"))
+}
+
+/// The transponders to scan for --scan-multiplex: the one the command line describes, else
+/// those in the channels file at the frequency. Exits if there are none.
+fn multiplex_transponders(matches: &ArgMatches) -> Vec<Transponder> {
+    let frequency = matches.value_of("scan_multiplex").unwrap().parse::<u32>().unwrap();
+    match matches.value_of("delivery_system") {
+        Some(delivery_system) => {
+            let mut parameters = Vec::new();
+            if let Some(bandwidth) = matches.value_of("bandwidth") {
+                parameters.push(("BANDWIDTH_HZ".to_string(), bandwidth.to_string()));
+            }
+            if let Some(modulation) = matches.value_of("modulation") {
+                parameters.push(("MODULATION".to_string(), modulation.to_string()));
+            }
+            vec![Transponder { delivery_system: delivery_system.to_string(), frequency, parameters }]
+        },
+        None => match multiplexes_at(frequency, &channels_of_channels_file()) {
+            Ok(transponders) => transponders,
+            Err(message) => {
+                error!("{}", message);
+                process::exit(exitcode::DATAERR);
+            },
+        },
+    }
+}
+
+/// Do what the options that work on the channels file rather than record ask, and exit.
+/// Returns if there are none of them.
+fn run_channels_file_modes(matches: &ArgMatches) {
+    let conflicts = if matches.is_present("replace") { Conflicts::Replace } else { Conflicts::Skip };
+    if let Some(vdr_path) = matches.value_of("import_vdr") {
+        import(Path::new(vdr_path), read_vdr(Path::new(vdr_path)), conflicts);
+    }
+    if let Some(path) = matches.value_of("import") {
+        import(Path::new(path), read_scan(Path::new(path)), conflicts);
+    }
+    if let Some(path) = matches.value_of("scan") {
+        let frontends = scanning_frontends(matches);
+        scan_and_import(Path::new(path), frontends, matches.value_of("lnb"), conflicts);
+    }
+    if matches.is_present("list_multiplexes") {
+        for line in multiplex_list(&channels_of_channels_file()) {
+            println!("{}", line);
+        }
+        process::exit(exitcode::OK);
+    }
+    if matches.is_present("scan_update") {
+        update_and_import(scanning_frontends(matches), matches.value_of("lnb"), conflicts);
+    }
+    if matches.is_present("scan_multiplex") {
+        let frontends = scanning_frontends(matches);
+        scan_transponders_and_import(&multiplex_transponders(matches), frontends, matches.value_of("lnb"), conflicts);
+    }
+}
+
+/// The frontends to try the recording on, in order, and their names. Those that cannot
+/// tune the channel, or cannot be opened, are left out. Exits if there are none.
+fn recording_frontends(matches: &ArgMatches, channel: &str, required_delivery_system: Option<DeliverySystem>) -> (Vec<FrontendId>, HashMap<FrontendId, String>) {
+    let frontend_id = matches.value_of("frontend_id").map(|fei| fei.parse::<FrontendId>().unwrap());
+    let frontend = match &frontend_id {
+        Some(fei) => fei.frontend,
+        None => matches.value_of("frontend").unwrap().parse::<u8>().expect("Couldn't parse frontend value as a positive integer."),
+    };
+    let is_auto = frontend_id.is_none() && matches.value_of("adapter").unwrap() == "auto";
+    let candidates = match (&frontend_id, matches.value_of("adapter").unwrap()) {
+        (Some(fei), _) => vec![fei.clone()],
+        // Multi-standard cards have a frontend for each delivery system, so unless told
+        // which frontend to use, any frontend that can tune the channel will do.
+        (None, "auto") => auto_frontends(if matches.occurrences_of("frontend") > 0 || required_delivery_system.is_none() { Some(frontend) } else { None }),
+        (None, list) => parse_adapter_list(list).unwrap().into_iter().map(|adapter| FrontendId { adapter, frontend }).collect(),
+    };
+    if candidates.is_empty() {
+        error!("There are no adapters with frontend {} installed.", frontend);
+        process::exit(exitcode::UNAVAILABLE);
+    }
+    // Rather than have tuning fail with an obscure GStreamer error, say why.
+    let capabilities = installed_frontends().into_iter()
+        .filter_map(|fei| delivery_systems(&fei).ok().map(|delivery_systems| (fei, delivery_systems)))
+        .collect::<HashMap<_, _>>();
+    let (candidates, reasons) = split_by_compatibility(candidates, required_delivery_system, &capabilities);
+    for reason in &reasons {
+        if is_auto { debug!("{}", reason); } else { error!("{}", reason); }
+    }
+    if candidates.is_empty() {
+        if is_auto {
+            error!("There are no frontends installed that can tune '{}', a {} channel.", channel, required_delivery_system.unwrap());
+        }
+        process::exit(exitcode::UNAVAILABLE);
+    }
+    let candidates = candidates.into_iter().filter(|fei| match inaccessibility_reason(fei) {
+        Some(reason) => {
+            error!("{}", reason);
+            false
+        },
+        None => true,
+    }).collect::<Vec<_>>();
+    if candidates.is_empty() {
+        process::exit(exitcode::NOPERM);
+    }
+    // Read whilst the frontends are free, the names are needed for messages about them later.
+    let display_names = candidates.iter().map(|fei| (fei.clone(), read_display_name(fei))).collect::<HashMap<_, _>>();
+    (candidates, display_names)
+}
+
+/// Start the thread that ends the recording when its time is up, or the event followed
+/// has finished or overrun, reporting the progress and pinging the systemd watchdog.
+fn spawn_duration_thread(control: &Arc<RecordingControl>, notifier: &Option<Arc<Notifier>>, max_overrun: chrono::Duration, json_output: bool) {
+    let watchdog_interval = if notifier.is_some() { watchdog_interval() } else { None };
+    thread::spawn({
+        let control = control.clone();
+        let notifier = notifier.clone();
+        move || {
+            let mut last_report = time::Instant::now();
+            let mut last_watchdog_ping = time::Instant::now();
+            loop {
+                let is_changed = {
+                    let timer = control.timer.lock().unwrap();
+                    let (_timer, result) = control.timer_changed.wait_timeout(timer, TIMER_POLL_INTERVAL).unwrap();
+                    !result.timed_out()
+                };
+                let now = time::Instant::now();
+                if let Some(follower) = &*control.event_follower.lock().unwrap() {
+                    if follower.state == EventState::Finished {
+                        break;
+                    }
+                    if follower.is_overrun(chrono::Utc::now().naive_utc(), max_overrun) {
+                        warn!("Event {:?} has overrun its scheduled end by more than {} minutes, stopping.", follower.event_id, max_overrun.num_minutes());
+                        break;
+                    }
+                }
+                let timer = control.timer.lock().unwrap();
+                if timer.remaining(now) == time::Duration::from_secs(0) {
+                    break;
+                }
+                if is_changed || now.duration_since(last_report) >= PROGRESS_REPORT_INTERVAL {
+                    info!("{}", timer.progress_line(now));
+                    emit(json_output, &RecordingEvent::Progress {
+                        elapsed_seconds: timer.recorded(now).as_secs(),
+                        remaining_seconds: timer.remaining(now).as_secs(),
+                        bytes: control.bytes_written(),
+                        paused: timer.is_paused(),
+                    });
+                    notify_systemd(&notifier, &format!("STATUS={}", timer.progress_line(now)));
+                    last_report = now;
+                }
+                // Ping at half the interval as sd_watchdog_enabled(3) suggests. Only ping
+                // when the stream is flowing, or when paused deliberately, so that systemd
+                // can restart a stalled recording.
+                if let Some(interval) = watchdog_interval {
+                    if now.duration_since(last_watchdog_ping) >= interval / 2 &&
+                        (timer.is_paused() || control.is_stream_flowing(now, interval)) {
+                        notify_systemd(&notifier, "WATCHDOG=1");
+                        last_watchdog_ping = now;
+                    }
+                }
+            }
+            request_eos(&control);
+        }
+    });
+}
+
+/// Start the thread reporting the frontend statistics dvbsrc posts, every `interval`.
+fn spawn_stats_thread(control: &Arc<RecordingControl>, interval: time::Duration, json_output: bool) {
+    thread::spawn({
+        let control = control.clone();
+        move || loop {
+            thread::sleep(interval);
+            if let Some(stats) = *control.frontend_stats.lock().unwrap() {
+                debug!("{}", stats.line());
+                emit(json_output, &RecordingEvent::Stats {
+                    signal: stats.signal,
+                    snr: stats.snr,
+                    ber: stats.ber,
+                    unc: stats.unc,
+                    lock: stats.lock,
+                });
+            }
+        }
+    });
+}
+
+/// Start the thread acting on the signals sent to the process.
+fn spawn_signal_thread(control: &Arc<RecordingControl>, extend_step: time::Duration) {
+    let mut signals = Signals::new(&[SIGINT, SIGTERM, SIGHUP, SIGUSR1, SIGUSR2, SIGRTMIN(), SIGRTMIN() + 1]).expect("Error setting signal handlers.");
+    thread::spawn({
+        let control = control.clone();
+        move || {
+            let mut last_signal_time: Option<time::Instant> = None;
+            for signal in signals.forever() {
+                handle_signal(signal, &control, extend_step, &mut last_signal_time);
+            }
+        }
+    });
+}
+
+/// Reserve the frontend for the recording, taking the lock other programs see, and have
+/// Me TV hand the frontend over if it is using it.
+fn acquire_frontend(reservations: &Reservations, fei: &FrontendId, channel: &str, lock_holder: &LockHolder) -> Result<Lease, String> {
+    let purpose = Purpose::Recording { channel: channel.to_string() };
+    let lease = reservations.reserve_with_lock(fei, purpose, &lock_directory(), lock_holder).map_err(|e| format!("Cannot record, {}.", e))?;
+    match acquire_frontend_from_gui(fei.adapter, fei.frontend, HANDOVER_GRACE_PERIOD) {
+        Handover::NotInUse => {},
+        Handover::Released => info!("Me TV released adapter {} frontend {} for the recording.", fei.adapter, fei.frontend),
+        Handover::TimedOut => return Err(format!("Me TV is using adapter {} frontend {} and did not release it.", fei.adapter, fei.frontend)),
+    }
+    Ok(lease)
+}
 
-A channel name and a duration must be provided.
-")
-        .arg(Arg::with_name("adapter")
-            .short("a")
-            .long("adapter")
-            .value_name("NUMBER")
-            .help("Sets the adapter number to use.")
-            .takes_value(true)
-            .default_value("0"))
-        .arg(Arg::with_name("frontend")
-            .short("f")
-            .long("frontend")
-            .value_name("NUMBER")
-            .help("Sets the frontend number to use.")
-            .takes_value(true)
-            .default_value("0"))
-        .arg(Arg::with_name("channel")
-            .short("c")
-            .long("channel")
-            .value_name("CHANNEL")
-            .help("Sets the channel name, must be specified, no default.")
-            .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("duration")
-            .short("d")
-            .long("duration")
-            .value_name("TIME")
-            .help("Sets the duration of recording in minutes, must be specified, no default.")
-            .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("output")
-            .short("o")
-            .long("output")
-            .value_name("PATH")
-            .help("Path to output file, must be specified, no default.")
-            .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("verbose")
-            .short("v")
-            .long("verbose")
-            .help("sets verbose mode"))
-        .get_matches();
-    let be_verbose = matches.is_present("verbose");
-    let adapter = matches.value_of("adapter").unwrap().parse::<u8>().expect("Couldn't parse adapter value as a positive integer.");
-    let frontend = matches.value_of("frontend").unwrap().parse::<u8>().expect("Couldn't parse frontend value as a positive integer.");
-    let channel = matches.value_of("channel").unwrap();
-    let duration = matches.value_of("duration").unwrap().parse::<u32>().expect("Couldn't parse the provided duration as a positive integer.");
-    let output_path = matches.value_of("output").unwrap();
-    if be_verbose {
-        println!("Recording channel '{}' for {} minutes on adapter {} frontend {}.", channel, duration, adapter, frontend);
-    }
-    //
-    // Construct the GStreamer graph described by:
-    //
-    //    gst-launch-1.0 -e uridecodebin uri=dvb://<channel> name=d ! queue ! x264enc ! mp4mux name=m ! filesink location=<output-path> d. ! queue ! avenc_ac3 ! m.
-    //
-    gst::init().unwrap();
-    let pipeline = gst::Pipeline::new(None);
-    let uridecodebin = {
-        let element = gst::ElementFactory::make("uridecodebin", None).expect("cannot make uridecodebin");
-        element.set_property("uri", &format!("dvb://{}", channel)).expect("cannot set uri property on uridecodebin");
-        element.connect("source-setup",  false, {
-            let adapter_number = adapter;
-            let frontend_number = frontend;
-            move |values| {
-                // values[0] .get::<gst::Element>() is an Option on the uridecodebin itself.
-                let element = values[1].get::<gst::Element>()
-                    .expect("Failed to get a handle on the Element being created")
-                    .expect("Option on Element was None");
-                if let Some(element_factory) = element.get_factory() {
-                    if element_factory.get_name() == "dvbbasebin" {
-                        let current_adapter_number = element
-                            .get_property("adapter")
-                            .expect("Could not retrieve adapter number Value")
-                            .get::<i32>()
-                            .expect("Could not get the i32 value from the adapter number Value")
-                            .expect("Option on u32 returned None") as u8;
-                        let current_frontend_number = element
-                            .get_property("frontend")
-                            .expect("Could not retrieve frontend number Value.")
-                            .get::<i32>()
-                            .expect("Could not get the i32 value from the frontend number Value")
-                            .expect ("Option on u32 returned None") as u8;
-                        if current_adapter_number != adapter_number {
-                            element.set_property("adapter", &(adapter_number as i32)).expect("Could not set adapter number on dvbsrc element");
-                        }
-                        if current_frontend_number != adapter_number {
-                            element.set_property("frontend", &(frontend_number as i32)).expect("Could not set frontend number of dvbsrc element");
-                        }
+/// What is needed to record a channel, segment by segment, failing over between frontends.
+struct Recording<'a> {
+    channel: &'a str,
+    tuning: Option<TuningParameters>,
+    is_radio: bool,
+    candidates: Vec<FrontendId>,
+    display_names: HashMap<FrontendId, String>,
+    outputs: Outputs,
+    queue_settings: QueueSettings,
+    stats_interval: Option<time::Duration>,
+    resume_on_error: bool,
+    max_restarts: u32,
+    json_output: bool,
+    is_notifying: bool,
+}
+
+/// How the recording went.
+struct RecordingOutcome {
+    frontend: FrontendId,  // The last one recorded on.
+    captured: time::Duration,
+    is_failed: bool,
+}
+
+/// Record the segments of the recording until it is finished, trying the next frontend
+/// whenever tuning fails and, if resuming on error, starting a new segment after an error.
+fn record_segments(recording: &Recording, control: &Arc<RecordingControl>, notifier: &Option<Arc<Notifier>>, lock_holder: &LockHolder, started_at: chrono::DateTime<chrono::Local>) -> RecordingOutcome {
+    let channel = recording.channel;
+    let candidates = &recording.candidates;
+    // dvbsrc only reports the raw driver values, so the log shows the DVBv5 statistics,
+    // read directly from the frontend, which come with their units.
+    let signal_statuses = recording.stats_interval.map(|_| {
+        let (to_log, from_monitor) = mpsc::channel::<SignalStatus>();
+        thread::spawn(move || {
+            for status in from_monitor {
+                info!("{}", status.line());
+            }
+        });
+        to_log
+    });
+    let mut candidate_index = 0;
+    let mut is_frontend_acquired = false;
+    let mut restarts = 0;
+    let mut captured = time::Duration::from_secs(0);
+    let mut is_failed;
+    let mut had_device_error = false;
+    // This process does not follow frontends disappearing, so there is no grace period.
+    let reservations = Reservations::new(time::Duration::from_secs(0));
+    let mut _frontend_lease = None;
+    let mut _signal_monitor = None;
+    loop {
+        let FrontendId { adapter, frontend } = candidates[candidate_index].clone();
+        if !is_frontend_acquired {
+            // Release the lease on the previous adapter, if any, before trying the next.
+            _signal_monitor = None;
+            _frontend_lease = None;
+            match acquire_frontend(&reservations, &candidates[candidate_index], channel, lock_holder) {
+                Ok(lease) => _frontend_lease = Some(lease),
+                Err(message) => {
+                    error!("{}", message);
+                    if candidate_index + 1 < candidates.len() {
+                        candidate_index += 1;
+                        continue;
                     }
+                    notify_desktop(recording.is_notifying, &format!("Recording of {} failed", channel), &message);
+                    emit(recording.json_output, &RecordingEvent::Error { message });
+                    process::exit(exitcode::TEMPFAIL);
+                },
+            }
+            if let (Some(interval), Some(to_log)) = (recording.stats_interval, &signal_statuses) {
+                match SignalMonitor::start(&candidates[candidate_index], interval, to_log.clone()) {
+                    Ok(monitor) => _signal_monitor = Some(monitor),
+                    Err(e) => warn!("Cannot monitor the signal on adapter {} frontend {}, {}.", adapter, frontend, e),
                 }
-                None
             }
-        }).expect("Could not connect a handler to the source-setup signal.");
-        element
-    };
-    let mp4mux = gst::ElementFactory::make("mp4mux", None).expect("cannot make mp4mux");
-    let filesink = {
-        let element = gst::ElementFactory::make("filesink", None).expect("cannot make filesrc");
-        element.set_property("location", &output_path).expect("cannot set location for filesrc");
-        element
+            is_frontend_acquired = true;
+        }
+        let segment_path = recording.outputs.file.as_ref().map(|output_path| {
+            let mut segments = control.segments.lock().unwrap();
+            let segment_path = segment_output_path(output_path, segments.len() + 1);
+            segments.push(segment_path.clone());
+            segment_path
+        });
+        *control.segment_data_since.lock().unwrap() = None;
+        let outputs = Outputs { file: segment_path.clone(), ..recording.outputs.clone() };
+        let pipeline = build_pipeline(channel, &recording.tuning, adapter, frontend, &outputs, recording.is_radio, &recording.queue_settings, control, notifier);
+        *control.pipeline.lock().unwrap() = Some(pipeline.clone());
+        pipeline.set_state(gst::State::Playing).unwrap();
+        // A stop may have been requested while there was no pipeline to send the EOS to.
+        if control.is_stop_requested() {
+            pipeline.send_event(gst::event::Eos::new());
+        }
+        let segment_end = run_bus_loop(&pipeline, control, recording.json_output, channel, adapter, frontend);
+        is_failed = segment_end == SegmentEnd::Error || segment_end == SegmentEnd::NotTuned;
+        had_device_error |= is_failed;
+        *control.pipeline.lock().unwrap() = None;
+        shut_down_pipeline(&pipeline);
+        if segment_end == SegmentEnd::NotTuned {
+            // Nothing was recorded, so the segment is tried again on the next adapter.
+            if let Some(segment_path) = segment_path {
+                control.segments.lock().unwrap().pop();
+                if let Err(e) = fs::remove_file(&segment_path) {
+                    debug!("Could not remove the empty file {}: {}", segment_path, e);
+                }
+            }
+            if candidate_index + 1 < candidates.len() && !control.is_stop_requested() {
+                candidate_index += 1;
+                info!("Could not tune, trying {}.", frontend_name(&recording.display_names, &candidates[candidate_index]));
+                is_frontend_acquired = false;
+                continue;
+            }
+            error!("Could not tune on any of {}.", frontend_list(candidates, &recording.display_names));
+            break;
+        }
+        let now = time::Instant::now();
+        if let Some(since) = *control.segment_data_since.lock().unwrap() {
+            captured += now.duration_since(since);
+        }
+        let is_time_remaining = control.timer.lock().unwrap().remaining(now) > time::Duration::from_secs(0);
+        if segment_end == SegmentEnd::Error && recording.resume_on_error && is_time_remaining && !control.is_stop_requested() {
+            if restarts < recording.max_restarts {
+                restarts += 1;
+                let message = format!("Restarting the recording into a new segment, restart {} of {}.", restarts, recording.max_restarts);
+                warn!("{}", message);
+                emit(recording.json_output, &RecordingEvent::Warning { message });
+                thread::sleep(RESTART_DELAY);
+                continue;
+            }
+            error!("Not restarting the recording, there have already been {} restarts.", recording.max_restarts);
+        }
+        break;
+    }
+    if had_device_error {
+        log_device_history(started_at);
+    }
+    RecordingOutcome { frontend: candidates[candidate_index].clone(), captured, is_failed }
+}
+
+/// Write the files that go alongside the recording: the metadata sidecars, the chapters,
+/// and a thumbnail taken at `thumbnail_position` if asked for one.
+fn write_companion_files(
+    recording: &Recording,
+    control: &RecordingControl,
+    outcome: &RecordingOutcome,
+    started_at: chrono::DateTime<chrono::Local>,
+    sidecar_formats: Vec<SidecarFormat>,
+    thumbnail_position: Option<time::Duration>,
+) {
+    let channel = recording.channel;
+    let output_path = match &recording.outputs.file {
+        Some(output_path) => output_path,
+        None => return,
     };
-    pipeline.add_many(&[&uridecodebin, &mp4mux, &filesink]).expect("could not add elements to pipeline");
-    gst::Element::link_many(&[&mp4mux, &filesink]).expect("could not link elements in pipeline");
-    // Heed the warnings about strong references, circular references and memory leaks.
-    let pipeline_weak_ref = pipeline.downgrade();
-    uridecodebin.connect_pad_added(move |d_b, src_pad| {
-        let pipeline = match pipeline_weak_ref.upgrade() {
-            Some(pipeline) => pipeline,
-            None => return,
+    if !sidecar_formats.is_empty() {
+        let (title, description) = match &*control.event_follower.lock().unwrap() {
+            Some(follower) => (follower.title.clone(), follower.description.clone()),
+            None => (None, None),
         };
-        let (is_audio, is_video) = {
-            let media_type = src_pad.get_current_caps().and_then(|caps| {
-                caps.get_structure(0).map(|s| {
-                    let name = s.get_name();
-                    (name.starts_with("audio/"), name.starts_with("video/"))
-                })
-            });
-            match media_type {
-                Some(media_type) => media_type,
-                None => {
-                    gst_element_warning!(d_b, gst::CoreError::Negotiation, ("Failed to get media type from pad {}", src_pad.get_name()));
-                    return;
-                },
-            }
+        let metadata = RecordingMetadata {
+            channel: channel.to_string(),
+            start: started_at.to_rfc3339(),
+            end: chrono::Local::now().to_rfc3339(),
+            captured_seconds: outcome.captured.as_secs(),
+            adapter: outcome.frontend.adapter,
+            frontend: outcome.frontend.frontend,
+            title,
+            description,
         };
-        let insert_sink = |is_audio, is_video| -> Result<(), ()> {
-            if is_audio && is_video { panic!("sink is both audio and video at the same time"); }
-            if ! is_audio && ! is_video { return Ok(()); }
-            let queue = gst::ElementFactory::make("queue", None).expect("cannot make a queue");
-            let new_element = if is_audio {
-                gst::ElementFactory::make("avenc_ac3", None).expect("cannot make a avenc_ac3")
-            } else {
-                gst::ElementFactory::make("x264enc", None).expect("cannot make a x264enc")
-            };
-            let elements = &[&queue, &new_element];
-            pipeline.add_many(elements).expect("could not add elements to pipeline");
-            gst::Element::link_many(elements).expect("could not link elements in pipeline");
-            for e in elements {
-                e.sync_state_with_parent().expect("could not sync state of elements with parent");
+        for format in sidecar_formats {
+            match write_sidecar(output_path, format, &metadata) {
+                Ok(path) => debug!("Wrote metadata to {}.", path.display()),
+                Err(e) => warn!("Could not write the {:?} metadata file: {}", format, e),
             }
-            let sink_pad = queue.get_static_pad("sink").expect("video queue has no sink pad");
-            src_pad.link(&sink_pad).expect("linking src_pad to sink_pad of new queue failed");
-            let new_element_src_pad = new_element.get_static_pad("src").expect("new element has no src pad");
-            let sink_pad_template = if is_audio { "audio_%u" } else { "video_%u" };
-            let mp4mux_sink_pad = mp4mux.get_request_pad(sink_pad_template).expect(&format!("mp4mux has no {} sink pad", sink_pad_template));
-            new_element_src_pad.link(&mp4mux_sink_pad).expect("linking new element to mp4mux failed.");
-            Ok(())
-        };
-        if let Err(err) = insert_sink(is_audio, is_video) {
-            //  TODO why are the parentheses needed around the string?
-            gst_element_error!(d_b, gst::LibraryError::Failed, ("Failed to insert sink"), ["{:?}", err]);
         }
-    });
-    pipeline.set_state(gst::State::Playing).unwrap();
-    thread::spawn({
-        let pipeline_weak_ref = pipeline.downgrade();
-        move || {
-            thread::sleep(time::Duration::from_secs((duration * 60).into()));
-            let pipeline = match pipeline_weak_ref.upgrade() {
-                Some(pipeline) => pipeline,
-                None => panic!("no access to the pipeline"),
-            };
-            pipeline.send_event(gst::event::Eos::new());
+    }
+    if let Some(chapter_marks) = &*control.chapter_marks.lock().unwrap() {
+        if !chapter_marks.chapters().is_empty() {
+            match write_chapters(output_path, chapter_marks.chapters(), outcome.captured) {
+                Ok(path) => info!("Wrote {} chapters to {}.", chapter_marks.chapters().len(), path.display()),
+                Err(e) => warn!("Could not write the chapters file: {}", e),
+            }
+        }
+    }
+    if let Some(position) = thumbnail_position {
+        let thumbnail = thumbnail_path(output_path);
+        match write_thumbnail(Path::new(output_path), &thumbnail, position) {
+            Ok(()) => debug!("Wrote the thumbnail {}.", thumbnail.display()),
+            Err(e) => warn!("Could not make a thumbnail of {}: {}", output_path, e),
         }
+    }
+}
+
+fn main() {
+    let matches = command_line().get_matches();
+    let json_output = matches.is_present("json");
+    init_logging(matches.value_of("log_level"), matches.is_present("verbose"));
+    if let Some(path) = matches.value_of("dvb_path") {
+        set_dvb_devices(DvbDevices::new(path));
+    }
+    run_channels_file_modes(&matches);
+    let tuning = matches.value_of("frequency").map(|frequency| TuningParameters {
+        delivery_system: matches.value_of("delivery_system").unwrap().to_string(),
+        frequency: frequency.parse().unwrap(),
+        bandwidth_hz: matches.value_of("bandwidth").map(|b| b.parse().unwrap()),
+        modulation: matches.value_of("modulation").map(String::from),
+        service_id: matches.value_of("service_id").unwrap().parse().unwrap(),
     });
-    ctrlc::set_handler({
-        let pipeline_weak_ref = pipeline.downgrade();
-        move || {
-            let pipeline = match pipeline_weak_ref.upgrade() {
-                Some(pipeline) => pipeline,
-                None => panic!("no access to the pipeline"),
-            };
-            pipeline.send_event(gst::event::Eos::new());
+    let channel = match &tuning {
+        Some(tuning) => matches.value_of("name").map(String::from).unwrap_or_else(|| format!("Service {}", tuning.service_id)),
+        None => matches.value_of("channel").unwrap().to_string(),
+    };
+    let channel = channel.as_str();
+    // Me TV tunes the copy of a service its channel editor says, from a channels file of
+    // its own, unless the channels file to use has been given.
+    if tuning.is_none() && env::var_os(CHANNELS_FILE_VARIABLE).is_none() {
+        let preferred = preferred_copies_file_path();
+        if read_channel_names(&preferred).map_or(false, |names| names.iter().any(|name| name == channel)) {
+            info!("Tuning the copy of {} Me TV uses, from {}.", channel, preferred.display());
+            env::set_var(CHANNELS_FILE_VARIABLE, &*preferred);
         }
-    }).expect("Error setting ctrl-c handler.");
-    let bus = pipeline.get_bus().expect("Pipeline without bus. Shouldn't happen!");
-    while let Some(msg) = bus.timed_pop(gst::CLOCK_TIME_NONE) {
-        use gst::MessageView;
-        match msg.view() {
-            MessageView::Eos(..) => break,
-            MessageView::Error(err) => {
-                pipeline.set_state(gst::State::Null).unwrap();
-                println!("Error: {} {} {} {}",
-                         err.get_src().map(|s| s.get_path_string()).unwrap_or_else(|| glib::GString::from("None")),
-                         err.get_error().to_string(),
-                         err.get_debug().unwrap_or_else(|| String::from("None")),
-                         err.get_error(),
-                );
-                break
+    }
+    if matches.is_present("emit_channels_line") {
+        print!("{}", tuning.as_ref().unwrap().channels_file_entry(channel));
+        process::exit(exitcode::OK);
+    }
+    let is_radio = match &tuning {
+        Some(_) => matches.is_present("radio"),
+        None => read_is_radio(&channels_file_path(), channel).unwrap_or(false),
+    };
+    let required_delivery_system = match &tuning {
+        Some(tuning) => tuning.delivery_system.parse::<DeliverySystem>().ok(),
+        None => read_delivery_system(&channels_file_path(), channel),
+    };
+    let (candidates, display_names) = recording_frontends(&matches, channel, required_delivery_system);
+    let FrontendId { adapter, frontend } = candidates[0].clone();
+    let duration = matches.value_of("duration").map(|d| d.parse::<u32>().expect("Couldn't parse the provided duration as a positive integer."));
+    let event_id = matches.value_of("event_id").map(|e| e.parse::<u16>().expect("Couldn't parse the event id as a positive integer."));
+    let is_following_eit = event_id.is_some() || matches.is_present("follow_eit");
+    let max_overrun = chrono::Duration::minutes(matches.value_of("max_overrun").unwrap().parse::<i64>().expect("Couldn't parse the maximum overrun as an integer."));
+    let sidecar_formats = matches.values_of("sidecar").unwrap().filter_map(SidecarFormat::from_name).collect::<Vec<_>>();
+    let is_notifying = matches.is_present("notify");
+    let queue_settings = QueueSettings {
+        max_bytes: matches.value_of("buffer_size").unwrap().parse::<u32>().unwrap().saturating_mul(1024 * 1024),
+        max_time: time::Duration::from_secs(matches.value_of("buffer_time").unwrap().parse().unwrap()),
+        leaky: matches.value_of("buffer_leaky").unwrap().to_string(),
+    };
+    let extend_step = time::Duration::from_secs(matches.value_of("extend_step").unwrap().parse::<u64>().unwrap() * 60);
+    let stats_interval = if matches.is_present("stats") {
+        Some(time::Duration::from_secs(matches.value_of("stats").map(|s| s.parse::<u64>().unwrap()).unwrap_or(1).max(1)))
+    } else {
+        None
+    };
+    let resume_on_error = matches.is_present("resume_on_error");
+    let max_restarts = matches.value_of("max_restarts").unwrap().parse::<u32>().expect("Couldn't parse the maximum number of restarts as a positive integer.");
+    let duration_limit = match duration {
+        Some(d) => time::Duration::from_secs(u64::from(d) * 60),
+        None => EIT_TIME_LIMIT,
+    };
+    let outputs = Outputs {
+        file: matches.value_of("output").map(String::from),
+        stream: matches.value_of("stream").map(|url| StreamTarget::parse(url).unwrap()),
+        hls: matches.value_of("hls").map(|directory| HlsOutput {
+            directory: directory.to_string(),
+            segment_seconds: matches.value_of("hls_segment_duration").unwrap().parse().unwrap(),
+            playlist_length: matches.value_of("hls_playlist_length").unwrap().parse().unwrap(),
+            keep_all: matches.is_present("hls_keep_all"),
+        }),
+    };
+    // What to tell the user the output of the recording is.
+    let output_description = match (&outputs.file, matches.value_of("stream"), &outputs.hls) {
+        (Some(output_path), _, _) => output_path.clone(),
+        (None, Some(url), _) => url.to_string(),
+        (None, None, Some(hls)) => hls.playlist_location(),
+        (None, None, None) => unreachable!("clap requires an output"),
+    };
+    if matches.is_present("dry_run") {
+        gst::init().unwrap();
+        dry_run(channel, &tuning, adapter, frontend, &outputs, is_radio);
+    }
+    if let Some(hls) = &outputs.hls {
+        if let Err(e) = fs::create_dir_all(&hls.directory) {
+            error!("Could not create the HLS directory {}: {}", hls.directory, e);
+            process::exit(exitcode::CANTCREAT);
+        }
+    }
+    let thumbnail_position = if !matches.is_present("thumbnail") {
+        None
+    } else if is_radio {
+        info!("'{}' is a radio channel, so there is no thumbnail.", channel);
+        None
+    } else {
+        Some(time::Duration::from_secs(matches.value_of("thumbnail_position").unwrap().parse().unwrap()))
+    };
+    let service_id = match &tuning {
+        Some(tuning) => Some(tuning.service_id),
+        None => read_service_id(&channels_file_path(), channel),
+    };
+    let event_follower = if is_following_eit {
+        match service_id {
+            Some(service_id) => Some(EventFollower::new(service_id, event_id)),
+            None => {
+                let message = format!("Could not find the service id of channel '{}', which is needed to follow the EIT.", channel);
+                error!("{}", message);
+                notify_desktop(is_notifying, &format!("Recording of {} failed", channel), &message);
+                emit(json_output, &RecordingEvent::Error { message });
+                process::exit(exitcode::DATAERR);
             },
-            MessageView::StateChanged(s) => {
-                if be_verbose {
-                    println!(
-                        "State changed from {:?}: {:?} -> {:?} ({:?})",
-                        s.get_src().map(|s| s.get_path_string()),
-                        s.get_old(),
-                        s.get_current(),
-                        s.get_pending()
-                    );
-                }
+        }
+    } else {
+        None
+    };
+    let started_at = chrono::Local::now();
+    info!("Recording channel '{}' for {} minutes on {}.", channel, duration_limit.as_secs() / 60, frontend_name(&display_names, &candidates[0]));
+    if let Some(tuning) = &tuning {
+        info!("Tuning explicitly: {}.", tuning.description());
+    }
+    emit(json_output, &RecordingEvent::Started {
+        channel: channel.to_string(),
+        adapter,
+        frontend,
+        output: output_description.clone(),
+        duration_seconds: duration_limit.as_secs(),
+    });
+    if candidates.len() > 1 {
+        info!("If tuning fails, {} will be tried in turn.", frontend_list(&candidates[1..], &display_names));
+    }
+    gst::init().unwrap();
+    let notifier = match Notifier::from_environment() {
+        Ok(notifier) => notifier.map(Arc::new),
+        Err(e) => {
+            warn!("Could not connect to the systemd notification socket: {}", e);
+            None
+        },
+    };
+    let control = Arc::new(RecordingControl::new(duration_limit));
+    *control.event_follower.lock().unwrap() = event_follower;
+    if matches.is_present("chapters") {
+        match service_id {
+            Some(service_id) => *control.chapter_marks.lock().unwrap() = Some(ChapterMarks::new(service_id)),
+            None => warn!("Could not find the service id of channel '{}', so there will be no chapters.", channel),
+        }
+    }
+    spawn_duration_thread(&control, &notifier, max_overrun, json_output);
+    if let Some(interval) = stats_interval {
+        spawn_stats_thread(&control, interval, json_output);
+    }
+    start_dbus_service(&control, channel);
+    spawn_signal_thread(&control, extend_step);
+    let lock_holder = LockHolder {
+        pid: process::id(),
+        channel: channel.to_string(),
+        end: (started_at + chrono::Duration::from_std(duration_limit).unwrap()).to_rfc3339(),
+    };
+    let recording = Recording {
+        channel,
+        tuning,
+        is_radio,
+        candidates,
+        display_names,
+        outputs,
+        queue_settings,
+        stats_interval,
+        resume_on_error,
+        max_restarts,
+        json_output,
+        is_notifying,
+    };
+    let outcome = record_segments(&recording, &control, &notifier, &lock_holder, started_at);
+    let elapsed_seconds = control.timer.lock().unwrap().recorded(time::Instant::now()).as_secs();
+    let segments = control.segments.lock().unwrap().clone();
+    let bytes = control.bytes_written();
+    let captured = outcome.captured;
+    info!("Recording finished on adapter {} frontend {}, {} bytes written, {} captured, in {} segment(s): {}.",
+          outcome.frontend.adapter, outcome.frontend.frontend, bytes, format_minutes_seconds(captured), segments.len(), segments.join(", "));
+    notify_desktop(
+        is_notifying,
+        &format!("Recording of {} {}", channel, if outcome.is_failed { "failed" } else { "finished" }),
+        &recording_notification_body(channel, captured.as_secs(), &output_description),
+    );
+    write_companion_files(&recording, &control, &outcome, started_at, sidecar_formats, thumbnail_position);
+    if outcome.is_failed {
+        let message = format!("Recording of {} failed, {} bytes written, {} captured.", channel, bytes, format_minutes_seconds(captured));
+        error!("{}", message);
+        emit(json_output, &RecordingEvent::Error { message });
+        process::exit(exitcode::UNAVAILABLE);
+    }
+    emit(json_output, &RecordingEvent::Finished {
+        output: output_description,
+        bytes,
+        elapsed_seconds,
+        captured_seconds: captured.as_secs(),
+        segments,
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Read;
+
+    use me_tv::signal_monitor::Measurement;
+
+    #[test]
+    fn first_signal_is_not_a_forced_quit() {
+        assert!(!is_forced_quit(None, time::Instant::now()));
+    }
+
+    #[test]
+    fn second_signal_in_grace_period_is_a_forced_quit() {
+        let first = time::Instant::now();
+        assert!(is_forced_quit(Some(first), first + time::Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn second_signal_after_grace_period_is_not_a_forced_quit() {
+        let first = time::Instant::now();
+        assert!(!is_forced_quit(Some(first), first + FORCE_QUIT_GRACE_PERIOD + time::Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn paused_time_does_not_count_against_the_duration() {
+        let start = time::Instant::now();
+        let mut timer = RecordingTimer::new(time::Duration::from_secs(600), start);
+        timer.pause(start + time::Duration::from_secs(100));
+        assert!(timer.is_paused());
+        assert_eq!(timer.recorded(start + time::Duration::from_secs(400)), time::Duration::from_secs(100));
+        timer.resume(start + time::Duration::from_secs(400));
+        assert!(!timer.is_paused());
+        assert_eq!(timer.remaining(start + time::Duration::from_secs(500)), time::Duration::from_secs(400));
+        assert_eq!(timer.remaining(start + time::Duration::from_secs(1000)), time::Duration::from_secs(0));
+    }
+
+    #[test]
+    fn progress_line_shows_paused_state() {
+        let start = time::Instant::now();
+        let mut timer = RecordingTimer::new(time::Duration::from_secs(3600), start);
+        timer.pause(start + time::Duration::from_secs(90));
+        assert_eq!(timer.progress_line(start + time::Duration::from_secs(200)), "Recorded 01:30 of 60:00 (paused).");
+    }
+
+    /// Check the top level box structure of an MP4 file: the boxes must exactly cover the
+    /// file, and there must be an ftyp, an mdat, and a moov – the last being what is missing
+    /// if the muxer did not see the EOS.
+    fn is_structurally_valid_mp4(path: &std::path::Path) -> bool {
+        let mut data = Vec::new();
+        fs::File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        let mut box_types = Vec::new();
+        let mut position = 0usize;
+        while position + 8 <= data.len() {
+            let mut size = u32::from_be_bytes([data[position], data[position + 1], data[position + 2], data[position + 3]]) as usize;
+            let box_type = String::from_utf8_lossy(&data[position + 4..position + 8]).to_string();
+            if size == 1 {
+                if position + 16 > data.len() { return false; }
+                let mut large_size = [0u8; 8];
+                large_size.copy_from_slice(&data[position + 8..position + 16]);
+                size = u64::from_be_bytes(large_size) as usize;
+            } else if size == 0 {
+                size = data.len() - position;
             }
-            _ => (),
+            if size < 8 { return false; }
+            box_types.push(box_type);
+            position += size;
+        }
+        position == data.len() && ["ftyp", "mdat", "moov"].iter().all(|t| box_types.iter().any(|b| b == t))
+    }
+
+    #[test]
+    fn recording_stopped_by_signal_is_finalised() {
+        gst::init().unwrap();
+        // videotestsrc stands in for the DVB source, skip if the needed plugins are missing.
+        let pipeline = match gst::parse_launch("videotestsrc is-live=true ! x264enc ! mp4mux ! filesink name=sink") {
+            Ok(p) => p.downcast::<gst::Pipeline>().unwrap(),
+            Err(_) => return,
+        };
+        let output = tempfile::Builder::new().suffix(".mp4").tempfile().unwrap();
+        pipeline.get_by_name("sink").unwrap().set_property("location", &output.path().to_str().unwrap()).unwrap();
+        let control = RecordingControl::new(time::Duration::from_secs(600));
+        pipeline.set_state(gst::State::Playing).unwrap();
+        thread::sleep(time::Duration::from_secs(2));
+        *control.pipeline.lock().unwrap() = Some(pipeline.clone());
+        let mut last_signal_time = None;
+        handle_signal(SIGTERM, &control, time::Duration::from_secs(15 * 60), &mut last_signal_time);
+        let bus = pipeline.get_bus().unwrap();
+        let msg = bus.timed_pop_filtered(gst::ClockTime::from_seconds(EOS_TIMEOUT.as_secs()), &[gst::MessageType::Eos, gst::MessageType::Error]);
+        assert_eq!(msg.map(|m| m.get_type()), Some(gst::MessageType::Eos));
+        shut_down_pipeline(&pipeline);
+        assert!(is_structurally_valid_mp4(output.path()));
+    }
+
+    #[test]
+    fn eos_is_overdue_only_after_the_timeout() {
+        let control = RecordingControl::new(time::Duration::from_secs(600));
+        let now = time::Instant::now();
+        assert!(!control.is_eos_overdue(now));
+        *control.eos_requested_at.lock().unwrap() = Some(now);
+        assert!(!control.is_eos_overdue(now + time::Duration::from_secs(1)));
+        assert!(control.is_eos_overdue(now + EOS_TIMEOUT + time::Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn pipeline_description_includes_uri_tuning_and_location() {
+        assert_eq!(
+            pipeline_description("BBC NEWS", &None, 1, 0, &Outputs { file: Some("/tmp/news.mp4".to_string()), ..Outputs::default() }, false),
+            "uridecodebin uri=\"dvb://BBC NEWS\" source::adapter=1 source::frontend=0 name=d ! queue ! x264enc ! mp4mux name=m ! filesink location=\"/tmp/news.mp4\" d. ! queue ! avenc_ac3 ! m."
+        );
+    }
+
+    #[test]
+    fn pipeline_description_with_explicit_tuning_uses_dvbbasebin() {
+        let tuning = TuningParameters {
+            delivery_system: "DVBT2".to_string(),
+            frequency: 490000000,
+            bandwidth_hz: Some(8000000),
+            modulation: Some("QAM/256".to_string()),
+            service_id: 4164,
+        };
+        assert_eq!(
+            pipeline_description("Service 4164", &Some(tuning), 1, 0, &Outputs { file: Some("/tmp/news.mp4".to_string()), ..Outputs::default() }, false),
+            "dvbbasebin adapter=1 frontend=0 delsys=DVBT2 frequency=490000000 bandwidth-hz=8000000 modulation=\"QAM 256\" program-numbers=4164 ! decodebin name=d ! queue ! x264enc ! mp4mux name=m ! filesink location=\"/tmp/news.mp4\" d. ! queue ! avenc_ac3 ! m."
+        );
+    }
+
+    #[test]
+    fn pipeline_description_with_file_and_stream_tees() {
+        let outputs = Outputs {
+            file: Some("/tmp/news.mp4".to_string()),
+            stream: Some(StreamTarget::Udp { host: "192.168.1.2".to_string(), port: 5000 }),
+            hls: None,
+        };
+        assert_eq!(
+            pipeline_description("BBC NEWS", &None, 0, 0, &outputs, false),
+            "uridecodebin uri=\"dvb://BBC NEWS\" source::adapter=0 source::frontend=0 name=d ! queue ! x264enc ! tee name=vt ! queue ! mp4mux name=m ! filesink location=\"/tmp/news.mp4\" vt. ! queue ! h264parse config-interval=-1 ! mpegtsmux name=t ! udpsink host=192.168.1.2 port=5000 d. ! queue ! avenc_ac3 ! tee name=at ! queue ! m. at. ! queue ! t."
+        );
+    }
+
+    #[test]
+    fn pipeline_description_for_radio_has_no_video() {
+        assert_eq!(
+            pipeline_description("BBC Radio 4", &None, 1, 0, &Outputs { file: Some("/tmp/r4.mp4".to_string()), ..Outputs::default() }, true),
+            "uridecodebin uri=\"dvb://BBC Radio 4\" source::adapter=1 source::frontend=0 name=d ! queue ! avenc_ac3 ! mp4mux name=m ! filesink location=\"/tmp/r4.mp4\""
+        );
+        let outputs = Outputs {
+            file: Some("/tmp/r4.mp4".to_string()),
+            stream: Some(StreamTarget::Udp { host: "192.168.1.2".to_string(), port: 5000 }),
+            hls: None,
+        };
+        assert_eq!(
+            pipeline_description("BBC Radio 4", &None, 0, 0, &outputs, true),
+            "uridecodebin uri=\"dvb://BBC Radio 4\" source::adapter=0 source::frontend=0 name=d ! queue ! avenc_ac3 ! tee name=at ! queue ! mp4mux name=m ! filesink location=\"/tmp/r4.mp4\" at. ! queue ! mpegtsmux name=t ! udpsink host=192.168.1.2 port=5000"
+        );
+    }
+
+    #[test]
+    fn hls_keeps_a_window_of_segments_unless_keeping_all() {
+        let hls = HlsOutput { directory: "/tmp/hls".to_string(), segment_seconds: 6, playlist_length: 5, keep_all: false };
+        assert_eq!(hls.limits(), (5, 5));
+        assert_eq!(HlsOutput { keep_all: true, ..hls.clone() }.limits(), (0, 0));
+        assert_eq!(hls.playlist_location(), "/tmp/hls/playlist.m3u8");
+        assert_eq!(
+            pipeline_description("BBC NEWS", &None, 0, 0, &Outputs { hls: Some(hls), ..Outputs::default() }, false),
+            "uridecodebin uri=\"dvb://BBC NEWS\" source::adapter=0 source::frontend=0 name=d ! queue ! x264enc ! h264parse config-interval=-1 ! hlssink2 name=h location=\"/tmp/hls/segment%05d.ts\" playlist-location=\"/tmp/hls/playlist.m3u8\" target-duration=6 playlist-length=5 max-files=5 d. ! queue ! avenc_ac3 ! h."
+        );
+    }
+
+    #[test]
+    fn stream_targets_are_parsed() {
+        assert_eq!(StreamTarget::parse("udp://192.168.1.2:5000"), Ok(StreamTarget::Udp { host: "192.168.1.2".to_string(), port: 5000 }));
+        assert_eq!(StreamTarget::parse("rtp://239.0.0.1:5004"), Ok(StreamTarget::Rtp { host: "239.0.0.1".to_string(), port: 5004 }));
+        assert_eq!(StreamTarget::parse("tcp-listen://:8080"), Ok(StreamTarget::TcpListen { host: "0.0.0.0".to_string(), port: 8080 }));
+        assert!(StreamTarget::parse("udp://:5000").is_err());
+        assert!(StreamTarget::parse("http://host:80").is_err());
+        assert!(StreamTarget::parse("udp://host").is_err());
+    }
+
+    #[test]
+    fn nonexistent_element_factory_is_reported_missing() {
+        gst::init().unwrap();
+        assert_eq!(missing_element_factories(&["filesink", "no_such_element_factory"]), vec!["no_such_element_factory"]);
+    }
+
+    #[test]
+    fn extending_adds_to_the_remaining_time() {
+        let start = time::Instant::now();
+        let mut timer = RecordingTimer::new(time::Duration::from_secs(600), start);
+        timer.extend(time::Duration::from_secs(300));
+        assert_eq!(timer.remaining(start + time::Duration::from_secs(60)), time::Duration::from_secs(840));
+    }
+
+    #[test]
+    fn extending_by_signal_wakes_the_duration_thread() {
+        let control = Arc::new(RecordingControl::new(time::Duration::from_secs(600)));
+        let waiter = thread::spawn({
+            let control = control.clone();
+            move || {
+                let timer = control.timer.lock().unwrap();
+                let (_timer, result) = control.timer_changed.wait_timeout(timer, time::Duration::from_secs(10)).unwrap();
+                !result.timed_out()
+            }
+        });
+        // Give the waiter time to start waiting.
+        thread::sleep(time::Duration::from_millis(100));
+        let mut last_signal_time = None;
+        handle_signal(SIGRTMIN(), &control, time::Duration::from_secs(15 * 60), &mut last_signal_time);
+        assert!(waiter.join().unwrap());
+        let remaining = control.timer.lock().unwrap().remaining(time::Instant::now());
+        assert!(remaining > time::Duration::from_secs(1400));
+        assert_eq!(last_signal_time, None);
+    }
+
+    #[test]
+    fn data_is_flowing_only_if_recent() {
+        let control = RecordingControl::new(time::Duration::from_secs(600));
+        let now = time::Instant::now();
+        assert!(!control.is_stream_flowing(now, time::Duration::from_secs(5)));
+        assert!(control.data_arrived(now));
+        assert!(!control.data_arrived(now));
+        assert!(control.is_stream_flowing(now + time::Duration::from_secs(5), time::Duration::from_secs(5)));
+        assert!(!control.is_stream_flowing(now + time::Duration::from_secs(6), time::Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn stream_dropped_waiting_for_the_event_is_alive_and_tuned() {
+        let control = RecordingControl::new(time::Duration::from_secs(600));
+        let started_at = time::Instant::now();
+        assert!(!control.has_stream_since(started_at));
+        let now = started_at + time::Duration::from_secs(1);
+        assert!(control.stream_arrived(now));
+        assert!(!control.stream_arrived(now));
+        assert!(control.segment_data_since.lock().unwrap().is_none());
+        assert!(control.is_stream_flowing(now + time::Duration::from_secs(5), time::Duration::from_secs(5)));
+        assert!(control.has_stream_since(started_at));
+        assert!(!control.has_stream_since(now + time::Duration::from_secs(1)));
+        // Once the event starts, the first data written is not the first of the stream.
+        assert!(!control.data_arrived(now + time::Duration::from_secs(2)));
+        assert!(control.segment_data_since.lock().unwrap().is_some());
+    }
+
+    fn eit_section(section_number: u8, events: &[(u16, RunningStatus)]) -> EitSection {
+        EitSection {
+            table_id: ACTUAL_PRESENT_FOLLOWING,
+            service_id: 4164,
+            version_number: 0,
+            section_number,
+            last_section_number: 1,
+            transport_stream_id: 0x3004,
+            original_network_id: 0x233a,
+            segment_last_section_number: 1,
+            last_table_id: ACTUAL_PRESENT_FOLLOWING,
+            events: events.iter().map(|(event_id, running_status)| me_tv::eit::EitEvent {
+                event_id: *event_id,
+                start_time: Some(chrono::NaiveDate::from_ymd(2020, 10, 14).and_hms(21, 0, 0)),
+                duration_seconds: 3600,
+                running_status: *running_status,
+                free_ca_mode: false,
+                title: None,
+                description: None,
+                extended_description: None,
+                texts: Vec::new(),
+                content: Vec::new(),
+                ratings: Vec::new(),
+                programme_crid: None,
+                series_crid: None,
+            }).collect(),
         }
     }
-    pipeline.set_state(gst::State::Null).unwrap();
+
+    #[test]
+    fn event_is_recorded_while_running() {
+        let mut follower = EventFollower::new(4164, Some(7));
+        assert_eq!(follower.update(&eit_section(1, &[(7, RunningStatus::NotRunning)])), None);
+        assert!(!follower.is_writing());
+        assert_eq!(follower.update(&eit_section(0, &[(7, RunningStatus::Running)])), Some(EventState::Recording));
+        assert!(follower.is_writing());
+        assert_eq!(follower.update(&eit_section(0, &[(7, RunningStatus::Pausing)])), None);
+        assert_eq!(follower.update(&eit_section(0, &[(8, RunningStatus::Running)])), Some(EventState::Finished));
+        assert!(!follower.is_writing());
+    }
+
+    #[test]
+    fn following_takes_the_present_event() {
+        let mut follower = EventFollower::new(4164, None);
+        assert_eq!(follower.update(&eit_section(1, &[(8, RunningStatus::NotRunning)])), None);
+        assert_eq!(follower.update(&eit_section(0, &[(7, RunningStatus::Undefined)])), Some(EventState::Recording));
+        assert_eq!(follower.event_id, Some(7));
+        assert_eq!(follower.update(&eit_section(1, &[(7, RunningStatus::NotRunning)])), Some(EventState::Finished));
+    }
+
+    #[test]
+    fn other_services_are_ignored() {
+        let mut follower = EventFollower::new(4287, Some(7));
+        assert_eq!(follower.update(&eit_section(0, &[(7, RunningStatus::Running)])), None);
+    }
+
+    #[test]
+    fn a_late_start_is_measured_from_the_scheduled_start() {
+        let mut follower = EventFollower::new(4164, Some(7));
+        let start = chrono::NaiveDate::from_ymd(2020, 10, 14).and_hms(21, 0, 0);
+        assert_eq!(follower.start_slippage(start), None);
+        follower.update(&eit_section(0, &[(7, RunningStatus::Running)]));
+        assert_eq!(follower.start_slippage(start + chrono::Duration::minutes(1)), None);
+        assert_eq!(follower.start_slippage(start + chrono::Duration::minutes(6)), Some(Slippage::StartedLate(6)));
+    }
+
+    #[test]
+    fn overrun_is_measured_from_the_scheduled_end() {
+        let mut follower = EventFollower::new(4164, Some(7));
+        follower.update(&eit_section(0, &[(7, RunningStatus::Running)]));
+        let end = chrono::NaiveDate::from_ymd(2020, 10, 14).and_hms(22, 0, 0);
+        assert!(!follower.is_overrun(end + chrono::Duration::minutes(30), chrono::Duration::minutes(30)));
+        assert!(follower.is_overrun(end + chrono::Duration::minutes(31), chrono::Duration::minutes(30)));
+    }
+
+    #[test]
+    fn frontend_stats_are_read_from_the_dvbsrc_message() {
+        gst::init().unwrap();
+        let structure = gst::Structure::builder("dvb-frontend-stats")
+            .field("status", &31i32)
+            .field("signal", &52000i32)
+            .field("snr", &310i32)
+            .field("ber", &0i32)
+            .field("unc", &12i32)
+            .field("lock", &true)
+            .build();
+        let stats = FrontendStats::from_structure(&structure).unwrap();
+        assert_eq!(stats, FrontendStats { signal: 52000, snr: 310, ber: 0, unc: 12, lock: true });
+        assert_eq!(stats.line(), "Signal 52000, SNR 310, BER 0, uncorrected blocks 12, locked.");
+        assert_eq!(FrontendStats::from_structure(&gst::Structure::new_empty("something-else")), None);
+    }
+
+    #[test]
+    fn auto_is_a_valid_adapter_list() {
+        assert!(is_adapter_list("auto".to_string()).is_ok());
+        assert!(is_adapter_list("automatic".to_string()).is_err());
+    }
+
+    #[test]
+    fn auto_adapters_in_use_are_tried_last() {
+        let adapters = [(0, Availability::InUse), (1, Availability::Available), (2, Availability::Unknown), (3, Availability::InUse)];
+        assert_eq!(order_by_availability(&adapters), vec![1, 2, 0, 3]);
+    }
+
+    #[test]
+    fn frontends_that_cannot_tune_the_delivery_system_are_split_off() {
+        let dvbs2 = FrontendId { adapter: 0, frontend: 0 };
+        let dvbt2 = FrontendId { adapter: 0, frontend: 1 };
+        let unknown = FrontendId { adapter: 1, frontend: 0 };
+        let mut capabilities = HashMap::new();
+        capabilities.insert(dvbs2.clone(), vec![DeliverySystem::DVBS, DeliverySystem::DVBS2]);
+        capabilities.insert(dvbt2.clone(), vec![DeliverySystem::DVBT, DeliverySystem::DVBT2]);
+        let all = vec![dvbs2.clone(), dvbt2.clone(), unknown.clone()];
+        let (compatible, reasons) = split_by_compatibility(all.clone(), Some(DeliverySystem::DVBT2), &capabilities);
+        assert_eq!(compatible, vec![dvbt2.clone(), unknown.clone()]);
+        assert_eq!(reasons, vec!["adapter0:frontend0 cannot tune DVBT2 channels, it supports DVBS, DVBS2; use adapter0:frontend1.".to_string()]);
+        assert_eq!(split_by_compatibility(all.clone(), None, &capabilities), (all, vec![]));
+    }
+
+    #[test]
+    fn frontend_list_is_readable() {
+        let frontends = [FrontendId { adapter: 0, frontend: 1 }, FrontendId { adapter: 2, frontend: 0 }];
+        assert_eq!(frontend_list(&frontends, &HashMap::new()), "adapter0:frontend1, adapter2:frontend0");
+        let display_names = vec![(frontends[1].clone(), "Sony CXD2837ER (Hauppauge dualHD) — adapter2:frontend0".to_string())].into_iter().collect();
+        assert_eq!(frontend_list(&frontends, &display_names), "adapter0:frontend1, Sony CXD2837ER (Hauppauge dualHD) — adapter2:frontend0");
+    }
+
+    #[test]
+    fn device_history_report_lists_the_events_of_the_recording() {
+        use chrono::TimeZone;
+        use me_tv::device_history::{DeviceChange, DeviceEvent};
+        let event = |minute, change| DeviceEvent {
+            time: chrono::Local.ymd(2020, 10, 14).and_hms(21, minute, 0),
+            change,
+            fei: FrontendId { adapter: 0, frontend: 0 },
+            name: "adapter0:frontend0".to_string(),
+        };
+        let mut history = DeviceHistory::new();
+        history.record(event(0, DeviceChange::Appeared));
+        let since = chrono::Local.ymd(2020, 10, 14).and_hms(21, 30, 0);
+        assert_eq!(device_history_report(&history, since), vec!["No frontends appeared or disappeared during the recording."]);
+        history.record(event(40, DeviceChange::Disappeared));
+        history.record(event(41, DeviceChange::Appeared));
+        assert_eq!(device_history_report(&history, since), vec![
+            "Frontends appearing and disappearing during the recording:",
+            "    2020-10-14 21:40:00 adapter0:frontend0 disappeared",
+            "    2020-10-14 21:41:00 adapter0:frontend0 appeared",
+        ]);
+    }
+
+    #[test]
+    fn import_report_says_what_was_and_was_not_added() {
+        let import = Import {
+            added: vec!["Das Erste".to_string(), "ZDF".to_string()],
+            updated: vec!["BBC TWO".to_string()],
+            skipped: vec!["BBC ONE Lon".to_string()],
+            already_present: vec!["BBC ONE HD".to_string()],
+            not_zap: vec!["Das Erste HD".to_string()],
+        };
+        assert_eq!(import_report(&import, Path::new("/home/me/.config/gstreamer-1.0/dvb-channels.conf")), vec![
+            "2 channels added to /home/me/.config/gstreamer-1.0/dvb-channels.conf, 1 updated, 1 skipped.",
+            "'Das Erste' is added.",
+            "'ZDF' is added.",
+            "'BBC TWO' is now tuned as the imported channel is.",
+            "'BBC ONE Lon' is already in the channels file, it is left as it is.",
+            "'BBC ONE HD' is already the name of another channel in the channels file, it is not added.",
+            "'Das Erste HD' cannot be given in the zap format of the channels file, it is not added.",
+        ]);
+    }
+
+    #[test]
+    fn scan_progress_says_which_frontend_is_doing_what() {
+        let transponders = [
+            Transponder { delivery_system: "DVBT".to_string(), frequency: 490000000, parameters: Vec::new() },
+            Transponder { delivery_system: "DVBT2".to_string(), frequency: 474000000, parameters: Vec::new() },
+        ];
+        let fei = FrontendId { adapter: 1, frontend: 0 };
+        assert_eq!(scan_event_line(&ScanEvent::Scanning { fei: fei.clone(), transponder: 1 }, &transponders), "adapter1:frontend0: scanning DVBT2 474000000 (2 of 2)");
+        assert_eq!(scan_event_line(&ScanEvent::Scanned { fei: fei.clone(), transponder: 0, services: 12, signal: None }, &transponders), "adapter1:frontend0: 12 services on DVBT 490000000 (1 of 2)");
+        assert_eq!(
+            scan_event_line(&ScanEvent::Failed { fei: fei.clone(), transponder: 1, reason: "Timed out".to_string(), signal: Some(TransponderSignal::NoLock) }, &transponders),
+            "adapter1:frontend0: nothing found on DVBT2 474000000 (2 of 2): Timed out, no lock",
+        );
+        assert_eq!(
+            scan_event_line(&ScanEvent::FrontendFailed { fei, transponder: 0, reason: "Device or resource busy".to_string() }, &transponders),
+            "adapter1:frontend0: failed, leaving DVBT 490000000 (1 of 2) to the other frontends: Device or resource busy",
+        );
+        assert_eq!(
+            scan_event_line(&ScanEvent::ReadNit { fei: FrontendId { adapter: 0, frontend: 0 }, transponder: 1, transport_streams: 6 }, &transponders),
+            "adapter0:frontend0: the NIT on DVBT2 474000000 (2 of 2) announces 6 transport streams",
+        );
+    }
+
+    #[test]
+    fn signal_table_gives_the_signal_and_services_of_each_transponder() {
+        let transponders = [
+            Transponder { delivery_system: "DVBT".to_string(), frequency: 490000000, parameters: Vec::new() },
+            Transponder { delivery_system: "DVBT2".to_string(), frequency: 474000000, parameters: Vec::new() },
+            Transponder { delivery_system: "DVBT".to_string(), frequency: 522000000, parameters: Vec::new() },
+        ];
+        let channels = me_tv::channels_file::parse_channels("[BBC ONE Lon]
+\tSERVICE_ID = 4164
+\tDELIVERY_SYSTEM = DVBT
+\tFREQUENCY = 490000000
+
+[BBC TWO]
+\tSERVICE_ID = 4287
+\tDELIVERY_SYSTEM = DVBT
+\tFREQUENCY = 490000000
+").channels;
+        let results = ScanResults {
+            channels,
+            failed: vec![(2, "Timed out".to_string())],
+            unscanned: vec![1],
+            signals: vec![Some(TransponderSignal::Locked { strength: Some(Measurement::Relative(0.72)), snr: Some(Measurement::Decibels(25.1)) }), None, Some(TransponderSignal::NoLock)],
+        };
+        assert_eq!(signal_table(&transponders, &results), vec![
+            "Multiplex                Signal                           Services",
+            "DVBT 490000000           signal 72%, SNR 25.1 dB          2",
+            "DVBT2 474000000          unknown                          not scanned",
+            "DVBT 522000000           no lock                          none: Timed out",
+        ]);
+    }
+
+    #[test]
+    fn multiplexes_are_listed_and_found_by_frequency() {
+        let channels = me_tv::channels_file::parse_channels("[BBC ONE Lon]
+\tSERVICE_ID = 4164
+\tDELIVERY_SYSTEM = DVBT
+\tFREQUENCY = 490000000
+\tBANDWIDTH_HZ = 8000000
+
+[BBC TWO]
+\tSERVICE_ID = 4287
+\tDELIVERY_SYSTEM = DVBT
+\tFREQUENCY = 490000000
+\tBANDWIDTH_HZ = 8000000
+
+[BBC ONE HD]
+\tSERVICE_ID = 17472
+\tDELIVERY_SYSTEM = DVBT2
+\tFREQUENCY = 474000000
+\tSTREAM_ID = 0
+").channels;
+        assert_eq!(multiplex_list(&channels), vec!["DVBT 490000000: 2 channels", "DVBT2 474000000: 1 channels"]);
+        let transponders = multiplexes_at(474000200, &channels).unwrap();
+        assert_eq!(transponders, vec![Transponder { delivery_system: "DVBT2".to_string(), frequency: 474000000, parameters: vec![("STREAM_ID".to_string(), "0".to_string())] }]);
+        assert!(multiplexes_at(522000000, &channels).is_err());
+    }
+
+    #[test]
+    fn adapter_list_is_parsed_in_order() {
+        assert_eq!(parse_adapter_list("0,2,1"), Some(vec![0, 2, 1]));
+        assert_eq!(parse_adapter_list("3"), Some(vec![3]));
+        assert_eq!(parse_adapter_list("0,x"), None);
+    }
+
+    #[test]
+    fn first_segment_uses_the_output_path() {
+        assert_eq!(segment_output_path("/tmp/film.mp4", 1), "/tmp/film.mp4");
+    }
+
+    #[test]
+    fn later_segments_are_numbered_before_the_extension() {
+        assert_eq!(segment_output_path("/tmp/film.mp4", 2), "/tmp/film-2.mp4");
+        assert_eq!(segment_output_path("/tmp/film", 3), "/tmp/film-3");
+    }
 }
@@ -19,38 +19,217 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
 use clap::{Arg, App};
-use chrono::{Local, NaiveDateTime, NaiveTime};
+use chrono::{Local, NaiveDateTime};
+use log::{error, info, warn};
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGTERM};
 use time::Duration;
 
-fn parse_to_datetime(datum: &str) -> Result<NaiveDateTime, &str> {
-    let datetime_patterns = [
-        "%Y%m%dT%H%M%S",
-        "%Y-%m-%dT%H:%M:%S",
-        "%Y-%m-%d %H:%M:%S",
-        "%Y%m%dT%H%M",
-        "%Y-%m-%dT%H:%M",
-        "%Y-%m-%d %H:%M",
-    ];
-    let time_patterns= [
-        "%H%M%S",
-        "%H:%M:%S",
-        "%H%M",
-        "%H:%M",
-    ];
-    for pattern in datetime_patterns.iter() {
-        if let Ok(datetime) = NaiveDateTime::parse_from_str(datum, pattern) {
-            return Ok(datetime.into());
-        }
+use me_tv::frontends::{installed_frontends, FrontendId};
+use me_tv::logging::init_logging;
+use me_tv::schedule::{parse_to_datetime, read_schedule, read_state, schedule_file_path, schedule_state_path, write_state, Job, JobOutcome};
+
+/// How often the dæmon checks for jobs to start and recordings that have finished.
+const DAEMON_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A job currently being recorded by a me-tv-record child process.
+struct RunningJob {
+    key: String,
+    job: Job,
+    frontend: FrontendId,
+    child: process::Child,
+    is_unscheduled: bool,  // Stopped as the job has been removed from the schedule or changed.
+}
+
+/// Whether `job` is no longer one of `jobs`, the reread schedule, as it has been removed
+/// or changed.
+fn is_unscheduled(job: &Job, jobs: &[Job]) -> bool {
+    !jobs.contains(job)
+}
+
+/// Choose a frontend for a job: a free one on the preferred adapter if there is one,
+/// otherwise any free one.
+fn choose_frontend(preferred_adapter: Option<u8>, available: &[FrontendId], in_use: &[FrontendId]) -> Option<FrontendId> {
+    let free = available.iter().filter(|fei| !in_use.contains(fei));
+    let mut preferred = free.clone().filter(|fei| Some(fei.adapter) == preferred_adapter);
+    preferred.next().or_else(|| free.clone().next()).cloned()
+}
+
+/// Return the whole number of minutes, rounded up, from `now` to `end_time`.
+fn minutes_remaining(now: NaiveDateTime, end_time: NaiveDateTime) -> i64 {
+    let seconds = (end_time - now).num_seconds();
+    (seconds + 59) / 60
+}
+
+/// Start a me-tv-record process for the job, recording until `end_time`. A job with an
+/// event id has me-tv-record follow the event in the EIT, `end_time` being the limit.
+fn start_recording(job: &Job, output_path: &str, fei: &FrontendId, now: NaiveDateTime, end_time: NaiveDateTime) -> std::io::Result<process::Child> {
+    let mut command = process::Command::new("me-tv-record");
+    command
+        .arg(format!("--channel={}", job.channel))
+        .arg(format!("--duration={}", minutes_remaining(now, end_time)))
+        .arg(format!("--output={}", output_path))
+        .arg(format!("--adapter={}", fei.adapter))
+        .arg(format!("--frontend={}", fei.frontend));
+    if let Some(event_id) = job.event_id {
+        command.arg(format!("--event-id={}", event_id));
     }
-    for pattern in time_patterns.iter() {
-        if let Ok(time) = NaiveTime::parse_from_str(datum, pattern) {
-            return Ok(Local::today().and_time(time).unwrap().naive_local())
+    command
+        .stdin(process::Stdio::null())
+        .spawn()
+}
+
+/// Ask a me-tv-record process to finalise its file and stop.
+fn stop_recording(child: &process::Child) {
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM); }
+}
+
+/// Read the schedule file, reporting any problem.
+fn load_schedule(path: &Path) -> Option<Vec<Job>> {
+    match read_schedule(path) {
+        Ok(jobs) => {
+            info!("Read {} jobs from {}.", jobs.len(), path.display());
+            Some(jobs)
+        },
+        Err(e) => {
+            error!("{}", e);
+            None
+        },
+    }
+}
+
+/// Run as a dæmon recording the jobs in the schedule file as they become due.
+///
+/// SIGHUP causes the schedule file to be reread, any recording of a job that has been
+/// removed or changed being stopped and the job marked as interrupted. SIGINT and SIGTERM stop any
+/// recordings in progress, finalising their files, and terminate the dæmon.
+fn run_daemon(schedule_path: &Path, state_path: &Path) -> ! {
+    let reload = Arc::new(AtomicBool::new(false));
+    let terminate = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, reload.clone()).expect("Error setting the SIGHUP handler.");
+    signal_hook::flag::register(SIGINT, terminate.clone()).expect("Error setting the SIGINT handler.");
+    signal_hook::flag::register(SIGTERM, terminate.clone()).expect("Error setting the SIGTERM handler.");
+    let mut state = read_state(state_path);
+    // Any job running when the dæmon last stopped lost its recorder.
+    for (key, outcome) in state.iter_mut() {
+        if *outcome == JobOutcome::Running {
+            warn!("Job {} was interrupted by the dæmon stopping.", key);
+            *outcome = JobOutcome::Interrupted;
         }
+    }
+    let mut jobs = load_schedule(schedule_path).unwrap_or_default();
+    let mut running: Vec<RunningJob> = Vec::new();
+    let mut waiting_for_frontend = HashSet::new();
+    let save_state = |state: &HashMap<String, JobOutcome>| {
+        if let Err(e) = write_state(state_path, state) { error!("{}", e); }
     };
-    Err(datum)
+    save_state(&state);
+    loop {
+        if terminate.load(Ordering::Relaxed) {
+            info!("Stopping, finalising {} recordings in progress.", running.len());
+            for r in running.iter() {
+                stop_recording(&r.child);
+            }
+            for mut r in running.into_iter() {
+                let _ = r.child.wait();
+                state.insert(r.key, JobOutcome::Interrupted);
+            }
+            save_state(&state);
+            process::exit(exitcode::OK);
+        }
+        if reload.swap(false, Ordering::Relaxed) {
+            match load_schedule(schedule_path) {
+                Some(j) => jobs = j,
+                None => warn!("Keeping the previous schedule."),
+            }
+            for r in running.iter_mut().filter(|r| !r.is_unscheduled && is_unscheduled(&r.job, &jobs)) {
+                info!("Job {} has been removed from the schedule or changed, stopping its recording.", r.key);
+                stop_recording(&r.child);
+                r.is_unscheduled = true;
+            }
+        }
+        let mut is_changed = false;
+        let mut still_running = Vec::new();
+        for mut r in running.into_iter() {
+            match r.child.try_wait() {
+                Ok(Some(status)) => {
+                    let outcome = if r.is_unscheduled {
+                        info!("Job {} was stopped.", r.key);
+                        JobOutcome::Interrupted
+                    } else if status.success() {
+                        info!("Job {} finished.", r.key);
+                        JobOutcome::Succeeded
+                    } else {
+                        error!("Job {} failed, me-tv-record exit status {:?}.", r.key, status.code());
+                        JobOutcome::Failed { status: status.code() }
+                    };
+                    state.insert(r.key, outcome);
+                    is_changed = true;
+                },
+                Ok(None) => still_running.push(r),
+                Err(e) => {
+                    error!("Could not get the status of job {}: {}", r.key, e);
+                    still_running.push(r);
+                },
+            }
+        }
+        running = still_running;
+        let now = Local::now().naive_local();
+        for job in jobs.iter() {
+            let key = job.key();
+            if state.contains_key(&key) { continue; }
+            let (start_time, end_time, output_path) = match (job.start_time(), job.end_time(), job.output_path()) {
+                (Ok(s), Ok(e), Ok(o)) => (s, e, o),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                    error!("Job {} is not valid: {}", key, e);
+                    state.insert(key, JobOutcome::Failed { status: None });
+                    is_changed = true;
+                    continue;
+                },
+            };
+            if now < start_time { continue; }
+            if now >= end_time {
+                warn!("Job {} was missed.", key);
+                state.insert(key, JobOutcome::Missed);
+                is_changed = true;
+                continue;
+            }
+            let in_use = running.iter().map(|r| r.frontend.clone()).collect::<Vec<FrontendId>>();
+            let fei = match choose_frontend(job.adapter, &installed_frontends(), &in_use) {
+                Some(fei) => fei,
+                None => {
+                    if waiting_for_frontend.insert(key.clone()) {
+                        warn!("No free frontend for job {}, waiting for one.", key);
+                    }
+                    continue;
+                },
+            };
+            waiting_for_frontend.remove(&key);
+            match start_recording(job, &output_path, &fei, now, end_time) {
+                Ok(child) => {
+                    info!("Job {} started on adapter {} frontend {}, recording to {}.", key, fei.adapter, fei.frontend, output_path);
+                    state.insert(key.clone(), JobOutcome::Running);
+                    running.push(RunningJob { key, job: job.clone(), frontend: fei, child, is_unscheduled: false });
+                },
+                Err(e) => {
+                    error!("Job {} could not start me-tv-record: {}", key, e);
+                    state.insert(key, JobOutcome::Failed { status: None });
+                },
+            }
+            is_changed = true;
+        }
+        if is_changed {
+            save_state(&state);
+        }
+        thread::sleep(DAEMON_POLL_INTERVAL);
+    }
 }
 
 fn main() {
@@ -66,6 +245,8 @@ Start and end date-times must be in ISO8601 format. A full date-time is like
 20190123T0559 or 2019-01-23T05:59 basically YYYYMMDD'T'hhmm[ss]
 or YYYY-MM-DD'T'hh:mm[:ss]. For a time today the time alone is specified,
 for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
+
+With --daemon, run continuously recording the jobs in a schedule file instead.
 ")
         .arg(Arg::with_name("adapter")
             .short("a")
@@ -87,14 +268,14 @@ for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
             .value_name("CHANNEL")
             .help("Sets the channel name, must be specified, no default.")
             .takes_value(true)
-            .required(true))
+            .required_unless("daemon"))
         .arg(Arg::with_name("start_time")
             .short("s")
             .long("start-time")
             .value_name("DATE-TIME")
             .help("Sets the start date and time (or just time for today) of recording, ISO8601 format, must be specified, no default.")
             .takes_value(true)
-            .required(true))
+            .required_unless("daemon"))
         .arg(Arg::with_name("end_time")
             .short("e")
             .long("end-time")
@@ -108,20 +289,43 @@ for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
             .value_name("TIME")
             .help("Sets the duration of recording in minutes, no default. This must be set unless end-time is, but do not set both.")
             .takes_value(true)
-            .required_unless("end_time"))
+            .required_unless_one(&["end_time", "daemon"]))
         .arg(Arg::with_name("output")
             .short("o")
             .long("output")
             .value_name("PATH")
             .help("Path to output file, must be specified, no default.")
             .takes_value(true)
-            .required(true))
+            .required_unless("daemon"))
         .arg(Arg::with_name("verbose")
             .short("v")
             .long("verbose")
-            .help("sets verbose mode"))
+            .help("sets verbose mode, the same as --log-level=debug"))
+        .arg(Arg::with_name("log_level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .help("Sets the level of logging output, overrides --verbose and RUST_LOG.")
+            .takes_value(true)
+            .possible_values(&["off", "error", "warn", "info", "debug", "trace"]))
+        .arg(Arg::with_name("daemon")
+            .long("daemon")
+            .help("Run as a dæmon recording the jobs in the schedule file, reread on SIGHUP.")
+            .conflicts_with_all(&["channel", "start_time", "end_time", "duration", "output"]))
+        .arg(Arg::with_name("schedule")
+            .long("schedule")
+            .value_name("PATH")
+            .help("Sets the schedule file used in dæmon mode, defaults to $XDG_CONFIG_HOME/me-tv/schedule.yml.")
+            .takes_value(true)
+            .requires("daemon"))
         .get_matches();
-    let be_verbose = matches.is_present("verbose");
+    init_logging(matches.value_of("log_level"), matches.is_present("verbose"));
+    if matches.is_present("daemon") {
+        let schedule_path = match matches.value_of("schedule") {
+            Some(path) => Path::new(path).to_path_buf().into_boxed_path(),
+            None => schedule_file_path(),
+        };
+        run_daemon(&schedule_path, &schedule_state_path());
+    }
     let adapter = matches.value_of("adapter").unwrap().parse::<u8>().expect("Couldn't parse adapter value as a positive integer.");
     let frontend = matches.value_of("frontend").unwrap().parse::<u8>().expect("Couldn't parse frontend value as a positive integer.");
     let channel = matches.value_of("channel").unwrap();
@@ -131,7 +335,7 @@ for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
         NaiveDateTime::from_timestamp(timestamp.sec, timestamp.nsec as u32)
     };
     if start_time < now {
-        println!("Start time is before the present time, cannot schedule a recording in the past.");
+        error!("Start time is before the present time, cannot schedule a recording in the past.");
         process::exit(exitcode::USAGE);
     }
     let end_time = match matches.value_of("end_time") {
@@ -143,12 +347,12 @@ for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
         None => None,
     };
     if end_time.is_none() && duration.is_none() {
-        println!("You must specify either the end time or the duration of the recording.");
+        error!("You must specify either the end time or the duration of the recording.");
         process::exit(exitcode::USAGE);
     }
     if end_time.is_some() && duration.is_some() {
         if end_time.unwrap() - start_time != duration.unwrap() {
-            println!("Both end time and duration were supplied but there was a conflict between them, just give one or the other.");
+            error!("Both end time and duration were supplied but there was a conflict between them, just give one or the other.");
             process::exit(exitcode::USAGE);
         }
     }
@@ -158,21 +362,19 @@ for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
         duration.unwrap()
     };
     if duration < Duration::seconds(0) {
-        println!("Duration must be a positive number of minutes, cannot record backwards.");
+        error!("Duration must be a positive number of minutes, cannot record backwards.");
         process::exit(exitcode::USAGE);
     }
     let output_file = matches.value_of("output").unwrap();
-    if be_verbose {
-        println!(
-            "Scheduling recording of channel '{}' at {:?} for {} minutes to file {} using adapter {}, frontend {}.",
-            channel,
-            start_time,
-            duration.num_minutes(),
-            output_file,
-            adapter,
-            frontend,
-        );
-    }
+    info!(
+        "Scheduling recording of channel '{}' at {:?} for {} minutes to file {} using adapter {}, frontend {}.",
+        channel,
+        start_time,
+        duration.num_minutes(),
+        output_file,
+        adapter,
+        frontend,
+    );
     let echo_process = process::Command::new("echo")
         .arg(format!(
             "me-tv-record --channel={} --duration={} --output={} --adapter={} --frontend={}",
@@ -199,25 +401,53 @@ for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
 mod test {
     use super::*;
     use chrono::NaiveDate;
-    use rstest::rstest;
-
-    #[rstest(
-        datum, expected,
-        case("20181229T181533", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 33)),
-        case("2018-12-29T18:15:33", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 33)),
-        case("2018-12-29 18:15:33", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 33)),
-        case("20181229T1815", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 00)),
-        case("2018-12-29T18:15", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 00)),
-        case("2018-12-29 18:15", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 00)),
-        case("181513", Local::today().naive_local().and_hms(18, 15, 13)),
-        case("18:15:13", Local::today().naive_local().and_hms(18, 15, 13)),
-        case("1815", Local::today().naive_local().and_hms(18, 15, 00)),
-        case("18:15", Local::today().naive_local().and_hms(18, 15, 00)),
-    )]
-    fn parse_datetime_string(datum: &str, expected: NaiveDateTime) {
-        match parse_to_datetime(datum) {
-            Ok(result) => assert_eq!(result, expected),
-            Err(e) => assert!(false,"failed to parse: {}", e),
+
+    fn frontends() -> Vec<FrontendId> {
+        vec![
+            FrontendId { adapter: 0, frontend: 0 },
+            FrontendId { adapter: 1, frontend: 0 },
+            FrontendId { adapter: 1, frontend: 1 },
+        ]
+    }
+
+    #[test]
+    fn preferred_adapter_is_chosen_when_free() {
+        assert_eq!(choose_frontend(Some(1), &frontends(), &[]), Some(FrontendId { adapter: 1, frontend: 0 }));
+        assert_eq!(choose_frontend(Some(1), &frontends(), &[FrontendId { adapter: 1, frontend: 0 }]), Some(FrontendId { adapter: 1, frontend: 1 }));
+    }
+
+    #[test]
+    fn any_free_frontend_is_chosen_when_preferred_adapter_is_busy() {
+        let in_use = [FrontendId { adapter: 1, frontend: 0 }, FrontendId { adapter: 1, frontend: 1 }];
+        assert_eq!(choose_frontend(Some(1), &frontends(), &in_use), Some(FrontendId { adapter: 0, frontend: 0 }));
+    }
+
+    #[test]
+    fn no_frontend_is_chosen_when_all_are_busy() {
+        assert_eq!(choose_frontend(None, &frontends(), &frontends()), None);
+    }
+
+    #[test]
+    fn removed_and_changed_jobs_are_unscheduled() {
+        let job = |channel: &str, duration: u32| Job {
+            channel: channel.to_string(),
+            start: "2020-10-14T21:00".to_string(),
+            end: None,
+            duration: Some(duration),
+            output: "/tmp/{channel}.mp4".to_string(),
+            adapter: None,
+            event_id: None,
         };
+        let jobs = vec![job("BBC FOUR", 60), job("BBC NEWS", 30)];
+        assert!(!is_unscheduled(&job("BBC FOUR", 60), &jobs));
+        assert!(is_unscheduled(&job("BBC FOUR", 90), &jobs));
+        assert!(is_unscheduled(&job("BBC ONE", 60), &jobs));
+    }
+
+    #[test]
+    fn minutes_remaining_rounds_up() {
+        let now = NaiveDate::from_ymd(2020, 10, 14).and_hms(21, 0, 30);
+        assert_eq!(minutes_remaining(now, NaiveDate::from_ymd(2020, 10, 14).and_hms(22, 0, 0)), 60);
+        assert_eq!(minutes_remaining(now, NaiveDate::from_ymd(2020, 10, 14).and_hms(21, 1, 30)), 1);
     }
 }
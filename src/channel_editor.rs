@@ -0,0 +1,392 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The channel editor: the channels with their numbers and names, and whether they are
+//! favourites or hidden, all of which can be changed. Channels can also be deleted, or
+//! moved about their channels file. Where a service has copies, found on the multiplexes
+//! of overlapping transmitters, which copy is listed and tuned can be chosen.
+//!
+//! Names, deletions, and places are changed in the channels files, numbers, hiding, and
+//! copies in the Me TV channels data cache, and favourites in the preferences.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use glib;
+
+use gtk;
+use gtk::prelude::*;
+
+use me_tv::channels_file::{edit_channels_file, ChannelEdit};
+
+use crate::channels_data::{
+    channels_file_of, frequency_text, get_channel_name_of_logical_channel_number, get_channels_data, get_copies_data, get_details_data, is_hidden,
+    prefer_copy, set_hidden, set_logical_channel_number_of,
+};
+use crate::control_window::{relist_channels, reload_channels_file, ControlWindow};
+use crate::dialogs::display_an_error_dialog;
+use crate::favourites;
+use crate::preferences;
+
+lazy_static! {
+    static ref CHANNEL_EDITOR: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+}
+
+/// The window and the widgets of it that the edits need.
+struct ChannelEditor {
+    window: gtk::Window,
+    // The columns are the channel number, the name, whether it is a favourite, whether it
+    // is hidden, the channels file it is in, the frequency of its multiplex, whether it is
+    // the listed copy of its service, whether its service has other copies, the details
+    // of the channel, as markup, for its tooltip, and where it is in the channels data.
+    store: gtk::ListStore,
+    view: gtk::TreeView,
+    show_hidden_button: gtk::CheckButton,
+    move_up_button: gtk::Button,
+    move_down_button: gtk::Button,
+    delete_button: gtk::Button,
+    control_window: Rc<ControlWindow>,
+}
+
+impl ChannelEditor {
+    /// List the channels in the order of the channels data, that of the channels files,
+    /// selecting the channel `selected` if there is one. Hidden channels are only listed
+    /// if the user has asked for them, but how many there are is always shown.
+    fn fill_store(&self, selected: Option<&str>) {
+        self.store.clear();
+        let copies = get_copies_data();
+        let details = get_details_data();
+        let mut hidden_count = 0;
+        for (position, (number, name)) in get_channels_data().unwrap_or_default().into_iter().enumerate() {
+            if is_hidden(&name) {
+                hidden_count += 1;
+                if !self.show_hidden_button.get_active() { continue; }
+            }
+            let channel_number = if number == 0 { "".to_string() } else { number.to_string() };
+            let channels_file = channels_file_of(&name).display().to_string();
+            let (frequency, has_copies, listed) = copies.get(position).copied().unwrap_or((0, false, true));
+            let details = details.get(position).map_or_else(String::new, |details| glib::markup_escape_text(details).to_string());
+            let iter = self.store.insert_with_values(
+                None,
+                &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+                &[&channel_number, &name, &favourites::is_favourite(&name), &is_hidden(&name), &channels_file, &frequency_text(frequency), &listed, &has_copies, &details, &(position as u32)],
+            );
+            if selected == Some(name.as_str()) {
+                self.view.get_selection().select_iter(&iter);
+                self.view.scroll_to_cell(self.store.get_path(&iter).as_ref(), None::<&gtk::TreeViewColumn>, false, 0.0, 0.0);
+            }
+        }
+        self.show_hidden_button.set_label(&match hidden_count {
+            1 => "Show the hidden channel (there is 1)".to_string(),
+            count => format!("Show the hidden channels (there are {})", count),
+        });
+        self.update_buttons();
+    }
+
+    /// The value of `column` of the row at `path` as a string.
+    fn string_at(&self, path: &gtk::TreePath, column: i32) -> Option<String> {
+        let iter = self.store.get_iter(path)?;
+        self.store.get_value(&iter, column).get::<String>().ok().flatten()
+    }
+
+    /// The name of the selected channel, if one is.
+    fn selected_name(&self) -> Option<String> {
+        let (model, iter) = self.view.get_selection().get_selected()?;
+        model.get_value(&iter, 1).get::<String>().ok().flatten()
+    }
+
+    /// Only offer what can be done to the selected channel: moving it is only within its
+    /// channels file.
+    fn update_buttons(&self) {
+        let selected = self.view.get_selection().get_selected();
+        let in_same_file = |iter: &gtk::TreeIter, step: fn(&gtk::ListStore, &gtk::TreeIter) -> bool| {
+            let neighbour = iter.clone();
+            step(&self.store, &neighbour)
+                && self.store.get_value(&neighbour, 4).get::<String>().ok().flatten() == self.store.get_value(iter, 4).get::<String>().ok().flatten()
+        };
+        let (can_move_up, can_move_down) = match &selected {
+            Some((_, iter)) => (in_same_file(iter, |s, i| s.iter_previous(i)), in_same_file(iter, |s, i| s.iter_next(i))),
+            None => (false, false),
+        };
+        self.move_up_button.set_sensitive(can_move_up);
+        self.move_down_button.set_sensitive(can_move_down);
+        self.delete_button.set_sensitive(selected.is_some());
+    }
+
+    /// Change the channels file of a channel, reread the channels files, and list the
+    /// channels again, selecting `selected`. Return `true` if the file was changed.
+    fn edit(&self, edit: ChannelEdit, selected: Option<&str>) -> bool {
+        let path = channels_file_of(edit.name());
+        match edit_channels_file(&path, &edit) {
+            Ok(()) => {
+                reload_channels_file(&self.control_window);
+                self.fill_store(selected);
+                true
+            },
+            Err(e) => {
+                display_an_error_dialog(Some(&self.window), &format!("Could not change {}: {}", path.display(), e));
+                false
+            },
+        }
+    }
+
+    /// Give the channel at `path` the number `text`, or give it back to the SI packets to
+    /// number if `text` is empty.
+    fn renumber(&self, path: &gtk::TreePath, text: &str) {
+        let name = match self.string_at(path, 1) { Some(name) => name, None => return };
+        let text = text.trim();
+        let number = match text.parse::<u16>() {
+            _ if text.is_empty() => 0,
+            Ok(number) if number > 0 => number,
+            _ => {
+                display_an_error_dialog(Some(&self.window), &format!("'{}' is not a channel number.", text));
+                return;
+            },
+        };
+        match get_channel_name_of_logical_channel_number(number) {
+            Some(other) if other != name => {
+                display_an_error_dialog(Some(&self.window), &format!("{} already has channel number {}.", other, number));
+                return;
+            },
+            _ => {},
+        }
+        if set_logical_channel_number_of(&name, number) {
+            relist_channels(&self.control_window);
+            if let Some(iter) = self.store.get_iter(path) {
+                let channel_number = if number == 0 { "".to_string() } else { number.to_string() };
+                self.store.set_value(&iter, 0, &channel_number.to_value());
+            }
+        }
+    }
+
+    /// Rename the channel at `path`, the preferences following it.
+    fn rename(&self, path: &gtk::TreePath, text: &str) {
+        let name = match self.string_at(path, 1) { Some(name) => name, None => return };
+        let new_name = text.trim();
+        if new_name == name {
+            return;
+        }
+        if get_channels_data().unwrap_or_default().iter().any(|(_, other)| other == new_name) {
+            display_an_error_dialog(Some(&self.window), &format!("There is already a channel called {}.", new_name));
+            return;
+        }
+        if self.edit(ChannelEdit::Rename{name: name.clone(), new_name: new_name.to_string()}, Some(new_name)) {
+            if preferences::get_default_channel().as_deref() == Some(name.as_str()) {
+                preferences::set_default_channel(new_name.to_string(), true);
+            }
+            if preferences::get_last_channel().as_deref() == Some(name.as_str()) {
+                preferences::set_last_channel(new_name.to_string(), true);
+            }
+        }
+    }
+
+    /// Make the channel at `path` a favourite if it is not one, and not one if it is.
+    fn toggle_favourite(&self, path: &gtk::TreePath) {
+        if let (Some(name), Some(iter)) = (self.string_at(path, 1), self.store.get_iter(path)) {
+            favourites::toggle(&name);
+            self.control_window.refresh_channels_view();
+            self.store.set_value(&iter, 2, &favourites::is_favourite(&name).to_value());
+        }
+    }
+
+    /// Hide the channel at `path` if it is listed, and list it if it is hidden.
+    fn toggle_hidden(&self, path: &gtk::TreePath) {
+        if let Some(name) = self.string_at(path, 1) {
+            set_hidden(&name, !is_hidden(&name));
+            relist_channels(&self.control_window);
+            self.fill_store(Some(&name));
+        }
+    }
+
+    /// List and tune the copy of a service at `path` rather than the other copies.
+    fn prefer(&self, path: &gtk::TreePath) {
+        let name = self.string_at(path, 1);
+        let position = match self.store.get_iter(path) { Some(iter) => self.store.get_value(&iter, 9).get_some::<u32>().unwrap_or(0) as usize, None => return };
+        if prefer_copy(position) {
+            reload_channels_file(&self.control_window);
+            self.fill_store(name.as_deref());
+        }
+    }
+
+    /// Delete the selected channel from its channels file, once the user says so.
+    fn delete_selected(&self) {
+        let name = match self.selected_name() { Some(name) => name, None => return };
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            &format!("Delete {} from {}?\n\nOnly scanning again will bring it back.", name, channels_file_of(&name).display()),
+        );
+        let response = gtk::ResponseType::from(dialog.run());
+        unsafe { dialog.destroy(); }
+        if response == gtk::ResponseType::Ok && self.edit(ChannelEdit::Remove{name: name.clone()}, None) {
+            if preferences::get_default_channel().as_deref() == Some(name.as_str()) {
+                preferences::set_default_channel("".to_string(), true);
+            }
+            if preferences::get_last_channel().as_deref() == Some(name.as_str()) {
+                preferences::set_last_channel("".to_string(), true);
+            }
+        }
+    }
+}
+
+/// Add a column to the channels list, the `attribute` of `renderer` being `column` of
+/// the store.
+fn add_column<R: IsA<gtk::CellRenderer>>(view: &gtk::TreeView, title: &str, renderer: &R, attribute: &str, column: i32) -> gtk::TreeViewColumn {
+    let view_column = gtk::TreeViewColumn::new();
+    view_column.set_title(title);
+    view_column.pack_start(renderer, true);
+    view_column.add_attribute(renderer, attribute, column);
+    view.append_column(&view_column);
+    view_column
+}
+
+fn create(control_window: &Rc<ControlWindow>) -> gtk::Window {
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    window.set_title("Me TV Channels");
+    window.set_transient_for(Some(&control_window.window));
+    window.set_destroy_with_parent(true);
+    window.set_default_size(600, 500);
+    let main_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    main_box.set_border_width(10);
+    let label = gtk::Label::new(Some(
+        "Moving a channel moves it in its channels file, so it is the order of the channels lists when they are ordered as in the channels file. \
+         Where a service has copies on more than one multiplex, only the one ticked as used is listed and tuned."
+    ));
+    label.set_line_wrap(true);
+    label.set_xalign(0.0);
+    main_box.pack_start(&label, false, false, 0);
+    let store = gtk::ListStore::new(&[
+        String::static_type(), String::static_type(), bool::static_type(), bool::static_type(), String::static_type(), String::static_type(), bool::static_type(),
+        bool::static_type(), String::static_type(), u32::static_type(),
+    ]);
+    let view = gtk::TreeView::with_model(&store);
+    view.set_tooltip_column(8);
+    let scrolled_window = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    scrolled_window.add(&view);
+    main_box.pack_start(&scrolled_window, true, true, 0);
+    // Hidden channels are out of the way, but it must be clear that they are still there.
+    let show_hidden_button = gtk::CheckButton::with_label("Show the hidden channels");
+    main_box.pack_start(&show_hidden_button, false, false, 0);
+    let buttons_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    let move_up_button = gtk::Button::with_label("Move up");
+    let move_down_button = gtk::Button::with_label("Move down");
+    let delete_button = gtk::Button::with_label("Delete…");
+    buttons_box.pack_start(&move_up_button, false, false, 0);
+    buttons_box.pack_start(&move_down_button, false, false, 0);
+    buttons_box.pack_end(&delete_button, false, false, 0);
+    main_box.pack_start(&buttons_box, false, false, 0);
+    window.add(&main_box);
+    let editor = Rc::new(ChannelEditor {
+        window: window.clone(),
+        store,
+        view,
+        show_hidden_button,
+        move_up_button,
+        move_down_button,
+        delete_button,
+        control_window: control_window.clone(),
+    });
+    let number_renderer = gtk::CellRendererText::new();
+    number_renderer.set_property_editable(true);
+    number_renderer.connect_edited({
+        let e = editor.clone();
+        move |_, path, text| e.renumber(&path, text)
+    });
+    add_column(&editor.view, "Number", &number_renderer, "text", 0);
+    let name_renderer = gtk::CellRendererText::new();
+    name_renderer.set_property_editable(true);
+    name_renderer.connect_edited({
+        let e = editor.clone();
+        move |_, path, text| e.rename(&path, text)
+    });
+    add_column(&editor.view, "Name", &name_renderer, "text", 1);
+    let favourite_renderer = gtk::CellRendererToggle::new();
+    favourite_renderer.connect_toggled({
+        let e = editor.clone();
+        move |_, path| e.toggle_favourite(&path)
+    });
+    add_column(&editor.view, "Favourite", &favourite_renderer, "active", 2);
+    let hidden_renderer = gtk::CellRendererToggle::new();
+    hidden_renderer.connect_toggled({
+        let e = editor.clone();
+        move |_, path| e.toggle_hidden(&path)
+    });
+    add_column(&editor.view, "Hidden", &hidden_renderer, "active", 3);
+    add_column(&editor.view, "Channels file", &gtk::CellRendererText::new(), "text", 4);
+    add_column(&editor.view, "Frequency", &gtk::CellRendererText::new(), "text", 5);
+    let used_renderer = gtk::CellRendererToggle::new();
+    used_renderer.set_radio(true);
+    used_renderer.connect_toggled({
+        let e = editor.clone();
+        move |_, path| e.prefer(&path)
+    });
+    let used_column = add_column(&editor.view, "Used", &used_renderer, "active", 6);
+    used_column.add_attribute(&used_renderer, "visible", 7);
+    used_column.add_attribute(&used_renderer, "activatable", 7);
+    editor.view.get_selection().connect_changed({
+        let e = editor.clone();
+        move |_| e.update_buttons()
+    });
+    editor.move_up_button.connect_clicked({
+        let e = editor.clone();
+        move |_| if let Some(name) = e.selected_name() {
+            e.edit(ChannelEdit::MoveUp{name: name.clone()}, Some(&name));
+        }
+    });
+    editor.move_down_button.connect_clicked({
+        let e = editor.clone();
+        move |_| if let Some(name) = e.selected_name() {
+            e.edit(ChannelEdit::MoveDown{name: name.clone()}, Some(&name));
+        }
+    });
+    editor.delete_button.connect_clicked({
+        let e = editor.clone();
+        move |_| e.delete_selected()
+    });
+    editor.show_hidden_button.connect_toggled({
+        let e = editor.clone();
+        move |_| e.fill_store(e.selected_name().as_deref())
+    });
+    editor.fill_store(None);
+    window.show_all();
+    window
+}
+
+/// Display the channel editor in a non-modal way, but only if it is not already being displayed.
+pub fn present(control_window: &Rc<ControlWindow>) {
+    if let Ok(active) = CHANNEL_EDITOR.lock() {
+        if ! active.get() {
+            let window = create(control_window);
+            window.connect_destroy(move |_| {
+                if let Ok(active) = CHANNEL_EDITOR.lock() {
+                    active.set(false);
+                }
+            });
+            window.show();
+            active.set(true);
+        }
+    }
+}
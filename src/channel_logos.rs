@@ -0,0 +1,75 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The logos of the channels, scaled to the sizes they are shown at.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use gdk_pixbuf::Pixbuf;
+
+use log::warn;
+
+use me_tv::logos::{default_logos_directory, find_logo};
+
+use crate::preferences;
+
+/// The height of a logo in a channel selector.
+pub const SELECTOR_SIZE: i32 = 24;
+
+/// The height of a logo shown over the video.
+pub const OSD_SIZE: i32 = 64;
+
+// Pixbufs can only be used in the GTK event loop thread, so there is no need of a Mutex.
+thread_local! {
+    static LOGOS: RefCell<HashMap<(PathBuf, i32), Option<Pixbuf>>> = RefCell::new(HashMap::new());
+}
+
+/// The directory the logos are in, as set in the preferences or the default one.
+pub fn logos_directory() -> PathBuf {
+    match preferences::get_logos_directory() {
+        Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+        _ => default_logos_directory(),
+    }
+}
+
+/// The logo of a channel `size` pixels high, if there is one and it can be read. Each
+/// logo file is only read once at each size.
+pub fn get_logo(channel_name: &str, service_id: Option<u16>, size: i32) -> Option<Pixbuf> {
+    let path = find_logo(&logos_directory(), channel_name, service_id)?;
+    LOGOS.with(|logos| {
+        logos.borrow_mut().entry((path.clone(), size)).or_insert_with(|| {
+            match Pixbuf::from_file_at_scale(&path, -1, size, true) {
+                Ok(pixbuf) => Some(pixbuf),
+                Err(error) => {
+                    warn!("Cannot read the logo {}: {}", path.display(), error);
+                    None
+                },
+            }
+        }).clone()
+    })
+}
+
+/// Forget the logos read, so that they are read again, from a different directory say.
+pub fn forget_logos() {
+    LOGOS.with(|logos| logos.borrow_mut().clear());
+}
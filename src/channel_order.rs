@@ -0,0 +1,153 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The orders the channel selectors can list the channels in, which is also the order
+//! zapping goes through them in.
+
+use std::cmp::Ordering;
+use std::ffi::CString;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// How the channels are ordered in the channel selectors. The file order is the manual
+/// one, the channel editor moving channels up and down the channels file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ChannelOrder {
+    LogicalChannelNumber,
+    Alphabetical,
+    FileOrder,
+    Provider,
+}
+
+impl Default for ChannelOrder {
+    fn default() -> ChannelOrder { ChannelOrder::LogicalChannelNumber }
+}
+
+/// What a channel is ordered by: its logical channel number, zero if it is not known,
+/// its name, its provider, empty if it is not known, and where it is in the channels file.
+#[derive(Clone, Copy, Debug)]
+pub struct ListedChannel<'a> {
+    pub number: u16,
+    pub name: &'a str,
+    pub provider: &'a str,
+    pub position: u32,
+}
+
+/// The order of two strings in the collation order of the locale, whatever the case.
+/// Strings that differ only by case are in the collation order.
+fn collate(a: &str, b: &str) -> Ordering {
+    fn strcoll(a: &str, b: &str) -> Ordering {
+        match (CString::new(a), CString::new(b)) {
+            (Ok(a), Ok(b)) => unsafe { libc::strcoll(a.as_ptr(), b.as_ptr()) }.cmp(&0),
+            _ => a.cmp(b),
+        }
+    }
+    strcoll(&a.to_lowercase(), &b.to_lowercase()).then_with(|| strcoll(a, b))
+}
+
+/// The order of two channels by logical channel number, those without one coming after
+/// those with one.
+fn by_number(a: &ListedChannel, b: &ListedChannel) -> Ordering {
+    (a.number == 0).cmp(&(b.number == 0)).then(a.number.cmp(&b.number))
+}
+
+impl ChannelOrder {
+    /// The target of the menu item for the order.
+    pub fn action_target(&self) -> &'static str {
+        match self {
+            ChannelOrder::LogicalChannelNumber => "number",
+            ChannelOrder::Alphabetical => "name",
+            ChannelOrder::FileOrder => "file",
+            ChannelOrder::Provider => "provider",
+        }
+    }
+
+    /// The order of a menu item target.
+    pub fn from_action_target(target: &str) -> Option<ChannelOrder> {
+        [ChannelOrder::LogicalChannelNumber, ChannelOrder::Alphabetical, ChannelOrder::FileOrder, ChannelOrder::Provider].iter()
+            .find(|order| order.action_target() == target)
+            .cloned()
+    }
+
+    /// The order of two channels. Channels without a logical channel number come after
+    /// those with one. Names are in the collation order of the locale. The channels of
+    /// each provider are together, in channel number order, those whose provider is
+    /// not known coming last. Channels that are otherwise the same are in file order.
+    pub fn compare(&self, a: &ListedChannel, b: &ListedChannel) -> Ordering {
+        let ordering = match self {
+            ChannelOrder::LogicalChannelNumber => by_number(a, b),
+            ChannelOrder::Alphabetical => collate(a.name, b.name),
+            ChannelOrder::FileOrder => Ordering::Equal,
+            ChannelOrder::Provider => a.provider.is_empty().cmp(&b.provider.is_empty())
+                .then_with(|| collate(a.provider, b.provider))
+                .then_with(|| by_number(a, b)),
+        };
+        ordering.then(a.position.cmp(&b.position))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CHANNELS: [ListedChannel; 6] = [
+        ListedChannel{number: 0, name: "radio 4", provider: "BBC", position: 0},
+        ListedChannel{number: 3, name: "ITV", provider: "ITV", position: 1},
+        ListedChannel{number: 1, name: "BBC ONE Lon", provider: "BBC", position: 2},
+        ListedChannel{number: 0, name: "Channel 4+1", provider: "", position: 3},
+        ListedChannel{number: 2, name: "BBC TWO", provider: "BBC", position: 4},
+        ListedChannel{number: 0, name: "itv", provider: "", position: 5},
+    ];
+
+    fn ordered(order: ChannelOrder) -> Vec<&'static str> {
+        let mut channels = CHANNELS.to_vec();
+        channels.sort_by(|a, b| order.compare(a, b));
+        channels.iter().map(|channel| channel.name).collect()
+    }
+
+    #[test]
+    fn channels_without_a_number_come_after_those_with_one() {
+        assert_eq!(ordered(ChannelOrder::LogicalChannelNumber), vec!["BBC ONE Lon", "BBC TWO", "ITV", "radio 4", "Channel 4+1", "itv"]);
+    }
+
+    #[test]
+    fn channels_are_ordered_by_name_whatever_the_case() {
+        assert_eq!(ordered(ChannelOrder::Alphabetical), vec!["BBC ONE Lon", "BBC TWO", "Channel 4+1", "ITV", "itv", "radio 4"]);
+    }
+
+    #[test]
+    fn channels_can_be_in_file_order() {
+        assert_eq!(ordered(ChannelOrder::FileOrder), vec!["radio 4", "ITV", "BBC ONE Lon", "Channel 4+1", "BBC TWO", "itv"]);
+    }
+
+    #[test]
+    fn channels_of_a_provider_are_together_in_number_order() {
+        assert_eq!(ordered(ChannelOrder::Provider), vec!["BBC ONE Lon", "BBC TWO", "radio 4", "ITV", "Channel 4+1", "itv"]);
+    }
+
+    #[test]
+    fn orders_are_menu_item_targets() {
+        for order in &[ChannelOrder::LogicalChannelNumber, ChannelOrder::Alphabetical, ChannelOrder::FileOrder, ChannelOrder::Provider] {
+            assert_eq!(ChannelOrder::from_action_target(order.action_target()), Some(*order));
+        }
+        assert_eq!(ChannelOrder::from_action_target("random"), None);
+    }
+}
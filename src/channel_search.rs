@@ -0,0 +1,219 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! A search for a channel, for when there are too many channels for a channel selector
+//! to be usable: a button popping up a list of the channels that typing filters, the
+//! favourites, the TV channels and the radio channels each under their own heading.
+//! The arrow keys move through the channels listed, Enter or a click chooses one.
+
+use std::rc::Rc;
+
+use gdk;
+use gdk_pixbuf::Pixbuf;
+use glib;
+use gtk;
+use gtk::prelude::*;
+
+use me_tv::name_matching::matching;
+
+/// The columns of the list of channels found: the name of the channel, empty for a
+/// heading, what is shown for the channel or heading, whether it is a heading, and
+/// the logo of the channel if it has one.
+const NAME: u32 = 0;
+const TEXT: u32 = 1;
+const IS_HEADING: u32 = 2;
+const LOGO: u32 = 3;
+
+/// A channel as listed in a channels model.
+struct Channel {
+    number: String,
+    name: String,
+    is_favourite: bool,
+    is_radio: bool,
+    logo: Option<Pixbuf>,
+}
+
+/// The channels of a model with the columns of the channels store of the control window.
+fn channels_of(model: &gtk::TreeModel) -> Vec<Channel> {
+    let mut channels = Vec::new();
+    if let Some(iter) = model.get_iter_first() {
+        loop {
+            channels.push(Channel {
+                number: model.get_value(&iter, 0).get::<String>().unwrap().unwrap_or_default(),
+                name: model.get_value(&iter, 1).get::<String>().unwrap().unwrap_or_default(),
+                is_favourite: model.get_value(&iter, 2).get_some::<bool>().unwrap_or(false),
+                is_radio: model.get_value(&iter, 5).get_some::<bool>().unwrap_or(false),
+                logo: model.get_value(&iter, 9).get::<Pixbuf>().unwrap_or(None),
+            });
+            if !model.iter_next(&iter) { break; }
+        }
+    }
+    channels
+}
+
+/// List the channels of `model` whose names match `typed`, under the headings of the
+/// sections they are in. A favourite is only listed as a favourite.
+fn fill(list: &gtk::ListStore, model: &gtk::TreeModel, typed: &str) {
+    list.clear();
+    let channels = channels_of(model);
+    let sections: [(&str, fn(&Channel) -> bool); 3] = [
+        ("Favourites", |c| c.is_favourite),
+        ("TV", |c| !c.is_favourite && !c.is_radio),
+        ("Radio", |c| !c.is_favourite && c.is_radio),
+    ];
+    for (heading, is_in_section) in &sections {
+        let in_section = channels.iter().filter(|c| is_in_section(c)).collect::<Vec<_>>();
+        let found = matching(typed, &in_section, |c| c.name.as_str());
+        if found.is_empty() { continue; }
+        list.insert_with_values(None, &[NAME, TEXT, IS_HEADING], &[&"", &format!("<b>{}</b>", heading), &true]);
+        for channel in found {
+            let text = format!("{}  {}", channel.number, glib::markup_escape_text(&channel.name));
+            list.insert_with_values(None, &[NAME, TEXT, IS_HEADING, LOGO], &[&channel.name, &text.trim_start(), &false, &channel.logo]);
+        }
+    }
+    if list.get_iter_first().is_none() {
+        list.insert_with_values(None, &[NAME, TEXT, IS_HEADING], &[&"", &"<i>No channels match</i>", &true]);
+    }
+}
+
+/// Select the channel `step` rows from the one selected, or the first channel if none
+/// is, headings being skipped over.
+fn move_selection(view: &gtk::TreeView, list: &gtk::ListStore, step: i32) {
+    let count = list.iter_n_children(None);
+    let selected = view.get_selection().get_selected()
+        .and_then(|(_, iter)| list.get_path(&iter))
+        .and_then(|path| path.get_indices().first().cloned());
+    let mut index = match selected {
+        Some(index) => index + step,
+        None => 0,
+    };
+    while 0 <= index && index < count {
+        let iter = list.iter_nth_child(None, index).unwrap();
+        if !list.get_value(&iter, IS_HEADING as i32).get_some::<bool>().unwrap_or(true) {
+            view.get_selection().select_iter(&iter);
+            view.scroll_to_cell(list.get_path(&iter).as_ref(), None::<&gtk::TreeViewColumn>, false, 0.0, 0.0);
+            return;
+        }
+        index += if step < 0 { -1 } else { 1 };
+    }
+}
+
+/// The name of the channel selected, if one is.
+fn selected_channel(view: &gtk::TreeView) -> Option<String> {
+    let (model, iter) = view.get_selection().get_selected()?;
+    model.get_value(&iter, NAME as i32).get::<String>().unwrap().filter(|name| !name.is_empty())
+}
+
+/// A button popping up a search of the channels in `model`, which must have the columns
+/// of the channels store of the control window, `on_chosen` being called with the name
+/// of the channel chosen.
+pub fn channel_search_button<T: IsA<gtk::TreeModel>, F: Fn(&str) + 'static>(model: &T, on_chosen: F) -> gtk::MenuButton {
+    let model = model.clone().upcast::<gtk::TreeModel>();
+    let on_chosen = Rc::new(on_chosen);
+    let button = gtk::MenuButton::new();
+    button.set_image(Some(&gtk::Image::from_icon_name(Some("edit-find-symbolic"), gtk::IconSize::Button.into())));
+    button.set_tooltip_text(Some("Search the channels"));
+    let list = gtk::ListStore::new(&[String::static_type(), String::static_type(), bool::static_type(), Pixbuf::static_type()]);
+    let view = gtk::TreeView::with_model(&list);
+    view.set_headers_visible(false);
+    view.set_enable_search(false);
+    view.set_activate_on_single_click(true);
+    let column = gtk::TreeViewColumn::new();
+    let logo_renderer = gtk::CellRendererPixbuf::new();
+    column.pack_start(&logo_renderer, false);
+    column.add_attribute(&logo_renderer, "pixbuf", LOGO as i32);
+    let text_renderer = gtk::CellRendererText::new();
+    column.pack_start(&text_renderer, true);
+    column.add_attribute(&text_renderer, "markup", TEXT as i32);
+    view.append_column(&column);
+    // Headings cannot be chosen.
+    view.get_selection().set_select_function(Some(Box::new(|_, model, path, _| {
+        model.get_iter(path).map_or(false, |iter| !model.get_value(&iter, IS_HEADING as i32).get_some::<bool>().unwrap_or(true))
+    })));
+    let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+    scrolled_window.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled_window.set_min_content_height(300);
+    scrolled_window.set_propagate_natural_width(true);
+    scrolled_window.add(&view);
+    let entry = gtk::SearchEntry::new();
+    entry.set_placeholder_text(Some("Channel name"));
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 5);
+    content.set_border_width(5);
+    content.pack_start(&entry, false, false, 0);
+    content.pack_start(&scrolled_window, true, true, 0);
+    content.show_all();
+    let popover = gtk::Popover::new(Some(&button));
+    popover.add(&content);
+    button.set_popover(Some(&popover));
+    entry.connect_changed({
+        let model = model.clone();
+        let list = list.clone();
+        let view = view.clone();
+        move |entry| {
+            fill(&list, &model, &entry.get_text());
+            move_selection(&view, &list, 0);
+        }
+    });
+    entry.connect_key_press_event({
+        let list = list.clone();
+        let view = view.clone();
+        move |_, key| {
+            let keyval = key.get_keyval();
+            if keyval == gdk::keys::constants::Down {
+                move_selection(&view, &list, 1);
+                Inhibit(true)
+            } else if keyval == gdk::keys::constants::Up {
+                move_selection(&view, &list, -1);
+                Inhibit(true)
+            } else {
+                Inhibit(false)
+            }
+        }
+    });
+    entry.connect_activate({
+        let popover = popover.clone();
+        let view = view.clone();
+        let on_chosen = on_chosen.clone();
+        move |_| if let Some(name) = selected_channel(&view) {
+            popover.popdown();
+            on_chosen(&name);
+        }
+    });
+    view.connect_row_activated({
+        let popover = popover.clone();
+        move |view, _, _| if let Some(name) = selected_channel(view) {
+            popover.popdown();
+            on_chosen(&name);
+        }
+    });
+    // Each search starts afresh with all the channels, as they are now.
+    popover.connect_show({
+        let model = model.clone();
+        move |_| {
+            entry.set_text("");
+            fill(&list, &model, "");
+            move_selection(&view, &list, 0);
+            entry.grab_focus();
+        }
+    });
+    button
+}
@@ -19,29 +19,35 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions, create_dir_all};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File, OpenOptions, create_dir_all};
 use std::io::{Read, Write};
+use std::iter;
 use std::path::Path;
 use std::sync::RwLock;
 
-use ini;
 use lazy_static::lazy_static;
-use percent_encoding;
+use log::{info, warn};
 use serde_derive::{Serialize, Deserialize};
 use serde_yaml;
 use xdg;
 
-use crate::control_window::Message;
+use me_tv::channels_file::{dvb_uri, preferred_copies_file_path, read_channels, Channel, Channels, ServiceIdentity, CHANNELS_FILE_VARIABLE};
+use me_tv::frontend_info::DeliverySystem;
+use me_tv::m3u::{group_of, playlist, PlaylistEntry};
+use me_tv::multiplex_signals::{multiplex_signals_path, read_multiplex_signals, signal_of, MultiplexSignal};
 
-/// https://url.spec.whatwg.org/#fragment-percent-encode-set
-const FRAGMENT: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
-/// https://url.spec.whatwg.org/#path-percent-encode-set
-const PATH: &percent_encoding::AsciiSet = &FRAGMENT.add(b'#').add(b'?').add(b'{').add(b'}');
+use crate::channel_order::{ChannelOrder, ListedChannel};
+use crate::control_window::Message;
+use crate::favourites;
+use crate::preferences;
+use crate::recent_channels;
 
 /// Encode a string as used for display to one suitable to be an MRL.
 pub fn encode_to_mrl(channel_name: &String) -> String {
-    "dvb://".to_owned() + &percent_encoding::utf8_percent_encode(channel_name, PATH).to_string()
+    dvb_uri(channel_name)
 }
 
 /// Struct for the data of each channel stored for various lookups.
@@ -54,68 +60,434 @@ pub struct ChannelData {
     service_id: u16,
     // Channel 0 is not used so 0 can be used as "not yet known".
     pub logical_channel_number: u16,  // Used in control_window.rs.
+    // As the SDT says, not known until the multiplex of the channel has been tuned.
+    #[serde(default)]
+    pub encrypted: bool,  // Used in control_window.rs.
+    // Whether the channel is sound only, as the channels file or the SDT says.
+    #[serde(default)]
+    pub radio: bool,  // Used in control_window.rs.
+    // The DVBv5 name, as in the channels file.
+    #[serde(default)]
+    pub delivery_system: String,  // Used in control_window.rs.
+    // Whether the logical channel number was given in the channel editor, rather than by
+    // the SI packets, which then do not change it.
+    #[serde(default)]
+    pub numbered_by_user: bool,
+    // Whether the channel editor says the channel is not to be listed.
+    #[serde(default)]
+    pub hidden: bool,
+    // The frequency of the multiplex, as in the channels file, which tells apart the
+    // copies of a service that overlapping transmitters give.
+    #[serde(default)]
+    pub frequency: u32,
+    // The NETWORK_ID of the channels file, the original network id, 0 if it is not given.
+    #[serde(default)]
+    network_id: u16,
+    // The TRANSPORT_ID of the channels file, the transport stream id, 0 if it is not given.
+    #[serde(default)]
+    transport_stream_id: u16,
+    // How strong the signal of the multiplex was when it was scanned, 0 to 65535, if known.
+    #[serde(default)]
+    pub signal_strength: Option<u16>,
+    // Whether the channel editor says this is the copy of its service to list and tune.
+    #[serde(default)]
+    pub preferred: bool,
+    // The name of the provider of the service, as the SDT says, not known until the
+    // multiplex of the channel has been tuned.
+    #[serde(default)]
+    pub provider: Option<String>,  // Used in control_window.rs.
+    // The POLARIZATION of the channels file, for satellite channels.
+    #[serde(default)]
+    polarization: Option<String>,
+    // The service type of the service descriptor of the SDT, EN 300 468 table 87.
+    #[serde(default)]
+    service_type: Option<u8>,
+    // What the signal of the multiplex was when it was last scanned, for showing, the
+    // multiplex signals file being read afresh rather than its text being cached.
+    #[serde(skip)]
+    scan_signal: Option<String>,
+}
+
+impl ChannelData {
+    /// The identity of the service of the channel, the ids not given being unknown.
+    fn identity(&self) -> ServiceIdentity {
+        let known = |id: u16| if id == 0 { None } else { Some(id) };
+        ServiceIdentity {
+            original_network_id: known(self.network_id),
+            transport_stream_id: known(self.transport_stream_id),
+            service_id: self.service_id,
+        }
+    }
+}
+
+/// The channels that changed name, were added, and were lost when the channels files
+/// were reread.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChannelChanges {
+    pub renamed: Vec<(String, String)>,  // The old name and the new one.
+    pub added: Vec<String>,
+    pub lost: Vec<String>,
+}
+
+impl ChannelChanges {
+    pub fn is_empty(&self) -> bool {
+        self.renamed.is_empty() && self.added.is_empty() && self.lost.is_empty()
+    }
 }
 
 // A singleton of the channels data currently known.
 //
 // This is initialised from the GStreamer channels data file, then augmented from the
-// Me TV data cache file, and then updated as `LogicalChannelDescriptor` are received.
+// Me TV data cache file, and then updated as `LogicalChannelDescriptor` and SDT
+// services are received.
 // The data is written to the cache file as and when.
 //
 // TODO need to update the ListStore in the ControlWindow instance
 //   whenever a change is made here.
 //
+// The channels read from the channels files of the preferences rather than the default
+// one are kept with the file each was read from, as that is the file dvbbasebin must be
+// told of to tune it.
+//
+// The default channels file is found at start up: GST_DVB_CHANNELS_CONF is changed as
+// channels from other channels files are tuned.
+//
+// A channels file can have several channels of a name, copies of a service found on
+// the multiplexes of overlapping transmitters. dvbbasebin tunes the first of the name,
+// so the copies to list and tune that are not the first are written to a channels file
+// of their own and tuned from that.
+//
 lazy_static! {
     static ref CHANNELS_DATA: RwLock<Option<Vec<ChannelData>>> = RwLock::new(initialise_channels_data());
+    static ref FURTHER_CHANNELS_FILES: RwLock<HashMap<String, Box<Path>>> = RwLock::new(HashMap::new());
+    static ref PREFERRED_COPIES: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
+    static ref DEFAULT_CHANNELS_FILE_PATH: Box<Path> = me_tv::channels_file::channels_file_path();
 }
 
 /// Construct the value to be used to initialise `CHANNELS_DATA`.
 ///
-/// First read the data from the GStreamer channels data file (if it exists) and then
-/// augment using the Me TV data cache file (if it exists).
+/// First read the data from the GStreamer channels data file (if it exists) and the
+/// further channels files of the preferences (those that exist) and then augment using
+/// the Me TV data cache file (if it exists).
 fn initialise_channels_data() -> Option<Vec<ChannelData>> {
-    match ini::Ini::load_from_file(channels_file_path()) {
-        Ok(ini) => {
-            let mut channel_data = process_ini(&ini);
-            if let Some(cache) = read_channels_data_cache(&channels_data_cache_path()) {
-                let table = cache
-                    .iter()
-                    .map(|x|(x.service_id, x.logical_channel_number))
-                    .collect::<HashMap<u16, u16>>();
-                channel_data = channel_data
-                    .iter()
-                    .map(|x| if x.logical_channel_number == 0 {
-                        let logical_channel_number = match table.get(&x.service_id) {
-                            Some(x) => *x,
-                            None => 0,
-                        };
-                        ChannelData {
-                            name: x.name.clone(),
-                            service_id: x.service_id,
-                            logical_channel_number,
-                        }
-                    } else {
-                        x.clone()
-                    })
-                    .collect();
+    let path = channels_file_path();
+    let channels = read_channels(&path).ok();
+    let further = read_further_channels_files();
+    if channels.is_none() && further.is_empty() {
+        return None;
+    }
+    let (channels, files) = merge_channels(channels.unwrap_or_default(), further);
+    *FURTHER_CHANNELS_FILES.write().unwrap() = files;
+    let cache = read_channels_data_cache(&channels_data_cache_path());
+    let mut data = augmented_channels_data(&path, &channels, cache.as_deref());
+    add_multiplex_signals(&mut data, &read_scanned_signals());
+    // The channels files may have been rescanned since Me TV was last run.
+    if let Some(cache) = &cache {
+        migrate_renamed_channels(&channel_changes(cache, &data).renamed);
+    }
+    *PREFERRED_COPIES.write().unwrap() = write_preferred_copies_file(&preferred_copies_file_path(), &channels.channels, &data);
+    Some(data)
+}
+
+/// The channels of each of the further channels files of the preferences that can be
+/// read, with the file.
+fn read_further_channels_files() -> Vec<(Box<Path>, Channels)> {
+    preferences::get_channels_files().unwrap_or_default()
+        .iter()
+        .map(|name| Path::new(name).to_path_buf().into_boxed_path())
+        .filter(|path| *path != channels_file_path())
+        .filter_map(|path| match read_channels(&path) {
+            Ok(channels) => {
+                for warning in &channels.warnings {
+                    warn!("{}: {}", path.display(), warning);
+                }
+                Some((path, channels))
+            },
+            Err(e) => {
+                warn!("Could not read {}, {}, leaving its channels out.", path.display(), e);
+                None
+            },
+        })
+        .collect()
+}
+
+/// Add the channels of the further channels files to those of the default one, in file
+/// order. A channel with the name of one already there is left out, the name having to
+/// say which channel is meant. Return the channels with the file each added one is from.
+fn merge_channels(channels: Channels, further: Vec<(Box<Path>, Channels)>) -> (Channels, HashMap<String, Box<Path>>) {
+    let mut channels = channels;
+    let mut files = HashMap::new();
+    for (path, more) in further {
+        for channel in more.channels {
+            if channels.channels.iter().any(|x| x.name == channel.name) {
+                warn!("{}: {} is already in another channels file, leaving it out.", path.display(), channel.name);
+            } else {
+                files.insert(channel.name.clone(), path.clone());
+                channels.channels.push(channel);
             }
-            Some(channel_data)
-        },
-        Err(_) => None,
+        }
+    }
+    (channels, files)
+}
+
+/// The channels data of `candidates` that is that of the channel `x`, if there is one:
+/// the first of the service with the identity of `x` on the same frequency, a copy of
+/// a service being on the frequency of its transmitter, then the first of the service
+/// anywhere else, for when the multiplex has moved or the data is from before
+/// frequencies were kept. Failing those it is the first of the name of `x`, for when
+/// the service id has changed.
+///
+/// Identities with the same service id are taken to be of the same service unless both
+/// have the network and transport stream ids and they differ, as channels files written
+/// by some scanners do not have them.
+fn find_match<'a>(x: &ChannelData, candidates: &'a [ChannelData]) -> Option<&'a ChannelData> {
+    let identity = x.identity();
+    let same = |y: &ChannelData| identity.is_complete() && y.identity() == identity;
+    let compatible = |y: &ChannelData| identity.may_be(&y.identity());
+    candidates.iter().find(|y| same(y) && y.frequency == x.frequency)
+        .or_else(|| candidates.iter().find(|y| same(y)))
+        .or_else(|| candidates.iter().find(|y| compatible(y) && y.frequency == x.frequency))
+        .or_else(|| candidates.iter().find(|y| compatible(y)))
+        .or_else(|| candidates.iter().find(|y| y.name == x.name))
+}
+
+/// The channels renamed, added, and lost going from the channels data `old` to `new`.
+/// A channel whose name has gone is renamed if the data of a channel with a new name is
+/// that of its service.
+fn channel_changes(old: &[ChannelData], new: &[ChannelData]) -> ChannelChanges {
+    let has_name = |data: &[ChannelData], name: &str| data.iter().any(|x| x.name == name);
+    let mut changes = ChannelChanges::default();
+    for x in old.iter().filter(|x| !has_name(new, &x.name)) {
+        if changes.renamed.iter().any(|(name, _)| *name == x.name) || changes.lost.contains(&x.name) {
+            continue;
+        }
+        match find_match(x, new) {
+            Some(y) if !has_name(old, &y.name) && !changes.renamed.iter().any(|(_, name)| *name == y.name) => changes.renamed.push((x.name.clone(), y.name.clone())),
+            _ => changes.lost.push(x.name.clone()),
+        }
+    }
+    for y in new.iter().filter(|y| !has_name(old, &y.name)) {
+        if !changes.renamed.iter().any(|(_, name)| *name == y.name) && !changes.added.contains(&y.name) {
+            changes.added.push(y.name.clone());
+        }
+    }
+    changes
+}
+
+/// Have the favourites, the recently watched channels, and the default and last
+/// channels of the preferences follow the channels that have been renamed.
+fn migrate_renamed_channels(renamed: &[(String, String)]) {
+    for (name, new_name) in renamed {
+        info!("{} is now called {}.", name, new_name);
+        favourites::rename(name, new_name);
+        recent_channels::rename(name, new_name);
+        if preferences::get_default_channel().as_deref() == Some(name.as_str()) {
+            preferences::set_default_channel(new_name.to_string(), true);
+        }
+        if preferences::get_last_channel().as_deref() == Some(name.as_str()) {
+            preferences::set_last_channel(new_name.to_string(), true);
+        }
+    }
+}
+
+/// The channels data of the channels read from the channels file at `path`, augmented
+/// using the Me TV data cache, if there is one.
+fn augmented_channels_data(path: &Path, channels: &Channels, cache: Option<&[ChannelData]>) -> Vec<ChannelData> {
+    for warning in &channels.warnings {
+        warn!("{}: {}", path.display(), warning);
+    }
+    let mut channel_data = process_channels(&channels.channels);
+    if let Some(cache) = cache {
+        channel_data = channel_data
+            .iter()
+            .map(|x| match find_match(x, cache) {
+                Some(cached) => ChannelData {
+                    name: x.name.clone(),
+                    service_id: x.service_id,
+                    logical_channel_number: if x.logical_channel_number == 0 || cached.numbered_by_user { cached.logical_channel_number } else { x.logical_channel_number },
+                    encrypted: x.encrypted || cached.encrypted,
+                    radio: x.radio || cached.radio,
+                    delivery_system: x.delivery_system.clone(),
+                    numbered_by_user: cached.numbered_by_user,
+                    hidden: cached.hidden,
+                    frequency: x.frequency,
+                    network_id: x.network_id,
+                    transport_stream_id: x.transport_stream_id,
+                    signal_strength: if cached.frequency == x.frequency { x.signal_strength.or(cached.signal_strength) } else { x.signal_strength },
+                    preferred: cached.frequency == x.frequency && cached.preferred,
+                    provider: cached.provider.clone(),
+                    polarization: x.polarization.clone(),
+                    service_type: cached.service_type,
+                    scan_signal: x.scan_signal.clone(),
+                },
+                None => x.clone(),
+            })
+            .collect();
+    }
+    channel_data
+}
+
+/// Why the channels data should not be replaced by that of the channels read from the
+/// channels file at `path`, if it should not: none of the file could be read, so it is
+/// likely it is not the channels file it is meant to be, or is still being written.
+fn unusable_reason(path: &Path, channels: &Channels) -> Option<String> {
+    match channels.warnings.first() {
+        Some(warning) if channels.channels.is_empty() => Some(format!("no channels could be read from {}, {}", path.display(), warning)),
+        _ => None,
     }
 }
 
-/// Process an `Ini` to create a `Vec<ChannelData>`
-fn process_ini(ini: &ini::Ini) -> Vec<ChannelData> {
-    ini.iter()
-        .map(|(name, properties)| ChannelData{
-            name: name.unwrap().to_string(),
-            service_id: properties.get("SERVICE_ID").unwrap().parse::<u16>().unwrap(),
+/// Process the channels of a channels file, whatever its format, to create a `Vec<ChannelData>`
+fn process_channels(channels: &[Channel]) -> Vec<ChannelData> {
+    channels.iter()
+        .map(|channel| ChannelData{
+            name: channel.name.clone(),
+            service_id: channel.tuning.service_id,
             logical_channel_number: 0,
+            encrypted: false,
+            radio: channel.is_radio(),
+            delivery_system: channel.tuning.delivery_system.clone(),
+            numbered_by_user: false,
+            hidden: false,
+            frequency: channel.tuning.frequency,
+            network_id: channel.parameters.get("NETWORK_ID").and_then(|id| id.parse().ok()).unwrap_or(0),
+            transport_stream_id: channel.parameters.get("TRANSPORT_ID").and_then(|id| id.parse().ok()).unwrap_or(0),
+            signal_strength: None,
+            preferred: false,
+            provider: None,
+            polarization: channel.parameters.get("POLARIZATION").cloned(),
+            service_type: None,
+            scan_signal: None,
         })
         .collect()
 }
 
+/// The signals of the multiplexes when they were last scanned, none if the multiplex
+/// signals file cannot be read.
+fn read_scanned_signals() -> Vec<MultiplexSignal> {
+    let path = multiplex_signals_path();
+    read_multiplex_signals(&path).unwrap_or_else(|e| {
+        warn!("Could not read the multiplex signals file {}, {}", path.display(), e);
+        Vec::new()
+    })
+}
+
+/// Give the channels of `channels_data` on multiplexes that have been scanned the signal
+/// strength and signal of them when they were, so that the strongest copy of a service
+/// is the one listed.
+fn add_multiplex_signals(channels_data: &mut [ChannelData], signals: &[MultiplexSignal]) {
+    for x in channels_data.iter_mut() {
+        if let Some(scanned) = signal_of(signals, x.frequency, x.polarization.as_deref()) {
+            x.signal_strength = scanned.signal.strength_level().or(x.signal_strength);
+            x.scan_signal = Some(format!("When scanned on {}: {}", scanned.scanned.format("%-d %b %Y"), scanned.signal));
+        }
+    }
+}
+
+/// What the service type of the SDT, EN 300 468 table 87, says a service is, if it is a
+/// type of service that is watched or listened to.
+fn service_type_text(service_type: u8) -> Option<&'static str> {
+    match service_type {
+        0x01 => Some("digital television"),
+        0x02 => Some("digital radio"),
+        0x07 => Some("FM radio"),
+        0x0A => Some("advanced codec digital radio"),
+        0x11 => Some("MPEG-2 HD digital television"),
+        0x16 => Some("advanced codec SD digital television"),
+        0x19 => Some("advanced codec HD digital television"),
+        0x1C => Some("advanced codec frame compatible plano-stereoscopic HD digital television"),
+        0x1F => Some("HEVC digital television"),
+        0x20 => Some("HEVC UHD digital television"),
+        _ => None,
+    }
+}
+
+/// A frequency of the channels data as the user would know it: satellite frequencies
+/// are in kHz, others in Hz.
+pub fn frequency_text(frequency: u32) -> String {  // Used in channel_editor.rs.
+    match frequency {
+        0 => "".to_string(),
+        f if f < 100_000_000 => format!("{} MHz", f as f64 / 1_000.0),
+        f => format!("{} MHz", f as f64 / 1_000_000.0),
+    }
+}
+
+/// The details of a channel for the user to tell it from other channels, the copies of
+/// its service say: its provider, the type of service, its multiplex, its service id, and
+/// the signal of its multiplex when scanned.
+fn details_text(x: &ChannelData) -> String {
+    let mut lines = vec![x.name.clone()];
+    if let Some(provider) = &x.provider {
+        lines.push(format!("Provider: {}", provider));
+    }
+    match x.service_type {
+        Some(service_type) => lines.push(format!("Service type: {}", service_type_text(service_type).map_or_else(|| format!("0x{:02X}", service_type), String::from))),
+        None if x.radio => lines.push("Service type: radio".to_string()),
+        None => {},
+    }
+    let mut multiplex = vec![x.delivery_system.clone(), frequency_text(x.frequency)];
+    if let Some(polarization) = &x.polarization {
+        multiplex.push(format!("{} polarisation", polarization.to_lowercase()));
+    }
+    lines.push(multiplex.into_iter().filter(|part| !part.is_empty()).collect::<Vec<_>>().join(", "));
+    lines.push(format!("Service id: {}", x.service_id));
+    if let Some(scan_signal) = &x.scan_signal {
+        lines.push(scan_signal.clone());
+    }
+    lines.join("\n")
+}
+
+/// Whether `a` and `b` are copies of a service, as when a scan finds the service on
+/// the multiplexes of two transmitters whose areas overlap: they have the same original
+/// network and service ids, or the same name if the network of either is not known.
+fn are_copies(a: &ChannelData, b: &ChannelData) -> bool {
+    if a.network_id != 0 && b.network_id != 0 {
+        a.network_id == b.network_id && a.service_id == b.service_id
+    } else {
+        a.name == b.name
+    }
+}
+
+/// For each of `channels_data`, whether it is the copy of its service that is listed
+/// and tuned: the one the channel editor says, otherwise the one with the strongest
+/// signal when scanned, if that is known, otherwise the first.
+fn listed_copies(channels_data: &[ChannelData]) -> Vec<bool> {
+    (0..channels_data.len())
+        .map(|i| {
+            let copies = (0..channels_data.len()).filter(|&j| are_copies(&channels_data[i], &channels_data[j])).collect::<Vec<_>>();
+            let listed = copies.iter().copied().find(|&j| channels_data[j].preferred)
+                .or_else(|| copies.iter().copied().filter(|&j| channels_data[j].signal_strength.is_some()).max_by_key(|&j| (channels_data[j].signal_strength, Reverse(j))))
+                .unwrap_or(copies[0]);
+            listed == i
+        })
+        .collect()
+}
+
+/// Write the listed copies of `channels`, whose data is `channels_data`, that dvbbasebin
+/// would not tune, there being another channel of the name before them, to a channels
+/// file at `path`, removing it if there are none. Return the names of those written.
+fn write_preferred_copies_file(path: &Path, channels: &[Channel], channels_data: &[ChannelData]) -> HashSet<String> {
+    let listed = listed_copies(channels_data);
+    let preferred = channels.iter().enumerate()
+        .filter(|&(index, channel)| listed[index] && channels[..index].iter().any(|x| x.name == channel.name))
+        .map(|(_, channel)| channel)
+        .collect::<Vec<_>>();
+    if preferred.is_empty() {
+        if let Err(e) = fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Could not remove {} – {}", path.display(), e);
+            }
+        }
+        return HashSet::new();
+    }
+    let contents = preferred.iter().map(|channel| channel.channels_file_entry()).collect::<Vec<_>>().join("\n");
+    if let Err(e) = create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(path, contents)) {
+        warn!("Could not write {}, the first channel of each name will be tuned – {}", path.display(), e);
+        return HashSet::new();
+    }
+    preferred.iter().map(|channel| channel.name.clone()).collect()
+}
+
 /// Read channels data from the channels file, if it exists, augmented by the cache data, if it exists.
 ///
 /// Return Boolean specifies whether the data was set to `Some`thing (`true`) or `None` (`false`).
@@ -134,17 +506,70 @@ pub fn read_channels_data() -> bool { // Used in control_window.rs after a chann
     }
 }
 
+/// Reread the channels file after it has changed, the preferences following the
+/// channels that have been renamed, and return what changed. If it cannot be read, or
+/// none of it can be, the channels data is left as it was and the reason returned.
+pub fn reload_channels_data() -> Result<ChannelChanges, String> { // Used in control_window.rs when the channels file changes.
+    let path = channels_file_path();
+    let channels = read_channels(&path).map_err(|e| format!("could not read {}, {}", path.display(), e))?;
+    if let Some(reason) = unusable_reason(&path, &channels) {
+        return Err(reason);
+    }
+    let (channels, files) = merge_channels(channels, read_further_channels_files());
+    let mut data = augmented_channels_data(&path, &channels, read_channels_data_cache(&channels_data_cache_path()).as_deref());
+    add_multiplex_signals(&mut data, &read_scanned_signals());
+    let changes = channel_changes(CHANNELS_DATA.read().unwrap().as_deref().unwrap_or_default(), &data);
+    migrate_renamed_channels(&changes.renamed);
+    *FURTHER_CHANNELS_FILES.write().unwrap() = files;
+    *PREFERRED_COPIES.write().unwrap() = write_preferred_copies_file(&preferred_copies_file_path(), &channels.channels, &data);
+    *CHANNELS_DATA.write().unwrap() = Some(data);
+    Ok(changes)
+}
+
 /// Return a `Vec` containing the (logical number, name) pairs of the channels from the channels data.
 fn get_numbers_and_names_from_channels_data(channels_data: &Vec<ChannelData>) -> Vec<(u16, String)> {
     channels_data.iter().map(|x| (x.logical_channel_number, x.name.clone()) ).collect()
 }
 
-/// Return a `Box<Path>` to the GStreamer dvbsrc plugin channels file using the XDG directory structure.
+/// Return a `Box<Path>` to the default channels file, the GStreamer dvbsrc plugin one
+/// as it was when Me TV started.
 pub fn channels_file_path() -> Box<Path> {
-    let xdg_dirs = xdg::BaseDirectories::with_prefix("gstreamer-1.0").expect("Cannot set XDG prefix.");
-    let mut path_buf = xdg_dirs.get_config_home();
-    path_buf.push("dvb-channels.conf");
-    path_buf.into_boxed_path()
+    DEFAULT_CHANNELS_FILE_PATH.clone()
+}
+
+/// The channels of the default channels file and of the further channels files of the
+/// preferences, as they are now, in file order and with any duplicates. A file that
+/// cannot be read has none, which will have been reported when the channels were loaded.
+pub fn read_all_channels() -> Vec<Channel> {  // Used in epg_harvester.rs.
+    let further = preferences::get_channels_files().unwrap_or_default();
+    iter::once(channels_file_path())
+        .chain(further.iter().map(|name| Path::new(name).to_path_buf().into_boxed_path()))
+        .filter_map(|path| read_channels(&path).ok())
+        .flat_map(|channels| channels.channels)
+        .collect()
+}
+
+/// Return a `Box<Path>` to the channels file the channel `channel_name` was read from.
+pub fn channels_file_of(channel_name: &str) -> Box<Path> {  // Used in gstreamer_engine.rs.
+    match FURTHER_CHANNELS_FILES.read().unwrap().get(channel_name) {
+        Some(path) => path.clone(),
+        None => channels_file_path(),
+    }
+}
+
+/// Return a `Box<Path>` to the channels file dvbbasebin must read to tune the listed copy
+/// of the channel `channel_name`.
+pub fn tuning_file_of(channel_name: &str) -> Box<Path> {  // Used in gstreamer_engine.rs.
+    if PREFERRED_COPIES.read().unwrap().contains(channel_name) {
+        preferred_copies_file_path()
+    } else {
+        channels_file_of(channel_name)
+    }
+}
+
+/// Have dvbbasebin read the channels file `path` when it is next given a channel to tune.
+pub fn select_channels_file(path: &Path) {  // Used in gstreamer_engine.rs.
+    env::set_var(CHANNELS_FILE_VARIABLE, path);
 }
 
 /// Return a `Box<Path>` to the Me TV channels data cache file using the XDG directory structure.
@@ -173,12 +598,223 @@ pub fn get_channels_data() -> Option<Vec<(u16, String)>> {
     }
 }
 
+/// Return a `Vec` containing, for each channel of the channels data, its frequency,
+/// whether other channels are copies of its service, and whether it is the copy that
+/// is listed.
+pub fn get_copies_data() -> Vec<(u32, bool, bool)> {  // Used in control_window.rs and channel_editor.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    match &*channels_data {
+        Some(c_d) => c_d.iter()
+            .zip(listed_copies(c_d))
+            .map(|(x, listed)| (x.frequency, c_d.iter().filter(|y| are_copies(x, y)).count() > 1, listed))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Have the channel at `position` in the channels data be the copy of its service that
+/// is listed and tuned, as the channel editor says. The channels files must be reread
+/// for it to be tuned.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+pub fn prefer_copy(position: usize) -> bool {  // Used in channel_editor.rs.
+    let mut channels_data = CHANNELS_DATA.write().unwrap();
+    let c_d = match channels_data.as_mut() {
+        Some(c_d) if position < c_d.len() => c_d,
+        _ => return false,
+    };
+    let chosen = c_d[position].clone();
+    let mut rv = false;
+    for (index, x) in c_d.iter_mut().enumerate() {
+        if are_copies(x, &chosen) && x.preferred != (index == position) {
+            x.preferred = index == position;
+            rv = true;
+        }
+    }
+    if rv {
+        write_channels_data_cache(&*channels_data_cache_path(), c_d);
+    }
+    rv
+}
+
+/// Return a `Vec` containing the details of each channel of the channels data, for the
+/// user to tell copies of a service apart.
+pub fn get_details_data() -> Vec<String> {  // Used in channel_editor.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    channels_data.as_ref().map_or_else(Vec::new, |c_d| c_d.iter().map(details_text).collect())
+}
+
+/// The details of the listed channel `channel_name`, if there is one, for the user to
+/// tell it from other channels.
+pub fn get_channel_details(channel_name: &str) -> Option<String> {  // Used in control_window_button.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    let c_d = channels_data.as_ref()?;
+    c_d.iter().zip(listed_copies(c_d)).find(|(x, listed)| *listed && x.name == channel_name).map(|(x, _)| details_text(x))
+}
+
+/// Return a `Vec` containing the (name, service id) pairs of the channels from the channels data.
+pub fn get_channel_names_and_service_ids() -> Option<Vec<(String, u16)>> {
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    channels_data.as_ref().map(|c_d| c_d.iter().map(|x| (x.name.clone(), x.service_id)).collect())
+}
+
 /// Update the channels file data.
 ///
-/// For use when getting SI packets that build the Logical Channel Table.
+/// For use when getting SI packets that build the Logical Channel Table. A channel
+/// numbered in the channel editor keeps its number.
 ///
 /// Return `true` if a change was made to the channels data, `false` otherwise.
 pub fn add_logical_channel_number_for_service_id(service_id: u16, logical_channel_number: u16, to_cw: Option<&glib::Sender<Message>>) -> bool {
+    update_channel_data_for_service_id(
+        service_id,
+        |x| if x.logical_channel_number != logical_channel_number && !x.numbered_by_user {
+            Some(ChannelData { logical_channel_number, ..x.clone() })
+        } else {
+            None
+        },
+        |cd| Message::UpdatedLogicalChannelNumber { cd },
+        to_cw,
+    )
+}
+
+/// Update the channels file data.
+///
+/// For use when getting SDT sections, which say whether each service is encrypted.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+pub fn set_encrypted_for_service_id(service_id: u16, encrypted: bool, to_cw: Option<&glib::Sender<Message>>) -> bool {
+    update_channel_data_for_service_id(
+        service_id,
+        |x| if x.encrypted != encrypted {
+            Some(ChannelData { encrypted, ..x.clone() })
+        } else {
+            None
+        },
+        |cd| Message::UpdatedEncryption { cd },
+        to_cw,
+    )
+}
+
+/// Update the channels file data.
+///
+/// For use when getting SDT sections, the service type of each service saying whether
+/// it is a radio service.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+pub fn set_radio_for_service_id(service_id: u16, radio: bool, to_cw: Option<&glib::Sender<Message>>) -> bool {
+    update_channel_data_for_service_id(
+        service_id,
+        |x| if x.radio != radio {
+            Some(ChannelData { radio, ..x.clone() })
+        } else {
+            None
+        },
+        |cd| Message::UpdatedRadio { cd },
+        to_cw,
+    )
+}
+
+/// Update the channels file data.
+///
+/// For use when getting SDT sections, the service descriptor of each service giving
+/// the name of its provider.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+pub fn set_provider_for_service_id(service_id: u16, provider: &str, to_cw: Option<&glib::Sender<Message>>) -> bool {
+    update_channel_data_for_service_id(
+        service_id,
+        |x| if !provider.is_empty() && x.provider.as_deref() != Some(provider) {
+            Some(ChannelData { provider: Some(provider.to_string()), ..x.clone() })
+        } else {
+            None
+        },
+        |cd| Message::UpdatedProvider { cd },
+        to_cw,
+    )
+}
+
+/// Update the channels file data.
+///
+/// For use when getting SDT sections, the service descriptor of each service giving
+/// its service type.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+pub fn set_service_type_for_service_id(service_id: u16, service_type: u8) -> bool {
+    update_channel_data(
+        |x| x.service_id == service_id,
+        |x| if x.service_type != Some(service_type) {
+            Some(ChannelData { service_type: Some(service_type), ..x.clone() })
+        } else {
+            None
+        },
+        |_| {},
+    )
+}
+
+/// Update the channels data.
+///
+/// For use by the channel editor, a logical channel number of 0 giving the channel back
+/// to the SI packets to number.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+pub fn set_logical_channel_number_of(channel_name: &str, logical_channel_number: u16) -> bool {  // Used in channel_editor.rs.
+    update_channel_data(
+        |x| x.name == channel_name,
+        |x| if x.logical_channel_number != logical_channel_number || x.numbered_by_user != (logical_channel_number != 0) {
+            Some(ChannelData { logical_channel_number, numbered_by_user: logical_channel_number != 0, ..x.clone() })
+        } else {
+            None
+        },
+        |_| {},
+    )
+}
+
+/// Update the channels data.
+///
+/// For use by the channel editor.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+pub fn set_hidden(channel_name: &str, hidden: bool) -> bool {  // Used in channel_editor.rs.
+    update_channel_data(
+        |x| x.name == channel_name,
+        |x| if x.hidden != hidden {
+            Some(ChannelData { hidden, ..x.clone() })
+        } else {
+            None
+        },
+        |_| {},
+    )
+}
+
+/// Replace the channels data of the channels with `service_id` that `update` changes,
+/// telling the control window, if there is one, with the `message` of the changed data.
+/// The cache file is written if anything changed.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+fn update_channel_data_for_service_id(
+    service_id: u16,
+    update: impl Fn(&ChannelData) -> Option<ChannelData>,
+    message: impl Fn(ChannelData) -> Message,
+    to_cw: Option<&glib::Sender<Message>>,
+) -> bool {
+    update_channel_data(
+        |x| x.service_id == service_id,
+        update,
+        |cd| if let Some(to_cw) = to_cw {
+            to_cw.send(message(cd.clone())).unwrap();
+        },
+    )
+}
+
+/// Replace the channels data of the `selected` channels that `update` changes, passing
+/// each changed one to `changed`. The cache file is written if anything changed.
+///
+/// Return `true` if a change was made to the channels data, `false` otherwise.
+fn update_channel_data(
+    selected: impl Fn(&ChannelData) -> bool,
+    update: impl Fn(&ChannelData) -> Option<ChannelData>,
+    changed: impl Fn(&ChannelData),
+) -> bool {
     // TODO This does a full (albeit shallow) copy of the data structure, should a more
     //   efficient way of doing the update be found?
     //   Freeview from Crystal Palace has a maximum 184 channels as at 2020-07-07.
@@ -190,19 +826,14 @@ pub fn add_logical_channel_number_for_service_id(service_id: u16, logical_channe
                 c_d
                     .iter()
                     .map(|x| {
-                        if x.service_id == service_id && x.logical_channel_number != logical_channel_number {
-                            rv = true;
-                            let cd = ChannelData {
-                                name: x.name.clone(),
-                                service_id: x.service_id,
-                                logical_channel_number,
-                            };
-                            if to_cw.is_some() {
-                                to_cw.unwrap().send(Message::UpdatedLogicalChannelNumber { cd: cd.clone() }).unwrap();
-                            }
-                            cd
-                        } else {
-                            x.clone()
+                        let updated = if selected(x) { update(x) } else { None };
+                        match updated {
+                            Some(cd) => {
+                                rv = true;
+                                changed(&cd);
+                                cd
+                            },
+                            None => x.clone(),
                         }
                     })
                     .collect()
@@ -230,7 +861,12 @@ pub fn get_channel_name_of_logical_channel_number(logical_channel_number: u16) -
             Some(c_d) => {
                 // TODO Can we do better than linear search, or does it not matter?
                 //    Freeview from Crystal Palace has a maximum 182 channels as at 2020-07-07.
-                let result: Vec<&ChannelData> = c_d.iter().filter(|x| x.logical_channel_number == logical_channel_number).collect();
+                // Copies of a service have its number, only the listed one is meant.
+                let result: Vec<&ChannelData> = c_d.iter()
+                    .zip(listed_copies(c_d))
+                    .filter(|(x, listed)| *listed && x.logical_channel_number == logical_channel_number)
+                    .map(|(x, _)| x)
+                    .collect();
                 match result.len() {
                     0 => None,
                     1 => Some(result[0].name.clone()),
@@ -242,12 +878,73 @@ pub fn get_channel_name_of_logical_channel_number(logical_channel_number: u16) -
     }
 }
 
+/// Whether the channel `channel_name` is known to be encrypted.
+pub fn is_encrypted(channel_name: &str) -> bool {  // Used in control_window.rs and control_window_button.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    match &*channels_data {
+        Some(c_d) => c_d.iter().any(|x| x.name == channel_name && x.encrypted),
+        None => false,
+    }
+}
+
+/// The delivery system of the channel `channel_name`, if it is known.
+pub fn get_delivery_system(channel_name: &str) -> Option<DeliverySystem> {  // Used in control_window.rs and frontend_manager.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    channels_data.as_ref()?.iter().find(|x| x.name == channel_name)?.delivery_system.parse::<DeliverySystem>().ok()
+}
+
+/// The name of the provider of the channel `channel_name`, if it is known.
+pub fn get_provider(channel_name: &str) -> Option<String> {  // Used in control_window.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    channels_data.as_ref()?.iter().find(|x| x.name == channel_name)?.provider.clone()
+}
+
+/// Whether the channel editor says the channel `channel_name` is not to be listed.
+pub fn is_hidden(channel_name: &str) -> bool {  // Used in control_window.rs and channel_editor.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    match &*channels_data {
+        Some(c_d) => c_d.iter().any(|x| x.name == channel_name && x.hidden),
+        None => false,
+    }
+}
+
+/// Whether the channel `channel_name` is known to be a radio channel.
+pub fn is_radio(channel_name: &str) -> bool {  // Used in control_window.rs and frontend_window.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    match &*channels_data {
+        Some(c_d) => c_d.iter().any(|x| x.name == channel_name && x.radio),
+        None => false,
+    }
+}
+
+/// The channels, other than those the channel editor hides and copies of services not
+/// listed, as an extended M3U playlist in channel number order, each played from its
+/// dvb:// URL.
+pub fn channels_playlist() -> Option<String> {  // Used in control_window.rs and main.rs.
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    fn listed<'a>((position, x): &(usize, &'a ChannelData)) -> ListedChannel<'a> {
+        ListedChannel { number: x.logical_channel_number, name: &x.name, provider: x.provider.as_deref().unwrap_or(""), position: *position as u32 }
+    }
+    let c_d = channels_data.as_ref()?;
+    let mut channels = c_d.iter().enumerate().zip(listed_copies(c_d)).filter(|((_, x), listed)| *listed && !x.hidden).map(|(x, _)| x).collect::<Vec<_>>();
+    channels.sort_by(|a, b| ChannelOrder::LogicalChannelNumber.compare(&listed(a), &listed(b)));
+    let entries = channels.iter()
+        .map(|(_, x)| PlaylistEntry {
+            name: x.name.clone(),
+            number: x.logical_channel_number,
+            group: group_of(favourites::is_favourite(&x.name), x.radio),
+            url: dvb_uri(&x.name),
+        })
+        .collect::<Vec<_>>();
+    Some(playlist(&entries))
+}
+
 /// Write the channels data to a cache file.
 fn write_channels_data_cache(path: &Path, channels_data: &Vec<ChannelData>) {
     if let Err(error) = create_dir_all(path.parent().unwrap()) {
         panic!("create_dir_all({:?}) failed: {:?}", path.parent().unwrap(), error);
     }
-    match OpenOptions::new().write(true).create(true).open(path) {
+    match OpenOptions::new().write(true).truncate(true).create(true).open(path) {
         Ok(mut f) => {
             let s = serde_yaml::to_string(&channels_data).unwrap();
             match f.write(s.as_ref()) {
@@ -255,10 +952,10 @@ fn write_channels_data_cache(path: &Path, channels_data: &Vec<ChannelData>) {
                     assert_eq!(count, s.len());
                     f.flush().unwrap();
                 },
-                Err(e) => println!("Error writing {:?}, {:?} – {}", path.to_str().unwrap(), f, e),
+                Err(e) => warn!("Error writing {:?}, {:?} – {}", path.to_str().unwrap(), f, e),
             };
         },
-        Err(e) => println!("Failed to open {} – {}", path.to_str().unwrap(), e),
+        Err(e) => warn!("Failed to open {} – {}", path.to_str().unwrap(), e),
     };
 }
 
@@ -266,26 +963,25 @@ fn write_channels_data_cache(path: &Path, channels_data: &Vec<ChannelData>) {
 fn read_channels_data_cache(path: &Path) -> Option<Vec<ChannelData>> {
     match File::open(path) {
         Ok(mut f) => {
-            let mut buffer = [0u8; 20000];
-            match f.read(&mut buffer) {
-                Ok(count) => {
-                    let s = String::from_utf8_lossy(&buffer[..count]).to_string();
+            let mut s = String::new();
+            match f.read_to_string(&mut s) {
+                Ok(_) => {
                     match serde_yaml::from_str::<Vec<ChannelData>>(&s) {
                         Ok(x) => Some(x),
                         Err(e) => {
-                            println!("Failed to deserialise {} – {}", path.to_str().unwrap(), e);
+                            warn!("Failed to deserialise {} – {}", path.to_str().unwrap(), e);
                             None
                         },
                     }
                 },
                 Err(e) => {
-                    println!("Failed to read {} – {}", path.to_str().unwrap(), e);
+                    warn!("Failed to read {} – {}", path.to_str().unwrap(), e);
                     None
                 },
             }
         },
         Err(e) => {
-            println!("Failed to open {} – {}", path.to_str().unwrap(), e);
+            warn!("Failed to open {} – {}", path.to_str().unwrap(), e);
             None
         },
     }
@@ -295,22 +991,42 @@ fn read_channels_data_cache(path: &Path) -> Option<Vec<ChannelData>> {
 mod tests {
 
     use std::io::Read;
+    use std::path::Path;
     use std::sync::Mutex;
 
-    use ini;
+    use chrono::{Local, TimeZone};
+
     use lazy_static::lazy_static;
     use tempfile;
 
+    use me_tv::channels_file::{parse_channels, parse_zap, read_channel_names, read_channels};
+    use me_tv::multiplex_signals::{MultiplexSignal, TransponderSignal};
+    use me_tv::signal_monitor::Measurement;
+
     use super::{
-        add_logical_channel_number_for_service_id,
+        add_logical_channel_number_for_service_id, add_multiplex_signals,
+        are_copies, listed_copies, prefer_copy, write_preferred_copies_file,
+        channel_changes, find_match,
         channels_file_path,
-        encode_to_mrl, process_ini,
+        encode_to_mrl, process_channels, unusable_reason,
         get_numbers_and_names_from_channels_data,
         get_channel_name_of_logical_channel_number,
+        get_provider,
+        is_encrypted,
+        is_hidden,
+        is_radio,
+        merge_channels,
         read_channels_data,
+        set_encrypted_for_service_id,
+        set_hidden,
+        set_logical_channel_number_of,
+        set_provider_for_service_id,
+        set_radio_for_service_id,
+        set_service_type_for_service_id,
+        details_text, frequency_text, get_channel_details,
         write_channels_data_cache,
         read_channels_data_cache,
-        ChannelData, CHANNELS_DATA
+        ChannelChanges, ChannelData, CHANNELS_DATA
     };
 
     #[test]
@@ -382,12 +1098,11 @@ mod tests {
         HIERARCHY = NONE
         DELIVERY_SYSTEM = DVBT
 ";
-        let ini = ini::Ini::load_from_str(data).unwrap();
-        process_ini(&ini)
+        process_channels(&parse_channels(data).channels)
     }
 
     #[test]
-    fn process_ini_with_two_entries() {
+    fn process_channels_with_two_entries() {
         let result = create_two_entry_channel_data_vec();
         assert_eq!(result.len(), 2);
         let bbc_1 = &result[0];
@@ -400,6 +1115,27 @@ mod tests {
         assert_eq!(bbc_2.logical_channel_number,  0);
     }
 
+    #[test]
+    fn process_channels_from_a_zap_file() {
+        let channels = parse_zap("BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164\n");
+        let result = process_channels(&channels.channels);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].name, "BBC ONE Lon");
+        assert_eq!(result[0].service_id, 4164);
+        assert_eq!(result[0].logical_channel_number, 0);
+    }
+
+    #[test]
+    fn a_channels_file_none_of_which_can_be_read_is_unusable() {
+        let path = std::path::Path::new("dvb-channels.conf");
+        assert_eq!(unusable_reason(path, &parse_channels("")), None);
+        assert_eq!(unusable_reason(path, &parse_zap("BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164\nBBC TWO:490000000\n")), None);
+        assert_eq!(
+            unusable_reason(path, &parse_channels("[BBC ONE Lon]\n\tSERVICE_ID = 4164\n")),
+            Some("no channels could be read from dvb-channels.conf, line 1: BBC ONE Lon has no DELIVERY_SYSTEM".to_string()),
+        );
+    }
+
     // Tests need to be able to set specific values to CHANNELS_DATA rather than just
     // load the files. Although access to CHANNELS_DATA is controlled, there is an
     // assumption the value is that of reading the files. By default, tests are run
@@ -483,6 +1219,265 @@ mod tests {
         assert_eq!(get_channel_name_of_logical_channel_number(10), None);
     }
 
+    #[test]
+    fn mark_channels_encrypted() {
+        let test_lock = TEST_LOCK.lock().unwrap();
+        let data = create_two_entry_channel_data_vec();
+        {
+            let mut channels_data = CHANNELS_DATA.write().unwrap();
+            *channels_data = Some(data);
+        }
+        assert!(!is_encrypted("BBC ONE Lon"));
+        assert!(set_encrypted_for_service_id(4164, true, None));
+        assert!(!set_encrypted_for_service_id(4164, true, None));
+        assert!(!set_encrypted_for_service_id(3000, true, None));
+        assert!(is_encrypted("BBC ONE Lon"));
+        assert!(!is_encrypted("BBC TWO"));
+        assert!(set_encrypted_for_service_id(4164, false, None));
+        assert!(!is_encrypted("BBC ONE Lon"));
+    }
+
+    #[test]
+    fn mark_channels_radio() {
+        let test_lock = TEST_LOCK.lock().unwrap();
+        let data = process_channels(&parse_zap("BBC Radio 4:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:0:7234:7232\n").channels);
+        {
+            let mut channels_data = CHANNELS_DATA.write().unwrap();
+            *channels_data = Some(data);
+        }
+        assert!(is_radio("BBC Radio 4"));
+        assert!(!set_radio_for_service_id(7232, true, None));
+        assert!(set_radio_for_service_id(7232, false, None));
+        assert!(!is_radio("BBC Radio 4"));
+        assert!(!is_radio("BBC ONE Lon"));
+    }
+
+    #[test]
+    fn providers_are_as_the_sdt_says() {
+        let test_lock = TEST_LOCK.lock().unwrap();
+        let data = create_two_entry_channel_data_vec();
+        {
+            let mut channels_data = CHANNELS_DATA.write().unwrap();
+            *channels_data = Some(data);
+        }
+        assert_eq!(get_provider("BBC TWO"), None);
+        assert!(set_provider_for_service_id(4287, "BBC", None));
+        assert!(!set_provider_for_service_id(4287, "BBC", None));
+        assert!(!set_provider_for_service_id(4287, "", None));
+        assert_eq!(get_provider("BBC TWO").as_deref(), Some("BBC"));
+        assert_eq!(get_provider("BBC ONE Lon"), None);
+    }
+
+    #[test]
+    fn frequencies_are_in_mhz_whatever_the_channels_file_has() {
+        assert_eq!(frequency_text(490000000), "490 MHz");
+        assert_eq!(frequency_text(11493750), "11493.75 MHz");
+        assert_eq!(frequency_text(0), "");
+    }
+
+    #[test]
+    fn details_say_what_tells_copies_apart() {
+        let mut data = create_two_entry_channel_data_vec();
+        assert_eq!(details_text(&data[0]), "BBC ONE Lon\nDVBT, 490 MHz\nService id: 4164");
+        data[0].provider = Some("BBC".to_string());
+        data[0].service_type = Some(0x19);
+        assert_eq!(details_text(&data[0]), "BBC ONE Lon\nProvider: BBC\nService type: advanced codec HD digital television\nDVBT, 490 MHz\nService id: 4164");
+        let satellite = process_channels(&parse_zap("BBC One HD:10847:v:0:23000:5500:5502:6940\n").channels);
+        assert_eq!(details_text(&ChannelData{service_type: Some(0x80), ..satellite[0].clone()}), "BBC One HD\nService type: 0x80\nDVBS, 10847 MHz, vertical polarisation\nService id: 6940");
+    }
+
+    #[test]
+    fn the_signals_of_scanned_multiplexes_are_those_of_their_channels() {
+        let mut data = process_channels(&parse_channels(OVERLAPPING_TRANSMITTERS).channels);
+        let scanned = |frequency, signal| MultiplexSignal{scanned: Local.ymd(2020, 10, 4).and_hms(21, 0, 0), delivery_system: "DVBT".to_string(), frequency, polarization: None, signal};
+        add_multiplex_signals(&mut data, &[
+            scanned(data[2].frequency, TransponderSignal::Locked{strength: Some(Measurement::Relative(0.5)), snr: Some(Measurement::Decibels(25.1))}),
+            scanned(data[0].frequency, TransponderSignal::NoLock),
+        ]);
+        assert_eq!(data[2].signal_strength, Some(32768));
+        assert_eq!(data[0].signal_strength, None);
+        assert_eq!(data[0].scan_signal.as_deref(), Some("When scanned on 4 Oct 2020: no lock"));
+        assert!(details_text(&data[2]).ends_with("\nWhen scanned on 4 Oct 2020: signal 50%, SNR 25.1 dB"));
+        assert!(listed_copies(&data)[2]);
+    }
+
+    #[test]
+    fn details_are_of_the_listed_copy() {
+        let test_lock = TEST_LOCK.lock().unwrap();
+        let data = process_channels(&parse_channels(OVERLAPPING_TRANSMITTERS).channels);
+        {
+            let mut channels_data = CHANNELS_DATA.write().unwrap();
+            *channels_data = Some(data);
+        }
+        assert!(set_service_type_for_service_id(4164, 0x01));
+        assert!(!set_service_type_for_service_id(4164, 0x01));
+        assert!(prefer_copy(2));
+        assert_eq!(get_channel_details("BBC ONE Lon").unwrap(), "BBC ONE Lon\nService type: digital television\nDVBT, 618 MHz\nService id: 4164");
+        assert_eq!(get_channel_details("ITV"), None);
+    }
+
+    #[test]
+    fn channels_numbered_in_the_editor_keep_their_numbers() {
+        let test_lock = TEST_LOCK.lock().unwrap();
+        let data = create_two_entry_channel_data_vec();
+        {
+            let mut channels_data = CHANNELS_DATA.write().unwrap();
+            *channels_data = Some(data);
+        }
+        assert!(set_logical_channel_number_of("BBC TWO", 102));
+        assert!(!add_logical_channel_number_for_service_id(4287, 2, None));
+        assert_eq!(get_channel_name_of_logical_channel_number(102).unwrap(), "BBC TWO");
+        assert!(set_logical_channel_number_of("BBC TWO", 0));
+        assert!(add_logical_channel_number_for_service_id(4287, 2, None));
+        assert_eq!(get_channel_name_of_logical_channel_number(2).unwrap(), "BBC TWO");
+    }
+
+    #[test]
+    fn hide_channels() {
+        let test_lock = TEST_LOCK.lock().unwrap();
+        let data = create_two_entry_channel_data_vec();
+        {
+            let mut channels_data = CHANNELS_DATA.write().unwrap();
+            *channels_data = Some(data);
+        }
+        assert!(!is_hidden("BBC TWO"));
+        assert!(set_hidden("BBC TWO", true));
+        assert!(!set_hidden("BBC TWO", true));
+        assert!(is_hidden("BBC TWO"));
+        assert!(!is_hidden("BBC ONE Lon"));
+    }
+
+    #[test]
+    fn channels_of_further_files_are_added_unless_already_there() {
+        let terrestrial = parse_zap("BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164\n");
+        let satellite = parse_zap("BBC One HD:10847:v:0:23000:5500:5502:6940\nBBC ONE Lon:10773:h:0:22000:5000:5002:6301\n");
+        let satellite_path = Path::new("/srv/tv/dvb-s2-channels.conf").to_path_buf().into_boxed_path();
+        let (channels, files) = merge_channels(terrestrial, vec![(satellite_path.clone(), satellite)]);
+        assert_eq!(channels.channels.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(), vec!["BBC ONE Lon", "BBC One HD"]);
+        assert_eq!(channels.channels[0].tuning.service_id, 4164);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files.get("BBC One HD"), Some(&satellite_path));
+    }
+
+    /// BBC ONE Lon from Crystal Palace and from Guildford, and from Rowridge as BBC ONE
+    /// South, the same service by its network and service ids, and BBC TWO.
+    const OVERLAPPING_TRANSMITTERS: &str = "[BBC ONE Lon]
+	SERVICE_ID = 4164
+	NETWORK_ID = 9018
+	FREQUENCY = 490000000
+	DELIVERY_SYSTEM = DVBT
+[BBC TWO]
+	SERVICE_ID = 4287
+	NETWORK_ID = 9018
+	FREQUENCY = 490000000
+	DELIVERY_SYSTEM = DVBT
+[BBC ONE Lon]
+	SERVICE_ID = 4164
+	NETWORK_ID = 9018
+	FREQUENCY = 618000000
+	DELIVERY_SYSTEM = DVBT
+[BBC ONE South]
+	SERVICE_ID = 4164
+	NETWORK_ID = 9018
+	FREQUENCY = 650000000
+	DELIVERY_SYSTEM = DVBT
+";
+
+    #[test]
+    fn copies_are_the_same_service_or_failing_that_the_same_name() {
+        let data = process_channels(&parse_channels(OVERLAPPING_TRANSMITTERS).channels);
+        assert!(are_copies(&data[0], &data[2]));
+        assert!(are_copies(&data[0], &data[3]));
+        assert!(!are_copies(&data[0], &data[1]));
+        let zap = process_channels(&parse_zap("BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164
+BBC ONE Lon:618000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164
+BBC ONE South:650000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164
+").channels);
+        assert!(are_copies(&zap[0], &zap[1]));
+        assert!(!are_copies(&zap[0], &zap[2]));
+    }
+
+    #[test]
+    fn the_listed_copy_is_chosen_then_the_strongest_then_the_first() {
+        let mut data = process_channels(&parse_channels(OVERLAPPING_TRANSMITTERS).channels);
+        assert_eq!(listed_copies(&data), vec![true, true, false, false]);
+        data[2].signal_strength = Some(40000);
+        data[3].signal_strength = Some(30000);
+        assert_eq!(listed_copies(&data), vec![false, true, true, false]);
+        data[3].preferred = true;
+        assert_eq!(listed_copies(&data), vec![false, true, false, true]);
+    }
+
+    #[test]
+    fn listed_copies_dvbbasebin_would_not_tune_are_written_to_a_file_of_their_own() {
+        let channels = parse_channels(OVERLAPPING_TRANSMITTERS).channels;
+        let mut data = process_channels(&channels);
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("me-tv").join("preferred-copies.conf");
+        assert!(write_preferred_copies_file(&path, &channels, &data).is_empty());
+        assert!(!path.exists());
+        data[2].preferred = true;
+        assert_eq!(write_preferred_copies_file(&path, &channels, &data).into_iter().collect::<Vec<_>>(), vec!["BBC ONE Lon"]);
+        assert_eq!(read_channels(&path).unwrap().channels, vec![channels[2].clone()]);
+        data[2].preferred = false;
+        data[3].preferred = true;
+        assert!(write_preferred_copies_file(&path, &channels, &data).is_empty());
+        assert_eq!(read_channel_names(&path), None);
+    }
+
+    #[test]
+    fn channel_numbers_of_copies_give_the_listed_copy() {
+        let test_lock = TEST_LOCK.lock().unwrap();
+        let data = process_channels(&parse_channels(OVERLAPPING_TRANSMITTERS).channels);
+        {
+            let mut channels_data = CHANNELS_DATA.write().unwrap();
+            *channels_data = Some(data);
+        }
+        assert!(add_logical_channel_number_for_service_id(4164, 1, None));
+        assert_eq!(get_channel_name_of_logical_channel_number(1).unwrap(), "BBC ONE Lon");
+        assert!(prefer_copy(3));
+        assert!(!prefer_copy(3));
+        assert_eq!(get_channel_name_of_logical_channel_number(1).unwrap(), "BBC ONE South");
+        assert!(!prefer_copy(4));
+    }
+
+    #[test]
+    fn cached_data_is_that_of_the_same_service_then_the_same_name() {
+        let cache = process_channels(&parse_channels(OVERLAPPING_TRANSMITTERS).channels);
+        let mut data = create_two_entry_channel_data_vec();
+        assert_eq!(find_match(&data[0], &cache), Some(&cache[0]));
+        data[0].frequency = 618000000;
+        assert_eq!(find_match(&data[0], &cache), Some(&cache[2]));
+        data[0].frequency = 506000000;
+        assert_eq!(find_match(&data[0], &cache), Some(&cache[0]));
+        // Another multiplex of the network with a service of the service id.
+        data[0].transport_stream_id = 8199;
+        let mut moved = cache.clone();
+        moved[0].transport_stream_id = 4164;
+        moved[2].transport_stream_id = 4164;
+        moved[3].transport_stream_id = 4164;
+        assert_eq!(find_match(&data[0], &moved), Some(&moved[0]));
+        data[0].name = "BBC ONE HD".to_string();
+        assert_eq!(find_match(&data[0], &moved), None);
+        data[1].service_id = 4288;
+        assert_eq!(find_match(&data[1], &cache), Some(&cache[1]));
+    }
+
+    #[test]
+    fn channels_renamed_added_and_lost_by_a_rescan_are_found() {
+        let old = process_channels(&parse_channels(OVERLAPPING_TRANSMITTERS).channels);
+        let mut new = create_two_entry_channel_data_vec();
+        assert_eq!(channel_changes(&old, &old), ChannelChanges::default());
+        assert!(channel_changes(&old, &old).is_empty());
+        new[0].name = "BBC One London".to_string();
+        new.push(ChannelData{name: "ITV".to_string(), service_id: 8261, ..new[1].clone()});
+        assert_eq!(channel_changes(&old, &new), ChannelChanges{
+            renamed: vec![("BBC ONE Lon".to_string(), "BBC One London".to_string())],
+            added: vec!["ITV".to_string()],
+            lost: vec!["BBC ONE South".to_string()],
+        });
+    }
+
     #[test]
     fn write_and_read_channels_data_cache() {
         let test_lock = TEST_LOCK.lock().unwrap();
@@ -506,15 +1501,41 @@ mod tests {
         let mut buffer = [0u8; 4096];
         match file.read(&mut buffer) {
             Ok(count) => {
-                assert_eq!(count, 133);
+                assert_eq!(count, 653);
                 let result = String::from_utf8_lossy(&buffer[..count]).to_string();
                 assert_eq!(result, "---
 - name: BBC ONE Lon
   service_id: 4164
   logical_channel_number: 1
+  encrypted: false
+  radio: false
+  delivery_system: DVBT
+  numbered_by_user: false
+  hidden: false
+  frequency: 490000000
+  network_id: 9018
+  transport_stream_id: 4164
+  signal_strength: ~
+  preferred: false
+  provider: ~
+  polarization: ~
+  service_type: ~
 - name: BBC TWO
   service_id: 4287
-  logical_channel_number: 2");
+  logical_channel_number: 2
+  encrypted: false
+  radio: false
+  delivery_system: DVBT
+  numbered_by_user: false
+  hidden: false
+  frequency: 490000000
+  network_id: 9018
+  transport_stream_id: 4164
+  signal_strength: ~
+  preferred: false
+  provider: ~
+  polarization: ~
+  service_type: ~");
             },
             Err(e) => assert!(false, "Failed to read file {:?} – {}", file_path, e),
         }
@@ -0,0 +1,1837 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Access to the GStreamer dvbsrc plugin channels file.
+//!
+//! GStreamer uses the XDG directory structure with, currently, gstreamer-1.0 as its
+//! name. The dvbsrc plugin assumes the name dvb-channels.conf, unless the
+//! GST_DVB_CHANNELS_CONF environment variable gives another. The DVBv5 file format
+//! is INI style: a sequence of blocks, one for each channel, starting with a channel
+//! name surrounded by brackets and then a sequence of binding of keys to values each
+//! one indented.
+//!
+//! dvbbasebin also reads the older zap format, of the tzap, czap, szap and azap
+//! tools: a line for each channel, the fields separated by colons, how many there are
+//! saying which sort of delivery system it is for. Which format a file is in is decided
+//! by what is in it, whatever it is called.
+//!
+//! Channels can also be imported from the channels.conf file of VDR, which neither
+//! dvbbasebin nor Me TV read as a channels file, and from what the w_scan2 and
+//! dvbv5-scan scanners write, which is in one of these three formats.
+
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use percent_encoding;
+use xdg;
+
+use crate::frontend_info::DeliverySystem;
+
+/// The environment variable dvbbasebin takes the path of the channels file from, if it
+/// is set.
+pub const CHANNELS_FILE_VARIABLE: &str = "GST_DVB_CHANNELS_CONF";
+
+/// Return a `Box<Path>` to the GStreamer dvbsrc plugin channels file: the one named by
+/// GST_DVB_CHANNELS_CONF if it is set, as for dvbbasebin, otherwise the one in the XDG
+/// directory structure.
+pub fn channels_file_path() -> Box<Path> {
+    channels_file_path_from(env::var_os(CHANNELS_FILE_VARIABLE))
+}
+
+/// https://url.spec.whatwg.org/#fragment-percent-encode-set
+const FRAGMENT: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
+/// https://url.spec.whatwg.org/#path-percent-encode-set, and % so that a channel name
+/// with one in is not taken to be already encoded.
+const PATH: &percent_encoding::AsciiSet = &FRAGMENT.add(b'#').add(b'?').add(b'{').add(b'}').add(b'%');
+
+/// The URI dvbbasebin plays the channel `channel_name` from.
+pub fn dvb_uri(channel_name: &str) -> String {
+    "dvb://".to_owned() + &percent_encoding::utf8_percent_encode(channel_name, PATH).to_string()
+}
+
+/// Return a `Box<Path>` to the channels file of the copies of services that Me TV tunes
+/// rather than the first of the name in the channels file, using the XDG directory
+/// structure. Me TV writes it, me-tv-record reads it.
+pub fn preferred_copies_file_path() -> Box<Path> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_cache_home();
+    path_buf.push("preferred-copies.conf");
+    path_buf.into_boxed_path()
+}
+
+/// The channels file given the value of GST_DVB_CHANNELS_CONF. An empty value is as if
+/// it were not set.
+fn channels_file_path_from(variable: Option<OsString>) -> Box<Path> {
+    match variable {
+        Some(path) if !path.is_empty() => PathBuf::from(path).into_boxed_path(),
+        _ => {
+            let xdg_dirs = xdg::BaseDirectories::with_prefix("gstreamer-1.0").expect("Cannot set XDG prefix.");
+            let mut path_buf = xdg_dirs.get_config_home();
+            path_buf.push("dvb-channels.conf");
+            path_buf.into_boxed_path()
+        },
+    }
+}
+
+/// The formats of channels file that dvbbasebin reads.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChannelsFileFormat {
+    DvbV5,
+    Zap,
+}
+
+impl ChannelsFileFormat {
+    /// The format of a channels file with `contents`: DVBv5 if the first line that is
+    /// not blank or a comment starts a block, zap otherwise.
+    pub fn of(contents: &str) -> ChannelsFileFormat {
+        match contents.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#')) {
+            Some(line) if line.starts_with('[') => ChannelsFileFormat::DvbV5,
+            _ => ChannelsFileFormat::Zap,
+        }
+    }
+}
+
+/// A block of a DVBv5 format channels file: the name of the channel, the line the name
+/// is on, and the bindings of keys to values in file order.
+pub(crate) struct Section {
+    pub(crate) name: String,
+    pub(crate) line: usize,
+    pub(crate) properties: Vec<(String, String)>,
+}
+
+impl Section {
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.properties.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+}
+
+/// The blocks of a DVBv5 format channels file, and what was wrong with the lines that
+/// are not part of one.
+pub(crate) fn dvbv5_sections(contents: &str) -> (Vec<Section>, Vec<ParseWarning>) {
+    let mut sections = Vec::<Section>::new();
+    let mut warnings = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            sections.push(Section{name: name.trim().to_string(), line: index + 1, properties: Vec::new()});
+            continue;
+        }
+        let message = match (line.find('='), sections.last_mut()) {
+            (Some(position), Some(section)) => {
+                section.properties.push((line[..position].trim().to_string(), line[position + 1..].trim().to_string()));
+                continue;
+            },
+            (Some(_), None) => format!("'{}' is not in a channel", line),
+            (None, _) => format!("'{}' is neither a channel name in brackets nor a KEY = VALUE binding", line),
+        };
+        warnings.push(ParseWarning{line: index + 1, message});
+    }
+    (sections, warnings)
+}
+
+/// The keys of a DVBv5 block that are fields of `Channel` rather than in its parameters.
+const CHANNEL_KEYS: [&str; 7] = ["SERVICE_ID", "DELIVERY_SYSTEM", "FREQUENCY", "BANDWIDTH_HZ", "MODULATION", "VIDEO_PID", "AUDIO_PID"];
+
+/// The channel of a block of a DVBv5 format channels file.
+fn dvbv5_channel(section: &Section) -> Result<Channel, String> {
+    let required = |key: &str| section.get(key).ok_or_else(|| format!("{} has no {}", section.name, key));
+    let number = |key: &str, value: &str| value.parse::<u32>().map_err(|_| format!("'{}' is not a {}", value, key));
+    let pid = |value: &str| value.parse::<u16>().map_err(|_| format!("'{}' is not a PID", value));
+    if section.name.is_empty() {
+        return Err("the channel has no name".to_string());
+    }
+    let service_id = required("SERVICE_ID")?;
+    let service_id = service_id.parse::<u16>().map_err(|_| format!("'{}' is not a service id", service_id))?;
+    let video_pid = match section.get("VIDEO_PID").map(pid).transpose()? {
+        Some(0) | None => None,
+        pid => pid,
+    };
+    Ok(Channel {
+        name: section.name.clone(),
+        tuning: TuningParameters {
+            delivery_system: required("DELIVERY_SYSTEM")?.to_string(),
+            frequency: number("FREQUENCY", required("FREQUENCY")?)?,
+            bandwidth_hz: section.get("BANDWIDTH_HZ").map(|value| number("BANDWIDTH_HZ", value)).transpose()?,
+            modulation: section.get("MODULATION").map(str::to_string),
+            service_id,
+        },
+        video_pid,
+        audio_pids: section.get("AUDIO_PID").map_or(Ok(Vec::new()), |pids| pids.split_whitespace().map(pid).collect())?,
+        parameters: section.properties.iter()
+            .filter(|(key, _)| !CHANNEL_KEYS.contains(&key.as_str()))
+            .cloned()
+            .collect(),
+    })
+}
+
+/// Read the channels of a DVBv5 format channels file. A block without the service id,
+/// delivery system, and frequency of the channel is left out.
+pub fn parse_dvbv5(contents: &str) -> Channels {
+    let (sections, warnings) = dvbv5_sections(contents);
+    let mut channels = Channels{channels: Vec::new(), warnings};
+    for section in &sections {
+        match dvbv5_channel(section) {
+            Ok(channel) => channels.channels.push(channel),
+            Err(message) => channels.warnings.push(ParseWarning{line: section.line, message}),
+        }
+    }
+    channels.warnings.sort_by_key(|warning| warning.line);
+    channels
+}
+
+/// Read the channels of a channels file in either format.
+pub fn parse_channels(contents: &str) -> Channels {
+    match ChannelsFileFormat::of(contents) {
+        ChannelsFileFormat::DvbV5 => parse_dvbv5(contents),
+        ChannelsFileFormat::Zap => parse_zap(contents),
+    }
+}
+
+/// Read the channels of the channels file at `path`, in either format.
+pub fn read_channels(path: &Path) -> io::Result<Channels> {
+    Ok(parse_channels(&fs::read_to_string(path)?))
+}
+
+/// Return the names of the channels in the channels file at `path`, in file order, or
+/// `None` if the file cannot be read. In a DVBv5 format file, a block that is not a
+/// channel that can be tuned to still has its name returned.
+pub fn read_channel_names(path: &Path) -> Option<Vec<String>> {
+    let contents = fs::read_to_string(path).ok()?;
+    Some(match ChannelsFileFormat::of(&contents) {
+        ChannelsFileFormat::DvbV5 => dvbv5_sections(&contents).0.into_iter().map(|section| section.name).collect(),
+        ChannelsFileFormat::Zap => parse_zap(&contents).channels.into_iter().map(|channel| channel.name).collect(),
+    })
+}
+
+/// The value of `key` in the DVBv5 block, or the same from the zap line, of the named
+/// channel in the channels file at `path`.
+fn read_channel_value(path: &Path, channel: &str, key: &str) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    match ChannelsFileFormat::of(&contents) {
+        ChannelsFileFormat::DvbV5 => dvbv5_sections(&contents).0.iter()
+            .find(|section| section.name == channel)?
+            .get(key)
+            .map(str::to_string),
+        ChannelsFileFormat::Zap => parse_zap(&contents).channels.into_iter()
+            .find(|c| c.name == channel)
+            .and_then(|c| match key {
+                "SERVICE_ID" => Some(c.tuning.service_id.to_string()),
+                "DELIVERY_SYSTEM" => Some(c.tuning.delivery_system),
+                _ => None,
+            }),
+    }
+}
+
+/// Return the service id of the named channel in the channels file at `path`, or
+/// `None` if the file cannot be read or the channel is not in it.
+pub fn read_service_id(path: &Path, channel: &str) -> Option<u16> {
+    read_channel_value(path, channel, "SERVICE_ID")?.parse::<u16>().ok()
+}
+
+/// Return the delivery system of the named channel in the channels file at `path`, or
+/// `None` if the file cannot be read, the channel is not in it, or the delivery system
+/// is not given or not known.
+pub fn read_delivery_system(path: &Path, channel: &str) -> Option<DeliverySystem> {
+    read_channel_value(path, channel, "DELIVERY_SYSTEM")?.parse::<DeliverySystem>().ok()
+}
+
+/// Return whether the named channel in the channels file at `path` is a radio channel,
+/// or `None` if the file cannot be read or the channel is not in it.
+pub fn read_is_radio(path: &Path, channel: &str) -> Option<bool> {
+    read_channels(path).ok()?.channels.iter().find(|c| c.name == channel).map(Channel::is_radio)
+}
+
+/// The DVBv5 names of the delivery systems that can be given explicitly.
+pub const DELIVERY_SYSTEMS: [&str; 8] = ["DVBT", "DVBT2", "DVBC/ANNEX_A", "DVBC/ANNEX_B", "DVBS", "DVBS2", "ATSC", "ISDBT"];
+
+/// The DVBv5 names of the modulations that can be given explicitly.
+pub const MODULATIONS: [&str; 10] = ["QPSK", "PSK/8", "QAM/16", "QAM/32", "QAM/64", "QAM/128", "QAM/256", "QAM/AUTO", "VSB/8", "VSB/16"];
+
+/// The parameters needed to tune to a service without using the channels file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TuningParameters {
+    pub delivery_system: String,  // A DVBv5 name, one of DELIVERY_SYSTEMS unless read from a channels file.
+    pub frequency: u32,  // Hz, or kHz for satellite delivery systems as in the channels file.
+    pub bandwidth_hz: Option<u32>,
+    pub modulation: Option<String>,  // A DVBv5 name, one of MODULATIONS.
+    pub service_id: u16,
+}
+
+impl TuningParameters {
+    /// The nick of the dvbbasebin delsys property value for the delivery system.
+    pub fn delsys_nick(&self) -> String {
+        self.delivery_system.replace('/', "_")
+    }
+
+    /// The nick of the dvbbasebin modulation property value for the modulation.
+    pub fn modulation_nick(&self) -> Option<String> {
+        self.modulation.as_ref().map(|m| match m.as_str() {
+            "PSK/8" => "8PSK".to_string(),
+            "QAM/AUTO" => "AUTO".to_string(),
+            "VSB/8" => "8VSB".to_string(),
+            "VSB/16" => "16VSB".to_string(),
+            other => other.replace('/', " "),
+        })
+    }
+
+    /// A one line human readable description of the parameters.
+    pub fn description(&self) -> String {
+        let mut description = format!("{} {}", self.delivery_system, self.frequency);
+        if let Some(bandwidth_hz) = self.bandwidth_hz {
+            description.push_str(&format!(" bandwidth {}", bandwidth_hz));
+        }
+        if let Some(modulation) = &self.modulation {
+            description.push_str(&format!(" {}", modulation));
+        }
+        description.push_str(&format!(" service {}", self.service_id));
+        description
+    }
+
+    /// The entry for the channels file that tunes to the same service.
+    pub fn channels_file_entry(&self, name: &str) -> String {
+        let mut entry = format!("[{}]\n", name);
+        entry.push_str(&format!("\tSERVICE_ID = {}\n", self.service_id));
+        entry.push_str(&format!("\tDELIVERY_SYSTEM = {}\n", self.delivery_system));
+        entry.push_str(&format!("\tFREQUENCY = {}\n", self.frequency));
+        if let Some(bandwidth_hz) = self.bandwidth_hz {
+            entry.push_str(&format!("\tBANDWIDTH_HZ = {}\n", bandwidth_hz));
+        }
+        if let Some(modulation) = &self.modulation {
+            entry.push_str(&format!("\tMODULATION = {}\n", modulation));
+        }
+        entry
+    }
+}
+
+/// A channel of a channels file, whatever the format of the file, the parameters beyond
+/// those needed to tune being by their DVBv5 names and values.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    pub tuning: TuningParameters,
+    pub video_pid: Option<u16>,
+    pub audio_pids: Vec<u16>,
+    pub parameters: BTreeMap<String, String>,  // E.g. INVERSION, SYMBOL_RATE, POLARIZATION.
+}
+
+/// What identifies a service whatever the name of its channel: the original network id
+/// and transport stream id of its multiplex, and its service id. Channels files written
+/// by scanners that do not record the network and transport stream ids only have the
+/// service id.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ServiceIdentity {
+    pub original_network_id: Option<u16>,
+    pub transport_stream_id: Option<u16>,
+    pub service_id: u16,
+}
+
+impl ServiceIdentity {
+    /// Whether the identity has all three ids, and so is unique.
+    pub fn is_complete(&self) -> bool {
+        self.original_network_id.is_some() && self.transport_stream_id.is_some()
+    }
+
+    /// Whether the two identities may be of the same service: the same service id,
+    /// and no id known for both that differs.
+    pub fn may_be(&self, other: &ServiceIdentity) -> bool {
+        fn agree(a: Option<u16>, b: Option<u16>) -> bool { a.is_none() || b.is_none() || a == b }
+        self.service_id == other.service_id
+            && agree(self.original_network_id, other.original_network_id)
+            && agree(self.transport_stream_id, other.transport_stream_id)
+    }
+}
+
+/// A line of a channels file that could not be read, so the channel is left out.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseWarning {
+    pub line: usize,  // From 1.
+    pub message: String,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The channels read from a channels file, in file order, and why any lines were not.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Channels {
+    pub channels: Vec<Channel>,
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// The DVBv5 value of a zap format parameter, the zap value without its prefix and with
+/// code rates and guard intervals as fractions. None if the prefix is not there.
+fn from_zap_value(value: &str, prefix: &str) -> Option<String> {
+    let value = value.strip_prefix(prefix)?.strip_prefix('_')?;
+    let parts = value.split('_').collect::<Vec<_>>();
+    if parts.len() == 2 && parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())) {
+        Some(parts.join("/"))
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// The zap format value of a DVBv5 parameter value.
+fn to_zap_value(value: &str, prefix: &str) -> String {
+    format!("{}_{}", prefix, value.replace('/', "_"))
+}
+
+/// The DVBv5 name of a zap format modulation, if it is one Me TV knows.
+fn from_zap_modulation(modulation: &str) -> Option<String> {
+    let modulation = match modulation {
+        "8VSB" => "VSB/8".to_string(),
+        "16VSB" => "VSB/16".to_string(),
+        "8PSK" => "PSK/8".to_string(),
+        other => other.replace('_', "/"),
+    };
+    if MODULATIONS.contains(&modulation.as_str()) { Some(modulation) } else { None }
+}
+
+/// The zap format name of a DVBv5 modulation.
+fn to_zap_modulation(modulation: &str) -> String {
+    match modulation {
+        "VSB/8" => "8VSB".to_string(),
+        "VSB/16" => "16VSB".to_string(),
+        "PSK/8" => "8PSK".to_string(),
+        other => other.replace('/', "_"),
+    }
+}
+
+/// The bandwidth in Hz of a zap format bandwidth, None for BANDWIDTH_AUTO.
+fn from_zap_bandwidth(bandwidth: &str) -> Result<Option<u32>, String> {
+    let megahertz = bandwidth.strip_prefix("BANDWIDTH_").and_then(|b| b.strip_suffix("_MHZ"));
+    match (bandwidth, megahertz) {
+        ("BANDWIDTH_AUTO", _) => Ok(None),
+        (_, Some("1_712")) => Ok(Some(1_712_000)),
+        (_, Some(megahertz)) => megahertz.parse::<u32>().map(|m| Some(m * 1_000_000)).map_err(|_| format!("'{}' is not a bandwidth", bandwidth)),
+        _ => Err(format!("'{}' is not a bandwidth", bandwidth)),
+    }
+}
+
+/// The zap format name of a bandwidth in Hz.
+fn to_zap_bandwidth(bandwidth_hz: Option<u32>) -> String {
+    match bandwidth_hz {
+        None => "BANDWIDTH_AUTO".to_string(),
+        Some(1_712_000) => "BANDWIDTH_1_712_MHZ".to_string(),
+        Some(hz) => format!("BANDWIDTH_{}_MHZ", hz / 1_000_000),
+    }
+}
+
+/// Read a number field of a zap format line.
+fn zap_number<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value.trim().parse::<T>().map_err(|_| format!("'{}' is not a {}", value, what))
+}
+
+/// Read a zap format parameter given by a prefixed name into `parameters`.
+fn zap_parameter(parameters: &mut BTreeMap<String, String>, name: &str, value: &str, prefix: &str) -> Result<(), String> {
+    let value = from_zap_value(value, prefix).ok_or_else(|| format!("'{}' is not a {}", value, prefix))?;
+    parameters.insert(name.to_string(), value);
+    Ok(())
+}
+
+/// The PIDs of an audio PID field, which may list several, each perhaps followed by
+/// a language.
+fn zap_audio_pids(value: &str) -> Result<Vec<u16>, String> {
+    value.split(|c| c == ',' || c == ';')
+        .map(|pid| pid.split('=').next().unwrap_or(pid))
+        .filter(|pid| !pid.is_empty())
+        .map(|pid| zap_number::<u16>(pid, "PID"))
+        .filter(|pid| pid != &Ok(0))
+        .collect()
+}
+
+/// Whether a terrestrial channel uses anything only DVB-T2 has. The zap format does not
+/// say, but DVB-T goes no further than QAM 64 and the 8K transmission mode.
+fn is_dvbt2(modulation: Option<&str>, parameters: &BTreeMap<String, String>, bandwidth_hz: Option<u32>) -> bool {
+    modulation == Some("QAM/256")
+        || parameters.get("TRANSMISSION_MODE").map_or(false, |mode| ["1K", "16K", "32K"].contains(&mode.as_str()))
+        || parameters.get("GUARD_INTERVAL").map_or(false, |guard| ["1/128", "19/128", "19/256"].contains(&guard.as_str()))
+        || bandwidth_hz == Some(1_712_000)
+}
+
+/// Read the channel of a line of a zap format channels file.
+///
+/// The lines for each sort of delivery system are:
+///
+/// ```text
+/// terrestrial: name:frequency:inversion:bandwidth:code rate HP:code rate LP:modulation:transmission mode:guard interval:hierarchy:video PID:audio PID:service id
+/// cable:       name:frequency:inversion:symbol rate:FEC:modulation:video PID:audio PID:service id
+/// satellite:   name:frequency MHz:polarisation:satellite number:symbol rate kBd:video PID:audio PID:service id
+/// ATSC:        name:frequency:modulation:video PID:audio PID:service id
+/// ```
+///
+/// Satellite channels are taken to be DVB-S, the zap format cannot say DVB-S2.
+fn parse_zap_line(line: &str) -> Result<Channel, String> {
+    let fields = line.split(':').collect::<Vec<_>>();
+    let name = fields[0].trim().to_string();
+    if name.is_empty() {
+        return Err("the channel has no name".to_string());
+    }
+    let mut parameters = BTreeMap::new();
+    let (delivery_system, frequency, bandwidth_hz, modulation, pids) = match fields.len() {
+        13 => {
+            let bandwidth_hz = from_zap_bandwidth(fields[3])?;
+            zap_parameter(&mut parameters, "INVERSION", fields[2], "INVERSION")?;
+            zap_parameter(&mut parameters, "CODE_RATE_HP", fields[4], "FEC")?;
+            zap_parameter(&mut parameters, "CODE_RATE_LP", fields[5], "FEC")?;
+            let modulation = from_zap_modulation(fields[6]).ok_or_else(|| format!("'{}' is not a modulation", fields[6]))?;
+            zap_parameter(&mut parameters, "TRANSMISSION_MODE", fields[7], "TRANSMISSION_MODE")?;
+            zap_parameter(&mut parameters, "GUARD_INTERVAL", fields[8], "GUARD_INTERVAL")?;
+            zap_parameter(&mut parameters, "HIERARCHY", fields[9], "HIERARCHY")?;
+            let delivery_system = if is_dvbt2(Some(modulation.as_str()), &parameters, bandwidth_hz) { "DVBT2" } else { "DVBT" };
+            (delivery_system, zap_number::<u32>(fields[1], "frequency")?, bandwidth_hz, Some(modulation), &fields[10..])
+        },
+        9 => {
+            zap_parameter(&mut parameters, "INVERSION", fields[2], "INVERSION")?;
+            parameters.insert("SYMBOL_RATE".to_string(), zap_number::<u32>(fields[3], "symbol rate")?.to_string());
+            zap_parameter(&mut parameters, "INNER_FEC", fields[4], "FEC")?;
+            let modulation = from_zap_modulation(fields[5]).ok_or_else(|| format!("'{}' is not a modulation", fields[5]))?;
+            ("DVBC/ANNEX_A", zap_number::<u32>(fields[1], "frequency")?, None, Some(modulation), &fields[6..])
+        },
+        8 => {
+            let polarization = match fields[2].trim().to_ascii_lowercase().as_str() {
+                "h" => "HORIZONTAL",
+                "v" => "VERTICAL",
+                "l" => "LEFT",
+                "r" => "RIGHT",
+                _ => return Err(format!("'{}' is not a polarisation", fields[2])),
+            };
+            parameters.insert("POLARIZATION".to_string(), polarization.to_string());
+            parameters.insert("SAT_NUMBER".to_string(), zap_number::<u8>(fields[3], "satellite number")?.to_string());
+            let symbol_rate = zap_number::<u32>(fields[4], "symbol rate")?.checked_mul(1000).ok_or("the symbol rate is too big")?;
+            parameters.insert("SYMBOL_RATE".to_string(), symbol_rate.to_string());
+            let frequency = zap_number::<u32>(fields[1], "frequency")?.checked_mul(1000).ok_or("the frequency is too big")?;
+            ("DVBS", frequency, None, None, &fields[5..])
+        },
+        6 => {
+            let modulation = from_zap_modulation(fields[2]).ok_or_else(|| format!("'{}' is not a modulation", fields[2]))?;
+            ("ATSC", zap_number::<u32>(fields[1], "frequency")?, None, Some(modulation), &fields[3..])
+        },
+        n => return Err(format!("{} fields is not a terrestrial (13), cable (9), satellite (8) or ATSC (6) channel", n)),
+    };
+    let video_pid = match zap_number::<u16>(pids[0], "video PID")? {
+        0 => None,
+        pid => Some(pid),
+    };
+    Ok(Channel {
+        name,
+        tuning: TuningParameters {
+            delivery_system: delivery_system.to_string(),
+            frequency,
+            bandwidth_hz,
+            modulation,
+            service_id: zap_number::<u16>(pids[2], "service id")?,
+        },
+        video_pid,
+        audio_pids: zap_audio_pids(pids[1])?,
+        parameters,
+    })
+}
+
+/// Read the channels of a zap format channels file. Blank lines and comments, lines
+/// starting with #, are skipped.
+pub fn parse_zap(contents: &str) -> Channels {
+    let mut channels = Channels::default();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        match parse_zap_line(line) {
+            Ok(channel) => channels.channels.push(channel),
+            Err(message) => channels.warnings.push(ParseWarning{line: index + 1, message}),
+        }
+    }
+    channels
+}
+
+/// The parameters of a VDR channels.conf line: letters each followed by a number, but
+/// for the polarisations which are letters alone.
+fn vdr_parameters(value: &str) -> Result<BTreeMap<char, Option<u32>>, String> {
+    let mut parameters = BTreeMap::new();
+    let mut characters = value.trim().chars().peekable();
+    while let Some(letter) = characters.next() {
+        if !letter.is_ascii_alphabetic() {
+            return Err(format!("'{}' is not a parameters field", value));
+        }
+        let mut digits = String::new();
+        while let Some(&digit) = characters.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(digit);
+            characters.next();
+        }
+        let number = if digits.is_empty() { None } else { Some(zap_number::<u32>(&digits, "parameter value")?) };
+        parameters.insert(letter.to_ascii_uppercase(), number);
+    }
+    Ok(parameters)
+}
+
+/// Read a VDR parameter given by a number into `parameters` by its DVBv5 name and value.
+/// Nothing is read if the parameter is not given.
+fn vdr_parameter(parameters: &mut BTreeMap<String, String>, vdr: &BTreeMap<char, Option<u32>>, letter: char, name: &str, values: &[(u32, &str)]) -> Result<(), String> {
+    if let Some(number) = vdr.get(&letter) {
+        let value = values.iter().find(|(n, _)| Some(*n) == *number).ok_or_else(|| format!("{}{} is not a {}", letter, number.map_or(String::new(), |n| n.to_string()), name))?;
+        parameters.insert(name.to_string(), value.1.to_string());
+    }
+    Ok(())
+}
+
+const VDR_CODE_RATES: [(u32, &str); 12] = [(0, "NONE"), (12, "1/2"), (23, "2/3"), (34, "3/4"), (35, "3/5"), (45, "4/5"), (56, "5/6"), (67, "6/7"), (78, "7/8"), (89, "8/9"), (910, "9/10"), (999, "AUTO")];
+const VDR_GUARD_INTERVALS: [(u32, &str); 8] = [(4, "1/4"), (8, "1/8"), (16, "1/16"), (32, "1/32"), (128, "1/128"), (19128, "19/128"), (19256, "19/256"), (999, "AUTO")];
+const VDR_HIERARCHIES: [(u32, &str); 5] = [(0, "NONE"), (1, "1"), (2, "2"), (4, "4"), (999, "AUTO")];
+const VDR_INVERSIONS: [(u32, &str); 3] = [(0, "OFF"), (1, "ON"), (999, "AUTO")];
+const VDR_MODULATIONS: [(u32, &str); 10] = [(2, "QPSK"), (5, "PSK/8"), (10, "VSB/8"), (11, "VSB/16"), (16, "QAM/16"), (32, "QAM/32"), (64, "QAM/64"), (128, "QAM/128"), (256, "QAM/256"), (998, "QAM/AUTO")];
+const VDR_ROLLOFFS: [(u32, &str); 4] = [(0, "AUTO"), (20, "20"), (25, "25"), (35, "35")];
+const VDR_TRANSMISSION_MODES: [(u32, &str); 7] = [(1, "1K"), (2, "2K"), (4, "4K"), (8, "8K"), (16, "16K"), (32, "32K"), (999, "AUTO")];
+
+/// The frequency of a VDR channels.conf line, which may be in MHz, kHz, or Hz, in kHz
+/// for a satellite channel and Hz otherwise, as for the channels file.
+fn vdr_frequency(value: &str) -> Result<u32, String> {
+    let mut frequency = zap_number::<u32>(value, "frequency")?;
+    if frequency == 0 {
+        return Err("0 is not a frequency".to_string());
+    }
+    while frequency < 1_000_000 {
+        frequency *= 1000;
+    }
+    Ok(frequency)
+}
+
+/// Read the channel of a line of a VDR channels.conf file.
+///
+/// A line is:
+///
+/// ```text
+/// name,short name;provider:frequency:parameters:source:symbol rate:video PID:audio PIDs:teletext PID:conditional access:service id:network id:transport stream id:radio id
+/// ```
+///
+/// The source is S and the orbital position (S19.2E say) for a satellite channel, C
+/// for cable, T for terrestrial, and A for ATSC: with the S parameter, 0 for the first
+/// generation or 1 for the second, it gives the delivery system. The polarisation of a
+/// satellite channel is an H, V, L, or R parameter. The orbital position is not kept,
+/// which satellite dish a channel is received from being a matter of SAT_NUMBER.
+fn parse_vdr_line(line: &str) -> Result<Channel, String> {
+    let fields = line.split(':').collect::<Vec<_>>();
+    if fields.len() < 10 {
+        return Err(format!("{} fields is too few for a VDR channel, there must be at least 10", fields.len()));
+    }
+    // VDR writes colons in names as bars.
+    let name = fields[0].split(';').next().unwrap_or("").split(',').next().unwrap_or("").trim().replace('|', ":");
+    if name.is_empty() {
+        return Err("the channel has no name".to_string());
+    }
+    let vdr = vdr_parameters(fields[2])?;
+    let second_generation = match vdr.get(&'S') {
+        None | Some(Some(0)) => false,
+        Some(Some(1)) => true,
+        Some(_) => return Err(format!("'{}' does not give a delivery system", fields[2])),
+    };
+    let mut parameters = BTreeMap::new();
+    vdr_parameter(&mut parameters, &vdr, 'I', "INVERSION", &VDR_INVERSIONS)?;
+    let modulation = match vdr.get(&'M') {
+        Some(Some(999)) | None => None,
+        Some(number) => Some(VDR_MODULATIONS.iter().find(|(n, _)| Some(*n) == *number).ok_or_else(|| format!("M{} is not a modulation", number.map_or(String::new(), |n| n.to_string())))?.1.to_string()),
+    };
+    if let Some(Some(stream_id)) = vdr.get(&'P') {
+        parameters.insert("STREAM_ID".to_string(), stream_id.to_string());
+    }
+    let symbol_rate = || -> Result<String, String> {
+        Ok(zap_number::<u32>(fields[4], "symbol rate")?.checked_mul(1000).ok_or("the symbol rate is too big")?.to_string())
+    };
+    let source = fields[3].trim();
+    let mut bandwidth_hz = None;
+    let delivery_system = match source.chars().next() {
+        Some('S') => {
+            let position = &source[1..];
+            let degrees = position.strip_suffix('E').or_else(|| position.strip_suffix('W'));
+            if degrees.map_or(true, |degrees| degrees.parse::<f32>().is_err()) {
+                return Err(format!("'{}' is not a satellite source", source));
+            }
+            let polarization = match ['H', 'V', 'L', 'R'].iter().copied().find(|letter| vdr.contains_key(letter)) {
+                Some('H') => "HORIZONTAL",
+                Some('V') => "VERTICAL",
+                Some('L') => "LEFT",
+                Some('R') => "RIGHT",
+                _ => return Err(format!("'{}' gives no polarisation", fields[2])),
+            };
+            parameters.insert("POLARIZATION".to_string(), polarization.to_string());
+            parameters.insert("SYMBOL_RATE".to_string(), symbol_rate()?);
+            vdr_parameter(&mut parameters, &vdr, 'C', "INNER_FEC", &VDR_CODE_RATES)?;
+            vdr_parameter(&mut parameters, &vdr, 'O', "ROLLOFF", &VDR_ROLLOFFS)?;
+            if second_generation { "DVBS2" } else { "DVBS" }
+        },
+        Some('C') if source.len() == 1 => {
+            parameters.insert("SYMBOL_RATE".to_string(), symbol_rate()?);
+            vdr_parameter(&mut parameters, &vdr, 'C', "INNER_FEC", &VDR_CODE_RATES)?;
+            "DVBC/ANNEX_A"
+        },
+        Some('T') if source.len() == 1 => {
+            bandwidth_hz = match vdr.get(&'B') {
+                None => None,
+                Some(Some(1712)) => Some(1_712_000),
+                Some(Some(megahertz)) if [5, 6, 7, 8, 10].contains(megahertz) => Some(megahertz * 1_000_000),
+                Some(_) => return Err(format!("'{}' does not give a bandwidth", fields[2])),
+            };
+            vdr_parameter(&mut parameters, &vdr, 'C', "CODE_RATE_HP", &VDR_CODE_RATES)?;
+            vdr_parameter(&mut parameters, &vdr, 'D', "CODE_RATE_LP", &VDR_CODE_RATES)?;
+            vdr_parameter(&mut parameters, &vdr, 'G', "GUARD_INTERVAL", &VDR_GUARD_INTERVALS)?;
+            vdr_parameter(&mut parameters, &vdr, 'T', "TRANSMISSION_MODE", &VDR_TRANSMISSION_MODES)?;
+            vdr_parameter(&mut parameters, &vdr, 'Y', "HIERARCHY", &VDR_HIERARCHIES)?;
+            if second_generation { "DVBT2" } else { "DVBT" }
+        },
+        Some('A') if source.len() == 1 => "ATSC",
+        _ => return Err(format!("'{}' is not a satellite, cable, terrestrial, or ATSC source", source)),
+    };
+    // 0 is what VDR writes when the network or transport stream is not known.
+    for (index, key) in [(10, "NETWORK_ID"), (11, "TRANSPORT_ID")].iter() {
+        if let Some(field) = fields.get(*index) {
+            match zap_number::<u16>(field, "network or transport stream id")? {
+                0 => {},
+                id => { parameters.insert(key.to_string(), id.to_string()); },
+            }
+        }
+    }
+    let video_pid = match zap_number::<u16>(fields[5].split(|c| c == '+' || c == '=').next().unwrap_or(""), "video PID")? {
+        0 => None,
+        pid => Some(pid),
+    };
+    Ok(Channel {
+        name,
+        tuning: TuningParameters {
+            delivery_system: delivery_system.to_string(),
+            frequency: vdr_frequency(fields[1])?,
+            bandwidth_hz,
+            modulation,
+            service_id: zap_number::<u16>(fields[9], "service id")?,
+        },
+        video_pid,
+        audio_pids: zap_audio_pids(fields[6])?,
+        parameters,
+    })
+}
+
+/// Read the channels of a VDR channels.conf file. Blank lines and group separators,
+/// lines starting with :, are skipped.
+pub fn parse_vdr(contents: &str) -> Channels {
+    let mut channels = Channels::default();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(':') { continue; }
+        match parse_vdr_line(line) {
+            Ok(channel) => channels.channels.push(channel),
+            Err(message) => channels.warnings.push(ParseWarning{line: index + 1, message}),
+        }
+    }
+    channels
+}
+
+/// Read the channels of the VDR channels.conf file at `path`.
+pub fn read_vdr(path: &Path) -> io::Result<Channels> {
+    Ok(parse_vdr(&fs::read_to_string(path)?))
+}
+
+/// The formats channels can be imported from: those of a channels file, which is what
+/// dvbv5-scan and w_scan2 -X write, and VDR, which is what w_scan2 writes otherwise.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScanFormat {
+    DvbV5,
+    Zap,
+    Vdr,
+}
+
+impl ScanFormat {
+    /// The format of `contents`: VDR if the first line that is not blank or a comment is
+    /// a group separator or has a VDR source as its fourth field, T, C, A, or S and an
+    /// orbital position, which in a zap format line is a bandwidth or a number; otherwise
+    /// that of a channels file.
+    pub fn of(contents: &str) -> ScanFormat {
+        let line = contents.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#'));
+        let is_vdr = line.map_or(false, |line| {
+            let source = line.split(':').nth(3).unwrap_or("").trim();
+            line.starts_with(':')
+                || ["T", "C", "A"].contains(&source)
+                || (source.starts_with('S') && source[1..].starts_with(|c: char| c.is_ascii_digit()))
+        });
+        match ChannelsFileFormat::of(contents) {
+            ChannelsFileFormat::DvbV5 => ScanFormat::DvbV5,
+            ChannelsFileFormat::Zap if is_vdr => ScanFormat::Vdr,
+            ChannelsFileFormat::Zap => ScanFormat::Zap,
+        }
+    }
+}
+
+/// Read the channels of scan results in any of the formats channels can be imported
+/// from.
+pub fn parse_scan(contents: &str) -> Channels {
+    match ScanFormat::of(contents) {
+        ScanFormat::DvbV5 => parse_dvbv5(contents),
+        ScanFormat::Zap => parse_zap(contents),
+        ScanFormat::Vdr => parse_vdr(contents),
+    }
+}
+
+/// Read the channels of the scan results at `path`, in any of the formats channels can
+/// be imported from.
+pub fn read_scan(path: &Path) -> io::Result<Channels> {
+    Ok(parse_scan(&fs::read_to_string(path)?))
+}
+
+/// Whether the frequencies `a` and `b` are of the same transponder: they are within
+/// 0.05% of each other, as scanners round them differently.
+pub fn are_same_frequency(a: u32, b: u32) -> bool {
+    a.max(b) - a.min(b) <= a.max(b) / 2000
+}
+
+impl Channel {
+    /// Whether the channel is a radio channel: it has sound but no video. A channel the
+    /// channels file gives no PIDs for is not known to be one.
+    pub fn is_radio(&self) -> bool {
+        self.video_pid.is_none() && !self.audio_pids.is_empty()
+    }
+
+    /// Whether the channel is the service `other` is, the same service id on the same
+    /// transponder. Frequencies within 0.05% of each other are the same, as scanners
+    /// round them differently, a satellite transponder also having to have the same
+    /// polarisation.
+    pub fn is_same_service_as(&self, other: &Channel) -> bool {
+        self.tuning.service_id == other.tuning.service_id
+            && are_same_frequency(self.tuning.frequency, other.tuning.frequency)
+            && self.parameters.get("POLARIZATION") == other.parameters.get("POLARIZATION")
+    }
+
+    /// The identity of the service of the channel.
+    pub fn identity(&self) -> ServiceIdentity {
+        let id = |key: &str| self.parameters.get(key).and_then(|value| value.parse().ok());
+        ServiceIdentity {
+            original_network_id: id("NETWORK_ID"),
+            transport_stream_id: id("TRANSPORT_ID"),
+            service_id: self.tuning.service_id,
+        }
+    }
+
+    /// The DVBv5 format channels file block for the channel.
+    pub fn channels_file_entry(&self) -> String {
+        let mut entry = self.tuning.channels_file_entry(&self.name);
+        if let Some(video_pid) = self.video_pid {
+            entry.push_str(&format!("\tVIDEO_PID = {}\n", video_pid));
+        }
+        if !self.audio_pids.is_empty() {
+            entry.push_str(&format!("\tAUDIO_PID = {}\n", self.audio_pids.iter().map(|pid| pid.to_string()).collect::<Vec<_>>().join(" ")));
+        }
+        for (key, value) in &self.parameters {
+            entry.push_str(&format!("\t{} = {}\n", key, value));
+        }
+        entry
+    }
+
+    /// A parameter, or the zap format default if it is not given.
+    fn zap_parameter(&self, name: &str, prefix: &str) -> String {
+        to_zap_value(self.parameters.get(name).map_or("AUTO", String::as_str), prefix)
+    }
+
+    /// The line of a zap format channels file for the channel, or None if the delivery
+    /// system cannot be given in the zap format.
+    pub fn zap_line(&self) -> Option<String> {
+        let tuning = &self.tuning;
+        let modulation = to_zap_modulation(tuning.modulation.as_deref().unwrap_or("QAM/AUTO"));
+        let middle = match tuning.delivery_system.as_str() {
+            "DVBT" | "DVBT2" => format!(
+                "{}:{}:{}:{}:{}:{}:{}:{}:{}:{}",
+                tuning.frequency,
+                self.zap_parameter("INVERSION", "INVERSION"),
+                to_zap_bandwidth(tuning.bandwidth_hz),
+                self.zap_parameter("CODE_RATE_HP", "FEC"),
+                self.zap_parameter("CODE_RATE_LP", "FEC"),
+                modulation,
+                self.zap_parameter("TRANSMISSION_MODE", "TRANSMISSION_MODE"),
+                self.zap_parameter("GUARD_INTERVAL", "GUARD_INTERVAL"),
+                self.zap_parameter("HIERARCHY", "HIERARCHY"),
+                "",
+            ),
+            "DVBC/ANNEX_A" => format!(
+                "{}:{}:{}:{}:{}:",
+                tuning.frequency,
+                self.zap_parameter("INVERSION", "INVERSION"),
+                self.parameters.get("SYMBOL_RATE")?,
+                self.zap_parameter("INNER_FEC", "FEC"),
+                modulation,
+            ),
+            "DVBS" => {
+                let polarization = match self.parameters.get("POLARIZATION")?.as_str() {
+                    "HORIZONTAL" => "h",
+                    "VERTICAL" => "v",
+                    "LEFT" => "l",
+                    "RIGHT" => "r",
+                    _ => return None,
+                };
+                format!(
+                    "{}:{}:{}:{}:",
+                    tuning.frequency / 1000,
+                    polarization,
+                    self.parameters.get("SAT_NUMBER").map_or("0", String::as_str),
+                    self.parameters.get("SYMBOL_RATE")?.parse::<u32>().ok()? / 1000,
+                )
+            },
+            "ATSC" => format!("{}:{}:", tuning.frequency, modulation),
+            _ => return None,
+        };
+        let audio_pids = if self.audio_pids.is_empty() {
+            "0".to_string()
+        } else {
+            self.audio_pids.iter().map(|pid| pid.to_string()).collect::<Vec<_>>().join(",")
+        };
+        Some(format!("{}:{}{}:{}:{}", self.name, middle, self.video_pid.unwrap_or(0), audio_pids, tuning.service_id))
+    }
+}
+
+/// What to do with an imported channel that is the same service as one already in the
+/// channels file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Conflicts {
+    Skip,  // Leave the channel in the file as it is.
+    Replace,  // Tune the channel in the file as the imported one is, keeping its name.
+}
+
+/// What adding channels to a channels file did, by channel name.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Import {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,  // By the name in the file, which may not be the imported one.
+    pub skipped: Vec<String>,  // The file already has the service, and it is left as it is.
+    pub already_present: Vec<String>,  // The file already has a channel of the name, which is left as it is.
+    pub not_zap: Vec<String>,  // The file is in zap format, which cannot give these channels.
+}
+
+/// Add `channels` to the end of the channels file at `path`, in the format of the file,
+/// or DVBv5 format if there is no file or it is empty. A channel that is the same service
+/// as one already in the file is dealt with as `conflicts` says, one with the name of
+/// another channel already in the file is not added.
+pub fn import_channels(path: &Path, channels: &[Channel], conflicts: Conflicts) -> io::Result<Import> {
+    let (import, contents) = imported(&read_channels_file_contents(path)?, channels, conflicts);
+    if let Some(contents) = contents {
+        write_channels_file(path, &contents)?;
+    }
+    Ok(import)
+}
+
+/// What adding `channels` to the channels file at `path` as `import_channels` does would
+/// do, without the file being changed, for asking the user first.
+pub fn preview_import(path: &Path, channels: &[Channel], conflicts: Conflicts) -> io::Result<Import> {
+    Ok(imported(&read_channels_file_contents(path)?, channels, conflicts).0)
+}
+
+/// The contents of the channels file at `path`, empty if there is no file.
+fn read_channels_file_contents(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(error) => Err(error),
+    }
+}
+
+/// What adding `channels` to a channels file with `contents` does, and the contents it
+/// then has if they have changed.
+fn imported(contents: &str, channels: &[Channel], conflicts: Conflicts) -> (Import, Option<String>) {
+    let format = if contents.trim().is_empty() { ChannelsFileFormat::DvbV5 } else { ChannelsFileFormat::of(contents) };
+    let mut entries = channels_file_entries(contents, format);
+    let mut names = entries.iter().filter_map(|(name, _)| name.clone()).collect::<HashSet<_>>();
+    let mut present = parse_channels(contents).channels;
+    let entry_of = |channel: &Channel| match format {
+        ChannelsFileFormat::DvbV5 => Some(channel.channels_file_entry()),
+        ChannelsFileFormat::Zap => channel.zap_line().map(|line| line + "\n"),
+    };
+    let mut import = Import::default();
+    for channel in channels {
+        if let Some(index) = present.iter().position(|x| x.is_same_service_as(channel)) {
+            let replacement = Channel{name: present[index].name.clone(), ..channel.clone()};
+            if conflicts == Conflicts::Skip || replacement == present[index] {
+                import.skipped.push(channel.name.clone());
+                continue;
+            }
+            match entry_of(&replacement) {
+                Some(entry) => {
+                    if let Some((_, text)) = entries.iter_mut().find(|(name, _)| name.as_ref() == Some(&replacement.name)) {
+                        *text = entry;
+                    }
+                    import.updated.push(replacement.name.clone());
+                    present[index] = replacement;
+                },
+                None => import.not_zap.push(channel.name.clone()),
+            }
+            continue;
+        }
+        if names.contains(&channel.name) {
+            import.already_present.push(channel.name.clone());
+            continue;
+        }
+        match entry_of(channel) {
+            Some(entry) => {
+                entries.push((Some(channel.name.clone()), entry));
+                names.insert(channel.name.clone());
+                present.push(channel.clone());
+                import.added.push(channel.name.clone());
+            },
+            None => import.not_zap.push(channel.name.clone()),
+        }
+    }
+    if import.added.is_empty() && import.updated.is_empty() {
+        return (import, None);
+    }
+    (import, Some(entries.into_iter().map(|(_, text)| text).collect()))
+}
+
+/// Write the channels file at `path` to a file alongside it that then replaces it, so
+/// that neither dvbbasebin nor the watcher of the file ever read it half written.
+fn write_channels_file(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let temporary = path.with_extension("new");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+/// A change to a channel of a channels file, the channel given by its name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChannelEdit {
+    Rename{name: String, new_name: String},
+    Remove{name: String},
+    MoveUp{name: String},
+    MoveDown{name: String},
+}
+
+impl ChannelEdit {
+    /// The name of the channel changed.
+    pub fn name(&self) -> &str {
+        match self {
+            ChannelEdit::Rename{name, ..} | ChannelEdit::Remove{name} | ChannelEdit::MoveUp{name} | ChannelEdit::MoveDown{name} => name,
+        }
+    }
+}
+
+/// The text of a channels file split into that of each channel, with its name, and that
+/// of the comments and blank lines of a zap format file and the lines before the first
+/// block of a DVBv5 format one, without. The text of each ends with a newline.
+fn channels_file_entries(contents: &str, format: ChannelsFileFormat) -> Vec<(Option<String>, String)> {
+    let mut entries = Vec::<(Option<String>, String)>::new();
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        let name = match format {
+            ChannelsFileFormat::DvbV5 => trimmed.strip_prefix('[').and_then(|line| line.strip_suffix(']')).map(|name| name.trim().to_string()),
+            ChannelsFileFormat::Zap if trimmed.starts_with('#') => None,
+            ChannelsFileFormat::Zap => trimmed.find(':').map(|position| trimmed[..position].trim().to_string()),
+        };
+        match (format, name, entries.last_mut()) {
+            (ChannelsFileFormat::DvbV5, None, Some((_, text))) => {
+                text.push_str(line);
+                text.push('\n');
+            },
+            (_, name, _) => entries.push((name, format!("{}\n", line))),
+        }
+    }
+    entries
+}
+
+/// Change a channel of the channels file at `path`, leaving the rest of the file as it
+/// is, even the lines that cannot be read. Moving the first channel up, or the last
+/// down, leaves it where it is.
+pub fn edit_channels_file(path: &Path, edit: &ChannelEdit) -> io::Result<()> {
+    let contents = fs::read_to_string(path)?;
+    let format = ChannelsFileFormat::of(&contents);
+    let mut entries = channels_file_entries(&contents, format);
+    let named = entries.iter().enumerate().filter(|(_, (name, _))| name.is_some()).map(|(index, _)| index).collect::<Vec<_>>();
+    let position = named.iter().position(|&index| entries[index].0.as_deref() == Some(edit.name()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is not in {}", edit.name(), path.display())))?;
+    let index = named[position];
+    match edit {
+        ChannelEdit::Rename{name, new_name} => {
+            let new_name = new_name.trim();
+            if new_name.is_empty() || new_name.contains('\n') || (format == ChannelsFileFormat::Zap && new_name.contains(':')) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' cannot be the name of a channel in {}", new_name, path.display())));
+            }
+            if new_name != name && entries.iter().any(|(name, _)| name.as_deref() == Some(new_name)) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is already in {}", new_name, path.display())));
+            }
+            let text = &entries[index].1;
+            let text = match format {
+                ChannelsFileFormat::DvbV5 => format!("[{}]{}", new_name, &text[text.find('\n').unwrap()..]),
+                ChannelsFileFormat::Zap => format!("{}{}", new_name, &text[text.find(':').unwrap()..]),
+            };
+            entries[index] = (Some(new_name.to_string()), text);
+        },
+        ChannelEdit::Remove{..} => { entries.remove(index); },
+        ChannelEdit::MoveUp{..} => if position > 0 { entries.swap(named[position - 1], index); },
+        ChannelEdit::MoveDown{..} => if position + 1 < named.len() { entries.swap(index, named[position + 1]); },
+    }
+    write_channels_file(path, &entries.into_iter().map(|(_, text)| text).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::BTreeMap;
+    use std::ffi::OsString;
+    use std::io::{self, Write};
+    use std::path::Path;
+
+    use quickcheck::quickcheck;
+
+    use tempfile;
+
+    use crate::frontend_info::DeliverySystem;
+
+    use super::{channels_file_path_from, dvb_uri, parse_zap, read_channel_names, read_delivery_system, read_is_radio, read_service_id, parse_channels, parse_dvbv5, read_channels, parse_vdr, parse_scan, import_channels, preview_import, edit_channels_file, Channel, ChannelEdit, Channels, ChannelsFileFormat, Conflicts, Import, ParseWarning, ScanFormat, ServiceIdentity, TuningParameters};
+
+    fn tuning_parameters() -> TuningParameters {
+        TuningParameters {
+            delivery_system: "DVBT2".to_string(),
+            frequency: 490000000,
+            bandwidth_hz: Some(8000000),
+            modulation: Some("QAM/256".to_string()),
+            service_id: 4164,
+        }
+    }
+
+    #[test]
+    fn channel_names_are_read_in_order() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"[BBC ONE Lon]
+        SERVICE_ID = 4164
+        FREQUENCY = 490000000
+[BBC TWO]
+        SERVICE_ID = 4287
+        FREQUENCY = 490000000
+").unwrap();
+        assert_eq!(read_channel_names(file.path()), Some(vec!["BBC ONE Lon".to_string(), "BBC TWO".to_string()]));
+    }
+
+    #[test]
+    fn service_id_is_read() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"[BBC ONE Lon]
+        SERVICE_ID = 4164
+[BBC TWO]
+        SERVICE_ID = 4287
+").unwrap();
+        assert_eq!(read_service_id(file.path(), "BBC TWO"), Some(4287));
+        assert_eq!(read_service_id(file.path(), "BBC THREE"), None);
+    }
+
+    #[test]
+    fn delivery_system_is_read() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"[BBC ONE Lon]
+        SERVICE_ID = 4164
+        DELIVERY_SYSTEM = DVBT2
+[Astra]
+        SERVICE_ID = 17
+        DELIVERY_SYSTEM = DVBS2
+[Unknown]
+        SERVICE_ID = 5
+").unwrap();
+        assert_eq!(read_delivery_system(file.path(), "BBC ONE Lon"), Some(DeliverySystem::DVBT2));
+        assert_eq!(read_delivery_system(file.path(), "Astra"), Some(DeliverySystem::DVBS2));
+        assert_eq!(read_delivery_system(file.path(), "Unknown"), None);
+        assert_eq!(read_delivery_system(file.path(), "BBC THREE"), None);
+    }
+
+    #[test]
+    fn radio_channels_are_those_with_sound_but_no_video() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"[BBC ONE Lon]
+        SERVICE_ID = 4164
+        VIDEO_PID = 101
+        AUDIO_PID = 102
+        FREQUENCY = 490000000
+        DELIVERY_SYSTEM = DVBT
+[BBC Radio 4]
+        SERVICE_ID = 7232
+        VIDEO_PID = 0
+        AUDIO_PID = 7234
+        FREQUENCY = 490000000
+        DELIVERY_SYSTEM = DVBT
+[BBC RB 1]
+        SERVICE_ID = 17920
+        FREQUENCY = 490000000
+        DELIVERY_SYSTEM = DVBT
+").unwrap();
+        assert_eq!(read_is_radio(file.path(), "BBC ONE Lon"), Some(false));
+        assert_eq!(read_is_radio(file.path(), "BBC Radio 4"), Some(true));
+        assert_eq!(read_is_radio(file.path(), "BBC RB 1"), Some(false));
+        assert_eq!(read_is_radio(file.path(), "BBC THREE"), None);
+        let channels = parse_zap("BBC Radio 4:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:0:7234:7232\n");
+        assert!(channels.channels[0].is_radio());
+    }
+
+    #[test]
+    fn missing_file_gives_none() {
+        let directory = tempfile::tempdir().unwrap();
+        assert_eq!(read_channel_names(&directory.path().join("dvb-channels.conf")), None);
+    }
+
+    #[test]
+    fn tuning_parameters_map_to_dvbbasebin_nicks() {
+        let parameters = TuningParameters { delivery_system: "DVBC/ANNEX_A".to_string(), ..tuning_parameters() };
+        assert_eq!(parameters.delsys_nick(), "DVBC_ANNEX_A");
+        assert_eq!(parameters.modulation_nick(), Some("QAM 256".to_string()));
+        let parameters = TuningParameters { modulation: Some("VSB/8".to_string()), ..tuning_parameters() };
+        assert_eq!(parameters.modulation_nick(), Some("8VSB".to_string()));
+    }
+
+    #[test]
+    fn channels_file_entry_is_readable() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(tuning_parameters().channels_file_entry("New Mux").as_bytes()).unwrap();
+        assert_eq!(read_channel_names(file.path()), Some(vec!["New Mux".to_string()]));
+        assert_eq!(read_service_id(file.path(), "New Mux"), Some(4164));
+    }
+
+    /// A zap format file as made by w_scan and the scan tool of dvb-apps for each
+    /// delivery system, with a comment, a blank line, and malformed lines.
+    const ZAP_FILE: &str = "# Crystal Palace, London
+BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_3_4:FEC_3_4:QAM_16:TRANSMISSION_MODE_2K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102,106:4164
+BBC ONE HD:474000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_256:TRANSMISSION_MODE_32K:GUARD_INTERVAL_1_128:HIERARCHY_NONE:5500:5502:17540
+
+Das Erste:410000000:INVERSION_AUTO:6900000:FEC_NONE:QAM_64:101:102:28106
+Das Erste HD:11494:h:0:22000:5101:5102=deu:10301
+KQED-HD:213028615:8VSB:49:52:1
+BBC TWO:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_3_4
+Radio 4:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_3_4:FEC_3_4:QAM_16:TRANSMISSION_MODE_2K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:0:106:x
+";
+
+    fn parameters(parameters: &[(&str, &str)]) -> BTreeMap<String, String> {
+        parameters.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn channels_file_is_as_for_dvbbasebin() {
+        assert_eq!(channels_file_path_from(Some(OsString::from("/srv/tv/channels.conf"))).as_ref(), Path::new("/srv/tv/channels.conf"));
+        assert!(channels_file_path_from(Some(OsString::new())).ends_with("gstreamer-1.0/dvb-channels.conf"));
+        assert!(channels_file_path_from(None).ends_with("gstreamer-1.0/dvb-channels.conf"));
+    }
+
+    #[test]
+    fn dvb_uris_have_odd_characters_encoded() {
+        assert_eq!(dvb_uri("BBC ONE Lon"), "dvb://BBC%20ONE%20Lon");
+        assert_eq!(dvb_uri("100% \"Hits\" #1?"), "dvb://100%25%20%22Hits%22%20%231%3F");
+        assert_eq!(dvb_uri("Das Erste/ZDF"), "dvb://Das%20Erste/ZDF");
+        assert_eq!(dvb_uri("Télé 5"), "dvb://T%C3%A9l%C3%A9%205");
+    }
+
+    #[test]
+    fn zap_terrestrial_channels_are_read() {
+        let channels = parse_zap(ZAP_FILE).channels;
+        assert_eq!(channels[0], Channel {
+            name: "BBC ONE Lon".to_string(),
+            tuning: TuningParameters {
+                delivery_system: "DVBT".to_string(),
+                frequency: 490000000,
+                bandwidth_hz: Some(8000000),
+                modulation: Some("QAM/16".to_string()),
+                service_id: 4164,
+            },
+            video_pid: Some(101),
+            audio_pids: vec![102, 106],
+            parameters: parameters(&[
+                ("CODE_RATE_HP", "3/4"), ("CODE_RATE_LP", "3/4"), ("GUARD_INTERVAL", "1/32"),
+                ("HIERARCHY", "NONE"), ("INVERSION", "AUTO"), ("TRANSMISSION_MODE", "2K"),
+            ]),
+        });
+        assert_eq!(channels[1].tuning.delivery_system, "DVBT2");
+        assert_eq!(channels[1].tuning.modulation, Some("QAM/256".to_string()));
+        assert_eq!(channels[1].parameters["TRANSMISSION_MODE"], "32K");
+        assert_eq!(channels[1].parameters["GUARD_INTERVAL"], "1/128");
+    }
+
+    #[test]
+    fn zap_cable_satellite_and_atsc_channels_are_read() {
+        let channels = parse_zap(ZAP_FILE).channels;
+        assert_eq!(channels[2].tuning, TuningParameters {
+            delivery_system: "DVBC/ANNEX_A".to_string(),
+            frequency: 410000000,
+            bandwidth_hz: None,
+            modulation: Some("QAM/64".to_string()),
+            service_id: 28106,
+        });
+        assert_eq!(channels[2].parameters, parameters(&[("INNER_FEC", "NONE"), ("INVERSION", "AUTO"), ("SYMBOL_RATE", "6900000")]));
+        assert_eq!(channels[3].tuning.delivery_system, "DVBS");
+        assert_eq!(channels[3].tuning.frequency, 11494000);
+        assert_eq!(channels[3].audio_pids, vec![5102]);
+        assert_eq!(channels[3].parameters, parameters(&[("POLARIZATION", "HORIZONTAL"), ("SAT_NUMBER", "0"), ("SYMBOL_RATE", "22000000")]));
+        assert_eq!(channels[4].tuning.delivery_system, "ATSC");
+        assert_eq!(channels[4].tuning.modulation, Some("VSB/8".to_string()));
+        assert_eq!((channels[4].video_pid, channels[4].tuning.service_id), (Some(49), 1));
+        assert_eq!(channels[4].tuning.delivery_system.parse::<DeliverySystem>().ok(), Some(DeliverySystem::ATSC));
+    }
+
+    #[test]
+    fn malformed_zap_lines_are_warned_about_and_skipped() {
+        let channels = parse_zap(ZAP_FILE);
+        assert_eq!(channels.channels.iter().map(|channel| channel.name.as_str()).collect::<Vec<_>>(), vec!["BBC ONE Lon", "BBC ONE HD", "Das Erste", "Das Erste HD", "KQED-HD"]);
+        assert_eq!(channels.warnings, vec![
+            ParseWarning{line: 8, message: "5 fields is not a terrestrial (13), cable (9), satellite (8) or ATSC (6) channel".to_string()},
+            ParseWarning{line: 9, message: "'x' is not a service id".to_string()},
+        ]);
+        assert_eq!(channels.warnings[1].to_string(), "line 9: 'x' is not a service id");
+    }
+
+    #[test]
+    fn zap_file_round_trips() {
+        let channels = parse_zap(ZAP_FILE).channels;
+        let lines = channels.iter().map(|channel| channel.zap_line().unwrap()).collect::<Vec<_>>();
+        assert_eq!(lines[0], "BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_3_4:FEC_3_4:QAM_16:TRANSMISSION_MODE_2K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102,106:4164");
+        assert_eq!(lines[3], "Das Erste HD:11494:h:0:22000:5101:5102:10301");
+        assert_eq!(parse_zap(&lines.join("\n")), Channels{channels, warnings: vec![]});
+    }
+
+    /// DVBv5 format files as written by dvbv5-scan, for DVB-T2 from Crystal Palace and
+    /// DVB-S2 from Astra 19.2°E.
+    const DVBV5_T2_FILE: &str = "[BBC ONE HD]
+	SERVICE_ID = 17540
+	NETWORK_ID = 12339
+	TRANSPORT_ID = 16384
+	VIDEO_PID = 5500
+	AUDIO_PID = 5502 5503
+	PID_0b = 7030
+	FREQUENCY = 474000000
+	MODULATION = QAM/256
+	BANDWIDTH_HZ = 8000000
+	INVERSION = AUTO
+	CODE_RATE_HP = 2/3
+	CODE_RATE_LP = NONE
+	GUARD_INTERVAL = 1/128
+	TRANSMISSION_MODE = 32K
+	HIERARCHY = NONE
+	STREAM_ID = 0
+	DELIVERY_SYSTEM = DVBT2
+
+[BBC RB 1]
+	SERVICE_ID = 17920
+	NETWORK_ID = 12339
+	TRANSPORT_ID = 16384
+	FREQUENCY = 474000000
+	MODULATION = QAM/256
+	BANDWIDTH_HZ = 8000000
+	DELIVERY_SYSTEM = DVBT2
+";
+
+    const DVBV5_S2_FILE: &str = "# Astra 19.2E
+[Das Erste HD]
+	SERVICE_ID = 10301
+	VIDEO_PID = 5101
+	AUDIO_PID = 5102 5103 5106
+	FREQUENCY = 11493750
+	MODULATION = PSK/8
+	INVERSION = AUTO
+	SYMBOL_RATE = 22000000
+	INNER_FEC = 2/3
+	PILOT = AUTO
+	ROLLOFF = 35
+	POLARIZATION = HORIZONTAL
+	STREAM_ID = 0
+	DELIVERY_SYSTEM = DVBS2
+
+[arte HD]
+	SERVICE_ID = 10302
+	FREQUENCY = 11493750
+	POLARIZATION = HORIZONTAL
+this line is broken
+[ZDF HD]
+	SERVICE_ID = 11110
+	VIDEO_PID = 6110
+	AUDIO_PID = 6120
+	FREQUENCY = 11361750
+	MODULATION = PSK/8
+	SYMBOL_RATE = 22000000
+	INNER_FEC = 2/3
+	POLARIZATION = HORIZONTAL
+	DELIVERY_SYSTEM = DVBS2
+";
+
+    #[test]
+    fn format_is_detected_from_the_contents() {
+        assert_eq!(ChannelsFileFormat::of(ZAP_FILE), ChannelsFileFormat::Zap);
+        assert_eq!(ChannelsFileFormat::of(DVBV5_T2_FILE), ChannelsFileFormat::DvbV5);
+        assert_eq!(ChannelsFileFormat::of(DVBV5_S2_FILE), ChannelsFileFormat::DvbV5);
+        assert_eq!(ChannelsFileFormat::of(""), ChannelsFileFormat::Zap);
+        assert_eq!(parse_channels(ZAP_FILE), parse_zap(ZAP_FILE));
+        assert_eq!(parse_channels(DVBV5_T2_FILE), parse_dvbv5(DVBV5_T2_FILE));
+    }
+
+    #[test]
+    fn dvbv5_t2_channels_are_read() {
+        let channels = parse_dvbv5(DVBV5_T2_FILE);
+        assert_eq!(channels.warnings, vec![]);
+        let channel = &channels.channels[0];
+        assert_eq!(channel.tuning, TuningParameters {
+            delivery_system: "DVBT2".to_string(),
+            frequency: 474000000,
+            bandwidth_hz: Some(8000000),
+            modulation: Some("QAM/256".to_string()),
+            service_id: 17540,
+        });
+        assert_eq!((channel.video_pid, channel.audio_pids.clone()), (Some(5500), vec![5502, 5503]));
+        assert_eq!(channel.parameters["TRANSMISSION_MODE"], "32K");
+        assert_eq!(channel.parameters["PID_0b"], "7030");
+        assert!(!channel.parameters.contains_key("SERVICE_ID"));
+        let radio = &channels.channels[1];
+        assert_eq!((radio.name.as_str(), radio.video_pid, radio.audio_pids.is_empty()), ("BBC RB 1", None, true));
+    }
+
+    #[test]
+    fn services_are_identified_by_network_transport_stream_and_service_ids() {
+        let identity = parse_dvbv5(DVBV5_T2_FILE).channels[0].identity();
+        assert_eq!(identity, ServiceIdentity{original_network_id: Some(12339), transport_stream_id: Some(16384), service_id: 17540});
+        assert!(identity.is_complete());
+        let zap = parse_zap(ZAP_FILE).channels[0].identity();
+        assert_eq!((zap.original_network_id, zap.transport_stream_id, zap.is_complete()), (None, None, false));
+        let service_only = ServiceIdentity{original_network_id: None, transport_stream_id: None, service_id: 17540};
+        assert!(service_only.may_be(&identity) && identity.may_be(&service_only));
+        assert!(!identity.may_be(&ServiceIdentity{transport_stream_id: Some(16516), ..identity}));
+        assert!(!identity.may_be(&ServiceIdentity{service_id: 17920, ..identity}));
+    }
+
+    #[test]
+    fn dvbv5_s2_channels_are_read_and_broken_blocks_warned_about() {
+        let channels = parse_dvbv5(DVBV5_S2_FILE);
+        assert_eq!(channels.channels.iter().map(|channel| channel.name.as_str()).collect::<Vec<_>>(), vec!["Das Erste HD", "ZDF HD"]);
+        let channel = &channels.channels[0];
+        assert_eq!(channel.tuning.delivery_system, "DVBS2");
+        assert_eq!(channel.tuning.frequency, 11493750);
+        assert_eq!(channel.tuning.modulation_nick(), Some("8PSK".to_string()));
+        assert_eq!(channel.parameters, parameters(&[
+            ("INNER_FEC", "2/3"), ("INVERSION", "AUTO"), ("PILOT", "AUTO"), ("POLARIZATION", "HORIZONTAL"),
+            ("ROLLOFF", "35"), ("STREAM_ID", "0"), ("SYMBOL_RATE", "22000000"),
+        ]));
+        assert_eq!(channels.warnings, vec![
+            ParseWarning{line: 17, message: "arte HD has no DELIVERY_SYSTEM".to_string()},
+            ParseWarning{line: 21, message: "'this line is broken' is neither a channel name in brackets nor a KEY = VALUE binding".to_string()},
+        ]);
+    }
+
+    #[test]
+    fn dvbv5_files_round_trip() {
+        for file in &[DVBV5_T2_FILE, DVBV5_S2_FILE] {
+            let channels = parse_dvbv5(file).channels;
+            let entries = channels.iter().map(Channel::channels_file_entry).collect::<Vec<_>>().join("\n");
+            assert_eq!(parse_dvbv5(&entries), Channels{channels, warnings: vec![]});
+        }
+    }
+
+    #[test]
+    fn zap_channels_can_be_written_in_dvbv5_format() {
+        let channels = parse_zap(ZAP_FILE).channels;
+        let entries = channels.iter().map(Channel::channels_file_entry).collect::<Vec<_>>().join("\n");
+        assert_eq!(parse_dvbv5(&entries).channels, channels);
+    }
+
+    #[test]
+    fn channels_files_of_either_format_are_read() {
+        for contents in &[ZAP_FILE, DVBV5_S2_FILE] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+            assert_eq!(read_channels(file.path()).unwrap(), parse_channels(contents));
+        }
+    }
+
+    #[test]
+    fn channel_lookups_work_on_zap_files() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(ZAP_FILE.as_bytes()).unwrap();
+        assert_eq!(read_channel_names(file.path()), Some(vec!["BBC ONE Lon", "BBC ONE HD", "Das Erste", "Das Erste HD", "KQED-HD"].into_iter().map(String::from).collect()));
+        assert_eq!(read_service_id(file.path(), "Das Erste"), Some(28106));
+        assert_eq!(read_delivery_system(file.path(), "BBC ONE HD"), Some(DeliverySystem::DVBT2));
+        assert_eq!(read_delivery_system(file.path(), "Das Erste HD"), Some(DeliverySystem::DVBS));
+        assert_eq!(read_service_id(file.path(), "BBC TWO"), None);
+    }
+
+    /// A VDR channels.conf with a channel for each delivery system, group separators, and
+    /// malformed lines.
+    const VDR_FILE: &str = ":Astra 19.2E
+Das Erste HD;ARD:11494:HC23M5O35P0S1:S19.2E:22000:5101=27:5102=deu@3,5103=mis@3;5106=deu@106:5104;5105=deu:0:10301:1:1019:0
+ZDF;ZDFvision:11953:hC34M2S0:S19.2E:27500:110=2:120=deu@3,121=mis@3;125=deu@106:130:0:28006:1:1079:0
+BBC ONE HD,BBC1 HD;BBC:10847:VC23M5O35S1:S27.5W:23000:5500:5502=eng@17:0:0:6941:2:2050:0
+:Crystal Palace
+BBC ONE Lon;BBC:490000:B8C34D34G32M16T2Y0S0:T:0:101=2:102=eng@3,106=NAR@3:0:0:4164:9018:4164:0
+BBC ONE HD;BBC:474000:B8C23D999G128M256P0T32Y0S1:T:0:5500=27:5502=eng@17:0:0:17540:9018:16516:0
+:Kabel
+Das Erste;ARD:410:M64:C:6900:101=2:102=deu@3:104:0:28106:61441:10003:0
+KQED-HD;PBS:213028:M10:A:0:49=2:52=eng@3:0:0:1:0:0:0
+Broken|Satellite;Nobody:11494:M5S1:S19.2E:22000:5101:5102:0:0:10302:1:1019:0
+Web TV;Nobody:1:S0:I:0:1:2:0:0:3:0:0:0
+Short;Nobody:11494:H:S19.2E:22000
+";
+
+    #[test]
+    fn vdr_satellite_channels_are_read() {
+        let channels = parse_vdr(VDR_FILE).channels;
+        assert_eq!(channels[0], Channel {
+            name: "Das Erste HD".to_string(),
+            tuning: TuningParameters {
+                delivery_system: "DVBS2".to_string(),
+                frequency: 11494000,
+                bandwidth_hz: None,
+                modulation: Some("PSK/8".to_string()),
+                service_id: 10301,
+            },
+            video_pid: Some(5101),
+            audio_pids: vec![5102, 5103, 5106],
+            parameters: parameters(&[
+                ("INNER_FEC", "2/3"), ("NETWORK_ID", "1"), ("POLARIZATION", "HORIZONTAL"), ("ROLLOFF", "35"), ("STREAM_ID", "0"), ("SYMBOL_RATE", "22000000"),
+                ("TRANSPORT_ID", "1019"),
+            ]),
+        });
+        let zdf = &channels[1];
+        assert_eq!((zdf.tuning.delivery_system.as_str(), zdf.tuning.modulation.as_deref()), ("DVBS", Some("QPSK")));
+        assert_eq!(zdf.parameters, parameters(&[
+            ("INNER_FEC", "3/4"), ("NETWORK_ID", "1"), ("POLARIZATION", "HORIZONTAL"), ("SYMBOL_RATE", "27500000"), ("TRANSPORT_ID", "1079"),
+        ]));
+        let bbc = &channels[2];
+        assert_eq!((bbc.name.as_str(), bbc.tuning.delivery_system.as_str(), bbc.parameters["POLARIZATION"].as_str()), ("BBC ONE HD", "DVBS2", "VERTICAL"));
+    }
+
+    #[test]
+    fn vdr_terrestrial_channels_are_read() {
+        let channels = parse_vdr(VDR_FILE).channels;
+        assert_eq!(channels[3], Channel {
+            name: "BBC ONE Lon".to_string(),
+            tuning: TuningParameters {
+                delivery_system: "DVBT".to_string(),
+                frequency: 490000000,
+                bandwidth_hz: Some(8000000),
+                modulation: Some("QAM/16".to_string()),
+                service_id: 4164,
+            },
+            video_pid: Some(101),
+            audio_pids: vec![102, 106],
+            parameters: parameters(&[
+                ("CODE_RATE_HP", "3/4"), ("CODE_RATE_LP", "3/4"), ("GUARD_INTERVAL", "1/32"), ("HIERARCHY", "NONE"), ("NETWORK_ID", "9018"),
+                ("TRANSMISSION_MODE", "2K"), ("TRANSPORT_ID", "4164"),
+            ]),
+        });
+        let t2 = &channels[4];
+        assert_eq!((t2.tuning.delivery_system.as_str(), t2.tuning.frequency, t2.tuning.modulation.as_deref()), ("DVBT2", 474000000, Some("QAM/256")));
+        assert_eq!(t2.parameters, parameters(&[
+            ("CODE_RATE_HP", "2/3"), ("CODE_RATE_LP", "AUTO"), ("GUARD_INTERVAL", "1/128"), ("HIERARCHY", "NONE"), ("NETWORK_ID", "9018"), ("STREAM_ID", "0"),
+            ("TRANSMISSION_MODE", "32K"), ("TRANSPORT_ID", "16516"),
+        ]));
+    }
+
+    #[test]
+    fn vdr_cable_and_atsc_channels_are_read() {
+        let channels = parse_vdr(VDR_FILE).channels;
+        assert_eq!(channels[5].tuning, TuningParameters {
+            delivery_system: "DVBC/ANNEX_A".to_string(),
+            frequency: 410000000,
+            bandwidth_hz: None,
+            modulation: Some("QAM/64".to_string()),
+            service_id: 28106,
+        });
+        assert_eq!(channels[5].parameters, parameters(&[("NETWORK_ID", "61441"), ("SYMBOL_RATE", "6900000"), ("TRANSPORT_ID", "10003")]));
+        assert_eq!(channels[6].tuning, TuningParameters {
+            delivery_system: "ATSC".to_string(),
+            frequency: 213028000,
+            bandwidth_hz: None,
+            modulation: Some("VSB/8".to_string()),
+            service_id: 1,
+        });
+        assert_eq!(channels.len(), 7);
+    }
+
+    #[test]
+    fn malformed_vdr_lines_are_warned_about_and_skipped() {
+        assert_eq!(parse_vdr(VDR_FILE).warnings, vec![
+            ParseWarning{line: 11, message: "'M5S1' gives no polarisation".to_string()},
+            ParseWarning{line: 12, message: "'I' is not a satellite, cable, terrestrial, or ATSC source".to_string()},
+            ParseWarning{line: 13, message: "5 fields is too few for a VDR channel, there must be at least 10".to_string()},
+        ]);
+    }
+
+    #[test]
+    fn vdr_channels_name_bars_are_colons() {
+        let channels = parse_vdr("Broken|Satellite;Nobody:11494:VM5S1:S19.2E:22000:5101:5102:0:0:10302:1:1019:0
+").channels;
+        assert_eq!(channels[0].name, "Broken:Satellite");
+    }
+
+    #[test]
+    fn vdr_channels_are_imported_into_a_dvbv5_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("gstreamer-1.0").join("dvb-channels.conf");
+        let channels = parse_vdr(VDR_FILE).channels;
+        assert_eq!(import_channels(&path, &channels[..4], Conflicts::Skip).unwrap().added.len(), 4);
+        let import = import_channels(&path, &channels, Conflicts::Skip).unwrap();
+        assert_eq!(import, Import {
+            added: vec!["Das Erste", "KQED-HD"].into_iter().map(String::from).collect(),
+            updated: vec![],
+            skipped: vec!["Das Erste HD", "ZDF", "BBC ONE HD", "BBC ONE Lon"].into_iter().map(String::from).collect(),
+            already_present: vec!["BBC ONE HD".to_string()],
+            not_zap: vec![],
+        });
+        let mut expected = channels.clone();
+        expected.remove(4);
+        assert_eq!(read_channels(&path).unwrap(), Channels{channels: expected, warnings: vec![]});
+        assert_eq!(ChannelsFileFormat::of(&std::fs::read_to_string(&path).unwrap()), ChannelsFileFormat::DvbV5);
+    }
+
+    #[test]
+    fn vdr_channels_are_imported_into_a_zap_file_if_they_can_be() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_3_4:FEC_3_4:QAM_16:TRANSMISSION_MODE_2K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102,106:4164").unwrap();
+        let channels = parse_vdr(VDR_FILE).channels;
+        let import = import_channels(file.path(), &channels, Conflicts::Skip).unwrap();
+        assert_eq!(import.added, vec!["ZDF", "BBC ONE HD", "Das Erste", "KQED-HD"]);
+        assert_eq!(import.skipped, vec!["BBC ONE Lon"]);
+        assert!(import.already_present.is_empty());
+        assert_eq!(import.not_zap, vec!["Das Erste HD", "BBC ONE HD"]);
+        let channels = read_channels(file.path()).unwrap();
+        assert_eq!(channels.warnings, vec![]);
+        assert_eq!(channels.channels.iter().map(|channel| channel.name.as_str()).collect::<Vec<_>>(), vec!["BBC ONE Lon", "ZDF", "BBC ONE HD", "Das Erste", "KQED-HD"]);
+    }
+
+    fn edited(contents: &str, edits: &[ChannelEdit]) -> io::Result<String> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        for edit in edits {
+            edit_channels_file(file.path(), edit)?;
+        }
+        Ok(std::fs::read_to_string(file.path()).unwrap())
+    }
+
+    fn names(contents: &str) -> Vec<String> {
+        parse_channels(contents).channels.into_iter().map(|channel| channel.name).collect()
+    }
+
+    #[test]
+    fn dvbv5_channels_are_renamed_moved_and_removed() {
+        let contents = edited(DVBV5_S2_FILE, &[
+            ChannelEdit::Rename{name: "Das Erste HD".to_string(), new_name: " Das Erste ".to_string()},
+            ChannelEdit::MoveUp{name: "ZDF HD".to_string()},
+            ChannelEdit::MoveUp{name: "Das Erste".to_string()},
+        ]).unwrap();
+        assert!(contents.starts_with("# Astra 19.2E\n[Das Erste]\n\tSERVICE_ID = 10301\n"));
+        assert!(contents.ends_with("[arte HD]\n\tSERVICE_ID = 10302\n\tFREQUENCY = 11493750\n\tPOLARIZATION = HORIZONTAL\nthis line is broken\n"));
+        assert_eq!(names(&contents), vec!["Das Erste", "ZDF HD"]);
+        let contents = edited(&contents, &[
+            ChannelEdit::MoveDown{name: "ZDF HD".to_string()},
+            ChannelEdit::Remove{name: "Das Erste".to_string()},
+        ]).unwrap();
+        assert!(contents.starts_with("# Astra 19.2E\n[arte HD]\n"));
+        assert_eq!(parse_channels(&contents).channels, vec![parse_dvbv5(DVBV5_S2_FILE).channels[1].clone()]);
+    }
+
+    #[test]
+    fn zap_channels_are_renamed_moved_and_removed() {
+        let contents = edited(ZAP_FILE, &[
+            ChannelEdit::Rename{name: "BBC ONE HD".to_string(), new_name: "BBC One HD".to_string()},
+            ChannelEdit::MoveDown{name: "BBC ONE Lon".to_string()},
+            ChannelEdit::Remove{name: "KQED-HD".to_string()},
+        ]).unwrap();
+        assert!(contents.starts_with("# Crystal Palace, London\nBBC One HD:474000000:INVERSION_AUTO:"));
+        assert!(contents.contains("\n\nDas Erste:"));
+        assert_eq!(names(&contents), vec!["BBC One HD", "BBC ONE Lon", "Das Erste", "Das Erste HD"]);
+        assert_eq!(parse_channels(&contents).warnings.len(), 2);
+    }
+
+    #[test]
+    fn channels_cannot_be_given_names_that_are_taken_or_unusable() {
+        let rename = |name: &str, new_name: &str| ChannelEdit::Rename{name: name.to_string(), new_name: new_name.to_string()};
+        assert_eq!(edited(ZAP_FILE, &[rename("BBC ONE HD", "BBC ONE Lon")]).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(edited(ZAP_FILE, &[rename("BBC ONE HD", "BBC: ONE")]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(edited(DVBV5_T2_FILE, &[rename("BBC ONE HD", "  ")]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(edited(DVBV5_T2_FILE, &[ChannelEdit::Remove{name: "ITV".to_string()}]).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(edited(DVBV5_T2_FILE, &[rename("BBC ONE HD", "BBC ONE HD"), ChannelEdit::MoveUp{name: "BBC ONE HD".to_string()}]).unwrap(), DVBV5_T2_FILE);
+    }
+
+    #[test]
+    fn importing_nothing_new_leaves_the_file_alone() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("dvb-channels.conf");
+        assert_eq!(import_channels(&path, &[], Conflicts::Replace).unwrap(), Import::default());
+        assert!(!path.exists());
+    }
+
+    /// What w_scan2 -ft -c GB writes, in the VDR format it writes by default.
+    const W_SCAN2_VDR_FILE: &str = "BBC ONE Lon;BBC:490000:B8C23D0G32M64S0T8Y0:T:27500:101=2:102=eng@3,106=eng@3:105:0:4164:9018:4164:0
+BBC TWO;BBC:490000:B8C23D0G32M64S0T8Y0:T:27500:201=2:202=eng@3,206=eng@3:205:0:4287:9018:4164:0
+BBC Radio 4;BBC:490000:B8C23D0G32M64S0T8Y0:T:27500:0:406=eng@3:0:0:4288:9018:4164:0
+BBC ONE HD;BBC:474000:B8C23D999G128M256P0S1T32Y0:T:27500:5500=27:5502=eng@17,5503=eng@17:0:0:17540:9018:16516:0
+Das Erste HD;ARD:11494:HC23M5O35P0S1:S19.2E:22000:5101=27:5102=deu@3,5103=mis@3;5106=deu@106:5104;5105=deu:0:10301:1:1019:0
+";
+
+    /// What w_scan2 -ft -c GB -X writes, in zap format.
+    const W_SCAN2_ZAP_FILE: &str = "BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_NONE:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164
+BBC TWO:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_NONE:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:201:202:4287
+BBC Radio 4:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_NONE:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:0:406:4288
+";
+
+    /// What dvbv5-scan -o writes, in DVBv5 format.
+    const DVBV5_SCAN_FILE: &str = "[BBC ONE Lon]
+	SERVICE_ID = 4164
+	VIDEO_PID = 101
+	AUDIO_PID = 102 106
+	PID_06 = 105
+	FREQUENCY = 490000000
+	INVERSION = AUTO
+	BANDWIDTH_HZ = 8000000
+	CODE_RATE_HP = 2/3
+	CODE_RATE_LP = NONE
+	MODULATION = QAM/64
+	TRANSMISSION_MODE = 8K
+	GUARD_INTERVAL = 1/32
+	HIERARCHY = NONE
+	DELIVERY_SYSTEM = DVBT
+
+[BBC Radio 4]
+	SERVICE_ID = 4288
+	AUDIO_PID = 406
+	FREQUENCY = 490000000
+	INVERSION = AUTO
+	BANDWIDTH_HZ = 8000000
+	CODE_RATE_HP = 2/3
+	CODE_RATE_LP = NONE
+	MODULATION = QAM/64
+	TRANSMISSION_MODE = 8K
+	GUARD_INTERVAL = 1/32
+	HIERARCHY = NONE
+	DELIVERY_SYSTEM = DVBT
+
+[Das Erste HD]
+	SERVICE_ID = 10301
+	VIDEO_PID = 5101
+	AUDIO_PID = 5102 5103 5106
+	FREQUENCY = 11493750
+	INVERSION = AUTO
+	SYMBOL_RATE = 22000000
+	INNER_FEC = 2/3
+	MODULATION = PSK/8
+	PILOT = AUTO
+	ROLLOFF = 35
+	POLARIZATION = HORIZONTAL
+	STREAM_ID = 0
+	DELIVERY_SYSTEM = DVBS2
+
+";
+
+    #[test]
+    fn scan_results_of_each_format_are_read() {
+        assert_eq!(ScanFormat::of(W_SCAN2_VDR_FILE), ScanFormat::Vdr);
+        assert_eq!(ScanFormat::of(VDR_FILE), ScanFormat::Vdr);
+        assert_eq!(ScanFormat::of(W_SCAN2_ZAP_FILE), ScanFormat::Zap);
+        assert_eq!(ScanFormat::of(ZAP_FILE), ScanFormat::Zap);
+        assert_eq!(ScanFormat::of(DVBV5_SCAN_FILE), ScanFormat::DvbV5);
+        assert_eq!(parse_scan(W_SCAN2_VDR_FILE), parse_vdr(W_SCAN2_VDR_FILE));
+        for contents in &[W_SCAN2_VDR_FILE, W_SCAN2_ZAP_FILE, DVBV5_SCAN_FILE] {
+            let channels = parse_scan(contents);
+            assert_eq!(channels.warnings, vec![]);
+            let radio = channels.channels.iter().find(|channel| channel.name == "BBC Radio 4").unwrap();
+            assert_eq!((radio.tuning.service_id, radio.is_radio()), (4288, true));
+        }
+        let zap = parse_scan(W_SCAN2_ZAP_FILE).channels;
+        let vdr = parse_scan(W_SCAN2_VDR_FILE).channels;
+        assert_eq!(zap[0].tuning, TuningParameters {
+            delivery_system: "DVBT".to_string(),
+            frequency: 490000000,
+            bandwidth_hz: Some(8000000),
+            modulation: Some("QAM/64".to_string()),
+            service_id: 4164,
+        });
+        assert_eq!(vdr[0].tuning, zap[0].tuning);
+        assert_eq!(vdr[4].tuning.delivery_system, "DVBS2");
+    }
+
+    #[test]
+    fn services_are_the_same_whichever_scanner_found_them() {
+        let dvbv5 = parse_scan(DVBV5_SCAN_FILE).channels;
+        let vdr = parse_scan(W_SCAN2_VDR_FILE).channels;
+        assert!(dvbv5[0].is_same_service_as(&vdr[0]));
+        assert!(dvbv5[2].is_same_service_as(&vdr[4]));
+        assert!(!dvbv5[0].is_same_service_as(&vdr[1]));
+        let mut vertical = vdr[4].clone();
+        vertical.parameters.insert("POLARIZATION".to_string(), "VERTICAL".to_string());
+        assert!(!dvbv5[2].is_same_service_as(&vertical));
+        let mut next_multiplex = vdr[0].clone();
+        next_multiplex.tuning.frequency = 498000000;
+        assert!(!dvbv5[0].is_same_service_as(&next_multiplex));
+    }
+
+    #[test]
+    fn imported_services_already_there_are_skipped_or_replaced() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("dvb-channels.conf");
+        let dvbv5 = parse_scan(DVBV5_SCAN_FILE).channels;
+        let mut vdr = parse_scan(W_SCAN2_VDR_FILE).channels;
+        vdr[0].name = "BBC One London".to_string();
+        assert_eq!(import_channels(&path, &dvbv5, Conflicts::Skip).unwrap().added, vec!["BBC ONE Lon", "BBC Radio 4", "Das Erste HD"]);
+        assert_eq!(preview_import(&path, &vdr, Conflicts::Skip).unwrap().added, vec!["BBC TWO", "BBC ONE HD"]);
+        assert_eq!(read_channels(&path).unwrap().channels.len(), 3);
+        assert_eq!(import_channels(&path, &vdr, Conflicts::Skip).unwrap(), Import {
+            added: vec!["BBC TWO", "BBC ONE HD"].into_iter().map(String::from).collect(),
+            skipped: vec!["BBC One London", "BBC Radio 4", "Das Erste HD"].into_iter().map(String::from).collect(),
+            ..Import::default()
+        });
+        let before = read_channels(&path).unwrap();
+        assert_eq!(before.channels[0], dvbv5[0]);
+        assert_eq!(import_channels(&path, &vdr, Conflicts::Replace).unwrap(), Import {
+            updated: vec!["BBC ONE Lon", "BBC Radio 4", "Das Erste HD"].into_iter().map(String::from).collect(),
+            skipped: vec!["BBC TWO", "BBC ONE HD"].into_iter().map(String::from).collect(),
+            ..Import::default()
+        });
+        let after = read_channels(&path).unwrap();
+        assert_eq!(after.warnings, vec![]);
+        assert_eq!(after.channels.iter().map(|channel| channel.name.as_str()).collect::<Vec<_>>(), vec!["BBC ONE Lon", "BBC Radio 4", "Das Erste HD", "BBC TWO", "BBC ONE HD"]);
+        assert_eq!(after.channels[0], Channel{name: "BBC ONE Lon".to_string(), ..vdr[0].clone()});
+        assert_eq!(after.channels[2].tuning.frequency, 11494000);
+        assert_eq!(import_channels(&path, &vdr, Conflicts::Replace).unwrap().skipped.len(), 5);
+    }
+
+    quickcheck! {
+        fn parsing_anything_does_not_panic(contents: String) -> bool {
+            let _ = parse_zap(&contents);
+            let _ = parse_vdr(&contents);
+            true
+        }
+    }
+
+    quickcheck! {
+        fn parsing_any_fields_does_not_panic(fields: Vec<String>) -> bool {
+            let channels = parse_zap(&fields.join(":"));
+            channels.channels.len() + channels.warnings.len() <= 1 + fields.iter().map(|f| f.matches('\n').count()).sum::<usize>()
+        }
+    }
+
+    quickcheck! {
+        fn terrestrial_zap_line_round_trips(frequency: u32, video_pid: u16, audio_pid: u16, service_id: u16) -> bool {
+            let line = format!(
+                "Channel:{}:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_3_4:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:{}:{}:{}",
+                frequency, video_pid, audio_pid, service_id,
+            );
+            let channels = parse_zap(&line).channels;
+            channels.len() == 1 && channels[0].zap_line() == Some(line)
+        }
+    }
+
+}
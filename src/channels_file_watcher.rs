@@ -0,0 +1,147 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Watching the channels file so that changes to it, made by a scan or by it being
+//! edited, are noticed whilst Me TV is running.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use glib;
+
+use log::{info, warn};
+
+use notify::{Watcher, RecursiveMode, RawEvent, op, raw_watcher};
+
+use me_tv::debounce::Debouncer;
+
+use crate::channels_data::channels_file_path;
+use crate::control_window::Message;
+
+/// How long the channels file must have been left alone before it is reread, so that a
+/// file being written a piece at a time is only read once it is finished.
+const DEBOUNCE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Call `changed` each time the file at `path` has changed, once it has been left alone
+/// for `debounce_window`. It is the directory of the file that is watched, so that the
+/// file being created, or being replaced by another renamed to it as editors do, is
+/// noticed as much as it being written.
+///
+/// Only returns if the watch cannot be set up or stops working.
+fn watch<F: Fn()>(path: &Path, debounce_window: Duration, changed: F) -> Result<(), String> {
+    let (directory, file_name) = match (path.parent(), path.file_name()) {
+        (Some(directory), Some(file_name)) => (directory, OsString::from(file_name)),
+        _ => return Err(format!("{} is not a file in a directory", path.display())),
+    };
+    fs::create_dir_all(directory).map_err(|e| format!("could not create {}: {}", directory.display(), e))?;
+    let (transmit_end, receive_end) = channel();
+    let mut watcher = raw_watcher(transmit_end).map_err(|e| e.to_string())?;
+    watcher.watch(directory, RecursiveMode::NonRecursive).map_err(|e| format!("could not watch {}: {}", directory.display(), e))?;
+    let mut debouncer = Debouncer::new(debounce_window);
+    loop {
+        let event = match debouncer.time_to_next_quiet(Instant::now()) {
+            Some(timeout) => receive_end.recv_timeout(timeout),
+            None => receive_end.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match event {
+            Ok(RawEvent{path: Some(path), op: Ok(op), cookie: _}) => {
+                if path.file_name() == Some(&file_name) && op.intersects(op::CREATE | op::WRITE | op::RENAME | op::REMOVE) {
+                    debouncer.record((), Instant::now());
+                }
+            },
+            Ok(RawEvent{path: _, op: Err(e), cookie: _}) => warn!("channels_file_watcher::watch: watch error: {:?}", e),
+            Ok(_) => {},
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return Err("the watch stopped".to_string()),
+        }
+        if !debouncer.take_quiet(Instant::now()).is_empty() {
+            changed();
+        }
+    }
+}
+
+/// The dæmon that tells the control window each time the channels file has changed.
+pub fn run(to_cw: glib::Sender<Message>) {
+    let path = channels_file_path();
+    if let Err(reason) = watch(&path, DEBOUNCE_WINDOW, || {
+        info!("{} has changed, rereading it.", path.display());
+        to_cw.send(Message::ChannelsFileChanged).unwrap_or_else(|e| warn!("channels_file_watcher::run: could not tell the control window: {}", e));
+    }) {
+        warn!("Changes to {} will not be noticed, {}.", path.display(), reason);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Watch `path` in another thread, with a short debounce window, returning what
+    /// gets a message each time the file is reckoned to have changed.
+    fn watch_in_background(path: &Path) -> mpsc::Receiver<()> {
+        let (sender, receiver) = mpsc::channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || watch(&path, Duration::from_millis(200), || sender.send(()).unwrap()));
+        // Give the watch time to be set up.
+        thread::sleep(Duration::from_millis(200));
+        receiver
+    }
+
+    #[test]
+    fn a_burst_of_writes_is_one_change() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("dvb-channels.conf");
+        let changes = watch_in_background(&path);
+        for i in 0..5 {
+            fs::write(&path, format!("[Channel {}]\n", i)).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)), Ok(()));
+        assert_eq!(changes.recv_timeout(Duration::from_millis(500)), Err(RecvTimeoutError::Timeout));
+    }
+
+    #[test]
+    fn a_file_renamed_over_the_channels_file_is_a_change() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("dvb-channels.conf");
+        fs::write(&path, "[BBC ONE Lon]\n").unwrap();
+        let changes = watch_in_background(&path);
+        let replacement = directory.path().join("dvb-channels.conf.swp");
+        fs::write(&replacement, "[BBC TWO]\n").unwrap();
+        assert_eq!(changes.recv_timeout(Duration::from_millis(500)), Err(RecvTimeoutError::Timeout));
+        fs::rename(&replacement, &path).unwrap();
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)), Ok(()));
+    }
+
+    #[test]
+    fn the_directory_is_created_if_need_be() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("gstreamer-1.0").join("dvb-channels.conf");
+        let changes = watch_in_background(&path);
+        fs::write(&path, "[BBC ONE Lon]\n").unwrap();
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)), Ok(()));
+    }
+}
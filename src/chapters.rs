@@ -0,0 +1,174 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Chapter marks at the programme boundaries of a recording, found from the EIT.
+//!
+//! The recordings are MPEG-4 files, so the chapters are written to an accompanying
+//! FFmpeg metadata file, from which `ffmpeg -i film.mp4 -i film.ffmetadata -map_metadata 1
+//! -codec copy out.mp4` can put them in the container.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::eit::{EitSection, ACTUAL_PRESENT_FOLLOWING};
+
+/// A chapter, starting at a position in the recording.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: String,
+}
+
+/// Follows the present event of a service in the EIT, starting a chapter each time a
+/// different event becomes present.
+#[derive(Clone, Debug)]
+pub struct ChapterMarks {
+    service_id: u16,
+    present_event_id: Option<u16>,
+    chapters: Vec<Chapter>,
+}
+
+impl ChapterMarks {
+    pub fn new(service_id: u16) -> ChapterMarks {
+        ChapterMarks { service_id, present_event_id: None, chapters: Vec::new() }
+    }
+
+    pub fn chapters(&self) -> &[Chapter] { &self.chapters }
+
+    /// Update from an EIT section seen at `position` in the recording, returning the new
+    /// chapter if one started. The first event seen is the start of the recording.
+    pub fn update(&mut self, section: &EitSection, position: Duration) -> Option<&Chapter> {
+        if section.table_id != ACTUAL_PRESENT_FOLLOWING || section.service_id != self.service_id || section.section_number != 0 {
+            return None;
+        }
+        let event = section.events.first()?;
+        if Some(event.event_id) == self.present_event_id {
+            return None;
+        }
+        self.present_event_id = Some(event.event_id);
+        let start = if self.chapters.is_empty() { Duration::from_secs(0) } else { position };
+        let title = event.title.clone().unwrap_or_else(|| format!("Event {}", event.event_id));
+        self.chapters.push(Chapter { start, title });
+        self.chapters.last()
+    }
+}
+
+/// The chapters in FFmpeg metadata format, each ending where the next starts and the
+/// last ending at `end`.
+pub fn to_ffmetadata(chapters: &[Chapter], end: Duration) -> String {
+    let escape = |text: &str| {
+        text.chars().fold(String::new(), |mut escaped, c| {
+            if "=;#\\\n".contains(c) { escaped.push('\\'); }
+            escaped.push(c);
+            escaped
+        })
+    };
+    let mut metadata = String::from(";FFMETADATA1\n");
+    for (i, chapter) in chapters.iter().enumerate() {
+        let chapter_end = chapters.get(i + 1).map(|c| c.start).unwrap_or(end);
+        metadata.push_str("[CHAPTER]\nTIMEBASE=1/1000\n");
+        metadata.push_str(&format!("START={}\nEND={}\n", chapter.start.as_millis(), chapter_end.as_millis()));
+        metadata.push_str(&format!("title={}\n", escape(&chapter.title)));
+    }
+    metadata
+}
+
+/// The path of the chapters file for a recording.
+pub fn chapters_path(output_path: &str) -> PathBuf {
+    Path::new(output_path).with_extension("ffmetadata")
+}
+
+/// Write the chapters file for a recording, returning the path written.
+pub fn write_chapters(output_path: &str, chapters: &[Chapter], end: Duration) -> io::Result<PathBuf> {
+    let path = chapters_path(output_path);
+    fs::write(&path, to_ffmetadata(chapters, end))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::eit::{EitEvent, RunningStatus};
+
+    fn present(service_id: u16, event_id: u16, title: Option<&str>) -> EitSection {
+        EitSection {
+            table_id: ACTUAL_PRESENT_FOLLOWING,
+            service_id,
+            version_number: 0,
+            section_number: 0,
+            last_section_number: 1,
+            transport_stream_id: 4168,
+            original_network_id: 9018,
+            segment_last_section_number: 1,
+            last_table_id: ACTUAL_PRESENT_FOLLOWING,
+            events: vec![EitEvent {
+                event_id,
+                start_time: None,
+                duration_seconds: 1800,
+                running_status: RunningStatus::Running,
+                free_ca_mode: false,
+                title: title.map(String::from),
+                description: None,
+                extended_description: None,
+                texts: Vec::new(),
+                content: Vec::new(),
+                ratings: Vec::new(),
+                programme_crid: None,
+                series_crid: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn a_chapter_starts_at_each_new_present_event() {
+        let mut marks = ChapterMarks::new(4164);
+        assert!(marks.update(&present(4164, 1, Some("News")), Duration::from_secs(5)).is_some());
+        assert!(marks.update(&present(4164, 1, Some("News")), Duration::from_secs(60)).is_none());
+        assert!(marks.update(&present(4287, 9, Some("Other")), Duration::from_secs(90)).is_none());
+        assert!(marks.update(&present(4164, 2, None), Duration::from_secs(1800)).is_some());
+        assert_eq!(marks.chapters(), &[
+            Chapter { start: Duration::from_secs(0), title: "News".to_string() },
+            Chapter { start: Duration::from_secs(1800), title: "Event 2".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn ffmetadata_has_chapters_ending_at_the_next_start() {
+        let chapters = vec![
+            Chapter { start: Duration::from_secs(0), title: "News".to_string() },
+            Chapter { start: Duration::from_secs(1800), title: "Film; Part=1".to_string() },
+        ];
+        assert_eq!(
+            to_ffmetadata(&chapters, Duration::from_secs(3600)),
+            ";FFMETADATA1\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=1800000\ntitle=News\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=1800000\nEND=3600000\ntitle=Film\\; Part\\=1\n"
+        );
+    }
+
+    #[test]
+    fn chapters_file_replaces_the_extension() {
+        assert_eq!(chapters_path("/tmp/evening.mp4"), PathBuf::from("/tmp/evening.ffmetadata"));
+    }
+}
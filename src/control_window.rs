@@ -19,31 +19,47 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 use std::cell::{Cell, RefCell};
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
-use std::process;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::rc::Rc;
-use std::thread;
+use std::time::{Duration, Instant};
 
 use gio;
 use gio::prelude::*;
 use glib;
+use glib::ToVariant;
 //use glib::prelude::*;
 use gtk;
 use gtk::prelude::*;
 
-use tempfile;
+use log::{info, warn};
 
-use gst_mpegts;
+use me_tv::channels_file::{import_channels, read_scan, Conflicts};
+use me_tv::epg::ProgrammeStore;
+use me_tv::scan::read_initial_tuning;
+use me_tv::xmltv;
 
 use crate::about;
-use crate::channels_data::{channels_file_path, get_channels_data, read_channels_data, ChannelData};
+use crate::channel_editor;
+use crate::channel_logos;
+use crate::channel_order::{ChannelOrder, ListedChannel};
+use crate::channels_data::{channels_file_path, channels_playlist, get_channel_names_and_service_ids, get_channels_data, get_copies_data, get_delivery_system, get_provider, is_encrypted, is_hidden, is_radio, read_channels_data, reload_channels_data, ChannelChanges, ChannelData};
 use crate::control_window_button::ControlWindowButton;
+use crate::device_events_dialog;
 use crate::dialogs::display_an_error_dialog;
-use crate::frontend_manager::FrontendId;
+use crate::epg_manager;
+use crate::epg_search_window;
+use crate::epg_window;
+use crate::favourites::{self, ChannelView};
+use crate::frontend_manager::{self, Availability, FrontendHardware, FrontendId, FrontendInfo, ReservationEvent};
+use crate::handover_service;
+use crate::metvcombobox::MeTVComboBoxExt;
 use crate::preferences;
 use crate::preferences_dialog;
+use crate::recent_channels;
 use crate::remote_control::TargettedKeystroke;
+use crate::scan_dialog;
 use crate::transmitter_dialog;
 
 /// A `ControlWindow` is an `gtk::ApplicationWindow` but there is no inheritance
@@ -55,19 +71,61 @@ pub struct ControlWindow {
     frontends_box: gtk::Box,
     label: gtk::Label,
     channels_data_store: gtk::ListStore,
-    pub channels_data_sorter: gtk::TreeModelSort, // Used by ControlWindowButton and FrontendWindow.
+    channels_data_filter: gtk::TreeModelFilter,
+    pub channels_data_sorter: gtk::TreeModelSort, // Used by ControlWindowButton, the preferences dialog, and the EPG window.
+    revealed_channels: Rc<RefCell<HashSet<String>>>,  // Hidden channels listed all the same, having been asked for by name.
     channels_data_loaded: Cell<bool>,
     control_window_buttons: RefCell<Vec<Rc<ControlWindowButton>>>,
-    pub to_epg_manager: std::sync::mpsc::Sender<gst_mpegts::Section>, // Used by ControlWindowButton.
+    dropouts: RefCell<Vec<Dropout>>,
+    notices_box: gtk::Box,
+    disconnections: RefCell<HashMap<FrontendId, Disconnection>>,
+    pub to_epg_manager: std::sync::mpsc::Sender<epg_manager::Input>, // Used by ControlWindowButton.
+    last_channel_resumed: Cell<bool>,
+    resume_waiting: Cell<bool>,
+}
+
+/// A frontend that was being watched when it disappeared, and the channel it was on,
+/// to go back to if the frontend comes back soon enough. USB tuners can drop out for a
+/// moment.
+#[derive(Clone, Debug)]
+struct Dropout {
+    fei: FrontendId,
+    hardware: FrontendHardware,
+    channel: String,
+    gone_at: Instant,
+}
+
+impl Dropout {
+    /// Whether a frontend appearing at `now` is the one that dropped out coming back
+    /// within `window`. The adapter number may change when a USB device reconnects,
+    /// so the same hardware on the same bus with the same frontend number counts too.
+    fn is_reappearance(&self, fei: &FrontendId, hardware: &FrontendHardware, now: Instant, window: Duration) -> bool {
+        let is_same_hardware = self.hardware.name.is_some() && self.hardware.bus_info.is_some()
+            && *hardware == self.hardware && fei.frontend == self.fei.frontend;
+        now.duration_since(self.gone_at) <= window && (*fei == self.fei || is_same_hardware)
+    }
 }
 
 /// All the message types that  can be sent to the ControllerWindow.
 #[derive(Clone, Debug)]
 pub enum Message {
-    FrontendAppeared{fei: FrontendId},
+    ChannelsFileChanged,
+    EpgHarvestChanged{fei: FrontendId, status: Option<String>},
+    FrontendAppeared{fei: FrontendId, info: Option<FrontendInfo>, hardware: FrontendHardware, display_name: String, availability: Availability},
+    FrontendAvailabilityChanged{fei: FrontendId, availability: Availability},
+    FrontendInaccessible{fei: FrontendId, reason: String},
+    FrontendReservationChanged{event: ReservationEvent},
     FrontendDisappeared{fei: FrontendId},
+    FrontendManagerStopped,
+    FrontendRequested{fei: FrontendId},
+    NowNextChanged{service_id: u16},
+    ProgrammeCacheLoaded,
+    ProgrammesChanged{service_id: u16},
     TargettedKeystrokeReceived{tk: TargettedKeystroke},
     UpdatedLogicalChannelNumber{cd: ChannelData},
+    UpdatedEncryption{cd: ChannelData},
+    UpdatedProvider{cd: ChannelData},
+    UpdatedRadio{cd: ChannelData},
 }
 
 impl ControlWindow {
@@ -77,18 +135,11 @@ impl ControlWindow {
     pub fn new(
         application: &gtk::Application,
         message_channel: glib::Receiver<Message>,
-        to_epg_manager: std::sync::mpsc::Sender<gst_mpegts::Section>,
+        to_epg_manager: std::sync::mpsc::Sender<epg_manager::Input>,
     ) -> Rc<ControlWindow> {
         let window = gtk::ApplicationWindow::new(application);
         window.set_title("Me TV");
         window.set_border_width(10);
-        window.connect_delete_event({
-            let a = application.clone();
-            move |_, _| {
-                a.quit();
-                Inhibit(false)
-            }
-        });
         let header_bar = gtk::HeaderBar::new();
         header_bar.set_title(Some("Me TV"));
         header_bar.set_show_close_button(true);
@@ -98,8 +149,46 @@ impl ControlWindow {
         let window_menu = menu_builder.get_object::<gio::Menu>("control_window_menu").unwrap();
         let epg_action = gio::SimpleAction::new("epg", None);
         window.add_action(&epg_action);
+        let search_epg_action = gio::SimpleAction::new("search_epg", None);
+        window.add_action(&search_epg_action);
         let channels_file_action = gio::SimpleAction::new("create_channels_file", None);
         window.add_action(&channels_file_action);
+        let import_action = gio::SimpleAction::new("import_channels", None);
+        window.add_action(&import_action);
+        let edit_channels_action = gio::SimpleAction::new("edit_channels", None);
+        window.add_action(&edit_channels_action);
+        let export_m3u_action = gio::SimpleAction::new("export_m3u", None);
+        window.add_action(&export_m3u_action);
+        let export_xmltv_action = gio::SimpleAction::new("export_xmltv", None);
+        window.add_action(&export_xmltv_action);
+        let epg_statistics_action = gio::SimpleAction::new("epg_statistics", None);
+        window.add_action(&epg_statistics_action);
+        let device_events_action = gio::SimpleAction::new("device_events", None);
+        window.add_action(&device_events_action);
+        let channel_view_action = gio::SimpleAction::new_stateful(
+            "channel_view",
+            Some(glib::VariantTy::new("s").unwrap()),
+            &preferences::get_channel_view().action_target().to_variant(),
+        );
+        window.add_action(&channel_view_action);
+        let channel_order_action = gio::SimpleAction::new_stateful(
+            "channel_order",
+            Some(glib::VariantTy::new("s").unwrap()),
+            &preferences::get_channel_order().action_target().to_variant(),
+        );
+        window.add_action(&channel_order_action);
+        let radio_channels_action = gio::SimpleAction::new_stateful(
+            "radio_channels",
+            None,
+            &preferences::get_show_radio_channels().to_variant(),
+        );
+        window.add_action(&radio_channels_action);
+        let all_sources_action = gio::SimpleAction::new_stateful(
+            "all_sources",
+            None,
+            &preferences::get_show_all_sources().to_variant(),
+        );
+        window.add_action(&all_sources_action);
         let preferences_action = gio::SimpleAction::new("preferences", None);
         window.add_action(&preferences_action);
         let about_action = gio::SimpleAction::new("about", None);
@@ -111,19 +200,41 @@ impl ControlWindow {
         let label = gtk::Label::new(Some("\nNo frontends available.\n"));
         let frontends_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
         main_box.pack_start(&label, true, true, 0);
+        let notices_box = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        main_box.pack_end(&notices_box, false, false, 0);
         window.add(&main_box);
         window.show_all();
         //
-        let channels_data_store = gtk::ListStore::new(&[String::static_type(), String::static_type()]);
-        let channels_data_sorter = gtk::TreeModelSort::new(&channels_data_store);
-        channels_data_sorter.set_default_sort_func(|model, iter_a, iter_b| {
-            // Order by channel number.
-            let a = model.get_value(&iter_a, 0).get::<String>().unwrap().unwrap();
-            let a = a.parse::<u16>().unwrap_or(0);
-            let b = model.get_value(&iter_b, 0).get::<String>().unwrap().unwrap();
-            let b = b.parse::<u16>().unwrap_or(0);
-            a.cmp(&b)
+        // The third column is whether the channel is a favourite, the fourth is where it is
+        // in the channels file, the fifth whether it is encrypted, the sixth whether it is
+        // a radio channel, the seventh its delivery system if known, the eighth whether the
+        // channel editor hides it, the ninth whether it is a copy of a service that another
+        // copy is listed for, the tenth its logo if it has one, the eleventh its provider if
+        // known.
+        let channels_data_store = gtk::ListStore::new(&[
+            String::static_type(), String::static_type(), bool::static_type(), u32::static_type(), bool::static_type(), bool::static_type(), String::static_type(), bool::static_type(),
+            bool::static_type(), gdk_pixbuf::Pixbuf::static_type(), String::static_type(),
+        ]);
+        let channels_data_filter = gtk::TreeModelFilter::new(&channels_data_store, None);
+        let revealed_channels = Rc::new(RefCell::new(HashSet::<String>::new()));
+        channels_data_filter.set_visible_func({
+            let revealed_channels = revealed_channels.clone();
+            move |model, iter| {
+                let is_favourite = model.get_value(iter, 2).get_some::<bool>().unwrap_or(false);
+                let is_encrypted = model.get_value(iter, 4).get_some::<bool>().unwrap_or(false);
+                let is_radio = model.get_value(iter, 5).get_some::<bool>().unwrap_or(false);
+                let is_hidden = model.get_value(iter, 7).get_some::<bool>().unwrap_or(false)
+                    && !model.get_value(iter, 1).get::<String>().unwrap().map_or(false, |name| revealed_channels.borrow().contains(&name));
+                let is_unlisted_copy = model.get_value(iter, 8).get_some::<bool>().unwrap_or(false);
+                preferences::get_channel_view().is_listed(is_favourite, !favourites::favourites().is_empty())
+                    && !(is_encrypted && preferences::get_hide_encrypted_channels())
+                    && !(is_radio && !preferences::get_show_radio_channels())
+                    && !is_hidden
+                    && !is_unlisted_copy
+            }
         });
+        let channels_data_sorter = gtk::TreeModelSort::new(&channels_data_filter);
+        channels_data_sorter.set_default_sort_func(by_favourite_then_order);
         //
         // TODO How to trigger the per-column sorting rather than default sorting.
         //
@@ -148,20 +259,46 @@ impl ControlWindow {
             frontends_box,
             label,
             channels_data_store,
+            channels_data_filter,
             channels_data_sorter,
+            revealed_channels,
             channels_data_loaded: Cell::new(false),
             control_window_buttons: RefCell::new(Vec::new()),
+            dropouts: RefCell::new(Vec::new()),
+            notices_box,
+            disconnections: RefCell::new(HashMap::new()),
             to_epg_manager,
+            last_channel_resumed: Cell::new(false),
+            resume_waiting: Cell::new(false),
+        });
+        control_window.window.connect_delete_event({
+            let a = application.clone();
+            let c_w = control_window.clone();
+            move |_, _| {
+                remember_watched_channel(&c_w);
+                a.quit();
+                Inhibit(false)
+            }
         });
         control_window.update_channels_store();
         epg_action.connect_activate({
             let c_w = control_window.clone();
             move |_, _| {
-                display_an_error_dialog(Some(&c_w.window), if c_w.control_window_buttons.borrow().is_empty() {
-                    "No frontends, so no EPG."
+                if c_w.is_channels_store_loaded() {
+                    epg_window::present(&c_w);
+                } else {
+                    display_an_error_dialog(Some(&c_w.window), "No channels file, so no EPG.");
+                }
+            }
+        });
+        search_epg_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| {
+                if c_w.is_channels_store_loaded() {
+                    epg_search_window::present(&c_w);
                 } else {
-                    "Should display the EPG window."  // TODO Get the EPG window working.
-                });
+                    display_an_error_dialog(Some(&c_w.window), "No channels file, so no EPG.");
+                }
             }
         });
         channels_file_action.connect_activate({
@@ -174,6 +311,68 @@ impl ControlWindow {
                 }
             }
         });
+        import_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| import_scanned_channels(&c_w)
+        });
+        edit_channels_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| channel_editor::present(&c_w)
+        });
+        export_m3u_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| export_m3u(&c_w)
+        });
+        export_xmltv_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| export_xmltv(&c_w)
+        });
+        epg_statistics_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| show_epg_statistics(&c_w)
+        });
+        device_events_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| device_events_dialog::present(Some(&c_w.window))
+        });
+        channel_view_action.connect_activate({
+            let c_w = control_window.clone();
+            move |action, parameter| {
+                if let Some(view) = parameter.and_then(|p| p.get::<String>()).and_then(|target| ChannelView::from_action_target(&target)) {
+                    action.set_state(&view.action_target().to_variant());
+                    preferences::set_channel_view(view, true);
+                    c_w.refresh_channels_view();
+                }
+            }
+        });
+        channel_order_action.connect_activate({
+            let c_w = control_window.clone();
+            move |action, parameter| {
+                if let Some(order) = parameter.and_then(|p| p.get::<String>()).and_then(|target| ChannelOrder::from_action_target(&target)) {
+                    action.set_state(&order.action_target().to_variant());
+                    preferences::set_channel_order(order, true);
+                    c_w.refresh_channels_view();
+                }
+            }
+        });
+        radio_channels_action.connect_activate({
+            let c_w = control_window.clone();
+            move |action, _| {
+                let is_shown = !action.get_state().and_then(|state| state.get::<bool>()).unwrap_or(true);
+                action.set_state(&is_shown.to_variant());
+                preferences::set_show_radio_channels(is_shown, true);
+                c_w.refresh_channels_view();
+            }
+        });
+        all_sources_action.connect_activate({
+            let c_w = control_window.clone();
+            move |action, _| {
+                let is_shown = !action.get_state().and_then(|state| state.get::<bool>()).unwrap_or(false);
+                action.set_state(&is_shown.to_variant());
+                preferences::set_show_all_sources(is_shown, true);
+                c_w.refresh_channels_view();
+            }
+        });
         preferences_action.connect_activate({
             let c_w = control_window.clone();
             move |_, _| preferences_dialog::present(&c_w)
@@ -186,10 +385,23 @@ impl ControlWindow {
             let c_w = control_window.clone();
             message_channel.attach(None, move |message| {
                 match message {
-                    Message::FrontendAppeared{fei} => add_frontend(&c_w, &fei),
+                    Message::ChannelsFileChanged => report_channel_changes(&c_w, reload_channels_file(&c_w)),
+                    Message::FrontendAppeared{fei, info, hardware, display_name, availability} => add_frontend(&c_w, &fei, info, &hardware, &display_name, availability),
+                    Message::FrontendAvailabilityChanged{fei, availability} => change_frontend_availability(&c_w, &fei, availability),
+                    Message::FrontendInaccessible{fei, reason} => report_inaccessible_frontend(&c_w, &fei, &reason),
+                    Message::EpgHarvestChanged{fei, status} => change_epg_harvest(&c_w, &fei, status.as_deref()),
+                    Message::FrontendReservationChanged{event} => change_frontend_reservation(&c_w, &event),
                     Message::FrontendDisappeared{fei} => remove_frontend(&c_w, &fei),
+                    Message::FrontendManagerStopped => info!("The frontend manager has stopped, frontends appearing and disappearing will not be noticed."),
+                    Message::FrontendRequested{fei} => hand_over_frontend(&c_w, &fei),
+                    Message::NowNextChanged{service_id} => update_now_next(&c_w, service_id),
+                    Message::ProgrammeCacheLoaded => epg_window::cache_loaded(),
+                    Message::ProgrammesChanged{service_id} => epg_window::programmes_changed(service_id),
                     Message::TargettedKeystrokeReceived{tk} => process_targetted_keystroke(&c_w, &tk),
                     Message::UpdatedLogicalChannelNumber {cd} => add_logical_channel_number(&c_w, &cd),
+                    Message::UpdatedEncryption {cd} => set_channel_encryption(&c_w, &cd),
+                    Message::UpdatedProvider {cd} => set_channel_provider(&c_w, &cd),
+                    Message::UpdatedRadio {cd} => set_channel_radio(&c_w, &cd),
                 }
                 Continue(true)
             });
@@ -199,22 +411,92 @@ impl ControlWindow {
 
     /// Transfer the list of channel names held by the control window into the selector box and set the default.
     pub fn update_channels_store(&self) {
+        self.fill_channels_store();
+        for button in self.control_window_buttons.borrow().iter() {
+            button.reset_active_channel();
+        }
+    }
+
+    /// Transfer the list of channel names held by the control window into the selector box.
+    fn fill_channels_store(&self) {
         self.channels_data_store.clear();
         match get_channels_data() {
             Some(channel_data) => {
-                for (number, name) in channel_data {
+                let names_and_service_ids = get_channel_names_and_service_ids().unwrap_or_default();
+                favourites::reconcile_with(&names_and_service_ids);
+                recent_channels::reconcile_with(&names_and_service_ids);
+                let service_ids = names_and_service_ids.into_iter().collect::<HashMap<_, _>>();
+                let copies = get_copies_data();
+                for (position, (number, name)) in channel_data.into_iter().enumerate() {
                     let channel_number = if number == 0 { "".to_string() } else { number.to_string() };
-                    self.channels_data_store.insert_with_values(None, &[0, 1], &[&channel_number, &name]);
+                    let is_favourite = favourites::is_favourite(&name);
+                    let delivery_system = get_delivery_system(&name).map_or_else(String::new, |d| d.to_string());
+                    let is_unlisted_copy = copies.get(position).map_or(false, |(_, _, listed)| !listed);
+                    let logo = channel_logos::get_logo(&name, service_ids.get(&name).cloned(), channel_logos::SELECTOR_SIZE);
+                    self.channels_data_store.insert_with_values(
+                        None,
+                        &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+                        &[&channel_number, &name, &is_favourite, &(position as u32), &is_encrypted(&name), &is_radio(&name), &delivery_system, &is_hidden(&name), &is_unlisted_copy, &logo, &get_provider(&name).unwrap_or_default()],
+                    );
                 };
                 self.channels_data_loaded.set(true);
             },
             None => {
-                self.channels_data_store.insert_with_values(None, &[0, 1], &[&"", &"No channels file."]);
+                self.channels_data_store.insert_with_values(None, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10], &[&"", &"No channels file.", &false, &0u32, &false, &false, &"", &false, &false, &None::<gdk_pixbuf::Pixbuf>, &""]);
                 self.channels_data_loaded.set(false);
             }
         }
         for button in self.control_window_buttons.borrow().iter() {
-            button.reset_active_channel();
+            button.update_favourite_button();
+        }
+    }
+
+    /// Show the channel logos in the logos directory, it having changed.
+    pub fn refresh_logos(&self) {  // Used in preferences_dialog.rs
+        let service_ids = get_channel_names_and_service_ids().unwrap_or_default().into_iter().collect::<HashMap<_, _>>();
+        if let Some(iter) = self.channels_data_store.get_iter_first() {
+            loop {
+                if let Some(name) = self.channels_data_store.get_value(&iter, 1).get::<String>().unwrap() {
+                    let logo = channel_logos::get_logo(&name, service_ids.get(&name).cloned(), channel_logos::SELECTOR_SIZE);
+                    self.channels_data_store.set_value(&iter, 9, &logo.to_value());
+                }
+                if !self.channels_data_store.iter_next(&iter) { break; }
+            }
+        }
+    }
+
+    /// List the hidden channel `name` in the selectors all the same, for the rest of the
+    /// session, it having been asked for by name or being watched when it was hidden.
+    pub fn reveal_channel(&self, name: &str) {  // Used in control_window_button.rs
+        if self.revealed_channels.borrow_mut().insert(name.to_string()) {
+            self.refresh_channels_view();
+        }
+    }
+
+    /// List the channels in the selectors as the favourites and the channel view now
+    /// say, the frontends staying on the channels they are on.
+    pub fn refresh_channels_view(&self) {  // Used in control_window_button.rs when a favourite is toggled.
+        let buttons = self.control_window_buttons.borrow().clone();
+        let channels = buttons.iter().map(|c_w_b| c_w_b.channel_selector.get_active_text()).collect::<Vec<_>>();
+        if let Some(iter) = self.channels_data_store.get_iter_first() {
+            loop {
+                if let Some(name) = self.channels_data_store.get_value(&iter, 1).get::<String>().unwrap() {
+                    self.channels_data_store.set_value(&iter, 2, &favourites::is_favourite(&name).to_value());
+                }
+                if !self.channels_data_store.iter_next(&iter) { break; }
+            }
+        }
+        self.channels_data_filter.refilter();
+        self.channels_data_sorter.set_default_sort_func(by_favourite_then_order);
+        for (c_w_b, channel) in buttons.iter().zip(channels) {
+            c_w_b.channels_model.refilter();
+            match channel {
+                Some(channel) if c_w_b.restore_channel(&channel) => {},
+                // The channel being watched is not shown but it is still being watched.
+                Some(_) if c_w_b.frontend_button.get_active() => {},
+                _ => c_w_b.reset_active_channel(),
+            }
+            c_w_b.update_favourite_button();
         }
     }
 
@@ -226,88 +508,314 @@ impl ControlWindow {
 ///
 /// If the transmitter files are not present this function will do nothing.
 ///
-/// Currently try to use dvbv5-scan to create the file, or if it isn't present, try dvbscan or w_scan.
+/// The transponders of the transmitter file the user chooses are scanned with dvbv5-scan,
+/// on the frontends not in use, in the scan dialog, which offers to save the channels found.
 fn ensure_channel_file_present(control_window: &Rc<ControlWindow>) {
-    match  transmitter_dialog::present(Some(&control_window.window)) {
-        Some(path_to_transmitter_file) => {
-            //  TODO Turn this into a dialog that follows the GNOME HIG. Probably best to create a custom dialog.
-            let start_dialog = gtk::MessageDialog::new(
-                Some(&control_window.window),
-                gtk::DialogFlags::MODAL,
-                gtk::MessageType::Info,
-                gtk::ButtonsType::OkCancel,   // TODO This button type is discourage by the GNOME HIG, incorrect button placements.
-                &format!("Run:\n\n    dvbv5-scan {}\n\n?\n\nYou need to have already closed all open channel viewers for this to work.", path_to_transmitter_file.to_str().unwrap()),
-            );
-            let response = gtk::ResponseType::from(start_dialog.run());
-            unsafe { start_dialog.destroy(); }
-            if response== gtk::ResponseType::Ok {
-                let wait_dialog = gtk::MessageDialog::new(
-                    Some(&control_window.window),
-                    gtk::DialogFlags::MODAL,
-                    gtk::MessageType::Info,
-                    gtk::ButtonsType::None,
-                    &format!("Running:\n\n    dvbv5-scan {}\n\nThis may take a while.", path_to_transmitter_file.to_str().unwrap())
-                );
-                wait_dialog.show_all();
-                let (sender, receiver) = glib::MainContext::channel::<bool>(glib::PRIORITY_DEFAULT);
-                {
-                    let c_w = control_window.clone();
-                    let w_d = wait_dialog.clone();
-                    receiver.attach(None, move |result| {
-                        unsafe { w_d.destroy(); }
-                        if result {
-                            c_w.update_channels_store();
-                        } else {
-                            display_an_error_dialog(Some(&c_w.window), "dvbv5-scan failed to generate a file.");
-                        }
-                        Continue(false)
-                    });
-                };
-                thread::spawn({
-                    let p_t_t_f = path_to_transmitter_file.clone();
-                    move || {
-                        let mut temporary_file = tempfile::NamedTempFile::new().expect("Could not create a temporary file.");
-                        match process::Command::new("dvbv5-scan")
-                            .arg("-o")
-                            .arg(&temporary_file.path())
-                            .arg(&*p_t_t_f)
-                            .output() {
-                            Ok(_) => {
-                                let mut destination = OpenOptions::new()
-                                    .write(true)
-                                    .truncate(true)
-                                    .create(true)
-                                    .open(channels_file_path())
-                                    .expect("Could not open channels file.");
-                                let mut buffer = String::new();
-                                temporary_file.read_to_string(&mut buffer).expect("Could not read temporary channels file.");
-                                destination.write(&buffer.as_bytes()).expect("Could not write channels file.");
-                                if !read_channels_data() {
-                                    panic!("Could not read the file that was just written.");
-                                }
-                                sender.send(true).expect("Could not send result for some reason.")
-                            },
-                            Err(error) => sender.send(false).expect(&format!("Could not send result of error:{}", error)),
-                        };
-                    }
-                });
+    let path_to_transmitter_file = match transmitter_dialog::present(Some(&control_window.window)) {
+        Some(path) => path,
+        None => return,  // User already informed of problem.
+    };
+    let transponders = match read_initial_tuning(&path_to_transmitter_file) {
+        Ok((transponders, warnings)) => {
+            for warning in &warnings {
+                warn!("{}: {}, the transponder is not scanned.", path_to_transmitter_file.display(), warning);
             }
+            transponders
+        },
+        Err(e) => {
+            display_an_error_dialog(Some(&control_window.window), &format!("Could not read {}: {}", path_to_transmitter_file.display(), e));
+            return;
+        },
+    };
+    if transponders.is_empty() {
+        display_an_error_dialog(Some(&control_window.window), &format!("There are no transponders in {} to scan.", path_to_transmitter_file.display()));
+        return;
+    }
+    let frontends = control_window.control_window_buttons.borrow().iter().map(|button| button.frontend_id.clone()).collect::<Vec<_>>();
+    scan_dialog::present(control_window, transponders, &frontends);
+}
+
+/// Add the channels of the scan results of w_scan2 or dvbv5-scan, or a VDR channels.conf
+/// file, the user chooses to the channels file, and say what was added, updated, and
+/// skipped.
+fn import_scanned_channels(control_window: &Rc<ControlWindow>) {
+    let chooser = gtk::FileChooserDialog::with_buttons(
+        Some("Import channels"),
+        Some(&control_window.window),
+        gtk::FileChooserAction::Open,
+        &[("_Cancel", gtk::ResponseType::Cancel), ("_Import", gtk::ResponseType::Accept)],
+    );
+    let replace = gtk::CheckButton::with_mnemonic("_Replace the tuning of channels already in the channels file");
+    chooser.set_extra_widget(&replace);
+    let response = gtk::ResponseType::from(chooser.run());
+    let scan_path = chooser.get_filename();
+    let conflicts = if replace.get_active() { Conflicts::Replace } else { Conflicts::Skip };
+    unsafe { chooser.destroy(); }
+    let scan_path = match (response, scan_path) {
+        (gtk::ResponseType::Accept, Some(path)) => path,
+        _ => return,
+    };
+    let channels = match read_scan(&scan_path) {
+        Ok(channels) => channels,
+        Err(e) => {
+            display_an_error_dialog(Some(&control_window.window), &format!("Could not read {}: {}", scan_path.display(), e));
+            return;
+        },
+    };
+    for warning in &channels.warnings {
+        warn!("{}: {}, the channel is not added.", scan_path.display(), warning);
+    }
+    let channels_file = channels_file_path();
+    let import = match import_channels(&channels_file, &channels.channels, conflicts) {
+        Ok(import) => import,
+        Err(e) => {
+            display_an_error_dialog(Some(&control_window.window), &format!("Could not write the channels file {}: {}", channels_file.display(), e));
+            return;
+        },
+    };
+    if !import.added.is_empty() || !import.updated.is_empty() {
+        read_channels_data();
+        control_window.update_channels_store();
+    }
+    let mut message = format!("{} channels added to {}, {} updated, {} skipped.", import.added.len(), channels_file.display(), import.updated.len(), import.skipped.len());
+    if !import.updated.is_empty() {
+        message.push_str(&format!("\n\nNow tuned as imported: {}.", import.updated.join(", ")));
+    }
+    if !import.skipped.is_empty() {
+        message.push_str(&format!("\n\nAlready in the channels file, so left as they are: {}.", import.skipped.join(", ")));
+    }
+    if !import.already_present.is_empty() {
+        message.push_str(&format!("\n\nNot added, other channels in the channels file have their names: {}.", import.already_present.join(", ")));
+    }
+    if !import.not_zap.is_empty() {
+        message.push_str(&format!("\n\nNot added, the zap format of the channels file cannot give them: {}.", import.not_zap.join(", ")));
+    }
+    if !channels.warnings.is_empty() {
+        message.push_str(&format!("\n\n{} lines of {} could not be read, the log says why.", channels.warnings.len(), scan_path.display()));
+    }
+    let dialog = gtk::MessageDialog::new(
+        Some(&control_window.window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Info,
+        gtk::ButtonsType::Ok,
+        &message,
+    );
+    dialog.run();
+    unsafe { dialog.destroy(); }
+}
+
+/// Write the channels as an extended M3U playlist to a file the user chooses, for other
+/// players to open.
+fn export_m3u(control_window: &Rc<ControlWindow>) {
+    let playlist = match channels_playlist() {
+        Some(playlist) => playlist,
+        None => {
+            display_an_error_dialog(Some(&control_window.window), "No channels file, so no channels to export.");
+            return;
+        },
+    };
+    let chooser = gtk::FileChooserDialog::with_buttons(
+        Some("Export channels as M3U"),
+        Some(&control_window.window),
+        gtk::FileChooserAction::Save,
+        &[("_Cancel", gtk::ResponseType::Cancel), ("_Export", gtk::ResponseType::Accept)],
+    );
+    chooser.set_do_overwrite_confirmation(true);
+    chooser.set_current_name("channels.m3u");
+    let response = gtk::ResponseType::from(chooser.run());
+    let path = chooser.get_filename();
+    unsafe { chooser.destroy(); }
+    let path = match (response, path) {
+        (gtk::ResponseType::Accept, Some(path)) => path,
+        _ => return,
+    };
+    if let Err(e) = fs::write(&path, playlist) {
+        display_an_error_dialog(Some(&control_window.window), &format!("Could not write {}: {}", path.display(), e));
+    }
+}
+
+/// Write the programme guide as XMLTV listings to a file the user chooses, for other
+/// programme guides to read.
+fn export_xmltv(control_window: &Rc<ControlWindow>) {
+    let channels = match get_channel_names_and_service_ids() {
+        Some(channels) => channels,
+        None => {
+            display_an_error_dialog(Some(&control_window.window), "No channels file, so no programme guide to export.");
+            return;
+        },
+    };
+    let listings = epg_manager::with_programmes(|store| {
+        let programmes = store.service_ids().into_iter()
+            .map(|service_id| (service_id, store.programmes(service_id).to_vec()))
+            .collect::<HashMap<_, _>>();
+        xmltv::export_xmltv(&channels, &programmes)
+    });
+    let chooser = gtk::FileChooserDialog::with_buttons(
+        Some("Export the EPG as XMLTV"),
+        Some(&control_window.window),
+        gtk::FileChooserAction::Save,
+        &[("_Cancel", gtk::ResponseType::Cancel), ("_Export", gtk::ResponseType::Accept)],
+    );
+    chooser.set_do_overwrite_confirmation(true);
+    chooser.set_current_name("epg.xml");
+    let response = gtk::ResponseType::from(chooser.run());
+    let path = chooser.get_filename();
+    unsafe { chooser.destroy(); }
+    let path = match (response, path) {
+        (gtk::ResponseType::Accept, Some(path)) => path,
+        _ => return,
+    };
+    if let Err(e) = fs::write(&path, listings) {
+        display_an_error_dialog(Some(&control_window.window), &format!("Could not write {}: {}", path.display(), e));
+    }
+}
+
+/// Show how many programmes the programme guide has, and about how much memory they
+/// take, with how many the preferences say to keep, so as to see it being kept in bounds.
+fn show_epg_statistics(control_window: &Rc<ControlWindow>) {
+    let stats = epg_manager::with_programmes(ProgrammeStore::stats);
+    let mut message = format!(
+        "The programme guide has {}.\n\nIt keeps programmes for up to {} days ahead, and at most {} of them. {} programmes to be recorded or reminded of are kept however full it gets.",
+        stats, preferences::get_epg_days_ahead(), preferences::get_epg_max_programmes(), stats.kept,
+    );
+    if stats.is_full {
+        message.push_str("\n\nIt is full, so programmes that have ended, and those furthest ahead, are being left out.");
+    }
+    let dialog = gtk::MessageDialog::new(Some(&control_window.window), gtk::DialogFlags::MODAL, gtk::MessageType::Info, gtk::ButtonsType::Ok, &message);
+    dialog.set_title("EPG statistics");
+    dialog.run();
+    unsafe { dialog.destroy(); }
+}
+
+/// Order channels, favourites first if the channel view says so, then television before
+/// radio, then in the channel order.
+fn by_favourite_then_order(model: &gtk::TreeModel, iter_a: &gtk::TreeIter, iter_b: &gtk::TreeIter) -> Ordering {
+    let number = |iter| model.get_value(iter, 0).get::<String>().unwrap().unwrap().parse::<u16>().unwrap_or(0);
+    let name = |iter| model.get_value(iter, 1).get::<String>().unwrap().unwrap_or_default();
+    let is_favourite = |iter| model.get_value(iter, 2).get_some::<bool>().unwrap_or(false);
+    let position = |iter| model.get_value(iter, 3).get_some::<u32>().unwrap_or(0);
+    let is_radio = |iter| model.get_value(iter, 5).get_some::<bool>().unwrap_or(false);
+    let provider = |iter| model.get_value(iter, 10).get::<String>().unwrap().unwrap_or_default();
+    let (name_a, name_b) = (name(iter_a), name(iter_b));
+    let (provider_a, provider_b) = (provider(iter_a), provider(iter_b));
+    preferences::get_channel_view().ordering(is_favourite(iter_a), is_favourite(iter_b))
+        .then(is_radio(iter_a).cmp(&is_radio(iter_b)))
+        .then_with(|| preferences::get_channel_order().compare(
+            &ListedChannel { number: number(iter_a), name: &name_a, provider: &provider_a, position: position(iter_a) },
+            &ListedChannel { number: number(iter_b), name: &name_b, provider: &provider_b, position: position(iter_b) },
+        ))
+}
+
+/// Reread the channels files after one has changed, or the further ones of the preferences
+/// have, the frontends staying on the channels they are on if they are still in them,
+/// and return what changed. If the default one cannot be read, or none of it can be, the
+/// channels list is left as it was.
+pub fn reload_channels_file(control_window: &Rc<ControlWindow>) -> Option<ChannelChanges> {  // Used in preferences_dialog.rs and channel_editor.rs.
+    match reload_channels_data() {
+        Ok(changes) => {
+            relist_renamed_channels(control_window, &changes.renamed);
+            Some(changes)
+        },
+        Err(reason) => {
+            warn!("The channels files have changed but {}, keeping the channels list as it was.", reason);
+            None
         },
-        None => ()  // User already informed of problem.
     }
 }
 
+/// Tell the user which channels were renamed, added, and lost when the channels files
+/// were reread, if any were, as after a rescan.
+fn report_channel_changes(control_window: &ControlWindow, changes: Option<ChannelChanges>) {
+    let changes = match changes {
+        Some(changes) if !changes.is_empty() => changes,
+        _ => return,
+    };
+    let mut message = "The channels files have changed.".to_string();
+    if !changes.renamed.is_empty() {
+        let renamed = changes.renamed.iter().map(|(name, new_name)| format!("{} is now {}", name, new_name)).collect::<Vec<_>>();
+        message.push_str(&format!("\n\nRenamed, the favourites and settings following them: {}.", renamed.join(", ")));
+    }
+    if !changes.added.is_empty() {
+        message.push_str(&format!("\n\nAdded: {}.", changes.added.join(", ")));
+    }
+    if !changes.lost.is_empty() {
+        message.push_str(&format!("\n\nNo longer there: {}.", changes.lost.join(", ")));
+    }
+    let dialog = gtk::MessageDialog::new(
+        Some(&control_window.window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Info,
+        gtk::ButtonsType::Ok,
+        &message,
+    );
+    dialog.run();
+    unsafe { dialog.destroy(); }
+}
+
+/// List the channels as the channels data now has them, the frontends staying on the
+/// channels they are on if they are still there.
+pub fn relist_channels(control_window: &ControlWindow) {  // Used in channel_editor.rs.
+    relist_renamed_channels(control_window, &[]);
+}
+
+/// List the channels as the channels data now has them, the frontends staying on the
+/// channels they are on, by their new names if they have been `renamed`, if they are
+/// still there.
+fn relist_renamed_channels(control_window: &ControlWindow, renamed: &[(String, String)]) {
+    let buttons = control_window.control_window_buttons.borrow().clone();
+    let channels = buttons.iter()
+        .map(|c_w_b| c_w_b.channel_selector.get_active_text().map(|channel| {
+            renamed.iter().find(|(name, _)| name.as_str() == channel.as_str()).map_or(channel.to_string(), |(_, new_name)| new_name.clone())
+        }))
+        .collect::<Vec<_>>();
+    control_window.fill_channels_store();
+    let names = get_channel_names_and_service_ids().unwrap_or_default();
+    for (c_w_b, channel) in buttons.iter().zip(channels) {
+        match channel {
+            Some(channel) if c_w_b.restore_channel(&channel) => {},
+            // The channel being watched is not shown but it is still being watched.
+            Some(channel) if c_w_b.frontend_button.get_active() && names.iter().any(|(name, _)| *name == channel) => {},
+            Some(channel) if c_w_b.frontend_button.get_active() => show_notice(control_window, &format!(
+                "{}, being watched on {}, is no longer in the channels file.",
+                channel,
+                frontend_manager::frontend_display_name(&c_w_b.frontend_id),
+            )),
+            _ => c_w_b.reset_active_channel(),
+        }
+    }
+}
+
+/// Tell the user something without getting in the way, until they close it.
+fn show_notice(control_window: &ControlWindow, text: &str) {
+    let bar = gtk::InfoBar::new();
+    bar.set_message_type(gtk::MessageType::Info);
+    bar.set_show_close_button(true);
+    let label = gtk::Label::new(Some(text));
+    label.set_line_wrap(true);
+    bar.get_content_area().add(&label);
+    bar.connect_response({
+        let notices_box = control_window.notices_box.clone();
+        move |bar, _| notices_box.remove(bar)
+    });
+    control_window.notices_box.pack_start(&bar, false, false, 0);
+    bar.show_all();
+}
+
 /// Add a new frontend to this control window.
-fn add_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
+fn add_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId, info: Option<FrontendInfo>, hardware: &FrontendHardware, display_name: &str, availability: Availability) {
     if control_window.main_box.get_children()[0] == control_window.label.clone().upcast::<gtk::Widget>() {
         control_window.main_box.remove(&control_window.label);
         control_window.main_box.pack_start(&control_window.frontends_box, true, true, 0);
     }
-    let control_window_button = ControlWindowButton::new(control_window, fei);
+    let control_window_button = ControlWindowButton::new(control_window, fei, info, hardware, display_name);
+    control_window_button.set_availability(availability);
     let c_w_b = control_window_button.clone();
     control_window.frontends_box.pack_start(&control_window_button.widget, true, true, 0);
     control_window.control_window_buttons.borrow_mut().push(control_window_button);
     control_window.window.show_all();
+    if reconnect_after_dropout(control_window, &c_w_b) {
+        return;
+    }
     // TODO Why is the FrontendWindow positioned before the ControlWindow when showing  a default channel.
     let first_adapter_number = FrontendId{adapter: 0, frontend: 0};
     if *fei == first_adapter_number {
@@ -319,11 +827,11 @@ fn add_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
                             display_an_error_dialog(Some(&c_w_b.control_window.window), "The channel is the empty string and cannot be tuned to.");
                         } else {
                             // TODO What to do if None is returned?
-                            if let Some(iterator) = control_window.channels_data_sorter.get_iter_first() {
+                            if let Some(iterator) = c_w_b.channels_model.get_iter_first() {
                                 loop {
-                                    if let Some(channel_name) = control_window.channels_data_sorter.get_value(&iterator, 1).get::<String>().unwrap() {
+                                    if let Some(channel_name) = c_w_b.channels_model.get_value(&iterator, 1).get::<String>().unwrap() {
                                         if target_channel_name == channel_name {
-                                            match control_window.channels_data_sorter.get_path(&iterator) {
+                                            match c_w_b.channels_model.get_path(&iterator) {
                                                 Some(mut tree_path) => {
                                                     let index = tree_path.get_indices_with_depth()[0];
                                                     if index < 0 { panic!("index cannot be a negative integer"); }
@@ -335,7 +843,7 @@ fn add_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
                                             break;
                                         }
                                     }
-                                    if !control_window.channels_data_sorter.iter_next(&iterator) {
+                                    if !c_w_b.channels_model.iter_next(&iterator) {
                                         display_an_error_dialog(Some(&c_w_b.control_window.window), &format!("The channel {} could not be found for immediate TV display.", target_channel_name));
                                         break;
                                     }
@@ -349,13 +857,112 @@ fn add_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
             tune_to_channel(if preferences::get_use_last_channel() { preferences::get_last_channel() } else { preferences::get_default_channel() });
         }
     }
+    if !preferences::get_immediate_tv() && preferences::get_resume_last_channel() {
+        resume_last_channel(control_window, &c_w_b);
+    }
+}
+
+/// How long to wait, once a frontend that can tune the channel last watched has appeared,
+/// for the frontend it was watched on to appear as well.
+const RESUME_WAIT: Duration = Duration::from_secs(3);
+
+/// The channel last watched and the frontend it was watched on, if there is one.
+fn last_tuning() -> Option<(String, Option<FrontendId>)> {
+    let channel = preferences::get_last_channel().filter(|channel| !channel.is_empty())?;
+    let fei = preferences::get_last_frontend().and_then(|fei| fei.parse::<FrontendId>().ok());
+    Some((channel, fei))
+}
+
+/// On start up, tune the channel last watched on the frontend it was watched on. A frontend
+/// that cannot tune the channel is passed over; if another that can appears first, the one
+/// it was watched on is waited for, for a while, before watching on the first that can. If
+/// there is nothing to tune, or nothing to tune it on, nothing is done other than say why.
+fn resume_last_channel(control_window: &Rc<ControlWindow>, control_window_button: &Rc<ControlWindowButton>) {
+    if control_window.last_channel_resumed.get() { return; }
+    let name = frontend_manager::frontend_display_name(&control_window_button.frontend_id);
+    let (channel, fei) = match last_tuning() {
+        Some(tuning) => tuning,
+        None => {
+            info!("No channel has been watched, so there is none to tune on start up.");
+            control_window.last_channel_resumed.set(true);
+            return;
+        },
+    };
+    if !get_channel_names_and_service_ids().unwrap_or_default().iter().any(|(n, _)| *n == channel) {
+        info!("{}, the channel last watched, is no longer in the channels list, so is not tuned on start up.", channel);
+        control_window.last_channel_resumed.set(true);
+        return;
+    }
+    if let Some(reason) = frontend_manager::channel_incompatibility(&control_window_button.frontend_id, &channel) {
+        info!("{} cannot tune {}, the channel last watched: {}", name, channel, reason);
+        return;
+    }
+    match fei {
+        Some(fei) if fei != control_window_button.frontend_id => {
+            if control_window.resume_waiting.replace(true) { return; }
+            info!("{} can tune {}, the channel last watched, waiting for {} it was watched on.", name, channel, frontend_manager::frontend_display_name(&fei));
+            glib::timeout_add_seconds_local(RESUME_WAIT.as_secs() as u32, {
+                let c_w = control_window.clone();
+                move || {
+                    if !c_w.last_channel_resumed.replace(true) {
+                        let control_window_buttons = c_w.control_window_buttons.borrow().clone();
+                        let target = find_button(&c_w, &fei).filter(|cwb| frontend_manager::channel_incompatibility(&cwb.frontend_id, &channel).is_none())
+                            .or_else(|| alternative_frontend(&control_window_buttons, &fei, &channel).and_then(|alternative| find_button(&c_w, &alternative)));
+                        match target {
+                            Some(cwb) => { watch_channel(&cwb, &channel); },
+                            None => info!("No frontend that can tune {}, the channel last watched, is free, so it is not tuned on start up.", channel),
+                        }
+                    }
+                    glib::Continue(false)
+                }
+            });
+        },
+        _ => {
+            control_window.last_channel_resumed.set(true);
+            watch_channel(control_window_button, &channel);
+        },
+    }
+}
+
+/// On quit, remember the channel being watched, on the frontend it was last remembered
+/// being watched on if that is still being watched, so as to tune it on start up.
+fn remember_watched_channel(control_window: &ControlWindow) {
+    let remembered = last_tuning().and_then(|(_, fei)| fei);
+    let control_window_buttons = control_window.control_window_buttons.borrow().clone();
+    let watching = control_window_buttons.iter().filter(|cwb| cwb.frontend_button.get_active()).collect::<Vec<_>>();
+    if let Some(cwb) = watching.iter().find(|cwb| Some(&cwb.frontend_id) == remembered.as_ref()).or_else(|| watching.first()) {
+        if let Some(channel) = cwb.channel_selector.get_active_text() {
+            cwb.remember_channel(&channel);
+        }
+    }
 }
 
-/// Remove the frontend from this control window.
+/// Remove the frontend from this control window. If it was being watched, stop that
+/// and tell the user, remembering the channel to go back to if the frontend comes back.
 fn remove_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
+    info!("{} has gone.", frontend_manager::frontend_display_name(fei));
     let mut remove_index = 0;
-    for (index, control_window_button) in control_window.control_window_buttons.borrow().iter().enumerate() {
+    // Toggling buttons runs handlers, so do not hold the borrow while doing it.
+    let control_window_buttons = control_window.control_window_buttons.borrow().clone();
+    for (index, control_window_button) in control_window_buttons.iter().enumerate() {
         if control_window_button.frontend_id == *fei {
+            if control_window_button.frontend_button.get_active() {
+                let channel = control_window_button.channel_selector.get_active_text();
+                control_window_button.frontend_button.set_active(false);
+                if let Some(channel) = channel {
+                    let is_reconnecting = preferences::get_reconnect_after_dropout();
+                    let alternative = alternative_frontend(&control_window_buttons, fei, &channel);
+                    report_disconnection(control_window, fei, &channel, alternative, is_reconnecting);
+                    if is_reconnecting {
+                        await_reconnection(control_window, Dropout {
+                            fei: fei.clone(),
+                            hardware: control_window_button.hardware.clone(),
+                            channel,
+                            gone_at: Instant::now(),
+                        });
+                    }
+                }
+            }
             control_window.frontends_box.remove(&control_window_button.widget);
             remove_index = index;
             break;
@@ -369,6 +976,271 @@ fn remove_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
     control_window.window.show_all();
 }
 
+/// How long after a watched frontend disappears that it coming back is reconnected to.
+fn reconnect_window() -> Duration {
+    Duration::from_secs(preferences::get_reconnect_window() as u64)
+}
+
+/// The response of the infobar for a disconnected frontend asking to try the frontend
+/// again.
+const RETRY: gtk::ResponseType = gtk::ResponseType::Other(1);
+
+/// The response of the infobar for a disconnected frontend asking to watch the channel
+/// on another frontend.
+const SWITCH: gtk::ResponseType = gtk::ResponseType::Other(2);
+
+/// The infobar telling the user a frontend being watched has been disconnected.
+#[derive(Clone, Debug)]
+struct Disconnection {
+    bar: gtk::InfoBar,
+    label: gtk::Label,
+}
+
+/// The button of a frontend that is present.
+fn find_button(control_window: &ControlWindow, fei: &FrontendId) -> Option<Rc<ControlWindowButton>> {
+    control_window.control_window_buttons.borrow().iter().find(|cwb| cwb.frontend_id == *fei).cloned()
+}
+
+/// A frontend, other than `gone`, that is not being used and can tune `channel`, to
+/// watch it on instead.
+fn alternative_frontend(control_window_buttons: &[Rc<ControlWindowButton>], gone: &FrontendId, channel: &str) -> Option<FrontendId> {
+    control_window_buttons.iter()
+        .find(|cwb| cwb.frontend_id != *gone
+              && !cwb.frontend_button.get_active()
+              && cwb.frontend_button.get_sensitive()
+              && !handover_service::is_frontend_in_use(&cwb.frontend_id)
+              && frontend_manager::channel_incompatibility(&cwb.frontend_id, channel).is_none())
+        .map(|cwb| cwb.frontend_id.clone())
+}
+
+/// Watch `channel` on a frontend, returning whether it could be.
+fn watch_channel(control_window_button: &Rc<ControlWindowButton>, channel: &str) -> bool {
+    let name = frontend_manager::frontend_display_name(&control_window_button.frontend_id);
+    if !control_window_button.frontend_button.get_sensitive() {
+        info!("{} cannot be used, not watching {} on it.", name, channel);
+        return false;
+    }
+    if is_hidden(channel) {
+        control_window_button.control_window.reveal_channel(channel);
+    }
+    let mut channel_selector = control_window_button.channel_selector.clone();
+    if !channel_selector.set_active_text(channel.to_string()) {
+        info!("{} is no longer in the channels list, not watching it on {}.", channel, name);
+        return false;
+    }
+    control_window_button.frontend_button.set_active(true);
+    true
+}
+
+/// Watch `channel` now: on a frontend being watched that can tune it, or else on one
+/// that is not being used. False if there is no such frontend, or the channel cannot
+/// be watched, the user having been told why.
+pub fn watch_channel_now(control_window: &Rc<ControlWindow>, channel: &str) -> bool {  // Used in programme_details.rs
+    let control_window_buttons = control_window.control_window_buttons.borrow().clone();
+    let can_tune = |cwb: &&Rc<ControlWindowButton>| cwb.frontend_button.get_sensitive() && frontend_manager::channel_incompatibility(&cwb.frontend_id, channel).is_none();
+    let watched = control_window_buttons.iter().filter(can_tune).find(|cwb| cwb.frontend_button.get_active());
+    let free = control_window_buttons.iter().filter(can_tune).find(|cwb| !cwb.frontend_button.get_active() && !handover_service::is_frontend_in_use(&cwb.frontend_id));
+    match watched.or(free) {
+        Some(cwb) => cwb.can_tune(channel) && watch_channel(cwb, channel),
+        None => {
+            display_an_error_dialog(Some(&control_window.window), &format!("No frontend can be used to watch {} now.", channel));
+            false
+        },
+    }
+}
+
+/// Tell the user that the frontend they were watching `channel` on has gone, with the
+/// options of trying it again, and of watching on `alternative` if there is one.
+fn report_disconnection(control_window: &Rc<ControlWindow>, fei: &FrontendId, channel: &str, alternative: Option<FrontendId>, is_reconnecting: bool) {
+    end_disconnection(control_window, fei);
+    let bar = gtk::InfoBar::new();
+    bar.set_message_type(gtk::MessageType::Warning);
+    bar.set_show_close_button(true);
+    let label = gtk::Label::new(Some(&format!(
+        "The tuner for {} was disconnected.{}",
+        channel,
+        if is_reconnecting { " Reconnecting when it comes back…" } else { "" },
+    )));
+    label.set_line_wrap(true);
+    bar.get_content_area().add(&label);
+    bar.add_button("Retry", RETRY);
+    if let Some(alternative) = &alternative {
+        bar.add_button(&format!("Watch on {}", frontend_manager::frontend_display_name(alternative)), SWITCH);
+    }
+    bar.connect_response({
+        let c_w = control_window.clone();
+        let fei = fei.clone();
+        let channel = channel.to_string();
+        move |_, response| {
+            if response == RETRY {
+                match find_button(&c_w, &fei) {
+                    Some(c_w_b) => if watch_channel(&c_w_b, &channel) {
+                        end_disconnection(&c_w, &fei);
+                    },
+                    None => if let Some(disconnection) = c_w.disconnections.borrow().get(&fei) {
+                        disconnection.label.set_text(&format!("The tuner for {} was disconnected and is not back yet.", channel));
+                    },
+                }
+            } else if response == SWITCH {
+                match alternative.as_ref().and_then(|alternative| find_button(&c_w, alternative)) {
+                    Some(c_w_b) => if watch_channel(&c_w_b, &channel) {
+                        end_disconnection(&c_w, &fei);
+                    },
+                    None => if let Some(disconnection) = c_w.disconnections.borrow().get(&fei) {
+                        disconnection.label.set_text(&format!("The tuner for {} was disconnected, and the other tuner has gone too.", channel));
+                    },
+                }
+            } else {
+                end_disconnection(&c_w, &fei);
+            }
+        }
+    });
+    control_window.notices_box.pack_start(&bar, false, false, 0);
+    bar.show_all();
+    control_window.disconnections.borrow_mut().insert(fei.clone(), Disconnection{bar, label});
+}
+
+/// Stop telling the user that a frontend has been disconnected, and stop waiting for it
+/// to come back.
+fn end_disconnection(control_window: &ControlWindow, fei: &FrontendId) {
+    control_window.dropouts.borrow_mut().retain(|dropout| dropout.fei != *fei);
+    let disconnection = control_window.disconnections.borrow_mut().remove(fei);
+    if let Some(disconnection) = disconnection {
+        control_window.notices_box.remove(&disconnection.bar);
+    }
+}
+
+/// Wait for a watched frontend that has disappeared to come back, giving up after the
+/// reconnect window.
+fn await_reconnection(control_window: &Rc<ControlWindow>, dropout: Dropout) {
+    let window = reconnect_window();
+    info!("Waiting up to {} seconds for {} to come back.", window.as_secs(), frontend_manager::frontend_display_name(&dropout.fei));
+    control_window.dropouts.borrow_mut().push(dropout);
+    glib::timeout_add_seconds_local(window.as_secs() as u32 + 1, {
+        let c_w = control_window.clone();
+        move || {
+            let now = Instant::now();
+            let window = reconnect_window();
+            let mut expired = Vec::new();
+            c_w.dropouts.borrow_mut().retain(|dropout| {
+                let is_waiting = now.duration_since(dropout.gone_at) <= window;
+                if !is_waiting {
+                    expired.push(dropout.clone());
+                }
+                is_waiting
+            });
+            for dropout in expired {
+                info!("{} did not come back, not reconnecting to {}.", frontend_manager::frontend_display_name(&dropout.fei), dropout.channel);
+                if let Some(disconnection) = c_w.disconnections.borrow().get(&dropout.fei) {
+                    disconnection.label.set_text(&format!("The tuner for {} was disconnected and did not come back.", dropout.channel));
+                }
+            }
+            glib::Continue(false)
+        }
+    });
+}
+
+/// If a frontend that has just appeared is one that dropped out whilst being watched,
+/// go back to watching the channel it was on. Returns whether it was.
+fn reconnect_after_dropout(control_window: &Rc<ControlWindow>, control_window_button: &Rc<ControlWindowButton>) -> bool {
+    let now = Instant::now();
+    let dropout = match control_window.dropouts.borrow().iter()
+        .find(|dropout| dropout.is_reappearance(&control_window_button.frontend_id, &control_window_button.hardware, now, reconnect_window())) {
+        Some(dropout) => dropout.clone(),
+        None => return false,
+    };
+    if watch_channel(control_window_button, &dropout.channel) {
+        info!("{} is back, reconnected to {}.", frontend_manager::frontend_display_name(&control_window_button.frontend_id), dropout.channel);
+        end_disconnection(control_window, &dropout.fei);
+    } else {
+        control_window.dropouts.borrow_mut().retain(|d| d.fei != dropout.fei);
+        if let Some(disconnection) = control_window.disconnections.borrow().get(&dropout.fei) {
+            disconnection.label.set_text(&format!("The tuner for {} is back but could not be reconnected to.", dropout.channel));
+        }
+    }
+    true
+}
+
+/// Another process has started or stopped using a frontend.
+fn change_frontend_availability(control_window: &Rc<ControlWindow>, fei: &FrontendId, availability: Availability) {
+    for c_w_b in control_window.control_window_buttons.borrow().iter()
+        .filter(|cwb| cwb.frontend_id == *fei) {
+        c_w_b.set_availability(availability);
+    }
+}
+
+/// The user does not have permission to use a frontend. Tell them how to fix this, once
+/// rather than for every frontend, and mark the frontend as unusable.
+fn report_inaccessible_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId, reason: &str) {
+    let control_window_buttons = control_window.control_window_buttons.borrow().clone();
+    let is_first = !control_window_buttons.iter().any(|cwb| cwb.is_inaccessible());
+    for c_w_b in control_window_buttons.iter().filter(|cwb| cwb.frontend_id == *fei) {
+        c_w_b.set_inaccessible(reason);
+    }
+    if is_first {
+        display_an_error_dialog(Some(&control_window.window), reason);
+    }
+}
+
+/// Show what each frontend is reserved for.
+fn change_frontend_reservation(control_window: &Rc<ControlWindow>, event: &ReservationEvent) {
+    let (fei, purpose) = match event {
+        ReservationEvent::Granted{fei, purpose} => (fei, Some(purpose)),
+        ReservationEvent::Released{fei, ..} => (fei, None),
+        ReservationEvent::Refused{fei, purpose, reason} => {
+            info!("Reservation of adaptor{} frontend{} for {} refused: {}.", fei.adapter, fei.frontend, purpose, reason);
+            return;
+        },
+    };
+    for c_w_b in control_window.control_window_buttons.borrow().iter()
+        .filter(|cwb| cwb.frontend_id == *fei) {
+        c_w_b.set_purpose(purpose);
+    }
+}
+
+/// Show what a frontend is harvesting the EPG from, if anything, so that the user knows
+/// why it is in use.
+fn change_epg_harvest(control_window: &Rc<ControlWindow>, fei: &FrontendId, status: Option<&str>) {
+    for c_w_b in control_window.control_window_buttons.borrow().iter()
+        .filter(|cwb| cwb.frontend_id == *fei) {
+        c_w_b.set_harvesting(status);
+    }
+}
+
+/// A recording needs a frontend that is being used for viewing. Move the viewing to
+/// a free frontend if there is one, otherwise ask the user whether to stop viewing.
+fn hand_over_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
+    // Toggling buttons runs handlers, so do not hold the borrow while doing it.
+    let control_window_buttons = control_window.control_window_buttons.borrow().clone();
+    let current = match control_window_buttons.iter().find(|cwb| cwb.frontend_id == *fei && cwb.frontend_button.get_active()) {
+        Some(current) => current,
+        None => return,
+    };
+    match control_window_buttons.iter().find(|cwb| !cwb.frontend_button.get_active() && cwb.frontend_button.get_sensitive() && !handover_service::is_frontend_in_use(&cwb.frontend_id)) {
+        Some(free) => {
+            info!("Moving viewing from adaptor{} frontend{} to adaptor{} frontend{} for a recording.",
+                  fei.adapter, fei.frontend, free.frontend_id.adapter, free.frontend_id.frontend);
+            free.channel_selector.set_active(current.channel_selector.get_active());
+            current.frontend_button.set_active(false);
+            free.frontend_button.set_active(true);
+        },
+        None => {
+            let dialog = gtk::MessageDialog::new(
+                Some(&control_window.window),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Question,
+                gtk::ButtonsType::YesNo,
+                &format!("A recording needs adaptor{} frontend{}, and there is no other frontend to move to.\n\nStop watching so that the recording can proceed?", fei.adapter, fei.frontend),
+            );
+            let response = gtk::ResponseType::from(dialog.run());
+            unsafe { dialog.destroy(); }
+            if response == gtk::ResponseType::Yes {
+                current.frontend_button.set_active(false);
+            }
+        },
+    }
+}
+
 /// Process a keystroke bound for a given frontend.
 fn process_targetted_keystroke(control_window: &Rc<ControlWindow>, tk: &TargettedKeystroke) {
     for c_w_b in control_window.control_window_buttons.borrow().iter()
@@ -396,3 +1268,90 @@ fn add_logical_channel_number(control_window: &Rc<ControlWindow>, cd: &ChannelDa
         }
     }
 }
+
+/// Set the value in `column` of the channel `name` in the channels store.
+fn set_channel_value(control_window: &ControlWindow, name: &str, column: u32, value: &glib::Value) {
+    let list_store = &control_window.channels_data_store;
+    if let Some(iter) = list_store.get_iter_first() {
+        loop {
+            if list_store.get_value(&iter, 1).get::<String>().unwrap().as_deref() == Some(name) {
+                list_store.set_value(&iter, column, value);
+            }
+            if !list_store.iter_next(&iter) { break; }
+        }
+    }
+}
+
+/// Process learning whether a channel is encrypted, telling the user if it is one being
+/// watched.
+fn set_channel_encryption(control_window: &Rc<ControlWindow>, cd: &ChannelData) {
+    set_channel_value(control_window, &cd.name, 4, &cd.encrypted.to_value());
+    let is_being_watched = control_window.control_window_buttons.borrow().iter()
+        .any(|c_w_b| c_w_b.frontend_button.get_active() && c_w_b.channel_selector.get_active_text().as_ref() == Some(&cd.name));
+    if cd.encrypted && is_being_watched {
+        show_notice(control_window, &format!("{} is encrypted and cannot be viewed without a CAM.", cd.name));
+    }
+    if preferences::get_hide_encrypted_channels() {
+        control_window.refresh_channels_view();
+    }
+}
+
+/// Process learning whether a channel is a radio channel, changing how the frontends
+/// watching it present it.
+fn set_channel_radio(control_window: &Rc<ControlWindow>, cd: &ChannelData) {
+    set_channel_value(control_window, &cd.name, 5, &cd.radio.to_value());
+    control_window.refresh_channels_view();
+    for c_w_b in control_window.control_window_buttons.borrow().iter() {
+        c_w_b.update_presentation();
+    }
+}
+
+/// Process a change of the programme on now or next on a service, which the frontend
+/// windows showing the channel may be showing, and the programme guide may be showing
+/// as having started late or early.
+fn update_now_next(control_window: &Rc<ControlWindow>, service_id: u16) {
+    for c_w_b in control_window.control_window_buttons.borrow().iter() {
+        c_w_b.update_now_next(service_id);
+    }
+    epg_window::programmes_changed(service_id);
+}
+
+/// Process learning the provider of a channel, which the channels may be ordered by.
+fn set_channel_provider(control_window: &Rc<ControlWindow>, cd: &ChannelData) {
+    set_channel_value(control_window, &cd.name, 10, &cd.provider.as_deref().unwrap_or("").to_value());
+    if preferences::get_channel_order() == ChannelOrder::Provider {
+        control_window.refresh_channels_view();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dropout() -> Dropout {
+        Dropout {
+            fei: FrontendId{adapter: 1, frontend: 0},
+            hardware: FrontendHardware{name: Some("Hauppauge dualHD".to_string()), bus_info: Some("usb 1-2:1.0".to_string())},
+            channel: "BBC NEWS".to_string(),
+            gone_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn same_frontend_coming_back_in_the_window_is_a_reappearance() {
+        let dropout = dropout();
+        let window = Duration::from_secs(30);
+        assert!(dropout.is_reappearance(&dropout.fei, &FrontendHardware::default(), dropout.gone_at + Duration::from_secs(5), window));
+        assert!(!dropout.is_reappearance(&dropout.fei, &dropout.hardware, dropout.gone_at + Duration::from_secs(31), window));
+    }
+
+    #[test]
+    fn same_hardware_on_another_adapter_is_a_reappearance() {
+        let dropout = dropout();
+        let window = Duration::from_secs(30);
+        let now = dropout.gone_at + Duration::from_secs(5);
+        assert!(dropout.is_reappearance(&FrontendId{adapter: 2, frontend: 0}, &dropout.hardware, now, window));
+        assert!(!dropout.is_reappearance(&FrontendId{adapter: 2, frontend: 1}, &dropout.hardware, now, window));
+        assert!(!dropout.is_reappearance(&FrontendId{adapter: 2, frontend: 0}, &FrontendHardware::default(), now, window));
+    }
+}
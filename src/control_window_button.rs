@@ -19,20 +19,30 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::time::Duration;
 
 use gtk;
 use gtk::prelude::*;
 
-use crate::channels_data::{encode_to_mrl, get_channel_name_of_logical_channel_number};
-use crate::control_window::ControlWindow;
+use log::{debug, warn};
+
+use crate::channel_search;
+use crate::channels_data::{get_channel_details, get_channel_names_and_service_ids, is_encrypted, is_hidden, set_hidden};
+use crate::control_window::{relist_channels, ControlWindow};
 use crate::dialogs::display_an_error_dialog;
-use crate::frontend_manager::FrontendId;
+use crate::epg_bus_service;
+use crate::epg_manager;
+use crate::favourites;
+use crate::frontend_manager::{self, Availability, DeliverySystem, FrontendHardware, FrontendId, FrontendInfo, Purpose};
 use crate::frontend_window::FrontendWindow;
+use crate::handover_service;
 use crate::input_event_codes;
 use crate::metvcombobox::{MeTVComboBox, MeTVComboBoxExt};
 use crate::preferences;
+use crate::programme_details;
+use crate::recent_channels::{self, recent_channels};
 use crate::remote_control::TargettedKeystroke;
 
 /// A `ControlWindowButton` is a `gtk::Box` but there is no inheritance so use
@@ -41,36 +51,79 @@ use crate::remote_control::TargettedKeystroke;
 pub struct ControlWindowButton {
     pub control_window: Rc<ControlWindow>, // FrontendWindow instance needs access to this.
     pub frontend_id: FrontendId, // ControlWindow instance needs access to this for searching.
+    pub hardware: FrontendHardware, // ControlWindow instance needs access to this to recognise a frontend coming back.
     pub widget: gtk::Box, // ControlWindow instance needs access to this for packing.
     pub frontend_button: gtk::ToggleButton, // FrontendWindow needs access to this.
+    pub channels_model: gtk::TreeModelFilter, // The channels the frontend can receive, FrontendWindow and ControlWindow use this.
     pub channel_selector: MeTVComboBox, // FrontendWindow needs read access to this.
+    favourite_button: gtk::ToggleButton,
     frontend_window: RefCell<Option<Rc<FrontendWindow>>>,
+    display_name: String,  // For the label.
+    tooltip: String,  // The capabilities of the frontend, for the toggle button.
+    inaccessible: Cell<bool>,
+    harvesting: Cell<bool>,  // Whether the frontend is harvesting the EPG, so in use but not busy.
+    restoring_channel: Cell<bool>,  // Whilst set, channel changes are the channels list being reloaded, not the user choosing.
     channel_number_dialog: gtk::Dialog,
     channel_number_entry: gtk::Entry,
+    // Shared by the clones made for timeouts, so not just a Cell.
+    zap_target: Rc<Cell<Option<u32>>>,  // The index of the channel being zapped to, until it is tuned.
+    zap_count: Rc<Cell<u32>>,  // Counts zaps so that a timeout can tell if there has been another since.
+    recall_position: Rc<Cell<Option<usize>>>,  // How far back through the recently watched channels, until it is tuned.
+    channel_number: Rc<RefCell<String>>,  // The digits of a channel number entered so far.
 }
 
+/// How long the channel being zapped to must stay the same before it is tuned, so that
+/// holding a key down does not tune every channel passed on the way.
+const ZAP_SETTLE: Duration = Duration::from_millis(300);
+
+/// How long to wait after going back to a recently watched channel before tuning it, so
+/// that going back again goes further back.
+const RECALL_SETTLE: Duration = Duration::from_millis(1000);
+
 impl ControlWindowButton {
     /// Construct a new button representing an available front end.
     ///
-    /// The adapter and frontend numbers for the label for a toggle button that is used
-    /// to start and stop a frontend window displaying the stream for that frontend. Below
-    /// is a drop down list button to select the channel to tune the front end to.
+    /// The display name of the frontend, naming the hardware if known, for the
+    /// label for a toggle button that is used to start and stop a frontend window
+    /// displaying the stream for that frontend. Below
+    /// is a drop down list button to select the channel to tune the front end to. The
+    /// capabilities of the frontend, if known, and the hardware it is part of, if known,
+    /// are the tooltip of the toggle button.
     ///
     /// This function is executed in the GTK event loop thread.
-    pub fn new(control_window: &Rc<ControlWindow>, fei: &FrontendId) -> Rc<ControlWindowButton> {
+    pub fn new(control_window: &Rc<ControlWindow>, fei: &FrontendId, frontend_info: Option<FrontendInfo>, hardware: &FrontendHardware, display_name: &str) -> Rc<ControlWindowButton> {
         let frontend_id = fei.clone();
-        let frontend_button = gtk::ToggleButton::with_label(
-            format!("adaptor{}\nfrontend{}", frontend_id.adapter, frontend_id.frontend).as_ref()
-        );
-        let channel_selector = MeTVComboBox::new_with_model(&control_window.channels_data_sorter);
+        let display_name = display_name.to_string();
+        let frontend_button = gtk::ToggleButton::with_label(&Self::label_text(&display_name, None));
+        let mut tooltip = match &frontend_info {
+            Some(info) => info.description(),
+            None => "Capabilities unknown".to_string(),
+        };
+        if let Some(description) = hardware.description() {
+            tooltip = tooltip + "\n" + &description;
+        }
+        frontend_button.set_tooltip_text(Some(&tooltip));
+        let channels_model = gtk::TreeModelFilter::new(&control_window.channels_data_sorter, None);
+        channels_model.set_visible_func({
+            let fei = fei.clone();
+            move |model, iter| {
+                let delivery_system = model.get_value(iter, 6).get::<String>().unwrap().and_then(|d| d.parse::<DeliverySystem>().ok());
+                preferences::get_show_all_sources() || frontend_manager::can_receive(&fei, delivery_system)
+            }
+        });
+        let channel_selector = MeTVComboBox::new_with_model(&channels_model);
+        let favourite_button = gtk::ToggleButton::new();
+        favourite_button.set_tooltip_text(Some("Favourite channel"));
+        let channel_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        channel_box.pack_start(&channel_selector, true, true, 0);
+        channel_box.pack_start(&favourite_button, false, false, 0);
         let widget = gtk::Box::new(gtk::Orientation::Vertical, 0);
         widget.pack_start(&frontend_button, true, true, 0);
-        widget.pack_start(&channel_selector, true, true, 0);
+        widget.pack_start(&channel_box, true, true, 0);
         let channel_number_dialog = gtk::Dialog::new();
         let channel_number_entry = gtk::Entry::new();
-        let max_length = 3;
-        channel_number_entry.set_max_length(max_length);
-        channel_number_entry.set_max_width_chars(max_length);
+        channel_number_entry.set_editable(false);
+        channel_number_entry.set_width_chars(14);  // Room for telling the user there is no such channel.
         channel_number_entry.set_alignment(1.0);
         channel_number_entry.set_progress_fraction(1.0);
         channel_number_entry.show();
@@ -79,17 +132,69 @@ impl ControlWindowButton {
         let control_window_button = Rc::new(ControlWindowButton {
             control_window: control_window.clone(),
             frontend_id,
+            hardware: hardware.clone(),
             widget,
             frontend_button,
+            channels_model,
             channel_selector,
+            favourite_button,
             frontend_window: RefCell::new(None),
+            display_name,
+            tooltip,
+            inaccessible: Cell::new(false),
+            harvesting: Cell::new(false),
+            restoring_channel: Cell::new(false),
             channel_number_dialog,
             channel_number_entry,
+            zap_target: Rc::new(Cell::new(None)),
+            zap_count: Rc::new(Cell::new(0)),
+            recall_position: Rc::new(Cell::new(None)),
+            channel_number: Rc::new(RefCell::new(String::new())),
         });
+        channel_box.pack_start(&Self::channel_search_button(&control_window_button), false, false, 0);
+        channel_box.pack_start(&Self::recent_channels_button(&control_window_button), false, false, 0);
         control_window_button.reset_active_channel();
+        control_window_button.update_favourite_button();
         control_window_button.channel_selector.connect_changed({
             let c_w_b = control_window_button.clone();
-            move |_| Self::on_channel_changed(&c_w_b, c_w_b.channel_selector.get_active().unwrap())
+            move |_| c_w_b.update_favourite_button()
+        });
+        // The details are those known when the tooltip is shown, the SDT giving some of them.
+        control_window_button.channel_selector.set_has_tooltip(true);
+        control_window_button.channel_selector.connect_query_tooltip(|selector, _, _, _, tooltip| {
+            match selector.get_active_text().and_then(|name| get_channel_details(&name)) {
+                Some(details) => {
+                    tooltip.set_text(Some(&details));
+                    true
+                },
+                None => false,
+            }
+        });
+        control_window_button.channel_selector.connect_button_press_event({
+            let c_w_b = control_window_button.clone();
+            move |_, event| {
+                if event.get_event_type() == gdk::EventType::ButtonPress && event.get_button() == 3 {
+                    c_w_b.pop_up_channel_menu(event);
+                    return Inhibit(true);
+                }
+                Inhibit(false)
+            }
+        });
+        control_window_button.favourite_button.connect_toggled({
+            let c_w_b = control_window_button.clone();
+            move |favourite_button| if let Some(channel_name) = c_w_b.channel_selector.get_active_text() {
+                // The button is also set to show whether a channel is a favourite.
+                if favourite_button.get_active() != favourites::is_favourite(&channel_name) {
+                    favourites::toggle(&channel_name);
+                    c_w_b.control_window.refresh_channels_view();
+                }
+            }
+        });
+        control_window_button.channel_selector.connect_changed({
+            let c_w_b = control_window_button.clone();
+            move |_| if let Some(channel_index) = c_w_b.channel_selector.get_active() {
+                Self::on_channel_changed(&c_w_b, channel_index);
+            }
         });
         control_window_button.frontend_button.connect_toggled({
             let c_w_b = control_window_button.clone();
@@ -104,6 +209,87 @@ impl ControlWindowButton {
         control_window_button
     }
 
+    /// Grey out the frontend whilst another process, tvheadend or a recording say, is
+    /// using it. Whilst this button is active, or the EPG is being harvested, the
+    /// frontend is in use by Me TV itself so nothing is greyed out.
+    pub fn set_availability(&self, availability: Availability) {  // Used in control_window.rs
+        let is_unusable = match availability {
+            Availability::InUse => !self.frontend_button.get_active() && !self.harvesting.get(),
+            Availability::Inaccessible => true,
+            _ => false,
+        };
+        self.frontend_button.set_sensitive(!is_unusable);
+        self.channel_selector.set_sensitive(!is_unusable);
+    }
+
+    /// Grey out the frontend as the user does not have permission to use it, with the
+    /// reason as the tooltip.
+    pub fn set_inaccessible(&self, reason: &str) {  // Used in control_window.rs
+        self.inaccessible.set(true);
+        self.set_availability(Availability::Inaccessible);
+        self.frontend_button.set_tooltip_text(Some(reason));
+    }
+
+    /// Show what the frontend is harvesting the EPG from in the tooltip, None for it not
+    /// harvesting. Harvesting gives way to viewing, so the frontend stays usable.
+    pub fn set_harvesting(&self, status: Option<&str>) {  // Used in control_window.rs
+        self.harvesting.set(status.is_some());
+        if self.inaccessible.get() {
+            return;
+        }
+        match status {
+            Some(status) => {
+                self.frontend_button.set_tooltip_text(Some(&format!("{}\n{}", self.tooltip, status)));
+                self.frontend_button.set_sensitive(true);
+                self.channel_selector.set_sensitive(true);
+            },
+            None => self.frontend_button.set_tooltip_text(Some(&self.tooltip)),
+        }
+    }
+
+    /// Whether the user has been told they do not have permission to use the frontend.
+    pub fn is_inaccessible(&self) -> bool {  // Used in control_window.rs
+        self.inaccessible.get()
+    }
+
+    /// The text of the toggle button label, the display name of the frontend broken
+    /// before the adapter and frontend numbers to keep the button narrow, and what the
+    /// frontend is reserved for if it is.
+    fn label_text(display_name: &str, purpose: Option<&Purpose>) -> String {
+        let mut text = display_name.replacen(" — ", "\n", 1);
+        if let Some(purpose) = purpose {
+            text += &format!("\n({})", purpose);
+        }
+        text
+    }
+
+    /// Show what the frontend is reserved for, or that it is not.
+    pub fn set_purpose(&self, purpose: Option<&Purpose>) {  // Used in control_window.rs
+        self.frontend_button.set_label(&Self::label_text(&self.display_name, purpose));
+    }
+
+    /// Whether the frontend can tune a channel, telling the user why not if it cannot.
+    pub fn can_tune(&self, channel_name: &str) -> bool {  // Used in frontend_window.rs
+        if is_encrypted(channel_name) {
+            display_an_error_dialog(Some(&self.control_window.window), &format!("Cannot play {}:\nthis channel is encrypted and cannot be viewed without a CAM.", channel_name));
+            return false;
+        }
+        match frontend_manager::channel_incompatibility(&self.frontend_id, channel_name) {
+            Some(reason) => {
+                display_an_error_dialog(Some(&self.control_window.window), &format!("Cannot play {}:\n{}", channel_name, reason));
+                false
+            },
+            None => true,
+        }
+    }
+
+    /// Remember `channel_name` as the channel last watched, and this frontend as the one
+    /// it was watched on, to tune on start up.
+    pub fn remember_channel(&self, channel_name: &str) {  // Used in control_window.rs and frontend_window.rs
+        preferences::set_last_frontend(self.frontend_id.to_string(), false);
+        preferences::set_last_channel(channel_name.to_string(), true);
+    }
+
     /// Set the active channel to index 0.
     pub fn reset_active_channel(&self) {  // Used in control_window.rs
         self.channel_selector.set_active(Some(0));
@@ -113,19 +299,72 @@ impl ControlWindowButton {
         }
     }
 
+    /// Select the channel `name` again after the channels list has been reloaded, without
+    /// retuning to it if it is being watched. False if it is no longer in the list.
+    pub fn restore_channel(&self, name: &str) -> bool {  // Used in control_window.rs
+        self.restoring_channel.set(true);
+        let mut channel_selector = self.channel_selector.clone();
+        let is_present = channel_selector.set_active_text(name.to_string());
+        if let Some(channel_index) = self.channel_selector.get_active() {
+            self.set_channel_index(channel_index);
+        }
+        self.restoring_channel.set(false);
+        is_present
+    }
+
+    /// Show whether the selected channel is a favourite.
+    pub fn update_favourite_button(&self) {  // Used in control_window.rs
+        let is_favourite = match self.channel_selector.get_active_text() {
+            Some(channel_name) if self.control_window.is_channels_store_loaded() => {
+                self.favourite_button.set_sensitive(true);
+                favourites::is_favourite(&channel_name)
+            },
+            _ => {
+                self.favourite_button.set_sensitive(false);
+                false
+            },
+        };
+        self.favourite_button.set_active(is_favourite);
+        let icon_name = if is_favourite { "starred-symbolic" } else { "non-starred-symbolic" };
+        self.favourite_button.set_image(Some(&gtk::Image::from_icon_name(Some(icon_name), gtk::IconSize::Button.into())));
+    }
+
+    /// Present the selected channel in the frontend window, if there is one, as television
+    /// or as radio as the channel is.
+    pub fn update_presentation(&self) {  // Used in control_window.rs
+        if let Some(ref frontend_window) = *self.frontend_window.borrow() {
+            if let Some(channel_name) = self.channel_selector.get_active_text() {
+                frontend_window.present_channel(&channel_name);
+            }
+        }
+    }
+
+    /// Have the frontend window, if there is one, show the change of the programme on now
+    /// on the service `service_id` if that is the channel selected, and signal the change
+    /// to other programs.
+    pub fn update_now_next(&self, service_id: u16) {  // Used in control_window.rs
+        if let Some(ref frontend_window) = *self.frontend_window.borrow() {
+            if let Some(channel_name) = self.channel_selector.get_active_text() {
+                let is_selected = get_channel_names_and_service_ids().unwrap_or_default().iter()
+                    .any(|(name, id)| *name == channel_name.as_str() && *id == service_id);
+                if is_selected {
+                    frontend_window.update_programme(&channel_name);
+                    epg_bus_service::now_next_changed(&channel_name);
+                }
+            }
+        }
+    }
+
     /// Set the state of all the channel control widgets.
     fn set_channel_index(&self, channel_index: u32) {
-        let current = self.channel_selector.get_active().unwrap();
-        if current != channel_index {
+        if self.channel_selector.get_active() != Some(channel_index) {
             self.channel_selector.set_active(Some(channel_index));
         }
         if let Some(ref frontend_window) = *self.frontend_window.borrow() {
-            let fe_current = frontend_window.channel_selector.get_active().unwrap();
-            if fe_current != channel_index {
+            if frontend_window.channel_selector.get_active() != Some(channel_index) {
                 frontend_window.channel_selector.set_active(Some(channel_index));
             }
-            let fs_fe_current = frontend_window.fullscreen_channel_selector.get_active().unwrap();
-            if fs_fe_current != channel_index {
+            if frontend_window.fullscreen_channel_selector.get_active() != Some(channel_index) {
                 frontend_window.fullscreen_channel_selector.set_active(Some(channel_index));
             }
         }
@@ -148,6 +387,7 @@ impl ControlWindowButton {
                     Some(_) => panic!("Inconsistent state of frontend,"),
                     None => {},
                 };
+                handover_service::set_frontend_in_use(&control_window_button.frontend_id, true);
             }
             // TODO Should there be an else activity here?
         } else {
@@ -155,11 +395,15 @@ impl ControlWindowButton {
                 Some(ref frontend_window) => frontend_window.stop(),
                 None => panic!("Inconsistent state of frontend,"),
             }
+            handover_service::set_frontend_in_use(&control_window_button.frontend_id, false);
         }
     }
 
     /// Callback for an observed channel change.
     pub fn on_channel_changed(control_window_button: &Rc<ControlWindowButton>, channel_index: u32) { // Used in frontend_window.rs
+        if control_window_button.restoring_channel.get() {
+            return;
+        }
         // TODO status is Option<u32> apparently which isn't a great bool value.
         let status = control_window_button.frontend_button.get_active();
         if let Some(ref frontend_window) = *control_window_button.frontend_window.borrow() {
@@ -189,16 +433,19 @@ impl ControlWindowButton {
                         }
                     },
                 }
-                println!("========  Channel changed callback called");
+                debug!("Channel changed callback called");
                 // TODO Why does changing channel on the FrontendWindow result in three calls here.
             }
             control_window_button.set_channel_index(channel_index);
             let channel_name = control_window_button.channel_selector.get_active_text().unwrap();
-            frontend_window.engine.set_mrl(&encode_to_mrl(&channel_name));
-            preferences::set_last_channel(channel_name, true);
-            if status {
+            frontend_window.engine.set_channel(&channel_name);
+            frontend_window.present_channel(&channel_name);
+            if status && control_window_button.can_tune(&channel_name) {
                 // TODO Must handle not being able to tune to a channel better than panicking.
                 frontend_window.engine.play();
+                control_window_button.remember_channel(&channel_name);
+                recent_channels::record(&channel_name);
+                frontend_window.show_banner(&channel_name);
             }
         }
     }
@@ -209,21 +456,19 @@ impl ControlWindowButton {
         match tk.keystroke {
             input_event_codes::KEY_CHANNELUP => {
                 if tk.value > 0 {
-                    let selector = &self.channel_selector;
-                    let index = selector.get_active().unwrap();
-                    // TODO Need to stop going beyond the number of channels there are.
-                    selector.set_active(Some(index + 1));
+                    self.zap(1);
                 }
             }
             input_event_codes::KEY_CHANNELDOWN => {
                 if tk.value > 0 {
-                    let selector = &self.channel_selector;
-                    let index = selector.get_active().unwrap();
-                    if index > 0 {
-                        selector.set_active(Some(index - 1));
-                    }
+                    self.zap(-1);
                 }
             }
+            input_event_codes::KEY_LAST => {
+                if tk.value == 1 {
+                    self.recall();
+                }
+            },
             input_event_codes::KEY_VOLUMEUP => {
                 if tk.value > 0 {
                     if let Some(ref f_w) = *self.frontend_window.borrow() {
@@ -264,46 +509,317 @@ impl ControlWindowButton {
                     self.process_numeric_keystroke(tk);
                 }
             },
-            x => println!("Got an unprocessed keystroke {}", x),
+            x => debug!("Got an unprocessed keystroke {}", x),
         }
     }
 
-    /// Change the channel to the one collected by the `Entry`.
-    fn change_channel_after_keystrokes(&self, channel_number: &str) {
-        let channel_number = channel_number.parse::<u16>().unwrap();
-        match get_channel_name_of_logical_channel_number(channel_number) {
-            Some(channel_name) => {
-                let index = {
-                    let model = &self.control_window.channels_data_sorter;
-                    let iterator = model.get_iter_first().unwrap();
-                    let mut index = 0u32;
-                    let mut success = false;
-                    loop {
-                        let name = model.get_value(&iterator, 1).get::<String>().unwrap().unwrap();
-                        if name == channel_name {
-                            success = true;
-                            break
-                        }
-                        if ! model.iter_next(&iterator) { break }
-                        index += 1;
+    /// Move `step` channels through the channels list, as it is ordered and filtered,
+    /// wrapping round at the ends. The channel is only tuned once it has stayed the same
+    /// for `ZAP_SETTLE`, until then the frontend window just shows its name.
+    pub fn zap(&self, step: i32) {  // Used in frontend_window.rs
+        let count = self.channels_model.iter_n_children(None);
+        if count == 0 { return; }
+        // No channel is selected if the one being watched has gone from the channels file.
+        let target = match self.zap_target.get().or_else(|| self.channel_selector.get_active()) {
+            Some(index) => (index as i32 + step).rem_euclid(count) as u32,
+            None => if step > 0 { 0 } else { count as u32 - 1 },
+        };
+        self.zap_target.set(Some(target));
+        self.recall_position.set(None);
+        if let Some(ref frontend_window) = *self.frontend_window.borrow() {
+            let channel_name = self.channels_model.iter_nth_child(None, target as i32)
+                .and_then(|iterator| self.channels_model.get_value(&iterator, 1).get::<String>().unwrap());
+            frontend_window.show_zapping(channel_name.as_deref());
+        }
+        let zap = self.zap_count.get().wrapping_add(1);
+        self.zap_count.set(zap);
+        glib::timeout_add_local(ZAP_SETTLE.as_millis() as u32, {
+            let s = self.clone();
+            move || {
+                if s.zap_count.get() == zap {
+                    s.zap_target.set(None);
+                    if let Some(ref frontend_window) = *s.frontend_window.borrow() {
+                        frontend_window.show_zapping(None);
                     }
-                    if ! success { panic!("Failed to find {} in the data model", channel_name); }
-                    index
-                };
-                self.set_channel_index(index);
+                    s.set_channel_index(target);
+                }
+                Continue(false)
+            }
+        });
+    }
+
+    /// The numbers the channels can be chosen by, with their index in the channels list:
+    /// the logical channel numbers if the channels have them, otherwise their positions in
+    /// the list counting from 1.
+    fn channel_numbers(&self) -> Vec<(String, u32)> {
+        let model = &self.channels_model;
+        let mut numbers = Vec::new();
+        let mut count = 0u32;
+        if let Some(iterator) = model.get_iter_first() {
+            loop {
+                if let Some(number) = model.get_value(&iterator, 0).get::<String>().unwrap() {
+                    if !number.is_empty() {
+                        numbers.push((number, count));
+                    }
+                }
+                count += 1;
+                if ! model.iter_next(&iterator) { break }
+            }
+        }
+        if numbers.is_empty() {
+            numbers = (0..count).map(|index| ((index + 1).to_string(), index)).collect();
+        }
+        numbers
+    }
+
+    /// Show the channel number entered so far, or some other text about it, over the video
+    /// if the frontend is being watched and in the channel number dialog if not.
+    fn show_channel_number(&self, text: &str) {
+        match *self.frontend_window.borrow() {
+            Some(ref frontend_window) => frontend_window.show_osd(Some(text)),
+            None => {
+                self.channel_number_entry.set_text(text);
+                self.channel_number_dialog.show_all();
             },
-            None => println!("Failed to find channel name from channel number."),
         }
+    }
+
+    /// Stop showing the channel number, forgetting the digits entered.
+    fn hide_channel_number(&self) {
         self.channel_number_entry.set_text("");
         self.channel_number_entry.set_progress_fraction(1.0);
         self.channel_number_dialog.hide();
+        if let Some(ref frontend_window) = *self.frontend_window.borrow() {
+            frontend_window.show_osd(None);
+        }
     }
 
-    /// Process a numeric keystroke, most likely from a remote.
+    /// Tell the user there is no channel `channel_number`, for a moment.
+    fn show_no_such_channel(&self, channel_number: &str) {
+        warn!("There is no channel {}.", channel_number);
+        let text = format!("No channel {}", channel_number);
+        self.hide_channel_number();
+        self.show_channel_number(&text);
+        self.channel_number_entry.set_progress_fraction(0.0);
+        glib::timeout_add_seconds_local(1, {
+            let s = self.clone();
+            move || {
+                // Unless there is another channel number being entered.
+                if s.channel_number.borrow().is_empty() {
+                    s.hide_channel_number();
+                }
+                Continue(false)
+            }
+        });
+    }
+
+    /// The index in the channels list of the channel `name`, if this frontend lists it.
+    fn channel_index_of(&self, name: &str) -> Option<u32> {
+        let model = &self.channels_model;
+        let iterator = model.get_iter_first()?;
+        let mut index = 0u32;
+        loop {
+            if model.get_value(&iterator, 1).get::<String>().unwrap().as_deref() == Some(name) {
+                return Some(index);
+            }
+            if ! model.iter_next(&iterator) { return None; }
+            index += 1;
+        }
+    }
+
+    /// Go back through the recently watched channels that this frontend lists: once to the
+    /// channel watched before this one, as the previous channel button of a remote control
+    /// does, and again, before that is tuned, to the one before that, and so on round.
+    pub fn recall(&self) {  // Used in frontend_window.rs
+        let current = self.channel_selector.get_active_text();
+        let channels = recent_channels().into_iter()
+            .filter(|channel| Some(&channel.name) != current.as_ref())
+            .filter_map(|channel| self.channel_index_of(&channel.name).map(|index| (channel.name, index)))
+            .collect::<Vec<_>>();
+        if channels.is_empty() { return; }
+        let position = self.recall_position.get().map_or(0, |position| (position + 1) % channels.len());
+        self.recall_position.set(Some(position));
+        self.zap_target.set(None);
+        let (name, index) = channels[position].clone();
+        if let Some(ref frontend_window) = *self.frontend_window.borrow() {
+            frontend_window.show_zapping(Some(&name));
+        }
+        let zap = self.zap_count.get().wrapping_add(1);
+        self.zap_count.set(zap);
+        glib::timeout_add_local(RECALL_SETTLE.as_millis() as u32, {
+            let s = self.clone();
+            move || {
+                if s.zap_count.get() == zap {
+                    s.recall_position.set(None);
+                    if let Some(ref frontend_window) = *s.frontend_window.borrow() {
+                        frontend_window.show_zapping(None);
+                    }
+                    s.set_channel_index(index);
+                }
+                Continue(false)
+            }
+        });
+    }
+
+    /// A button popping up a menu of the recently watched channels that the frontend
+    /// lists, to choose one of them.
+    pub fn recent_channels_button(control_window_button: &Rc<ControlWindowButton>) -> gtk::Button {  // Used in frontend_window.rs
+        let button = gtk::Button::new();
+        button.set_image(Some(&gtk::Image::from_icon_name(Some("document-open-recent-symbolic"), gtk::IconSize::Button.into())));
+        button.set_tooltip_text(Some("Recently watched channels"));
+        button.connect_clicked({
+            let c_w_b = control_window_button.clone();
+            move |b| {
+                let menu = gtk::Menu::new();
+                for channel in recent_channels() {
+                    if let Some(index) = c_w_b.channel_index_of(&channel.name) {
+                        let item = gtk::MenuItem::with_label(&channel.name);
+                        item.set_tooltip_text(Some(&format!("Watched {}", channel.watched_text())));
+                        item.connect_activate({
+                            let c_w_b = c_w_b.clone();
+                            move |_| c_w_b.set_channel_index(index)
+                        });
+                        menu.append(&item);
+                    }
+                }
+                if menu.get_children().is_empty() {
+                    let item = gtk::MenuItem::with_label("No recently watched channels");
+                    item.set_sensitive(false);
+                    menu.append(&item);
+                }
+                menu.set_attach_widget(Some(b));
+                menu.show_all();
+                menu.popup_at_widget(b, gdk::Gravity::SouthWest, gdk::Gravity::NorthWest, None);
+            }
+        });
+        button
+    }
+
+    /// Pop up the menu of what can be done to the channel selected: making it a favourite,
+    /// or hiding it from the channels lists. A channel being watched when it is hidden
+    /// stays in the lists until Me TV is next started. The programmes on now and next,
+    /// if they are known, are there too, for their details.
+    fn pop_up_channel_menu(&self, event: &gdk::EventButton) {
+        let channel_name = match self.channel_selector.get_active_text() { Some(name) => name, None => return };
+        let menu = gtk::Menu::new();
+        let favourite_item = gtk::CheckMenuItem::with_label("Favourite");
+        favourite_item.set_active(favourites::is_favourite(&channel_name));
+        favourite_item.connect_toggled({
+            let favourite_button = self.favourite_button.clone();
+            move |item| favourite_button.set_active(item.get_active())
+        });
+        menu.append(&favourite_item);
+        let hidden_item = gtk::CheckMenuItem::with_label("Hidden");
+        hidden_item.set_tooltip_text(Some("Hide the channel from the channels lists, the channel editor can list it again"));
+        hidden_item.set_active(is_hidden(&channel_name));
+        hidden_item.connect_toggled({
+            let control_window = self.control_window.clone();
+            let channel_name = channel_name.clone();
+            let is_watched = self.frontend_button.get_active();
+            move |item| {
+                set_hidden(&channel_name, item.get_active());
+                if is_watched {
+                    control_window.reveal_channel(&channel_name);
+                }
+                relist_channels(&control_window);
+            }
+        });
+        menu.append(&hidden_item);
+        if let Some(now_next) = epg_manager::now_next(&channel_name) {
+            menu.append(&gtk::SeparatorMenuItem::new());
+            for (when, programme) in vec![("Now", now_next.now), ("Next", now_next.next)] {
+                if let Some(programme) = programme {
+                    let item = gtk::MenuItem::with_label(&format!("{}: {}…", when, programme.title));
+                    item.connect_activate({
+                        let control_window = self.control_window.clone();
+                        let channel_name = channel_name.clone();
+                        move |_| programme_details::present(Some(&control_window.window), &control_window, &channel_name, &programme)
+                    });
+                    menu.append(&item);
+                }
+            }
+        }
+        menu.set_attach_widget(Some(&self.channel_selector));
+        menu.show_all();
+        menu.popup_at_pointer(Some(&**event));
+    }
+
+    /// A button popping up a search of the channels that the frontend lists, to choose
+    /// one of them.
+    pub fn channel_search_button(control_window_button: &Rc<ControlWindowButton>) -> gtk::MenuButton {  // Used in frontend_window.rs
+        channel_search::channel_search_button(&control_window_button.channels_model, {
+            let c_w_b = control_window_button.clone();
+            move |name| if let Some(index) = c_w_b.channel_index_of(name) {
+                c_w_b.set_channel_index(index);
+            }
+        })
+    }
+
+    /// Change the channel to the one with the number entered.
+    fn change_channel_after_keystrokes(&self, channel_number: &str) {
+        self.channel_number.borrow_mut().clear();
+        match self.channel_numbers().into_iter().find(|(number, _)| number == channel_number) {
+            Some((_, index)) => {
+                self.hide_channel_number();
+                self.zap_target.set(None);
+                self.set_channel_index(index);
+            },
+            None => self.show_no_such_channel(channel_number),
+        }
+    }
+
+    /// Tune the channel with the number entered so far, Enter having been pressed.
+    pub fn enter_channel_number(&self) -> bool {  // Used in frontend_window.rs
+        let channel_number = self.channel_number.borrow().clone();
+        if channel_number.is_empty() { return false; }
+        self.change_channel_after_keystrokes(&channel_number);
+        true
+    }
+
+    /// Forget the channel number entered so far, Escape having been pressed, returning
+    /// whether there was one.
+    pub fn cancel_channel_number(&self) -> bool {  // Used in frontend_window.rs
+        if self.channel_number.borrow().is_empty() { return false; }
+        self.channel_number.borrow_mut().clear();
+        self.hide_channel_number();
+        true
+    }
+
+    /// Add a digit to the channel number being entered, from a remote control or the
+    /// keyboard.
     ///
-    /// Displays a dialogue which displays the digits received so far. If there are
-    /// three digits present or there has been a delay of 3 seconds since the last digit
-    /// then the input is assumed to be the channel number the user wants to switch to.
+    /// The digits entered so far are shown. A number that is the start of no channel
+    /// number is rejected straight away. If the number can be the start of no longer
+    /// channel number, or there has been a delay of 3 seconds since the last digit, then
+    /// it is assumed to be the number of the channel the user wants to switch to.
+    pub fn process_digit(&self, digit: u32) {  // Used in frontend_window.rs
+        let text = self.channel_number.borrow().clone() + &digit.to_string();
+        let numbers = self.channel_numbers();
+        if !numbers.iter().any(|(number, _)| number.starts_with(&text)) {
+            self.channel_number.borrow_mut().clear();
+            self.show_no_such_channel(&text);
+            return;
+        }
+        self.channel_number.replace(text.clone());
+        let max_length = numbers.iter().map(|(number, _)| number.len()).max().unwrap_or(1);
+        self.show_channel_number(&text);
+        self.channel_number_entry.set_progress_fraction(1.0 - (text.len() as f64) / (max_length as f64));
+        if !numbers.iter().any(|(number, _)| number.len() > text.len() && number.starts_with(&text)) {
+            self.change_channel_after_keystrokes(&text)
+        } else {
+            glib::timeout_add_seconds_local(3, {
+                let s = self.clone();
+                move || {
+                    if *s.channel_number.borrow() == text {
+                        s.change_channel_after_keystrokes(&text);
+                    }
+                    Continue(false)
+                }
+            });
+        }
+    }
+
+    /// Process a numeric keystroke, most likely from a remote.
     fn process_numeric_keystroke(&self, tk: &TargettedKeystroke) {
         let digit = match tk.keystroke {
             input_event_codes::KEY_NUMERIC_0 => 0,
@@ -318,28 +834,6 @@ impl ControlWindowButton {
             input_event_codes::KEY_NUMERIC_9 => 9,
             x => panic!("Got a keystroke that it is impossible to get at this point: {}", x),
         };
-        let dialog = &self.channel_number_dialog;
-        dialog.show_all();
-        let entry = &self.channel_number_entry;
-        let max_length = entry.get_max_length() as usize;
-        let fraction = |s: &str| -> f64 { 1.0 - (s.len() as f64) / (max_length as f64) };
-        let text = entry.get_text().to_string() + &digit.to_string();
-        entry.set_text(&text);
-        entry.set_progress_fraction(fraction(&text));
-        if text.len() >= max_length {
-            self.change_channel_after_keystrokes(&text)
-        } else {
-            glib::timeout_add_seconds_local(3, {
-                let s = self.clone();
-                let e = entry.clone();
-                let t = text.clone();
-                move || {
-                    if e.get_text() == t {
-                        s.change_channel_after_keystrokes(&t);
-                    }
-                    Continue(false)
-                }
-            });
-        }
+        self.process_digit(digit);
     }
 }
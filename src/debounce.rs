@@ -0,0 +1,105 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Coalescing bursts of device events.
+//!
+//! Flaky USB tuners may disconnect and reconnect several times a second. Rather than
+//! act on each event, the devices are looked at again once their events have stopped
+//! for a while, so that only the net result of a burst is acted on.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// The devices, identified by `K`, that have had events, and when the last was.
+#[derive(Debug)]
+pub struct Debouncer<K> {
+    window: Duration,
+    last_events: BTreeMap<K, Instant>,
+}
+
+impl<K: Clone + Ord> Debouncer<K> {
+    /// Devices are quiet once they have had no events for `window`.
+    pub fn new(window: Duration) -> Debouncer<K> {
+        Debouncer{window, last_events: BTreeMap::new()}
+    }
+
+    /// Record an event for device `key` at `now`.
+    pub fn record(&mut self, key: K, now: Instant) {
+        self.last_events.insert(key, now);
+    }
+
+    /// Return, in order, the devices that have been quiet since their events as of `now`,
+    /// forgetting them.
+    pub fn take_quiet(&mut self, now: Instant) -> Vec<K> {
+        let window = self.window;
+        let quiet = self.last_events.iter()
+            .filter(|(_, last_event)| now.saturating_duration_since(**last_event) >= window)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in &quiet {
+            self.last_events.remove(key);
+        }
+        quiet
+    }
+
+    /// How long after `now` the next device will be quiet, None if no device has had
+    /// events.
+    pub fn time_to_next_quiet(&self, now: Instant) -> Option<Duration> {
+        self.last_events.values()
+            .map(|last_event| (*last_event + self.window).saturating_duration_since(now))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_of_events_gives_one_quiet_device() {
+        let window = Duration::from_secs(2);
+        let mut debouncer = Debouncer::new(window);
+        let start = Instant::now();
+        for i in 0..20 {
+            debouncer.record(1u8, start + Duration::from_millis(i * 50));
+        }
+        let last_event = start + Duration::from_millis(19 * 50);
+        assert_eq!(debouncer.take_quiet(last_event + Duration::from_millis(1999)), vec![]);
+        assert_eq!(debouncer.time_to_next_quiet(last_event), Some(window));
+        assert_eq!(debouncer.take_quiet(last_event + window), vec![1]);
+        assert_eq!(debouncer.take_quiet(last_event + window * 2), vec![]);
+        assert_eq!(debouncer.time_to_next_quiet(last_event + window), None);
+    }
+
+    #[test]
+    fn devices_are_quiet_independently() {
+        let window = Duration::from_secs(2);
+        let mut debouncer = Debouncer::new(window);
+        let start = Instant::now();
+        debouncer.record(3u8, start);
+        debouncer.record(0u8, start + Duration::from_secs(1));
+        assert_eq!(debouncer.time_to_next_quiet(start), Some(window));
+        assert_eq!(debouncer.take_quiet(start + window), vec![3]);
+        assert_eq!(debouncer.time_to_next_quiet(start + window), Some(Duration::from_secs(1)));
+        debouncer.record(3u8, start + Duration::from_secs(3));
+        assert_eq!(debouncer.take_quiet(start + Duration::from_secs(5)), vec![0, 3]);
+    }
+}
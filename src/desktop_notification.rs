@@ -0,0 +1,70 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Desktop notifications using the org.freedesktop.Notifications D-Bus interface.
+//!
+//! Notifications are a nicety: without a session bus or a notification dæmon nothing
+//! happens, and no error is reported.
+
+use std::time::Duration;
+
+use dbus::arg::PropMap;
+use dbus::blocking::Connection;
+
+const BUS_NAME: &str = "org.freedesktop.Notifications";
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+/// How long to wait for the notification dæmon to reply.
+const METHOD_CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Show a notification on the desktop, returning whether it was delivered.
+pub fn send_notification(summary: &str, body: &str) -> bool {
+    let connection = match Connection::new_session() {
+        Ok(connection) => connection,
+        Err(_) => return false,
+    };
+    let proxy = connection.with_proxy(BUS_NAME, OBJECT_PATH, METHOD_CALL_TIMEOUT);
+    let result: Result<(u32,), _> = proxy.method_call(
+        INTERFACE,
+        "Notify",
+        ("Me TV", 0u32, "me-tv", summary, body, Vec::<&str>::new(), PropMap::new(), -1i32),
+    );
+    result.is_ok()
+}
+
+/// The body of the notification about a finished, or failed, recording.
+pub fn recording_notification_body(channel: &str, captured_seconds: u64, output_path: &str) -> String {
+    format!("{}: {}:{:02} captured to {}", channel, captured_seconds / 60, captured_seconds % 60, output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_has_channel_duration_and_output() {
+        assert_eq!(
+            recording_notification_body("BBC NEWS", 3725, "/tmp/news.mp4"),
+            "BBC NEWS: 62:05 captured to /tmp/news.mp4"
+        );
+    }
+}
@@ -0,0 +1,62 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use gtk;
+use gtk::prelude::*;
+
+use crate::frontend_manager;
+
+/// The text to show for the device history, most recent event first.
+fn history_text() -> String {
+    let history = frontend_manager::device_history();
+    if history.is_empty() {
+        "No frontends have appeared or disappeared.".to_string()
+    } else {
+        history.events().rev().map(|event| event.line()).collect::<Vec<_>>().join("\n")
+    }
+}
+
+fn create(parent: Option<&gtk::ApplicationWindow>) -> gtk::Dialog {
+    let dialog = gtk::Dialog::with_buttons(
+        Some("Me TV Device Events"),
+        parent,
+        gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &[("Close", gtk::ResponseType::Close)],
+    );
+    let label = gtk::Label::new(Some(&history_text()));
+    label.set_selectable(true);
+    label.set_xalign(0.0);
+    label.set_yalign(0.0);
+    let scrolled_window = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    scrolled_window.set_min_content_width(600);
+    scrolled_window.set_min_content_height(300);
+    scrolled_window.add(&label);
+    dialog.get_content_area().pack_start(&scrolled_window, true, true, 10);
+    dialog
+}
+
+/// Present the list of frontends appearing and disappearing in a non-modal way, so that
+/// a USB tuner dropping out can be seen.
+pub fn present(parent: Option<&gtk::ApplicationWindow>) {
+    let dialog = create(parent);
+    dialog.connect_response(|d, _| unsafe { d.destroy(); });
+    dialog.show_all();
+}
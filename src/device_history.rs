@@ -0,0 +1,222 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! A history of frontends appearing and disappearing.
+//!
+//! Intermittent USB problems show as a frontend disappearing and reappearing, often
+//! unnoticed. Me TV keeps the most recent of these events, in memory for the GUI to show
+//! and in a file in the runtime directory so that a recording that failed can say
+//! whether the tuner dropped out.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+
+use crate::frontend_lock::lock_directory;
+use crate::frontends::FrontendId;
+
+/// How many events are kept.
+pub const HISTORY_LENGTH: usize = 200;
+
+/// What happened to a frontend.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeviceChange {
+    Appeared,
+    Disappeared,
+}
+
+impl fmt::Display for DeviceChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DeviceChange::Appeared => "appeared",
+            DeviceChange::Disappeared => "disappeared",
+        })
+    }
+}
+
+impl FromStr for DeviceChange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DeviceChange, String> {
+        match s {
+            "appeared" => Ok(DeviceChange::Appeared),
+            "disappeared" => Ok(DeviceChange::Disappeared),
+            _ => Err(format!("'{}' is not a device change.", s)),
+        }
+    }
+}
+
+/// A frontend appearing or disappearing, with the name it was shown with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceEvent {
+    pub time: DateTime<Local>,
+    pub change: DeviceChange,
+    pub fei: FrontendId,
+    pub name: String,
+}
+
+impl DeviceEvent {
+    /// A one line human readable description, for listing the events.
+    pub fn line(&self) -> String {
+        format!("{} {} {}", self.time.format("%Y-%m-%d %H:%M:%S"), self.name, self.change)
+    }
+
+    /// The line of the history file for the event: the time, change, frontend and name
+    /// separated by tabs.
+    fn record(&self) -> String {
+        format!("{}\t{}\t{}\t{}", self.time.to_rfc3339(), self.change, self.fei, self.name)
+    }
+
+    /// The event of a line of the history file, None if it is not one.
+    fn from_record(line: &str) -> Option<DeviceEvent> {
+        let mut fields = line.splitn(4, '\t');
+        let time = DateTime::parse_from_rfc3339(fields.next()?).ok()?.with_timezone(&Local);
+        let change = fields.next()?.parse().ok()?;
+        let fei = fields.next()?.parse().ok()?;
+        let name = fields.next()?.to_string();
+        Some(DeviceEvent{time, change, fei, name})
+    }
+}
+
+/// The most recent `HISTORY_LENGTH` device events, oldest first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceHistory {
+    events: VecDeque<DeviceEvent>,
+}
+
+impl DeviceHistory {
+    pub fn new() -> DeviceHistory { DeviceHistory::default() }
+
+    /// Add an event, forgetting the oldest if there are too many.
+    pub fn record(&mut self, event: DeviceEvent) {
+        if self.events.len() == HISTORY_LENGTH {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// The events, oldest first.
+    pub fn events(&self) -> impl DoubleEndedIterator<Item = &DeviceEvent> {
+        self.events.iter()
+    }
+
+    /// The events at or after `time`, oldest first.
+    pub fn since(&self, time: DateTime<Local>) -> Vec<&DeviceEvent> {
+        self.events.iter().filter(|event| event.time >= time).collect()
+    }
+
+    pub fn is_empty(&self) -> bool { self.events.is_empty() }
+
+    /// Read a history file, lines that are not events being ignored. A file that does not
+    /// exist is an empty history.
+    pub fn read(path: &Path) -> io::Result<DeviceHistory> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(DeviceHistory::new()),
+            Err(e) => return Err(e),
+        };
+        let mut history = DeviceHistory::new();
+        for event in contents.lines().filter_map(DeviceEvent::from_record) {
+            history.record(event);
+        }
+        Ok(history)
+    }
+
+    /// Write the history file, replacing it as a whole so that a reader never sees part
+    /// of it.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let contents = self.events.iter().map(|event| event.record() + "\n").collect::<String>();
+        let temporary = path.with_extension("new");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, path)
+    }
+}
+
+/// The path of the history file Me TV keeps in the runtime directory.
+pub fn history_path() -> PathBuf {
+    lock_directory().join("device-events")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn event(minute: u32, change: DeviceChange) -> DeviceEvent {
+        DeviceEvent{
+            time: Local.ymd(2020, 10, 14).and_hms(21, minute, 0),
+            change,
+            fei: FrontendId{adapter: 0, frontend: 0},
+            name: "Sony CXD2837ER (Hauppauge dualHD) — adapter0:frontend0".to_string(),
+        }
+    }
+
+    #[test]
+    fn event_line_is_readable() {
+        assert_eq!(event(3, DeviceChange::Disappeared).line(), "2020-10-14 21:03:00 Sony CXD2837ER (Hauppauge dualHD) — adapter0:frontend0 disappeared");
+    }
+
+    #[test]
+    fn oldest_events_are_forgotten() {
+        let mut history = DeviceHistory::new();
+        for i in 0..HISTORY_LENGTH + 10 {
+            let mut event = event(0, DeviceChange::Appeared);
+            event.name = i.to_string();
+            history.record(event);
+        }
+        assert_eq!(history.events().count(), HISTORY_LENGTH);
+        assert_eq!(history.events().next().unwrap().name, "10");
+        assert_eq!(history.events().last().unwrap().name, (HISTORY_LENGTH + 9).to_string());
+    }
+
+    #[test]
+    fn events_since_a_time_are_found() {
+        let mut history = DeviceHistory::new();
+        history.record(event(0, DeviceChange::Appeared));
+        history.record(event(30, DeviceChange::Disappeared));
+        history.record(event(31, DeviceChange::Appeared));
+        let since = history.since(Local.ymd(2020, 10, 14).and_hms(21, 30, 0));
+        assert_eq!(since, vec![&event(30, DeviceChange::Disappeared), &event(31, DeviceChange::Appeared)]);
+    }
+
+    #[test]
+    fn history_file_is_written_and_read_back() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("me-tv").join("device-events");
+        assert_eq!(DeviceHistory::read(&path).unwrap(), DeviceHistory::new());
+        let mut history = DeviceHistory::new();
+        history.record(event(0, DeviceChange::Appeared));
+        history.record(event(1, DeviceChange::Disappeared));
+        history.write(&path).unwrap();
+        assert_eq!(DeviceHistory::read(&path).unwrap(), history);
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "not an event\n").unwrap();
+        assert_eq!(DeviceHistory::read(&path).unwrap(), history);
+    }
+}
@@ -0,0 +1,230 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Decoding the strings of the DVB service information, the names and descriptions of
+//! the EIT and the SDT, ETSI EN 300 468 annex A: the first bytes of a string select the
+//! character table, ISO/IEC 6937 if they do not, and the control codes mark emphasis and
+//! line breaks.
+
+/// The character tables a DVB string can be in, ETSI EN 300 468 annex A.2.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum CharacterTable {
+    Iso6937,  // Table 00, the default.
+    Iso8859(u8),
+    Ucs2,
+    Utf8,
+}
+
+/// The character table selected by the first bytes of a DVB string, and the text after
+/// the selection. Selections not decoded here, such as the East Asian ones, are treated
+/// as the default table.
+fn character_table(data: &[u8]) -> (CharacterTable, &[u8]) {
+    match data.first() {
+        None => (CharacterTable::Iso6937, data),
+        Some(part @ 0x01..=0x0b) => (CharacterTable::Iso8859(part + 4), &data[1..]),
+        Some(0x10) => match data.get(1..3) {
+            Some(&[0x00, part]) => (CharacterTable::Iso8859(part), &data[3..]),
+            _ => (CharacterTable::Iso6937, data.get(3..).unwrap_or(&[])),
+        },
+        Some(0x11) => (CharacterTable::Ucs2, &data[1..]),
+        Some(0x15) => (CharacterTable::Utf8, &data[1..]),
+        Some(b) if *b < 0x20 => (CharacterTable::Iso6937, &data[1..]),
+        Some(_) => (CharacterTable::Iso6937, data),
+    }
+}
+
+/// The characters 0xa0 to 0xff of table 00, the non-spacing diacritical marks 0xc1 to
+/// 0xcf being given here in their spacing forms for when there is no letter after them.
+const TABLE_00_UPPER: &str = concat!(
+    "\u{a0}¡¢£$¥#§¤‘“«←↑→↓",
+    "°±²³×µ¶·÷’”»¼½¾¿",
+    "\u{fffd}`´ˆ˜¯˘˙¨\u{fffd}˚¸\u{fffd}˝˛ˇ",
+    "―¹®©™♪¬¦\u{fffd}\u{fffd}\u{fffd}\u{fffd}⅛⅜⅝⅞",
+    "ΩÆĐªĦ\u{fffd}ĲĿŁØŒºÞŦŊŉ",
+    "ĸæđðħıĳŀłøœßþŧŋ\u{ad}",
+);
+
+/// The combining mark for a non-spacing diacritical mark of table 00.
+fn diacritical_mark(byte: u8) -> Option<char> {
+    Some(match byte {
+        0xc1 => '\u{300}',  // Grave.
+        0xc2 => '\u{301}',  // Acute.
+        0xc3 => '\u{302}',  // Circumflex.
+        0xc4 => '\u{303}',  // Tilde.
+        0xc5 => '\u{304}',  // Macron.
+        0xc6 => '\u{306}',  // Breve.
+        0xc7 => '\u{307}',  // Dot above.
+        0xc8 => '\u{308}',  // Diaeresis.
+        0xca => '\u{30a}',  // Ring above.
+        0xcb => '\u{327}',  // Cedilla.
+        0xcd => '\u{30b}',  // Double acute.
+        0xce => '\u{328}',  // Ogonek.
+        0xcf => '\u{30c}',  // Caron.
+        _ => return None,
+    })
+}
+
+/// A letter with a diacritical mark as the one precomposed character, for the letters
+/// that have one in the common European languages.
+fn compose(letter: char, mark: char) -> Option<char> {
+    const COMPOSED: &[(char, &str, &str)] = &[
+        ('\u{300}', "AEIOUaeiou", "ÀÈÌÒÙàèìòù"),
+        ('\u{301}', "AEIOUYaeiouyCcNnSsZz", "ÁÉÍÓÚÝáéíóúýĆćŃńŚśŹź"),
+        ('\u{302}', "AEIOUaeiou", "ÂÊÎÔÛâêîôû"),
+        ('\u{303}', "ANOano", "ÃÑÕãñõ"),
+        ('\u{308}', "AEIOUaeiouy", "ÄËÏÖÜäëïöüÿ"),
+        ('\u{30a}', "AaUu", "ÅåŮů"),
+        ('\u{327}', "CcSs", "ÇçŞş"),
+        ('\u{30b}', "OoUu", "ŐőŰű"),
+        ('\u{328}', "AaEe", "ĄąĘę"),
+        ('\u{30c}', "CcEeNnRrSsZz", "ČčĚěŇňŘřŠšŽž"),
+    ];
+    let (_, letters, composed) = COMPOSED.iter().find(|(m, _, _)| *m == mark)?;
+    letters.chars().position(|l| l == letter).and_then(|i| composed.chars().nth(i))
+}
+
+/// Decode text in table 00, ISO/IEC 6937, in which a diacritical mark comes before the
+/// letter it is on.
+fn decode_iso_6937(text: &[u8]) -> String {
+    let mut decoded = String::new();
+    let mut bytes = text.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        match (diacritical_mark(byte), bytes.peek()) {
+            (Some(mark), Some(&&letter)) if letter.is_ascii_alphabetic() => {
+                bytes.next();
+                let letter = char::from(letter);
+                match compose(letter, mark) {
+                    Some(c) => decoded.push(c),
+                    None => { decoded.push(letter); decoded.push(mark); },
+                }
+            },
+            _ if byte >= 0xa0 => decoded.push(TABLE_00_UPPER.chars().nth(usize::from(byte - 0xa0)).unwrap()),
+            _ => decoded.push(char::from(byte)),
+        }
+    }
+    decoded
+}
+
+/// The characters 0xa1 to 0xff of ISO/IEC 8859 part 2, Central European.
+const ISO_8859_2_UPPER: &str = concat!(
+    "Ą˘Ł¤ĽŚ§¨ŠŞŤŹ\u{ad}ŽŻ",
+    "°ą˛ł´ľśˇ¸šşťź˝žż",
+    "ŔÁÂĂÄĹĆÇČÉĘËĚÍÎĎ",
+    "ĐŃŇÓÔŐÖ×ŘŮÚŰÜÝŢß",
+    "ŕáâăäĺćçčéęëěíîď",
+    "đńňóôőö÷řůúűüýţ˙",
+);
+
+/// Decode a character of ISO/IEC 8859 part `part`. Parts 1, 2 (Central European), 5
+/// (Cyrillic), 7 (Greek), 9 (Turkish) and 15 (Latin-9) are decoded, the others being
+/// treated as part 1.
+fn decode_iso_8859(part: u8, byte: u8) -> char {
+    let mapped = match (part, byte) {
+        (_, 0x00..=0xa0) => None,
+        (2, _) => ISO_8859_2_UPPER.chars().nth(usize::from(byte - 0xa1)),
+        (5, 0xad) => None,
+        (5, 0xf0) => Some('№'),
+        (5, 0xfd) => Some('§'),
+        (5, _) => std::char::from_u32(u32::from(byte) + 0x360),
+        (7, 0xa1) => Some('‘'),
+        (7, 0xa2) => Some('’'),
+        (7, 0xa4) => Some('€'),
+        (7, 0xa5) => Some('₯'),
+        (7, 0xaa) => Some('ͺ'),
+        (7, 0xaf) => Some('―'),
+        (7, 0xb7) | (7, 0xbb) | (7, 0xbd) => None,
+        (7, 0xb4..=0xfe) => std::char::from_u32(u32::from(byte) + 0x2d0),
+        (9, 0xd0) => Some('Ğ'),
+        (9, 0xdd) => Some('İ'),
+        (9, 0xde) => Some('Ş'),
+        (9, 0xf0) => Some('ğ'),
+        (9, 0xfd) => Some('ı'),
+        (9, 0xfe) => Some('ş'),
+        (15, 0xa4) => Some('€'),
+        (15, 0xa6) => Some('Š'),
+        (15, 0xa8) => Some('š'),
+        (15, 0xb4) => Some('Ž'),
+        (15, 0xb8) => Some('ž'),
+        (15, 0xbc) => Some('Œ'),
+        (15, 0xbd) => Some('œ'),
+        (15, 0xbe) => Some('Ÿ'),
+        _ => None,
+    };
+    mapped.unwrap_or_else(|| char::from(byte))
+}
+
+/// Decode a DVB string, ETSI EN 300 468 annex A.
+///
+/// The default table, ISO/IEC 6937, the commonly used parts of ISO/IEC 8859, UCS-2
+/// and UTF-8 are decoded. The CR/LF control code becomes a newline, the other control
+/// codes, the emphasis ones, are dropped.
+pub fn decode_dvb_text(data: &[u8]) -> String {
+    let (table, text) = character_table(data);
+    let decoded = match table {
+        CharacterTable::Iso6937 => decode_iso_6937(text),
+        CharacterTable::Iso8859(part) => text.iter().map(|b| decode_iso_8859(part, *b)).collect(),
+        CharacterTable::Ucs2 => std::char::decode_utf16(text.chunks_exact(2).map(|pair| (u16::from(pair[0]) << 8) | u16::from(pair[1])))
+            .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+            .collect(),
+        CharacterTable::Utf8 => String::from_utf8_lossy(text).to_string(),
+    };
+    decoded.chars()
+        .filter_map(|c| match c {
+            '\u{8a}' | '\u{e08a}' => Some('\n'),
+            '\u{80}'..='\u{9f}' | '\u{e080}'..='\u{e09f}' => None,  // Emphasis and other control codes.
+            c => Some(c),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_text_is_decoded() {
+        assert_eq!(decode_dvb_text(b"\x15Caf\xc3\xa9"), "Café");
+        assert_eq!(decode_dvb_text(b"\x15Caf\xc3\xa9\xee\x82\x8aBar"), "Café\nBar");
+    }
+
+    #[test]
+    fn default_table_text_is_decoded() {
+        assert_eq!(decode_dvb_text(b"Caf\xc2e\x8aBar"), "Café\nBar");
+        assert_eq!(decode_dvb_text(b"\x86Stra\xfbe\x87 \xc8uber \xc3Ile-de-France"), "Straße über Île-de-France");
+        assert_eq!(decode_dvb_text(b"Pr\xcfe"), "Prě");
+        assert_eq!(decode_dvb_text(b"\xc8x \xa310"), "x\u{308} £10");
+    }
+
+    #[test]
+    fn iso_8859_text_is_decoded() {
+        assert_eq!(decode_dvb_text(b"\x10\x00\x01Caf\xe9"), "Café");
+        assert_eq!(decode_dvb_text(b"\x10\x00\x0f\xa4 5"), "€ 5");
+        assert_eq!(decode_dvb_text(b"\x01\xbd\xde\xd2\xde\xe1\xe2\xd8"), "Новости");
+        assert_eq!(decode_dvb_text(b"\x05\xddstanbul"), "İstanbul");
+        assert_eq!(decode_dvb_text(b"\x10\x00\x02Zpr\xe1vy \xbelut\xfd k\xf9\xf2"), "Zprávy žlutý kůň");
+        assert_eq!(decode_dvb_text(b"\x03\xc5\xe9\xe4\xde\xf3\xe5\xe9\xf2"), "Ειδήσεις");
+    }
+
+    #[test]
+    fn ucs2_text_is_decoded() {
+        assert_eq!(decode_dvb_text(b"\x11\x00C\x00a\x00f\x00\xe9\xe0\x8a\x04\x1d"), "Café\nН");
+    }
+}
@@ -0,0 +1,671 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Assemble Event Information Table (EIT) sections from a transport stream and
+//! parse them, ETSI EN 300 468 §5.2.4.
+//!
+//! This works on the raw bytes so it can be used from a pad probe on the transport
+//! stream, independently of what the demuxer makes available.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::dvb_text::decode_dvb_text;
+use crate::genres::CONTENT_DESCRIPTOR_TAG;
+
+/// The PID on which EIT sections are transmitted.
+pub const EIT_PID: u16 = 0x12;
+
+/// The table id of the present/following table for the actual transport stream.
+pub const ACTUAL_PRESENT_FOLLOWING: u8 = 0x4e;
+
+/// The table id of the present/following table for other transport streams.
+pub const OTHER_PRESENT_FOLLOWING: u8 = 0x4f;
+
+/// The table ids of the schedule tables, 0x50 to 0x5f for the actual transport stream
+/// and 0x60 to 0x6f for other transport streams.
+pub const SCHEDULE_TABLE_IDS: RangeInclusive<u8> = 0x50..=0x6f;
+
+/// The tag of a parental rating descriptor.
+const PARENTAL_RATING_DESCRIPTOR_TAG: u8 = 0x55;
+
+/// The tag of a content identifier descriptor, of the CRIDs of an event, ETSI TS 102 323
+/// §12.1.
+const CONTENT_IDENTIFIER_DESCRIPTOR_TAG: u8 = 0x76;
+
+/// The CRID types of the CRID of the programme, that of TS 102 323 and that of the UK
+/// D-Book.
+const PROGRAMME_CRID_TYPES: [u8; 2] = [0x01, 0x31];
+
+/// The CRID types of the CRID of the series of the programme, that of TS 102 323 and that
+/// of the UK D-Book.
+const SERIES_CRID_TYPES: [u8; 2] = [0x02, 0x32];
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+/// Collects the sections carried on one PID from a sequence of transport stream packets.
+#[derive(Debug)]
+pub struct SectionAssembler {
+    pid: u16,
+    buffer: Vec<u8>,
+    is_collecting: bool,
+}
+
+impl SectionAssembler {
+    pub fn new(pid: u16) -> SectionAssembler {
+        SectionAssembler { pid, buffer: Vec::new(), is_collecting: false }
+    }
+
+    /// Process a buffer of whole transport stream packets, returning the sections completed.
+    pub fn push_buffer(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        data.chunks(PACKET_SIZE).flat_map(|packet| self.push_packet(packet)).collect()
+    }
+
+    /// Process one transport stream packet, returning the sections completed.
+    pub fn push_packet(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        let mut sections = Vec::new();
+        if packet.len() != PACKET_SIZE || packet[0] != SYNC_BYTE || packet[1] & 0x80 != 0 {
+            return sections;
+        }
+        let pid = (u16::from(packet[1] & 0x1f) << 8) | u16::from(packet[2]);
+        if pid != self.pid {
+            return sections;
+        }
+        let is_unit_start = packet[1] & 0x40 != 0;
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+        if adaptation_field_control & 0x01 == 0 {
+            return sections;  // No payload.
+        }
+        let offset = if adaptation_field_control == 0x03 { 5 + packet[4] as usize } else { 4 };
+        if offset >= PACKET_SIZE {
+            return sections;
+        }
+        let payload = &packet[offset..];
+        if is_unit_start {
+            let pointer = payload[0] as usize;
+            if 1 + pointer > payload.len() {
+                self.buffer.clear();
+                self.is_collecting = false;
+                return sections;
+            }
+            if self.is_collecting {
+                self.buffer.extend_from_slice(&payload[1..1 + pointer]);
+                self.extract_sections(&mut sections);
+            }
+            self.buffer.clear();
+            self.buffer.extend_from_slice(&payload[1 + pointer..]);
+            self.is_collecting = true;
+        } else if self.is_collecting {
+            self.buffer.extend_from_slice(payload);
+        }
+        self.extract_sections(&mut sections);
+        sections
+    }
+
+    fn extract_sections(&mut self, sections: &mut Vec<Vec<u8>>) {
+        while self.buffer.len() >= 3 {
+            if self.buffer[0] == 0xff {
+                // Stuffing, the rest of the packet carries nothing.
+                self.buffer.clear();
+                self.is_collecting = false;
+                break;
+            }
+            let length = (((self.buffer[1] & 0x0f) as usize) << 8 | self.buffer[2] as usize) + 3;
+            if self.buffer.len() < length {
+                break;
+            }
+            sections.push(self.buffer.drain(..length).collect());
+        }
+    }
+}
+
+/// The running status of an event, ETSI EN 300 468 table 6.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RunningStatus {
+    Undefined,
+    NotRunning,
+    StartsInAFewSeconds,
+    Pausing,
+    Running,
+    ServiceOffAir,
+    Reserved(u8),
+}
+
+impl Default for RunningStatus {
+    fn default() -> RunningStatus {
+        RunningStatus::Undefined
+    }
+}
+
+/// Serialised as the value broadcast.
+impl serde::Serialize for RunningStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(u8::from(*self))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for RunningStatus {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<RunningStatus, D::Error> {
+        <u8 as serde::Deserialize>::deserialize(deserializer).map(RunningStatus::from)
+    }
+}
+
+impl From<u8> for RunningStatus {
+    fn from(value: u8) -> RunningStatus {
+        match value {
+            0 => RunningStatus::Undefined,
+            1 => RunningStatus::NotRunning,
+            2 => RunningStatus::StartsInAFewSeconds,
+            3 => RunningStatus::Pausing,
+            4 => RunningStatus::Running,
+            5 => RunningStatus::ServiceOffAir,
+            x => RunningStatus::Reserved(x),
+        }
+    }
+}
+
+impl From<RunningStatus> for u8 {
+    fn from(status: RunningStatus) -> u8 {
+        match status {
+            RunningStatus::Undefined => 0,
+            RunningStatus::NotRunning => 1,
+            RunningStatus::StartsInAFewSeconds => 2,
+            RunningStatus::Pausing => 3,
+            RunningStatus::Running => 4,
+            RunningStatus::ServiceOffAir => 5,
+            RunningStatus::Reserved(x) => x,
+        }
+    }
+}
+
+/// An event from an EIT section.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EitEvent {
+    pub event_id: u16,
+    pub start_time: Option<NaiveDateTime>,  // UTC, None if undefined.
+    pub duration_seconds: u32,
+    pub running_status: RunningStatus,
+    pub free_ca_mode: bool,
+    pub title: Option<String>,  // Those of the first language of texts.
+    pub description: Option<String>,
+    pub extended_description: Option<String>,  // From the extended event descriptors, in order.
+    pub texts: Vec<EventText>,  // In each language, in the order the languages are first broadcast.
+    pub content: Vec<u8>,  // The content nibbles of the content descriptors, level 1 in the high four bits.
+    pub ratings: Vec<ParentalRating>,  // From the parental rating descriptors, in order.
+    pub programme_crid: Option<String>,  // As broadcast, often relative to the default authority of the service, e.g. "/KFNVXV".
+    pub series_crid: Option<String>,  // As broadcast, the same for all the episodes of a series.
+}
+
+/// The title and descriptions of an event in one language, from the short and extended
+/// event descriptors of the language, empty if there are none.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EventText {
+    pub language: String,  // The ISO 639-2 code as broadcast, e.g. "cym" or "wel".
+    pub title: String,
+    pub description: String,
+    pub extended_description: String,
+}
+
+/// The rating of an event in a country, from a parental rating descriptor, ETSI EN 300 468
+/// §6.2.28.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ParentalRating {
+    pub country: String,  // The ISO 3166 alpha-3 code, e.g. "GBR", or a code of a group of countries.
+    pub rating: u8,  // 0x01 to 0x0f the minimum age less three, 0x00 undefined, the rest defined by the broadcaster.
+}
+
+impl ParentalRating {
+    /// The minimum age the rating is, if it is one.
+    pub fn minimum_age(&self) -> Option<u8> {
+        match self.rating {
+            0x01..=0x0f => Some(self.rating + 3),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParentalRating {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.minimum_age(), self.rating) {
+            (Some(age), _) => write!(f, "{}+ in {}", age, self.country),
+            (None, 0) => write!(f, "unrated in {}", self.country),
+            (None, rating) => write!(f, "rated {:#04x} by the broadcaster in {}", rating, self.country),
+        }
+    }
+}
+
+/// A parsed EIT section.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EitSection {
+    pub table_id: u8,
+    pub service_id: u16,
+    pub version_number: u8,
+    pub section_number: u8,
+    pub last_section_number: u8,
+    pub transport_stream_id: u16,
+    pub original_network_id: u16,
+    pub segment_last_section_number: u8,
+    pub last_table_id: u8,
+    pub events: Vec<EitEvent>,
+}
+
+/// The CRC used by MPEG-2 sections. Computed over a whole section including its CRC
+/// the result is zero.
+pub fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= u32::from(*byte) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn bcd(value: u8) -> u32 {
+    u32::from(value >> 4) * 10 + u32::from(value & 0x0f)
+}
+
+/// Decode the 40 bit MJD and BCD UTC time used in EIT.
+fn decode_start_time(data: &[u8]) -> Option<NaiveDateTime> {
+    if data.iter().all(|b| *b == 0xff) {
+        return None;
+    }
+    let mjd = (i64::from(data[0]) << 8) | i64::from(data[1]);
+    let date = NaiveDate::from_ymd(1858, 11, 17) + Duration::days(mjd);
+    date.and_hms_opt(bcd(data[2]), bcd(data[3]), bcd(data[4]))
+}
+
+/// What the short and extended event descriptors of an event in one language have: the
+/// title and description of the first short event descriptor, and the numbers and texts
+/// of the extended event descriptors.
+struct LanguageDescriptors {
+    language: String,
+    short: Option<(String, String)>,
+    extended: Vec<(u8, String)>,
+}
+
+impl LanguageDescriptors {
+    /// Those of the language with the ISO 639-2 code `code` of `languages`, added if there
+    /// are none yet.
+    fn of<'a>(languages: &'a mut Vec<LanguageDescriptors>, code: &[u8]) -> &'a mut LanguageDescriptors {
+        let language = code.iter().map(|byte| char::from(*byte)).collect::<String>();
+        let index = match languages.iter().position(|descriptors| descriptors.language == language) {
+            Some(index) => index,
+            None => {
+                languages.push(LanguageDescriptors { language, short: None, extended: Vec::new() });
+                languages.len() - 1
+            },
+        };
+        &mut languages[index]
+    }
+
+    /// The title and descriptions, the extended descriptions being joined in order.
+    fn text(mut self) -> EventText {
+        self.extended.sort_by_key(|(number, _)| *number);
+        let (title, description) = self.short.unwrap_or_default();
+        EventText { language: self.language, title, description, extended_description: self.extended.into_iter().map(|(_, text)| text).collect() }
+    }
+}
+
+/// Parse an EIT section, checking its structure and CRC.
+pub fn parse_eit_section(section: &[u8]) -> Result<EitSection, String> {
+    if section.len() < 18 {
+        return Err(format!("EIT section too short: {} bytes.", section.len()));
+    }
+    let table_id = section[0];
+    if table_id < 0x4e || table_id > 0x6f {
+        return Err(format!("Table id {:#04x} is not an EIT.", table_id));
+    }
+    let length = (((section[1] & 0x0f) as usize) << 8 | section[2] as usize) + 3;
+    if length != section.len() {
+        return Err(format!("EIT section length {} does not match the data length {}.", length, section.len()));
+    }
+    if crc32_mpeg2(section) != 0 {
+        return Err("EIT section has an incorrect CRC.".to_string());
+    }
+    let u16_at = |i: usize| (u16::from(section[i]) << 8) | u16::from(section[i + 1]);
+    let mut events = Vec::new();
+    let end = length - 4;
+    let mut i = 14;
+    while i + 12 <= end {
+        let event_id = u16_at(i);
+        let start_time = decode_start_time(&section[i + 2..i + 7]);
+        let duration_seconds = bcd(section[i + 7]) * 3600 + bcd(section[i + 8]) * 60 + bcd(section[i + 9]);
+        let running_status = RunningStatus::from(section[i + 10] >> 5);
+        let free_ca_mode = section[i + 10] & 0x10 != 0;
+        let descriptors_length = (((section[i + 10] & 0x0f) as usize) << 8) | section[i + 11] as usize;
+        i += 12;
+        if i + descriptors_length > end {
+            return Err(format!("Descriptors of event {} overrun the section.", event_id));
+        }
+        let mut languages = Vec::<LanguageDescriptors>::new();
+        let mut content = Vec::new();
+        let mut ratings = Vec::new();
+        let (mut programme_crid, mut series_crid) = (None, None);
+        let mut j = i;
+        while j + 2 <= i + descriptors_length {
+            let tag = section[j];
+            let descriptor_length = section[j + 1] as usize;
+            let body = &section[j + 2..(j + 2 + descriptor_length).min(i + descriptors_length)];
+            // Short event descriptor: language, name length, name, text length, text.
+            if tag == 0x4d && body.len() >= 4 {
+                let name_length = body[3] as usize;
+                if 4 + name_length < body.len() {
+                    let text_length = body[4 + name_length] as usize;
+                    let text_start = 5 + name_length;
+                    let description = body.get(text_start..text_start + text_length).map(decode_dvb_text).unwrap_or_default();
+                    let descriptors = LanguageDescriptors::of(&mut languages, &body[..3]);
+                    if descriptors.short.is_none() {
+                        descriptors.short = Some((decode_dvb_text(&body[4..4 + name_length]), description));
+                    }
+                }
+            }
+            // Extended event descriptor: number and last number, language, items length,
+            // items, text length, text.
+            if tag == 0x4e && body.len() >= 6 {
+                let items_length = body[4] as usize;
+                if let Some(&text_length) = body.get(5 + items_length) {
+                    let text_start = 6 + items_length;
+                    let text_end = text_start + text_length as usize;
+                    if text_end <= body.len() {
+                        LanguageDescriptors::of(&mut languages, &body[1..4]).extended.push((body[0] >> 4, decode_dvb_text(&body[text_start..text_end])));
+                    }
+                }
+            }
+            // Content descriptor: pairs of the content nibbles and a user byte.
+            if tag == CONTENT_DESCRIPTOR_TAG {
+                content.extend(body.chunks_exact(2).map(|pair| pair[0]));
+            }
+            // Parental rating descriptor: three bytes of the country code then the rating.
+            if tag == PARENTAL_RATING_DESCRIPTOR_TAG {
+                ratings.extend(body.chunks_exact(4).map(|rating| ParentalRating {
+                    country: rating[..3].iter().map(|byte| char::from(*byte)).collect(),
+                    rating: rating[3],
+                }));
+            }
+            // Content identifier descriptor: CRIDs, each a type and location byte then,
+            // if it is carried in the descriptor, its length and the CRID, else a
+            // reference to a CRID of a table Me TV does not read.
+            if tag == CONTENT_IDENTIFIER_DESCRIPTOR_TAG {
+                let mut k = 0;
+                while k < body.len() {
+                    let (crid_type, crid_location) = (body[k] >> 2, body[k] & 0x03);
+                    let crid = match crid_location {
+                        0 => {
+                            let crid_length = match body.get(k + 1) { Some(length) => usize::from(*length), None => break };
+                            let crid = match body.get(k + 2..k + 2 + crid_length) { Some(crid) => crid, None => break };
+                            k += 2 + crid_length;
+                            Some(decode_dvb_text(crid))
+                        },
+                        1 => {
+                            k += 3;
+                            None
+                        },
+                        _ => break,
+                    };
+                    if let Some(crid) = crid.filter(|crid| !crid.is_empty()) {
+                        if PROGRAMME_CRID_TYPES.contains(&crid_type) {
+                            programme_crid = Some(crid);
+                        } else if SERIES_CRID_TYPES.contains(&crid_type) {
+                            series_crid = Some(crid);
+                        }
+                    }
+                }
+            }
+            j += 2 + descriptor_length;
+        }
+        i += descriptors_length;
+        let texts = languages.into_iter()
+            .map(|descriptors| (descriptors.short.is_some(), descriptors.text()))
+            .collect::<Vec<_>>();
+        // The title and descriptions of the event are those of the first language.
+        let (title, description, extended_description) = match texts.first() {
+            Some((has_title, text)) => (
+                Some(text.title.clone()).filter(|_| *has_title),
+                Some(text.description.clone()).filter(|_| *has_title),
+                Some(text.extended_description.clone()).filter(|description| !description.is_empty()),
+            ),
+            None => (None, None, None),
+        };
+        let texts = texts.into_iter().map(|(_, text)| text).collect();
+        events.push(EitEvent { event_id, start_time, duration_seconds, running_status, free_ca_mode, title, description, extended_description, texts, content, ratings,
+            programme_crid, series_crid,
+        });
+    }
+    Ok(EitSection {
+        table_id,
+        service_id: u16_at(3),
+        version_number: (section[5] >> 1) & 0x1f,
+        section_number: section[6],
+        last_section_number: section[7],
+        transport_stream_id: u16_at(8),
+        original_network_id: u16_at(10),
+        segment_last_section_number: section[12],
+        last_table_id: section[13],
+        events,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build an EIT section for a service with one event with a short event descriptor.
+    fn create_section(section_number: u8, event_id: u16, running_status: u8, title: &str) -> Vec<u8> {
+        let mut descriptor = vec![0x4d, 0, b'e', b'n', b'g', title.len() as u8];
+        descriptor.extend_from_slice(title.as_bytes());
+        descriptor.push(0);
+        descriptor[1] = (descriptor.len() - 2) as u8;
+        let mut event = vec![(event_id >> 8) as u8, event_id as u8];
+        // 2020-10-14 is MJD 59136 (0xe700), 21:00:00, duration 01:30:00.
+        event.extend_from_slice(&[0xe7, 0x00, 0x21, 0x00, 0x00, 0x01, 0x30, 0x00]);
+        event.push((running_status << 5) | ((descriptor.len() >> 8) as u8));
+        event.push(descriptor.len() as u8);
+        event.extend_from_slice(&descriptor);
+        let mut section = vec![ACTUAL_PRESENT_FOLLOWING, 0, 0, 0x10, 0x44, 0xc1, section_number, 1, 0x30, 0x04, 0x23, 0x3a, 1, ACTUAL_PRESENT_FOLLOWING];
+        section.extend_from_slice(&event);
+        let length = section.len() + 4 - 3;
+        section[1] = 0xf0 | (length >> 8) as u8;
+        section[2] = length as u8;
+        let crc = crc32_mpeg2(&section);
+        section.extend_from_slice(&crc.to_be_bytes());
+        section
+    }
+
+    /// Build an EIT section for a service with one event with the descriptors `descriptors`.
+    fn create_section_with_descriptors(descriptors: &[u8]) -> Vec<u8> {
+        let mut section = create_section(0, 1, 4, "");
+        section.truncate(14 + 10);
+        section.push((4 << 5) | (descriptors.len() >> 8) as u8);
+        section.push(descriptors.len() as u8);
+        section.extend_from_slice(descriptors);
+        let length = section.len() + 4 - 3;
+        section[1] = 0xf0 | (length >> 8) as u8;
+        section[2] = length as u8;
+        let crc = crc32_mpeg2(&section);
+        section.extend_from_slice(&crc.to_be_bytes());
+        section
+    }
+
+    /// Split a section into transport stream packets on the EIT PID.
+    fn packetise(section: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut payload = vec![0u8];  // Pointer field.
+        payload.extend_from_slice(section);
+        for (index, chunk) in payload.chunks(PACKET_SIZE - 4).enumerate() {
+            let mut packet = vec![SYNC_BYTE, if index == 0 { 0x40 } else { 0x00 }, EIT_PID as u8, 0x10 | (index as u8 & 0x0f)];
+            packet.extend_from_slice(chunk);
+            packet.resize(PACKET_SIZE, 0xff);
+            result.extend_from_slice(&packet);
+        }
+        result
+    }
+
+    #[test]
+    fn crc_of_section_with_crc_is_zero() {
+        assert_eq!(crc32_mpeg2(&create_section(0, 1, 4, "News")), 0);
+    }
+
+    #[test]
+    fn section_is_parsed() {
+        let section = parse_eit_section(&create_section(0, 0x1234, 4, "Newsnight")).unwrap();
+        assert_eq!(section.table_id, ACTUAL_PRESENT_FOLLOWING);
+        assert_eq!(section.service_id, 0x1044);
+        assert_eq!(section.section_number, 0);
+        assert_eq!(section.transport_stream_id, 0x3004);
+        assert_eq!(section.original_network_id, 0x233a);
+        assert_eq!(section.events, vec![EitEvent {
+            event_id: 0x1234,
+            start_time: Some(NaiveDate::from_ymd(2020, 10, 14).and_hms(21, 0, 0)),
+            duration_seconds: 5400,
+            running_status: RunningStatus::Running,
+            free_ca_mode: false,
+            title: Some("Newsnight".to_string()),
+            description: Some("".to_string()),
+            extended_description: None,
+            texts: vec![EventText { language: "eng".to_string(), title: "Newsnight".to_string(), ..EventText::default() }],
+            content: Vec::new(),
+            ratings: Vec::new(),
+            programme_crid: None,
+            series_crid: None,
+        }]);
+        assert_eq!(section.segment_last_section_number, 1);
+        assert_eq!(section.last_table_id, ACTUAL_PRESENT_FOLLOWING);
+    }
+
+    #[test]
+    fn extended_descriptions_are_joined_in_order() {
+        // Two extended event descriptors, the second first, the first with an item.
+        let descriptors: Vec<u8> = [
+            &[0x4e, 0x0b, 0x11, b'e', b'n', b'g', 0x00, 0x05][..], b" end.",
+            &[0x4e, 0x13, 0x01, b'e', b'n', b'g', 0x07, 0x04][..], b"Cast", &[0x01][..], b"X", &[0x06][..], b"Start,",
+        ].concat();
+        let section = create_section_with_descriptors(&descriptors);
+        let event = &parse_eit_section(&section).unwrap().events[0];
+        assert_eq!(event.title, None);
+        assert_eq!(event.extended_description, Some("Start, end.".to_string()));
+    }
+
+    #[test]
+    fn texts_are_kept_in_each_language() {
+        // Short event descriptors in Welsh and English, a second Welsh one, and an extended
+        // event descriptor in English.
+        let short = |language: &[u8], title: &[u8], text: &[u8]| {
+            let mut descriptor = vec![0x4d, (5 + title.len() + text.len()) as u8];
+            descriptor.extend_from_slice(language);
+            descriptor.push(title.len() as u8);
+            descriptor.extend_from_slice(title);
+            descriptor.push(text.len() as u8);
+            descriptor.extend_from_slice(text);
+            descriptor
+        };
+        let descriptors: Vec<u8> = [
+            short(b"cym", b"Newyddion", b"Y newyddion diweddaraf."),
+            short(b"eng", b"News", b"The latest news."),
+            short(b"cym", b"Eto", b""),
+            [&[0x4e, 0x0b, 0x00, b'e', b'n', b'g', 0x00, 0x05][..], b"At 9."].concat(),
+        ].concat();
+        let section = create_section_with_descriptors(&descriptors);
+        let event = &parse_eit_section(&section).unwrap().events[0];
+        assert_eq!(event.title, Some("Newyddion".to_string()));
+        assert_eq!(event.extended_description, None);
+        assert_eq!(event.texts, vec![
+            EventText {
+                language: "cym".to_string(),
+                title: "Newyddion".to_string(),
+                description: "Y newyddion diweddaraf.".to_string(),
+                extended_description: String::new(),
+            },
+            EventText {
+                language: "eng".to_string(),
+                title: "News".to_string(),
+                description: "The latest news.".to_string(),
+                extended_description: "At 9.".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn content_nibbles_are_collected() {
+        // Two content descriptors, the first with two pairs of nibbles and user bytes, the
+        // second truncated.
+        let descriptors = [0x54, 0x04, 0x43, 0x00, 0xb3, 0xff, 0x54, 0x03, 0x41, 0x00, 0x42];
+        let section = create_section_with_descriptors(&descriptors);
+        assert_eq!(parse_eit_section(&section).unwrap().events[0].content, vec![0x43, 0xb3, 0x41]);
+    }
+
+    #[test]
+    fn parental_ratings_are_collected() {
+        // A parental rating descriptor of three countries, the last rated by the
+        // broadcaster, and a truncated rating.
+        let descriptors = [0x55, 0x0e, b'G', b'B', b'R', 0x09, b'I', b'R', b'L', 0x0c, b'F', b'R', b'A', 0x12, b'D', b'E'];
+        let section = create_section_with_descriptors(&descriptors);
+        let ratings = parse_eit_section(&section).unwrap().events[0].ratings.clone();
+        assert_eq!(ratings.iter().map(|rating| rating.to_string()).collect::<Vec<_>>(), vec!["12+ in GBR", "15+ in IRL", "rated 0x12 by the broadcaster in FRA"]);
+        assert_eq!(ratings.iter().map(ParentalRating::minimum_age).collect::<Vec<_>>(), vec![Some(12), Some(15), None]);
+    }
+
+    #[test]
+    fn crids_are_collected() {
+        // A content identifier descriptor in the form UK DVB-T broadcasters use, the D-Book
+        // types, relative CRIDs, a series CRID then a programme CRID, then a recommendation
+        // CRID by reference.
+        let mut descriptors = vec![0x76, 0x00, (0x32 << 2), 0x07];
+        descriptors.extend_from_slice(b"/KFNVXV");
+        descriptors.extend_from_slice(&[(0x31 << 2), 0x08]);
+        descriptors.extend_from_slice(b"/KFNVXVA");
+        descriptors.extend_from_slice(&[(0x33 << 2) | 0x01, 0x00, 0x07]);
+        descriptors[1] = (descriptors.len() - 2) as u8;
+        let section = create_section_with_descriptors(&descriptors);
+        let event = &parse_eit_section(&section).unwrap().events[0];
+        assert_eq!(event.programme_crid.as_deref(), Some("/KFNVXVA"));
+        assert_eq!(event.series_crid.as_deref(), Some("/KFNVXV"));
+    }
+
+    #[test]
+    fn corrupted_section_is_rejected() {
+        let mut section = create_section(0, 1, 4, "News");
+        section[20] ^= 0x01;
+        assert!(parse_eit_section(&section).is_err());
+    }
+
+    #[test]
+    fn sections_are_assembled_across_packets() {
+        let title = "A very long title indeed ".repeat(8);
+        let section = create_section(1, 7, 1, &title);
+        assert!(section.len() > PACKET_SIZE);
+        let mut assembler = SectionAssembler::new(EIT_PID);
+        assert_eq!(assembler.push_buffer(&packetise(&section)), vec![section]);
+    }
+
+    #[test]
+    fn packets_on_other_pids_are_ignored() {
+        let mut packets = packetise(&create_section(0, 1, 4, "News"));
+        packets[2] = 0x11;
+        let mut assembler = SectionAssembler::new(EIT_PID);
+        assert!(assembler.push_buffer(&packets).is_empty());
+    }
+}
@@ -0,0 +1,1148 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The electronic programme guide: what is on now and next on each service, from the
+//! present/following tables of the EIT, ETSI EN 300 468 §5.2.4, and the programmes to
+//! come, from the schedule tables.
+//!
+//! The tables for the actual transport stream cover all the services of the multiplex
+//! being received, so all those services are kept track of, not just the one being
+//! watched. Where the EIT has little, XMLTV listings can fill in the schedule, the EIT
+//! being taken as right where the two disagree.
+//!
+//! Broadcasters running late or early move the present event of the present/following
+//! table when a programme actually starts, so when that happens is kept too, for the
+//! programmes to be marked as having started late or early, or as running late.
+//!
+//! How far ahead, and how many, programmes are kept is limited, so that harvesting and
+//! listings together do not make the programme guide, and so the EPG cache, grow and grow.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::mem;
+
+use chrono::{Duration, Local, NaiveDateTime, TimeZone};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::eit::{EitEvent, EitSection, EventText, ParentalRating, RunningStatus, ACTUAL_PRESENT_FOLLOWING, OTHER_PRESENT_FOLLOWING, SCHEDULE_TABLE_IDS};
+use crate::genres::genres_of_content;
+use crate::languages::preferred_language;
+
+/// How long after a programme has ended it is kept in the programme store.
+const EXPIRY_MARGIN_MINUTES: i64 = 60;
+
+/// How far under the most programmes it keeps the programme store must get before it is
+/// no longer full, so that it is not full and then not with each section received.
+const RETENTION_SLACK: f64 = 0.9;
+
+/// How much a programme of the listings can overlap programmes of the EIT and still be
+/// in the schedule, listings times often being a minute or two out.
+const LISTINGS_OVERLAP_MINUTES: i64 = 5;
+
+/// How far from its scheduled start a programme can start and still be on time.
+const SLIPPAGE_TOLERANCE_MINUTES: i64 = 2;
+
+/// A programme, from an event in the EIT or from XMLTV listings.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Programme {
+    pub event_id: Option<u16>,  // None for a programme from XMLTV listings.
+    #[serde(with = "optional_timestamp")]
+    pub start: Option<NaiveDateTime>,  // UTC, None if undefined.
+    pub duration_seconds: u32,
+    pub running_status: RunningStatus,
+    pub title: String,
+    pub description: String,
+    pub extended_description: String,
+    pub language: String,  // The ISO 639-2 code of the title and descriptions, empty if not known, as for listings.
+    pub texts: Vec<EventText>,  // In each language, when the EIT has more than one, in the order broadcast.
+    pub genres: Vec<String>,  // From the content descriptors, or the categories of the listings.
+    pub ratings: Vec<ParentalRating>,  // From the parental rating descriptors, none for listings.
+    pub programme_crid: Option<String>,  // From the content identifier descriptors, None for listings.
+    pub series_crid: Option<String>,  // From the content identifier descriptors, None for listings.
+}
+
+/// An optional time serialised as seconds since the epoch, UTC.
+mod optional_timestamp {
+    use chrono::NaiveDateTime;
+
+    pub fn serialize<S: serde::Serializer>(time: &Option<NaiveDateTime>, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&time.map(|time| time.timestamp()), serializer)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error> {
+        match <Option<i64> as serde::Deserialize>::deserialize(deserializer)? {
+            Some(seconds) => NaiveDateTime::from_timestamp_opt(seconds, 0)
+                .map(Some)
+                .ok_or_else(|| serde::de::Error::custom(format!("a time out of range, {}", seconds))),
+            None => Ok(None),
+        }
+    }
+}
+
+impl From<&EitEvent> for Programme {
+    fn from(event: &EitEvent) -> Programme {
+        Programme {
+            event_id: Some(event.event_id),
+            start: event.start_time,
+            duration_seconds: event.duration_seconds,
+            running_status: event.running_status,
+            title: event.title.clone().unwrap_or_default(),
+            description: event.description.clone().unwrap_or_default(),
+            extended_description: event.extended_description.clone().unwrap_or_default(),
+            language: event.texts.first().map(|text| text.language.clone()).unwrap_or_default(),
+            texts: if event.texts.len() > 1 { event.texts.clone() } else { Vec::new() },
+            genres: genres_of_content(&event.content),
+            ratings: event.ratings.clone(),
+            programme_crid: event.programme_crid.clone(),
+            series_crid: event.series_crid.clone(),
+        }
+    }
+}
+
+impl Programme {
+    /// When the programme ends, UTC.
+    pub fn end(&self) -> Option<NaiveDateTime> {
+        self.start.map(|start| start + Duration::seconds(i64::from(self.duration_seconds)))
+    }
+
+    /// How far through the programme `now`, UTC, is: 0.0 at the start, 1.0 at the end.
+    pub fn fraction_elapsed(&self, now: NaiveDateTime) -> Option<f64> {
+        let start = self.start?;
+        if self.duration_seconds == 0 {
+            return None;
+        }
+        let fraction = (now - start).num_seconds() as f64 / f64::from(self.duration_seconds);
+        Some(fraction.max(0.0).min(1.0))
+    }
+
+    /// The start and end of the programme in local time, for showing to the user.
+    pub fn times_text(&self) -> Option<String> {
+        let local = |time: NaiveDateTime| Local.from_utc_datetime(&time).format("%H:%M").to_string();
+        Some(format!("{}–{}", local(self.start?), local(self.end()?)))
+    }
+
+    /// The greatest of the minimum ages of the ratings of the programme, for a badge.
+    pub fn minimum_age(&self) -> Option<u8> {
+        self.ratings.iter().filter_map(ParentalRating::minimum_age).max()
+    }
+
+    /// The badge of the minimum age of the programme, e.g. "[12+]", None if it has none.
+    pub fn rating_badge(&self) -> Option<String> {
+        self.minimum_age().map(|age| format!("[{}+]", age))
+    }
+
+    /// The ratings of the programme, for showing to the user, None if it has none.
+    pub fn ratings_text(&self) -> Option<String> {
+        if self.ratings.is_empty() {
+            return None;
+        }
+        Some(format!("Rated {}", self.ratings.iter().map(|rating| rating.to_string()).collect::<Vec<_>>().join(", ")))
+    }
+
+    /// Is the programme of the same series as `other`, as their series CRIDs say? Programmes
+    /// without a series CRID are of no series.
+    pub fn is_same_series(&self, other: &Programme) -> bool {
+        self.series_crid.is_some() && self.series_crid == other.series_crid
+    }
+
+    /// Show the title and descriptions in the first of `languages`, in order of
+    /// preference, the programme has them in, or if it has none of them in the language
+    /// broadcast first, returning whether they changed.
+    pub fn show_language(&mut self, languages: &[String]) -> bool {
+        let index = preferred_language(self.texts.iter().map(|text| text.language.as_str()), languages).unwrap_or(0);
+        match self.texts.get(index) {
+            Some(text) if text.language != self.language => {
+                self.language = text.language.clone();
+                self.title = text.title.clone();
+                self.description = text.description.clone();
+                self.extended_description = text.extended_description.clone();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Is the programme on at any time from `from` to `to`, UTC?
+    pub fn overlaps(&self, from: NaiveDateTime, to: NaiveDateTime) -> bool {
+        match (self.start, self.end()) {
+            (Some(start), Some(end)) => start < to && end > from,
+            _ => false,
+        }
+    }
+
+    /// How long the programme and `other` are both on for.
+    fn overlap(&self, other: &Programme) -> Duration {
+        match (self.start, self.end(), other.start, other.end()) {
+            (Some(start), Some(end), Some(other_start), Some(other_end)) => {
+                let overlap = end.min(other_end) - start.max(other_start);
+                if overlap > Duration::zero() { overlap } else { Duration::zero() }
+            },
+            _ => Duration::zero(),
+        }
+    }
+
+    /// Has the programme ended long enough before `now`, UTC, to be forgotten? A
+    /// programme with no start time never has.
+    fn has_expired(&self, now: NaiveDateTime) -> bool {
+        self.end().map_or(false, |end| end + Duration::minutes(EXPIRY_MARGIN_MINUTES) < now)
+    }
+
+    /// About how many bytes of memory the programme takes, with all it has in it.
+    fn bytes(&self) -> usize {
+        let strings = |strings: &[String]| strings.iter().map(String::capacity).sum::<usize>();
+        mem::size_of::<Programme>()
+            + self.title.capacity() + self.description.capacity() + self.extended_description.capacity()
+            + self.genres.capacity() * mem::size_of::<String>() + strings(&self.genres)
+            + self.ratings.capacity() * mem::size_of::<ParentalRating>() + self.ratings.iter().map(|r| r.country.capacity()).sum::<usize>()
+            + self.programme_crid.as_ref().map_or(0, String::capacity) + self.series_crid.as_ref().map_or(0, String::capacity)
+            + self.language.capacity() + self.texts.capacity() * mem::size_of::<EventText>()
+            + self.texts.iter().map(|t| t.language.capacity() + t.title.capacity() + t.description.capacity() + t.extended_description.capacity()).sum::<usize>()
+    }
+}
+
+/// The programme on now and the programme on next on a service.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NowNext {
+    pub now: Option<Programme>,
+    pub next: Option<Programme>,
+}
+
+/// How a programme is on compared with the time it is scheduled for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Slippage {
+    StartedLate(i64),  // Minutes.
+    StartedEarly(i64),  // Minutes.
+    RunningLate(i64),  // Minutes since it was scheduled to start, it not having started yet.
+}
+
+impl Slippage {
+    /// The slippage of a programme scheduled to start at `scheduled` that started at
+    /// `started`, None if that is near enough to be on time.
+    pub fn of_start(scheduled: NaiveDateTime, started: NaiveDateTime) -> Option<Slippage> {
+        let minutes = (started - scheduled).num_minutes();
+        if minutes > SLIPPAGE_TOLERANCE_MINUTES {
+            Some(Slippage::StartedLate(minutes))
+        } else if minutes < -SLIPPAGE_TOLERANCE_MINUTES {
+            Some(Slippage::StartedEarly(-minutes))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Slippage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = |m: i64| if m == 1 { "1 minute".to_string() } else { format!("{} minutes", m) };
+        match self {
+            Slippage::StartedLate(m) => write!(f, "started {} late", minutes(*m)),
+            Slippage::StartedEarly(m) => write!(f, "started {} early", minutes(*m)),
+            Slippage::RunningLate(m) => write!(f, "running {} late", minutes(*m)),
+        }
+    }
+}
+
+/// The now and next programmes of the services seen in the EIT, by service id.
+#[derive(Clone, Debug, Default)]
+pub struct NowNextTable {
+    services: HashMap<u16, NowNext>,
+    started: HashMap<u16, (u16, NaiveDateTime)>,  // The event id of the programme seen to start, and when, UTC.
+    languages: Vec<String>,  // The languages to show the programmes in, in order of preference.
+}
+
+impl NowNextTable {
+    /// Update from an EIT section received at `now`, UTC, returning the service id if the
+    /// now or next programme of the service changed. Section 0 of a present/following
+    /// table is the programme on now and section 1 the one on next, an empty section
+    /// meaning there is none. Sections of the schedule tables are ignored.
+    ///
+    /// A programme becoming the running one on now, when another was or it was not yet
+    /// running, is taken to have started at `now`. One already running when first seen
+    /// may have started at any time.
+    pub fn update(&mut self, section: &EitSection, now: NaiveDateTime) -> Option<u16> {
+        if section.table_id != ACTUAL_PRESENT_FOLLOWING && section.table_id != OTHER_PRESENT_FOLLOWING {
+            return None;
+        }
+        let programme = section.events.first().map(Programme::from).map(|mut programme| {
+            programme.show_language(&self.languages);
+            programme
+        });
+        let now_next = self.services.entry(section.service_id).or_default();
+        let slot = match section.section_number {
+            0 => &mut now_next.now,
+            1 => &mut now_next.next,
+            _ => return None,
+        };
+        if *slot == programme {
+            return None;
+        }
+        if section.section_number == 0 {
+            if let (Some(before), Some(present)) = (slot.as_ref(), programme.as_ref()) {
+                let has_started = present.running_status == RunningStatus::Running
+                    && (before.event_id != present.event_id || before.running_status != RunningStatus::Running);
+                if let (true, Some(event_id)) = (has_started, present.event_id) {
+                    self.started.insert(section.service_id, (event_id, now));
+                }
+            }
+        }
+        *slot = programme;
+        Some(section.service_id)
+    }
+
+    /// The slippage of `programme` on the service `service_id` at `now`, UTC, as the
+    /// present/following table shows it: the programme on now having been seen to start
+    /// other than when it was scheduled to, or the programme on next not having started
+    /// by when it was. None if it is on time, or the table says nothing of it.
+    pub fn slippage(&self, service_id: u16, programme: &Programme, now: NaiveDateTime) -> Option<Slippage> {
+        let (event_id, scheduled) = (programme.event_id?, programme.start?);
+        let now_next = self.services.get(&service_id)?;
+        let is_event = |p: &Option<Programme>| p.as_ref().and_then(|p| p.event_id) == Some(event_id);
+        if is_event(&now_next.now) {
+            return match self.started.get(&service_id) {
+                Some((started_id, started)) if *started_id == event_id => Slippage::of_start(scheduled, *started),
+                _ => None,
+            };
+        }
+        let minutes = (now - scheduled).num_minutes();
+        if is_event(&now_next.next) && minutes > SLIPPAGE_TOLERANCE_MINUTES { Some(Slippage::RunningLate(minutes)) } else { None }
+    }
+
+    /// The now and next programmes of the service `service_id`, if any have been seen.
+    pub fn now_next(&self, service_id: u16) -> Option<&NowNext> {
+        self.services.get(&service_id)
+    }
+
+    /// Show the programmes in the first of `languages`, in order of preference, they are
+    /// in, from now on, returning the service ids of the services the now or next
+    /// programmes of which changed, in order.
+    pub fn set_languages(&mut self, languages: &[String]) -> Vec<u16> {
+        self.languages = languages.to_vec();
+        let mut service_ids = self.services.iter_mut()
+            .filter_map(|(service_id, now_next)| {
+                let programmes = now_next.now.iter_mut().chain(now_next.next.iter_mut());
+                let is_changed = programmes.fold(false, |is_changed, programme| programme.show_language(languages) || is_changed);
+                if is_changed { Some(*service_id) } else { None }
+            })
+            .collect::<Vec<_>>();
+        service_ids.sort_unstable();
+        service_ids
+    }
+}
+
+/// The sections received of the version being received of one schedule table of a service.
+#[derive(Clone, Debug)]
+struct ScheduleTable {
+    version_number: u8,
+    last_section_number: u8,
+    segment_last_section_numbers: HashMap<u8, u8>,  // By segment, segment n being sections 8n to 8n + 7.
+    sections: BTreeMap<u8, Vec<Programme>>,
+}
+
+impl ScheduleTable {
+    fn new(section: &EitSection) -> ScheduleTable {
+        ScheduleTable {
+            version_number: section.version_number,
+            last_section_number: section.last_section_number,
+            segment_last_section_numbers: HashMap::new(),
+            sections: BTreeMap::new(),
+        }
+    }
+
+    /// Have all the sections of the table been received? Each three hour segment of the
+    /// schedule has up to eight sections, the last of which its sections say.
+    fn is_complete(&self) -> bool {
+        (0..=self.last_section_number / 8).all(|segment| match self.segment_last_section_numbers.get(&segment) {
+            Some(last) => (segment * 8..=*last).all(|n| self.sections.contains_key(&n)),
+            None => false,
+        })
+    }
+}
+
+/// The schedule tables, cached programmes and listings of a service, and the programmes
+/// in them in order of start.
+#[derive(Clone, Debug, Default)]
+struct ServiceSchedule {
+    last_table_id: u8,
+    tables: BTreeMap<u8, ScheduleTable>,
+    cached: Vec<Programme>,  // From the EIT before Me TV last stopped, until the tables are complete.
+    listings: Vec<Programme>,
+    programmes: Vec<Programme>,
+}
+
+impl ServiceSchedule {
+    /// Update from a schedule section, showing the programmes in the first of
+    /// `languages` they are in and leaving out those `keeps` says not to keep, returning
+    /// whether the programmes changed. A new version of a table replaces all the
+    /// programmes of the old one.
+    fn update(&mut self, section: &EitSection, languages: &[String], keeps: impl Fn(&Programme) -> bool) -> bool {
+        let table = self.tables.entry(section.table_id).or_insert_with(|| ScheduleTable::new(section));
+        let is_new_version = table.version_number != section.version_number;
+        if is_new_version {
+            *table = ScheduleTable::new(section);
+        }
+        table.last_section_number = section.last_section_number;
+        table.segment_last_section_numbers.insert(section.section_number / 8, section.segment_last_section_number);
+        self.last_table_id = section.last_table_id;
+        let programmes = section.events.iter()
+            .map(Programme::from)
+            .filter(|p| keeps(p))
+            .map(|mut p| {
+                p.show_language(languages);
+                p
+            })
+            .collect::<Vec<_>>();
+        if table.sections.get(&section.section_number) == Some(&programmes) && !is_new_version {
+            return false;
+        }
+        table.sections.insert(section.section_number, programmes);
+        if self.is_complete() {
+            self.cached.clear();
+        }
+        self.collate();
+        true
+    }
+
+    /// Replace the cached programmes, showing them in the first of `languages` they are
+    /// in and leaving out those `keeps` says not to keep, returning whether they changed.
+    fn set_cached(&mut self, cached: &[Programme], languages: &[String], keeps: impl Fn(&Programme) -> bool) -> bool {
+        let cached = cached.iter()
+            .filter(|p| keeps(p))
+            .cloned()
+            .map(|mut p| {
+                p.show_language(languages);
+                p
+            })
+            .collect::<Vec<_>>();
+        if cached == self.cached {
+            return false;
+        }
+        self.cached = cached;
+        self.collate();
+        true
+    }
+
+    /// Replace the programmes of the listings, leaving out those `keeps` says not to
+    /// keep, returning whether they changed.
+    fn set_listings(&mut self, listings: &[Programme], keeps: impl Fn(&Programme) -> bool) -> bool {
+        let listings = listings.iter().filter(|p| keeps(p)).cloned().collect::<Vec<_>>();
+        if listings == self.listings {
+            return false;
+        }
+        self.listings = listings;
+        self.collate();
+        true
+    }
+
+    /// Forget the programmes `keeps` says not to keep, returning whether there were any.
+    fn retain(&mut self, keeps: impl Fn(&Programme) -> bool) -> bool {
+        let mut is_forgotten = false;
+        let sections = self.tables.values_mut().flat_map(|table| table.sections.values_mut());
+        for programmes in sections.chain(Some(&mut self.cached)).chain(Some(&mut self.listings)) {
+            let count = programmes.len();
+            programmes.retain(|p| keeps(p));
+            is_forgotten |= programmes.len() != count;
+        }
+        if is_forgotten {
+            self.collate();
+        }
+        is_forgotten
+    }
+
+    /// Show the programmes of the EIT in the first of `languages` they are in, returning
+    /// whether any changed. The programmes of the listings are in one language.
+    fn show_language(&mut self, languages: &[String]) -> bool {
+        let mut is_changed = false;
+        let sections = self.tables.values_mut().flat_map(|table| table.sections.values_mut());
+        for programmes in sections.chain(Some(&mut self.cached)).chain(Some(&mut self.programmes)) {
+            for programme in programmes.iter_mut() {
+                is_changed |= programme.show_language(languages);
+            }
+        }
+        is_changed
+    }
+
+    /// All the programmes held, those of the tables, the cache and the listings as well as
+    /// those in order of start.
+    fn held(&self) -> impl Iterator<Item = &Programme> {
+        self.tables.values()
+            .flat_map(|table| table.sections.values().flatten())
+            .chain(&self.cached)
+            .chain(&self.listings)
+            .chain(&self.programmes)
+    }
+
+    /// Put the programmes of all the tables in order of start, a programme in more than
+    /// one table, as when a programme moves, being there once, with the cached programmes
+    /// not in the tables, and the programmes of the listings, at the times the tables have
+    /// no programmes.
+    fn collate(&mut self) {
+        let mut seen = HashSet::new();
+        let mut programmes = self.tables.values()
+            .flat_map(|table| table.sections.values().flatten())
+            .filter(|p| seen.insert(p.event_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        let tolerance = Duration::minutes(LISTINGS_OVERLAP_MINUTES);
+        let cached = self.cached.iter()
+            .filter(|cached| !seen.contains(&cached.event_id) && programmes.iter().all(|p| p.overlap(cached) <= tolerance))
+            .cloned()
+            .collect::<Vec<_>>();
+        programmes.extend(cached);
+        let listed = self.listings.iter()
+            .filter(|listed| programmes.iter().all(|p| p.overlap(listed) <= tolerance))
+            .cloned()
+            .collect::<Vec<_>>();
+        programmes.extend(listed);
+        programmes.sort_by_key(|p| (p.start.is_none(), p.start, p.event_id));
+        self.programmes = programmes;
+    }
+
+    /// Have all the sections of all the tables been received?
+    fn is_complete(&self) -> bool {
+        if self.tables.is_empty() {
+            return false;
+        }
+        let first_table_id = if self.last_table_id < 0x60 { 0x50 } else { 0x60 };
+        (first_table_id..=self.last_table_id).all(|table_id| self.tables.get(&table_id).map_or(false, ScheduleTable::is_complete))
+    }
+}
+
+/// How much of the programme guide the programme store keeps.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Retention {
+    pub days_ahead: u32,  // How many days from now, the programmes starting after then being left out.
+    pub max_programmes: usize,  // Of all the services together.
+}
+
+impl Default for Retention {
+    fn default() -> Retention {
+        Retention { days_ahead: 14, max_programmes: 50_000 }
+    }
+}
+
+/// What programmes the programme store keeps, and whether it is full.
+#[derive(Clone, Debug, Default)]
+struct Keeping {
+    retention: Retention,
+    is_full: bool,  // If so, the programmes that have ended are forgotten at once.
+    full_ahead: Option<Duration>,  // How far ahead programmes are kept whilst full, if less than the retention says.
+    kept: HashSet<(u16, NaiveDateTime)>,  // The service ids and starts, UTC, of the programmes kept however full.
+}
+
+impl Keeping {
+    /// Whether `programme` of the service `service_id` is kept at `now`, UTC. A
+    /// programme that has expired is never kept.
+    fn keeps(&self, service_id: u16, programme: &Programme, now: NaiveDateTime) -> bool {
+        if programme.has_expired(now) {
+            return false;
+        }
+        let start = match programme.start {
+            Some(start) => start,
+            None => return true,
+        };
+        if self.kept.contains(&(service_id, start)) {
+            return true;
+        }
+        let ahead = Duration::days(i64::from(self.retention.days_ahead));
+        let ahead = self.full_ahead.map_or(ahead, |full_ahead| full_ahead.min(ahead));
+        let has_ended = programme.end().map_or(false, |end| end <= now);
+        start < now + ahead && !(self.is_full && has_ended)
+    }
+}
+
+/// How many programmes the programme store has, and about how much memory they take.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StoreStats {
+    pub services: usize,
+    pub programmes: usize,  // In the schedules of the services.
+    pub held: usize,  // Including those of the tables, the cache and the listings the schedules are made from.
+    pub kept: usize,  // Kept however full the store is.
+    pub bytes: usize,
+    pub is_full: bool,
+}
+
+impl fmt::Display for StoreStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "{} programmes of {} services, {} held in all, taking about {:.1} MB",
+            self.programmes, self.services, self.held, self.bytes as f64 / (1024.0 * 1024.0),
+        )
+    }
+}
+
+/// The programmes to come of the services seen in the EIT schedule tables or in listings,
+/// by service id, the programmes that have ended being forgotten and how many are kept
+/// being limited by the retention.
+#[derive(Clone, Debug, Default)]
+pub struct ProgrammeStore {
+    services: HashMap<u16, ServiceSchedule>,
+    keeping: Keeping,
+    languages: Vec<String>,  // The languages to show the programmes in, in order of preference.
+}
+
+impl ProgrammeStore {
+    /// Update from an EIT section received at `now`, UTC, returning the service id if the
+    /// programmes of the service changed. Present/following sections are ignored.
+    pub fn update(&mut self, section: &EitSection, now: NaiveDateTime) -> Option<u16> {
+        if !SCHEDULE_TABLE_IDS.contains(&section.table_id) {
+            return None;
+        }
+        let (keeping, languages) = (&self.keeping, &self.languages);
+        let service_id = section.service_id;
+        let schedule = self.services.entry(service_id).or_default();
+        if schedule.update(section, languages, |p| keeping.keeps(service_id, p, now)) { Some(service_id) } else { None }
+    }
+
+    /// Replace the programmes of the listings with `listings`, by service id, leaving out
+    /// those not kept at `now`, UTC, returning the service ids of the services the
+    /// programmes of which changed, in order.
+    pub fn set_listings(&mut self, listings: &HashMap<u16, Vec<Programme>>, now: NaiveDateTime) -> Vec<u16> {
+        for service_id in listings.keys() {
+            self.services.entry(*service_id).or_default();
+        }
+        let keeping = &self.keeping;
+        let mut service_ids = self.services.iter_mut()
+            .filter_map(|(service_id, schedule)| {
+                let programmes = listings.get(service_id).map(Vec::as_slice).unwrap_or(&[]);
+                if schedule.set_listings(programmes, |p| keeping.keeps(*service_id, p, now)) { Some(*service_id) } else { None }
+            })
+            .collect::<Vec<_>>();
+        service_ids.sort_unstable();
+        service_ids
+    }
+
+    /// Replace the cached programmes with `cached`, by service id, programmes of the EIT
+    /// from before Me TV last stopped, leaving out those not kept at `now`, UTC,
+    /// returning the service ids of the services the programmes of which changed, in
+    /// order. The cached programmes are where the tables have no programmes until the
+    /// whole of the schedule of a service has been received again.
+    pub fn set_cached(&mut self, cached: &HashMap<u16, Vec<Programme>>, now: NaiveDateTime) -> Vec<u16> {
+        for service_id in cached.keys() {
+            self.services.entry(*service_id).or_default();
+        }
+        let (keeping, languages) = (&self.keeping, &self.languages);
+        let mut service_ids = self.services.iter_mut()
+            .filter_map(|(service_id, schedule)| {
+                let programmes = cached.get(service_id).map(Vec::as_slice).unwrap_or(&[]);
+                if schedule.set_cached(programmes, languages, |p| keeping.keeps(*service_id, p, now)) { Some(*service_id) } else { None }
+            })
+            .collect::<Vec<_>>();
+        service_ids.sort_unstable();
+        service_ids
+    }
+
+    /// The programmes of the EIT, rather than of the listings, by service id, so as to
+    /// cache them.
+    pub fn eit_programmes(&self) -> HashMap<u16, Vec<Programme>> {
+        self.services.iter()
+            .map(|(service_id, schedule)| (*service_id, schedule.programmes.iter().filter(|p| p.event_id.is_some()).cloned().collect::<Vec<_>>()))
+            .filter(|(_, programmes)| !programmes.is_empty())
+            .collect()
+    }
+
+    /// Forget the programmes that ended more than an hour before `now`, UTC, and those
+    /// the retention says not to keep, returning the service ids of the services that had
+    /// any, in order.
+    pub fn expire(&mut self, now: NaiveDateTime) -> Vec<u16> {
+        let keeping = &self.keeping;
+        let mut service_ids = self.services.iter_mut()
+            .filter_map(|(service_id, schedule)| if schedule.retain(|p| keeping.keeps(*service_id, p, now)) { Some(*service_id) } else { None })
+            .collect::<Vec<_>>();
+        service_ids.sort_unstable();
+        service_ids
+    }
+
+    /// Forget the programmes `retention` says not to keep at `now`, UTC, other than those
+    /// of `kept`, service ids and starts, UTC, as of programmes to be recorded or reminded
+    /// of, returning the service ids of the services that had any, in order. From then on
+    /// the programmes received are left out in the same way.
+    ///
+    /// When there are more than the most programmes the retention says, the store is full:
+    /// the programmes that have ended go at once rather than an hour later, then, if there
+    /// are still too many, those furthest ahead, so that programmes only so far ahead are
+    /// kept. The store stays full until there are well under the most programmes.
+    pub fn retain(&mut self, now: NaiveDateTime, retention: Retention, kept: HashSet<(u16, NaiveDateTime)>) -> Vec<u16> {
+        if retention != self.keeping.retention {
+            self.keeping = Keeping::default();
+        }
+        self.keeping.retention = retention;
+        self.keeping.kept = kept;
+        let mut service_ids = self.expire(now);
+        let count = self.iter().count();
+        if (count as f64) < retention.max_programmes as f64 * RETENTION_SLACK {
+            self.keeping.is_full = false;
+            self.keeping.full_ahead = None;
+        }
+        if count > retention.max_programmes && !self.keeping.is_full {
+            self.keeping.is_full = true;
+            service_ids.extend(self.expire(now));
+        }
+        let count = self.iter().count();
+        if count > retention.max_programmes {
+            let keeping = &self.keeping;
+            let mut starts = self.iter()
+                .filter_map(|(service_id, p)| p.start.filter(|start| *start > now && !keeping.kept.contains(&(service_id, *start))))
+                .collect::<Vec<_>>();
+            starts.sort_unstable();
+            if let Some(horizon) = starts.get(starts.len().saturating_sub(count - retention.max_programmes)) {
+                self.keeping.full_ahead = Some(*horizon - now);
+                service_ids.extend(self.expire(now));
+            }
+        }
+        service_ids.sort_unstable();
+        service_ids.dedup();
+        service_ids
+    }
+
+    /// Show the programmes in the first of `languages`, in order of preference, they are
+    /// in, from now on, returning the service ids of the services the programmes of
+    /// which changed, in order.
+    pub fn set_languages(&mut self, languages: &[String]) -> Vec<u16> {
+        self.languages = languages.to_vec();
+        let mut service_ids = self.services.iter_mut()
+            .filter_map(|(service_id, schedule)| if schedule.show_language(languages) { Some(*service_id) } else { None })
+            .collect::<Vec<_>>();
+        service_ids.sort_unstable();
+        service_ids
+    }
+
+    /// How many programmes there are, and about how much memory they take.
+    pub fn stats(&self) -> StoreStats {
+        let held = || self.services.values().flat_map(ServiceSchedule::held);
+        StoreStats {
+            services: self.services.len(),
+            programmes: self.services.values().map(|schedule| schedule.programmes.len()).sum(),
+            held: held().count(),
+            kept: self.keeping.kept.len(),
+            bytes: held().map(Programme::bytes).sum(),
+            is_full: self.keeping.is_full,
+        }
+    }
+
+    /// The service ids of the services with a schedule, in order.
+    pub fn service_ids(&self) -> Vec<u16> {
+        let mut service_ids = self.services.keys().cloned().collect::<Vec<_>>();
+        service_ids.sort_unstable();
+        service_ids
+    }
+
+    /// The programmes of the service `service_id` in order of start.
+    pub fn programmes(&self, service_id: u16) -> &[Programme] {
+        self.services.get(&service_id).map(|schedule| schedule.programmes.as_slice()).unwrap_or(&[])
+    }
+
+    /// The programmes of the service `service_id` on at any time from `from` to `to`, UTC,
+    /// in order of start.
+    pub fn programmes_between(&self, service_id: u16, from: NaiveDateTime, to: NaiveDateTime) -> impl Iterator<Item = &Programme> {
+        self.programmes(service_id).iter().filter(move |p| p.overlaps(from, to))
+    }
+
+    /// The programmes of the service `service_id` of the series with the series CRID
+    /// `series_crid`, in order of start. CRIDs are mostly relative to the default authority
+    /// of the service, so a series is only looked for on the one service.
+    pub fn episodes<'a>(&'a self, service_id: u16, series_crid: &'a str) -> impl Iterator<Item = &'a Programme> {
+        self.programmes(service_id).iter().filter(move |p| p.series_crid.as_deref() == Some(series_crid))
+    }
+
+    /// All the programmes, by service in order of service id and then in order of start.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Programme)> {
+        self.service_ids().into_iter().flat_map(move |service_id| self.programmes(service_id).iter().map(move |p| (service_id, p)))
+    }
+
+    /// The programmes on now, at `now`, UTC, and on next on the service `service_id`.
+    pub fn now_next_at(&self, service_id: u16, now: NaiveDateTime) -> NowNext {
+        let programmes = self.programmes(service_id);
+        let now_programme = programmes.iter().find(|p| p.overlaps(now, now + Duration::seconds(1)));
+        let next_from = now_programme.and_then(Programme::end).unwrap_or(now);
+        let next = programmes.iter().find(|p| p.start.map_or(false, |start| start >= next_from));
+        NowNext { now: now_programme.cloned(), next: next.cloned() }
+    }
+
+    /// Has the whole of the schedule of the service `service_id` been received?
+    pub fn is_complete(&self, service_id: u16) -> bool {
+        self.services.get(&service_id).map_or(false, ServiceSchedule::is_complete)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    use crate::eit::{parse_eit_section, EitEvent};
+    use crate::languages::parse_languages;
+
+    // The present/following sections of the EIT for BBC One London, service 0x1044 on
+    // transport stream 0x1004 of original network 0x233a, version 3. The present event
+    // is Newsnight, the following is Question Time with its title in UTF-8 and its text
+    // in the default table with a diacritical mark and a CR/LF.
+
+    const PRESENT: [u8; 99] = [
+        0x4e, 0xf0, 0x60, 0x10, 0x44, 0xc7, 0x00, 0x01, 0x10, 0x04, 0x23, 0x3a, 0x01, 0x4e, 0x5a, 0x1f,
+        0xe7, 0x00, 0x21, 0x30, 0x00, 0x00, 0x45, 0x00, 0x80, 0x45, 0x4d, 0x43, 0x65, 0x6e, 0x67, 0x09,
+        0x4e, 0x65, 0x77, 0x73, 0x6e, 0x69, 0x67, 0x68, 0x74, 0x35, 0x54, 0x68, 0x65, 0x20, 0x73, 0x74,
+        0x6f, 0x72, 0x69, 0x65, 0x73, 0x20, 0x62, 0x65, 0x68, 0x69, 0x6e, 0x64, 0x20, 0x74, 0x68, 0x65,
+        0x20, 0x68, 0x65, 0x61, 0x64, 0x6c, 0x69, 0x6e, 0x65, 0x73, 0x2c, 0x20, 0x77, 0x69, 0x74, 0x68,
+        0x20, 0x45, 0x6d, 0x69, 0x6c, 0x79, 0x20, 0x4d, 0x61, 0x69, 0x74, 0x6c, 0x69, 0x73, 0x2e, 0xad,
+        0x38, 0xb3, 0xf9,
+    ];
+
+    const FOLLOWING: [u8; 121] = [
+        0x4e, 0xf0, 0x76, 0x10, 0x44, 0xc7, 0x01, 0x01, 0x10, 0x04, 0x23, 0x3a, 0x01, 0x4e, 0x5a, 0x20,
+        0xe7, 0x00, 0x22, 0x15, 0x00, 0x01, 0x00, 0x00, 0x20, 0x5b, 0x4d, 0x59, 0x65, 0x6e, 0x67, 0x17,
+        0x15, 0x51, 0x75, 0x65, 0x73, 0x74, 0x69, 0x6f, 0x6e, 0x20, 0x54, 0x69, 0x6d, 0x65, 0x20, 0xe2,
+        0x80, 0x94, 0x20, 0x4c, 0x69, 0x76, 0x65, 0x3d, 0x46, 0x69, 0x6f, 0x6e, 0x61, 0x20, 0x42, 0x72,
+        0x75, 0x63, 0x65, 0x20, 0x63, 0x68, 0x61, 0x69, 0x72, 0x73, 0x20, 0x74, 0x68, 0x65, 0x20, 0x64,
+        0x65, 0x62, 0x61, 0x74, 0x65, 0x20, 0x66, 0x72, 0x6f, 0x6d, 0x20, 0x43, 0x61, 0x66, 0xc2, 0x65,
+        0x20, 0x4e, 0x65, 0x72, 0x6f, 0x8a, 0x77, 0x69, 0x74, 0x68, 0x20, 0x74, 0x68, 0x65, 0x20, 0x70,
+        0x61, 0x6e, 0x65, 0x6c, 0x2e, 0x50, 0x3d, 0x1e, 0x94,
+    ];
+
+    #[test]
+    fn now_and_next_come_from_the_present_and_following_sections() {
+        let mut table = NowNextTable::default();
+        assert_eq!(table.update(&parse_eit_section(&PRESENT).unwrap(), at(21)), Some(0x1044));
+        assert_eq!(table.update(&parse_eit_section(&FOLLOWING).unwrap(), at(21)), Some(0x1044));
+        let now_next = table.now_next(0x1044).unwrap();
+        assert_eq!(now_next.now, Some(Programme {
+            event_id: Some(0x5a1f),
+            start: Some(NaiveDate::from_ymd(2020, 10, 14).and_hms(21, 30, 0)),
+            duration_seconds: 2700,
+            running_status: RunningStatus::Running,
+            title: "Newsnight".to_string(),
+            description: "The stories behind the headlines, with Emily Maitlis.".to_string(),
+            language: "eng".to_string(),
+            ..Programme::default()
+        }));
+        let next = now_next.next.as_ref().unwrap();
+        assert_eq!(next.title, "Question Time — Live");
+        assert_eq!(next.description, "Fiona Bruce chairs the debate from Café Nero\nwith the panel.");
+        assert_eq!(next.running_status, RunningStatus::NotRunning);
+        assert_eq!(next.end(), Some(NaiveDate::from_ymd(2020, 10, 14).and_hms(23, 15, 0)));
+        assert!(table.now_next(0x1084).is_none());
+    }
+
+    #[test]
+    fn only_changes_are_notified() {
+        let mut table = NowNextTable::default();
+        let present = parse_eit_section(&PRESENT).unwrap();
+        assert_eq!(table.update(&present, at(21)), Some(0x1044));
+        assert_eq!(table.update(&present, at(21)), None);
+        let following = parse_eit_section(&FOLLOWING).unwrap();
+        assert_eq!(table.update(&following, at(21)), Some(0x1044));
+        assert_eq!(table.update(&EitSection { events: Vec::new(), ..following.clone() }, at(21)), Some(0x1044));
+        assert_eq!(table.now_next(0x1044).unwrap().next, None);
+        assert!(table.now_next(0x1044).unwrap().now.is_some());
+    }
+
+    #[test]
+    fn other_transport_streams_are_followed_and_schedules_ignored() {
+        let mut table = NowNextTable::default();
+        let present = parse_eit_section(&PRESENT).unwrap();
+        assert_eq!(table.update(&EitSection { table_id: OTHER_PRESENT_FOLLOWING, service_id: 0x10bf, ..present.clone() }, at(21)), Some(0x10bf));
+        assert_eq!(table.update(&EitSection { table_id: 0x50, ..present }, at(21)), None);
+        assert!(table.now_next(0x1044).is_none());
+    }
+
+    #[test]
+    fn programmes_starting_late_or_early_are_noticed() {
+        let mut table = NowNextTable::default();
+        let at = |h, m| NaiveDate::from_ymd(2020, 10, 14).and_hms(h, m, 0);
+        let present = parse_eit_section(&PRESENT).unwrap();
+        let following = parse_eit_section(&FOLLOWING).unwrap();
+        table.update(&present, at(21, 40));
+        table.update(&following, at(21, 40));
+        let newsnight = Programme::from(&present.events[0]);
+        let question_time = Programme::from(&following.events[0]);
+        // Newsnight was running when first seen, so when it started is not known.
+        assert_eq!(table.slippage(0x1044, &newsnight, at(21, 40)), None);
+        assert_eq!(table.slippage(0x1044, &question_time, at(22, 16)), None);
+        assert_eq!(table.slippage(0x1044, &question_time, at(22, 22)), Some(Slippage::RunningLate(7)));
+        let mut started = following.clone();
+        started.section_number = 0;
+        started.events[0].running_status = RunningStatus::Running;
+        assert_eq!(table.update(&started, at(22, 24)), Some(0x1044));
+        assert_eq!(table.slippage(0x1044, &question_time, at(22, 30)), Some(Slippage::StartedLate(9)));
+        assert_eq!(table.slippage(0x1044, &newsnight, at(22, 30)), None);
+        assert_eq!(Slippage::StartedLate(9).to_string(), "started 9 minutes late");
+        assert_eq!(Slippage::of_start(at(22, 15), at(22, 14)), None);
+        assert_eq!(Slippage::of_start(at(22, 15), at(22, 12)).map(|s| s.to_string()), Some("started 3 minutes early".to_string()));
+        assert_eq!(Slippage::RunningLate(1).to_string(), "running 1 minute late");
+    }
+
+    #[test]
+    fn the_fraction_of_a_programme_elapsed_is_bounded() {
+        let programme = Programme::from(&parse_eit_section(&PRESENT).unwrap().events[0]);
+        let at = |h, m| NaiveDate::from_ymd(2020, 10, 14).and_hms(h, m, 0);
+        assert_eq!(programme.fraction_elapsed(at(21, 45)), Some(1.0 / 3.0));
+        assert_eq!(programme.fraction_elapsed(at(21, 0)), Some(0.0));
+        assert_eq!(programme.fraction_elapsed(at(23, 0)), Some(1.0));
+        assert_eq!(Programme { start: None, ..programme }.fraction_elapsed(at(21, 45)), None);
+    }
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 10, 14).and_hms(hour, 0, 0)
+    }
+
+    /// A section of the actual schedule table `table_id` of BBC One London, with hour long
+    /// events starting at the hours given.
+    fn schedule(table_id: u8, version_number: u8, section_number: u8, segment_last_section_number: u8, events: &[(u16, u32)]) -> EitSection {
+        EitSection {
+            table_id,
+            service_id: 0x1044,
+            version_number,
+            section_number,
+            last_section_number: 15,
+            transport_stream_id: 0x1004,
+            original_network_id: 0x233a,
+            segment_last_section_number,
+            last_table_id: 0x50,
+            events: events.iter().map(|(event_id, hour)| EitEvent {
+                event_id: *event_id,
+                start_time: Some(at(*hour)),
+                duration_seconds: 3600,
+                running_status: RunningStatus::Undefined,
+                free_ca_mode: false,
+                title: Some(format!("Programme {}", event_id)),
+                description: None,
+                extended_description: Some("At length.".to_string()),
+                texts: Vec::new(),
+                content: Vec::new(),
+                ratings: Vec::new(),
+                programme_crid: None,
+                series_crid: None,
+            }).collect(),
+        }
+    }
+
+    fn event_ids<'a>(programmes: impl Iterator<Item = &'a Programme>) -> Vec<u16> {
+        programmes.filter_map(|p| p.event_id).collect()
+    }
+
+    #[test]
+    fn programmes_of_all_the_sections_are_in_order_of_start() {
+        let mut store = ProgrammeStore::default();
+        assert_eq!(store.update(&schedule(0x50, 1, 8, 8, &[(5, 4), (6, 5)]), at(0)), Some(0x1044));
+        assert_eq!(store.update(&schedule(0x50, 1, 0, 1, &[(1, 0), (2, 1)]), at(0)), Some(0x1044));
+        assert_eq!(store.update(&schedule(0x50, 1, 0, 1, &[(1, 0), (2, 1)]), at(0)), None);
+        assert_eq!(event_ids(store.programmes(0x1044).iter()), vec![1, 2, 5, 6]);
+        assert_eq!(store.programmes(0x1044)[0].extended_description, "At length.");
+        assert_eq!(event_ids(store.programmes_between(0x1044, at(1), at(5))), vec![2, 5]);
+        assert_eq!(store.iter().map(|(service_id, p)| (service_id, p.event_id)).collect::<Vec<_>>(), vec![(0x1044, Some(1)), (0x1044, Some(2)), (0x1044, Some(5)), (0x1044, Some(6))]);
+        assert_eq!(store.service_ids(), vec![0x1044]);
+        assert!(store.programmes(0x10bf).is_empty());
+    }
+
+    #[test]
+    fn a_new_version_of_a_table_replaces_the_old() {
+        let mut store = ProgrammeStore::default();
+        store.update(&schedule(0x50, 1, 0, 1, &[(1, 0), (2, 1)]), at(0));
+        store.update(&schedule(0x50, 1, 1, 1, &[(3, 2)]), at(0));
+        assert_eq!(store.update(&schedule(0x50, 2, 0, 1, &[(1, 0), (4, 1)]), at(0)), Some(0x1044));
+        assert_eq!(event_ids(store.programmes(0x1044).iter()), vec![1, 4]);
+    }
+
+    #[test]
+    fn a_schedule_is_complete_when_every_segment_is() {
+        let mut store = ProgrammeStore::default();
+        store.update(&schedule(0x50, 1, 0, 1, &[(1, 0)]), at(0));
+        store.update(&schedule(0x50, 1, 8, 8, &[(5, 4)]), at(0));
+        assert!(!store.is_complete(0x1044));
+        store.update(&schedule(0x50, 1, 1, 1, &[]), at(0));
+        assert!(store.is_complete(0x1044));
+        store.update(&schedule(0x50, 2, 0, 1, &[(1, 0)]), at(0));
+        assert!(!store.is_complete(0x1044));
+        assert!(!store.is_complete(0x10bf));
+    }
+
+    #[test]
+    fn programmes_that_have_ended_are_forgotten() {
+        let mut store = ProgrammeStore::default();
+        store.update(&schedule(0x50, 1, 0, 1, &[(1, 0), (2, 1), (3, 2)]), at(0));
+        assert_eq!(store.expire(at(1)), Vec::<u16>::new());
+        // Programmes are kept for an hour after they end.
+        assert_eq!(store.expire(at(2)), Vec::<u16>::new());
+        assert_eq!(store.expire(at(3)), vec![0x1044]);
+        assert_eq!(event_ids(store.programmes(0x1044).iter()), vec![2, 3]);
+        // The programmes that have ended are not put back when the section comes round again.
+        assert_eq!(store.update(&schedule(0x50, 1, 0, 1, &[(1, 0), (2, 1), (3, 2)]), at(3)), None);
+        assert_eq!(event_ids(store.programmes(0x1044).iter()), vec![2, 3]);
+    }
+
+    #[test]
+    fn present_following_sections_are_not_in_the_store() {
+        let mut store = ProgrammeStore::default();
+        assert_eq!(store.update(&parse_eit_section(&PRESENT).unwrap(), at(21)), None);
+        assert!(store.service_ids().is_empty());
+    }
+
+    fn listed(title: &str, from: NaiveDateTime, minutes: u32) -> Programme {
+        Programme {
+            start: Some(from),
+            duration_seconds: minutes * 60,
+            title: title.to_string(),
+            ..Programme::default()
+        }
+    }
+
+    fn titles(programmes: &[Programme]) -> Vec<&str> {
+        programmes.iter().map(|p| p.title.as_str()).collect()
+    }
+
+    #[test]
+    fn listings_fill_in_where_the_eit_has_no_programmes() {
+        let mut store = ProgrammeStore::default();
+        store.update(&schedule(0x50, 1, 0, 1, &[(1, 0), (2, 1)]), at(0));
+        let listings = vec![
+            (0x1044, vec![
+                listed("Late", at(0) + Duration::minutes(2), 60),
+                listed("Early", at(1) + Duration::minutes(57), 60),
+                listed("After", at(3), 30),
+            ]),
+            (0x10bf, vec![listed("Elsewhere", at(0), 60)]),
+        ].into_iter().collect();
+        assert_eq!(store.set_listings(&listings, at(0)), vec![0x1044, 0x10bf]);
+        assert_eq!(titles(store.programmes(0x1044)), vec!["Programme 1", "Programme 2", "Early", "After"]);
+        assert_eq!(titles(store.programmes(0x10bf)), vec!["Elsewhere"]);
+        assert!(!store.is_complete(0x10bf));
+        assert_eq!(store.set_listings(&listings, at(0)), Vec::<u16>::new());
+        // The EIT coming to have a programme where there was only one of the listings.
+        store.update(&schedule(0x50, 1, 1, 1, &[(3, 3)]), at(0));
+        assert_eq!(titles(store.programmes(0x1044)), vec!["Programme 1", "Programme 2", "Early", "Programme 3"]);
+        // New listings replace the old, for all the services.
+        let listings = vec![(0x1044, vec![listed("Later", at(5), 30)])].into_iter().collect();
+        assert_eq!(store.set_listings(&listings, at(0)), vec![0x1044, 0x10bf]);
+        assert_eq!(titles(store.programmes(0x1044)), vec!["Programme 1", "Programme 2", "Programme 3", "Later"]);
+        assert!(store.programmes(0x10bf).is_empty());
+    }
+
+    #[test]
+    fn cached_programmes_fill_in_until_the_schedule_is_received() {
+        let mut store = ProgrammeStore::default();
+        store.update(&schedule(0x50, 1, 0, 1, &[(1, 0), (2, 1)]), at(0));
+        let mut moved = store.programmes(0x1044)[1].clone();
+        moved.start = Some(at(4));
+        let listings = vec![(0x1044, vec![listed("Listed", at(2), 60), listed("Later", at(5), 60)])].into_iter().collect();
+        store.set_listings(&listings, at(0));
+        let cached = vec![(0x1044, vec![moved, Programme { event_id: Some(3), ..listed("Cached", at(2), 60) }])].into_iter().collect();
+        assert_eq!(store.set_cached(&cached, at(0)), vec![0x1044]);
+        assert_eq!(titles(store.programmes(0x1044)), vec!["Programme 1", "Programme 2", "Cached", "Later"]);
+        assert_eq!(store.eit_programmes()[&0x1044].iter().filter_map(|p| p.event_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(store.set_cached(&cached, at(0)), Vec::<u16>::new());
+        // The schedule being complete, the programmes of the tables are all there are.
+        store.update(&schedule(0x50, 1, 8, 8, &[(5, 4)]), at(0));
+        store.update(&schedule(0x50, 1, 1, 1, &[]), at(0));
+        assert_eq!(titles(store.programmes(0x1044)), vec!["Programme 1", "Programme 2", "Listed", "Programme 5", "Later"]);
+    }
+
+    #[test]
+    fn now_and_next_can_come_from_the_store() {
+        let mut store = ProgrammeStore::default();
+        let listings = vec![(0x10bf, vec![listed("First", at(20), 60), listed("Second", at(21), 60), listed("Third", at(22), 60)])].into_iter().collect();
+        store.set_listings(&listings, at(20));
+        let now_next = store.now_next_at(0x10bf, at(21) + Duration::minutes(10));
+        assert_eq!(now_next.now.map(|p| p.title), Some("Second".to_string()));
+        assert_eq!(now_next.next.map(|p| p.title), Some("Third".to_string()));
+        assert_eq!(store.now_next_at(0x10bf, at(19)).next.map(|p| p.title), Some("First".to_string()));
+        assert_eq!(store.now_next_at(0x10bf, at(23)), NowNext::default());
+        assert_eq!(store.expire(at(23)), vec![0x10bf]);
+        assert_eq!(titles(store.programmes(0x10bf)), vec!["Second", "Third"]);
+    }
+
+    #[test]
+    fn episodes_are_found_by_series_crid_not_title() {
+        let mut store = ProgrammeStore::default();
+        let episode = |title, from, crid: Option<&str>| Programme { series_crid: crid.map(String::from), ..listed(title, from, 30) };
+        let listings = vec![
+            (0x1044, vec![
+                episode("EastEnders", at(19), Some("/KFNVXV")),
+                episode("EastEnders: Omnibus", at(20), Some("/KFNVXV")),
+                episode("EastEnders", at(21), None),
+                episode("EastEnders", at(22), Some("/KFNW2B")),
+            ]),
+            (0x10bf, vec![episode("EastEnders", at(19), Some("/KFNVXV"))]),
+        ].into_iter().collect();
+        store.set_listings(&listings, at(18));
+        let episodes = store.episodes(0x1044, "/KFNVXV").collect::<Vec<_>>();
+        assert_eq!(episodes.iter().map(|p| p.start).collect::<Vec<_>>(), vec![Some(at(19)), Some(at(20))]);
+        assert!(episodes[0].is_same_series(episodes[1]));
+        let programmes = store.programmes(0x1044);
+        assert!(!programmes[2].is_same_series(&programmes[2]));
+        assert!(!programmes[0].is_same_series(&programmes[3]));
+    }
+
+    #[test]
+    fn programmes_are_shown_in_the_language_preferred() {
+        let text = |language: &str, title: &str| EventText { language: language.to_string(), title: title.to_string(), ..EventText::default() };
+        let bilingual = |event_id, hour| {
+            let mut section = schedule(0x50, 1, event_id as u8 - 1, 1, &[(event_id, hour)]);
+            section.events[0].title = Some(format!("Newyddion {}", event_id));
+            section.events[0].texts = vec![text("cym", &format!("Newyddion {}", event_id)), text("eng", &format!("News {}", event_id))];
+            section
+        };
+        let mut store = ProgrammeStore::default();
+        store.update(&bilingual(1, 0), at(0));
+        assert_eq!(titles(store.programmes(0x1044)), vec!["Newyddion 1"]);
+        assert_eq!(store.set_languages(&parse_languages("gla, en")), vec![0x1044]);
+        assert_eq!(titles(store.programmes(0x1044)), vec!["News 1"]);
+        assert_eq!(store.programmes(0x1044)[0].language, "eng");
+        // Programmes received from then on are shown in it too.
+        store.update(&bilingual(2, 1), at(0));
+        assert_eq!(titles(store.programmes(0x1044)), vec!["News 1", "News 2"]);
+        assert_eq!(store.set_languages(&parse_languages("eng")), Vec::<u16>::new());
+        assert_eq!(store.set_languages(&[]), vec![0x1044]);
+        assert_eq!(titles(store.programmes(0x1044)), vec!["Newyddion 1", "Newyddion 2"]);
+    }
+
+    #[test]
+    fn programmes_too_far_ahead_are_not_kept() {
+        let mut store = ProgrammeStore::default();
+        let listings = vec![(0x10bf, vec![listed("Today", at(20), 60), listed("Tomorrow", at(20) + Duration::days(1), 60), listed("Later", at(20) + Duration::days(3), 60)])]
+            .into_iter().collect();
+        store.set_listings(&listings, at(18));
+        assert_eq!(store.retain(at(18), Retention { days_ahead: 2, max_programmes: 100 }, HashSet::new()), vec![0x10bf]);
+        assert_eq!(titles(store.programmes(0x10bf)), vec!["Today", "Tomorrow"]);
+        // They are left out when received again too.
+        assert_eq!(store.set_listings(&listings, at(18)), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn a_full_store_forgets_what_has_ended_then_what_is_furthest_ahead_but_not_what_is_kept() {
+        let mut store = ProgrammeStore::default();
+        let listings = vec![(0x10bf, (10..=16).map(|hour| listed(&format!("At {}", hour), at(hour), 60)).collect::<Vec<_>>())].into_iter().collect();
+        let now = at(12) + Duration::minutes(30);
+        store.set_listings(&listings, now);
+        assert_eq!(store.programmes(0x10bf).len(), 6);
+        let kept = vec![(0x10bf, at(15))].into_iter().collect::<HashSet<_>>();
+        let retention = Retention { days_ahead: 14, max_programmes: 4 };
+        assert_eq!(store.retain(now, retention, kept.clone()), vec![0x10bf]);
+        assert_eq!(titles(store.programmes(0x10bf)), vec!["At 12", "At 13", "At 14", "At 15"]);
+        let stats = store.stats();
+        assert_eq!((stats.services, stats.programmes, stats.held, stats.kept, stats.is_full), (1, 4, 8, 1, true));
+        assert!(stats.bytes > 8 * mem::size_of::<Programme>());
+        assert_eq!(store.set_listings(&listings, now), Vec::<u16>::new());
+        // Once well under the most programmes the store is no longer full.
+        assert_eq!(store.retain(now, Retention { max_programmes: 100, ..retention }, kept), Vec::<u16>::new());
+        assert!(!store.stats().is_full);
+        assert_eq!(store.set_listings(&listings, now), vec![0x10bf]);
+        assert_eq!(store.programmes(0x10bf).len(), 6);
+    }
+}
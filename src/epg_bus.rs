@@ -0,0 +1,190 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The programme guide of the Me TV GUI as other programs, a dashboard for example, see
+//! it on the D-Bus session bus.
+//!
+//! Whilst it runs the GUI offers the interface `uk.org.winder.MeTV.EPG` at the object path
+//! `/uk/org/winder/MeTV/EPG` under the bus name of the same name. It is read only:
+//!
+//! * `GetNowNext(channel)` is the programmes on now and next on the channel;
+//! * `GetSchedule(channel, from, to)` is the programmes on the channel at any time from
+//!   `from` to `to`, in order of start;
+//! * the signal `NowNextChanged(channel, now_next)` is the new programmes on now and next
+//!   on a channel being watched, whenever they change.
+//!
+//! The programmes are JSON strings and the times Unix times, seconds since the start of
+//! 1970 UTC. A channel the programme guide has nothing for yet, or nothing for then, has
+//! no programmes rather than being an error. A name that is not that of a channel is an
+//! error.
+
+use chrono::NaiveDateTime;
+
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::epg::{NowNext, Programme};
+
+/// The bus name of the GUI's programme guide service.
+pub const BUS_NAME: &str = "uk.org.winder.MeTV.EPG";
+
+/// The object path of the GUI's programme guide service.
+pub const OBJECT_PATH: &str = "/uk/org/winder/MeTV/EPG";
+
+/// The interface of the GUI's programme guide service.
+pub const INTERFACE: &str = "uk.org.winder.MeTV.EPG";
+
+/// The signal of the now and next programmes on a channel being watched changing.
+pub const NOW_NEXT_CHANGED: &str = "NowNextChanged";
+
+/// A programme as the service gives it, times being Unix times.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BusProgramme {
+    pub title: String,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub duration_seconds: u32,
+    pub description: String,
+    pub extended_description: String,
+    pub language: String,
+    pub genres: Vec<String>,
+    pub minimum_age: Option<u8>,
+    pub programme_crid: Option<String>,
+    pub series_crid: Option<String>,
+}
+
+impl From<&Programme> for BusProgramme {
+    fn from(programme: &Programme) -> BusProgramme {
+        BusProgramme {
+            title: programme.title.clone(),
+            start: programme.start.map(|start| start.timestamp()),
+            end: programme.end().map(|end| end.timestamp()),
+            duration_seconds: programme.duration_seconds,
+            description: programme.description.clone(),
+            extended_description: programme.extended_description.clone(),
+            language: programme.language.clone(),
+            genres: programme.genres.clone(),
+            minimum_age: programme.minimum_age(),
+            programme_crid: programme.programme_crid.clone(),
+            series_crid: programme.series_crid.clone(),
+        }
+    }
+}
+
+/// The now and next programmes on a channel as the service gives them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BusNowNext {
+    pub channel: String,
+    pub now: Option<BusProgramme>,
+    pub next: Option<BusProgramme>,
+}
+
+/// The programmes on a channel from one time to another as the service gives them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BusSchedule {
+    pub channel: String,
+    pub from: i64,
+    pub to: i64,
+    pub programmes: Vec<BusProgramme>,
+}
+
+/// The time, UTC, of the Unix time `seconds`, if there is one.
+pub fn time_of(seconds: i64) -> Option<NaiveDateTime> {
+    NaiveDateTime::from_timestamp_opt(seconds, 0)
+}
+
+/// The JSON of `now_next`, the now and next programmes on the channel `channel`, None for
+/// none being known.
+pub fn now_next_json(channel: &str, now_next: Option<&NowNext>) -> String {
+    let now_next = BusNowNext {
+        channel: channel.to_string(),
+        now: now_next.and_then(|now_next| now_next.now.as_ref()).map(BusProgramme::from),
+        next: now_next.and_then(|now_next| now_next.next.as_ref()).map(BusProgramme::from),
+    };
+    serde_json::to_string(&now_next).expect("Could not serialise the now and next programmes.")
+}
+
+/// The JSON of `programmes`, the programmes on the channel `channel` from `from` to `to`, UTC.
+pub fn schedule_json<'a>(channel: &str, from: NaiveDateTime, to: NaiveDateTime, programmes: impl Iterator<Item = &'a Programme>) -> String {
+    let schedule = BusSchedule {
+        channel: channel.to_string(),
+        from: from.timestamp(),
+        to: to.timestamp(),
+        programmes: programmes.map(BusProgramme::from).collect(),
+    };
+    serde_json::to_string(&schedule).expect("Could not serialise the schedule.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    fn programme(title: &str, hour: u32) -> Programme {
+        Programme {
+            event_id: Some(7),
+            start: Some(NaiveDate::from_ymd(2020, 10, 14).and_hms(hour, 0, 0)),
+            duration_seconds: 3600,
+            title: title.to_string(),
+            description: "Archive films.".to_string(),
+            language: "eng".to_string(),
+            genres: vec!["Arts/Culture".to_string()],
+            programme_crid: Some("/ABCD12".to_string()),
+            ..Programme::default()
+        }
+    }
+
+    #[test]
+    fn now_next_is_given_with_unix_times() {
+        let now_next = NowNext { now: Some(programme("Britain on Film", 21)), next: None };
+        let value: serde_json::Value = serde_json::from_str(&now_next_json("BBC FOUR", Some(&now_next))).unwrap();
+        assert_eq!(value["channel"], "BBC FOUR");
+        assert_eq!(value["now"]["title"], "Britain on Film");
+        assert_eq!(value["now"]["start"], 1_602_709_200);
+        assert_eq!(value["now"]["end"], 1_602_712_800);
+        assert_eq!(value["now"]["genres"][0], "Arts/Culture");
+        assert_eq!(value["now"]["programme_crid"], "/ABCD12");
+        assert!(value["next"].is_null());
+    }
+
+    #[test]
+    fn nothing_known_is_no_programmes() {
+        let value: serde_json::Value = serde_json::from_str(&now_next_json("BBC FOUR", None)).unwrap();
+        assert!(value["now"].is_null());
+        assert!(value["next"].is_null());
+        let (from, to) = (time_of(1_602_705_600).unwrap(), time_of(1_602_720_000).unwrap());
+        let value: serde_json::Value = serde_json::from_str(&schedule_json("BBC FOUR", from, to, std::iter::empty())).unwrap();
+        assert_eq!(value["from"], 1_602_705_600);
+        assert_eq!(value["programmes"].as_array().map(Vec::len), Some(0));
+    }
+
+    #[test]
+    fn schedule_has_the_programmes_in_order() {
+        let programmes = vec![programme("Britain on Film", 21), programme("Timeshift", 22)];
+        let (from, to) = (time_of(1_602_705_600).unwrap(), time_of(1_602_720_000).unwrap());
+        let value: serde_json::Value = serde_json::from_str(&schedule_json("BBC FOUR", from, to, programmes.iter())).unwrap();
+        assert_eq!(value["to"], 1_602_720_000);
+        assert_eq!(value["programmes"][0]["title"], "Britain on Film");
+        assert_eq!(value["programmes"][1]["title"], "Timeshift");
+        assert_eq!(value["programmes"][1]["language"], "eng");
+    }
+}
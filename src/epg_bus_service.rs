@@ -0,0 +1,130 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The GUI end of the programme guide on the D-Bus session bus, see `me_tv::epg_bus`.
+
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use dbus::blocking::Connection;
+use dbus::channel::Sender;
+use dbus::message::MatchRule;
+use dbus_crossroads::{Crossroads, MethodErr};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+
+use me_tv::epg_bus::{now_next_json, schedule_json, time_of, BUS_NAME, INTERFACE, NOW_NEXT_CHANGED, OBJECT_PATH};
+
+use crate::channels_data::get_channel_names_and_service_ids;
+use crate::epg_manager;
+
+/// How long the service waits for method calls before emitting the signals there are.
+const SIGNAL_INTERVAL: Duration = Duration::from_millis(250);
+
+// The channels being watched whose now and next programmes have changed, to be
+// signalled, whilst the service is running.
+lazy_static! {
+    static ref NOW_NEXT_CHANGES: Mutex<Option<mpsc::Sender<String>>> = Mutex::new(None);
+}
+
+/// Signal that the now and next programmes on the channel `channel_name`, which is being
+/// watched, have changed, if the service is running.
+pub fn now_next_changed(channel_name: &str) { // Used in control_window_button.rs
+    if let Some(changes) = NOW_NEXT_CHANGES.lock().unwrap().as_ref() {
+        let _ = changes.send(channel_name.to_string());
+    }
+}
+
+/// The service id of the channel `channel`, an invalid argument if it is not one of the
+/// channels.
+fn service_id_of(channel: &str) -> Result<u16, MethodErr> {
+    get_channel_names_and_service_ids().unwrap_or_default().into_iter()
+        .find(|(name, _)| name == channel)
+        .map(|(_, service_id)| service_id)
+        .ok_or_else(|| MethodErr::invalid_arg(&channel))
+}
+
+/// Emit the signal of the now and next programmes on the channel `channel` having changed.
+fn signal_now_next(connection: &Connection, channel: &str) {
+    let now_next = now_next_json(channel, epg_manager::now_next(channel).as_ref());
+    match dbus::Message::new_signal(OBJECT_PATH, INTERFACE, NOW_NEXT_CHANGED) {
+        Ok(signal) => {
+            if connection.send(signal.append2(channel, now_next)).is_err() {
+                debug!("Could not signal the change of the now and next programmes on {}.", channel);
+            }
+        },
+        Err(e) => warn!("Could not make the signal of the now and next programmes: {}", e),
+    }
+}
+
+/// The dæmon offering the programme guide on the D-Bus session bus.
+///
+/// The programme store is only ever read. If there is no session bus the programme guide
+/// is not offered, but is otherwise unaffected.
+pub fn run() {
+    let connection = match Connection::new_session() {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!("No D-Bus session bus, so the programme guide is not offered on it: {}", e);
+            return;
+        },
+    };
+    if let Err(e) = connection.request_name(BUS_NAME, false, true, true) {
+        warn!("Could not acquire the D-Bus name {}, so the programme guide is not offered on it: {}", BUS_NAME, e);
+        return;
+    }
+    let mut crossroads = Crossroads::new();
+    let interface = crossroads.register(INTERFACE, |builder| {
+        builder.method("GetNowNext", ("channel",), ("now_next",), |_, _, (channel,): (String,)| {
+            service_id_of(&channel)?;
+            Ok((now_next_json(&channel, epg_manager::now_next(&channel).as_ref()),))
+        });
+        builder.method("GetSchedule", ("channel", "from", "to"), ("schedule",), |_, _, (channel, from, to): (String, i64, i64)| {
+            let service_id = service_id_of(&channel)?;
+            let (from, to) = match (time_of(from), time_of(to)) {
+                (Some(from), Some(to)) if from <= to => (from, to),
+                _ => return Err(MethodErr::invalid_arg(&(from, to))),
+            };
+            Ok((epg_manager::with_programmes(|store| schedule_json(&channel, from, to, store.programmes_between(service_id, from, to))),))
+        });
+        builder.signal::<(String, String), _>(NOW_NEXT_CHANGED, ("channel", "now_next"));
+    });
+    crossroads.insert(OBJECT_PATH, &[interface], ());
+    connection.start_receive(MatchRule::new_method_call(), Box::new(move |message, connection| {
+        if crossroads.handle_message(message, connection).is_err() {
+            debug!("A D-Bus message to the programme guide service was not a method call.");
+        }
+        true
+    }));
+    let (to_service, changes) = mpsc::channel();
+    *NOW_NEXT_CHANGES.lock().unwrap() = Some(to_service);
+    info!("Offering the programme guide on the D-Bus session bus as {}.", BUS_NAME);
+    loop {
+        if let Err(e) = connection.process(SIGNAL_INTERVAL) {
+            warn!("The programme guide service stopped: {}", e);
+            break;
+        }
+        for channel in changes.try_iter() {
+            signal_now_next(&connection, &channel);
+        }
+    }
+    *NOW_NEXT_CHANGES.lock().unwrap() = None;
+}
@@ -0,0 +1,185 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The cache of the programmes of the EIT, kept so that the programme guide has
+//! something in it as soon as Me TV starts rather than only once the schedules have been
+//! received again.
+//!
+//! The cache file is JSON: the format version, the time the cache was saved in seconds
+//! since the epoch, UTC, and the programmes by service id, as serde serialises them. A
+//! cache of a different format version is not read, so changing the format only needs
+//! the version changing.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, create_dir_all};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{Duration, NaiveDateTime};
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use xdg;
+
+use crate::epg::Programme;
+
+/// The version of the format of the cache file, to be changed whenever the format is.
+pub const FORMAT_VERSION: u8 = 5;
+
+/// The contents of a cache file.
+#[derive(Deserialize, Serialize)]
+struct Cache<'a> {
+    version: u8,
+    saved_at: i64,  // Seconds since the epoch, UTC.
+    programmes: Cow<'a, HashMap<u16, Vec<Programme>>>,
+}
+
+/// Just the format version of a cache file, so that a cache of another format can be
+/// told from one that is broken.
+#[derive(Deserialize)]
+struct Version {
+    version: u8,
+}
+
+/// The path of the cache file, in the XDG cache directory.
+pub fn cache_file_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_cache_home();
+    path_buf.push("epg.cache");
+    path_buf
+}
+
+/// The cache of the programmes `programmes`, by service id, saved at `now`, UTC.
+pub fn encode(programmes: &HashMap<u16, Vec<Programme>>, now: NaiveDateTime) -> Vec<u8> {
+    let cache = Cache { version: FORMAT_VERSION, saved_at: now.timestamp(), programmes: Cow::Borrowed(programmes) };
+    serde_json::to_vec(&cache).expect("Could not serialise the EPG cache.")
+}
+
+/// The programmes, by service id, of the cache `bytes`, leaving out those that had ended
+/// by `now`, UTC, and all of them if the cache was saved more than `max_age` before
+/// `now`. It is an error for the cache to be of a different format version.
+pub fn decode(bytes: &[u8], now: NaiveDateTime, max_age: Duration) -> Result<HashMap<u16, Vec<Programme>>, String> {
+    let version = serde_json::from_slice::<Version>(bytes).map_err(|e| format!("The file is not a Me TV EPG cache: {}", e))?.version;
+    if version != FORMAT_VERSION {
+        return Err(format!("The cache is of format version {}, not {}.", version, FORMAT_VERSION));
+    }
+    let cache = serde_json::from_slice::<Cache>(bytes).map_err(|e| format!("The cache file is broken: {}", e))?;
+    let saved_at = NaiveDateTime::from_timestamp_opt(cache.saved_at, 0).ok_or_else(|| format!("The cache file has a time out of range, {}.", cache.saved_at))?;
+    if saved_at + max_age < now {
+        return Ok(HashMap::new());
+    }
+    let mut by_service = HashMap::new();
+    for (service_id, programmes) in cache.programmes.into_owned() {
+        let programmes = programmes.into_iter().filter(|programme| programme.end().map_or(true, |end| end > now)).collect::<Vec<_>>();
+        if !programmes.is_empty() {
+            by_service.insert(service_id, programmes);
+        }
+    }
+    Ok(by_service)
+}
+
+/// Write the cache of the programmes `programmes`, by service id, saved at `now`, UTC,
+/// to `path`. The cache is written to a file beside it which then replaces it, so that a
+/// cache is never half written.
+pub fn write_cache(path: &Path, programmes: &HashMap<u16, Vec<Programme>>, now: NaiveDateTime) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        create_dir_all(directory).map_err(|e| format!("Could not create {}: {}", directory.display(), e))?;
+    }
+    let new_path = path.with_extension("cache.new");
+    fs::write(&new_path, encode(programmes, now)).map_err(|e| format!("Could not write the EPG cache {}: {}", new_path.display(), e))?;
+    fs::rename(&new_path, path).map_err(|e| format!("Could not replace the EPG cache {}: {}", path.display(), e))
+}
+
+/// Read the cache at `path` as [decode](fn.decode.html) does. A missing cache has no
+/// programmes.
+pub fn read_cache(path: &Path, now: NaiveDateTime, max_age: Duration) -> Result<HashMap<u16, Vec<Programme>>, String> {
+    match fs::read(path) {
+        Ok(bytes) => decode(&bytes, now, max_age).map_err(|e| format!("{} {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(format!("Could not read the EPG cache {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    use crate::eit::{EventText, ParentalRating, RunningStatus};
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 10, 14).and_hms(hour, 0, 0)
+    }
+
+    fn programme(event_id: Option<u16>, start: Option<NaiveDateTime>, title: &str) -> Programme {
+        Programme {
+            event_id,
+            start,
+            duration_seconds: 3600,
+            running_status: RunningStatus::NotRunning,
+            title: title.to_string(),
+            description: "Ünïcödé – description".to_string(),
+            language: "cym".to_string(),
+            texts: vec![
+                EventText { language: "cym".to_string(), title: title.to_string(), ..EventText::default() },
+                EventText { language: "eng".to_string(), title: "In English".to_string(), ..EventText::default() },
+            ],
+            genres: vec!["News".to_string(), "Current affairs".to_string()],
+            ratings: vec![ParentalRating { country: "GBR".to_string(), rating: 0x09 }],
+            programme_crid: event_id.map(|event_id| format!("/KFNVX{}", event_id)),
+            series_crid: Some("/KFNVXV".to_string()),
+            ..Programme::default()
+        }
+    }
+
+    fn cache() -> HashMap<u16, Vec<Programme>> {
+        let mut programmes = HashMap::new();
+        programmes.insert(4164, vec![programme(Some(1), Some(at(18)), "News"), programme(Some(2), Some(at(19)), "Weather")]);
+        programmes.insert(4287, vec![programme(Some(7), None, "Films"), programme(None, Some(at(21)), "Late")]);
+        programmes
+    }
+
+    #[test]
+    fn programmes_round_trip() {
+        let decoded = decode(&encode(&cache(), at(17)), at(17), Duration::days(7)).unwrap();
+        assert_eq!(decoded, cache());
+    }
+
+    #[test]
+    fn a_cache_of_another_format_version_is_not_read() {
+        let other_version = String::from_utf8(encode(&cache(), at(17))).unwrap()
+            .replacen(&format!("\"version\":{}", FORMAT_VERSION), &format!("\"version\":{}", FORMAT_VERSION + 1), 1);
+        assert_eq!(decode(other_version.as_bytes(), at(17), Duration::days(7)), Err(format!("The cache is of format version {}, not {}.", FORMAT_VERSION + 1, FORMAT_VERSION)));
+        assert!(decode(b"Not a cache at all", at(17), Duration::days(7)).is_err());
+        let bytes = encode(&cache(), at(17));
+        assert!(decode(&bytes[..bytes.len() - 3], at(17), Duration::days(7)).is_err());
+    }
+
+    #[test]
+    fn programmes_that_have_ended_and_caches_too_old_are_left_out() {
+        let decoded = decode(&encode(&cache(), at(17)), at(20), Duration::days(7)).unwrap();
+        assert!(!decoded.contains_key(&4164));
+        let titles = decoded[&4287].iter().map(|p| p.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Films", "Late"]);
+        assert!(decode(&encode(&cache(), at(17)), at(17) + Duration::days(8), Duration::days(7)).unwrap().is_empty());
+    }
+}
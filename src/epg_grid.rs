@@ -0,0 +1,282 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The layout of the programme guide grid: a row for each channel, time going across,
+//! and each programme a block as wide as it is long.
+//!
+//! A week of programmes of a hundred channels is tens of thousands of programmes, so
+//! nothing is laid out for the whole grid, only for the part of it on show, and that
+//! afresh whenever what is on show changes.
+
+use std::ops::Range;
+
+use chrono::{Duration, Local, NaiveDateTime, TimeZone};
+
+use crate::epg::{Programme, ProgrammeStore};
+
+/// How tall the row of a channel is, in pixels.
+pub const ROW_HEIGHT: i32 = 48;
+
+/// How wide a minute is, in pixels.
+pub const PIXELS_PER_MINUTE: f64 = 5.0;
+
+/// How far apart the marks of the time line are, in minutes. The grid starts at one.
+const MARK_INTERVAL_MINUTES: i64 = 30;
+
+/// How many days the grid goes on for, the EIT schedule tables going up to eight days
+/// ahead.
+const GRID_DAYS: i64 = 8;
+
+/// A programme laid out in the grid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub row: usize,
+    pub start: NaiveDateTime,  // UTC, later than the programme starts if the programme before it ends later.
+    pub end: NaiveDateTime,  // UTC.
+    pub is_trimmed: bool,  // The programme starts before the programme before it ends.
+    pub programme: Programme,
+}
+
+/// The ways of moving from a programme of the grid to another.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// The grid of the programmes of some channels, from the start of the half hour it was
+/// made in.
+#[derive(Clone, Debug)]
+pub struct Grid {
+    channels: Vec<(String, u16)>,  // The name and service id of the channel of each row.
+    start: NaiveDateTime,  // UTC.
+    end: NaiveDateTime,  // UTC.
+}
+
+impl Grid {
+    /// A grid of the channels `channels`, names and service ids, from the start of the
+    /// half hour `now`, UTC, is in.
+    pub fn new(channels: Vec<(String, u16)>, now: NaiveDateTime) -> Grid {
+        let interval = MARK_INTERVAL_MINUTES * 60;
+        let start = NaiveDateTime::from_timestamp(now.timestamp().div_euclid(interval) * interval, 0);
+        Grid { channels, start, end: start + Duration::days(GRID_DAYS) }
+    }
+
+    /// The names and service ids of the channels of the rows.
+    pub fn channels(&self) -> &[(String, u16)] { &self.channels }
+
+    /// How wide the whole grid is, in pixels.
+    pub fn width(&self) -> i32 { self.x_of(self.end) }
+
+    /// How tall the whole grid is, in pixels.
+    pub fn height(&self) -> i32 { self.channels.len() as i32 * ROW_HEIGHT }
+
+    /// How far across the grid `time`, UTC, is, in pixels, times before or after the
+    /// grid being at its edges.
+    pub fn x_of(&self, time: NaiveDateTime) -> i32 {
+        let time = time.max(self.start).min(self.end);
+        ((time - self.start).num_seconds() as f64 * PIXELS_PER_MINUTE / 60.0).round() as i32
+    }
+
+    /// The time, UTC, `x` pixels across the grid.
+    pub fn time_at(&self, x: f64) -> NaiveDateTime {
+        self.start + Duration::seconds((x.max(0.0) * 60.0 / PIXELS_PER_MINUTE) as i64)
+    }
+
+    /// The rows any part of which is in the `height` pixels from `y` pixels down the grid.
+    pub fn rows_in(&self, y: f64, height: f64) -> Range<usize> {
+        let row_height = f64::from(ROW_HEIGHT);
+        let first = (y.max(0.0) / row_height).floor() as usize;
+        let last = ((y + height).max(0.0) / row_height).ceil() as usize;
+        first.min(self.channels.len())..last.min(self.channels.len())
+    }
+
+    /// The programmes on show in the part of the grid `width` by `height` pixels from `x`
+    /// pixels across and `y` pixels down, laid out.
+    pub fn blocks(&self, store: &ProgrammeStore, x: f64, y: f64, width: f64, height: f64) -> Vec<Block> {
+        let (from, to) = (self.time_at(x), self.time_at(x + width));
+        self.rows_in(y, height)
+            .flat_map(|row| self.row_blocks(store, row, to))
+            .filter(|block| block.end > from)
+            .collect()
+    }
+
+    /// The marks of the time line in the `width` pixels from `x` pixels across: how far
+    /// across each is, and its time as local time, with the day at midnight.
+    pub fn marks(&self, x: f64, width: f64) -> Vec<(i32, String)> {
+        let interval = MARK_INTERVAL_MINUTES * 60;
+        let (from, to) = (self.time_at(x), self.time_at(x + width).min(self.end));
+        let first = ((from - self.start).num_seconds() + interval - 1) / interval;
+        (first..)
+            .map(|n| self.start + Duration::seconds(n * interval))
+            .take_while(|time| *time <= to)
+            .map(|time| {
+                let local = Local.from_utc_datetime(&time);
+                let text = local.format("%H:%M").to_string();
+                (self.x_of(time), if text == "00:00" { local.format("%a %e %b").to_string() } else { text })
+            })
+            .collect()
+    }
+
+    /// The programme of the row `row` on at `time`, UTC, or else the first after it, or
+    /// else the last before it, laid out. None if the channel has no programmes.
+    pub fn block_at(&self, store: &ProgrammeStore, row: usize, time: NaiveDateTime) -> Option<Block> {
+        let blocks = self.row_blocks(store, row, self.end);
+        blocks.iter().find(|block| block.end > time).or_else(|| blocks.last()).cloned()
+    }
+
+    /// The programme to move to from `block` in the direction `direction`: the one
+    /// before or after it on its channel, or the one on when it starts on the nearest
+    /// channel above or below it that has any programmes.
+    pub fn next_block(&self, store: &ProgrammeStore, block: &Block, direction: Direction) -> Option<Block> {
+        match direction {
+            Direction::Left => self.row_blocks(store, block.row, block.start).into_iter().rev().find(|b| b.start < block.start),
+            Direction::Right => self.row_blocks(store, block.row, self.end).into_iter().find(|b| b.start > block.start),
+            Direction::Up => (0..block.row).rev().find_map(|row| self.block_at(store, row, block.start)),
+            Direction::Down => (block.row + 1..self.channels.len()).find_map(|row| self.block_at(store, row, block.start)),
+        }
+    }
+
+    /// The programmes of the row `row` on at any time from the start of the grid to `to`,
+    /// UTC, laid out.
+    fn row_blocks(&self, store: &ProgrammeStore, row: usize, to: NaiveDateTime) -> Vec<Block> {
+        match self.channels.get(row) {
+            Some((_, service_id)) => lay_out(row, store.programmes_between(*service_id, self.start, to.min(self.end))),
+            None => vec![],
+        }
+    }
+}
+
+/// Lay out the programmes `programmes`, in order of start, of the row `row`, so that no
+/// two blocks overlap. The events of the EIT can contradict each other: a programme that
+/// starts before the programme before it ends starts where that one ends, and one that
+/// ends before then too is left out.
+fn lay_out<'a>(row: usize, programmes: impl Iterator<Item = &'a Programme>) -> Vec<Block> {
+    let mut blocks: Vec<Block> = vec![];
+    for programme in programmes {
+        let (start, end) = match (programme.start, programme.end()) {
+            (Some(start), Some(end)) => (start, end),
+            _ => continue,
+        };
+        let block_start = blocks.last().map_or(start, |previous| start.max(previous.end));
+        if end > block_start {
+            blocks.push(Block { row, start: block_start, end, is_trimmed: block_start != start, programme: programme.clone() });
+        }
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 11, 2).and_hms(hour, minute, 0)
+    }
+
+    fn listed(title: &str, from: NaiveDateTime, minutes: u32) -> Programme {
+        Programme {
+            start: Some(from),
+            duration_seconds: minutes * 60,
+            title: title.to_string(),
+            ..Programme::default()
+        }
+    }
+
+    fn titles(blocks: &[Block]) -> Vec<(usize, &str)> {
+        blocks.iter().map(|block| (block.row, block.programme.title.as_str())).collect()
+    }
+
+    /// Three channels, the middle one with no programmes, and the last with a programme
+    /// that the broadcaster has overlapping the ones either side of it.
+    fn grid_and_store() -> (Grid, ProgrammeStore) {
+        let channels = vec![("BBC ONE".to_string(), 1), ("Empty".to_string(), 2), ("ITV".to_string(), 3)];
+        let grid = Grid::new(channels, at(9, 47));
+        let mut store = ProgrammeStore::default();
+        let mut listings = HashMap::new();
+        listings.insert(1, vec![listed("Breakfast", at(6, 0), 195), listed("Homes", at(9, 15), 45), listed("Bargain", at(10, 0), 60), listed("News", at(11, 0), 30)]);
+        listings.insert(3, vec![listed("GMB", at(6, 0), 240), listed("Overlap", at(9, 50), 10), listed("Lorraine", at(10, 0), 60), listed("Loose", at(10, 0), 45)]);
+        store.set_listings(&listings, at(9, 47));
+        (grid, store)
+    }
+
+    #[test]
+    fn the_grid_starts_at_the_half_hour() {
+        let (grid, _) = grid_and_store();
+        assert_eq!(grid.x_of(at(9, 30)), 0);
+        assert_eq!(grid.x_of(at(6, 0)), 0);
+        assert_eq!(grid.x_of(at(10, 0)), 150);
+        assert_eq!(grid.time_at(150.0), at(10, 0));
+        assert_eq!(grid.width(), 8 * 24 * 60 * 5);
+        assert_eq!(grid.height(), 3 * ROW_HEIGHT);
+        assert_eq!(grid.marks(10.0, 300.0).into_iter().map(|(x, _)| x).collect::<Vec<_>>(), vec![150, 300]);
+    }
+
+    #[test]
+    fn only_what_is_on_show_is_laid_out() {
+        let (grid, store) = grid_and_store();
+        assert_eq!(grid.rows_in(0.0, 40.0), 0..1);
+        assert_eq!(grid.rows_in(40.0, 60.0), 0..3);
+        assert_eq!(grid.rows_in(100.0, 1000.0), 2..3);
+        assert_eq!(titles(&grid.blocks(&store, 0.0, 0.0, 100.0, 40.0)), vec![(0, "Homes")]);
+        assert_eq!(titles(&grid.blocks(&store, 100.0, 0.0, 400.0, 200.0)), vec![(0, "Homes"), (0, "Bargain"), (0, "News"), (2, "GMB"), (2, "Lorraine")]);
+    }
+
+    #[test]
+    fn overlapping_programmes_do_not_overlap_in_the_grid() {
+        let (grid, store) = grid_and_store();
+        let blocks = grid.blocks(&store, 0.0, 100.0, 900.0, 40.0);
+        assert_eq!(titles(&blocks), vec![(2, "GMB"), (2, "Lorraine")]);
+        assert_eq!((blocks[1].start, blocks[1].end, blocks[1].is_trimmed), (at(10, 0), at(11, 0), false));
+        let (grid, mut store) = grid_and_store();
+        let mut listings = HashMap::new();
+        listings.insert(3, vec![listed("Short", at(9, 30), 20), listed("Long", at(9, 40), 30)]);
+        store.set_listings(&listings, at(9, 47));
+        let blocks = grid.blocks(&store, 0.0, 100.0, 900.0, 40.0);
+        assert_eq!(titles(&blocks), vec![(2, "Short"), (2, "Long")]);
+        assert_eq!((blocks[1].start, blocks[1].end, blocks[1].is_trimmed), (at(9, 50), at(10, 10), true));
+    }
+
+    #[test]
+    fn moving_about_the_grid_skips_channels_with_no_programmes() {
+        let (grid, store) = grid_and_store();
+        let homes = grid.block_at(&store, 0, at(9, 47)).unwrap();
+        assert_eq!(homes.programme.title, "Homes");
+        assert_eq!(grid.block_at(&store, 1, at(9, 47)), None);
+        let bargain = grid.next_block(&store, &homes, Direction::Right).unwrap();
+        assert_eq!(bargain.programme.title, "Bargain");
+        assert_eq!(grid.next_block(&store, &bargain, Direction::Left), Some(homes.clone()));
+        assert_eq!(grid.next_block(&store, &homes, Direction::Left), None);
+        let lorraine = grid.next_block(&store, &bargain, Direction::Down).unwrap();
+        assert_eq!((lorraine.row, lorraine.programme.title.as_str()), (2, "Lorraine"));
+        assert_eq!(grid.next_block(&store, &lorraine, Direction::Down), None);
+        assert_eq!(grid.next_block(&store, &lorraine, Direction::Up), Some(bargain));
+        let news = grid.block_at(&store, 0, at(11, 10)).unwrap();
+        assert_eq!(grid.next_block(&store, &news, Direction::Right), None);
+        assert_eq!(grid.block_at(&store, 0, at(13, 0)), Some(news));
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Deciding where to harvest the EPG: the EIT schedule of a multiplex only comes from
+//! tuning to it, so frontends that are not otherwise in use are tuned to each of the
+//! transponders of the channels in turn, for a while each, to fill in the programme
+//! guide for the channels not being watched.
+//!
+//! A transponder never harvested comes first, then the one harvested longest ago, and
+//! none is harvested again until a while after it last was, the schedules only changing
+//! now and then.
+
+use std::time::{Duration, Instant};
+
+use crate::scan::Transponder;
+
+/// Whether `hour`, 0 to 23, is in the hours from the start of hour `from` to the start
+/// of hour `to`, which is the next day if it is not after `from`. From and to being the
+/// same is all day.
+pub fn is_harvesting_hour(hour: u32, from: u32, to: u32) -> bool {
+    match from.cmp(&to) {
+        std::cmp::Ordering::Less => hour >= from && hour < to,
+        std::cmp::Ordering::Equal => true,
+        std::cmp::Ordering::Greater => hour >= from || hour < to,
+    }
+}
+
+/// The transponders to harvest and when each was last harvested.
+#[derive(Clone, Debug, Default)]
+pub struct HarvestPlan {
+    transponders: Vec<(Transponder, Option<Instant>)>,
+}
+
+impl HarvestPlan {
+    /// Harvest `transponders`, keeping when those already in the plan were harvested.
+    pub fn set_transponders(&mut self, transponders: Vec<Transponder>) {
+        let previous = std::mem::replace(&mut self.transponders, Vec::new());
+        self.transponders = transponders.into_iter()
+            .map(|transponder| {
+                let harvested = previous.iter().find(|(t, _)| t.is_same_as(&transponder)).and_then(|(_, harvested)| *harvested);
+                (transponder, harvested)
+            })
+            .collect();
+    }
+
+    /// The transponder to harvest next at `now` of those that `can_harvest` says can be,
+    /// none having been harvested within `revisit` of `now`.
+    pub fn next(&self, now: Instant, revisit: Duration, can_harvest: impl Fn(&Transponder) -> bool) -> Option<&Transponder> {
+        self.transponders.iter()
+            .filter(|(_, harvested)| harvested.map_or(true, |harvested| now.duration_since(harvested) >= revisit))
+            .filter(|(transponder, _)| can_harvest(transponder))
+            .min_by_key(|(_, harvested)| *harvested)
+            .map(|(transponder, _)| transponder)
+    }
+
+    /// `transponder` has been harvested at `now`.
+    pub fn harvested(&mut self, transponder: &Transponder, now: Instant) {
+        if let Some((_, harvested)) = self.transponders.iter_mut().find(|(t, _)| t.is_same_as(transponder)) {
+            *harvested = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transponder(frequency: u32) -> Transponder {
+        Transponder{delivery_system: "DVBT2".to_string(), frequency, parameters: vec![]}
+    }
+
+    fn frequency(transponder: Option<&Transponder>) -> Option<u32> {
+        transponder.map(|t| t.frequency)
+    }
+
+    #[test]
+    fn harvesting_hours_may_run_past_midnight() {
+        assert!(is_harvesting_hour(2, 1, 6));
+        assert!(!is_harvesting_hour(6, 1, 6));
+        assert!(is_harvesting_hour(23, 22, 6));
+        assert!(is_harvesting_hour(3, 22, 6));
+        assert!(!is_harvesting_hour(12, 22, 6));
+        assert!(is_harvesting_hour(12, 0, 24));
+        assert!(is_harvesting_hour(12, 4, 4));
+    }
+
+    #[test]
+    fn transponders_are_harvested_least_recently_harvested_first() {
+        let start = Instant::now();
+        let revisit = Duration::from_secs(3 * 60 * 60);
+        let mut plan = HarvestPlan::default();
+        plan.set_transponders(vec![transponder(490_000_000), transponder(514_000_000), transponder(545_833_000)]);
+        assert_eq!(frequency(plan.next(start, revisit, |_| true)), Some(490_000_000));
+        plan.harvested(&transponder(490_000_000), start);
+        assert_eq!(frequency(plan.next(start, revisit, |_| true)), Some(514_000_000));
+        assert_eq!(frequency(plan.next(start, revisit, |t| t.frequency != 514_000_000)), Some(545_833_000));
+        plan.harvested(&transponder(514_000_000), start + Duration::from_secs(120));
+        plan.harvested(&transponder(545_833_000), start + Duration::from_secs(240));
+        assert_eq!(frequency(plan.next(start + Duration::from_secs(600), revisit, |_| true)), None);
+        assert_eq!(frequency(plan.next(start + revisit + Duration::from_secs(300), revisit, |_| true)), Some(490_000_000));
+    }
+
+    #[test]
+    fn the_harvest_times_of_transponders_still_there_are_kept() {
+        let start = Instant::now();
+        let revisit = Duration::from_secs(3 * 60 * 60);
+        let mut plan = HarvestPlan::default();
+        plan.set_transponders(vec![transponder(490_000_000), transponder(514_000_000)]);
+        plan.harvested(&transponder(490_000_000), start);
+        plan.set_transponders(vec![transponder(490_000_000), transponder(530_000_000)]);
+        assert_eq!(frequency(plan.next(start, revisit, |_| true)), Some(530_000_000));
+        plan.harvested(&transponder(530_000_000), start);
+        assert_eq!(frequency(plan.next(start, revisit, |_| true)), None);
+    }
+}
@@ -0,0 +1,241 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Harvesting the EPG: when the preferences allow, a frontend that nothing is using is
+//! tuned to each of the multiplexes of the channels in turn, for a couple of minutes
+//! each, so that the EIT schedules of the channels not being watched get into the
+//! programme guide.
+//!
+//! Harvesting always gives way. Its reservation of the frontend is pre-empted by viewing,
+//! recording or scanning in Me TV, and a recording by me-tv-record asking for the
+//! frontend stops it at once. A frontend another process holds the lock of is never
+//! harvested. The control window shows which frontend is harvesting which multiplex.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, Timelike};
+
+use glib;
+
+use gst;
+use gst::prelude::*;
+
+use lazy_static::lazy_static;
+
+use log::{debug, info, warn};
+
+use me_tv::eit::EIT_PID;
+use me_tv::epg_harvest::{is_harvesting_hour, HarvestPlan};
+use me_tv::frontend_lock::{holder_of, lock_directory};
+use me_tv::scan::{known_transponders, tuned_dvbsrc, Transponder};
+
+use crate::channels_data;
+use crate::control_window::Message;
+use crate::epg_manager::Input;
+use crate::frontend_manager::{self, DeliverySystem, FrontendId};
+use crate::gstreamer_engine::add_eit_probe;
+use crate::preferences;
+
+/// How often whether there is anything to harvest, and a frontend to do it with, is
+/// checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a multiplex is harvested for, long enough for all of the EIT schedule to
+/// have been broadcast.
+const DWELL: Duration = Duration::from_secs(120);
+
+/// How long before a multiplex is harvested again.
+const REVISIT: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// How often a harvest looks to see if it should stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A harvest under way, shared with whatever stops it.
+struct Harvest {
+    fei: FrontendId,
+    pipeline: Mutex<Option<gst::Pipeline>>,  // None until it is playing, and once stopped.
+    is_stopped: AtomicBool,
+}
+
+impl Harvest {
+    /// Stop the harvest, letting go of the frontend before returning.
+    fn stop(&self) {
+        self.is_stopped.store(true, Ordering::SeqCst);
+        if let Some(pipeline) = self.pipeline.lock().unwrap().take() {
+            let _ = pipeline.set_state(gst::State::Null);
+        }
+    }
+}
+
+/// How a harvest of a multiplex ended.
+#[derive(Debug, Eq, PartialEq)]
+enum Outcome {
+    /// It ran for the dwell time.
+    Harvested,
+    /// The multiplex could not be harvested, which trying again soon will not change.
+    Failed,
+    /// Something else wanted the frontend, or the preferences said to stop.
+    Stopped,
+}
+
+lazy_static! {
+    static ref HARVEST: Mutex<Option<Arc<Harvest>>> = Mutex::new(None);
+}
+
+/// Whether the frontend `fei` is being used for harvesting the EPG.
+pub fn is_harvesting(fei: &FrontendId) -> bool {  // Used in handover_service.rs.
+    HARVEST.lock().unwrap().as_ref().map_or(false, |harvest| harvest.fei == *fei && !harvest.is_stopped.load(Ordering::SeqCst))
+}
+
+/// Stop harvesting the EPG on the frontend `fei`, if it is being, at once.
+pub fn yield_frontend(fei: &FrontendId) {  // Used in handover_service.rs.
+    let harvest = HARVEST.lock().unwrap().clone();
+    if let Some(harvest) = harvest.filter(|harvest| harvest.fei == *fei) {
+        info!("Stopping harvesting the EPG on {} for a recording.", fei);
+        harvest.stop();
+    }
+}
+
+/// Whether the preferences say to harvest the EPG now.
+fn is_harvesting_time() -> bool {
+    preferences::get_epg_harvesting()
+        && is_harvesting_hour(Local::now().hour(), preferences::get_epg_harvesting_from_hour(), preferences::get_epg_harvesting_to_hour())
+}
+
+/// Whether the frontend `fei` can receive `transponder`.
+fn can_tune(fei: &FrontendId, transponder: &Transponder) -> bool {
+    frontend_manager::can_receive(fei, transponder.delivery_system.parse::<DeliverySystem>().ok())
+}
+
+/// The idle frontend to harvest with, and the transponder to harvest with it, if there are any.
+fn choose(plan: &HarvestPlan) -> Option<(FrontendId, Transponder)> {
+    let lock_directory = lock_directory();
+    frontend_manager::idle_frontends().into_iter()
+        .filter(|fei| holder_of(&lock_directory, fei).is_none())
+        .filter_map(|fei| {
+            let transponder = plan.next(Instant::now(), REVISIT, |transponder| can_tune(&fei, transponder))?.clone();
+            Some((fei, transponder))
+        })
+        .next()
+}
+
+/// Tell the control window what the frontend `fei` is harvesting, None for nothing.
+fn tell(to_cw: &glib::Sender<Message>, fei: &FrontendId, status: Option<String>) {
+    to_cw.send(Message::EpgHarvestChanged{fei: fei.clone(), status}).unwrap_or_else(|e| debug!("epg_harvester::tell: could not tell the control window: {}", e));
+}
+
+/// A pipeline passing the EIT of `transponder`, tuned by the frontend `fei`, to the EPG manager.
+fn harvesting_pipeline(fei: &FrontendId, transponder: &Transponder, to_epg_manager: mpsc::Sender<Input>) -> Result<gst::Pipeline, String> {
+    let source = tuned_dvbsrc(fei, transponder, &EIT_PID.to_string()).ok_or_else(|| "there is no dvbsrc GStreamer element".to_string())?;
+    let sink = gst::ElementFactory::make("fakesink", None).map_err(|_| "there is no fakesink GStreamer element".to_string())?;
+    let pipeline = gst::Pipeline::new(None);
+    pipeline.add_many(&[&source, &sink]).map_err(|e| e.to_string())?;
+    source.link(&sink).map_err(|e| e.to_string())?;
+    add_eit_probe(&source, to_epg_manager);
+    Ok(pipeline)
+}
+
+/// Harvest `transponder` with the frontend `fei` for the dwell time, or until the harvest
+/// is stopped, an error happens, or the preferences say to stop.
+fn harvest(fei: &FrontendId, transponder: &Transponder, to_cw: &glib::Sender<Message>, to_epg_manager: &mpsc::Sender<Input>) -> Outcome {
+    let harvest = Arc::new(Harvest{fei: fei.clone(), pipeline: Mutex::new(None), is_stopped: AtomicBool::new(false)});
+    let _lease = match frontend_manager::reserve_yielding(fei, {
+        let harvest = harvest.clone();
+        move || harvest.stop()
+    }) {
+        Ok(lease) => lease,
+        Err(e) => {
+            debug!("Could not reserve {} for harvesting the EPG: {}", fei, e);
+            return Outcome::Stopped;
+        },
+    };
+    let pipeline = match harvesting_pipeline(fei, transponder, to_epg_manager.clone()) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            warn!("Cannot harvest the EPG, {}.", e);
+            return Outcome::Failed;
+        },
+    };
+    {
+        // Held whilst starting so that a harvest stopped meanwhile is not started.
+        let mut playing = harvest.pipeline.lock().unwrap();
+        if harvest.is_stopped.load(Ordering::SeqCst) {
+            return Outcome::Stopped;
+        }
+        if pipeline.set_state(gst::State::Playing).is_err() {
+            let _ = pipeline.set_state(gst::State::Null);
+            warn!("Could not tune {} to {} to harvest the EPG.", fei, transponder);
+            return Outcome::Failed;
+        }
+        *playing = Some(pipeline.clone());
+    }
+    *HARVEST.lock().unwrap() = Some(harvest.clone());
+    info!("Harvesting the EPG on {} from {}.", fei, transponder);
+    tell(to_cw, fei, Some(format!("Collecting the programme guide from {} since {}", transponder, Local::now().format("%H:%M"))));
+    let started = Instant::now();
+    let bus = pipeline.get_bus().expect("A pipeline has no bus.");
+    let poll = gst::ClockTime::from_mseconds(STOP_POLL_INTERVAL.as_millis() as u64);
+    let outcome = loop {
+        if harvest.is_stopped.load(Ordering::SeqCst) {
+            break Outcome::Stopped;
+        }
+        if !is_harvesting_time() {
+            info!("Stopping harvesting the EPG on {}, the preferences say not to now.", fei);
+            break Outcome::Stopped;
+        }
+        if let Some(message) = bus.timed_pop_filtered(poll, &[gst::MessageType::Error]) {
+            if let gst::MessageView::Error(error) = message.view() {
+                warn!("Harvesting the EPG on {} from {} failed: {}", fei, transponder, error.get_error());
+                break Outcome::Failed;
+            }
+        }
+        if started.elapsed() >= DWELL {
+            break Outcome::Harvested;
+        }
+    };
+    harvest.stop();
+    *HARVEST.lock().unwrap() = None;
+    tell(to_cw, fei, None);
+    outcome
+}
+
+/// The dæmon that harvests the EPG on idle frontends whilst the preferences say to.
+///
+/// Only one frontend harvests at a time, there being no hurry. A multiplex that could not
+/// be harvested waits as long as one that was before it is tried again.
+pub fn run(to_cw: glib::Sender<Message>, to_epg_manager: mpsc::Sender<Input>) {
+    let mut plan = HarvestPlan::default();
+    loop {
+        if is_harvesting_time() {
+            plan.set_transponders(known_transponders(&channels_data::read_all_channels()));
+            while let Some((fei, transponder)) = choose(&plan) {
+                if harvest(&fei, &transponder, &to_cw, &to_epg_manager) == Outcome::Stopped {
+                    break;
+                }
+                plan.harvested(&transponder, Instant::now());
+            }
+        }
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
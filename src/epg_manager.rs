@@ -19,7 +19,16 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
 use std::panic;
+use std::path::Path;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{NaiveDateTime, Utc};
 
 use glib;
 use glib::translate::{from_glib, ToGlib};
@@ -27,8 +36,61 @@ use glib::translate::{from_glib, ToGlib};
 use gst;
 use gst_mpegts;
 
+use lazy_static::lazy_static;
+
+use log::{debug, info, warn};
+
+use me_tv::eit::EitSection;
+use me_tv::epg::{NowNext, NowNextTable, Programme, ProgrammeStore, Retention, Slippage};
+use me_tv::epg_cache::{cache_file_path, read_cache, write_cache};
+use me_tv::sdt::parse_service_descriptor;
+use me_tv::xmltv::{map_channels, programmes_by_service, read_xmltv};
+
 use crate::control_window::Message;
-use crate::channels_data::add_logical_channel_number_for_service_id;
+use crate::channels_data::{add_logical_channel_number_for_service_id, get_channel_names_and_service_ids, set_encrypted_for_service_id, set_provider_for_service_id, set_radio_for_service_id, set_service_type_for_service_id};
+use crate::preferences;
+use crate::programme_details;
+
+/// What the EPG manager is sent: the sections the demuxer posts on the bus, and the EIT
+/// sections parsed from the transport stream. The programme information comes from the
+/// latter, as decoding the short event descriptors of the former can panic.
+pub enum Input {
+    Section(gst_mpegts::Section),
+    Eit(EitSection),
+}
+
+lazy_static! {
+    /// The now and next programmes of the services of the multiplexes being received.
+    static ref NOW_NEXT: RwLock<NowNextTable> = RwLock::new(NowNextTable::default());
+    /// The programmes to come on the services of the multiplexes being received.
+    static ref PROGRAMMES: RwLock<ProgrammeStore> = RwLock::new(ProgrammeStore::default());
+}
+
+/// How often the programmes that have ended, and those the preferences say not to keep,
+/// are forgotten.
+const PROGRAMME_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the XMLTV listings file is checked for having changed.
+const LISTINGS_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the programmes of the EIT are written to the EPG cache if they have changed.
+const CACHE_SAVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Whether the EPG cache is being read, in which case it is not written over.
+static IS_LOADING_CACHE: AtomicBool = AtomicBool::new(true);
+
+/// Whether the programmes of the EIT have changed since the EPG cache was written.
+static IS_CACHE_STALE: AtomicBool = AtomicBool::new(false);
+
+/// What the listings in the programme store were made from, so as to know when they
+/// need making again.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ListingsSource {
+    path: String,
+    modified: Option<SystemTime>,
+    channels: Vec<(String, u16)>,
+    configured: BTreeMap<String, String>,  // The channels of the XMLTV channel ids, from the preferences.
+}
 
 static PRINT_BAT: bool = false;
 static PRINT_CAT: bool = false;
@@ -46,16 +108,16 @@ static PRINT_DATA: bool = false;
 fn build_bat(bat: &gst_mpegts::BAT, to_cw: &glib::Sender<Message>) {
     // Do not seem to get any of these on BBC News on Freeview from Crystal Palace.
     if PRINT_BAT {
-        println!("========  BAT section.");
+        debug!("========  BAT section.");
     }
     for descriptor in bat.get_descriptors().iter() {
         if PRINT_BAT {
-            println!("         {:?}", descriptor);
+            debug!("         {:?}", descriptor);
         }
     }
     for stream in bat.get_streams().iter() {
         if PRINT_BAT {
-            println!("         {:?}", stream);
+            debug!("         {:?}", stream);
         }
     }
 }
@@ -63,43 +125,43 @@ fn build_bat(bat: &gst_mpegts::BAT, to_cw: &glib::Sender<Message>) {
 fn build_cat(cat: &Vec<gst_mpegts::Descriptor>, to_cw: &glib::Sender<Message>) {
     // Do not seem to get any of these on BBC News on Freeview from Crystal Palace.
     if PRINT_CAT {
-        println!("========  CAT section:  {:?}", &cat);
+        debug!("========  CAT section:  {:?}", &cat);
     }
 }
 
 fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
     if PRINT_EIT {
-        println!("========  EIT section.");
+        debug!("========  EIT section.");
     }
     for event in eit.get_events().iter() {
         if PRINT_EIT {
-            println!("    EITEvent:  event_id = {:?}", event.get_event_id());
+            debug!("    EITEvent:  event_id = {:?}", event.get_event_id());
         }
         for d in event.get_descriptors().iter() {
             match d.get_tag() {
                 gst_mpegts::DVBDescriptorType::Component => {
                     let component = d.parse_dvb_component().unwrap();
                     if PRINT_EIT {
-                        println!("            Component  {:?}", &component);
+                        debug!("            Component  {:?}", &component);
                     }
                 },
                 gst_mpegts::DVBDescriptorType::Content => {
                     let c = d.parse_dvb_content().unwrap();
                     for item in c.iter() {
                         if PRINT_EIT {
-                            println!("            {}", gst_mpegts::content_description(item.get_content_nibble_1().to_glib(), item.get_content_nibble_2()))
+                            debug!("            {}", gst_mpegts::content_description(item.get_content_nibble_1().to_glib(), item.get_content_nibble_2()))
                         }
                     }
                 },
                 gst_mpegts::DVBDescriptorType::ContentIdentifier => {
                     if PRINT_EIT {
-                        println!("            Unknown processing technique");
+                        debug!("            Unknown processing technique");
                     }
                 },
                 gst_mpegts::DVBDescriptorType::Linkage => {
                     let linkage = d.parse_dvb_linkage().unwrap();
                     if PRINT_EIT {
-                        println!("            Linkage  {:?}", &linkage);
+                        debug!("            Linkage  {:?}", &linkage);
                     }
                 }
                 gst_mpegts::DVBDescriptorType::ShortEvent => {
@@ -111,21 +173,21 @@ fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
                     match panic::catch_unwind(|| {
                         let (language_code, title, blurb) = d.parse_dvb_short_event().unwrap();
                         if PRINT_EIT {
-                            println!("            {}, {}, {}", &language_code, &title, &blurb);
+                            debug!("            {}, {}, {}", &language_code, &title, &blurb);
                         }
                     }) {
                         Ok(_) => {},
-                        Err(_) => println!("************  parse_dvb_short_event panicked, assume there is a 0x1f encoding byte in the string."),
+                        Err(_) => warn!("parse_dvb_short_event panicked, assume there is a 0x1f encoding byte in the string."),
                     }
                 },
                 gst_mpegts::DVBDescriptorType::PrivateDataSpecifier => {
                     if PRINT_EIT {
-                        println!("            PrivateDataSpecifier  {:?}", &d.parse_dvb_private_data_specifier());
+                        debug!("            PrivateDataSpecifier  {:?}", &d.parse_dvb_private_data_specifier());
                     }
                 },
                 gst_mpegts::DVBDescriptorType::FtaContentManagement => {
                     if PRINT_EIT {
-                        println!("            FtaContentManagement  Unknown processing technique");
+                        debug!("            FtaContentManagement  Unknown processing technique");
                     }
                 },
                 x => {
@@ -155,16 +217,16 @@ fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
                                 None => "String with language code could not be processed.".to_string(),
                             };
                             if PRINT_EIT {
-                                println!("            MiscDescriptorType::__Unknown({}):  language_code = {}, private_data = {}", &x.to_glib(), &language_code, &message);
+                                debug!("            MiscDescriptorType::__Unknown({}):  language_code = {}, private_data = {}", &x.to_glib(), &language_code, &message);
                             }
                             // The messages all seem to be warnings about the programs.
                         },
-                        y => println!("************  Got an EIT tag: {:?}", y),
+                        y => debug!("Got an EIT tag: {:?}", y),
                     }
                 },
             }
             if PRINT_DATA {
-                println!("        {:?}", d.get_data());
+                debug!("        {:?}", d.get_data());
             }
         }
     }
@@ -172,7 +234,7 @@ fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
 
 fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
     if PRINT_NIT {
-        println!("========  NIT section: actual_network = {}, network_id = {}", nit.get_actual_network(), nit.get_network_id());
+        debug!("========  NIT section: actual_network = {}, network_id = {}", nit.get_actual_network(), nit.get_network_id());
     }
     for descriptor in nit.get_descriptors().iter() {
         // EN 300 468 Table 12 states which descriptors are allowed.
@@ -180,7 +242,7 @@ fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
             gst_mpegts::DVBDescriptorType::NetworkName => {
                 let name = descriptor.parse_dvb_network_name().unwrap();
                 if PRINT_NIT {
-                    println!("    NetworkName:  {}", &name);
+                    debug!("    NetworkName:  {}", &name);
                 }
             },
             gst_mpegts::DVBDescriptorType::Extension => {
@@ -188,7 +250,7 @@ fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
                     gst_mpegts::DVBExtendedDescriptorType::TargetRegionName => {
                         let target_region_name = descriptor.parse_target_region_name().unwrap();
                         if PRINT_NIT {
-                            println!("    Extension:  TargetRegionName:   country_code = {}, iso_639_language_code = {}, region_data = {:?}",
+                            debug!("    Extension:  TargetRegionName:   country_code = {}, iso_639_language_code = {}, region_data = {:?}",
                                      &target_region_name.get_country_code(),
                                      &target_region_name.get_iso_639_language_code(),
                                      &target_region_name.get_region_data());
@@ -197,7 +259,7 @@ fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
                     gst_mpegts::DVBExtendedDescriptorType::TargetRegion => {
                         let target_region = &descriptor.parse_target_region().unwrap();
                         if PRINT_NIT {
-                            println!("    Extension:  TargetRegion:  country_code = {}, additional_country_codes = {:?}",
+                            debug!("    Extension:  TargetRegion:  country_code = {}, additional_country_codes = {:?}",
                                      &target_region.get_country_code(),
                                      &target_region.get_additional_country_codes());
                         }
@@ -205,7 +267,7 @@ fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
                     gst_mpegts::DVBExtendedDescriptorType::Message => {
                         let message = descriptor.parse_message().unwrap();
                         if PRINT_NIT {
-                            println!("    Extension:  Message:  message_id = {}, iso_639_language_code = {}, message = {}",
+                            debug!("    Extension:  Message:  message_id = {}, iso_639_language_code = {}, message = {}",
                                      &message.get_message_id(),
                                      &message.get_iso_639_language_code(),
                                      &message.get_message());
@@ -214,20 +276,20 @@ fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
                     gst_mpegts::DVBExtendedDescriptorType::UriLinkage => {
                         let uri_linkage = descriptor.parse_uri_linkage().unwrap();
                         if PRINT_NIT {
-                            println!("    Extension:  UriLinkage:  uri_linkage_type = {:?}, uri = {}, min_polling_interval = {}, private_data = {:?}",
+                            debug!("    Extension:  UriLinkage:  uri_linkage_type = {:?}, uri = {}, min_polling_interval = {}, private_data = {:?}",
                                      &uri_linkage.get_uri_linkage_type(),
                                      &uri_linkage.get_uri(),
                                      &uri_linkage.get_min_polling_interval(),
                                      &uri_linkage.get_private_data());
                         }
                     },
-                    x => println!("************  Got an extended descriptor type {:?}", x),
+                    x => debug!("Got an extended descriptor type {:?}", x),
                 }
             },
             gst_mpegts::DVBDescriptorType::Linkage => {
                 let linkage = descriptor.parse_dvb_linkage().unwrap();
                 if PRINT_NIT {
-                    println!("    Linkage:  transport_stream_id = {}, original_network_id = {}, service_id = {}, linkage_type = {:?}",
+                    debug!("    Linkage:  transport_stream_id = {}, original_network_id = {}, service_id = {}, linkage_type = {:?}",
                              linkage.get_transport_stream_id(),
                              linkage.get_original_network_id(),
                              linkage.get_service_id(),
@@ -240,29 +302,29 @@ fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
                 // It seems that this is the original_network_id being presented at the NIT section level.
                 let private_data = descriptor.parse_dvb_private_data_specifier().unwrap();
                 if PRINT_NIT {
-                    println!("    PrivateDataSpecifier: {}, {:?}", &private_data.0, &private_data.1);
+                    debug!("    PrivateDataSpecifier: {}, {:?}", &private_data.0, &private_data.1);
                 }
             },
-            x => println!("************  Got a descriptor type {:?}", x),
+            x => debug!("Got a descriptor type {:?}", x),
         }
         if PRINT_DATA {
-            println!("        {:?}", descriptor.get_data());
+            debug!("        {:?}", descriptor.get_data());
         }
     }
     for stream in nit.get_streams().iter() {
         if PRINT_NIT {
-            println!("    NITStream:  transport_stream_id = {}, original_network_id = {}", stream.get_transport_stream_id(), stream.get_original_network_id());
+            debug!("    NITStream:  transport_stream_id = {}, original_network_id = {}", stream.get_transport_stream_id(), stream.get_original_network_id());
         }
         for descriptor in stream.get_descriptors().iter() {
             match descriptor.get_tag() {
                 gst_mpegts::DVBDescriptorType::ServiceList => {
                     let service_list = descriptor.parse_dvb_service_list().unwrap();
                     if PRINT_NIT {
-                        println!("        ServiceList:");
+                        debug!("        ServiceList:");
                     }
                     for service in service_list.iter() {
                         if PRINT_NIT {
-                            println!("            service_id = {}, service_type = {:?}",
+                            debug!("            service_id = {}, service_type = {:?}",
                                      &service.get_service_id(),
                                      &service.get_type());
                         }
@@ -271,7 +333,7 @@ fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
                 gst_mpegts::DVBDescriptorType::TerrestrialDeliverySystem => {
                     let terrestrial_delivery_system = descriptor.parse_terrestrial_delivery_system().unwrap();
                     if PRINT_NIT {
-                        println!("    TerrestrialDeliverySystem: \
+                        debug!("    TerrestrialDeliverySystem: \
 frequency = {}, bandwidth = {}, priority = {}, time_slicing = {}, mpe_fec = {}, constellation = {:?}, hierarchy = {:?}, \
 code_rate_hp = {:?}, code_rate_lp = {:?}, guard_interval = {:?}, transmission_mode = {:?}, other_frequency = {}",
                                  &terrestrial_delivery_system.get_frequency(),
@@ -293,7 +355,7 @@ code_rate_hp = {:?}, code_rate_lp = {:?}, guard_interval = {:?}, transmission_mo
                         gst_mpegts::DVBExtendedDescriptorType::TargetRegion => {
                             let target_region = descriptor.parse_target_region().unwrap();
                             if PRINT_NIT {
-                                println!("        Extension:  TargetRegion:  country_code = {}, additional_country_codes = {:?}",
+                                debug!("        Extension:  TargetRegion:  country_code = {}, additional_country_codes = {:?}",
                                          &target_region.get_country_code(),
                                          &target_region.get_additional_country_codes());
                             }
@@ -301,7 +363,7 @@ code_rate_hp = {:?}, code_rate_lp = {:?}, guard_interval = {:?}, transmission_mo
                         gst_mpegts::DVBExtendedDescriptorType::T2DeliverySystem => {
                             let t2_delivery_system = descriptor.parse_dvb_t2_delivery_system().unwrap();
                             if PRINT_NIT {
-                                println!("        Extension:  T2DeliverySystem:  plp_id = {}, t2_system_id = {}, siso_miso = {}, bandwidth = {}, \
+                                debug!("        Extension:  T2DeliverySystem:  plp_id = {}, t2_system_id = {}, siso_miso = {}, bandwidth = {}, \
                              guard_interval = {:?}, transmission_mode = {:?}, other_frequency = {}, tfs = {}, cells = {}",
                                          &t2_delivery_system.get_plp_id(),
                                          &t2_delivery_system.get_t2_system_id(),
@@ -315,14 +377,14 @@ code_rate_hp = {:?}, code_rate_lp = {:?}, guard_interval = {:?}, transmission_mo
                                 );
                             }
                         },
-                        x => println!("************  Got an extended descriptor type {:?}", x),
+                        x => debug!("Got an extended descriptor type {:?}", x),
                     }
                 },
                 gst_mpegts::DVBDescriptorType::PrivateDataSpecifier => {
                     // It seems that this is the original_network_id being presented at the NIT section level.
                     let private_data = descriptor.parse_dvb_private_data_specifier().unwrap();
                     if PRINT_NIT {
-                        println!("    Private Data Specifier: {}, {:?}", &private_data.0, &private_data.1);
+                        debug!("    Private Data Specifier: {}, {:?}", &private_data.0, &private_data.1);
                     }
                 },
                 x => {
@@ -336,16 +398,16 @@ code_rate_hp = {:?}, code_rate_lp = {:?}, guard_interval = {:?}, transmission_mo
                         assert_eq!(tag, gst_mpegts::MiscDescriptorType::DtgLogicalChannel);
                         let dtg_logical_channel_descriptor = descriptor.parse_logical_channel().unwrap();
                         if PRINT_NIT {
-                            println!("    LogicalChannelDescriptor:");
+                            debug!("    LogicalChannelDescriptor:");
                         }
                         for item in dtg_logical_channel_descriptor.get_channels().iter() {
                             if ! add_logical_channel_number_for_service_id(item.get_service_id(), item.get_logical_channel_number(), Some(&to_cw)) {
                                 if PRINT_NIT {
-                                    println!("Failed to add logical_channel_number {} to service_id {}.", &item.get_logical_channel_number(), &item.get_service_id());
+                                    debug!("Failed to add logical_channel_number {} to service_id {}.", &item.get_logical_channel_number(), &item.get_service_id());
                                 }
                             }
                             if PRINT_NIT {
-                                println!("        LogicalChannel:  service_id = {}, visible_service = {}, logical_channel_number = {}",
+                                debug!("        LogicalChannel:  service_id = {}, visible_service = {}, logical_channel_number = {}",
                                     &item.get_service_id(),
                                     &item.get_visible_service(),
                                     &item.get_logical_channel_number(),
@@ -353,12 +415,12 @@ code_rate_hp = {:?}, code_rate_lp = {:?}, guard_interval = {:?}, transmission_mo
                             }
                         }
                     } else {
-                        println!("************  Got an unknown stream type {:?}", x)
+                        debug!("Got an unknown stream type {:?}", x)
                     }
                 },
             }
             if PRINT_DATA {
-                println!("            {:?}", &descriptor.get_data());
+                debug!("            {:?}", &descriptor.get_data());
             }
         }
     }
@@ -367,52 +429,52 @@ code_rate_hp = {:?}, code_rate_lp = {:?}, guard_interval = {:?}, transmission_mo
 fn build_pat(pat: &Vec<gst_mpegts::PatProgram>, to_cw: &glib::Sender<Message>) {
     // Only seem to get a couple of these on BBC News on Freeview from Crystal Palace.
     if PRINT_PAT {
-        println!("========  PAT Section.");
+        debug!("========  PAT Section.");
     }
     for p in pat.iter() {
         if PRINT_PAT {
-            println!("    PatProgram:  {}, {}", &p.get_program_number(), &p.get_network_or_program_map_pid());
+            debug!("    PatProgram:  {}, {}", &p.get_program_number(), &p.get_network_or_program_map_pid());
         }
     }
 }
 
 fn build_pmt(pmt: &gst_mpegts::PMT, to_cw: &glib::Sender<Message>) {
     if PRINT_PMT {
-        println!("========  PMT section:  program_number = {}", &pmt.get_program_number());
+        debug!("========  PMT section:  program_number = {}", &pmt.get_program_number());
         for descriptor in pmt.get_descriptors().iter() {
             match descriptor.get_tag() {
-                x => println!("************  Got an unhandled descriptor type {:?}", x)
+                x => debug!("Got an unhandled descriptor type {:?}", x)
             }
             if PRINT_DATA {
-                println!("        {:?}", descriptor.get_data());
+                debug!("        {:?}", descriptor.get_data());
             }
         }
         for stream in pmt.get_streams().iter() {
-            println!("    PMTStream:  stream_type = {:?}, pid = {}", stream.get_stream_type(), stream.get_pid());
+            debug!("    PMTStream:  stream_type = {:?}, pid = {}", stream.get_stream_type(), stream.get_pid());
             for descriptor in stream.get_descriptors().iter() {
                 match descriptor.get_tag() {
                     gst_mpegts::DVBDescriptorType::Extension => {
                         match descriptor.get_tag_extension().unwrap() {
                             gst_mpegts::DVBExtendedDescriptorType::SupplementaryAudio => {
                                 let supplementary_audio_extended_descriptor = descriptor.parse_supplementary_audio().unwrap();
-                                println!("    Extension:  SupplementaryAudio: mix_type = {}, editorial_classification = {}, iso_639_language_code = {:?}, private_data = {:?}",
+                                debug!("    Extension:  SupplementaryAudio: mix_type = {}, editorial_classification = {}, iso_639_language_code = {:?}, private_data = {:?}",
                                          &supplementary_audio_extended_descriptor.get_mix_type(),
                                          &supplementary_audio_extended_descriptor.get_editorial_classification(),
                                          &supplementary_audio_extended_descriptor.get_iso_639_language_code(),
                                          &supplementary_audio_extended_descriptor.get_private_data(),
                                 );
                             },
-                            x => println!("************  Got an unhandled extension descriptor type {:?}", x)
+                            x => debug!("Got an unhandled extension descriptor type {:?}", x)
                         }
                     },
                     gst_mpegts::DVBDescriptorType::StreamIdentifier => {
                         let identifier = descriptor.parse_dvb_stream_identifier();
-                        println!("        StreamIdentifier:  {:?}", &identifier);
+                        debug!("        StreamIdentifier:  {:?}", &identifier);
                     },
                     gst_mpegts::DVBDescriptorType::Subtitling => {
                         let subtitling_descriptor = descriptor.parse_dvb_subtitling().unwrap();
                         for item in subtitling_descriptor.get_items().iter() {
-                            println!("        Subtitling:  iso_639_language_code = {}, subtitling_type = {}, composition_page_id = {}, ancilliary_page_id = {}",
+                            debug!("        Subtitling:  iso_639_language_code = {}, subtitling_type = {}, composition_page_id = {}, ancilliary_page_id = {}",
                                  &item.get_iso_639_language_code(),
                                  &item.get_subtitling_type(),
                                  &item.get_composition_page_id(),
@@ -421,29 +483,29 @@ fn build_pmt(pmt: &gst_mpegts::PMT, to_cw: &glib::Sender<Message>) {
                     },
                     gst_mpegts::DVBDescriptorType::DataBroadcastId => {
                         let (data_broadcast_id, id_selector_bytes) = descriptor.parse_dvb_data_broadcast_id().unwrap();
-                        println!("    DataBroadcastId:  {}, {:?}", &data_broadcast_id, id_selector_bytes);
+                        debug!("    DataBroadcastId:  {}, {:?}", &data_broadcast_id, id_selector_bytes);
                     },
                     gst_mpegts::DVBDescriptorType::ApplicationSignalling => {
                         let data = descriptor.parse_application_signalling().unwrap();
-                        println!("    ApplicationSignalling:  {:?}", &data);
+                        debug!("    ApplicationSignalling:  {:?}", &data);
                     },
                     x => {
                         let tag: gst_mpegts::DescriptorType = unsafe { from_glib(x.to_glib() as i32) };
                         match tag {
                             gst_mpegts::DescriptorType::Iso639Language => {
                                 let language_descriptor = descriptor.parse_iso_639_language().unwrap();
-                                println!("    Iso639Language: {:?}", &language_descriptor.get_items());
+                                debug!("    Iso639Language: {:?}", &language_descriptor.get_items());
                             },
                             gst_mpegts::DescriptorType::DsmccCarouselIdentifier => {
                                 // TODO Sort this out.
-                                println!("    DsmccCarouselIdentifier: {:?}", &descriptor);
+                                debug!("    DsmccCarouselIdentifier: {:?}", &descriptor);
                             },
-                            y => println!("************  Got an unhandled PMTStream descriptor type {:?}, {:?}", &x, &y)
+                            y => debug!("Got an unhandled PMTStream descriptor type {:?}, {:?}", &x, &y)
                         }
                     }
                 }
                 if PRINT_DATA {
-                    println!("            {:?}", &descriptor.get_data());
+                    debug!("            {:?}", &descriptor.get_data());
                 }
             }
         }
@@ -452,11 +514,11 @@ fn build_pmt(pmt: &gst_mpegts::PMT, to_cw: &glib::Sender<Message>) {
 
 fn build_sdt(sdt: &gst_mpegts::SDT, to_cw: &glib::Sender<Message>) {
     if PRINT_SDT {
-        println!("========  SDT section:  original_network_id = {:?}, transport_stream_id ={:?}", &sdt.get_original_network_id(), &sdt.get_transport_stream_id());
+        debug!("========  SDT section:  original_network_id = {:?}, transport_stream_id ={:?}", &sdt.get_original_network_id(), &sdt.get_transport_stream_id());
     }
     for service in sdt.get_services().iter() {
         if PRINT_SDT {
-            println!("    SDTService:  service_id = {}, \
+            debug!("    SDTService:  service_id = {}, \
 eit_schedule_flag = {}, \
 eit_present_following = {}, \
 running_status = {:?}, \
@@ -468,12 +530,14 @@ free_ca_mode = {}",
                      service.get_free_ca_mode(),
             );
         }
+        // The free CA mode flag is set if any of the streams of the service is scrambled.
+        set_encrypted_for_service_id(service.get_service_id(), service.get_free_ca_mode(), Some(&to_cw));
         for descriptor in service.get_descriptors().iter() {
             match descriptor.get_tag() {
                 gst_mpegts::DVBDescriptorType::DefaultAuthority => {
                     let authority = descriptor.parse_dvb_default_authority();
                     if PRINT_SDT {
-                        println!("        DefaultAuthority:  {:?}", authority);
+                        debug!("        DefaultAuthority:  {:?}", authority);
                     }
                 },
                 // TODO Process Extension descriptors.
@@ -483,45 +547,54 @@ free_ca_mode = {}",
                             let (old_original_network_id, old_transport_stream_id, old_service_id) =
                                 descriptor.parse_dvb_service_relocated().unwrap();
                                 if PRINT_SDT {
-                                    println!("        Extension:  ServiceRelocated:  old_original_network_id = {}, old_transport_stream_id = {}, old_service_id = {}",
+                                    debug!("        Extension:  ServiceRelocated:  old_original_network_id = {}, old_transport_stream_id = {}, old_service_id = {}",
                                              old_original_network_id,
                                              old_transport_stream_id,
                                              old_service_id);
                                 }
                         },
-                        x => println!("************  Got an extended descriptor type {:?}", x),
+                        x => debug!("Got an extended descriptor type {:?}", x),
                     }
                 },
                 gst_mpegts::DVBDescriptorType::FtaContentManagement => {
                     match descriptor.parse_fta_content_management() {
                         Some(d) =>
                             if PRINT_SDT {
-                                println!("        FtaContentManagement:  user_defined = {}, do_not_scramble {}, control_remote_access_over_internet {:?}, do_not_apply_revocation = {}",
+                                debug!("        FtaContentManagement:  user_defined = {}, do_not_scramble {}, control_remote_access_over_internet {:?}, do_not_apply_revocation = {}",
                                          d.get_user_defined(),
                                          d.get_do_not_scramble(),
                                          d.get_control_remote_access_over_internet(),
                                          d.get_do_not_apply_revocation(),
                                 )
                             },
-                        None => println!("        FtaContentManagement:  None"),
+                        None => debug!("        FtaContentManagement:  None"),
                     };
                 },
                     gst_mpegts::DVBDescriptorType::PrivateDataSpecifier => {
                         let private_data = descriptor.parse_dvb_private_data_specifier().unwrap();
                         if PRINT_SDT {
-                            println!("        PrivateDataSpecifier: {}, {:?}", &private_data.0, &private_data.1);
+                            debug!("        PrivateDataSpecifier: {}, {:?}", &private_data.0, &private_data.1);
                         }
                     },
                 gst_mpegts::DVBDescriptorType::Service => {
-                    let (service_type, service_name, some_string_possibly_empty) = descriptor.parse_dvb_service().unwrap();
-                    if PRINT_SDT {
-                        println!("        Service:  {:?}, '{}', '{}'", service_type, service_name, some_string_possibly_empty);
+                    // Decoded here rather than by GStreamer so that the names are in the
+                    // character table the broadcaster chose, as those of the EIT are.
+                    match parse_service_descriptor(&descriptor.get_data()) {
+                        Some(service_descriptor) => {
+                            if PRINT_SDT {
+                                debug!("        Service:  {:#04x}, '{}', '{}'", service_descriptor.service_type, service_descriptor.service_name, service_descriptor.provider_name);
+                            }
+                            set_provider_for_service_id(service.get_service_id(), &service_descriptor.provider_name, Some(&to_cw));
+                            set_service_type_for_service_id(service.get_service_id(), service_descriptor.service_type);
+                            set_radio_for_service_id(service.get_service_id(), service_descriptor.is_radio(), Some(&to_cw));
+                        },
+                        None => debug!("        Service:  a malformed service descriptor {:?}", descriptor.get_data()),
                     }
                 },
-                x => println!("************  Got an unhandled descriptor of type {:?}", x)
+                x => debug!("Got an unhandled descriptor of type {:?}", x)
             }
             if PRINT_DATA {
-                println!("            {:?}", descriptor.get_data());
+                debug!("            {:?}", descriptor.get_data());
             }
         }
     }
@@ -529,31 +602,31 @@ free_ca_mode = {}",
 
 fn build_tdt(tdt: &gst::DateTime, to_cw: &glib::Sender<Message>) {
     if PRINT_TDT {
-        println!("========  TDT section:  utc_time = {}", &tdt);
+        debug!("========  TDT section:  utc_time = {}", &tdt);
     }
 }
 
 fn build_tsdt(tsdt: &Vec<gst_mpegts::Descriptor>, to_cw: &glib::Sender<Message>) {
     // Do not seem to get any of these on BBC News on Freeview from Crystal Palace.
     if PRINT_TSDT {
-        println!("========  TSDT section:  {:?}", &tsdt);
+        debug!("========  TSDT section:  {:?}", &tsdt);
     }
 }
 
 fn build_tot(tot: &gst_mpegts::TOT, to_cw: &glib::Sender<Message>) {
     if PRINT_TOT {
-        println!("========  TOT section:  utc_time = {}", &tot.get_utc_time());
+        debug!("========  TOT section:  utc_time = {}", &tot.get_utc_time());
     }
     for descriptor in tot.get_descriptors().iter() {
         match descriptor.get_tag() {
             gst_mpegts::DVBDescriptorType::LocalTimeOffset => {
                 let local_time_offset = descriptor.parse_local_time_offset().unwrap();
                 if PRINT_TOT {
-                    println!("    LocalTimeOffset:");
+                    debug!("    LocalTimeOffset:");
                 }
                 for item in local_time_offset.get_items().iter() {
                     if PRINT_TOT {
-                        println!("        LocalTimeOffsetItem:  \
+                        debug!("        LocalTimeOffsetItem:  \
                         country_code = {}, \
                         country_region_id = {}, \
                         local_time_offset_polarity = {}, \
@@ -570,11 +643,193 @@ fn build_tot(tot: &gst_mpegts::TOT, to_cw: &glib::Sender<Message>) {
                     }
                 }
             },
-            x => println!("************  Got an unhandled descriptor of type {:?}", x)
+            x => debug!("Got an unhandled descriptor of type {:?}", x)
         }
         if PRINT_DATA {
-            println!("        {:?}", &descriptor.get_data());
+            debug!("        {:?}", &descriptor.get_data());
+        }
+    }
+}
+
+/// Keep the now and next programmes and the programmes to come up to date from an EIT
+/// section, telling the control window of a change of the now and next programmes.
+fn update_epg(eit: &EitSection, to_cw: &glib::Sender<Message>) {
+    let now = Utc::now().naive_utc();
+    let changed = NOW_NEXT.write().unwrap().update(eit, now);
+    if let Some(service_id) = changed {
+        to_cw.send(Message::NowNextChanged{service_id}).unwrap();
+    }
+    let changed = PROGRAMMES.write().unwrap().update(eit, now);
+    if let Some(service_id) = changed {
+        IS_CACHE_STALE.store(true, Ordering::SeqCst);
+        to_cw.send(Message::ProgrammesChanged{service_id}).unwrap();
+    }
+}
+
+/// Put the programmes of the EPG cache in the programme store, unless the preferences
+/// say there is to be no cache, telling the control window of the services whose
+/// programmes changed and then that the cache has been read. The cache is decoded before
+/// the programme store is locked, so the programme guide can be looked at meanwhile.
+fn load_cache(to_cw: &glib::Sender<Message>) {
+    let days = preferences::get_epg_cache_days();
+    if days > 0 {
+        let path = cache_file_path();
+        let now = Utc::now().naive_utc();
+        match read_cache(&path, now, chrono::Duration::days(i64::from(days))) {
+            Ok(cached) => {
+                info!("Read the programmes of {} services from the EPG cache {}.", cached.len(), path.display());
+                let changed = PROGRAMMES.write().unwrap().set_cached(&cached, now);
+                for service_id in changed {
+                    to_cw.send(Message::NowNextChanged{service_id}).unwrap();
+                    to_cw.send(Message::ProgrammesChanged{service_id}).unwrap();
+                }
+            },
+            Err(e) => warn!("Could not use the EPG cache: {}", e),
+        }
+    }
+    IS_LOADING_CACHE.store(false, Ordering::SeqCst);
+    to_cw.send(Message::ProgrammeCacheLoaded).unwrap();
+}
+
+/// Is the EPG cache still being read?
+pub fn is_loading_cache() -> bool {  // Used in epg_window.rs
+    IS_LOADING_CACHE.load(Ordering::SeqCst)
+}
+
+/// Write the programmes of the EIT to the EPG cache if they have changed since it was
+/// last written, unless the preferences say there is to be no cache or it is still being
+/// read.
+pub fn save_cache() {  // Used in main.rs
+    if preferences::get_epg_cache_days() == 0 || is_loading_cache() || !IS_CACHE_STALE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let programmes = PROGRAMMES.read().unwrap().eit_programmes();
+    match write_cache(&cache_file_path(), &programmes, Utc::now().naive_utc()) {
+        Ok(()) => debug!("Wrote the programmes of {} services to the EPG cache.", programmes.len()),
+        Err(e) => {
+            warn!("{}", e);
+            IS_CACHE_STALE.store(true, Ordering::SeqCst);
+        },
+    }
+}
+
+/// Put the programmes of the XMLTV listings file of the preferences in the programme
+/// store if the file, the channels, or the channels the XMLTV channels are configured to
+/// be, have changed since `source`, telling the control window of the services whose
+/// programmes changed. If the file cannot be read the programmes from it stay as they were.
+fn check_listings(source: &mut ListingsSource, to_cw: &glib::Sender<Message>) {
+    let path = preferences::get_xmltv_file().unwrap_or_default();
+    let current = ListingsSource {
+        modified: if path.is_empty() { None } else { fs::metadata(&path).and_then(|metadata| metadata.modified()).ok() },
+        path,
+        channels: get_channel_names_and_service_ids().unwrap_or_default(),
+        configured: preferences::get_xmltv_channels().unwrap_or_default(),
+    };
+    if current == *source {
+        return;
+    }
+    *source = current;
+    let by_service = if source.path.is_empty() {
+        HashMap::new()
+    } else {
+        match read_xmltv(Path::new(&source.path)) {
+            Ok(listings) => {
+                let mapping = map_channels(&listings.channels, &source.channels, &source.configured);
+                for channel in listings.channels.iter().filter(|channel| !mapping.contains_key(&channel.id)) {
+                    debug!("XMLTV channel {} is not one of the channels.", channel.id);
+                }
+                info!("Read {} programmes of {} channels from {}, {} of the channels matched.", listings.programmes.len(), listings.channels.len(), source.path, mapping.len());
+                programmes_by_service(&listings, &mapping)
+            },
+            Err(e) => {
+                warn!("Could not read the XMLTV listings file {}: {}", source.path, e);
+                return;
+            },
         }
+    };
+    let changed = PROGRAMMES.write().unwrap().set_listings(&by_service, Utc::now().naive_utc());
+    for service_id in changed {
+        to_cw.send(Message::NowNextChanged{service_id}).unwrap();
+        to_cw.send(Message::ProgrammesChanged{service_id}).unwrap();
+    }
+}
+
+/// Show the programmes in the languages of the preferences if they are not `languages`,
+/// those the programmes are shown in, telling the control window of the services whose
+/// programmes changed.
+fn check_languages(languages: &mut Vec<String>, to_cw: &glib::Sender<Message>) {
+    let current = preferences::get_epg_languages().unwrap_or_default();
+    if current == *languages {
+        return;
+    }
+    *languages = current;
+    for service_id in NOW_NEXT.write().unwrap().set_languages(languages) {
+        to_cw.send(Message::NowNextChanged{service_id}).unwrap();
+    }
+    for service_id in PROGRAMMES.write().unwrap().set_languages(languages) {
+        to_cw.send(Message::NowNextChanged{service_id}).unwrap();
+        to_cw.send(Message::ProgrammesChanged{service_id}).unwrap();
+    }
+}
+
+/// Look at the programme store with `f`, which must not take long, the EPG manager
+/// waiting on it to update the store.
+pub fn with_programmes<R>(f: impl FnOnce(&ProgrammeStore) -> R) -> R {  // Used in epg_window.rs, epg_search_window.rs and control_window.rs
+    f(&PROGRAMMES.read().unwrap())
+}
+
+/// The now and next programmes on the channel `channel_name`, if any are known. Those of
+/// the EIT present/following table are taken over those of the programme store, so over
+/// the listings.
+pub fn now_next(channel_name: &str) -> Option<NowNext> {  // Used in frontend_window.rs and control_window_button.rs
+    let (_, service_id) = get_channel_names_and_service_ids()?.into_iter().find(|(name, _)| name == channel_name)?;
+    match NOW_NEXT.read().unwrap().now_next(service_id) {
+        Some(now_next) if now_next.now.is_some() => Some(now_next.clone()),
+        _ => Some(PROGRAMMES.read().unwrap().now_next_at(service_id, Utc::now().naive_utc()))
+            .filter(|now_next| now_next.now.is_some() || now_next.next.is_some()),
+    }
+}
+
+/// The slippage of `programme` on the service `service_id` now, as the present/following
+/// table shows it, None if it is on time or the table says nothing of it.
+pub fn slippage(service_id: u16, programme: &Programme) -> Option<Slippage> {  // Used in frontend_window.rs and epg_window.rs
+    NOW_NEXT.read().unwrap().slippage(service_id, programme, Utc::now().naive_utc())
+}
+
+/// How much of the programme guide the preferences say to keep.
+fn retention() -> Retention {
+    Retention { days_ahead: preferences::get_epg_days_ahead(), max_programmes: preferences::get_epg_max_programmes() as usize }
+}
+
+/// The service ids and starts, UTC, of the programmes of the programme store that are to
+/// be recorded or reminded of, so must be kept.
+fn wanted_programmes() -> HashSet<(u16, NaiveDateTime)> {
+    let mut channel_names = HashMap::<u16, Vec<String>>::new();
+    for (name, service_id) in get_channel_names_and_service_ids().unwrap_or_default() {
+        channel_names.entry(service_id).or_default().push(name);
+    }
+    let programmes = PROGRAMMES.read().unwrap();
+    programmes.iter()
+        .filter(|(service_id, programme)| {
+            channel_names.get(service_id).map_or(false, |names| names.iter().any(|name| programme_details::is_wanted(name, programme)))
+        })
+        .filter_map(|(service_id, programme)| programme.start.map(|start| (service_id, start)))
+        .collect()
+}
+
+/// Forget the programmes that have ended, and those the preferences say not to keep,
+/// other than those to be recorded or reminded of, telling the control window of the
+/// services whose programmes changed.
+fn retain_programmes(to_cw: &glib::Sender<Message>) {
+    let kept = wanted_programmes();
+    let mut programmes = PROGRAMMES.write().unwrap();
+    let changed = programmes.retain(Utc::now().naive_utc(), retention(), kept);
+    if !changed.is_empty() {
+        IS_CACHE_STALE.store(true, Ordering::SeqCst);
+        debug!("The programme store has {}.", programmes.stats());
+    }
+    for service_id in changed {
+        to_cw.send(Message::ProgrammesChanged{service_id}).unwrap();
     }
 }
 
@@ -584,10 +839,33 @@ fn build_tot(tot: &gst_mpegts::TOT, to_cw: &glib::Sender<Message>) {
 ///
 /// This is a separate process executed by a thread other than the Glib event loop thread
 /// so as to avoid that thread having to do too much work.
-pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiver<gst_mpegts::Section>) {
+pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiver<Input>) {
+    let mut listings_source = ListingsSource::default();
+    let mut languages = Vec::new();
+    let mut listings_checked: Option<Instant> = None;
+    let mut expired = Instant::now();
+    let mut saved = Instant::now();
+    // So that the cache is read as the preferences say.
+    retain_programmes(&to_cw);
+    check_languages(&mut languages, &to_cw);
+    load_cache(&to_cw);
     loop {
-        match from_gstreamer.recv() {
-            Ok(mut section) => {
+        if listings_checked.map_or(true, |checked| checked.elapsed() >= LISTINGS_CHECK_INTERVAL) {
+            check_languages(&mut languages, &to_cw);
+            check_listings(&mut listings_source, &to_cw);
+            listings_checked = Some(Instant::now());
+        }
+        if expired.elapsed() >= PROGRAMME_EXPIRY_INTERVAL {
+            retain_programmes(&to_cw);
+            expired = Instant::now();
+        }
+        if saved.elapsed() >= CACHE_SAVE_INTERVAL {
+            save_cache();
+            saved = Instant::now();
+        }
+        match from_gstreamer.recv_timeout(LISTINGS_CHECK_INTERVAL) {
+            Ok(Input::Eit(eit)) => update_epg(&eit, &to_cw),
+            Ok(Input::Section(mut section)) => {
                 match section.get_section_type() {
                     gst_mpegts::SectionType::AtscCvct => {},
                     gst_mpegts::SectionType::AtscEit => {},
@@ -599,7 +877,7 @@ pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiv
                         if let Some(bat) = section.get_bat() {
                             build_bat(&bat, &to_cw);
                         } else {
-                            println!("******** Got a BAT that wasn't a BAT {:?}", &section);
+                            warn!("Got a BAT that wasn't a BAT {:?}", &section);
                         }
                     },
                     gst_mpegts::SectionType::Cat => {
@@ -609,16 +887,16 @@ pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiv
                         if let Some(eit) = section.get_eit() {
                             build_eit(&eit, &to_cw);
                         } else {
-                            println!("********  Got an EIT that wasn't an EIT {:?}", &section);
-                            println!("********      Section type: {:?}", &section.get_section_type());
-                            println!("********      EIT: {:?}", &section.get_eit());
+                            warn!("Got an EIT that wasn't an EIT {:?}", &section);
+                            debug!("Section type: {:?}", &section.get_section_type());
+                            debug!("EIT: {:?}", &section.get_eit());
                         }
                     },
                     gst_mpegts::SectionType::Nit => {
                         if let Some(nit) = section.get_nit() {
                             build_nit(&nit, &to_cw);
                         } else {
-                            println!("******** Got a NIT that wasn't a NIT {:?}", &section);
+                            warn!("Got a NIT that wasn't a NIT {:?}", &section);
                         }
                     },
                     gst_mpegts::SectionType::Pat => {
@@ -628,21 +906,21 @@ pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiv
                         if let Some(pmt) = section.get_pmt() {
                             build_pmt(&pmt, &to_cw);
                         } else {
-                            println!("******** Got a PMT that wasn't a PMT {:?}", &section);
+                            warn!("Got a PMT that wasn't a PMT {:?}", &section);
                         }
                     },
                     gst_mpegts::SectionType::Sdt => {
                         if let Some(sdt) = section.get_sdt() {
                             build_sdt(&sdt, &to_cw);
                         } else {
-                            println!("******** Got a SDT that wasn't a SDT {:?}", &section);
+                            warn!("Got a SDT that wasn't a SDT {:?}", &section);
                         }
                     },
                     gst_mpegts::SectionType::Tdt => {
                         if let Some(tdt) = section.get_tdt() {
                             build_tdt(&tdt, &to_cw);
                         }else {
-                            println!("******** Got a TDT that wasn't a TDT {:?}", &section);
+                            warn!("Got a TDT that wasn't a TDT {:?}", &section);
                         }
                     },
                     gst_mpegts::SectionType::Tsdt => {
@@ -652,19 +930,20 @@ pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiv
                         if let Some(tot) = section.get_tot() {
                             build_tot(&tot, &to_cw);
                         } else {
-                            println!("******** Got a TOT that wasn't a TOT {:?}", &section);
+                            warn!("Got a TOT that wasn't a TOT {:?}", &section);
                         }
                     },
                     gst_mpegts::SectionType::Unknown => {
-                        println!("======== Got an Unknown section.");
+                        debug!("======== Got an Unknown section.");
                     },
                     x => {
-                        println!("******** got an unknown section type, number {:?}", x);
+                        debug!("got an unknown section type, number {:?}", x);
                     },
                 }
             },
+            Err(RecvTimeoutError::Timeout) => {},
             Err(e) => {
-                println!("********  failed to receive a section {:?}", e);
+                warn!("Failed to receive a section {:?}", e);
             }
         }
     }
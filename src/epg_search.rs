@@ -0,0 +1,200 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Searching the programmes for those whose title or descriptions have a word or phrase
+//! in them, on all channels or some, of any genre or category or one, and on in the time
+//! given.
+//!
+//! Text is matched as channel names are, ignoring case and accents, anywhere in a title
+//! or description or, if asked, only as whole words, so that "art" finds "Arts Night"
+//! but not "Dartmoor".
+
+use std::collections::{BTreeSet, HashSet};
+
+use chrono::NaiveDateTime;
+
+use crate::epg::Programme;
+use crate::genres::{category_of, Category};
+use crate::name_matching::fold;
+
+/// What to look for.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Query {
+    pub text: String,  // Anything if empty.
+    pub is_whole_words: bool,
+    pub service_ids: Option<HashSet<u16>>,  // All services if None.
+    pub genre: Option<String>,  // Any genre if None.
+    pub category: Option<Category>,  // Any category if None.
+    pub from: Option<NaiveDateTime>,  // UTC, the programmes ending after.
+    pub to: Option<NaiveDateTime>,  // UTC, the programmes starting before.
+}
+
+/// Whether the folded text `folded` has the folded `text` in it, only as whole words if
+/// `is_whole_words`.
+fn has(folded: &str, text: &str, is_whole_words: bool) -> bool {
+    if !is_whole_words {
+        return folded.contains(text);
+    }
+    folded.match_indices(text).any(|(i, _)| {
+        let before = folded[..i].chars().next_back();
+        let after = folded[i + text.len()..].chars().next();
+        !before.map_or(false, char::is_alphanumeric) && !after.map_or(false, char::is_alphanumeric)
+    })
+}
+
+/// Whether `programme` is on at any time from `from` to `to`, UTC, either being no limit.
+fn is_on_between(programme: &Programme, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> bool {
+    let is_after = from.map_or(true, |from| programme.end().map_or(false, |end| end > from));
+    let is_before = to.map_or(true, |to| programme.start.map_or(false, |start| start < to));
+    is_after && is_before
+}
+
+/// The programmes of `programmes`, service id and programme pairs, that `query` finds,
+/// in order of start and then of service id. The text is looked for in the titles and
+/// descriptions in all the languages of a programme.
+pub fn search<'a>(programmes: impl IntoIterator<Item = (u16, &'a Programme)>, query: &Query) -> Vec<(u16, Programme)> {
+    let text = fold(query.text.trim());
+    let genre = query.genre.as_deref().map(fold);
+    let mut found = programmes.into_iter()
+        .filter(|(service_id, _)| query.service_ids.as_ref().map_or(true, |service_ids| service_ids.contains(service_id)))
+        .filter(|(_, programme)| is_on_between(programme, query.from, query.to))
+        .filter(|(_, programme)| genre.as_ref().map_or(true, |genre| programme.genres.iter().any(|g| fold(g).contains(genre.as_str()))))
+        .filter(|(_, programme)| query.category.map_or(true, |category| category_of(&programme.genres) == Some(category)))
+        .filter(|(_, programme)| {
+            let texts = programme.texts.iter().flat_map(|t| vec![&t.title, &t.description, &t.extended_description]);
+            text.is_empty() || vec![&programme.title, &programme.description, &programme.extended_description].into_iter()
+                .chain(texts)
+                .any(|t| has(&fold(t), &text, query.is_whole_words))
+        })
+        .map(|(service_id, programme)| (service_id, programme.clone()))
+        .collect::<Vec<_>>();
+    found.sort_by_key(|(service_id, programme)| (programme.start, *service_id));
+    found
+}
+
+/// The genres of `programmes`, each once, in alphabetical order, to choose from.
+pub fn genres<'a>(programmes: impl IntoIterator<Item = &'a Programme>) -> Vec<String> {
+    programmes.into_iter()
+        .flat_map(|programme| programme.genres.iter().cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Duration, NaiveDate};
+
+    use crate::eit::EventText;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 11, 2).and_hms(hour, minute, 0)
+    }
+
+    fn listed(title: &str, description: &str, genre: &str, from: NaiveDateTime) -> Programme {
+        Programme {
+            start: Some(from),
+            duration_seconds: 3600,
+            title: title.to_string(),
+            description: description.to_string(),
+            genres: vec![genre.to_string()],
+            ..Programme::default()
+        }
+    }
+
+    fn programmes() -> Vec<(u16, Programme)> {
+        vec![
+            (4164, listed("World Snooker Championship", "The final frame.", "Sports", at(21, 0))),
+            (4287, listed("Arts Night", "Dartmoor in paint.", "Arts", at(20, 0))),
+            (4164, listed("Newsnight", "With a snooker report.", "News", at(22, 0))),
+            (8384, listed("Pot Black", "Snooker from the Crucible.", "Sports", at(20, 0) + Duration::days(8))),
+            (4287, listed("Élite Snookér", "", "Sports", at(19, 0))),
+        ]
+    }
+
+    fn titles(found: &[(u16, Programme)]) -> Vec<&str> {
+        found.iter().map(|(_, programme)| programme.title.as_str()).collect()
+    }
+
+    #[test]
+    fn titles_and_descriptions_are_searched_ignoring_case_and_accents() {
+        let programmes = programmes();
+        let query = Query { text: "SNOOKER".to_string(), ..Query::default() };
+        assert_eq!(
+            titles(&search(programmes.iter().map(|(s, p)| (*s, p)), &query)),
+            vec!["Élite Snookér", "World Snooker Championship", "Newsnight", "Pot Black"],
+        );
+    }
+
+    #[test]
+    fn whole_words_are_only_matched_at_word_boundaries() {
+        let programmes = programmes();
+        let query = |is_whole_words| Query { text: "art".to_string(), is_whole_words, ..Query::default() };
+        assert_eq!(titles(&search(programmes.iter().map(|(s, p)| (*s, p)), &query(false))), vec!["Arts Night"]);
+        assert!(search(programmes.iter().map(|(s, p)| (*s, p)), &query(true)).is_empty());
+        let query = Query { text: "arts".to_string(), is_whole_words: true, ..Query::default() };
+        assert_eq!(titles(&search(programmes.iter().map(|(s, p)| (*s, p)), &query)), vec!["Arts Night"]);
+    }
+
+    #[test]
+    fn services_genres_and_times_can_be_limited() {
+        let programmes = programmes();
+        let query = Query {
+            text: "snooker".to_string(),
+            service_ids: Some(vec![4164, 8384].into_iter().collect()),
+            genre: Some("sport".to_string()),
+            from: Some(at(20, 0)),
+            to: Some(at(20, 0) + Duration::days(7)),
+            ..Query::default()
+        };
+        assert_eq!(titles(&search(programmes.iter().map(|(s, p)| (*s, p)), &query)), vec!["World Snooker Championship"]);
+        let query = Query { genre: Some("Sports".to_string()), ..Query::default() };
+        assert_eq!(search(programmes.iter().map(|(s, p)| (*s, p)), &query).len(), 3);
+    }
+
+    #[test]
+    fn categories_are_recognised_from_the_genres() {
+        let programmes = programmes();
+        let query = Query { category: Some(Category::Sports), ..Query::default() };
+        assert_eq!(titles(&search(programmes.iter().map(|(s, p)| (*s, p)), &query)), vec!["Élite Snookér", "World Snooker Championship", "Pot Black"]);
+        let query = Query { category: Some(Category::ArtsCulture), ..Query::default() };
+        assert_eq!(titles(&search(programmes.iter().map(|(s, p)| (*s, p)), &query)), vec!["Arts Night"]);
+    }
+
+    #[test]
+    fn titles_in_every_language_are_searched() {
+        let mut programme = listed("Newyddion", "Y newyddion diweddaraf.", "News", at(21, 0));
+        programme.texts = vec![
+            EventText { language: "cym".to_string(), title: "Newyddion".to_string(), ..EventText::default() },
+            EventText { language: "eng".to_string(), title: "Welsh News".to_string(), ..EventText::default() },
+        ];
+        let query = Query { text: "welsh news".to_string(), ..Query::default() };
+        assert_eq!(titles(&search(vec![(4164, &programme)], &query)), vec!["Newyddion"]);
+    }
+
+    #[test]
+    fn genres_are_listed_once_each() {
+        let programmes = programmes();
+        assert_eq!(genres(programmes.iter().map(|(_, p)| p)), vec!["Arts".to_string(), "News".to_string(), "Sports".to_string()]);
+    }
+}
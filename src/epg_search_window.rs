@@ -0,0 +1,317 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Searching the programme guide: everything on in the next week, on all the channels
+//! or those of a group, and of any genre or one, or of a category of genres, with a title
+//! or description with what is typed in it. The programmes found are listed in order of start, or of any column
+//! clicked on, activating one presenting its details and what can be done with it.
+//!
+//! A week of programmes of a hundred channels is a lot to look through, so the search
+//! is done on a thread of its own, the results of a search overtaken by another being
+//! thrown away.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::thread;
+
+use chrono::{Duration, Local, TimeZone, Utc};
+
+use gdk;
+
+use glib;
+
+use gtk;
+use gtk::prelude::*;
+
+use me_tv::epg::{Programme, ProgrammeStore};
+use me_tv::epg_search::{genres, search, Query};
+use me_tv::genres::Category;
+
+use crate::channels_data::get_channel_names_and_service_ids;
+use crate::control_window::ControlWindow;
+use crate::epg_manager;
+use crate::programme_details;
+
+/// How far ahead the programmes are searched.
+const SEARCH_DAYS: i64 = 7;
+
+/// What the ids of the categories in the genre selector start with, the index of the
+/// category following.
+const CATEGORY_ID_PREFIX: &str = "category:";
+
+/// The columns of the list of programmes found: when the programme starts, as shown and
+/// as seconds since the epoch for sorting by, the channel, the title, the genres, and
+/// where the programme is in the results.
+const WHEN: u32 = 0;
+const START: u32 = 1;
+const CHANNEL: u32 = 2;
+const TITLE: u32 = 3;
+const GENRES: u32 = 4;
+const INDEX: u32 = 5;
+
+/// The groups of channels a search can be limited to, by id of the group selector.
+const GROUPS: [(&str, &str); 4] = [("all", "All channels"), ("favourites", "Favourites"), ("television", "Television"), ("radio", "Radio")];
+
+// Windows can only be used in the GTK event loop thread, so there is no need of a Mutex.
+thread_local! {
+    static EPG_SEARCH_WINDOW: RefCell<Option<Rc<EpgSearchWindow>>> = RefCell::new(None);
+}
+
+/// The window and the widgets of it that searching needs.
+struct EpgSearchWindow {
+    window: gtk::Window,
+    control_window: Rc<ControlWindow>,
+    entry: gtk::SearchEntry,
+    whole_words_button: gtk::CheckButton,
+    group_selector: gtk::ComboBoxText,
+    genre_selector: gtk::ComboBoxText,
+    store: gtk::ListStore,
+    status_label: gtk::Label,
+    results: RefCell<Vec<(String, Programme)>>,  // The channel and the programme.
+    search_number: Cell<u32>,
+}
+
+impl EpgSearchWindow {
+    /// The names and service ids of the channels of the group selected, in the order the
+    /// channel selectors list them.
+    fn channels(&self) -> Vec<(String, u16)> {
+        let service_ids = get_channel_names_and_service_ids().unwrap_or_default().into_iter().collect::<HashMap<_, _>>();
+        let group = self.group_selector.get_active_id().map(|id| id.to_string()).unwrap_or_default();
+        let model = &self.control_window.channels_data_sorter;
+        let mut channels = Vec::new();
+        if let Some(iter) = model.get_iter_first() {
+            loop {
+                let is_favourite = model.get_value(&iter, 2).get_some::<bool>().unwrap_or(false);
+                let is_radio = model.get_value(&iter, 5).get_some::<bool>().unwrap_or(false);
+                let is_in_group = match group.as_str() {
+                    "favourites" => is_favourite,
+                    "television" => !is_radio,
+                    "radio" => is_radio,
+                    _ => true,
+                };
+                if let Some(name) = model.get_value(&iter, 1).get::<String>().unwrap().filter(|_| is_in_group) {
+                    if let Some(service_id) = service_ids.get(&name) {
+                        channels.push((name, *service_id));
+                    }
+                }
+                if !model.iter_next(&iter) { break; }
+            }
+        }
+        channels
+    }
+
+    /// Search for what has been typed, with the genre and channels selected, listing
+    /// what is found when the search finishes unless another search has been started.
+    fn search(search_window: &Rc<EpgSearchWindow>) {
+        let search_number = search_window.search_number.get() + 1;
+        search_window.search_number.set(search_number);
+        let text = search_window.entry.get_text().to_string();
+        let id = search_window.genre_selector.get_active_id().map(|id| id.to_string()).filter(|id| !id.is_empty());
+        let category = id.as_deref()
+            .and_then(|id| id.strip_prefix(CATEGORY_ID_PREFIX))
+            .and_then(|index| index.parse::<usize>().ok())
+            .and_then(|index| Category::ALL.get(index).copied());
+        let genre = id.filter(|id| !id.starts_with(CATEGORY_ID_PREFIX));
+        if text.trim().is_empty() && genre.is_none() && category.is_none() {
+            search_window.show(Vec::new());
+            search_window.status_label.set_text("Type what to look for, or choose a genre.");
+            return;
+        }
+        let channels = search_window.channels();
+        let now = Utc::now().naive_utc();
+        let query = Query {
+            text,
+            is_whole_words: search_window.whole_words_button.get_active(),
+            service_ids: Some(channels.iter().map(|(_, service_id)| *service_id).collect()),
+            genre,
+            category,
+            from: Some(now),
+            to: Some(now + Duration::days(SEARCH_DAYS)),
+        };
+        search_window.status_label.set_text("Searching…");
+        let (to_window, from_search) = glib::MainContext::channel::<Vec<(u16, Programme)>>(glib::PRIORITY_DEFAULT);
+        from_search.attach(None, {
+            let s_w = search_window.clone();
+            move |found| {
+                if s_w.search_number.get() == search_number {
+                    let names = channels.iter().map(|(name, service_id)| (*service_id, name.clone())).collect::<HashMap<_, _>>();
+                    s_w.show(found.into_iter().filter_map(|(service_id, programme)| Some((names.get(&service_id)?.clone(), programme))).collect());
+                }
+                Continue(false)
+            }
+        });
+        thread::spawn(move || {
+            // Searching a copy so as not to hold up the EPG manager updating the store.
+            let store = epg_manager::with_programmes(ProgrammeStore::clone);
+            let _ = to_window.send(search(store.iter(), &query));
+        });
+    }
+
+    /// List the programmes `results`, channel and programme pairs.
+    fn show(&self, results: Vec<(String, Programme)>) {
+        self.store.clear();
+        for (index, (channel, programme)) in results.iter().enumerate() {
+            let start = programme.start.map(|start| Local.from_utc_datetime(&start));
+            self.store.insert_with_values(None, &[WHEN, START, CHANNEL, TITLE, GENRES, INDEX], &[
+                &start.map(|start| start.format("%a %e %b %H:%M").to_string()).unwrap_or_default(),
+                &start.map_or(0, |start| start.timestamp()),
+                channel,
+                &programme.title,
+                &programme.genres.join(", "),
+                &(index as u32),
+            ]);
+        }
+        self.status_label.set_text(&match results.len() {
+            0 => format!("Nothing found in the next {} days.", SEARCH_DAYS),
+            1 => format!("1 programme found in the next {} days.", SEARCH_DAYS),
+            count => format!("{} programmes found in the next {} days.", count, SEARCH_DAYS),
+        });
+        self.results.replace(results);
+    }
+
+    /// Present the details of the programme found at `path` of the list.
+    fn present_details(&self, path: &gtk::TreePath) {
+        let index = match self.store.get_iter(path) {
+            Some(iter) => self.store.get_value(&iter, INDEX as i32).get_some::<u32>().unwrap_or(0) as usize,
+            None => return,
+        };
+        if let Some((channel, programme)) = self.results.borrow().get(index) {
+            programme_details::present(Some(&self.window), &self.control_window, channel, programme);
+        }
+    }
+}
+
+/// Add a column to the list of programmes found, showing `column` of the store and
+/// sorted by `sort_column` of it.
+fn add_column(view: &gtk::TreeView, title: &str, column: u32, sort_column: u32) {
+    let renderer = gtk::CellRendererText::new();
+    let view_column = gtk::TreeViewColumn::new();
+    view_column.set_title(title);
+    view_column.set_resizable(true);
+    view_column.pack_start(&renderer, true);
+    view_column.add_attribute(&renderer, "text", column as i32);
+    view_column.set_sort_column_id(sort_column as i32);
+    view.append_column(&view_column);
+}
+
+fn create(control_window: &Rc<ControlWindow>) -> Rc<EpgSearchWindow> {
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    window.set_title("Me TV Programme Search");
+    window.set_transient_for(Some(&control_window.window));
+    window.set_destroy_with_parent(true);
+    window.set_default_size(700, 500);
+    let main_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    main_box.set_border_width(10);
+    let search_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    let entry = gtk::SearchEntry::new();
+    entry.set_hexpand(true);
+    search_box.pack_start(&entry, true, true, 0);
+    let whole_words_button = gtk::CheckButton::with_mnemonic("_Whole words");
+    search_box.pack_start(&whole_words_button, false, false, 0);
+    let group_selector = gtk::ComboBoxText::new();
+    for (id, text) in GROUPS.iter() {
+        group_selector.append(Some(id), text);
+    }
+    group_selector.set_active_id(Some("all"));
+    search_box.pack_start(&group_selector, false, false, 0);
+    let genre_selector = gtk::ComboBoxText::new();
+    genre_selector.append(Some(""), "Any genre");
+    for (index, category) in Category::ALL.iter().enumerate() {
+        genre_selector.append(Some(&format!("{}{}", CATEGORY_ID_PREFIX, index)), &format!("All {}", category));
+    }
+    for genre in epg_manager::with_programmes(|store| genres(store.iter().map(|(_, programme)| programme))) {
+        genre_selector.append(Some(&genre), &genre);
+    }
+    genre_selector.set_active_id(Some(""));
+    search_box.pack_start(&genre_selector, false, false, 0);
+    main_box.pack_start(&search_box, false, false, 0);
+    let store = gtk::ListStore::new(&[
+        String::static_type(), i64::static_type(), String::static_type(), String::static_type(), String::static_type(), u32::static_type(),
+    ]);
+    store.set_sort_column_id(gtk::SortColumn::Index(START), gtk::SortType::Ascending);
+    let view = gtk::TreeView::with_model(&store);
+    add_column(&view, "When", WHEN, START);
+    add_column(&view, "Channel", CHANNEL, CHANNEL);
+    add_column(&view, "Title", TITLE, TITLE);
+    add_column(&view, "Genre", GENRES, GENRES);
+    let scrolled_window = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    scrolled_window.add(&view);
+    scrolled_window.set_vexpand(true);
+    main_box.pack_start(&scrolled_window, true, true, 0);
+    let status_label = gtk::Label::new(Some("Type what to look for, or choose a genre."));
+    status_label.set_xalign(0.0);
+    main_box.pack_start(&status_label, false, false, 0);
+    window.add(&main_box);
+    let search_window = Rc::new(EpgSearchWindow {
+        window: window.clone(),
+        control_window: control_window.clone(),
+        entry,
+        whole_words_button,
+        group_selector,
+        genre_selector,
+        store,
+        status_label,
+        results: RefCell::new(Vec::new()),
+        search_number: Cell::new(0),
+    });
+    search_window.entry.connect_search_changed({
+        let s_w = search_window.clone();
+        move |_| EpgSearchWindow::search(&s_w)
+    });
+    search_window.whole_words_button.connect_toggled({
+        let s_w = search_window.clone();
+        move |_| EpgSearchWindow::search(&s_w)
+    });
+    for selector in &[&search_window.group_selector, &search_window.genre_selector] {
+        selector.connect_changed({
+            let s_w = search_window.clone();
+            move |_| EpgSearchWindow::search(&s_w)
+        });
+    }
+    view.connect_row_activated({
+        let s_w = search_window.clone();
+        move |_, path, _| s_w.present_details(path)
+    });
+    window.add_events(gdk::EventMask::KEY_PRESS_MASK);
+    window.connect_key_press_event(move |w, key| {
+        if key.get_keyval() == gdk::keys::constants::Escape {
+            w.close();
+            return Inhibit(true);
+        }
+        Inhibit(false)
+    });
+    window.show_all();
+    search_window
+}
+
+/// Display the programme search in a non-modal way, or bring it to the front if it is
+/// already being displayed.
+pub fn present(control_window: &Rc<ControlWindow>) {  // Used in control_window.rs and epg_window.rs
+    if let Some(search_window) = EPG_SEARCH_WINDOW.with(|s_w| s_w.borrow().clone()) {
+        search_window.window.present();
+        return;
+    }
+    let search_window = create(control_window);
+    search_window.entry.grab_focus();
+    search_window.window.connect_destroy(|_| EPG_SEARCH_WINDOW.with(|s_w| *s_w.borrow_mut() = None));
+    EPG_SEARCH_WINDOW.with(|s_w| *s_w.borrow_mut() = Some(search_window));
+}
@@ -0,0 +1,509 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The programme guide window: the channels down the side, as the channel selectors list
+//! them, the programmes of each across, from the programme store, and a line at now. The
+//! arrow keys move from programme to programme, Home goes back to now, and clicking on a
+//! programme, or pressing Return, presents the details of it. Ctrl+F searches it. Whilst
+//! the programmes of the EPG cache are being read a progress bar along the bottom says so.
+//! Programmes are coloured by the category of their genres, movies one colour, sport
+//! another, and so on. Programmes stay where the schedule has them, those that started
+//! late or early, or are running late, as the present/following table shows, being
+//! marked as such.
+//!
+//! Only the part of the grid on show has widgets, made afresh whenever it is scrolled or
+//! the programmes change. The scrollbars are of the whole grid, but the widgets are put
+//! where they are on show, a week of programmes being far wider than a window can be.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use chrono::Utc;
+
+use gdk;
+
+use glib;
+
+use gtk;
+use gtk::prelude::*;
+
+use log::warn;
+
+use pango;
+
+use me_tv::epg::ProgrammeStore;
+use me_tv::epg_grid::{Block, Direction, Grid, PIXELS_PER_MINUTE, ROW_HEIGHT};
+use me_tv::genres::{category_of, Category};
+
+use crate::channels_data::get_channel_names_and_service_ids;
+use crate::control_window::ControlWindow;
+use crate::epg_manager;
+use crate::epg_search_window;
+use crate::programme_details;
+
+/// How wide the column of channel names is, in pixels.
+const CHANNELS_WIDTH: i32 = 160;
+
+/// How tall the time line is, in pixels.
+const TIME_LINE_HEIGHT: i32 = 24;
+
+/// How far a step of the scroll wheel or a scroll arrow goes across, in minutes.
+const STEP_MINUTES: f64 = 15.0;
+
+/// How often the line at now is moved along, in seconds.
+const NOW_INTERVAL: u32 = 60;
+
+/// How often the progress bar pulses whilst the EPG cache is being read, in milliseconds.
+const LOADING_PULSE_INTERVAL: u32 = 100;
+
+/// The colours of the programmes of each category, in the order of `Category::ALL`, light
+/// enough for the dark text on them to be read.
+const CATEGORY_COLOURS: [&str; 10] = [
+    "#f4c7c3",  // Movie/Drama
+    "#c6dafc",  // News/Current affairs
+    "#fce8b2",  // Show/Game show
+    "#b7e1cd",  // Sports
+    "#f9cbe5",  // Children's/Youth
+    "#d9c6f0",  // Music/Ballet/Dance
+    "#e6d2b5",  // Arts/Culture
+    "#d0d7de",  // Social/Political issues/Economics
+    "#c4ece6",  // Education/Science/Factual
+    "#e2f0b6",  // Leisure hobbies
+];
+
+/// The style class of the programmes of `category`.
+fn category_class(category: Category) -> String {
+    format!("me-tv-category-{}", Category::ALL.iter().position(|c| *c == category).unwrap_or_default())
+}
+
+/// The CSS colouring the programmes by category, a selected programme keeping the colour
+/// of its selection.
+fn category_css() -> String {
+    Category::ALL.iter().zip(CATEGORY_COLOURS.iter())
+        .map(|(category, colour)| format!(
+            "button.{}:not(.suggested-action) {{ background-image: none; background-color: {}; color: #202020; }}\n",
+            category_class(*category), colour,
+        ))
+        .collect()
+}
+
+// Widgets can only be used in the GTK event loop thread, so there is no need of a Mutex.
+thread_local! {
+    static EPG_WINDOW: RefCell<Option<Rc<EpgWindow>>> = RefCell::new(None);
+}
+
+/// The window, the grid of it, and the widgets the part of the grid on show is put in.
+struct EpgWindow {
+    window: gtk::Window,
+    control_window: Rc<ControlWindow>,
+    grid: Grid,
+    channels: gtk::Layout,
+    time_line: gtk::Layout,
+    programmes: gtk::Layout,
+    hadjustment: gtk::Adjustment,  // Of the whole grid, in pixels.
+    vadjustment: gtk::Adjustment,  // Of the whole grid, in pixels.
+    loading_bar: gtk::ProgressBar,
+    css_provider: gtk::CssProvider,
+    selected: RefCell<Option<Block>>,
+}
+
+impl EpgWindow {
+    /// Make the widgets of the part of the grid on show: the programmes, a channel with
+    /// no programmes at all saying so, the names of the channels, the times, and the line
+    /// at now if now is on show.
+    fn refresh(epg_window: &Rc<EpgWindow>) {
+        for layout in &[&epg_window.channels, &epg_window.time_line, &epg_window.programmes] {
+            for child in layout.get_children() {
+                layout.remove(&child);
+            }
+        }
+        let grid = &epg_window.grid;
+        let (x, width) = (epg_window.hadjustment.get_value(), epg_window.hadjustment.get_page_size());
+        let (y, height) = (epg_window.vadjustment.get_value(), epg_window.vadjustment.get_page_size());
+        let (x_offset, y_offset) = (x as i32, y as i32);
+        let selected = epg_window.selected.borrow().clone();
+        epg_manager::with_programmes(|store| {
+            for block in grid.blocks(store, x, y, width, height) {
+                let button = EpgWindow::programme_button(epg_window, &block, selected.as_ref() == Some(&block));
+                epg_window.programmes.put(&button, grid.x_of(block.start) - x_offset, block.row as i32 * ROW_HEIGHT - y_offset);
+            }
+            for row in grid.rows_in(y, height) {
+                let (name, service_id) = &grid.channels()[row];
+                let label = gtk::Label::new(Some(name));
+                label.set_xalign(0.0);
+                label.set_ellipsize(pango::EllipsizeMode::End);
+                label.set_size_request(CHANNELS_WIDTH, ROW_HEIGHT);
+                epg_window.channels.put(&label, 0, row as i32 * ROW_HEIGHT - y_offset);
+                if store.programmes(*service_id).is_empty() {
+                    let label = gtk::Label::new(Some("No programme information"));
+                    label.set_sensitive(false);
+                    label.set_size_request(width as i32, ROW_HEIGHT);
+                    epg_window.programmes.put(&label, 0, row as i32 * ROW_HEIGHT - y_offset);
+                }
+            }
+        });
+        for (mark_x, text) in grid.marks(x, width) {
+            let label = gtk::Label::new(Some(&text));
+            label.set_size_request(-1, TIME_LINE_HEIGHT);
+            epg_window.time_line.put(&label, mark_x - x_offset, 0);
+        }
+        let now_x = grid.x_of(Utc::now().naive_utc());
+        if f64::from(now_x) >= x && f64::from(now_x) <= x + width {
+            let now_line = gtk::Separator::new(gtk::Orientation::Vertical);
+            now_line.set_size_request(2, height as i32);
+            epg_window.programmes.put(&now_line, now_x - x_offset, 0);
+        }
+        for layout in &[&epg_window.channels, &epg_window.time_line, &epg_window.programmes] {
+            layout.show_all();
+        }
+    }
+
+    /// A button as wide as the programme `block` is long, that selects it and presents
+    /// the details of it when clicked. A programme scheduled to be recorded is marked so,
+    /// as is one not on at the time scheduled.
+    fn programme_button(epg_window: &Rc<EpgWindow>, block: &Block, is_selected: bool) -> gtk::Button {
+        let (channel, service_id) = &epg_window.grid.channels()[block.row];
+        let is_scheduled = programme_details::is_scheduled(channel, &block.programme);
+        let slippage = epg_manager::slippage(*service_id, &block.programme);
+        let mut text = match block.programme.rating_badge() {
+            Some(badge) => format!("{} {}", badge, block.programme.title),
+            None => block.programme.title.clone(),
+        };
+        if slippage.is_some() {
+            text = format!("⏱ {}", text);
+        }
+        if is_scheduled {
+            text = format!("● {}", text);
+        }
+        let label = gtk::Label::new(Some(&text));
+        label.set_xalign(0.0);
+        label.set_ellipsize(pango::EllipsizeMode::End);
+        let button = gtk::Button::new();
+        button.add(&label);
+        button.set_can_focus(false);
+        button.set_size_request((epg_window.grid.x_of(block.end) - epg_window.grid.x_of(block.start)).max(1), ROW_HEIGHT);
+        let mut tooltip = format!("{}\n{}", block.programme.times_text().unwrap_or_default(), block.programme.title);
+        if !block.programme.genres.is_empty() {
+            tooltip.push_str(&format!("\n{}", block.programme.genres.join(", ")));
+        }
+        if let Some(ratings) = block.programme.ratings_text() {
+            tooltip.push_str(&format!("\n{}", ratings));
+        }
+        if let Some(slippage) = slippage {
+            tooltip.push_str(&format!("\nAs broadcast, {}.", slippage));
+        }
+        if block.is_trimmed {
+            tooltip.push_str("\nThe broadcaster has this starting before the programme before it ends.");
+        }
+        if is_scheduled {
+            tooltip.push_str("\nScheduled to be recorded.");
+        }
+        button.set_tooltip_text(Some(&tooltip));
+        let style_context = button.get_style_context();
+        style_context.add_provider(&epg_window.css_provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
+        if let Some(category) = category_of(&block.programme.genres) {
+            style_context.add_class(&category_class(category));
+        }
+        if is_selected {
+            style_context.add_class("suggested-action");
+        }
+        button.connect_clicked({
+            let e_w = epg_window.clone();
+            let block = block.clone();
+            move |_| {
+                EpgWindow::select(&e_w, block.clone());
+                e_w.present_details(&block);
+            }
+        });
+        button
+    }
+
+    /// Select the programme `block`, scrolling the grid to it if it is not on show.
+    fn select(epg_window: &Rc<EpgWindow>, block: Block) {
+        let (start_x, end_x) = (f64::from(epg_window.grid.x_of(block.start)), f64::from(epg_window.grid.x_of(block.end)));
+        let (x, width) = (epg_window.hadjustment.get_value(), epg_window.hadjustment.get_page_size());
+        if end_x <= x || start_x >= x + width {
+            epg_window.hadjustment.set_value(start_x);
+        }
+        let (top, bottom) = (f64::from(block.row as i32 * ROW_HEIGHT), f64::from((block.row as i32 + 1) * ROW_HEIGHT));
+        let (y, height) = (epg_window.vadjustment.get_value(), epg_window.vadjustment.get_page_size());
+        if top < y {
+            epg_window.vadjustment.set_value(top);
+        } else if bottom > y + height {
+            epg_window.vadjustment.set_value(bottom - height);
+        }
+        *epg_window.selected.borrow_mut() = Some(block);
+        EpgWindow::refresh(epg_window);
+    }
+
+    /// Select the programme next to the selected one in the direction `direction`, or
+    /// the one on now if none is selected.
+    fn move_selection(epg_window: &Rc<EpgWindow>, direction: Direction) {
+        let selected = epg_window.selected.borrow().clone();
+        let next = epg_manager::with_programmes(|store| match selected {
+            Some(block) => epg_window.grid.next_block(store, &block, direction),
+            None => epg_window.block_at_now(store, 0),
+        });
+        if let Some(block) = next {
+            EpgWindow::select(epg_window, block);
+        }
+    }
+
+    /// Scroll the grid back to now, selecting the programme on now on the channel of the
+    /// selected programme.
+    fn select_now(epg_window: &Rc<EpgWindow>) {
+        let row = epg_window.selected.borrow().as_ref().map_or(0, |block| block.row);
+        let now_x = f64::from(epg_window.grid.x_of(Utc::now().naive_utc()));
+        epg_window.hadjustment.set_value(now_x - epg_window.hadjustment.get_page_size() / 4.0);
+        if let Some(block) = epg_manager::with_programmes(|store| epg_window.block_at_now(store, row)) {
+            EpgWindow::select(epg_window, block);
+        }
+    }
+
+    /// The programme on now on the channel of the row `row`, or else on the first one
+    /// after it with any programmes.
+    fn block_at_now(&self, store: &ProgrammeStore, row: usize) -> Option<Block> {
+        let now = Utc::now().naive_utc();
+        (row..self.grid.channels().len()).find_map(|row| self.grid.block_at(store, row, now))
+    }
+
+    /// Present the details of the programme `block`.
+    fn present_details(&self, block: &Block) {
+        programme_details::present(Some(&self.window), &self.control_window, &self.grid.channels()[block.row].0, &block.programme);
+    }
+}
+
+/// The names and service ids of the channels in the order the channel selectors list
+/// them, leaving out those they do not.
+fn guide_channels(control_window: &ControlWindow) -> Vec<(String, u16)> {
+    let service_ids = get_channel_names_and_service_ids().unwrap_or_default().into_iter().collect::<HashMap<_, _>>();
+    let model = &control_window.channels_data_sorter;
+    let mut channels = Vec::new();
+    if let Some(iter) = model.get_iter_first() {
+        loop {
+            if let Some(name) = model.get_value(&iter, 1).get::<String>().unwrap() {
+                if let Some(service_id) = service_ids.get(&name) {
+                    channels.push((name, *service_id));
+                }
+            }
+            if !model.iter_next(&iter) { break; }
+        }
+    }
+    channels
+}
+
+fn create(control_window: &Rc<ControlWindow>) -> Rc<EpgWindow> {
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    window.set_title("Me TV Programme Guide");
+    window.set_transient_for(Some(&control_window.window));
+    window.set_destroy_with_parent(true);
+    window.set_default_size(1000, 600);
+    let grid = Grid::new(guide_channels(control_window), Utc::now().naive_utc());
+    let step = STEP_MINUTES * PIXELS_PER_MINUTE;
+    let hadjustment = gtk::Adjustment::new(0.0, 0.0, f64::from(grid.width()), step, 4.0 * step, 0.0);
+    let vadjustment = gtk::Adjustment::new(0.0, 0.0, f64::from(grid.height()), f64::from(ROW_HEIGHT), 4.0 * f64::from(ROW_HEIGHT), 0.0);
+    let time_line = gtk::Layout::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    time_line.set_size_request(-1, TIME_LINE_HEIGHT);
+    let channels = gtk::Layout::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    channels.set_size_request(CHANNELS_WIDTH, -1);
+    let programmes = gtk::Layout::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    programmes.set_hexpand(true);
+    programmes.set_vexpand(true);
+    programmes.add_events(gdk::EventMask::SCROLL_MASK | gdk::EventMask::SMOOTH_SCROLL_MASK);
+    let layout_grid = gtk::Grid::new();
+    layout_grid.set_border_width(10);
+    layout_grid.attach(&time_line, 1, 0, 1, 1);
+    layout_grid.attach(&channels, 0, 1, 1, 1);
+    layout_grid.attach(&programmes, 1, 1, 1, 1);
+    layout_grid.attach(&gtk::Scrollbar::new(gtk::Orientation::Vertical, Some(&vadjustment)), 2, 1, 1, 1);
+    layout_grid.attach(&gtk::Scrollbar::new(gtk::Orientation::Horizontal, Some(&hadjustment)), 1, 2, 1, 1);
+    let loading_bar = gtk::ProgressBar::new();
+    loading_bar.set_text(Some("Reading the programmes saved when Me TV last stopped…"));
+    loading_bar.set_show_text(true);
+    layout_grid.attach(&loading_bar, 0, 3, 3, 1);
+    window.add(&layout_grid);
+    let css_provider = gtk::CssProvider::new();
+    css_provider.load_from_data(category_css().as_bytes()).unwrap_or_else(|e| warn!("Could not colour the programme guide: {}", e));
+    let selected = epg_manager::with_programmes(|store| {
+        let now = Utc::now().naive_utc();
+        (0..grid.channels().len()).find_map(|row| grid.block_at(store, row, now))
+    });
+    let epg_window = Rc::new(EpgWindow {
+        window: window.clone(),
+        control_window: control_window.clone(),
+        grid,
+        channels,
+        time_line,
+        programmes,
+        hadjustment,
+        vadjustment,
+        loading_bar,
+        css_provider,
+        selected: RefCell::new(selected),
+    });
+    for adjustment in &[&epg_window.hadjustment, &epg_window.vadjustment] {
+        adjustment.connect_value_changed({
+            let e_w = epg_window.clone();
+            move |_| EpgWindow::refresh(&e_w)
+        });
+    }
+    epg_window.programmes.connect_size_allocate({
+        let e_w = epg_window.clone();
+        move |_, allocation| {
+            if allocation.width != e_w.hadjustment.get_page_size() as i32 || allocation.height != e_w.vadjustment.get_page_size() as i32 {
+                e_w.hadjustment.set_page_size(f64::from(allocation.width));
+                e_w.vadjustment.set_page_size(f64::from(allocation.height));
+                // Not whilst the widgets are being allocated space.
+                glib::idle_add_local({
+                    let e_w = e_w.clone();
+                    move || {
+                        EpgWindow::refresh(&e_w);
+                        Continue(false)
+                    }
+                });
+            }
+        }
+    });
+    epg_window.programmes.connect_scroll_event({
+        let e_w = epg_window.clone();
+        move |_, event| {
+            let (dx, dy) = match event.get_direction() {
+                gdk::ScrollDirection::Up => (0.0, -1.0),
+                gdk::ScrollDirection::Down => (0.0, 1.0),
+                gdk::ScrollDirection::Left => (-1.0, 0.0),
+                gdk::ScrollDirection::Right => (1.0, 0.0),
+                _ => event.get_delta(),
+            };
+            // The wheel scrolls across with Shift, as in other GTK applications.
+            let (dx, dy) = if event.get_state().contains(gdk::ModifierType::SHIFT_MASK) { (dy, dx) } else { (dx, dy) };
+            e_w.hadjustment.set_value(e_w.hadjustment.get_value() + dx * e_w.hadjustment.get_step_increment());
+            e_w.vadjustment.set_value(e_w.vadjustment.get_value() + dy * e_w.vadjustment.get_step_increment());
+            Inhibit(true)
+        }
+    });
+    window.add_events(gdk::EventMask::KEY_PRESS_MASK);
+    window.connect_key_press_event({
+        let e_w = epg_window.clone();
+        move |_, key| {
+            let keyval = key.get_keyval();
+            let direction = if keyval == gdk::keys::constants::Left {
+                Some(Direction::Left)
+            } else if keyval == gdk::keys::constants::Right {
+                Some(Direction::Right)
+            } else if keyval == gdk::keys::constants::Up {
+                Some(Direction::Up)
+            } else if keyval == gdk::keys::constants::Down {
+                Some(Direction::Down)
+            } else {
+                None
+            };
+            if let Some(direction) = direction {
+                EpgWindow::move_selection(&e_w, direction);
+                return Inhibit(true);
+            }
+            if keyval == gdk::keys::constants::Home {
+                EpgWindow::select_now(&e_w);
+                return Inhibit(true);
+            }
+            if keyval == gdk::keys::constants::Return || keyval == gdk::keys::constants::KP_Enter || keyval == gdk::keys::constants::space {
+                let selected = e_w.selected.borrow().clone();
+                if let Some(block) = selected {
+                    e_w.present_details(&block);
+                }
+                return Inhibit(true);
+            }
+            if keyval == gdk::keys::constants::f && key.get_state().contains(gdk::ModifierType::CONTROL_MASK) {
+                epg_search_window::present(&e_w.control_window);
+                return Inhibit(true);
+            }
+            if keyval == gdk::keys::constants::Escape {
+                e_w.window.close();
+                return Inhibit(true);
+            }
+            Inhibit(false)
+        }
+    });
+    glib::timeout_add_seconds_local(NOW_INTERVAL, {
+        let e_w = Rc::downgrade(&epg_window);
+        move || match e_w.upgrade() {
+            Some(e_w) if e_w.window.get_visible() => {
+                EpgWindow::refresh(&e_w);
+                Continue(true)
+            },
+            _ => Continue(false),
+        }
+    });
+    window.show_all();
+    if epg_manager::is_loading_cache() {
+        glib::timeout_add_local(LOADING_PULSE_INTERVAL, {
+            let e_w = Rc::downgrade(&epg_window);
+            move || match e_w.upgrade() {
+                Some(e_w) if epg_manager::is_loading_cache() => {
+                    e_w.loading_bar.pulse();
+                    Continue(true)
+                },
+                _ => Continue(false),
+            }
+        });
+    } else {
+        epg_window.loading_bar.hide();
+    }
+    epg_window
+}
+
+/// Display the programme guide in a non-modal way, or bring it to the front if it is
+/// already being displayed.
+pub fn present(control_window: &Rc<ControlWindow>) {  // Used in control_window.rs
+    if let Some(epg_window) = EPG_WINDOW.with(|e_w| e_w.borrow().clone()) {
+        epg_window.window.present();
+        return;
+    }
+    let epg_window = create(control_window);
+    epg_window.window.connect_destroy(|_| EPG_WINDOW.with(|e_w| *e_w.borrow_mut() = None));
+    EPG_WINDOW.with(|e_w| *e_w.borrow_mut() = Some(epg_window));
+}
+
+/// Show the programmes afresh if the programme guide is being displayed and has the
+/// service `service_id`, the programmes of which have changed.
+pub fn programmes_changed(service_id: u16) {  // Used in control_window.rs
+    if let Some(epg_window) = EPG_WINDOW.with(|e_w| e_w.borrow().clone()) {
+        if epg_window.grid.channels().iter().any(|(_, id)| *id == service_id) {
+            EpgWindow::refresh(&epg_window);
+        }
+    }
+}
+
+/// Show the programmes afresh if the programme guide is being displayed, the schedule
+/// of recordings having changed.
+pub fn schedule_changed() {  // Used in programme_details.rs
+    if let Some(epg_window) = EPG_WINDOW.with(|e_w| e_w.borrow().clone()) {
+        EpgWindow::refresh(&epg_window);
+    }
+}
+
+/// Stop showing the progress of reading the EPG cache if the programme guide is being
+/// displayed, the cache having been read.
+pub fn cache_loaded() {  // Used in control_window.rs
+    if let Some(epg_window) = EPG_WINDOW.with(|e_w| e_w.borrow().clone()) {
+        epg_window.loading_bar.hide();
+    }
+}
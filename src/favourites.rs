@@ -0,0 +1,247 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Favourite channels: the few of the many channels in the channels file that are
+//! actually watched, kept in the preferences so that they can be listed first, or
+//! alone, in the channel selectors.
+
+use std::cmp::Ordering;
+
+use log::warn;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::preferences;
+
+/// A favourite channel. The service id is kept as well as the name so that a channel
+/// renamed by a rescan is still a favourite.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FavouriteChannel {
+    pub name: String,
+    pub service_id: Option<u16>,  // None until the channel has been seen in the channels file.
+}
+
+/// Which channels the channel selectors list, and in which order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ChannelView {
+    All,
+    FavouritesFirst,
+    FavouritesOnly,
+}
+
+impl Default for ChannelView {
+    fn default() -> ChannelView { ChannelView::All }
+}
+
+impl ChannelView {
+    /// The target of the menu item for the view.
+    pub fn action_target(&self) -> &'static str {
+        match self {
+            ChannelView::All => "all",
+            ChannelView::FavouritesFirst => "favourites-first",
+            ChannelView::FavouritesOnly => "favourites-only",
+        }
+    }
+
+    /// The view of a menu item target.
+    pub fn from_action_target(target: &str) -> Option<ChannelView> {
+        [ChannelView::All, ChannelView::FavouritesFirst, ChannelView::FavouritesOnly].iter()
+            .find(|view| view.action_target() == target)
+            .cloned()
+    }
+
+    /// The order of two channels, given whether each is a favourite, before they are
+    /// ordered by channel number.
+    pub fn ordering(&self, a_is_favourite: bool, b_is_favourite: bool) -> Ordering {
+        match self {
+            ChannelView::All => Ordering::Equal,
+            _ => b_is_favourite.cmp(&a_is_favourite),
+        }
+    }
+
+    /// Whether a channel is listed. The only favourites view lists all channels if
+    /// there are no favourites, rather than none.
+    pub fn is_listed(&self, is_favourite: bool, has_favourites: bool) -> bool {
+        match self {
+            ChannelView::FavouritesOnly => is_favourite || !has_favourites,
+            _ => true,
+        }
+    }
+}
+
+/// The favourites matched against the (name, service id) pairs of the channels in the
+/// channels file, and the names of the favourites that are no longer there. A favourite
+/// whose name has gone, but whose service id is that of a channel that is not already a
+/// favourite, has been renamed and is kept with the new name.
+fn reconcile(favourites: &[FavouriteChannel], channels: &[(String, u16)]) -> (Vec<FavouriteChannel>, Vec<String>) {
+    let mut kept = Vec::<FavouriteChannel>::new();
+    let mut dropped = Vec::new();
+    for favourite in favourites {
+        let channel = channels.iter().find(|(name, _)| *name == favourite.name)
+            .or_else(|| channels.iter().find(|(name, service_id)| {
+                Some(*service_id) == favourite.service_id && !favourites.iter().any(|f| f.name == *name)
+            }));
+        match channel {
+            Some((name, service_id)) if !kept.iter().any(|f| f.name == *name) => kept.push(FavouriteChannel{name: name.clone(), service_id: Some(*service_id)}),
+            Some(_) => {},
+            None => dropped.push(favourite.name.clone()),
+        }
+    }
+    (kept, dropped)
+}
+
+/// The favourites with the channel `name` made a favourite if it was not one, and not
+/// one if it was.
+fn toggled(favourites: &[FavouriteChannel], name: &str) -> Vec<FavouriteChannel> {
+    if favourites.iter().any(|favourite| favourite.name == name) {
+        favourites.iter().filter(|favourite| favourite.name != name).cloned().collect()
+    } else {
+        let mut favourites = favourites.to_vec();
+        favourites.push(FavouriteChannel{name: name.to_string(), service_id: None});
+        favourites
+    }
+}
+
+/// The favourites with the channel `name` called `new_name`, unless that is already a
+/// favourite.
+fn renamed(favourites: &[FavouriteChannel], name: &str, new_name: &str) -> Vec<FavouriteChannel> {
+    let already = favourites.iter().any(|favourite| favourite.name == new_name);
+    favourites.iter()
+        .filter(|favourite| !(already && favourite.name == name))
+        .map(|favourite| if favourite.name == name { FavouriteChannel{name: new_name.to_string(), ..favourite.clone()} } else { favourite.clone() })
+        .collect()
+}
+
+/// The favourite channels.
+pub fn favourites() -> Vec<FavouriteChannel> {
+    preferences::get_favourite_channels().unwrap_or_default()
+}
+
+/// Whether the channel `name` is a favourite.
+pub fn is_favourite(name: &str) -> bool {
+    favourites().iter().any(|favourite| favourite.name == name)
+}
+
+/// Make the channel `name` a favourite if it is not one, and not one if it is.
+pub fn toggle(name: &str) {
+    preferences::set_favourite_channels(toggled(&favourites(), name), true);
+}
+
+/// Have a favourite that has been renamed `new_name` stay a favourite.
+pub fn rename(name: &str, new_name: &str) {  // Used in channels_data.rs.
+    let favourites = favourites();
+    let renamed = renamed(&favourites, name, new_name);
+    if renamed != favourites {
+        preferences::set_favourite_channels(renamed, true);
+    }
+}
+
+/// Bring the favourites up to date with the (name, service id) pairs of the channels
+/// now in the channels file, forgetting those that have gone.
+pub fn reconcile_with(channels: &[(String, u16)]) {
+    let favourites = favourites();
+    let (kept, dropped) = reconcile(&favourites, channels);
+    for name in &dropped {
+        warn!("{} is no longer in the channels file, so is no longer a favourite.", name);
+    }
+    if kept != favourites {
+        preferences::set_favourite_channels(kept, true);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn favourite(name: &str, service_id: Option<u16>) -> FavouriteChannel {
+        FavouriteChannel{name: name.to_string(), service_id}
+    }
+
+    fn channels(channels: &[(&str, u16)]) -> Vec<(String, u16)> {
+        channels.iter().map(|(name, service_id)| (name.to_string(), *service_id)).collect()
+    }
+
+    #[test]
+    fn favourites_are_matched_by_name_and_given_service_ids() {
+        let favourites = vec![favourite("BBC ONE Lon", None), favourite("BBC TWO", Some(4287))];
+        let (kept, dropped) = reconcile(&favourites, &channels(&[("BBC TWO", 4287), ("BBC ONE Lon", 4164), ("ITV", 8261)]));
+        assert_eq!(kept, vec![favourite("BBC ONE Lon", Some(4164)), favourite("BBC TWO", Some(4287))]);
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn renamed_favourites_are_matched_by_service_id() {
+        let favourites = vec![favourite("BBC ONE Lon", Some(4164)), favourite("BBC TWO", Some(4287))];
+        let (kept, dropped) = reconcile(&favourites, &channels(&[("BBC One London", 4164), ("BBC TWO", 4287)]));
+        assert_eq!(kept, vec![favourite("BBC One London", Some(4164)), favourite("BBC TWO", Some(4287))]);
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn favourites_no_longer_in_the_channels_file_are_dropped() {
+        let favourites = vec![favourite("BBC FOUR", Some(4352)), favourite("BBC TWO", Some(4287)), favourite("Dave", None)];
+        let (kept, dropped) = reconcile(&favourites, &channels(&[("BBC TWO", 4287), ("ITV", 8261)]));
+        assert_eq!(kept, vec![favourite("BBC TWO", Some(4287))]);
+        assert_eq!(dropped, vec!["BBC FOUR", "Dave"]);
+    }
+
+    #[test]
+    fn a_service_id_already_a_favourite_by_name_is_not_taken_by_another() {
+        let favourites = vec![favourite("BBC ONE Lon", Some(4164)), favourite("BBC ONE HD", Some(4164))];
+        let (kept, dropped) = reconcile(&favourites, &channels(&[("BBC ONE HD", 4164)]));
+        assert_eq!(kept, vec![favourite("BBC ONE HD", Some(4164))]);
+        assert_eq!(dropped, vec!["BBC ONE Lon"]);
+    }
+
+    #[test]
+    fn renamed_favourites_stay_favourites() {
+        let favourites = vec![favourite("BBC ONE Lon", Some(4164)), favourite("BBC TWO", Some(4287))];
+        assert_eq!(renamed(&favourites, "BBC ONE Lon", "BBC One London"), vec![favourite("BBC One London", Some(4164)), favourite("BBC TWO", Some(4287))]);
+        assert_eq!(renamed(&favourites, "BBC ONE Lon", "BBC TWO"), vec![favourite("BBC TWO", Some(4287))]);
+        assert_eq!(renamed(&favourites, "ITV", "ITV1"), favourites);
+    }
+
+    #[test]
+    fn toggling_adds_and_removes_favourites() {
+        let favourites = toggled(&[favourite("BBC TWO", Some(4287))], "ITV");
+        assert_eq!(favourites, vec![favourite("BBC TWO", Some(4287)), favourite("ITV", None)]);
+        assert_eq!(toggled(&favourites, "BBC TWO"), vec![favourite("ITV", None)]);
+    }
+
+    #[test]
+    fn views_order_and_list_favourites() {
+        assert_eq!(ChannelView::All.ordering(false, true), Ordering::Equal);
+        assert_eq!(ChannelView::FavouritesFirst.ordering(false, true), Ordering::Greater);
+        assert_eq!(ChannelView::FavouritesOnly.ordering(true, false), Ordering::Less);
+        assert_eq!(ChannelView::FavouritesFirst.ordering(true, true), Ordering::Equal);
+        assert!(ChannelView::FavouritesFirst.is_listed(false, true));
+        assert!(!ChannelView::FavouritesOnly.is_listed(false, true));
+        assert!(ChannelView::FavouritesOnly.is_listed(false, false));
+    }
+
+    #[test]
+    fn views_are_menu_item_targets() {
+        for view in &[ChannelView::All, ChannelView::FavouritesFirst, ChannelView::FavouritesOnly] {
+            assert_eq!(ChannelView::from_action_target(view.action_target()), Some(*view));
+        }
+        assert_eq!(ChannelView::from_action_target("some"), None);
+    }
+}
@@ -0,0 +1,223 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The parts of the Linux DVB frontend API, linux/dvb/frontend.h, that are used, for the
+//! use of the modules that talk to frontends.
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+use libc;
+use nix::ioctl_read;
+
+/// The fe_type value of satellite frontends, whose frequencies are reported in kHz.
+pub const FE_QPSK: u32 = 0;
+
+/// The fe_status bit set when the frontend has a lock.
+pub const FE_HAS_LOCK: u32 = 0x10;
+
+/// The DVBv5 property commands used.
+pub const DTV_ENUM_DELSYS: u32 = 44;
+pub const DTV_STAT_SIGNAL_STRENGTH: u32 = 62;
+pub const DTV_STAT_CNR: u32 = 63;
+pub const DTV_STAT_POST_ERROR_BIT_COUNT: u32 = 66;
+pub const DTV_STAT_POST_TOTAL_BIT_COUNT: u32 = 67;
+pub const DTV_STAT_ERROR_BLOCK_COUNT: u32 = 68;
+
+/// The fecap_scale_params values, saying how to interpret a statistic.
+pub const FE_SCALE_NOT_AVAILABLE: u8 = 0;
+pub const FE_SCALE_DECIBEL: u8 = 1;  // A signed value in units of 0.001 dB.
+pub const FE_SCALE_RELATIVE: u8 = 2;  // An unsigned value, 0 to 65535.
+pub const FE_SCALE_COUNTER: u8 = 3;  // An unsigned count.
+
+/// struct dvb_frontend_info.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DvbFrontendInfo {
+    pub name: [libc::c_char; 128],
+    pub fe_type: u32,
+    pub frequency_min: u32,
+    pub frequency_max: u32,
+    pub frequency_stepsize: u32,
+    pub frequency_tolerance: u32,
+    pub symbol_rate_min: u32,
+    pub symbol_rate_max: u32,
+    pub symbol_rate_tolerance: u32,
+    pub notifier_delay: u32,
+    pub caps: u32,
+}
+
+impl DvbFrontendInfo {
+    pub fn zeroed() -> DvbFrontendInfo {
+        DvbFrontendInfo {
+            name: [0; 128],
+            fe_type: 0,
+            frequency_min: 0,
+            frequency_max: 0,
+            frequency_stepsize: 0,
+            frequency_tolerance: 0,
+            symbol_rate_min: 0,
+            symbol_rate_max: 0,
+            symbol_rate_tolerance: 0,
+            notifier_delay: 0,
+            caps: 0,
+        }
+    }
+}
+
+/// The size of the union in struct dtv_property, that of its largest member, the buffer.
+const DTV_PROPERTY_UNION_SIZE: usize = 56;
+
+/// The offset of the len field of the buffer member of the union in struct dtv_property.
+const BUFFER_LEN_OFFSET: usize = 32;
+
+/// The size of a struct dtv_stats, which is packed.
+const DTV_STATS_SIZE: usize = 9;
+
+/// The number of struct dtv_stats in a struct dtv_fe_stats.
+const MAX_DTV_STATS: usize = 4;
+
+/// struct dtv_property, which is packed. The union is held as bytes and interpreted
+/// according to the command.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct DtvProperty {
+    pub cmd: u32,
+    pub reserved: [u32; 3],
+    pub u: [u8; DTV_PROPERTY_UNION_SIZE],
+    pub result: libc::c_int,
+}
+
+impl DtvProperty {
+    pub fn new(cmd: u32) -> DtvProperty {
+        DtvProperty { cmd, reserved: [0; 3], u: [0; DTV_PROPERTY_UNION_SIZE], result: 0 }
+    }
+
+    /// The contents of the union as the buffer member, as for DTV_ENUM_DELSYS.
+    pub fn buffer(&self) -> Vec<u8> {
+        let u = self.u;
+        let mut len = [0; 4];
+        len.copy_from_slice(&u[BUFFER_LEN_OFFSET..BUFFER_LEN_OFFSET + 4]);
+        let len = (u32::from_ne_bytes(len) as usize).min(BUFFER_LEN_OFFSET);
+        u[..len].to_vec()
+    }
+
+    /// Set the contents of the union as the buffer member, as the kernel would.
+    pub fn set_buffer(&mut self, data: &[u8]) {
+        let mut u = self.u;
+        u[..data.len()].copy_from_slice(data);
+        u[BUFFER_LEN_OFFSET..BUFFER_LEN_OFFSET + 4].copy_from_slice(&(data.len() as u32).to_ne_bytes());
+        self.u = u;
+    }
+
+    /// The contents of the union as the st member, a struct dtv_fe_stats: the scale and
+    /// value of each statistic, one per layer.
+    pub fn stats(&self) -> Vec<(u8, u64)> {
+        let u = self.u;
+        let len = (u[0] as usize).min(MAX_DTV_STATS);
+        (0..len).map(|i| {
+            let start = 1 + i * DTV_STATS_SIZE;
+            let mut value = [0; 8];
+            value.copy_from_slice(&u[start + 1..start + DTV_STATS_SIZE]);
+            (u[start], u64::from_ne_bytes(value))
+        }).collect()
+    }
+
+    /// Set the contents of the union as the st member, as the kernel would.
+    pub fn set_stats(&mut self, stats: &[(u8, u64)]) {
+        let mut u = self.u;
+        u[0] = stats.len() as u8;
+        for (i, (scale, value)) in stats.iter().enumerate() {
+            let start = 1 + i * DTV_STATS_SIZE;
+            u[start] = *scale;
+            u[start + 1..start + DTV_STATS_SIZE].copy_from_slice(&value.to_ne_bytes());
+        }
+        self.u = u;
+    }
+}
+
+/// struct dtv_properties.
+#[repr(C)]
+pub struct DtvProperties {
+    pub num: u32,
+    pub props: *mut DtvProperty,
+}
+
+ioctl_read!(fe_get_info, b'o', 61, DvbFrontendInfo);
+ioctl_read!(fe_read_status, b'o', 69, u32);
+ioctl_read!(fe_get_property, b'o', 83, DtvProperties);
+
+/// Get the values of the properties whose commands are set in `properties`.
+pub fn get_properties(fd: RawFd, properties: &mut [DtvProperty]) -> io::Result<()> {
+    let mut request = DtvProperties { num: properties.len() as u32, props: properties.as_mut_ptr() };
+    match unsafe { fe_get_property(fd, &mut request) } {
+        Ok(_) => Ok(()),
+        Err(_) => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem::size_of;
+
+    #[test]
+    fn structures_are_the_size_the_kernel_expects() {
+        assert_eq!(size_of::<DvbFrontendInfo>(), 168);
+        assert_eq!(size_of::<DtvProperty>(), 76);
+        assert_eq!(size_of::<DtvProperties>(), 16);
+    }
+
+    #[test]
+    fn buffer_round_trips() {
+        let mut property = DtvProperty::new(DTV_ENUM_DELSYS);
+        property.set_buffer(&[16, 3, 1]);
+        assert_eq!(property.buffer(), vec![16, 3, 1]);
+    }
+
+    #[test]
+    fn buffer_length_is_bounded() {
+        let mut property = DtvProperty::new(DTV_ENUM_DELSYS);
+        property.set_buffer(&[5; 32]);
+        let mut u = property.u;
+        u[BUFFER_LEN_OFFSET..BUFFER_LEN_OFFSET + 4].copy_from_slice(&1000u32.to_ne_bytes());
+        property.u = u;
+        assert_eq!(property.buffer().len(), 32);
+    }
+
+    #[test]
+    fn stats_round_trip() {
+        let mut property = DtvProperty::new(DTV_STAT_CNR);
+        let stats = [(FE_SCALE_DECIBEL, 31_500u64), (FE_SCALE_RELATIVE, 40_000), (FE_SCALE_NOT_AVAILABLE, 0), (FE_SCALE_COUNTER, 7)];
+        property.set_stats(&stats);
+        assert_eq!(property.stats(), stats.to_vec());
+    }
+
+    #[test]
+    fn stats_length_is_bounded() {
+        let mut property = DtvProperty::new(DTV_STAT_CNR);
+        let mut u = property.u;
+        u[0] = 200;
+        property.u = u;
+        assert_eq!(property.stats().len(), 4);
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! A serialisable mirror of the frontend messages the frontend manager sends to the GUI,
+//! for tools that record or relay what happened to which tuner.
+
+#[cfg(feature = "serialize")]
+use serde_derive::{Deserialize, Serialize};
+
+use crate::frontend_info::{Availability, FrontendInfo};
+use crate::frontends::FrontendId;
+
+/// The changes to the frontends of the system. The variant is labelled by the `event`
+/// field when serialised.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize), serde(tag = "event", rename_all = "snake_case"))]
+pub enum FrontendEvent {
+    Appeared {
+        fei: FrontendId,
+        availability: Availability,
+        info: Option<FrontendInfo>,  // None if the frontend could not be asked.
+    },
+    Disappeared {
+        fei: FrontendId,
+    },
+    AvailabilityChanged {
+        fei: FrontendId,
+        availability: Availability,
+    },
+    Inaccessible {
+        fei: FrontendId,
+        reason: String,
+    },
+}
+
+#[cfg(all(test, feature = "serialize"))]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    use crate::frontend_info::DeliverySystem;
+
+    fn events() -> Vec<FrontendEvent> {
+        let fei = FrontendId{adapter: 1, frontend: 0};
+        vec![
+            FrontendEvent::Appeared {
+                fei: fei.clone(),
+                availability: Availability::Available,
+                info: Some(FrontendInfo {
+                    name: "Silicon Labs Si2168".to_string(),
+                    delivery_systems: vec![DeliverySystem::DVBT2, DeliverySystem::DVBC_ANNEX_A],
+                    frequency_min_hz: 42_000_000,
+                    frequency_max_hz: 870_000_000,
+                }),
+            },
+            FrontendEvent::Appeared { fei: fei.clone(), availability: Availability::InUse, info: None },
+            FrontendEvent::AvailabilityChanged { fei: fei.clone(), availability: Availability::Available },
+            FrontendEvent::Inaccessible { fei: fei.clone(), reason: "Permission denied".to_string() },
+            FrontendEvent::Disappeared { fei },
+        ]
+    }
+
+    #[test]
+    fn json_field_names_are_stable() {
+        assert_eq!(
+            serde_json::to_string(&events()[0]).unwrap(),
+            r#"{"event":"appeared","fei":"adapter1:frontend0","availability":"available","info":{"name":"Silicon Labs Si2168","delivery_systems":["DVBT2","DVBC/ANNEX_A"],"frequency_min_hz":42000000,"frequency_max_hz":870000000}}"#,
+        );
+        assert_eq!(
+            serde_json::to_string(&events()[2]).unwrap(),
+            r#"{"event":"availability_changed","fei":"adapter1:frontend0","availability":"available"}"#,
+        );
+    }
+
+    #[test]
+    fn json_round_trips() {
+        for event in events() {
+            let json = serde_json::to_string(&event).unwrap();
+            assert_eq!(serde_json::from_str::<FrontendEvent>(&json).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn toml_round_trips() {
+        for event in events() {
+            let toml = toml::to_string(&event).unwrap();
+            assert_eq!(toml::from_str::<FrontendEvent>(&toml).unwrap(), event);
+        }
+    }
+}
@@ -0,0 +1,621 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The capabilities of a frontend, found using the FE_GET_INFO ioctl and the
+//! DTV_ENUM_DELSYS property of the DVBv5 property API. A hybrid card may have a single
+//! frontend supporting several delivery systems, DVB-T2 and DVB-C say, so the delivery
+//! systems are always a list.
+//!
+//! The frontend is opened read-only and non-blocking so that asking about a frontend
+//! another process is using neither blocks nor disturbs that process.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::str::FromStr;
+
+use libc;
+
+#[cfg(feature = "serialize")]
+use serde_derive::{Deserialize, Serialize};
+
+use crate::frontend_abi::{fe_get_info, get_properties, DtvProperty, DvbFrontendInfo, DTV_ENUM_DELSYS, FE_QPSK};
+use crate::frontends::{frontend_hardware, frontend_path, FrontendHardware, FrontendId};
+
+/// The delivery systems of the Linux DVB API; the discriminants are the
+/// fe_delivery_system values.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DeliverySystem {
+    DVBC_ANNEX_A = 1,
+    DVBC_ANNEX_B = 2,
+    DVBT = 3,
+    DSS = 4,
+    DVBS = 5,
+    DVBS2 = 6,
+    DVBH = 7,
+    ISDBT = 8,
+    ISDBS = 9,
+    ISDBC = 10,
+    ATSC = 11,
+    ATSCMH = 12,
+    DTMB = 13,
+    CMMB = 14,
+    DAB = 15,
+    DVBT2 = 16,
+    TURBO = 17,
+    DVBC_ANNEX_C = 18,
+}
+
+// Indexed by fe_delivery_system value less one.
+static DELIVERY_SYSTEMS: [DeliverySystem; 18] = [
+    DeliverySystem::DVBC_ANNEX_A,
+    DeliverySystem::DVBC_ANNEX_B,
+    DeliverySystem::DVBT,
+    DeliverySystem::DSS,
+    DeliverySystem::DVBS,
+    DeliverySystem::DVBS2,
+    DeliverySystem::DVBH,
+    DeliverySystem::ISDBT,
+    DeliverySystem::ISDBS,
+    DeliverySystem::ISDBC,
+    DeliverySystem::ATSC,
+    DeliverySystem::ATSCMH,
+    DeliverySystem::DTMB,
+    DeliverySystem::CMMB,
+    DeliverySystem::DAB,
+    DeliverySystem::DVBT2,
+    DeliverySystem::TURBO,
+    DeliverySystem::DVBC_ANNEX_C,
+];
+
+impl DeliverySystem {
+    /// The delivery system with a given fe_delivery_system value, if there is one;
+    /// SYS_UNDEFINED, 0, is not a delivery system.
+    pub fn from_value(value: u8) -> Option<DeliverySystem> {
+        if value == 0 { None } else { DELIVERY_SYSTEMS.get(value as usize - 1).copied() }
+    }
+
+    /// Whether the frequencies of the delivery system are in kHz rather than Hz.
+    pub fn is_satellite(&self) -> bool {
+        matches!(self, DeliverySystem::DSS | DeliverySystem::DVBS | DeliverySystem::DVBS2 | DeliverySystem::ISDBS | DeliverySystem::TURBO)
+    }
+}
+
+/// The DVBv5 name, as used in channels files.
+impl fmt::Display for DeliverySystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format!("{:?}", self).replacen('_', "/", 1))
+    }
+}
+
+/// Serialised as the DVBv5 name.
+#[cfg(feature = "serialize")]
+impl serde::Serialize for DeliverySystem {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de> serde::Deserialize<'de> for DeliverySystem {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<DeliverySystem, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Accepts the DVBv5 name, or the name with an underscore instead of the slash as used
+/// by dvbbasebin and the preferences.
+impl FromStr for DeliverySystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DeliverySystem, String> {
+        DELIVERY_SYSTEMS.iter()
+            .find(|d| d.to_string() == s || format!("{:?}", d) == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown delivery system {}", s))
+    }
+}
+
+/// Extract the delivery systems from the reply to getting DTV_ENUM_DELSYS. Values not
+/// known here, from a kernel newer than this code, are ignored.
+fn parse_enum_delsys_reply(property: &DtvProperty) -> io::Result<Vec<DeliverySystem>> {
+    let result = property.result;
+    if result < 0 { return Err(io::Error::from_raw_os_error(-result)); }
+    Ok(property.buffer().iter().filter_map(|v| DeliverySystem::from_value(*v)).collect())
+}
+
+/// The queries needed of a frontend device, separated out so that the interpretation of
+/// the answers can be tested without a DVB device.
+trait FrontendDevice {
+    /// The result of FE_GET_INFO.
+    fn frontend_info(&self) -> io::Result<DvbFrontendInfo>;
+
+    /// The reply to getting the DTV_ENUM_DELSYS property.
+    fn enum_delsys_reply(&self) -> io::Result<DtvProperty>;
+}
+
+impl FrontendDevice for File {
+    fn frontend_info(&self) -> io::Result<DvbFrontendInfo> {
+        let mut info = DvbFrontendInfo::zeroed();
+        match unsafe { fe_get_info(self.as_raw_fd(), &mut info) } {
+            Ok(_) => Ok(info),
+            Err(_) => Err(io::Error::last_os_error()),
+        }
+    }
+
+    fn enum_delsys_reply(&self) -> io::Result<DtvProperty> {
+        let mut properties = [DtvProperty::new(DTV_ENUM_DELSYS)];
+        get_properties(self.as_raw_fd(), &mut properties)?;
+        Ok(properties[0])
+    }
+}
+
+/// What a frontend says about itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct FrontendInfo {
+    pub name: String,
+    pub delivery_systems: Vec<DeliverySystem>,
+    pub frequency_min_hz: u64,
+    pub frequency_max_hz: u64,
+}
+
+impl FrontendInfo {
+    /// A one line human readable description, for labelling a tuner.
+    pub fn description(&self) -> String {
+        format!(
+            "{}: {}, {}–{} MHz",
+            self.name,
+            if self.delivery_systems.is_empty() {
+                "unknown delivery systems".to_string()
+            } else {
+                self.delivery_systems.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", ")
+            },
+            self.frequency_min_hz / 1_000_000,
+            self.frequency_max_hz / 1_000_000,
+        )
+    }
+
+    /// Whether the frontend can receive a delivery system.
+    pub fn supports(&self, delivery_system: DeliverySystem) -> bool {
+        self.delivery_systems.contains(&delivery_system)
+    }
+}
+
+/// The delivery system implied by the fe_type of FE_GET_INFO, for kernels too old to
+/// support DTV_ENUM_DELSYS.
+fn delivery_system_of_fe_type(fe_type: u32) -> Option<DeliverySystem> {
+    match fe_type {
+        FE_QPSK => Some(DeliverySystem::DVBS),
+        1 => Some(DeliverySystem::DVBC_ANNEX_A),
+        2 => Some(DeliverySystem::DVBT),
+        3 => Some(DeliverySystem::ATSC),
+        _ => None,
+    }
+}
+
+/// Interpret the answers a frontend device gives to the queries.
+fn query(device: &impl FrontendDevice) -> io::Result<FrontendInfo> {
+    let info = device.frontend_info()?;
+    let name = info.name.iter().take_while(|c| **c != 0).map(|c| *c as u8 as char).collect::<String>();
+    let delivery_systems = match device.enum_delsys_reply().and_then(|reply| parse_enum_delsys_reply(&reply)) {
+        Ok(delivery_systems) => delivery_systems,
+        Err(_) => delivery_system_of_fe_type(info.fe_type).into_iter().collect(),
+    };
+    let scale = if info.fe_type == FE_QPSK { 1000 } else { 1 };
+    Ok(FrontendInfo {
+        name: name.trim().to_string(),
+        delivery_systems,
+        frequency_min_hz: info.frequency_min as u64 * scale,
+        frequency_max_hz: info.frequency_max as u64 * scale,
+    })
+}
+
+/// Whether a frontend can be used for tuning.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum Availability {
+    Available,
+    InUse,  // Another process has the frontend open for tuning.
+    Inaccessible,  // The user does not have permission to use the frontend.
+    Unknown,
+}
+
+impl Availability {
+    /// The availability implied by the result of trying to open a frontend for tuning.
+    fn from_open_result<T>(result: &io::Result<T>) -> Availability {
+        match result {
+            Ok(_) => Availability::Available,
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => Availability::InUse,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Availability::Inaccessible,
+            Err(_) => Availability::Unknown,
+        }
+    }
+}
+
+/// Check whether the frontend device at `path` is in use by trying a non-blocking
+/// read-write open, which the kernel refuses with EBUSY whilst another process has the
+/// frontend open for tuning. The device is closed again immediately.
+pub fn availability_of(path: &Path) -> Availability {
+    Availability::from_open_result(&OpenOptions::new().read(true).write(true).custom_flags(libc::O_NONBLOCK).open(path))
+}
+
+/// Check whether a frontend is in use by another process.
+pub fn availability(fei: &FrontendId) -> Availability {
+    availability_of(&frontend_path(fei))
+}
+
+/// The name of the group owning the file at `path`.
+fn group_name_of(path: &Path) -> Option<String> {
+    let gid = fs::metadata(path).ok()?.gid();
+    // getgrgid is not thread safe but the name is copied out immediately.
+    let group = unsafe { libc::getgrgid(gid) };
+    if group.is_null() { return None; }
+    Some(unsafe { CStr::from_ptr((*group).gr_name) }.to_string_lossy().into_owned())
+}
+
+/// What to tell the user when they do not have permission to use the frontend at `path`
+/// owned by `group`, usually video.
+pub fn permission_guidance(path: &Path, group: Option<&str>) -> String {
+    format!(
+        "Permission denied on {}, add your user to the '{}' group (and log in again) to use it.",
+        path.display(),
+        group.unwrap_or("video"),
+    )
+}
+
+/// Check, using access(2) so that the device is not opened, whether the user has
+/// permission to use the frontend device at `path`, returning the guidance to give the
+/// user if not.
+pub fn inaccessibility_reason_of(path: &Path) -> Option<String> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    if unsafe { libc::access(c_path.as_ptr(), libc::R_OK | libc::W_OK) } == 0 { return None; }
+    match io::Error::last_os_error().kind() {
+        io::ErrorKind::PermissionDenied => Some(permission_guidance(path, group_name_of(path).as_deref())),
+        _ => None,
+    }
+}
+
+/// Check whether the user has permission to use a frontend, returning the guidance to
+/// give the user if not.
+pub fn inaccessibility_reason(fei: &FrontendId) -> Option<String> {
+    inaccessibility_reason_of(&frontend_path(fei))
+}
+
+/// Ask the frontend device at `path` about its capabilities.
+///
+/// A frontend that is in use may refuse even a read-only open with EBUSY; callers should
+/// treat that as the capabilities being unknown rather than the frontend being absent.
+pub fn frontend_info_of(path: &Path) -> io::Result<FrontendInfo> {
+    let device = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(path)?;
+    query(&device)
+}
+
+/// Ask a frontend about its capabilities.
+pub fn frontend_info(fei: &FrontendId) -> io::Result<FrontendInfo> {
+    frontend_info_of(&frontend_path(fei))
+}
+
+/// The name to show the user for frontend `fei`, so that identical looking adapters
+/// can be told apart: the demodulator named by FE_GET_INFO and the hardware it is part
+/// of, as far as they are known, then the adapter and frontend.
+pub fn display_name(fei: &FrontendId, info: Option<&FrontendInfo>, hardware: &FrontendHardware) -> String {
+    let chip = info.map(|info| info.name.trim()).filter(|name| !name.is_empty());
+    match (chip, &hardware.name) {
+        (Some(chip), Some(hardware)) => format!("{} ({}) — {}", chip, hardware, fei),
+        (Some(name), None) | (None, Some(name)) => format!("{} — {}", name, fei),
+        (None, None) => fei.to_string(),
+    }
+}
+
+/// The display name of frontend `fei` from what can be read of it now.
+pub fn read_display_name(fei: &FrontendId) -> String {
+    display_name(fei, frontend_info(fei).ok().as_ref(), &frontend_hardware(fei))
+}
+
+/// The delivery systems a frontend supports, using DTV_ENUM_DELSYS only.
+pub fn delivery_systems(fei: &FrontendId) -> io::Result<Vec<DeliverySystem>> {
+    let device = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(frontend_path(fei))?;
+    parse_enum_delsys_reply(&device.enum_delsys_reply()?)
+}
+
+/// The frontends, of those whose delivery systems are in `capabilities`, that can
+/// receive `required`, in order.
+pub fn compatible_frontends(required: DeliverySystem, capabilities: &HashMap<FrontendId, Vec<DeliverySystem>>) -> Vec<FrontendId> {
+    let mut frontends = capabilities.iter()
+        .filter(|(_, delivery_systems)| delivery_systems.contains(&required))
+        .map(|(fei, _)| fei.clone())
+        .collect::<Vec<_>>();
+    frontends.sort();
+    frontends
+}
+
+/// Why frontend `fei` cannot tune a channel using delivery system `required`, naming
+/// the frontends that can, or None if it can. Multi-standard cards have frontends for
+/// different delivery systems on the same adapter, so the frontends of an adapter are
+/// not interchangeable. A frontend whose capabilities are not in `capabilities` is given
+/// the benefit of the doubt.
+pub fn incompatibility_reason(fei: &FrontendId, required: DeliverySystem, capabilities: &HashMap<FrontendId, Vec<DeliverySystem>>) -> Option<String> {
+    let supported = capabilities.get(fei)?;
+    if supported.contains(&required) { return None; }
+    let supported = supported.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", ");
+    let alternatives = match compatible_frontends(required, capabilities).as_slice() {
+        [] => format!("no frontend supports {}", required),
+        compatible => format!("use {}", compatible.iter().map(|fei| fei.to_string()).collect::<Vec<_>>().join(" or ")),
+    };
+    Some(format!("{} cannot tune {} channels, it supports {}; {}.", fei, required, supported, alternatives))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockFrontend {
+        info: io::Result<DvbFrontendInfo>,
+        delivery_systems: io::Result<Vec<u8>>,  // The fe_delivery_system values of the reply.
+    }
+
+    fn enum_delsys_reply(values: &[u8]) -> DtvProperty {
+        let mut result = DtvProperty::new(DTV_ENUM_DELSYS);
+        result.set_buffer(values);
+        result
+    }
+
+    impl FrontendDevice for MockFrontend {
+        fn frontend_info(&self) -> io::Result<DvbFrontendInfo> {
+            match &self.info {
+                Ok(info) => Ok(*info),
+                Err(e) => Err(io::Error::from_raw_os_error(e.raw_os_error().unwrap())),
+            }
+        }
+
+        fn enum_delsys_reply(&self) -> io::Result<DtvProperty> {
+            match &self.delivery_systems {
+                Ok(values) => Ok(enum_delsys_reply(values)),
+                Err(e) => Err(io::Error::from_raw_os_error(e.raw_os_error().unwrap())),
+            }
+        }
+    }
+
+    fn dvb_frontend_info(name: &str, fe_type: u32, frequency_min: u32, frequency_max: u32) -> DvbFrontendInfo {
+        let mut result = DvbFrontendInfo { fe_type, frequency_min, frequency_max, ..DvbFrontendInfo::zeroed() };
+        for (i, b) in name.bytes().enumerate() { result.name[i] = b as libc::c_char; }
+        result
+    }
+
+    #[test]
+    fn enumerated_delivery_systems_are_named() {
+        let device = MockFrontend {
+            info: Ok(dvb_frontend_info("Silicon Labs Si2168", 2, 42_000_000, 870_000_000)),
+            delivery_systems: Ok(vec![16, 3, 1]),
+        };
+        assert_eq!(query(&device).unwrap(), FrontendInfo {
+            name: "Silicon Labs Si2168".to_string(),
+            delivery_systems: vec![DeliverySystem::DVBT2, DeliverySystem::DVBT, DeliverySystem::DVBC_ANNEX_A],
+            frequency_min_hz: 42_000_000,
+            frequency_max_hz: 870_000_000,
+        });
+    }
+
+    #[test]
+    fn satellite_frequencies_are_converted_from_khz() {
+        let device = MockFrontend {
+            info: Ok(dvb_frontend_info("STV090x", FE_QPSK, 950_000, 2_150_000)),
+            delivery_systems: Ok(vec![5, 6]),
+        };
+        let info = query(&device).unwrap();
+        assert_eq!(info.frequency_min_hz, 950_000_000);
+        assert_eq!(info.frequency_max_hz, 2_150_000_000);
+        assert!(info.supports(DeliverySystem::DVBS2));
+        assert!(!info.supports(DeliverySystem::DVBT));
+    }
+
+    #[test]
+    fn fe_type_is_used_when_delivery_systems_cannot_be_enumerated() {
+        let device = MockFrontend {
+            info: Ok(dvb_frontend_info("Old Card", 2, 174_000_000, 862_000_000)),
+            delivery_systems: Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP)),
+        };
+        assert_eq!(query(&device).unwrap().delivery_systems, vec![DeliverySystem::DVBT]);
+    }
+
+    #[test]
+    fn undefined_and_unknown_delivery_systems_are_dropped() {
+        let device = MockFrontend {
+            info: Ok(dvb_frontend_info("Card", 2, 0, 0)),
+            delivery_systems: Ok(vec![0, 3, 200]),
+        };
+        assert_eq!(query(&device).unwrap().delivery_systems, vec![DeliverySystem::DVBT]);
+    }
+
+    #[test]
+    fn failure_of_fe_get_info_is_an_error() {
+        let device = MockFrontend {
+            info: Err(io::Error::from_raw_os_error(libc::EBUSY)),
+            delivery_systems: Ok(vec![3]),
+        };
+        assert_eq!(query(&device).unwrap_err().raw_os_error(), Some(libc::EBUSY));
+    }
+
+    #[test]
+    fn a_device_that_is_not_a_frontend_is_an_error() {
+        assert_eq!(frontend_info_of(Path::new("/dev/null")).unwrap_err().raw_os_error(), Some(libc::ENOTTY));
+    }
+
+    #[test]
+    fn description_names_card_and_delivery_systems() {
+        let info = FrontendInfo {
+            name: "Si2168".to_string(),
+            delivery_systems: vec![DeliverySystem::DVBT, DeliverySystem::DVBT2],
+            frequency_min_hz: 42_000_000,
+            frequency_max_hz: 870_000_000,
+        };
+        assert_eq!(info.description(), "Si2168: DVBT, DVBT2, 42–870 MHz");
+    }
+
+    #[test]
+    fn ebusy_on_open_means_in_use() {
+        let result: io::Result<()> = Err(io::Error::from_raw_os_error(libc::EBUSY));
+        assert_eq!(Availability::from_open_result(&result), Availability::InUse);
+    }
+
+    #[test]
+    fn other_errors_on_open_mean_availability_unknown() {
+        let result: io::Result<()> = Err(io::Error::from_raw_os_error(libc::EACCES));
+        assert_eq!(Availability::from_open_result(&result), Availability::Unknown);
+        assert_eq!(availability_of(Path::new("/nonexistent/adapter0/frontend0")), Availability::Unknown);
+    }
+
+    #[test]
+    fn permission_errors_on_open_mean_inaccessible() {
+        let result: io::Result<()> = Err(io::Error::from_raw_os_error(libc::EACCES));
+        assert_eq!(Availability::from_open_result(&result), Availability::Inaccessible);
+        let result: io::Result<()> = Err(io::Error::from_raw_os_error(libc::EPERM));
+        assert_eq!(Availability::from_open_result(&result), Availability::Inaccessible);
+    }
+
+    #[test]
+    fn permission_guidance_names_the_group() {
+        assert_eq!(
+            permission_guidance(Path::new("/dev/dvb/adapter0/frontend0"), Some("video")),
+            "Permission denied on /dev/dvb/adapter0/frontend0, add your user to the 'video' group (and log in again) to use it.",
+        );
+        assert!(permission_guidance(Path::new("/dev/dvb/adapter0/frontend0"), None).contains("'video' group"));
+    }
+
+    #[test]
+    fn accessible_or_missing_devices_have_no_inaccessibility_reason() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert_eq!(inaccessibility_reason_of(file.path()), None);
+        assert_eq!(inaccessibility_reason_of(Path::new("/nonexistent/adapter0/frontend0")), None);
+    }
+
+    #[test]
+    fn successful_open_means_available() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert_eq!(availability_of(file.path()), Availability::Available);
+    }
+
+    #[test]
+    fn hybrid_enum_delsys_reply_is_parsed() {
+        let reply = enum_delsys_reply(&[16, 3, 1, 18]);
+        assert_eq!(parse_enum_delsys_reply(&reply).unwrap(), vec![
+            DeliverySystem::DVBT2, DeliverySystem::DVBT, DeliverySystem::DVBC_ANNEX_A, DeliverySystem::DVBC_ANNEX_C,
+        ]);
+    }
+
+    #[test]
+    fn empty_enum_delsys_reply_gives_no_delivery_systems() {
+        assert_eq!(parse_enum_delsys_reply(&enum_delsys_reply(&[])).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn enum_delsys_reply_with_negative_result_is_an_error() {
+        let mut reply = enum_delsys_reply(&[3]);
+        reply.result = -libc::EINVAL;
+        assert_eq!(parse_enum_delsys_reply(&reply).unwrap_err().raw_os_error(), Some(libc::EINVAL));
+    }
+
+    #[test]
+    fn delivery_system_values_round_trip() {
+        for value in 1..=18 {
+            assert_eq!(DeliverySystem::from_value(value).unwrap() as u8, value);
+        }
+        assert_eq!(DeliverySystem::from_value(0), None);
+        assert_eq!(DeliverySystem::from_value(19), None);
+    }
+
+    #[test]
+    fn delivery_system_display_is_the_dvbv5_name() {
+        assert_eq!(DeliverySystem::DVBT2.to_string(), "DVBT2");
+        assert_eq!(DeliverySystem::DVBC_ANNEX_A.to_string(), "DVBC/ANNEX_A");
+    }
+
+    #[test]
+    fn delivery_system_from_str_accepts_both_spellings() {
+        assert_eq!("DVBC/ANNEX_B".parse::<DeliverySystem>(), Ok(DeliverySystem::DVBC_ANNEX_B));
+        assert_eq!("DVBC_ANNEX_B".parse::<DeliverySystem>(), Ok(DeliverySystem::DVBC_ANNEX_B));
+        assert_eq!("ISDBT".parse::<DeliverySystem>(), Ok(DeliverySystem::ISDBT));
+        assert!("DVB-T".parse::<DeliverySystem>().is_err());
+    }
+
+    #[test]
+    fn satellite_delivery_systems_are_identified() {
+        assert!(DeliverySystem::DVBS2.is_satellite());
+        assert!(!DeliverySystem::DVBT2.is_satellite());
+    }
+
+    fn tbs_capabilities() -> HashMap<FrontendId, Vec<DeliverySystem>> {
+        let mut capabilities = HashMap::new();
+        capabilities.insert(FrontendId{adapter: 0, frontend: 0}, vec![DeliverySystem::DVBS, DeliverySystem::DVBS2]);
+        capabilities.insert(FrontendId{adapter: 0, frontend: 1}, vec![DeliverySystem::DVBT, DeliverySystem::DVBT2]);
+        capabilities.insert(FrontendId{adapter: 1, frontend: 0}, vec![DeliverySystem::DVBT]);
+        capabilities
+    }
+
+    #[test]
+    fn compatible_frontends_are_those_supporting_the_delivery_system() {
+        let capabilities = tbs_capabilities();
+        assert_eq!(compatible_frontends(DeliverySystem::DVBS2, &capabilities), vec![FrontendId{adapter: 0, frontend: 0}]);
+        assert_eq!(compatible_frontends(DeliverySystem::DVBT, &capabilities), vec![FrontendId{adapter: 0, frontend: 1}, FrontendId{adapter: 1, frontend: 0}]);
+        assert_eq!(compatible_frontends(DeliverySystem::ATSC, &capabilities), vec![]);
+    }
+
+    #[test]
+    fn frontends_of_an_adapter_are_not_interchangeable() {
+        let capabilities = tbs_capabilities();
+        assert_eq!(incompatibility_reason(&FrontendId{adapter: 0, frontend: 0}, DeliverySystem::DVBS2, &capabilities), None);
+        assert_eq!(
+            incompatibility_reason(&FrontendId{adapter: 0, frontend: 1}, DeliverySystem::DVBS2, &capabilities),
+            Some("adapter0:frontend1 cannot tune DVBS2 channels, it supports DVBT, DVBT2; use adapter0:frontend0.".to_string())
+        );
+        assert_eq!(
+            incompatibility_reason(&FrontendId{adapter: 0, frontend: 0}, DeliverySystem::ATSC, &capabilities),
+            Some("adapter0:frontend0 cannot tune ATSC channels, it supports DVBS, DVBS2; no frontend supports ATSC.".to_string())
+        );
+    }
+
+    #[test]
+    fn frontend_with_unknown_capabilities_is_not_refused() {
+        assert_eq!(incompatibility_reason(&FrontendId{adapter: 5, frontend: 0}, DeliverySystem::DVBS2, &tbs_capabilities()), None);
+    }
+
+    #[test]
+    fn display_name_uses_what_is_known() {
+        let fei = FrontendId{adapter: 0, frontend: 0};
+        let info = FrontendInfo{name: "Sony CXD2837ER ".to_string(), delivery_systems: vec![DeliverySystem::DVBT], frequency_min_hz: 0, frequency_max_hz: 0};
+        let hardware = FrontendHardware{name: Some("Hauppauge dualHD".to_string()), bus_info: Some("usb 1-2".to_string())};
+        assert_eq!(display_name(&fei, Some(&info), &hardware), "Sony CXD2837ER (Hauppauge dualHD) — adapter0:frontend0");
+        assert_eq!(display_name(&fei, Some(&info), &FrontendHardware::default()), "Sony CXD2837ER — adapter0:frontend0");
+        assert_eq!(display_name(&fei, None, &hardware), "Hauppauge dualHD — adapter0:frontend0");
+        let unnamed = FrontendInfo{name: String::new(), ..info};
+        assert_eq!(display_name(&fei, Some(&unnamed), &FrontendHardware::default()), "adapter0:frontend0");
+    }
+
+}
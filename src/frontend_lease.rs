@@ -0,0 +1,399 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Reservations of frontends, so that the parts of a program wanting a tuner, viewing,
+//! recording, EPG harvesting, scanning, do not tread on each other.
+//!
+//! A reservation is held as a `Lease` and released when the lease is dropped. A lease
+//! may also hold the frontend lock so that other processes are kept off the frontend.
+//! A reservation outlives its frontend disappearing for a grace period, so that a USB
+//! hiccup does not lose it.
+//!
+//! EPG harvesting only uses frontends nothing else wants, so a reservation for it gives
+//! way to a reservation for anything else, whoever holds it being told so that it stops
+//! using the frontend before the new reservation is granted.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::frontend_lock::{FrontendLock, LockError, LockHolder};
+use crate::frontends::FrontendId;
+
+/// What a frontend is reserved for.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Purpose {
+    Viewing,
+    Recording { channel: String },
+    EpgHarvesting,
+    Scanning,
+}
+
+impl Purpose {
+    /// Whether a reservation for the purpose gives way to a reservation for any other.
+    pub fn is_yielding(&self) -> bool {
+        *self == Purpose::EpgHarvesting
+    }
+}
+
+impl fmt::Display for Purpose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Purpose::Viewing => write!(f, "viewing"),
+            Purpose::Recording { channel } => write!(f, "recording {}", channel),
+            Purpose::EpgHarvesting => write!(f, "EPG harvesting"),
+            Purpose::Scanning => write!(f, "scanning"),
+        }
+    }
+}
+
+/// Why a frontend could not be reserved.
+#[derive(Debug)]
+pub enum Busy {
+    Reserved { fei: FrontendId, purpose: Purpose },  // By this process.
+    Locked(LockError),  // By another process, or the lock file could not be used.
+}
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Busy::Reserved { fei, purpose } => write!(f, "adapter {} frontend {} is reserved for {}", fei.adapter, fei.frontend, purpose),
+            Busy::Locked(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The changes to the reservations, for showing what each frontend is doing.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReservationEvent {
+    Granted { fei: FrontendId, purpose: Purpose },
+    Refused { fei: FrontendId, purpose: Purpose, reason: String },
+    Released { fei: FrontendId, purpose: Purpose },
+}
+
+/// What to do when a reservation gives way to another.
+struct OnPreempted(Box<dyn FnOnce() + Send>);
+
+impl fmt::Debug for OnPreempted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OnPreempted")
+    }
+}
+
+#[derive(Debug)]
+struct Reservation {
+    id: u64,
+    purpose: Purpose,
+    absent_since: Option<Instant>,  // Some whilst the frontend has disappeared.
+    on_preempted: Option<OnPreempted>,
+}
+
+struct Inner {
+    reservations: Mutex<(u64, HashMap<FrontendId, Reservation>)>,  // The next id and the reservations.
+    grace_period: Duration,
+    observer: Box<dyn Fn(ReservationEvent) + Send + Sync>,
+}
+
+impl Inner {
+    /// Remove a reservation if it is still the one with `id`, it may have expired.
+    fn release(&self, fei: &FrontendId, id: u64) {
+        let released = {
+            let mut reservations = self.reservations.lock().unwrap();
+            match reservations.1.get(fei) {
+                Some(reservation) if reservation.id == id => reservations.1.remove(fei),
+                _ => None,
+            }
+        };
+        if let Some(reservation) = released {
+            (self.observer)(ReservationEvent::Released { fei: fei.clone(), purpose: reservation.purpose });
+        }
+    }
+}
+
+/// The reservations of the frontends. Clones share the same reservations.
+#[derive(Clone)]
+pub struct Reservations {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for Reservations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reservations").field("reservations", &self.inner.reservations.lock().unwrap().1).finish()
+    }
+}
+
+/// A reservation of a frontend, released when dropped.
+#[derive(Debug)]
+pub struct Lease {
+    reservations: Reservations,
+    fei: FrontendId,
+    id: u64,
+    _lock: Option<FrontendLock>,  // Held to keep other processes off the frontend.
+}
+
+impl Lease {
+    pub fn fei(&self) -> &FrontendId { &self.fei }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.reservations.inner.release(&self.fei, self.id);
+    }
+}
+
+impl Reservations {
+    /// Reservations whose changes are not observed.
+    pub fn new(grace_period: Duration) -> Reservations {
+        Reservations::with_observer(grace_period, |_| {})
+    }
+
+    /// Reservations whose changes are passed to `observer`, which is not called with
+    /// the reservations locked.
+    pub fn with_observer(grace_period: Duration, observer: impl Fn(ReservationEvent) + Send + Sync + 'static) -> Reservations {
+        Reservations {
+            inner: Arc::new(Inner {
+                reservations: Mutex::new((0, HashMap::new())),
+                grace_period,
+                observer: Box::new(observer),
+            }),
+        }
+    }
+
+    /// Reserve a frontend for a purpose, refused if it is already reserved, unless the
+    /// reservation gives way to this one.
+    pub fn reserve(&self, fei: &FrontendId, purpose: Purpose) -> Result<Lease, Busy> {
+        self.reserve_with(fei, purpose, None)
+    }
+
+    /// Reserve a frontend for a purpose that gives way to any other, `on_preempted` being
+    /// called, before the other reservation is granted, if it does.
+    pub fn reserve_yielding(&self, fei: &FrontendId, purpose: Purpose, on_preempted: impl FnOnce() + Send + 'static) -> Result<Lease, Busy> {
+        self.reserve_with(fei, purpose, Some(OnPreempted(Box::new(on_preempted))))
+    }
+
+    fn reserve_with(&self, fei: &FrontendId, purpose: Purpose, on_preempted: Option<OnPreempted>) -> Result<Lease, Busy> {
+        let (result, preempted) = {
+            let mut reservations = self.inner.reservations.lock().unwrap();
+            let preempted = match reservations.1.get(fei) {
+                Some(reservation) if reservation.purpose.is_yielding() && !purpose.is_yielding() => reservations.1.remove(fei),
+                _ => None,
+            };
+            match reservations.1.get(fei) {
+                Some(reservation) => (Err(Busy::Reserved { fei: fei.clone(), purpose: reservation.purpose.clone() }), preempted),
+                None => {
+                    let id = reservations.0;
+                    reservations.0 += 1;
+                    reservations.1.insert(fei.clone(), Reservation { id, purpose: purpose.clone(), absent_since: None, on_preempted });
+                    (Ok(Lease { reservations: self.clone(), fei: fei.clone(), id, _lock: None }), preempted)
+                },
+            }
+        };
+        if let Some(reservation) = preempted {
+            if let Some(OnPreempted(on_preempted)) = reservation.on_preempted {
+                on_preempted();
+            }
+            (self.inner.observer)(ReservationEvent::Released { fei: fei.clone(), purpose: reservation.purpose });
+        }
+        (self.inner.observer)(match &result {
+            Ok(_) => ReservationEvent::Granted { fei: fei.clone(), purpose },
+            Err(busy) => ReservationEvent::Refused { fei: fei.clone(), purpose, reason: busy.to_string() },
+        });
+        result
+    }
+
+    /// Reserve a frontend for a purpose and take the frontend lock in `lock_directory`
+    /// so that other processes cannot use it either.
+    pub fn reserve_with_lock(&self, fei: &FrontendId, purpose: Purpose, lock_directory: &Path, holder: &LockHolder) -> Result<Lease, Busy> {
+        let mut lease = self.reserve(fei, purpose.clone())?;
+        match FrontendLock::acquire(lock_directory, fei, holder) {
+            Ok(lock) => {
+                lease._lock = Some(lock);
+                Ok(lease)
+            },
+            Err(e) => {
+                // Release before reporting the refusal so the events are in order.
+                drop(lease);
+                let busy = Busy::Locked(e);
+                (self.inner.observer)(ReservationEvent::Refused { fei: fei.clone(), purpose, reason: busy.to_string() });
+                Err(busy)
+            },
+        }
+    }
+
+    /// What a frontend is reserved for, if it is.
+    pub fn purpose_of(&self, fei: &FrontendId) -> Option<Purpose> {
+        self.inner.reservations.lock().unwrap().1.get(fei).map(|reservation| reservation.purpose.clone())
+    }
+
+    /// A frontend has disappeared, start the grace period of any reservation of it.
+    pub fn frontend_disappeared(&self, fei: &FrontendId, now: Instant) {
+        if let Some(reservation) = self.inner.reservations.lock().unwrap().1.get_mut(fei) {
+            reservation.absent_since.get_or_insert(now);
+        }
+    }
+
+    /// A frontend has appeared, any reservation of it is no longer at risk.
+    pub fn frontend_appeared(&self, fei: &FrontendId) {
+        if let Some(reservation) = self.inner.reservations.lock().unwrap().1.get_mut(fei) {
+            reservation.absent_since = None;
+        }
+    }
+
+    /// Release the reservations of frontends that have been gone for longer than the
+    /// grace period.
+    pub fn expire(&self, now: Instant) {
+        let expired = {
+            let mut reservations = self.inner.reservations.lock().unwrap();
+            let grace_period = self.inner.grace_period;
+            let feis = reservations.1.iter()
+                .filter(|(_, r)| r.absent_since.map_or(false, |t| now.duration_since(t) > grace_period))
+                .map(|(fei, _)| fei.clone())
+                .collect::<Vec<_>>();
+            feis.into_iter().filter_map(|fei| reservations.1.remove(&fei).map(|r| (fei, r.purpose))).collect::<Vec<_>>()
+        };
+        for (fei, purpose) in expired {
+            (self.inner.observer)(ReservationEvent::Released { fei, purpose });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile;
+
+    const FEI: FrontendId = FrontendId { adapter: 0, frontend: 0 };
+
+    fn observed() -> (Reservations, Arc<Mutex<Vec<ReservationEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let reservations = Reservations::with_observer(Duration::from_secs(30), {
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event)
+        });
+        (reservations, events)
+    }
+
+    #[test]
+    fn a_reserved_frontend_cannot_be_reserved_again() {
+        let reservations = Reservations::new(Duration::from_secs(30));
+        let _lease = reservations.reserve(&FEI, Purpose::Viewing).unwrap();
+        match reservations.reserve(&FEI, Purpose::Recording { channel: "BBC NEWS".to_string() }) {
+            Err(e @ Busy::Reserved { .. }) => assert_eq!(e.to_string(), "adapter 0 frontend 0 is reserved for viewing"),
+            result => panic!("expected the frontend to be reserved, got {:?}", result),
+        }
+        assert!(reservations.reserve(&FrontendId { adapter: 0, frontend: 1 }, Purpose::EpgHarvesting).is_ok());
+    }
+
+    #[test]
+    fn dropping_the_lease_releases_the_reservation() {
+        let (reservations, events) = observed();
+        let lease = reservations.reserve(&FEI, Purpose::Viewing).unwrap();
+        assert_eq!(reservations.purpose_of(&FEI), Some(Purpose::Viewing));
+        drop(lease);
+        assert_eq!(reservations.purpose_of(&FEI), None);
+        assert!(reservations.reserve(&FEI, Purpose::EpgHarvesting).is_ok());
+        assert_eq!(events.lock().unwrap()[..3], [
+            ReservationEvent::Granted { fei: FEI, purpose: Purpose::Viewing },
+            ReservationEvent::Released { fei: FEI, purpose: Purpose::Viewing },
+            ReservationEvent::Granted { fei: FEI, purpose: Purpose::EpgHarvesting },
+        ]);
+    }
+
+    #[test]
+    fn refusals_are_observed() {
+        let (reservations, events) = observed();
+        let _lease = reservations.reserve(&FEI, Purpose::Viewing).unwrap();
+        assert!(reservations.reserve(&FEI, Purpose::EpgHarvesting).is_err());
+        assert_eq!(events.lock().unwrap()[1], ReservationEvent::Refused {
+            fei: FEI,
+            purpose: Purpose::EpgHarvesting,
+            reason: "adapter 0 frontend 0 is reserved for viewing".to_string(),
+        });
+    }
+
+    #[test]
+    fn epg_harvesting_gives_way_to_anything_else() {
+        let (reservations, events) = observed();
+        let preempted = Arc::new(Mutex::new(0));
+        let harvesting = reservations.reserve_yielding(&FEI, Purpose::EpgHarvesting, {
+            let preempted = preempted.clone();
+            move || *preempted.lock().unwrap() += 1
+        }).unwrap();
+        assert!(reservations.reserve(&FEI, Purpose::EpgHarvesting).is_err());
+        assert_eq!(*preempted.lock().unwrap(), 0);
+        let _viewing = reservations.reserve(&FEI, Purpose::Viewing).unwrap();
+        assert_eq!(*preempted.lock().unwrap(), 1);
+        // The preempted lease must not release the new reservation.
+        drop(harvesting);
+        assert_eq!(reservations.purpose_of(&FEI), Some(Purpose::Viewing));
+        assert_eq!(events.lock().unwrap()[2..], [
+            ReservationEvent::Released { fei: FEI, purpose: Purpose::EpgHarvesting },
+            ReservationEvent::Granted { fei: FEI, purpose: Purpose::Viewing },
+        ]);
+    }
+
+    #[test]
+    fn reservation_survives_a_brief_disappearance() {
+        let reservations = Reservations::new(Duration::from_secs(30));
+        let _lease = reservations.reserve(&FEI, Purpose::Viewing).unwrap();
+        let start = Instant::now();
+        reservations.frontend_disappeared(&FEI, start);
+        reservations.expire(start + Duration::from_secs(10));
+        reservations.frontend_appeared(&FEI);
+        reservations.expire(start + Duration::from_secs(60));
+        assert_eq!(reservations.purpose_of(&FEI), Some(Purpose::Viewing));
+    }
+
+    #[test]
+    fn reservation_expires_after_the_grace_period() {
+        let (reservations, events) = observed();
+        let lease = reservations.reserve(&FEI, Purpose::Viewing).unwrap();
+        let start = Instant::now();
+        reservations.frontend_disappeared(&FEI, start);
+        reservations.expire(start + Duration::from_secs(31));
+        assert_eq!(reservations.purpose_of(&FEI), None);
+        let _new_lease = reservations.reserve(&FEI, Purpose::EpgHarvesting).unwrap();
+        // The expired lease must not release the new reservation.
+        drop(lease);
+        assert_eq!(reservations.purpose_of(&FEI), Some(Purpose::EpgHarvesting));
+        assert_eq!(events.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn lease_with_lock_keeps_other_processes_off() {
+        let directory = tempfile::tempdir().unwrap();
+        let holder = LockHolder { pid: 1234, channel: "BBC NEWS".to_string(), end: "22:00".to_string() };
+        let first = Reservations::new(Duration::from_secs(30));
+        let other_process = Reservations::new(Duration::from_secs(30));
+        let lease = first.reserve_with_lock(&FEI, Purpose::Recording { channel: "BBC NEWS".to_string() }, directory.path(), &holder).unwrap();
+        match other_process.reserve_with_lock(&FEI, Purpose::Viewing, directory.path(), &LockHolder::default()) {
+            Err(Busy::Locked(LockError::InUse { holder: h, .. })) => assert_eq!(h, holder),
+            result => panic!("expected the frontend to be locked, got {:?}", result),
+        }
+        // The refused reservation is not left behind.
+        assert_eq!(other_process.purpose_of(&FEI), None);
+        drop(lease);
+        assert!(other_process.reserve_with_lock(&FEI, Purpose::Viewing, directory.path(), &LockHolder::default()).is_ok());
+    }
+}
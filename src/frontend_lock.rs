@@ -0,0 +1,211 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Advisory locks on frontends, so that two recordings do not try to use the same one.
+//!
+//! The lock is an flock(2) on a file named after the frontend. The kernel releases the
+//! lock however the holding process ends, so a file left behind by a process that was
+//! killed does not stop the frontend being used. The file records who holds the lock
+//! so that a recording finding the frontend in use can say by whom.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use libc;
+use xdg;
+
+use crate::frontends::FrontendId;
+
+/// The directory holding the lock files, in the XDG runtime directory if there is one.
+/// Jobs run from cron often have no XDG runtime directory, so the fallback is the
+/// temporary directory.
+pub fn lock_directory() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    match xdg_dirs.get_runtime_directory() {
+        Ok(directory) => directory.join("me-tv"),
+        Err(_) => env::temp_dir().join("me-tv"),
+    }
+}
+
+/// The path of the lock file for a frontend.
+pub fn lock_path(directory: &Path, fei: &FrontendId) -> PathBuf {
+    directory.join(format!("adapter{}-frontend{}.lock", fei.adapter, fei.frontend))
+}
+
+/// The details of the process holding a frontend lock.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub channel: String,
+    pub end: String,
+}
+
+impl LockHolder {
+    fn to_contents(&self) -> String {
+        format!("pid={}\nchannel={}\nend={}\n", self.pid, self.channel, self.end)
+    }
+
+    fn from_contents(contents: &str) -> LockHolder {
+        let mut holder = LockHolder::default();
+        for line in contents.lines() {
+            match line.split_at(line.find('=').unwrap_or(0)) {
+                ("pid", value) => holder.pid = value[1..].parse().unwrap_or(0),
+                ("channel", value) => holder.channel = value[1..].to_string(),
+                ("end", value) => holder.end = value[1..].to_string(),
+                _ => {},
+            }
+        }
+        holder
+    }
+}
+
+/// Why a frontend lock could not be taken.
+#[derive(Debug)]
+pub enum LockError {
+    InUse { fei: FrontendId, holder: LockHolder },
+    Io(io::Error),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockError::InUse { fei, holder } => write!(
+                f, "adapter {} frontend {} is in use by PID {} (channel {}, until {})",
+                fei.adapter, fei.frontend, holder.pid, holder.channel, holder.end,
+            ),
+            LockError::Io(e) => write!(f, "could not use the frontend lock file: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> LockError { LockError::Io(e) }
+}
+
+/// A held lock on a frontend, released when dropped.
+#[derive(Debug)]
+pub struct FrontendLock {
+    _file: File,  // Held open to hold the lock.
+    path: PathBuf,
+}
+
+impl FrontendLock {
+    /// Take the lock on a frontend, recording the holder in the lock file.
+    pub fn acquire(directory: &Path, fei: &FrontendId, holder: &LockHolder) -> Result<FrontendLock, LockError> {
+        fs::create_dir_all(directory)?;
+        let path = lock_path(directory, fei);
+        loop {
+            let mut file = OpenOptions::new().read(true).write(true).create(true).open(&path)?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let error = io::Error::last_os_error();
+                if error.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                    return Err(LockError::Io(error));
+                }
+                let mut contents = String::new();
+                file.read_to_string(&mut contents)?;
+                return Err(LockError::InUse { fei: fei.clone(), holder: LockHolder::from_contents(&contents) });
+            }
+            // The previous holder may have removed the file between it being opened and
+            // locked here, in which case the lock is on a file no-one else can see.
+            match fs::metadata(&path) {
+                Ok(metadata) if metadata.ino() == file.metadata()?.ino() => {},
+                _ => continue,
+            }
+            file.set_len(0)?;
+            file.write_all(holder.to_contents().as_bytes())?;
+            return Ok(FrontendLock { _file: file, path });
+        }
+    }
+}
+
+/// Who holds the lock on a frontend, if a process that is still running does. The lock
+/// is not tried, so looking cannot get in the way of a process taking it.
+pub fn holder_of(directory: &Path, fei: &FrontendId) -> Option<LockHolder> {
+    let holder = LockHolder::from_contents(&fs::read_to_string(lock_path(directory, fei)).ok()?);
+    if holder.pid != 0 && Path::new(&format!("/proc/{}", holder.pid)).exists() { Some(holder) } else { None }
+}
+
+impl Drop for FrontendLock {
+    fn drop(&mut self) {
+        // Remove the file while still holding the lock, closing the file releases it.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile;
+
+    fn holder() -> LockHolder {
+        LockHolder { pid: 1234, channel: "BBC NEWS".to_string(), end: "2020-10-14T22:00:00+01:00".to_string() }
+    }
+
+    #[test]
+    fn second_lock_reports_the_holder() {
+        let directory = tempfile::tempdir().unwrap();
+        let fei = FrontendId { adapter: 0, frontend: 0 };
+        let _lock = FrontendLock::acquire(directory.path(), &fei, &holder()).unwrap();
+        match FrontendLock::acquire(directory.path(), &fei, &LockHolder::default()) {
+            Err(e @ LockError::InUse { .. }) => assert_eq!(
+                e.to_string(),
+                "adapter 0 frontend 0 is in use by PID 1234 (channel BBC NEWS, until 2020-10-14T22:00:00+01:00)"
+            ),
+            result => panic!("expected the frontend to be in use, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn lock_can_be_taken_again_once_dropped() {
+        let directory = tempfile::tempdir().unwrap();
+        let fei = FrontendId { adapter: 4, frontend: 1 };
+        drop(FrontendLock::acquire(directory.path(), &fei, &holder()).unwrap());
+        assert!(!lock_path(directory.path(), &fei).exists());
+        assert!(FrontendLock::acquire(directory.path(), &fei, &holder()).is_ok());
+    }
+
+    #[test]
+    fn the_holder_of_a_lock_is_only_a_running_process() {
+        let directory = tempfile::tempdir().unwrap();
+        let fei = FrontendId { adapter: 2, frontend: 0 };
+        assert_eq!(holder_of(directory.path(), &fei), None);
+        let running = LockHolder { pid: std::process::id(), ..holder() };
+        let lock = FrontendLock::acquire(directory.path(), &fei, &running).unwrap();
+        assert_eq!(holder_of(directory.path(), &fei), Some(running));
+        drop(lock);
+        // A file left behind by a process that was killed.
+        fs::write(lock_path(directory.path(), &fei), LockHolder { pid: u32::MAX, ..holder() }.to_contents()).unwrap();
+        assert_eq!(holder_of(directory.path(), &fei), None);
+    }
+
+    #[test]
+    fn different_frontends_do_not_conflict() {
+        let directory = tempfile::tempdir().unwrap();
+        let _lock = FrontendLock::acquire(directory.path(), &FrontendId { adapter: 0, frontend: 0 }, &holder()).unwrap();
+        assert!(FrontendLock::acquire(directory.path(), &FrontendId { adapter: 0, frontend: 1 }, &holder()).is_ok());
+    }
+}
@@ -19,211 +19,1102 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::fs;
-use std::os::unix::fs::FileTypeExt;
-use std::path::PathBuf;
-use std::sync::mpsc::channel;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use glib;
+use libc;
 //use glib::prelude::*;
 
-use notify::{Watcher, RecursiveMode, RawEvent, op, raw_watcher};
-use regex::Regex;
+use crossbeam_channel::{after, never, select, tick, unbounded, Receiver};
 
+use lazy_static::lazy_static;
+
+use log::{debug, error, info, warn};
+
+use notify::{self, Watcher, RecursiveMode, RawEvent, op, raw_watcher};
+
+pub use me_tv::frontends::{FrontendHardware, FrontendId};
+pub use me_tv::frontend_info::{Availability, DeliverySystem, FrontendInfo};
+use me_tv::debounce::Debouncer;
+use me_tv::device_history::{DeviceChange, DeviceEvent, DeviceHistory};
+use me_tv::frontend_info::{availability_of, display_name, frontend_info_of, inaccessibility_reason_of, incompatibility_reason};
+pub use me_tv::frontend_lease::{Busy, Lease, Purpose, ReservationEvent};
+use me_tv::frontend_lease::Reservations;
+use me_tv::frontend_lock::{lock_directory, LockHolder};
+use me_tv::frontends::{DvbDevices, DvbNode, missing_devices_reason};
+#[cfg(feature = "udev-hotplug")]
+use me_tv::hotplug::{HotplugEvent, UdevMonitor};
+
+use crate::channels_data;
 use crate::control_window::Message;
 
-/// A struct to represent the identity of a specific frontend currently
-/// available on the system.
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct FrontendId {
-    pub adapter: u8,
-    pub frontend: u8,
+/// How often the frontends are checked to see if another process has started or stopped
+/// using them. Each check opens the frontend, so not too often.
+const AVAILABILITY_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often udev is looked at for events. The udev monitor cannot be waited on
+/// together with the channels.
+#[cfg(feature = "udev-hotplug")]
+const UDEV_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a reservation of a frontend survives the frontend disappearing, to ride out
+/// USB hiccups.
+const RESERVATION_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// What a run of the frontend manager knows of the frontends, and the reservations of
+/// them, shared by the threads of the run.
+struct ManagerState {
+    delivery_systems: Mutex<HashMap<FrontendId, Vec<DeliverySystem>>>,
+    availabilities: Mutex<HashMap<FrontendId, Availability>>,
+    // Kept after a frontend disappears, so that it can still be named.
+    display_names: Mutex<HashMap<FrontendId, String>>,
+    history: Mutex<DeviceHistory>,
+    to_control_window: Arc<Mutex<Option<ToControlWindow>>>,  // Whilst running.
+    reservations: Reservations,
 }
 
-/// The path in the filesystem to the DVB related special files.
-fn dvb_base_path() -> PathBuf { PathBuf::from("/dev/dvb") }
+impl ManagerState {
+    fn new() -> ManagerState {
+        let to_control_window = Arc::new(Mutex::new(None::<ToControlWindow>));
+        let reservations = Reservations::with_observer(RESERVATION_GRACE_PERIOD, {
+            let to_control_window = to_control_window.clone();
+            move |event| if let Some(to_cw) = &*to_control_window.lock().unwrap() {
+                tell(to_cw, Message::FrontendReservationChanged{event});
+            }
+        });
+        ManagerState {
+            delivery_systems: Mutex::new(HashMap::new()),
+            availabilities: Mutex::new(HashMap::new()),
+            display_names: Mutex::new(HashMap::new()),
+            history: Mutex::new(DeviceHistory::new()),
+            to_control_window,
+            reservations,
+        }
+    }
 
-/// Return the path to the adapter director for a given adapter.
-fn adapter_path(id: u8) -> PathBuf {
-    let mut result = dvb_base_path();
-    result.push("adapter".to_string() + &id.to_string());
-    result
+    /// The name to show the user for a frontend, the adapter and frontend numbers if it
+    /// has not been announced.
+    fn display_name(&self, fei: &FrontendId) -> String {
+        self.display_names.lock().unwrap().get(fei).cloned().unwrap_or_else(|| fei.to_string())
+    }
 }
 
-/// Return the path to the special file for a given frontend.
-fn frontend_path(fei: &FrontendId) -> PathBuf {
-    let mut result = adapter_path(fei.adapter);
-    result.push("frontend".to_string() + &fei.frontend.to_string());
-    result
+lazy_static! {
+    // That of the frontend manager last started, for the GUI to ask about the frontends.
+    static ref CURRENT: Mutex<Arc<ManagerState>> = Mutex::new(Arc::new(ManagerState::new()));
 }
 
-/// Return the path to the special file of the demux for a given frontend.
-fn demux_path(fei: &FrontendId) -> PathBuf {
-    let mut result = adapter_path(fei.adapter);
-    result.push("demux".to_string() + &fei.frontend.to_string());
-    result
+/// The state of the frontend manager last started, or an empty one if none has been.
+fn current() -> Arc<ManagerState> {
+    CURRENT.lock().unwrap().clone()
 }
 
-/// Return the path to the special file of the data for a given frontend.
-fn dvr_path(fei: &FrontendId) -> PathBuf {
-    let mut result = adapter_path(fei.adapter);
-    result.push("dvr".to_string() + &fei.frontend.to_string());
-    result
+/// What the GUI can ask of the frontend manager.
+#[derive(Debug)]
+pub enum Command {
+    /// Stop following the frontends, tell the control window, and return from `run`.
+    Shutdown,
 }
 
-/// Search for any adapters already installed on start of the application.
+/// The way to send messages to the control window, which remembers if the control
+/// window has gone, as it does as the application quits, so that the frontend manager
+/// can stop.
+#[derive(Clone)]
+struct ToControlWindow {
+    sender: glib::Sender<Message>,
+    is_gone: Arc<AtomicBool>,
+}
+
+impl ToControlWindow {
+    fn new(sender: glib::Sender<Message>) -> ToControlWindow {
+        ToControlWindow{sender, is_gone: Arc::new(AtomicBool::new(false))}
+    }
+
+    /// Whether a message could not be sent as the control window has gone.
+    fn is_gone(&self) -> bool { self.is_gone.load(Ordering::SeqCst) }
+}
+
+/// Send a message to the control window, dropping it if the control window has gone.
+fn tell(to_cw: &ToControlWindow, message: Message) {
+    if to_cw.sender.send(message).is_err() && !to_cw.is_gone.swap(true, Ordering::SeqCst) {
+        debug!("The control window has gone, so the frontend manager is stopping.");
+    }
+}
+
+/// What the frontend manager has been woken up by.
+enum Wakeup<E> {
+    /// An event from the hotplug monitor.
+    Event(E),
+    /// The GUI has asked for shutdown, or gone, or the hotplug monitor has stopped.
+    Stop,
+    /// It is time to check the availability of the frontends and expire reservations.
+    Tick,
+    /// A device may be quiet.
+    Quiet,
+}
+
+/// Wait for whichever comes first of a command from the GUI, an event on `events`,
+/// `ticks`, and the next device in `debouncer` becoming quiet, so that a shutdown is
+/// acted on at once whatever is being waited for.
+fn next_wakeup<E, K: Clone + Ord>(from_gui: &Receiver<Command>, events: &Receiver<E>, ticks: &Receiver<Instant>, debouncer: &Debouncer<K>) -> Wakeup<E> {
+    let quiet = debouncer.time_to_next_quiet(Instant::now()).map_or_else(never, after);
+    select! {
+        recv(from_gui) -> command => match command {
+            Ok(Command::Shutdown) | Err(_) => Wakeup::Stop,
+        },
+        recv(events) -> event => match event {
+            Ok(event) => Wakeup::Event(event),
+            Err(_) => Wakeup::Stop,
+        },
+        recv(ticks) -> _ => Wakeup::Tick,
+        recv(quiet) -> _ => Wakeup::Quiet,
+    }
+}
+
+/// The name to show the user for a frontend that has been announced, even if it has
+/// since gone, the adapter and frontend numbers if it has not been.
+pub fn frontend_display_name(fei: &FrontendId) -> String {
+    current().display_name(fei)
+}
+
+/// The frontends that have appeared and disappeared since the frontend manager started,
+/// as many of the most recent as are kept.
+pub fn device_history() -> DeviceHistory {
+    current().history.lock().unwrap().clone()
+}
+
+/// Add a frontend appearing or disappearing to the history, and update the history file
+/// `history_path` for me-tv-record to look at when a recording fails.
+fn record_device_event(state: &ManagerState, history_path: &Path, fei: &FrontendId, change: DeviceChange) {
+    let name = state.display_name(fei);
+    let mut history = state.history.lock().unwrap();
+    history.record(DeviceEvent{time: chrono::Local::now(), change, fei: fei.clone(), name});
+    if let Err(e) = history.write(history_path) {
+        debug!("Could not write the device history file: {}", e);
+    }
+}
+
+/// Reserve a frontend for a purpose. Viewing, recording and EPG harvesting in Me TV all
+/// reserve the frontend they use, so that they do not contend for it. The control window
+/// is told of reservations granted, refused, and released.
+pub fn reserve(fei: &FrontendId, purpose: Purpose) -> Result<Lease, Busy> {
+    current().reservations.reserve(fei, purpose)
+}
+
+/// Reserve a frontend for EPG harvesting, giving it up to anything else that wants it,
+/// `on_preempted` being called as it is.
+pub fn reserve_yielding(fei: &FrontendId, on_preempted: impl FnOnce() + Send + 'static) -> Result<Lease, Busy> {  // Used in epg_harvester.rs.
+    current().reservations.reserve_yielding(fei, Purpose::EpgHarvesting, on_preempted)
+}
+
+/// The frontends that are present, available, and not reserved for anything.
+pub fn idle_frontends() -> Vec<FrontendId> {  // Used in epg_harvester.rs.
+    let state = current();
+    let mut feis = state.availabilities.lock().unwrap().iter()
+        .filter(|(_, availability)| **availability == Availability::Available)
+        .map(|(fei, _)| fei.clone())
+        .filter(|fei| state.reservations.purpose_of(fei).is_none())
+        .collect::<Vec<_>>();
+    feis.sort();
+    feis
+}
+
+/// Reserve a frontend for scanning, taking the frontend lock as well so that me-tv-record
+/// keeps off it until the scan is finished.
+pub fn reserve_for_scanning(fei: &FrontendId) -> Result<Lease, Busy> {  // Used in scan_dialog.rs.
+    let holder = LockHolder{pid: std::process::id(), channel: "scanning".to_string(), end: String::new()};
+    current().reservations.reserve_with_lock(fei, Purpose::Scanning, &lock_directory(), &holder)
+}
+
+/// The delivery systems supported by each frontend currently present whose capabilities
+/// are known.
+pub fn delivery_systems() -> HashMap<FrontendId, Vec<DeliverySystem>> {
+    current().delivery_systems.lock().unwrap().clone()
+}
+
+/// Why a frontend cannot tune a channel, according to the delivery system of the channel
+/// in its channels file and the delivery systems of the frontends, or None if it can or
+/// either is not known.
+pub fn channel_incompatibility(fei: &FrontendId, channel_name: &str) -> Option<String> {
+    let required = channels_data::get_delivery_system(channel_name)?;
+    incompatibility_reason(fei, required, &current().delivery_systems.lock().unwrap())
+}
+
+/// Whether a frontend can receive channels of a delivery system, true if either the
+/// delivery system or the capabilities of the frontend are not known.
+pub fn can_receive(fei: &FrontendId, delivery_system: Option<DeliverySystem>) -> bool {
+    match delivery_system {
+        Some(required) => incompatibility_reason(fei, required, &current().delivery_systems.lock().unwrap()).is_none(),
+        None => true,
+    }
+}
+
+/// Announce a frontend to the GUI, with its capabilities if they can be found.
 ///
-/// Inform the GUI and the remote control manager of the presence of
-/// any adaptors and frontends.
-pub fn add_already_installed_adaptors(to_cw: &mut glib::Sender<Message>) {
-    if dvb_base_path().is_dir() {
-        let mut adapter_number = 0;
-        loop {
-            if adapter_path(adapter_number).is_dir() {
-                let mut fei = FrontendId{adapter: adapter_number, frontend: 0};
-                loop {
-                    // TODO Is it worth doing the check for special file or just check for existence.
-                    let path = frontend_path(&fei);
-                    match fs::metadata(&path) {
-                        Ok(m) => {
-                            // NB m.is_file() is false for special files. :-(
-                            // Assume the special devices were are dealing with are
-                            // character devices not block devices.
-                            if m.file_type().is_char_device() {
-                                to_cw.send(Message::FrontendAppeared{fei: fei.clone()}).unwrap();
-                            }
-                        },
-                        Err(_) => break,
-                    };
-                    fei.frontend += 1;
-                }
+/// A frontend in use by another process may refuse to be opened; it is still announced,
+/// just with its capabilities unknown. A frontend the user does not have permission to
+/// use is announced as such as well, so the GUI can say why rather than let tuning fail.
+fn announce_frontend(state: &ManagerState, to_cw: &ToControlWindow, devices: &DvbDevices, history_path: &Path, fei: FrontendId, hardware: FrontendHardware) {
+    let path = devices.frontend_path(&fei);
+    let reason = inaccessibility_reason_of(&path);
+    let info = match frontend_info_of(&path) {
+        Ok(info) => {
+            state.delivery_systems.lock().unwrap().insert(fei.clone(), info.delivery_systems.clone());
+            Some(info)
+        },
+        Err(e) => {
+            if reason.is_some() {
+                // Reported separately below.
+            } else if e.raw_os_error() == Some(libc::EBUSY) {
+                info!("adapter{}/frontend{} is busy, its capabilities are unknown.", fei.adapter, fei.frontend);
             } else {
-                break;
+                warn!("Could not get the capabilities of adapter{}/frontend{}: {}", fei.adapter, fei.frontend, e);
             }
-            adapter_number += 1;
+            None
+        },
+    };
+    let availability = availability_of(&path);
+    state.availabilities.lock().unwrap().insert(fei.clone(), availability);
+    state.reservations.frontend_appeared(&fei);
+    let display_name = display_name(&fei, info.as_ref(), &hardware);
+    state.display_names.lock().unwrap().insert(fei.clone(), display_name.clone());
+    record_device_event(state, history_path, &fei, DeviceChange::Appeared);
+    tell(to_cw, Message::FrontendAppeared{fei: fei.clone(), info, hardware, display_name, availability});
+    if let Some(reason) = reason {
+        warn!("{}", reason);
+        tell(to_cw, Message::FrontendInaccessible{fei, reason});
+    }
+}
+
+/// Check the availability of each frontend present in `devices`, telling the GUI of any
+/// that have changed since they were last checked.
+///
+/// The frontends are opened without the availabilities locked, as opening one can take
+/// a while, so a frontend that has gone in the meantime is left gone.
+///
+/// Me TV viewing on a frontend makes it in use as far as this check is concerned, it
+/// is for the GUI to know which frontends it is using itself.
+fn poll_availabilities(state: &ManagerState, to_cw: &ToControlWindow, devices: &DvbDevices) {
+    let feis = state.availabilities.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+    for fei in feis {
+        let current = availability_of(&devices.frontend_path(&fei));
+        let is_changed = match state.availabilities.lock().unwrap().get_mut(&fei) {
+            Some(previous) if *previous != current => {
+                *previous = current;
+                true
+            },
+            _ => false,
+        };
+        if is_changed {
+            tell(to_cw, Message::FrontendAvailabilityChanged{fei, availability: current});
+        }
+    }
+}
+
+/// Search for any adapters already installed on start of the application.
+///
+/// Inform the GUI and the remote control manager of the presence of
+/// any adaptors and frontends. Frontends without their demux and dvr special files
+/// cannot be used so are not announced.
+fn add_already_installed_adaptors(state: &ManagerState, to_cw: &ToControlWindow, devices: &DvbDevices, history_path: &Path) {
+    for fei in devices.installed_frontends() {
+        let missing = devices.missing_devices(&fei);
+        if !missing.is_empty() {
+            warn!("{}", missing_devices_reason(&fei, &missing));
+            continue;
         }
+        let hardware = devices.frontend_hardware(&fei);
+        announce_frontend(state, to_cw, devices, history_path, fei, hardware);
     }
 }
 
-// TODO Find out how to fix this problem of notification of the frontendX files..
-//
 // Experimental evidence from Fedora Rawhide indicates that some USB DVB devices
 // (the newer ones) do not give an event for creating the frontend file.
 // Some USB devices do not give an event for the demux device. All seem to give events
-// for the dvr and net devices. All file removes are notified.
+// for the dvr and net devices. All file removes are notified. So any creation within an
+// adapter directory is taken as the cue to look for the frontends of that adapter.
+
+/// Report the frontends of an adapter that has just appeared as they become usable, not
+/// reporting any already reported.
+fn add_appearing_adapter(state: &ManagerState, to_cw: &ToControlWindow, devices: &DvbDevices, history_path: &Path, adapter: u8, timeout: Duration) {
+    let reported = devices.wait_for_frontends(
+        adapter,
+        timeout,
+        |fei| availability_of(&devices.frontend_path(fei)) != Availability::Unknown,
+        |fei| if !state.availabilities.lock().unwrap().contains_key(fei) {
+            announce_frontend(state, to_cw, devices, history_path, fei.clone(), devices.frontend_hardware(fei));
+        },
+    );
+    for fei in devices.adapter_frontends(adapter) {
+        let missing = devices.missing_devices(&fei);
+        if !reported.contains(&fei) && !missing.is_empty() {
+            warn!("{}", missing_devices_reason(&fei, &missing));
+        }
+    }
+    if reported.is_empty() && devices.is_adapter_present(adapter) {
+        warn!("adapter{} appeared but no usable frontends were found within {} seconds.", adapter, timeout.as_secs_f32());
+    }
+}
+
+/// Tell the GUI a frontend has gone, forgetting what is known about it, unless it was
+/// not known.
+fn remove_frontend(state: &ManagerState, to_cw: &ToControlWindow, history_path: &Path, fei: FrontendId) {
+    if state.availabilities.lock().unwrap().remove(&fei).is_none() { return; }
+    state.delivery_systems.lock().unwrap().remove(&fei);
+    state.reservations.frontend_disappeared(&fei, Instant::now());
+    record_device_event(state, history_path, &fei, DeviceChange::Disappeared);
+    tell(to_cw, Message::FrontendDisappeared{fei});
+}
+
+/// Bring what the GUI has been told about the frontends of adapter `adapter` up to
+/// date with what is there: those gone are removed, and those new reported as they
+/// become usable.
+fn reconcile_adapter(state: &ManagerState, to_cw: &ToControlWindow, devices: &DvbDevices, history_path: &Path, adapter: u8, timeout: Duration) {
+    let present = devices.adapter_frontends(adapter);
+    let gone = state.availabilities.lock().unwrap().keys()
+        .filter(|fei| fei.adapter == adapter && !present.contains(fei))
+        .cloned()
+        .collect::<Vec<_>>();
+    for fei in gone {
+        remove_frontend(state, to_cw, history_path, fei);
+    }
+    if devices.is_adapter_present(adapter) {
+        add_appearing_adapter(state, to_cw, devices, history_path, adapter, timeout);
+    }
+}
+
+/// Bring what the GUI has been told about frontend `fei` up to date with its special
+/// files: it is removed if any have gone, and reported once they are all there and it
+/// is usable, waiting up to `timeout`. The rest of the adapter is not looked at.
+fn reconcile_frontend(state: &ManagerState, to_cw: &ToControlWindow, devices: &DvbDevices, history_path: &Path, fei: FrontendId, timeout: Duration) {
+    if state.availabilities.lock().unwrap().contains_key(&fei) {
+        let missing = devices.missing_devices(&fei);
+        if !missing.is_empty() {
+            if !missing.contains(&devices.frontend_path(&fei)) {
+                warn!("{}", missing_devices_reason(&fei, &missing));
+            }
+            remove_frontend(state, to_cw, history_path, fei);
+        }
+        return;
+    }
+    let missing = devices.wait_for_devices(&fei, timeout);
+    if !missing.is_empty() {
+        if !missing.contains(&devices.frontend_path(&fei)) {
+            warn!("{}", missing_devices_reason(&fei, &missing));
+        }
+        return;
+    }
+    if availability_of(&devices.frontend_path(&fei)) == Availability::Unknown {
+        warn!("{} appeared but cannot be used.", fei);
+        return;
+    }
+    let hardware = devices.frontend_hardware(&fei);
+    announce_frontend(state, to_cw, devices, history_path, fei, hardware);
+}
+
+/// The adapters present and those with frontends the GUI has been told of, which are
+/// not present if the DVB directory has gone, as the DVB modules being unloaded makes it.
+fn adapters_present_or_known(state: &ManagerState, devices: &DvbDevices) -> BTreeSet<u8> {
+    let mut adapters = devices.adapters().into_iter().collect::<BTreeSet<_>>();
+    adapters.extend(state.availabilities.lock().unwrap().keys().map(|fei| fei.adapter));
+    adapters
+}
+
+/// The adapters or frontends whose changes are being dealt with, each in a thread of its
+/// own so that waiting for one to become usable does not hold up the others.
+struct InFlight<K>(Arc<Mutex<HashSet<K>>>);
+
+impl<K: Clone + Eq + Hash + Send + 'static> InFlight<K> {
+    fn new() -> InFlight<K> { InFlight(Arc::new(Mutex::new(HashSet::new()))) }
+
+    /// Do `work` for `key` in a thread of its own, unless work for `key` is still being
+    /// done, in which case false is returned.
+    fn spawn(&self, key: K, work: impl FnOnce() + Send + 'static) -> bool {
+        if !self.0.lock().unwrap().insert(key.clone()) { return false; }
+        let in_flight = self.0.clone();
+        thread::spawn(move || {
+            work();
+            in_flight.lock().unwrap().remove(&key);
+        });
+        true
+    }
+}
+
+/// Do the periodic checks.
+fn do_periodic_checks(state: &ManagerState, to_cw: &ToControlWindow, devices: &DvbDevices) {
+    poll_availabilities(state, to_cw, devices);
+    state.reservations.expire(Instant::now());
+}
 
-/// Ensure the name is adaptorXXX /frontendYYY where XXX and YYY are pure numeric,
-/// and return a `FrontendId` based on these numbers.
-fn frontend_id_from(path: &str) -> Option<FrontendId> {
-    let regex = Regex::new(r"/dev/dvb/adapter([0-9]+)/frontend([0-9]+)").unwrap();
-    if regex.is_match(&path) {
-        let captures = regex.captures(path).unwrap();
-        let adapter_number = u8::from_str_radix(&captures[1], 10).unwrap();
-        let frontend_number= u8::from_str_radix(&captures[2], 10).unwrap();
-        Some(FrontendId{adapter: adapter_number, frontend: frontend_number})
-    } else {
-        None
+/// How frontends appearing and disappearing are noticed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HotplugMonitor {
+    /// udev events for the dvb subsystem, which come after udev has created the special
+    /// files and include the names of the hardware. Only if built with the udev-hotplug
+    /// feature.
+    Udev,
+    /// inotify on /dev, which works without udev.
+    Inotify,
+}
+
+/// Follow the frontends using udev until the GUI asks for shutdown.
+///
+/// Only the last event for a frontend is acted on, once it has had no events for
+/// `debounce_window`, so a frontend flapping in and out ends up as it was left. udev
+/// announces the frontend, demux and dvr special files separately, so a frontend is only
+/// announced once the others are there, waiting up to `hotplug_timeout` for them.
+#[cfg(feature = "udev-hotplug")]
+fn watch_with_udev(state: &Arc<ManagerState>, to_cw: &ToControlWindow, from_gui: &Receiver<Command>, devices: &DvbDevices, history_path: &Path, hotplug_timeout: Duration, debounce_window: Duration, mut monitor: UdevMonitor) {
+    let ticks = tick(AVAILABILITY_POLL_INTERVAL);
+    let udev_polls = tick(UDEV_POLL_INTERVAL);
+    let mut debouncer = Debouncer::new(debounce_window);
+    let mut last_events = HashMap::new();
+    let in_flight = InFlight::new();
+    while !to_cw.is_gone() {
+        match next_wakeup(from_gui, &udev_polls, &ticks, &debouncer) {
+            Wakeup::Event(_) => while let Some(event) = monitor.next_event(Duration::from_secs(0)) {
+                let fei = match &event {
+                    HotplugEvent::Added{fei, ..} | HotplugEvent::Removed{fei} => fei.clone(),
+                };
+                debouncer.record(fei.clone(), Instant::now());
+                last_events.insert(fei, event);
+            },
+            Wakeup::Stop => break,
+            Wakeup::Tick => do_periodic_checks(state, to_cw, devices),
+            Wakeup::Quiet => {},
+        }
+        for fei in debouncer.take_quiet(Instant::now()) {
+            let event = last_events[&fei].clone();
+            let is_spawned = in_flight.spawn(fei.clone(), {
+                let state = state.clone();
+                let to_cw = to_cw.clone();
+                let devices = devices.clone();
+                let history_path = history_path.to_path_buf();
+                move || match event {
+                    HotplugEvent::Added{fei, hardware} => if !state.availabilities.lock().unwrap().contains_key(&fei) {
+                        let missing = devices.wait_for_devices(&fei, hotplug_timeout);
+                        if missing.is_empty() {
+                            announce_frontend(&state, &to_cw, &devices, &history_path, fei, hardware);
+                        } else {
+                            warn!("{}", missing_devices_reason(&fei, &missing));
+                        }
+                    },
+                    HotplugEvent::Removed{fei} => remove_frontend(&state, &to_cw, &history_path, fei),
+                }
+            });
+            if is_spawned {
+                last_events.remove(&fei);
+            } else {
+                // Try again once the earlier change has been dealt with.
+                debouncer.record(fei, Instant::now());
+            }
+        }
+    }
+}
+
+/// What in the DVB directory has had events.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum Changed {
+    DvbDirectory,
+    Adapter(u8),
+    Frontend(FrontendId),
+}
+
+/// A special file or directory of the DVB devices being created or removed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceNodeEvent {
+    Created(PathBuf),
+    Removed(PathBuf),
+}
+
+/// Where the frontend manager gets the creation and removal of the DVB special files
+/// from: inotify, or a script of them in the tests, which cannot have real devices.
+pub trait DeviceNodeEvents {
+    /// The events, disconnected once there will be no more.
+    fn receiver(&self) -> &Receiver<DeviceNodeEvent>;
+}
+
+/// The DVB special files being created and removed, as inotify sees it.
+///
+/// inotify is on the directory containing the DVB directory, /dev unless the DVB
+/// special files are elsewhere, so that the DVB directory appearing is seen. The DVB
+/// directory does not exist until the DVB modules are loaded, which may be when the
+/// first adapter is plugged in, after Me TV has started. It goes again when the modules
+/// are unloaded, or udev removes it with the last adapter; watching its parent means it
+/// coming back is seen. The recursive watch covers each adapter directory as it
+/// appears, and is dropped when it goes.
+pub struct InotifyEvents {
+    _watcher: notify::RecommendedWatcher,  // Watching for as long as this exists.
+    events: Receiver<DeviceNodeEvent>,
+}
+
+impl InotifyEvents {
+    pub fn new(devices: &DvbDevices) -> Result<InotifyEvents, String> {
+        // notify only sends to a std channel, so its events are forwarded to one that can be
+        // waited on together with the others. Unbounded since blocking would hold up notify
+        // reading the inotify events, which the kernel drops if they are not read; a burst is
+        // short lived, each event being handled in a moment.
+        let (transmit_end, receive_end) = mpsc::channel();
+        let (to_manager, events) = unbounded();
+        thread::spawn(move || {
+            for event in receive_end {
+                let event = match event {
+                    RawEvent{path: Some(path), op: Ok(op::CREATE), cookie: _} => DeviceNodeEvent::Created(path),
+                    RawEvent{path: Some(path), op: Ok(op::REMOVE), cookie: _} => DeviceNodeEvent::Removed(path),
+                    RawEvent{path: Some(_), op: Ok(_), cookie: _} => continue,
+                    RawEvent{path: _, op: Err(e), cookie: _} => { warn!("frontend_manager::run: watch error: {:?}", e); continue; },
+                    event => { warn!("frontend_manager::run: broken event: {:?}", event); continue; },
+                };
+                if to_manager.send(event).is_err() { break; }
+            }
+        });
+        let mut watcher = raw_watcher(transmit_end).unwrap();
+        //  A simple:
+        //
+        //    watcher.watch("/dev", RecursiveMode::Recursive).unwrap();
+        //
+        //  used to work for everyone. However, as per, https://github.com/Me-TV/Me-TV/issues/36
+        //  and https://github.com/Me-TV/Me-TV/issues/37 there are now reported problems.
+        //  This indicates that there is genuine risk of failure so using unwrap is not appropriate,
+        //  despite the notify documentation. It is not clear why the problem only just appears
+        //  now, nor is there a real indication of what the actual problem is.
+        //
+        //  TODO How to monitor in the face of permission error?
+        let watched = devices.base().parent().map_or_else(|| PathBuf::from("/dev"), Path::to_path_buf);
+        match watcher.watch(&watched, RecursiveMode::Recursive) {
+            Ok(_) => Ok(InotifyEvents{_watcher: watcher, events}),
+            Err(e) => Err(format!("Watch on {} failed: {:?}", watched.display(), e)),  // TODO How to set up the watcher rather than terminate the daemon.
+        }
+    }
+}
+
+impl DeviceNodeEvents for InotifyEvents {
+    fn receiver(&self) -> &Receiver<DeviceNodeEvent> { &self.events }
+}
+
+/// Follow the frontends using the DVB special files being created and removed, until
+/// the GUI asks for shutdown or there are no more events.
+///
+/// The events are for individual special files, so a frontend, rather than the whole
+/// adapter, is looked at when just its frontend, demux or dvr special file is created
+/// or removed, as when the driver of one frontend of a multi-standard card is reloaded.
+/// The DVB directory being created or removed has all the adapters looked at.
+///
+/// A frontend or adapter is looked at once it has had no events for `debounce_window`,
+/// so that only the net result of a flaky adapter disconnecting and reconnecting
+/// repeatedly is reported.
+///
+/// `hotplug_timeout` is how long to wait for the frontends of a newly plugged in
+/// adapter to become usable.
+fn watch_device_nodes(state: &Arc<ManagerState>, to_cw: &ToControlWindow, from_gui: &Receiver<Command>, devices: &DvbDevices, history_path: &Path, events: &dyn DeviceNodeEvents, hotplug_timeout: Duration, debounce_window: Duration) {
+    let ticks = tick(AVAILABILITY_POLL_INTERVAL);
+    let mut debouncer = Debouncer::new(debounce_window);
+    let in_flight = InFlight::new();
+    while !to_cw.is_gone() {
+        match next_wakeup(from_gui, events.receiver(), &ticks, &debouncer) {
+            Wakeup::Event(event) => {
+                let (path, is_removed) = match &event {
+                    DeviceNodeEvent::Created(path) => (path, false),
+                    DeviceNodeEvent::Removed(path) => (path, true),
+                };
+                match devices.node_from(path) {
+                    // The watch on a new DVB directory is only added once its
+                    // creation is noticed, by which time the adapter directories may
+                    // have been created in it unseen.
+                    Some(DvbNode::Directory) => {
+                        if is_removed {
+                            info!("{} has been removed, waiting for it to be created again.", devices.base().display());
+                        }
+                        debouncer.record(Changed::DvbDirectory, Instant::now());
+                    },
+                    Some(DvbNode::Adapter(adapter)) | Some(DvbNode::AdapterNode(adapter)) => debouncer.record(Changed::Adapter(adapter), Instant::now()),
+                    Some(DvbNode::FrontendNode(fei)) => debouncer.record(Changed::Frontend(fei), Instant::now()),
+                    None => {},
+                }
+            },
+            Wakeup::Stop => break,
+            Wakeup::Tick => do_periodic_checks(state, to_cw, devices),
+            Wakeup::Quiet => {},
+        }
+        for changed in debouncer.take_quiet(Instant::now()) {
+            let changes = match changed {
+                Changed::DvbDirectory => adapters_present_or_known(state, devices).into_iter().map(Changed::Adapter).collect(),
+                changed => vec![changed],
+            };
+            for changed in changes {
+                // Changes to an adapter are dealt with one at a time, so that a
+                // frontend is not reported twice.
+                let adapter = match &changed {
+                    Changed::Adapter(adapter) => *adapter,
+                    Changed::Frontend(fei) => fei.adapter,
+                    Changed::DvbDirectory => unreachable!("the DVB directory is dealt with as its adapters"),
+                };
+                let is_spawned = in_flight.spawn(adapter, {
+                    let state = state.clone();
+                    let to_cw = to_cw.clone();
+                    let devices = devices.clone();
+                    let history_path = history_path.to_path_buf();
+                    let changed = changed.clone();
+                    move || match changed {
+                        Changed::Frontend(fei) => reconcile_frontend(&state, &to_cw, &devices, &history_path, fei, hotplug_timeout),
+                        _ => reconcile_adapter(&state, &to_cw, &devices, &history_path, adapter, hotplug_timeout),
+                    }
+                });
+                if !is_spawned {
+                    // Look again once the earlier change has been dealt with.
+                    debouncer.record(changed, Instant::now());
+                }
+            }
+        }
     }
 }
 
 /// The main dæmon for adapter/frontend management.
 ///
 /// Distributes "appeared" and "disappeared" messages to the GUI whenever an
-/// adaptor/frontend state changes.
+/// adaptor/frontend state changes, and "availability changed" messages whenever
+/// another process starts or stops using a frontend.
 ///
 /// Remote controls in the adapters are handled separately, as the kernel deals with
 /// them differently. A separate daemon is spawned for this that then sends messages to
 /// the GUI as needed.
-pub fn run(mut to_cw: glib::Sender<Message>) {
-    add_already_installed_adaptors(&mut to_cw);
-    let (transmit_end, receive_end) = channel();
-    let mut watcher = raw_watcher(transmit_end).unwrap();
-    //  A simple:
-    //
-    //    watcher.watch("/dev", RecursiveMode::Recursive).unwrap();
-    //
-    //  used to work for everyone. However, as per, https://github.com/Me-TV/Me-TV/issues/36
-    //  and https://github.com/Me-TV/Me-TV/issues/37 there are now reported problems.
-    //  This indicates that there is genuine risk of failure so using unwrap is not appropriate,
-    //  despite the notify documentation. It is not clear why the problem only just appears
-    //  now, nor is there a real indication of what the actual problem is.
-    //
-    //  TODO How to monitor in the face of permission error?
-    match watcher.watch("/dev", RecursiveMode::Recursive) {
-        Ok(_) => loop {
-            match receive_end.recv() {
-                Ok(RawEvent{path: Some(path), op: Ok(op), cookie: _cookie}) => {
-                    match op {
-                        op::CREATE => {
-                            let path = path.to_str().unwrap();
-                            // Hack because of the lack of certainty that the frontend notifies.
-                            if path.contains("dvb") && path.contains("adapter") && path.contains("dvr") {
-                            let path = path.replace("dvr", "frontend");
-                                if let Some(fei) = frontend_id_from(&path) {
-                                    to_cw.send(Message::FrontendAppeared{fei: fei.clone()}).unwrap();
-                                }
-                            }
-                        },
-                        op::REMOVE => {
-                        let path = path.to_str().unwrap();
-                            if path.contains("dvb") && path.contains("adapter") && path.contains("frontend") {
-                                if let Some(fei) = frontend_id_from(&path) {
-                                    to_cw.send(Message::FrontendDisappeared{fei: fei.clone()}).unwrap();
-                                }
-                            }
-                        },
-                        _ => {},
-                    }
-                },
-                Ok(event) => println!("frontend_manager::run: broken event: {:?}", event),
-                Err(e) => println!("frontend_manager::run: watch error: {:?}", e),
-            }
-        },
-        Err(e) => println!("Watch on /dev/ failed: {:?}", e),  // TODO How to set up the watcher rather than terminate the daemon.
+///
+/// `devices` is where the DVB devices are, and `history_path` the file the frontends
+/// appearing and disappearing are written to. `hotplug_monitor` is how to notice frontends appearing and disappearing, inotify
+/// being used if udev cannot be. `hotplug_timeout` is how long to wait for the frontends
+/// of a newly plugged in adapter, and their demux and dvr special files, to become usable.
+/// `debounce_window` is how long an adapter must have had no events before its frontends
+/// are looked at.
+///
+/// Returns as soon as `Command::Shutdown` is received on `from_gui`, or `from_gui` is
+/// disconnected, or if the DVB special files cannot be watched, or once a message cannot be sent on `to_cw`. Any event in hand is
+/// finished, then the control window is sent `Message::FrontendManagerStopped`. Adapters
+/// being waited for are left to their threads, which finish within `hotplug_timeout`.
+///
+/// `to_cw` is a glib channel rather than a crossbeam one as it is the GTK main loop that
+/// receives from it.
+///
+/// What each run knows of the frontends, and their reservations, is its own; the
+/// functions the GUI asks about the frontends with use those of the run last started.
+pub fn run(to_cw: glib::Sender<Message>, from_gui: Receiver<Command>, devices: DvbDevices, history_path: PathBuf, hotplug_monitor: HotplugMonitor, hotplug_timeout: Duration, debounce_window: Duration) {
+    let to_cw = ToControlWindow::new(to_cw);
+    let state = Arc::new(ManagerState::new());
+    *CURRENT.lock().unwrap() = state.clone();
+    start(&state, &to_cw, &devices, &history_path);
+    if hotplug_monitor == HotplugMonitor::Udev {
+        #[cfg(feature = "udev-hotplug")]
+        match UdevMonitor::new() {
+            Ok(monitor) => {
+                watch_with_udev(&state, &to_cw, &from_gui, &devices, &history_path, hotplug_timeout, debounce_window, monitor);
+                stop(&state, &to_cw);
+                return;
+            },
+            Err(e) => warn!("Cannot monitor udev, {}, using inotify instead.", e),
+        }
+        #[cfg(not(feature = "udev-hotplug"))]
+        warn!("Me TV was built without the udev-hotplug feature, using inotify instead.");
     }
-    println!("Frontend Manager terminated.");
+    match InotifyEvents::new(&devices) {
+        Ok(events) => watch_device_nodes(&state, &to_cw, &from_gui, &devices, &history_path, &events, hotplug_timeout, debounce_window),
+        Err(e) => error!("{}", e),
+    }
+    stop(&state, &to_cw);
+}
+
+/// Start telling the control window about reservations, and about the frontends already
+/// there, writing them to the history file `history_path`.
+fn start(state: &ManagerState, to_cw: &ToControlWindow, devices: &DvbDevices, history_path: &Path) {
+    state.to_control_window.lock().unwrap().replace(to_cw.clone());
+    add_already_installed_adaptors(state, to_cw, devices, history_path);
+}
+
+/// Stop telling the control window about reservations, and tell it the frontend
+/// manager is no more.
+fn stop(state: &ManagerState, to_cw: &ToControlWindow) {
+    state.to_control_window.lock().unwrap().take();
+    tell(to_cw, Message::FrontendManagerStopped);
+    info!("Frontend Manager terminated.");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use quickcheck::quickcheck;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+    use std::thread;
+
+    /// Run the frontend manager on the DVB directory dvb in `directory`, which need not
+    /// exist, with the device history file in `directory` too, returning the way to command it, the thread running it, and its messages to
+    /// the control window delivered to `context`.
+    fn start_manager(context: &glib::MainContext, directory: &tempfile::TempDir) -> (crossbeam_channel::Sender<Command>, thread::JoinHandle<()>, Rc<RefCell<Vec<Message>>>) {
+        let base = directory.path().join("dvb");
+        let history_path = directory.path().join("device-events");
+        let (to_cw, from_manager) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        from_manager.attach(Some(context), {
+            let received = received.clone();
+            move |message| {
+                received.borrow_mut().push(message);
+                glib::Continue(true)
+            }
+        });
+        let (to_manager, from_gui) = unbounded();
+        let manager = thread::spawn(move || run(to_cw, from_gui, DvbDevices::new(base), history_path, HotplugMonitor::Inotify, Duration::from_secs(1), Duration::from_millis(500)));
+        (to_manager, manager, received)
+    }
+
+    #[test]
+    fn manager_stops_soon_after_shutdown() {
+        let context = glib::MainContext::new();
+        let directory = tempfile::tempdir().unwrap();
+        let (to_manager, manager, received) = start_manager(&context, &directory);
+        thread::sleep(Duration::from_millis(200));
+        let start = Instant::now();
+        to_manager.send(Command::Shutdown).unwrap();
+        manager.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        while context.iteration(false) {}
+        assert!(matches!(received.borrow().last(), Some(Message::FrontendManagerStopped)));
+    }
+
+    #[test]
+    fn manager_stops_when_the_gui_goes() {
+        let context = glib::MainContext::new();
+        let directory = tempfile::tempdir().unwrap();
+        let (to_manager, manager, _) = start_manager(&context, &directory);
+        let start = Instant::now();
+        drop(to_manager);
+        manager.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// Iterate `context` until `condition` holds of the messages received, or `timeout`.
+    fn wait_for_message(context: &glib::MainContext, received: &Rc<RefCell<Vec<Message>>>, timeout: Duration, condition: impl Fn(&Message) -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            while context.iteration(false) {}
+            if received.borrow().iter().any(&condition) { return true; }
+            if Instant::now() >= deadline { return false; }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn first_adapter_is_noticed_when_the_dvb_directory_appears() {
+        let context = glib::MainContext::new();
+        let directory = tempfile::tempdir().unwrap();
+        let (to_manager, manager, received) = start_manager(&context, &directory);
+        thread::sleep(Duration::from_millis(200));
+        let adapter_directory = directory.path().join("dvb").join("adapter9");
+        fs::create_dir_all(&adapter_directory).unwrap();
+        for name in &["frontend0", "demux0", "dvr0"] {
+            std::os::unix::fs::symlink("/dev/null", adapter_directory.join(name)).unwrap();
+        }
+        let fei = FrontendId{adapter: 9, frontend: 0};
+        assert!(wait_for_message(&context, &received, Duration::from_secs(5), |message| matches!(message, Message::FrontendAppeared{fei: appeared, ..} if *appeared == fei)));
+        to_manager.send(Command::Shutdown).unwrap();
+        manager.join().unwrap();
+    }
 
-    quickcheck! {
-        fn adapter_path_is_correct(id: u8) -> bool {
-            adapter_path(id).to_str().unwrap() == format!("/dev/dvb/adapter{}", id)
+    #[test]
+    fn burst_of_adapter_flapping_gives_its_final_state() {
+        let context = glib::MainContext::new();
+        let directory = tempfile::tempdir().unwrap();
+        let base = directory.path().join("dvb");
+        fs::create_dir(&base).unwrap();
+        let (to_manager, manager, received) = start_manager(&context, &directory);
+        thread::sleep(Duration::from_millis(200));
+        let adapter_directory = base.join("adapter8");
+        let plug_in = || {
+            fs::create_dir(&adapter_directory).unwrap();
+            for name in &["frontend0", "demux0", "dvr0"] {
+                std::os::unix::fs::symlink("/dev/null", adapter_directory.join(name)).unwrap();
+            }
+        };
+        for _ in 0..10 {
+            plug_in();
+            fs::remove_dir_all(&adapter_directory).unwrap();
         }
+        plug_in();
+        let fei = FrontendId{adapter: 8, frontend: 0};
+        let is_appeared = |message: &Message| matches!(message, Message::FrontendAppeared{fei: appeared, ..} if *appeared == fei);
+        assert!(wait_for_message(&context, &received, Duration::from_secs(5), is_appeared));
+        // Anything else would come within another debounce window.
+        thread::sleep(Duration::from_millis(700));
+        while context.iteration(false) {}
+        assert_eq!(received.borrow().iter().filter(|message| is_appeared(message)).count(), 1);
+        assert!(!received.borrow().iter().any(|message| matches!(message, Message::FrontendDisappeared{fei: gone} if *gone == fei)));
+        to_manager.send(Command::Shutdown).unwrap();
+        manager.join().unwrap();
     }
 
-    quickcheck! {
-        fn frontend_path_is_correct(a: u8, f: u8) -> bool {
-            frontend_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/frontend{}", a, f)
+    #[test]
+    fn frontend_going_and_coming_back_leaves_the_rest_of_the_adapter() {
+        let context = glib::MainContext::new();
+        let directory = tempfile::tempdir().unwrap();
+        let adapter_directory = directory.path().join("dvb").join("adapter7");
+        fs::create_dir_all(&adapter_directory).unwrap();
+        let nodes = |frontend: u8| ["frontend", "demux", "dvr"].iter()
+            .map(|name| adapter_directory.join(format!("{}{}", name, frontend)))
+            .collect::<Vec<_>>();
+        for path in nodes(0).into_iter().chain(nodes(1)) {
+            std::os::unix::fs::symlink("/dev/null", path).unwrap();
+        }
+        let (to_manager, manager, received) = start_manager(&context, &directory);
+        let frontend0 = FrontendId{adapter: 7, frontend: 0};
+        let frontend1 = FrontendId{adapter: 7, frontend: 1};
+        assert!(wait_for_message(&context, &received, Duration::from_secs(5), |message| matches!(message, Message::FrontendAppeared{fei, ..} if *fei == frontend1)));
+        thread::sleep(Duration::from_millis(200));
+        received.borrow_mut().clear();
+        for path in nodes(1) {
+            fs::remove_file(path).unwrap();
         }
+        assert!(wait_for_message(&context, &received, Duration::from_secs(5), |message| matches!(message, Message::FrontendDisappeared{fei} if *fei == frontend1)));
+        for path in nodes(1) {
+            std::os::unix::fs::symlink("/dev/null", path).unwrap();
+        }
+        assert!(wait_for_message(&context, &received, Duration::from_secs(5), |message| matches!(message, Message::FrontendAppeared{fei, ..} if *fei == frontend1)));
+        assert!(!received.borrow().iter().any(|message| match message {
+            Message::FrontendAppeared{fei, ..} | Message::FrontendDisappeared{fei} => *fei == frontend0,
+            _ => false,
+        }));
+        to_manager.send(Command::Shutdown).unwrap();
+        manager.join().unwrap();
+    }
+
+    #[test]
+    fn frontends_go_with_the_dvb_directory_and_come_back_with_it() {
+        let context = glib::MainContext::new();
+        let directory = tempfile::tempdir().unwrap();
+        let base = directory.path().join("dvb");
+        let plug_in = || {
+            let adapter_directory = base.join("adapter6");
+            fs::create_dir_all(&adapter_directory).unwrap();
+            for name in &["frontend0", "demux0", "dvr0"] {
+                std::os::unix::fs::symlink("/dev/null", adapter_directory.join(name)).unwrap();
+            }
+        };
+        plug_in();
+        let (to_manager, manager, received) = start_manager(&context, &directory);
+        let fei = FrontendId{adapter: 6, frontend: 0};
+        let is_appeared = |message: &Message| matches!(message, Message::FrontendAppeared{fei: appeared, ..} if *appeared == fei);
+        assert!(wait_for_message(&context, &received, Duration::from_secs(5), is_appeared));
+        thread::sleep(Duration::from_millis(200));
+        received.borrow_mut().clear();
+        fs::remove_dir_all(&base).unwrap();
+        assert!(wait_for_message(&context, &received, Duration::from_secs(5), |message| matches!(message, Message::FrontendDisappeared{fei: gone} if *gone == fei)));
+        plug_in();
+        assert!(wait_for_message(&context, &received, Duration::from_secs(5), is_appeared));
+        to_manager.send(Command::Shutdown).unwrap();
+        manager.join().unwrap();
     }
 
-    quickcheck! {
-        fn demux_path_is_correct(a: u8, f: u8) -> bool {
-            demux_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/demux{}", a, f)
+    #[test]
+    fn manager_stops_without_panicking_when_the_control_window_has_gone() {
+        let directory = tempfile::tempdir().unwrap();
+        let adapter_directory = directory.path().join("dvb").join("adapter5");
+        fs::create_dir_all(&adapter_directory).unwrap();
+        for name in &["frontend0", "demux0", "dvr0"] {
+            std::os::unix::fs::symlink("/dev/null", adapter_directory.join(name)).unwrap();
         }
+        let (to_cw, from_manager) = glib::MainContext::channel::<Message>(glib::PRIORITY_DEFAULT);
+        drop(from_manager);
+        let (_to_manager, from_gui) = unbounded();
+        let base = directory.path().join("dvb");
+        let history_path = directory.path().join("device-events");
+        let start = Instant::now();
+        let manager = thread::spawn(move || run(to_cw, from_gui, DvbDevices::new(base), history_path, HotplugMonitor::Inotify, Duration::from_secs(1), Duration::from_millis(500)));
+        assert!(manager.join().is_ok());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// A step of a script of the DVB special files being created and removed. Names are
+    /// relative to the DVB directory; those of adapters are directories, the others stand
+    /// in for special files.
+    enum Step {
+        Create(&'static str),
+        Remove(&'static str),
+        Pause(u64),  // Milliseconds.
     }
 
-    quickcheck! {
-        fn dvr_path_is_correct(a: u8, f: u8) -> bool {
-            dvr_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/dvr{}", a, f)
+    /// Device node events from running a script against a DVB directory, in place of
+    /// inotify, so that exactly the events of the script are seen.
+    struct ScriptedEvents {
+        events: Receiver<DeviceNodeEvent>,
+        _sender: crossbeam_channel::Sender<DeviceNodeEvent>,  // So that the events are not disconnected when the script ends.
+    }
+
+    impl ScriptedEvents {
+        fn run(base: PathBuf, script: Vec<Step>) -> ScriptedEvents {
+            let (sender, events) = unbounded();
+            thread::spawn({
+                let sender = sender.clone();
+                move || for step in script {
+                    let event = match step {
+                        Step::Create(name) => {
+                            let path = base.join(name);
+                            if name.contains('/') {
+                                std::os::unix::fs::symlink("/dev/null", &path).unwrap();
+                            } else {
+                                fs::create_dir_all(&path).unwrap();
+                            }
+                            DeviceNodeEvent::Created(path)
+                        },
+                        Step::Remove(name) => {
+                            let path = base.join(name);
+                            if path.is_dir() { fs::remove_dir_all(&path).unwrap(); } else { fs::remove_file(&path).unwrap(); }
+                            DeviceNodeEvent::Removed(path)
+                        },
+                        Step::Pause(milliseconds) => {
+                            thread::sleep(Duration::from_millis(milliseconds));
+                            continue;
+                        },
+                    };
+                    sender.send(event).unwrap();
+                }
+            });
+            ScriptedEvents{events, _sender: sender}
         }
     }
 
-    quickcheck! {
-        fn check_frontend_id_from_with_correct_structure(adapter: u8, frontend: u8) -> bool {
-            Some(FrontendId{adapter: adapter, frontend: frontend}) == frontend_id_from(&format!("/dev/dvb/adapter{}/frontend{}", adapter, frontend))
+    impl DeviceNodeEvents for ScriptedEvents {
+        fn receiver(&self) -> &Receiver<DeviceNodeEvent> { &self.events }
+    }
+
+    /// Run the frontend manager on the events of `script` run against the DVB directory
+    /// in `directory`, with the device history file in `directory` too, until `expected`
+    /// messages announcing or removing frontends have been received or `timeout`, then
+    /// shut it down. Returns the messages received, the appearing and disappearing as the
+    /// frontend, all else as is.
+    fn run_script(directory: &tempfile::TempDir, script: Vec<Step>, expected: usize, timeout: Duration) -> Vec<String> {
+        let base = directory.path().join("dvb");
+        fs::create_dir_all(&base).unwrap();
+        let context = glib::MainContext::new();
+        let (to_cw, from_manager) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        from_manager.attach(Some(&context), {
+            let received = received.clone();
+            move |message| {
+                received.borrow_mut().push(message);
+                glib::Continue(true)
+            }
+        });
+        let (to_manager, from_gui) = unbounded();
+        let history_path = directory.path().join("device-events");
+        let manager = thread::spawn({
+            let base = base.clone();
+            move || {
+                let to_cw = ToControlWindow::new(to_cw);
+                let state = Arc::new(ManagerState::new());
+                let devices = DvbDevices::new(base.clone());
+                start(&state, &to_cw, &devices, &history_path);
+                watch_device_nodes(&state, &to_cw, &from_gui, &devices, &history_path, &ScriptedEvents::run(base, script), Duration::from_secs(1), Duration::from_millis(500));
+                stop(&state, &to_cw);
+            }
+        });
+        let is_frontend_change = |message: &Message| matches!(message, Message::FrontendAppeared{..} | Message::FrontendDisappeared{..});
+        let deadline = Instant::now() + timeout;
+        while received.borrow().iter().filter(|message| is_frontend_change(message)).count() < expected && Instant::now() < deadline {
+            while context.iteration(false) {}
+            thread::sleep(Duration::from_millis(50));
         }
+        // Anything else would come within another debounce window.
+        thread::sleep(Duration::from_millis(700));
+        to_manager.send(Command::Shutdown).unwrap();
+        manager.join().unwrap();
+        while context.iteration(false) {}
+        let messages = received.borrow().iter()
+            .map(|message| match message {
+                Message::FrontendAppeared{fei, ..} => format!("appeared {}", fei),
+                Message::FrontendDisappeared{fei} => format!("disappeared {}", fei),
+                message => format!("{:?}", message),
+            })
+            .collect();
+        messages
     }
 
-    quickcheck! {
-        fn check_frontend_id_from_with_incorrect_structure(prefix: String, postfix: String, adapter: u8, frontend: u8) -> bool {
-            None == frontend_id_from(&format!("{}/adapter{}/frontend{}{}", prefix, adapter, frontend, postfix))
-         }
+    #[test]
+    fn adapter_appearing_disappearing_and_reappearing_is_followed() {
+        let directory = tempfile::tempdir().unwrap();
+        let messages = run_script(&directory, vec![
+            Step::Create("adapter4"),
+            Step::Create("adapter4/frontend0"),
+            // The demux and dvr special files come after the adapter is first looked at.
+            Step::Pause(800),
+            Step::Create("adapter4/demux0"),
+            Step::Create("adapter4/dvr0"),
+            Step::Pause(1500),
+            Step::Remove("adapter4"),
+            Step::Pause(1500),
+            Step::Create("adapter4"),
+            Step::Create("adapter4/frontend0"),
+            Step::Create("adapter4/demux0"),
+            Step::Create("adapter4/dvr0"),
+        ], 3, Duration::from_secs(10));
+        assert_eq!(messages, vec![
+            "appeared adapter4:frontend0",
+            "disappeared adapter4:frontend0",
+            "appeared adapter4:frontend0",
+            "FrontendManagerStopped",
+        ]);
     }
 
+    #[test]
+    fn frontends_appearing_and_disappearing_are_written_to_the_history_file() {
+        let directory = tempfile::tempdir().unwrap();
+        run_script(&directory, vec![
+            Step::Create("adapter1"),
+            Step::Create("adapter1/frontend0"),
+            Step::Create("adapter1/demux0"),
+            Step::Create("adapter1/dvr0"),
+            Step::Pause(1500),
+            Step::Remove("adapter1"),
+        ], 2, Duration::from_secs(5));
+        let fei = FrontendId{adapter: 1, frontend: 0};
+        let history = DeviceHistory::read(&directory.path().join("device-events")).unwrap();
+        let changes = history.events().filter(|event| event.fei == fei).map(|event| event.change).collect::<Vec<_>>();
+        assert_eq!(changes, vec![DeviceChange::Appeared, DeviceChange::Disappeared]);
+    }
+
+    #[test]
+    fn adapter_flapping_is_reported_once_as_it_was_left() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut script = Vec::new();
+        for _ in 0..10 {
+            script.extend(vec![
+                Step::Create("adapter3"),
+                Step::Create("adapter3/frontend0"),
+                Step::Create("adapter3/demux0"),
+                Step::Create("adapter3/dvr0"),
+                Step::Remove("adapter3"),
+            ]);
+        }
+        script.extend(vec![
+            Step::Create("adapter3"),
+            Step::Create("adapter3/frontend0"),
+            Step::Create("adapter3/demux0"),
+            Step::Create("adapter3/dvr0"),
+        ]);
+        let messages = run_script(&directory, script, 1, Duration::from_secs(5));
+        assert_eq!(messages, vec!["appeared adapter3:frontend0", "FrontendManagerStopped"]);
+    }
+
+    #[test]
+    fn frontend_whose_demux_and_dvr_never_come_is_not_reported() {
+        let directory = tempfile::tempdir().unwrap();
+        let messages = run_script(&directory, vec![
+            Step::Create("adapter2"),
+            Step::Create("adapter2/frontend0"),
+        ], 1, Duration::from_secs(3));
+        assert_eq!(messages, vec!["FrontendManagerStopped"]);
+    }
 }
@@ -19,9 +19,12 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use std::cell::Cell;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
+use chrono::Utc;
+
 // use glib;
 use glib::prelude::*;
 use gdk;
@@ -29,11 +32,17 @@ use gdk::prelude::*;
 use gtk;
 use gtk::prelude::*;
 
-use crate::channels_data::encode_to_mrl;
+use log::{debug, warn};
+
+use crate::channel_logos;
+use crate::channels_data::{get_channel_names_and_service_ids, get_channels_data, is_radio};
 use crate::control_window_button::ControlWindowButton;
+use crate::epg_manager;
 use crate::gstreamer_engine::GStreamerEngine;
 use crate::metvcombobox::{MeTVComboBox, MeTVComboBoxExt};
 use crate::preferences;
+use crate::programme_details;
+use crate::recent_channels;
 
 /// In fullscreen mode this holds the last time there was mouse movement
 /// or key press activity: it is used to provide a timeout for hiding the fullscreen
@@ -48,6 +57,7 @@ pub struct FrontendWindow {
     control_window_button: Rc<ControlWindowButton>,
     pub window: gtk::Window,  // ControlWindowButton instance needs access to this.
     pub close_button: gtk::Button, // ControlWindowButton instance needs access to this.
+    header_bar: gtk::HeaderBar,
     fullscreen_button: gtk::Button,
     volume_adjustment: gtk::Adjustment,
     pub volume_button: gtk::VolumeButton,  // ControlWindowButton instance uses this.
@@ -56,6 +66,16 @@ pub struct FrontendWindow {
     fullscreen_unfullscreen_button: gtk::Button,
     fullscreen_volume_button: gtk::VolumeButton,
     pub fullscreen_channel_selector: MeTVComboBox, // ControlWindowButton instance needs access to this.
+    presentation: gtk::Stack,  // The video, or for a radio channel what is being listened to.
+    radio_label: gtk::Label,
+    osd: gtk::Box,  // The channel being zapped to or the channel number being entered, over the video.
+    osd_logo: gtk::Image,
+    osd_label: gtk::Label,
+    banner: gtk::EventBox,  // The channel just changed to, over the video for a few seconds.
+    banner_title: gtk::Label,
+    banner_programme: gtk::Label,
+    banner_progress: gtk::ProgressBar,
+    banner_showings: Rc<Cell<u32>>,  // So that only the timeout of the latest showing hides the banner.
     inhibitor: u32,
     pub engine: GStreamerEngine, // ControlWindowButton instance needs access to this.
 }
@@ -99,11 +119,13 @@ impl FrontendWindow {
         // to be able to define the action associated with the volume_adjustment.
         let volume_button = gtk::VolumeButton::new();
         let channel_selector = {
-            let c_s = MeTVComboBox::new_with_model(&control_window_button.control_window.channels_data_sorter);
+            let c_s = MeTVComboBox::new_with_model(&control_window_button.channels_model);
             c_s.set_active(control_window_button.channel_selector.get_active());
             c_s.connect_changed({
                 let c_w_b = control_window_button.clone();
-                move |channel_selector| ControlWindowButton::on_channel_changed(&c_w_b, channel_selector.get_active().unwrap())
+                move |channel_selector| if let Some(channel_index) = channel_selector.get_active() {
+                    ControlWindowButton::on_channel_changed(&c_w_b, channel_index);
+                }
             });
             c_s
         };
@@ -115,6 +137,8 @@ impl FrontendWindow {
             h_b.pack_end(&fullscreen_button);
             h_b.pack_end(&volume_button);
             h_b.pack_start(&channel_selector);
+            h_b.pack_start(&ControlWindowButton::channel_search_button(&control_window_button));
+            h_b.pack_start(&ControlWindowButton::recent_channels_button(&control_window_button));
             h_b.show_all();
             h_b
         };
@@ -181,11 +205,13 @@ impl FrontendWindow {
         };
         let fullscreen_channel_selector = {
             let mut f_c_s = fullscreen_toolbar_builder.get_object::<MeTVComboBox>("fullscreen_channel_selector").unwrap();
-            f_c_s.init_with_model(&control_window_button.control_window.channels_data_sorter);
+            f_c_s.init_with_model(&control_window_button.channels_model);
             f_c_s.set_active(control_window_button.channel_selector.get_active());
             f_c_s.connect_changed({
                 let c_w_b = control_window_button.clone();
-                move |f_c_s| ControlWindowButton::on_channel_changed(&c_w_b, f_c_s.get_active().unwrap())
+                move |f_c_s| if let Some(channel_index) = f_c_s.get_active() {
+                    ControlWindowButton::on_channel_changed(&c_w_b, channel_index);
+                }
             });
             //
             // TODO There appear to be no 'event-after' events posted for a ComboBox or it's child.
@@ -193,13 +219,13 @@ impl FrontendWindow {
             f_c_s.connect_event_after(|_, ev| {
                 add_timeout();
                 unsafe {
-                    println!("Adding timeout from f_c_s: {:?}, {:?}, {:?}", Instant::now(), ev.get_event_type(), LAST_ACTIVITY_TIME);
+                    debug!("Adding timeout from f_c_s: {:?}, {:?}, {:?}", Instant::now(), ev.get_event_type(), LAST_ACTIVITY_TIME);
                 };
             });
             f_c_s.get_child().unwrap().connect_event_after(|_, ev| {
                 add_timeout();
                 unsafe {
-                    println!("Adding timeout from f_c_s child: {:?}, {:?}, {:?}", Instant::now(), ev.get_event_type(), LAST_ACTIVITY_TIME);
+                    debug!("Adding timeout from f_c_s child: {:?}, {:?}, {:?}", Instant::now(), ev.get_event_type(), LAST_ACTIVITY_TIME);
                 };
             });
             /*
@@ -237,24 +263,151 @@ impl FrontendWindow {
             */
             f_c_s
         };
+        let _fullscreen_channel_search_button = {
+            let f_c_s_b = ControlWindowButton::channel_search_button(&control_window_button);
+            f_c_s_b.connect_event_after(|_, _| { add_timeout(); });
+            f_c_s_b.get_popover().unwrap().connect_event_after(|_, _| { add_timeout(); });
+            f_c_s_b.show();
+            fullscreen_toolbar_builder.get_object::<gtk::Box>("fullscreen_channel_box").unwrap().pack_start(&f_c_s_b, false, false, 0);
+            f_c_s_b
+        };
         let volume = volume_adjustment.get_value();
         volume_button.set_value(volume);
         fullscreen_volume_button.set_value(volume);
         volume_button.set_adjustment(&volume_adjustment);
         fullscreen_volume_button.set_adjustment(&volume_adjustment);
+        let radio_label = gtk::Label::new(None);
+        let presentation = {
+            let radio_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+            radio_box.set_valign(gtk::Align::Center);
+            let radio_image = gtk::Image::from_icon_name(Some("audio-x-generic-symbolic"), gtk::IconSize::Dialog.into());
+            radio_image.set_pixel_size(128);
+            radio_box.pack_start(&radio_image, false, false, 0);
+            radio_box.pack_start(&radio_label, false, false, 0);
+            let stack = gtk::Stack::new();
+            stack.add_named(&engine.video_widget, "video");
+            stack.add_named(&radio_box, "radio");
+            stack
+        };
+        let osd_logo = gtk::Image::new();
+        let osd_label = gtk::Label::new(None);
+        let osd = {
+            let o = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+            o.get_style_context().add_class("osd");
+            o.set_halign(gtk::Align::Center);
+            o.set_valign(gtk::Align::End);
+            o.set_margin_bottom(30);
+            o.pack_start(&osd_logo, false, false, 0);
+            o.pack_start(&osd_label, false, false, 0);
+            o.set_no_show_all(true);
+            osd_label.show();
+            o
+        };
+        let banner_title = gtk::Label::new(None);
+        let banner_programme = gtk::Label::new(None);
+        let banner_progress = gtk::ProgressBar::new();
+        let banner = {
+            let content = gtk::Box::new(gtk::Orientation::Vertical, 5);
+            content.get_style_context().add_class("osd");
+            content.set_border_width(10);
+            banner_title.set_halign(gtk::Align::Start);
+            banner_programme.set_halign(gtk::Align::Start);
+            content.pack_start(&banner_title, false, false, 0);
+            content.pack_start(&banner_programme, false, false, 0);
+            content.pack_start(&banner_progress, false, false, 0);
+            content.show();
+            banner_title.show();
+            // Nothing of the banner takes the focus, clicking it dismisses it, presenting
+            // the details of the programme on now if it is known.
+            let b = gtk::EventBox::new();
+            b.set_visible_window(false);
+            b.set_can_focus(false);
+            b.set_valign(gtk::Align::End);
+            b.set_margin_start(30);
+            b.set_margin_end(30);
+            b.set_margin_bottom(30);
+            b.add(&content);
+            b.set_no_show_all(true);
+            b.connect_button_press_event({
+                let c_w_b = control_window_button.clone();
+                let w = window.clone();
+                move |b, _| {
+                    b.hide();
+                    if let Some(channel_name) = c_w_b.channel_selector.get_active_text() {
+                        if let Some(programme) = epg_manager::now_next(&channel_name).and_then(|now_next| now_next.now) {
+                            programme_details::present(Some(&w), &c_w_b.control_window, &channel_name, &programme);
+                        }
+                    }
+                    Inhibit(true)
+                }
+            });
+            b
+        };
+        // Scrolling over the video, or over what is shown for a radio channel, zaps.
+        let zap_area = {
+            let z_a = gtk::EventBox::new();
+            z_a.set_visible_window(false);
+            z_a.add_events(gdk::EventMask::SCROLL_MASK);
+            z_a.add(&presentation);
+            z_a.connect_scroll_event({
+                let c_w_b = control_window_button.clone();
+                move |_, event| {
+                    match event.get_direction() {
+                        gdk::ScrollDirection::Up => c_w_b.zap(1),
+                        gdk::ScrollDirection::Down => c_w_b.zap(-1),
+                        _ => return Inhibit(false),
+                    }
+                    Inhibit(true)
+                }
+            });
+            z_a
+        };
         let video_overlay = {
             let v_o = gtk::Overlay::new();
-            v_o.add(&engine.video_widget);
+            v_o.add(&zap_area);
             v_o.show_all();
             v_o.add_overlay(&fullscreen_toolbar);
+            v_o.add_overlay(&osd);
+            v_o.add_overlay(&banner);
             v_o
         };
         window.add(&video_overlay);
         window.add_events(gdk::EventMask::KEY_PRESS_MASK);
         window.connect_key_press_event({
             let f_t = fullscreen_toolbar.clone();
+            let c_w_b = control_window_button.clone();
+            let b = banner.clone();
             move |a_w, key| {
-                if key.get_keyval() == gdk::keys::constants::Escape {
+                let keyval = key.get_keyval();
+                if keyval == gdk::keys::constants::Page_Up || keyval == gdk::keys::constants::AudioNext {
+                    c_w_b.zap(1);
+                    return Inhibit(true);
+                }
+                if keyval == gdk::keys::constants::Page_Down || keyval == gdk::keys::constants::AudioPrev {
+                    c_w_b.zap(-1);
+                    return Inhibit(true);
+                }
+                if keyval == gdk::keys::constants::BackSpace {
+                    c_w_b.recall();
+                    return Inhibit(true);
+                }
+                if let Some(digit) = keyval.to_unicode().and_then(|c| c.to_digit(10)) {
+                    c_w_b.process_digit(digit);
+                    return Inhibit(true);
+                }
+                if keyval == gdk::keys::constants::Return || keyval == gdk::keys::constants::KP_Enter {
+                    if c_w_b.enter_channel_number() {
+                        return Inhibit(true);
+                    }
+                }
+                if keyval == gdk::keys::constants::Escape && c_w_b.cancel_channel_number() {
+                    return Inhibit(true);
+                }
+                if keyval == gdk::keys::constants::Escape && b.is_visible() {
+                    b.hide();
+                    return Inhibit(true);
+                }
+                if keyval == gdk::keys::constants::Escape {
                     if a_w.get_window().unwrap().get_state().intersects(gdk::WindowState::FULLSCREEN) {
                         set_timeout(None);
                         f_t.hide();
@@ -276,9 +429,13 @@ impl FrontendWindow {
             }
         });
         let channel_name = control_window_button.channel_selector.get_active_text().unwrap();
-        engine.set_mrl(&encode_to_mrl(&channel_name));
-        engine.play();
-        preferences::set_last_channel(channel_name, true);
+        engine.set_channel(&channel_name);
+        let tuned = control_window_button.can_tune(&channel_name);
+        if tuned {
+            engine.play();
+            control_window_button.remember_channel(&channel_name);
+            recent_channels::record(&channel_name);
+        }
         window.show();
         let inhibitor = control_window_button.control_window.window.get_application().unwrap().inhibit(
             Some(&window),
@@ -286,12 +443,13 @@ impl FrontendWindow {
             Some("Me TV inhibits when playing a channel."),
         );
         if inhibitor == 0 {
-            println!("Warning: could not set inhibitor.");
+            warn!("Could not set inhibitor.");
         }
         let frontend_window = Rc::new(FrontendWindow {
             control_window_button: control_window_button.clone(),
             window,
             close_button,
+            header_bar,
             fullscreen_button,
             volume_adjustment,
             volume_button,
@@ -300,9 +458,23 @@ impl FrontendWindow {
             fullscreen_unfullscreen_button,
             fullscreen_volume_button,
             fullscreen_channel_selector,
+            presentation,
+            radio_label,
+            osd,
+            osd_logo,
+            osd_label,
+            banner,
+            banner_title,
+            banner_programme,
+            banner_progress,
+            banner_showings: Rc::new(Cell::new(0)),
             inhibitor,
             engine,
         });
+        frontend_window.present_channel(&channel_name);
+        if tuned {
+            frontend_window.show_banner(&channel_name);
+        }
         frontend_window.volume_adjustment.connect_value_changed({
             let f_w = frontend_window.clone();
             move |v_a| f_w.engine.set_volume(v_a.get_value())
@@ -310,12 +482,147 @@ impl FrontendWindow {
         Ok(frontend_window)
     }
 
+    /// Show the video of a television channel, or, as a radio channel has none, what is
+    /// being listened to.
+    pub fn present_channel(&self, channel_name: &str) {  // Used in control_window_button.rs
+        self.show_title(channel_name);
+        if is_radio(channel_name) {
+            self.radio_label.set_text(channel_name);
+            self.presentation.set_visible_child_name("radio");
+        } else {
+            self.presentation.set_visible_child_name("video");
+        }
+    }
+
+    /// Show `text` over the video, or stop showing anything if there is none.
+    pub fn show_osd(&self, text: Option<&str>) {  // Used in control_window_button.rs
+        match text {
+            Some(text) => {
+                self.osd_label.set_text(text);
+                self.osd_logo.hide();
+                self.osd.show();
+            },
+            None => self.osd.hide(),
+        }
+    }
+
+    /// Show the logo, if it has one, and name of the channel being zapped to, until it is
+    /// tuned and there is none.
+    pub fn show_zapping(&self, channel_name: Option<&str>) {  // Used in control_window_button.rs
+        self.show_osd(channel_name);
+        if let Some(channel_name) = channel_name {
+            let service_id = get_channel_names_and_service_ids().unwrap_or_default().into_iter()
+                .find(|(name, _)| name == channel_name)
+                .map(|(_, service_id)| service_id);
+            if let Some(logo) = channel_logos::get_logo(channel_name, service_id, channel_logos::OSD_SIZE) {
+                self.osd_logo.set_from_pixbuf(Some(&logo));
+                self.osd_logo.show();
+            }
+            self.show_title(channel_name);
+        }
+    }
+
+    /// Show the number and name of the channel just changed to, and the programme on it
+    /// if that is known, over the video for the time the preferences say, if any.
+    pub fn show_banner(&self, channel_name: &str) {  // Used in control_window_button.rs
+        let seconds = preferences::get_banner_seconds();
+        if seconds == 0 {
+            return;
+        }
+        let title = match get_channels_data().unwrap_or_default().into_iter().find(|(_, name)| name == channel_name) {
+            Some((number, _)) if number != 0 => format!("{}  {}", number, channel_name),
+            _ => channel_name.to_string(),
+        };
+        self.banner_title.set_markup(&format!("<b>{}</b>", glib::markup_escape_text(&title)));
+        self.show_banner_programme(channel_name);
+        self.banner.show();
+        let showing = self.banner_showings.get().wrapping_add(1);
+        self.banner_showings.set(showing);
+        glib::timeout_add_seconds_local(seconds, {
+            let banner = self.banner.clone();
+            let banner_showings = self.banner_showings.clone();
+            move || {
+                if banner_showings.get() == showing {
+                    banner.hide();
+                }
+                Continue(false)
+            }
+        });
+    }
+
+    /// Show the change of the programme on now on the channel `channel_name` in the title,
+    /// and in the banner if it is showing.
+    pub fn update_programme(&self, channel_name: &str) {  // Used in control_window_button.rs
+        self.show_title(channel_name);
+        if self.banner.is_visible() {
+            self.show_banner_programme(channel_name);
+        }
+    }
+
+    /// Show the title of the programme on now on the channel of the banner, whether it
+    /// started late or early, and how far through it is, or nothing if it is not known,
+    /// there being no EPG data for the channel.
+    fn show_banner_programme(&self, channel_name: &str) {
+        let now = Utc::now().naive_utc();
+        let service_id = get_channel_names_and_service_ids().unwrap_or_default().into_iter()
+            .find(|(name, _)| name == channel_name)
+            .map(|(_, service_id)| service_id);
+        let programme = epg_manager::now_next(channel_name)
+            .and_then(|now_next| now_next.now)
+            .map(|programme| {
+                let fraction = programme.fraction_elapsed(now).unwrap_or(0.0);
+                let mut title = match programme.times_text() {
+                    Some(times) => format!("{}  {}", times, programme.title),
+                    None => programme.title.clone(),
+                };
+                if let Some(badge) = programme.rating_badge() {
+                    title = format!("{}  {}", title, badge);
+                }
+                if let Some(slippage) = service_id.and_then(|service_id| epg_manager::slippage(service_id, &programme)) {
+                    title = format!("{}  ({})", title, slippage);
+                }
+                (title, fraction)
+            });
+        match programme {
+            Some((title, fraction)) => {
+                self.banner_programme.set_text(&title);
+                self.banner_progress.set_fraction(fraction.max(0.0).min(1.0));
+                self.banner_programme.show();
+                self.banner_progress.show();
+            },
+            None => {
+                self.banner_programme.hide();
+                self.banner_progress.hide();
+            },
+        }
+    }
+
+    /// Show the channel `channel_name`, and the programme on now on it if that is known, as
+    /// the title, e.g. "BBC ONE — Doctor Who (20:00–20:50)". The title is only set if it
+    /// has changed, so that it does not flicker.
+    fn show_title(&self, channel_name: &str) {
+        let programme = epg_manager::now_next(channel_name)
+            .and_then(|now_next| now_next.now)
+            .filter(|programme| !programme.title.is_empty());
+        let title = match programme {
+            Some(programme) => match programme.times_text() {
+                Some(times) => format!("{} — {} ({})", channel_name, programme.title, times),
+                None => format!("{} — {}", channel_name, programme.title),
+            },
+            None => channel_name.to_string(),
+        };
+        if self.window.get_title().map_or(true, |shown| shown.as_str() != title) {
+            self.window.set_title(&title);
+            self.header_bar.set_title(Some(&title));
+        }
+    }
+
     pub fn stop(&self) {
         if self.inhibitor  != 0 {
             let application = self.control_window_button.control_window.window.get_application().unwrap();
             application.uninhibit(self.inhibitor);
         } else {
-            println!("Warning: inhibitor was not set.");
+            warn!("Inhibitor was not set.");
         }
         self.window.hide();
         self.engine.stop();
@@ -0,0 +1,1092 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The identity and special files of the DVB frontends on the system.
+
+use std::env;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use regex::Regex;
+
+/// A struct to represent the identity of a specific frontend currently
+/// available on the system.
+///
+/// Ordering is by adapter and then by frontend.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FrontendId {
+    pub adapter: u8,
+    pub frontend: u8,
+}
+
+/// Rendered as, for example, adapter0:frontend1.
+impl fmt::Display for FrontendId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "adapter{}:frontend{}", self.adapter, self.frontend)
+    }
+}
+
+/// Accepts the `Display` form, adapter0:frontend1, or just the numbers, 0:1.
+impl FromStr for FrontendId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FrontendId, String> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        if parts.len() != 2 {
+            return Err(format!("'{}' is not of the form ADAPTER:FRONTEND, e.g. 0:1 or adapter0:frontend1.", s));
+        }
+        let number = |part: &str, prefix: &str| {
+            let digits = part.strip_prefix(prefix).unwrap_or(part);
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!("'{}' in '{}' is not a valid {} number.", part, s, prefix));
+            }
+            digits.parse::<u8>().map_err(|_| format!("The {} number {} in '{}' is greater than 255.", prefix, digits, s))
+        };
+        Ok(FrontendId{adapter: number(parts[0], "adapter")?, frontend: number(parts[1], "frontend")?})
+    }
+}
+
+/// The environment variable that, if set, is the location of the DVB special files
+/// rather than /dev/dvb.
+pub const DVB_PATH_VARIABLE: &str = "ME_TV_DVB_PATH";
+
+/// The path in the filesystem to the DVB related special files by default.
+pub const DEFAULT_DVB_PATH: &str = "/dev/dvb";
+
+/// The path in the filesystem to the sysfs class directory of the DVB devices.
+pub fn sysfs_dvb_path() -> PathBuf { PathBuf::from("/sys/class/dvb") }
+
+/// The filesystem queries made in finding the DVB devices, so that discovery and
+/// hotplug handling can be tested against a `FakeFilesystem` rather than hardware.
+pub trait DeviceFilesystem: fmt::Debug + Send + Sync {
+    /// Whether there is a directory at `path`.
+    fn is_directory(&self, path: &Path) -> bool;
+
+    /// Whether there is a character device at `path`, following symbolic links.
+    fn is_char_device(&self, path: &Path) -> bool;
+
+    /// The names of the entries of the directory at `path`, in no particular order.
+    fn list_directory(&self, path: &Path) -> io::Result<Vec<String>>;
+}
+
+/// The filesystem of the system.
+#[derive(Debug)]
+pub struct RealFilesystem;
+
+impl DeviceFilesystem for RealFilesystem {
+    fn is_directory(&self, path: &Path) -> bool { path.is_dir() }
+
+    fn is_char_device(&self, path: &Path) -> bool {
+        // NB m.is_file() is false for special files. :-(
+        // Assume the special devices were are dealing with are
+        // character devices not block devices.
+        match fs::metadata(path) {
+            Ok(m) => m.file_type().is_char_device(),
+            Err(_) => false,
+        }
+    }
+
+    fn list_directory(&self, path: &Path) -> io::Result<Vec<String>> {
+        Ok(fs::read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().map(String::from))
+            .collect())
+    }
+}
+
+/// What is at a path in a `FakeFilesystem`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FakeEntry {
+    Directory,
+    CharDevice,
+    File,
+}
+
+/// An in-memory filesystem for testing. Clones share the same entries, so a test can
+/// change what a `DvbDevices` sees whilst it is in use, from another thread if need be.
+#[derive(Clone, Debug, Default)]
+pub struct FakeFilesystem {
+    entries: Arc<Mutex<BTreeMap<PathBuf, FakeEntry>>>,
+}
+
+impl FakeFilesystem {
+    pub fn new() -> FakeFilesystem { FakeFilesystem::default() }
+
+    fn add(&self, path: &Path, entry: FakeEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        for ancestor in path.ancestors().skip(1) {
+            entries.insert(ancestor.to_path_buf(), FakeEntry::Directory);
+        }
+        entries.insert(path.to_path_buf(), entry);
+    }
+
+    /// Add a directory, and any missing parent directories.
+    pub fn add_directory(&self, path: impl AsRef<Path>) { self.add(path.as_ref(), FakeEntry::Directory); }
+
+    /// Add a character device, and any missing parent directories.
+    pub fn add_char_device(&self, path: impl AsRef<Path>) { self.add(path.as_ref(), FakeEntry::CharDevice); }
+
+    /// Add a regular file, and any missing parent directories.
+    pub fn add_file(&self, path: impl AsRef<Path>) { self.add(path.as_ref(), FakeEntry::File); }
+
+    /// Remove an entry and, if a directory, everything in it.
+    pub fn remove(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        self.entries.lock().unwrap().retain(|entry, _| !entry.starts_with(path));
+    }
+
+    fn entry(&self, path: &Path) -> Option<FakeEntry> {
+        self.entries.lock().unwrap().get(path).copied()
+    }
+}
+
+impl DeviceFilesystem for FakeFilesystem {
+    fn is_directory(&self, path: &Path) -> bool { self.entry(path) == Some(FakeEntry::Directory) }
+
+    fn is_char_device(&self, path: &Path) -> bool { self.entry(path) == Some(FakeEntry::CharDevice) }
+
+    fn list_directory(&self, path: &Path) -> io::Result<Vec<String>> {
+        if !self.is_directory(path) { return Err(io::Error::from(io::ErrorKind::NotFound)); }
+        Ok(self.entries.lock().unwrap().keys()
+            .filter(|entry| entry.parent() == Some(path))
+            .filter_map(|entry| entry.file_name()?.to_str().map(String::from))
+            .collect())
+    }
+}
+
+/// Where the DVB special files are, and where sysfs describes the devices, if it does.
+///
+/// When the special files are somewhere other than /dev/dvb, bind mounted into a
+/// container say, sysfs does not describe them so only the special files are used.
+/// GStreamer's dvbsrc always uses /dev/dvb, though, so playing and recording need the
+/// devices to be there as well.
+#[derive(Clone, Debug)]
+pub struct DvbDevices {
+    base: PathBuf,
+    sysfs_base: Option<PathBuf>,
+    filesystem: Arc<dyn DeviceFilesystem>,
+}
+
+impl Default for DvbDevices {
+    fn default() -> DvbDevices {
+        DvbDevices{base: PathBuf::from(DEFAULT_DVB_PATH), sysfs_base: Some(sysfs_dvb_path()), filesystem: Arc::new(RealFilesystem)}
+    }
+}
+
+/// Equal if the paths are, whatever the filesystem.
+impl PartialEq for DvbDevices {
+    fn eq(&self, other: &DvbDevices) -> bool {
+        self.base == other.base && self.sysfs_base == other.sysfs_base
+    }
+}
+
+impl DvbDevices {
+    /// The DVB special files under `base`, the equivalent of /dev/dvb.
+    pub fn new(base: impl Into<PathBuf>) -> DvbDevices {
+        let base = base.into();
+        if base == Path::new(DEFAULT_DVB_PATH) { return DvbDevices::default(); }
+        DvbDevices{base, sysfs_base: None, filesystem: Arc::new(RealFilesystem)}
+    }
+
+    /// Looking at `filesystem` rather than the filesystem of the system.
+    pub fn with_filesystem(self, filesystem: impl DeviceFilesystem + 'static) -> DvbDevices {
+        DvbDevices{filesystem: Arc::new(filesystem), ..self}
+    }
+
+    /// With `sysfs_base`, the equivalent of /sys/class/dvb, describing the devices.
+    pub fn with_sysfs(self, sysfs_base: impl Into<PathBuf>) -> DvbDevices {
+        DvbDevices{sysfs_base: Some(sysfs_base.into()), ..self}
+    }
+
+    /// The DVB special files where `ME_TV_DVB_PATH` says, or /dev/dvb if it is not set.
+    pub fn from_environment() -> DvbDevices {
+        match env::var_os(DVB_PATH_VARIABLE) {
+            Some(path) if !path.is_empty() => DvbDevices::new(path),
+            _ => DvbDevices::default(),
+        }
+    }
+
+    /// The path to the directory of the adapter directories.
+    pub fn base(&self) -> &Path { &self.base }
+
+    /// Return the path to the adapter director for a given adapter.
+    pub fn adapter_path(&self, id: u8) -> PathBuf {
+        self.base.join("adapter".to_string() + &id.to_string())
+    }
+
+    /// Return the path to the special file for a given frontend.
+    pub fn frontend_path(&self, fei: &FrontendId) -> PathBuf {
+        self.adapter_path(fei.adapter).join("frontend".to_string() + &fei.frontend.to_string())
+    }
+
+    /// Return the path to the special file of the demux for a given frontend.
+    pub fn demux_path(&self, fei: &FrontendId) -> PathBuf {
+        self.adapter_path(fei.adapter).join("demux".to_string() + &fei.frontend.to_string())
+    }
+
+    /// Return the path to the special file of the data for a given frontend.
+    pub fn dvr_path(&self, fei: &FrontendId) -> PathBuf {
+        self.adapter_path(fei.adapter).join("dvr".to_string() + &fei.frontend.to_string())
+    }
+
+    /// Whether the directory of adapter number `adapter` is present.
+    pub fn is_adapter_present(&self, adapter: u8) -> bool {
+        self.filesystem.is_directory(&self.adapter_path(adapter))
+    }
+
+    /// Return the numbers of the adapters whose directories are present, in order, none
+    /// if the DVB directory itself is not there.
+    pub fn adapters(&self) -> Vec<u8> {
+        numbered_entries(&*self.filesystem, &self.base, "adapter").into_iter()
+            .filter(|adapter| self.is_adapter_present(*adapter))
+            .collect()
+    }
+
+    /// Return the frontends of adapter number `adapter`.
+    ///
+    /// The adapter directory is listed rather than probed for frontend0, frontend1, … in
+    /// sequence since some multi-standard cards, and udev renaming, leave gaps in the
+    /// frontend numbering. Entries that are not character devices, regular files for
+    /// example, are skipped.
+    pub fn adapter_frontends(&self, adapter: u8) -> Vec<FrontendId> {
+        if !self.is_adapter_present(adapter) { return Vec::new(); }
+        let adapter_directory = self.adapter_path(adapter);
+        numbered_entries(&*self.filesystem, &adapter_directory, "frontend").into_iter()
+            .filter(|frontend| self.filesystem.is_char_device(&adapter_directory.join(format!("frontend{}", frontend))))
+            .map(|frontend| FrontendId{adapter, frontend})
+            .collect()
+    }
+
+    /// Return the special files that tuning frontend `fei` needs, frontendN, demuxN and
+    /// dvrN, that are not present as character devices.
+    pub fn missing_devices(&self, fei: &FrontendId) -> Vec<PathBuf> {
+        vec![self.frontend_path(fei), self.demux_path(fei), self.dvr_path(fei)].into_iter()
+            .filter(|path| !self.filesystem.is_char_device(path))
+            .collect()
+    }
+
+    /// Wait up to `timeout` for the special files that tuning frontend `fei` needs,
+    /// returning those still missing. Some drivers create the demux and dvr special
+    /// files fractionally after the frontend one.
+    pub fn wait_for_devices(&self, fei: &FrontendId, timeout: Duration) -> Vec<PathBuf> {
+        let start = Instant::now();
+        loop {
+            let missing = self.missing_devices(fei);
+            if missing.is_empty() || start.elapsed() >= timeout { return missing; }
+            thread::sleep(READINESS_POLL_INTERVAL);
+        }
+    }
+
+    /// Return the frontends according to the special files.
+    ///
+    /// The adapter directories are listed rather than probed for in sequence since udev
+    /// rules may pin adapters to numbers leaving gaps, e.g. just adapter4 and adapter7.
+    pub fn special_file_frontends(&self) -> Vec<FrontendId> {
+        self.adapters().into_iter()
+            .flat_map(|adapter| self.adapter_frontends(adapter))
+            .collect()
+    }
+
+    /// Return the frontends according to sysfs, in order, if sysfs describes the devices
+    /// and is present, which it is not in some containers, or else according to the
+    /// special files.
+    ///
+    /// The kernel names the sysfs entries dvbA.frontendB from its own numbering of the
+    /// devices, so unlike the special files they do not depend on how udev has been set
+    /// up.
+    pub fn installed_frontends(&self) -> Vec<FrontendId> {
+        match &self.sysfs_base {
+            Some(sysfs_base) if self.filesystem.is_directory(sysfs_base) => {
+                let mut frontends = match self.filesystem.list_directory(sysfs_base) {
+                    Ok(names) => names.iter().filter_map(|name| frontend_id_from_sysfs_name(name)).collect::<Vec<_>>(),
+                    Err(_) => Vec::new(),
+                };
+                frontends.sort_unstable();
+                frontends
+            },
+            _ => self.special_file_frontends(),
+        }
+    }
+
+    /// Wait for the frontends of a newly appeared adapter to be ready.
+    ///
+    /// The frontend special files appear some time after the adapter directory, how long
+    /// depends on the device: more than a second for some USB tuners. So the directory is
+    /// polled until there is a ready frontend for every dvrN in it, or until `timeout`.
+    /// A frontend is ready once its demux and dvr special files are present as well, and
+    /// `is_ready` says it is usable. `on_ready` is called for each frontend as soon as it
+    /// is ready. The frontends reported are returned.
+    pub fn wait_for_frontends(
+        &self,
+        adapter: u8,
+        timeout: Duration,
+        is_ready: impl Fn(&FrontendId) -> bool,
+        mut on_ready: impl FnMut(&FrontendId),
+    ) -> Vec<FrontendId> {
+        let adapter_directory = self.adapter_path(adapter);
+        let start = Instant::now();
+        let mut reported: Vec<FrontendId> = Vec::new();
+        loop {
+            for fei in self.adapter_frontends(adapter) {
+                if !reported.contains(&fei) && self.missing_devices(&fei).is_empty() && is_ready(&fei) {
+                    on_ready(&fei);
+                    reported.push(fei);
+                }
+            }
+            let dvrs = numbered_entries(&*self.filesystem, &adapter_directory, "dvr");
+            let is_complete = !reported.is_empty() && dvrs.iter().all(|n| reported.iter().any(|fei| fei.frontend == *n));
+            if is_complete || start.elapsed() >= timeout { break; }
+            thread::sleep(READINESS_POLL_INTERVAL);
+        }
+        reported
+    }
+
+    /// Return the adapter number of a path within the adapterXXX directory, where XXX
+    /// is pure numeric.
+    pub fn adapter_number_from(&self, path: &str) -> Option<u8> {
+        let regex = Regex::new(&format!(r"{}/adapter([0-9]+)(/|$)", regex::escape(self.base.to_str()?))).unwrap();
+        regex.captures(path).and_then(|captures| captures[1].parse::<u8>().ok())
+    }
+
+    /// Ensure the name is adaptorXXX /frontendYYY where XXX and YYY are pure numeric,
+    /// and return a `FrontendId` based on these numbers.
+    pub fn frontend_id_from(&self, path: &str) -> Option<FrontendId> {
+        let regex = Regex::new(&format!(r"{}/adapter([0-9]+)/frontend([0-9]+)", regex::escape(self.base.to_str()?))).unwrap();
+        let captures = regex.captures(path)?;
+        Some(FrontendId{adapter: captures[1].parse().ok()?, frontend: captures[2].parse().ok()?})
+    }
+
+    /// Return what a path in the DVB directory is, according to its name, None if it is
+    /// not in the DVB directory.
+    pub fn node_from(&self, path: &Path) -> Option<DvbNode> {
+        let mut names = path.strip_prefix(&self.base).ok()?.iter().map(|name| name.to_str());
+        let adapter = match names.next() {
+            None => return Some(DvbNode::Directory),
+            Some(name) => numbered_name(name?, "adapter")?,
+        };
+        let name = match names.next() {
+            None => return Some(DvbNode::Adapter(adapter)),
+            Some(name) => name?,
+        };
+        if names.next().is_some() { return None; }
+        Some(["frontend", "demux", "dvr"].iter()
+            .find_map(|prefix| numbered_name(name, prefix))
+            .map_or(DvbNode::AdapterNode(adapter), |frontend| DvbNode::FrontendNode(FrontendId{adapter, frontend})))
+    }
+
+    /// Return what sysfs says about the hardware of a frontend.
+    ///
+    /// USB devices have product and manufacturer attributes, though on the USB device
+    /// rather than the interface the frontend is attached to. Other buses, PCI for example,
+    /// only have numeric identities, so the driver name is used instead.
+    pub fn frontend_hardware(&self, fei: &FrontendId) -> FrontendHardware {
+        let sysfs_base = match &self.sysfs_base {
+            Some(sysfs_base) => sysfs_base,
+            None => return FrontendHardware::default(),
+        };
+        let device = sysfs_base.join(format!("dvb{}.frontend{}", fei.adapter, fei.frontend)).join("device");
+        let device = match fs::canonicalize(&device) {
+            Ok(device) => device,
+            Err(_) => return FrontendHardware::default(),
+        };
+        let product = |directory: &Path| {
+            let product = sysfs_attribute(&directory.join("product"))?;
+            Some(match sysfs_attribute(&directory.join("manufacturer")) {
+                Some(manufacturer) if !product.starts_with(&manufacturer) => format!("{} {}", manufacturer, product),
+                _ => product,
+            })
+        };
+        let name = product(&device)
+            .or_else(|| device.parent().and_then(product))
+            .or_else(|| link_target_name(&device.join("driver")));
+        let bus_info = match (link_target_name(&device.join("subsystem")), device.file_name().and_then(|n| n.to_str())) {
+            (Some(subsystem), Some(address)) => Some(format!("{} {}", subsystem, address)),
+            _ => None,
+        };
+        FrontendHardware{name, bus_info}
+    }
+}
+
+lazy_static! {
+    static ref DVB_DEVICES: RwLock<DvbDevices> = RwLock::new(DvbDevices::from_environment());
+}
+
+/// Use `devices` for all the DVB devices of this process, rather than those from
+/// `ME_TV_DVB_PATH` or /dev/dvb. For a command line option to call before anything
+/// looks for frontends.
+pub fn set_dvb_devices(devices: DvbDevices) {
+    *DVB_DEVICES.write().unwrap() = devices;
+}
+
+/// The DVB devices of this process.
+pub fn dvb_devices() -> DvbDevices {
+    DVB_DEVICES.read().unwrap().clone()
+}
+
+/// The path in the filesystem to the DVB related special files.
+pub fn dvb_base_path() -> PathBuf { dvb_devices().base().to_path_buf() }
+
+/// Return the path to the adapter director for a given adapter.
+pub fn adapter_path(id: u8) -> PathBuf { dvb_devices().adapter_path(id) }
+
+/// Return the path to the special file for a given frontend.
+pub fn frontend_path(fei: &FrontendId) -> PathBuf { dvb_devices().frontend_path(fei) }
+
+/// Return the path to the special file of the demux for a given frontend.
+pub fn demux_path(fei: &FrontendId) -> PathBuf { dvb_devices().demux_path(fei) }
+
+/// Return the path to the special file of the data for a given frontend.
+pub fn dvr_path(fei: &FrontendId) -> PathBuf { dvb_devices().dvr_path(fei) }
+
+/// Return the frontends currently installed on the system.
+pub fn installed_frontends() -> Vec<FrontendId> { dvb_devices().installed_frontends() }
+
+/// Return what sysfs says about the hardware of a frontend.
+pub fn frontend_hardware(fei: &FrontendId) -> FrontendHardware { dvb_devices().frontend_hardware(fei) }
+
+/// The number N of a name that is `prefix` followed by N, e.g. adapterN.
+fn numbered_name(name: &str, prefix: &str) -> Option<u8> {
+    let number = name.strip_prefix(prefix)?;
+    if !number.bytes().all(|b| b.is_ascii_digit()) { return None; }
+    number.parse::<u8>().ok()
+}
+
+/// Return the numbers of the entries in `directory` named `prefix` followed by a number,
+/// in numerical order, ignoring anything else.
+fn numbered_entries(filesystem: &dyn DeviceFilesystem, directory: &Path, prefix: &str) -> Vec<u8> {
+    let mut numbers = match filesystem.list_directory(directory) {
+        Ok(names) => names.iter()
+            .filter_map(|name| numbered_name(name, prefix))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    numbers.sort_unstable();
+    numbers
+}
+
+/// How often an adapter directory is looked at whilst waiting for its frontends.
+pub const READINESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What to tell the user when frontend `fei` cannot be used because the special files
+/// `missing` are not there.
+pub fn missing_devices_reason(fei: &FrontendId, missing: &[PathBuf]) -> String {
+    let paths = missing.iter().map(|path| path.display().to_string()).collect::<Vec<_>>();
+    let (paths, verb) = match paths.as_slice() {
+        [path] => (path.clone(), "is"),
+        [init @ .., last] => (format!("{} and {}", init.join(", "), last), "are"),
+        [] => return format!("{} has all its special files.", fei),
+    };
+    format!("{} cannot be used as {} {} missing or not a character device, check the driver and the udev rules.", fei, paths, verb)
+}
+
+/// Return the `FrontendId` of a sysfs DVB class entry name, dvbA.frontendB.
+fn frontend_id_from_sysfs_name(name: &str) -> Option<FrontendId> {
+    let regex = Regex::new(r"^dvb([0-9]+)\.frontend([0-9]+)$").unwrap();
+    let captures = regex.captures(name)?;
+    Some(FrontendId{adapter: captures[1].parse().ok()?, frontend: captures[2].parse().ok()?})
+}
+
+/// What a path in the DVB directory is.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DvbNode {
+    /// The DVB directory itself.
+    Directory,
+    /// An adapter directory.
+    Adapter(u8),
+    /// The frontendN, demuxN or dvrN special file of an adapter, those needed for tuning
+    /// frontend N.
+    FrontendNode(FrontendId),
+    /// Any other file in an adapter directory, netN or caN say.
+    AdapterNode(u8),
+}
+
+/// What sysfs says about the hardware a frontend is part of, for display. Either may be
+/// unknown, not all drivers provide the attributes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrontendHardware {
+    pub name: Option<String>,  // E.g. "Realtek RTL2838UHIDIR", or the driver name.
+    pub bus_info: Option<String>,  // E.g. "usb 1-2:1.0" or "pci 0000:03:00.0".
+}
+
+impl FrontendHardware {
+    /// A one line human readable description, if anything is known.
+    pub fn description(&self) -> Option<String> {
+        match (&self.name, &self.bus_info) {
+            (Some(name), Some(bus_info)) => Some(format!("{} on {}", name, bus_info)),
+            (Some(name), None) => Some(name.clone()),
+            (None, Some(bus_info)) => Some(format!("Unknown device on {}", bus_info)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The trimmed contents of a sysfs attribute file, None if absent or empty.
+fn sysfs_attribute(path: &Path) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?.trim().to_string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+/// The name of the file a symbolic link points at, e.g. the driver or subsystem name.
+fn link_target_name(path: &Path) -> Option<String> {
+    Some(fs::read_link(path).ok()?.file_name()?.to_str()?.to_string())
+}
+
+/// Serialised as the `Display` form so that configuration and state files are readable.
+#[cfg(feature = "serialize")]
+impl serde::Serialize for FrontendId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de> serde::Deserialize<'de> for FrontendId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<FrontendId, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Return the adapter number of a path within /dev/dvb/adapterXXX, or wherever the DVB
+/// special files are, where XXX is pure numeric.
+pub fn adapter_number_from(path: &str) -> Option<u8> { dvb_devices().adapter_number_from(path) }
+
+/// Ensure the name is adaptorXXX /frontendYYY where XXX and YYY are pure numeric,
+/// and return a `FrontendId` based on these numbers.
+pub fn frontend_id_from(path: &str) -> Option<FrontendId> { dvb_devices().frontend_id_from(path) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    quickcheck! {
+        fn adapter_path_is_correct(id: u8) -> bool {
+            DvbDevices::default().adapter_path(id).to_str().unwrap() == format!("/dev/dvb/adapter{}", id)
+        }
+    }
+
+    quickcheck! {
+        fn frontend_path_is_correct(a: u8, f: u8) -> bool {
+            DvbDevices::default().frontend_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/frontend{}", a, f)
+        }
+    }
+
+    quickcheck! {
+        fn demux_path_is_correct(a: u8, f: u8) -> bool {
+            DvbDevices::default().demux_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/demux{}", a, f)
+        }
+    }
+
+    quickcheck! {
+        fn dvr_path_is_correct(a: u8, f: u8) -> bool {
+            DvbDevices::default().dvr_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/dvr{}", a, f)
+        }
+    }
+
+    quickcheck! {
+        fn check_frontend_id_from_with_correct_structure(adapter: u8, frontend: u8) -> bool {
+            Some(FrontendId{adapter: adapter, frontend: frontend}) == DvbDevices::default().frontend_id_from(&format!("/dev/dvb/adapter{}/frontend{}", adapter, frontend))
+        }
+    }
+
+    /// Make a fake /dev/dvb, the frontends being symbolic links to /dev/null so that
+    /// they are character devices.
+    fn fake_dvb_tree(frontends: &[(&str, &str)]) -> tempfile::TempDir {
+        let base = tempfile::tempdir().unwrap();
+        for (adapter, frontend) in frontends {
+            let adapter_directory = base.path().join(adapter);
+            fs::create_dir_all(&adapter_directory).unwrap();
+            std::os::unix::fs::symlink("/dev/null", adapter_directory.join(frontend)).unwrap();
+        }
+        base
+    }
+
+    #[test]
+    fn adapters_are_found_despite_gaps_in_the_numbering() {
+        let base = fake_dvb_tree(&[("adapter4", "frontend0"), ("adapter7", "frontend0"), ("adapter7", "frontend2")]);
+        assert_eq!(DvbDevices::new(base.path()).special_file_frontends(), vec![
+            FrontendId{adapter: 4, frontend: 0},
+            FrontendId{adapter: 7, frontend: 0},
+            FrontendId{adapter: 7, frontend: 2},
+        ]);
+    }
+
+    #[test]
+    fn adapters_are_sorted_numerically() {
+        let base = fake_dvb_tree(&[("adapter10", "frontend0"), ("adapter9", "frontend0"), ("adapter1", "frontend0")]);
+        let adapters = DvbDevices::new(base.path()).special_file_frontends().iter().map(|fei| fei.adapter).collect::<Vec<_>>();
+        assert_eq!(adapters, vec![1, 9, 10]);
+    }
+
+    #[test]
+    fn other_entries_and_regular_files_are_ignored() {
+        let base = fake_dvb_tree(&[("adapter0", "frontend0")]);
+        fs::create_dir(base.path().join("adapterX")).unwrap();
+        fs::create_dir(base.path().join("adapter")).unwrap();
+        fs::write(base.path().join("adapter0").join("frontend1"), "").unwrap();
+        fs::write(base.path().join("adapter0").join("frontend1a"), "").unwrap();
+        assert_eq!(DvbDevices::new(base.path()).special_file_frontends(), vec![FrontendId{adapter: 0, frontend: 0}]);
+    }
+
+    #[test]
+    fn frontends_are_found_despite_gaps_in_the_numbering() {
+        let base = fake_dvb_tree(&[("adapter0", "frontend0"), ("adapter0", "frontend2"), ("adapter0", "frontend11")]);
+        let frontends = DvbDevices::new(base.path()).adapter_frontends(0).iter().map(|fei| fei.frontend).collect::<Vec<_>>();
+        assert_eq!(frontends, vec![0, 2, 11]);
+    }
+
+    #[test]
+    fn adapter_with_no_frontends_gives_no_frontends() {
+        let base = fake_dvb_tree(&[("adapter1", "frontend0")]);
+        fs::create_dir(base.path().join("adapter0")).unwrap();
+        assert_eq!(DvbDevices::new(base.path()).adapter_frontends(0), vec![]);
+        assert_eq!(DvbDevices::new(base.path()).special_file_frontends(), vec![FrontendId{adapter: 1, frontend: 0}]);
+    }
+
+    #[test]
+    fn entries_that_are_not_character_devices_are_skipped() {
+        let base = fake_dvb_tree(&[("adapter0", "frontend1")]);
+        fs::write(base.path().join("adapter0").join("frontend0"), "").unwrap();
+        fs::create_dir(base.path().join("adapter0").join("frontend2")).unwrap();
+        assert_eq!(DvbDevices::new(base.path()).adapter_frontends(0), vec![FrontendId{adapter: 0, frontend: 1}]);
+    }
+
+    #[test]
+    fn frontend_appearing_after_its_adapter_directory_is_found() {
+        let base = tempfile::tempdir().unwrap();
+        let adapter_directory = base.path().join("adapter3");
+        fs::create_dir(&adapter_directory).unwrap();
+        assert_eq!(DvbDevices::new(base.path()).special_file_frontends(), vec![]);
+        std::os::unix::fs::symlink("/dev/null", adapter_directory.join("frontend0")).unwrap();
+        assert_eq!(DvbDevices::new(base.path()).special_file_frontends(), vec![FrontendId{adapter: 3, frontend: 0}]);
+    }
+
+    quickcheck! {
+        fn frontend_id_display_round_trips(adapter: u8, frontend: u8) -> bool {
+            let fei = FrontendId{adapter, frontend};
+            fei.to_string().parse::<FrontendId>() == Ok(fei)
+        }
+    }
+
+    quickcheck! {
+        fn frontend_id_numbers_only_form_is_parsed(adapter: u8, frontend: u8) -> bool {
+            format!("{}:{}", adapter, frontend).parse::<FrontendId>() == Ok(FrontendId{adapter, frontend})
+        }
+    }
+
+    quickcheck! {
+        fn frontend_id_ordering_is_adapter_major(a: (u8, u8), b: (u8, u8)) -> bool {
+            let a_fei = FrontendId{adapter: a.0, frontend: a.1};
+            let b_fei = FrontendId{adapter: b.0, frontend: b.1};
+            a_fei.cmp(&b_fei) == a.cmp(&b)
+        }
+    }
+
+    #[test]
+    fn frontend_id_display() {
+        assert_eq!(FrontendId{adapter: 0, frontend: 1}.to_string(), "adapter0:frontend1");
+    }
+
+    #[test]
+    fn frontend_id_from_str_errors_say_what_is_wrong() {
+        assert_eq!("2".parse::<FrontendId>(), Err("'2' is not of the form ADAPTER:FRONTEND, e.g. 0:1 or adapter0:frontend1.".to_string()));
+        assert_eq!("2:x".parse::<FrontendId>(), Err("'x' in '2:x' is not a valid frontend number.".to_string()));
+        assert_eq!("adapter:0".parse::<FrontendId>(), Err("'adapter' in 'adapter:0' is not a valid adapter number.".to_string()));
+        assert_eq!("300:0".parse::<FrontendId>(), Err("The adapter number 300 in '300:0' is greater than 255.".to_string()));
+        assert!("1:2:3".parse::<FrontendId>().is_err());
+        assert!("+1:0".parse::<FrontendId>().is_err());
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn frontend_id_serialises_as_its_display_form() {
+        #[derive(Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+        struct Recording {
+            frontend: FrontendId,
+        }
+        let fei = FrontendId{adapter: 2, frontend: 1};
+        assert_eq!(serde_json::to_string(&fei).unwrap(), "\"adapter2:frontend1\"");
+        assert_eq!(serde_json::from_str::<FrontendId>("\"adapter2:frontend1\"").unwrap(), fei);
+        assert!(serde_json::from_str::<FrontendId>("\"adapter2\"").is_err());
+        let recording = Recording{frontend: fei};
+        let toml = toml::to_string(&recording).unwrap();
+        assert_eq!(toml, "frontend = \"adapter2:frontend1\"\n");
+        assert_eq!(toml::from_str::<Recording>(&toml).unwrap(), recording);
+    }
+
+    #[test]
+    fn frontends_already_present_are_reported_without_waiting() {
+        let base = fake_dvb_tree(&[("adapter2", "frontend0"), ("adapter2", "demux0"), ("adapter2", "dvr0")]);
+        let mut seen = Vec::new();
+        let start = Instant::now();
+        let reported = DvbDevices::new(base.path()).wait_for_frontends(2, Duration::from_secs(5), |_| true, |fei| seen.push(fei.clone()));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(reported, vec![FrontendId{adapter: 2, frontend: 0}]);
+        assert_eq!(seen, reported);
+    }
+
+    #[test]
+    fn frontends_appearing_late_are_waited_for() {
+        let base = fake_dvb_tree(&[("adapter0", "demux0"), ("adapter0", "dvr0")]);
+        let adapter_directory = base.path().join("adapter0");
+        let creator = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            std::os::unix::fs::symlink("/dev/null", adapter_directory.join("frontend0")).unwrap();
+        });
+        let reported = DvbDevices::new(base.path()).wait_for_frontends(0, Duration::from_secs(5), |_| true, |_| {});
+        creator.join().unwrap();
+        assert_eq!(reported, vec![FrontendId{adapter: 0, frontend: 0}]);
+    }
+
+    #[test]
+    fn frontends_not_ready_are_not_reported() {
+        let base = fake_dvb_tree(&[
+            ("adapter0", "frontend0"), ("adapter0", "demux0"), ("adapter0", "dvr0"),
+            ("adapter0", "frontend1"), ("adapter0", "demux1"), ("adapter0", "dvr1"),
+        ]);
+        let reported = DvbDevices::new(base.path()).wait_for_frontends(0, Duration::from_millis(250), |fei| fei.frontend == 1, |_| {});
+        assert_eq!(reported, vec![FrontendId{adapter: 0, frontend: 1}]);
+    }
+
+    #[test]
+    fn waiting_gives_up_at_the_timeout() {
+        let base = tempfile::tempdir().unwrap();
+        fs::create_dir(base.path().join("adapter5")).unwrap();
+        let start = Instant::now();
+        assert_eq!(DvbDevices::new(base.path()).wait_for_frontends(5, Duration::from_millis(300), |_| true, |_| {}), vec![]);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn adapter_number_is_found_in_paths_within_the_adapter() {
+        let devices = DvbDevices::default();
+        assert_eq!(devices.adapter_number_from("/dev/dvb/adapter3"), Some(3));
+        assert_eq!(devices.adapter_number_from("/dev/dvb/adapter12/dvr0"), Some(12));
+        assert_eq!(devices.adapter_number_from("/dev/dvb/adapterX/dvr0"), None);
+        assert_eq!(devices.adapter_number_from("/dev/video0"), None);
+    }
+
+    quickcheck! {
+        fn paths_are_under_a_custom_base(a: u8, f: u8) -> bool {
+            let devices = DvbDevices::new("/srv/dvb");
+            let fei = FrontendId{adapter: a, frontend: f};
+            devices.adapter_path(a).to_str().unwrap() == format!("/srv/dvb/adapter{}", a)
+                && devices.frontend_path(&fei).to_str().unwrap() == format!("/srv/dvb/adapter{}/frontend{}", a, f)
+                && devices.demux_path(&fei).to_str().unwrap() == format!("/srv/dvb/adapter{}/demux{}", a, f)
+                && devices.dvr_path(&fei).to_str().unwrap() == format!("/srv/dvb/adapter{}/dvr{}", a, f)
+                && devices.frontend_id_from(devices.frontend_path(&fei).to_str().unwrap()) == Some(fei.clone())
+                && devices.adapter_number_from(devices.dvr_path(&fei).to_str().unwrap()) == Some(a)
+        }
+    }
+
+    #[test]
+    fn nodes_are_recognised_from_their_paths() {
+        let devices = DvbDevices::new("/srv/dvb");
+        let node = |path: &str| devices.node_from(Path::new(path));
+        assert_eq!(node("/srv/dvb"), Some(DvbNode::Directory));
+        assert_eq!(node("/srv/dvb/adapter3"), Some(DvbNode::Adapter(3)));
+        assert_eq!(node("/srv/dvb/adapter3/frontend1"), Some(DvbNode::FrontendNode(FrontendId{adapter: 3, frontend: 1})));
+        assert_eq!(node("/srv/dvb/adapter3/demux1"), Some(DvbNode::FrontendNode(FrontendId{adapter: 3, frontend: 1})));
+        assert_eq!(node("/srv/dvb/adapter3/dvr0"), Some(DvbNode::FrontendNode(FrontendId{adapter: 3, frontend: 0})));
+        assert_eq!(node("/srv/dvb/adapter3/net0"), Some(DvbNode::AdapterNode(3)));
+        assert_eq!(node("/srv/dvb/adapter3/frontend"), Some(DvbNode::AdapterNode(3)));
+        assert_eq!(node("/srv/dvb/adapterX/frontend0"), None);
+        assert_eq!(node("/srv/dvb/adapter3/frontend0/x"), None);
+        assert_eq!(node("/dev/dvb/adapter3/frontend0"), None);
+    }
+
+    #[test]
+    fn paths_outside_a_custom_base_are_not_recognised() {
+        let devices = DvbDevices::new("/srv/dvb");
+        assert_eq!(devices.frontend_id_from("/dev/dvb/adapter0/frontend0"), None);
+        assert_eq!(devices.adapter_number_from("/dev/dvb/adapter0"), None);
+        assert_eq!(devices.frontend_id_from("/srv/dvb/adapter300/frontend0"), None);
+    }
+
+    #[test]
+    fn custom_base_does_not_use_sysfs() {
+        let sysfs = fake_sysfs_tree(&["dvb3.frontend0"]);
+        let dev = fake_dvb_tree(&[("adapter0", "frontend0")]);
+        let devices = DvbDevices::new(dev.path());
+        assert_eq!(devices.installed_frontends(), vec![FrontendId{adapter: 0, frontend: 0}]);
+        assert_eq!(devices.frontend_hardware(&FrontendId{adapter: 0, frontend: 0}), FrontendHardware::default());
+        assert_eq!(DvbDevices::new(DEFAULT_DVB_PATH), DvbDevices::default());
+        assert_eq!(devices.with_sysfs(sysfs.path().join("class").join("dvb")).installed_frontends(), vec![FrontendId{adapter: 3, frontend: 0}]);
+    }
+
+    #[test]
+    fn tree_of_regular_files_gives_no_frontends() {
+        let base = tempfile::tempdir().unwrap();
+        let adapter_directory = base.path().join("adapter0");
+        fs::create_dir(&adapter_directory).unwrap();
+        for name in &["frontend0", "demux0", "dvr0"] {
+            fs::write(adapter_directory.join(name), "").unwrap();
+        }
+        let devices = DvbDevices::new(base.path());
+        assert_eq!(devices.installed_frontends(), vec![]);
+        assert_eq!(devices.wait_for_frontends(0, Duration::from_millis(200), |_| true, |_| {}), vec![]);
+    }
+
+    /// A `DvbDevices` on a fake /dev/dvb, with no sysfs, and the fake to change.
+    fn fake_devices() -> (DvbDevices, FakeFilesystem) {
+        let filesystem = FakeFilesystem::new();
+        filesystem.add_directory("/srv/dvb");
+        (DvbDevices::new("/srv/dvb").with_filesystem(filesystem.clone()), filesystem)
+    }
+
+    #[test]
+    fn missing_demux_and_dvr_are_found() {
+        let (devices, filesystem) = fake_devices();
+        let fei = FrontendId{adapter: 0, frontend: 1};
+        filesystem.add_char_device("/srv/dvb/adapter0/frontend1");
+        filesystem.add_file("/srv/dvb/adapter0/dvr1");
+        let missing = devices.missing_devices(&fei);
+        assert_eq!(missing, vec![PathBuf::from("/srv/dvb/adapter0/demux1"), PathBuf::from("/srv/dvb/adapter0/dvr1")]);
+        assert_eq!(
+            missing_devices_reason(&fei, &missing),
+            "adapter0:frontend1 cannot be used as /srv/dvb/adapter0/demux1 and /srv/dvb/adapter0/dvr1 are missing or not a character device, check the driver and the udev rules."
+        );
+        assert_eq!(
+            missing_devices_reason(&fei, &missing[1..]),
+            "adapter0:frontend1 cannot be used as /srv/dvb/adapter0/dvr1 is missing or not a character device, check the driver and the udev rules."
+        );
+        filesystem.add_char_device("/srv/dvb/adapter0/demux1");
+        filesystem.add_char_device("/srv/dvb/adapter0/dvr1");
+        assert_eq!(devices.missing_devices(&fei), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn frontend_is_not_ready_until_its_demux_and_dvr_are() {
+        let (devices, filesystem) = fake_devices();
+        filesystem.add_char_device("/srv/dvb/adapter2/frontend0");
+        filesystem.add_char_device("/srv/dvb/adapter2/dvr0");
+        let creator = thread::spawn({
+            let filesystem = filesystem.clone();
+            move || {
+                thread::sleep(Duration::from_millis(200));
+                filesystem.add_char_device("/srv/dvb/adapter2/demux0");
+            }
+        });
+        let start = Instant::now();
+        let reported = devices.wait_for_frontends(2, Duration::from_secs(5), |_| true, |_| {});
+        creator.join().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(reported, vec![FrontendId{adapter: 2, frontend: 0}]);
+    }
+
+    #[test]
+    fn waiting_for_devices_gives_up_with_those_missing() {
+        let (devices, filesystem) = fake_devices();
+        let fei = FrontendId{adapter: 0, frontend: 0};
+        filesystem.add_char_device("/srv/dvb/adapter0/frontend0");
+        filesystem.add_char_device("/srv/dvb/adapter0/demux0");
+        assert_eq!(devices.wait_for_devices(&fei, Duration::from_millis(200)), vec![PathBuf::from("/srv/dvb/adapter0/dvr0")]);
+        filesystem.add_char_device("/srv/dvb/adapter0/dvr0");
+        assert_eq!(devices.wait_for_devices(&fei, Duration::from_secs(5)), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn fake_adapter_with_frontend0_and_frontend2() {
+        let (devices, filesystem) = fake_devices();
+        filesystem.add_char_device("/srv/dvb/adapter0/frontend0");
+        filesystem.add_char_device("/srv/dvb/adapter0/frontend2");
+        filesystem.add_char_device("/srv/dvb/adapter0/dvr0");
+        assert_eq!(devices.installed_frontends(), vec![FrontendId{adapter: 0, frontend: 0}, FrontendId{adapter: 0, frontend: 2}]);
+    }
+
+    #[test]
+    fn fake_entries_that_are_not_character_devices_are_skipped() {
+        let (devices, filesystem) = fake_devices();
+        filesystem.add_file("/srv/dvb/adapter0/frontend0");
+        filesystem.add_directory("/srv/dvb/adapter0/frontend1");
+        filesystem.add_char_device("/srv/dvb/adapter0/frontend2");
+        filesystem.add_file("/srv/dvb/adapter1");
+        assert_eq!(devices.installed_frontends(), vec![FrontendId{adapter: 0, frontend: 2}]);
+        assert!(devices.is_adapter_present(0));
+        assert!(!devices.is_adapter_present(1));
+    }
+
+    #[test]
+    fn adapter_appearing_with_frontends_300ms_later() {
+        let (devices, filesystem) = fake_devices();
+        for name in &["demux0", "dvr0", "demux1", "dvr1"] {
+            filesystem.add_char_device(Path::new("/srv/dvb/adapter1").join(name));
+        }
+        assert_eq!(devices.installed_frontends(), vec![]);
+        let creator = thread::spawn({
+            let filesystem = filesystem.clone();
+            move || {
+                thread::sleep(Duration::from_millis(300));
+                filesystem.add_char_device("/srv/dvb/adapter1/frontend0");
+                filesystem.add_char_device("/srv/dvb/adapter1/frontend1");
+            }
+        });
+        let start = Instant::now();
+        let mut seen = Vec::new();
+        let reported = devices.wait_for_frontends(1, Duration::from_secs(5), |_| true, |fei| seen.push(fei.clone()));
+        creator.join().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(reported, vec![FrontendId{adapter: 1, frontend: 0}, FrontendId{adapter: 1, frontend: 1}]);
+        assert_eq!(seen, reported);
+    }
+
+    #[test]
+    fn adapters_are_those_with_directories() {
+        let (devices, filesystem) = fake_devices();
+        filesystem.remove("/srv/dvb");
+        assert_eq!(devices.adapters(), vec![]);
+        filesystem.add_directory("/srv/dvb/adapter3");
+        filesystem.add_char_device("/srv/dvb/adapter1/frontend0");
+        filesystem.add_file("/srv/dvb/adapter2");
+        assert_eq!(devices.adapters(), vec![1, 3]);
+    }
+
+    #[test]
+    fn adapter_disappearing_while_another_stays() {
+        let (devices, filesystem) = fake_devices();
+        filesystem.add_char_device("/srv/dvb/adapter0/frontend0");
+        filesystem.add_char_device("/srv/dvb/adapter1/frontend0");
+        filesystem.add_char_device("/srv/dvb/adapter1/frontend1");
+        assert_eq!(devices.installed_frontends().len(), 3);
+        filesystem.remove("/srv/dvb/adapter1");
+        assert_eq!(devices.installed_frontends(), vec![FrontendId{adapter: 0, frontend: 0}]);
+        assert!(!devices.is_adapter_present(1));
+        assert_eq!(devices.adapter_frontends(1), vec![]);
+    }
+
+    #[test]
+    fn fake_sysfs_is_used_for_discovery() {
+        let (devices, filesystem) = fake_devices();
+        filesystem.add_directory("/sys/class/dvb/dvb0.frontend0");
+        filesystem.add_directory("/sys/class/dvb/dvb0.demux0");
+        filesystem.add_directory("/sys/class/dvb/dvb2.frontend1");
+        let devices = devices.with_sysfs("/sys/class/dvb");
+        assert_eq!(devices.installed_frontends(), vec![FrontendId{adapter: 0, frontend: 0}, FrontendId{adapter: 2, frontend: 1}]);
+    }
+
+    #[test]
+    fn environment_variable_sets_the_base() {
+        env::set_var(DVB_PATH_VARIABLE, "/srv/dvb");
+        let devices = DvbDevices::from_environment();
+        env::remove_var(DVB_PATH_VARIABLE);
+        assert_eq!(devices.base(), Path::new("/srv/dvb"));
+        assert_eq!(DvbDevices::from_environment(), DvbDevices::default());
+    }
+
+    /// Make a fake /sys/class/dvb and /sys/devices, each DVB class entry having a device
+    /// link to a device directory, as the kernel does.
+    fn fake_sysfs_tree(entries: &[&str]) -> tempfile::TempDir {
+        let base = tempfile::tempdir().unwrap();
+        let class = base.path().join("class").join("dvb");
+        fs::create_dir_all(&class).unwrap();
+        for entry in entries {
+            fs::create_dir(class.join(entry)).unwrap();
+        }
+        base
+    }
+
+    /// Make a device directory in the fake sysfs, linked to from a DVB class entry.
+    fn fake_sysfs_device(base: &Path, entry: &str, device: &str, subsystem: &str) -> PathBuf {
+        let device_directory = base.join("devices").join(device);
+        fs::create_dir_all(&device_directory).unwrap();
+        let subsystem_directory = base.join("bus").join(subsystem);
+        fs::create_dir_all(&subsystem_directory).unwrap();
+        std::os::unix::fs::symlink(&subsystem_directory, device_directory.join("subsystem")).unwrap();
+        std::os::unix::fs::symlink(&device_directory, base.join("class").join("dvb").join(entry).join("device")).unwrap();
+        device_directory
+    }
+
+    #[test]
+    fn sysfs_frontends_are_found_and_sorted() {
+        let base = fake_sysfs_tree(&["dvb1.frontend0", "dvb0.demux0", "dvb0.frontend1", "dvb0.dvr0", "dvb10.frontend0", "dvb0.frontend0", "dvbX.frontend0"]);
+        assert_eq!(DvbDevices::new(base.path()).with_sysfs(base.path().join("class").join("dvb")).installed_frontends(), vec![
+            FrontendId{adapter: 0, frontend: 0},
+            FrontendId{adapter: 0, frontend: 1},
+            FrontendId{adapter: 1, frontend: 0},
+            FrontendId{adapter: 10, frontend: 0},
+        ]);
+    }
+
+    #[test]
+    fn sysfs_is_preferred_and_dev_used_without_it() {
+        let sysfs = fake_sysfs_tree(&["dvb3.frontend0"]);
+        let dev = fake_dvb_tree(&[("adapter0", "frontend0")]);
+        assert_eq!(DvbDevices::new(dev.path()).with_sysfs(sysfs.path().join("class").join("dvb")).installed_frontends(), vec![FrontendId{adapter: 3, frontend: 0}]);
+        assert_eq!(DvbDevices::new(dev.path()).with_sysfs(sysfs.path().join("nothing")).installed_frontends(), vec![FrontendId{adapter: 0, frontend: 0}]);
+    }
+
+    #[test]
+    fn usb_hardware_is_described_by_the_usb_device() {
+        let base = fake_sysfs_tree(&["dvb0.frontend0"]);
+        let interface = fake_sysfs_device(base.path(), "dvb0.frontend0", "usb1/1-2/1-2:1.0", "usb");
+        let usb_device = interface.parent().unwrap();
+        fs::write(usb_device.join("product"), "RTL2838UHIDIR\n").unwrap();
+        fs::write(usb_device.join("manufacturer"), "Realtek\n").unwrap();
+        let hardware = DvbDevices::new(base.path()).with_sysfs(base.path().join("class").join("dvb")).frontend_hardware(&FrontendId{adapter: 0, frontend: 0});
+        assert_eq!(hardware, FrontendHardware{name: Some("Realtek RTL2838UHIDIR".to_string()), bus_info: Some("usb 1-2:1.0".to_string())});
+        assert_eq!(hardware.description(), Some("Realtek RTL2838UHIDIR on usb 1-2:1.0".to_string()));
+    }
+
+    #[test]
+    fn pci_hardware_is_described_by_the_driver() {
+        let base = fake_sysfs_tree(&["dvb1.frontend0"]);
+        let device = fake_sysfs_device(base.path(), "dvb1.frontend0", "pci0000:00/0000:03:00.0", "pci");
+        let driver = base.path().join("bus").join("pci").join("drivers").join("cx23885");
+        fs::create_dir_all(&driver).unwrap();
+        std::os::unix::fs::symlink(&driver, device.join("driver")).unwrap();
+        let hardware = DvbDevices::new(base.path()).with_sysfs(base.path().join("class").join("dvb")).frontend_hardware(&FrontendId{adapter: 1, frontend: 0});
+        assert_eq!(hardware, FrontendHardware{name: Some("cx23885".to_string()), bus_info: Some("pci 0000:03:00.0".to_string())});
+    }
+
+    #[test]
+    fn hardware_of_an_unknown_frontend_is_unknown() {
+        let base = fake_sysfs_tree(&["dvb0.frontend0"]);
+        let hardware = DvbDevices::new(base.path()).with_sysfs(base.path().join("class").join("dvb")).frontend_hardware(&FrontendId{adapter: 0, frontend: 0});
+        assert_eq!(hardware, FrontendHardware::default());
+        assert_eq!(hardware.description(), None);
+    }
+
+    #[test]
+    fn missing_base_directory_gives_no_frontends() {
+        let base = tempfile::tempdir().unwrap();
+        assert_eq!(DvbDevices::new(base.path().join("dvb")).special_file_frontends(), vec![]);
+    }
+
+    quickcheck! {
+        fn check_frontend_id_from_with_incorrect_structure(prefix: String, postfix: String, adapter: u8, frontend: u8) -> bool {
+            None == DvbDevices::default().frontend_id_from(&format!("{}/adapter{}/frontend{}{}", prefix, adapter, frontend, postfix))
+         }
+    }
+
+}
@@ -0,0 +1,243 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The genres of programmes. An event of the EIT has content descriptors, ETSI EN 300 468
+//! §6.2.9, each a level 1 nibble for the broad category, movie, news, sport and so on,
+//! and a level 2 nibble for what within it, table 29. These are turned into the names of
+//! genres, the category first, as XMLTV listings have the names of their categories.
+//!
+//! The categories are also what the programme guide colours programmes by, and what the
+//! search of it can look for, so the categories of XMLTV listings, which grabbers name
+//! as they please, are recognised by the words in them.
+
+use std::fmt;
+
+use crate::name_matching::fold;
+
+/// The tag of a content descriptor.
+pub const CONTENT_DESCRIPTOR_TAG: u8 = 0x54;
+
+/// The name given to a genre that is none of those of the standard, reserved or
+/// undefined.
+const OTHER: &str = "Other";
+
+/// The name given to a genre that the broadcaster has defined for itself.
+const BROADCASTER_DEFINED: &str = "Broadcaster defined";
+
+/// The broad categories of programmes, the level 1 nibbles 0x1 to 0xa of a content
+/// descriptor.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Category {
+    MovieDrama,
+    NewsCurrentAffairs,
+    ShowGameShow,
+    Sports,
+    ChildrensYouth,
+    MusicBalletDance,
+    ArtsCulture,
+    SocialPoliticalEconomics,
+    EducationScienceFactual,
+    LeisureHobbies,
+}
+
+impl Category {
+    /// All the categories, in the order of the standard.
+    pub const ALL: [Category; 10] = [
+        Category::MovieDrama,
+        Category::NewsCurrentAffairs,
+        Category::ShowGameShow,
+        Category::Sports,
+        Category::ChildrensYouth,
+        Category::MusicBalletDance,
+        Category::ArtsCulture,
+        Category::SocialPoliticalEconomics,
+        Category::EducationScienceFactual,
+        Category::LeisureHobbies,
+    ];
+
+    /// The category of the level 1 nibble `nibble`, if it is one.
+    fn of_level_1(nibble: u8) -> Option<Category> {
+        Category::ALL.get(usize::from(nibble).checked_sub(1)?).copied()
+    }
+
+    /// The name of the category, as it is given as a genre.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::MovieDrama => "Movie/Drama",
+            Category::NewsCurrentAffairs => "News/Current affairs",
+            Category::ShowGameShow => "Show/Game show",
+            Category::Sports => "Sports",
+            Category::ChildrensYouth => "Children's/Youth",
+            Category::MusicBalletDance => "Music/Ballet/Dance",
+            Category::ArtsCulture => "Arts/Culture",
+            Category::SocialPoliticalEconomics => "Social/Political issues/Economics",
+            Category::EducationScienceFactual => "Education/Science/Factual",
+            Category::LeisureHobbies => "Leisure hobbies",
+        }
+    }
+
+    /// The genres of the level 2 nibbles 0x1 onwards of the category, 0x0 being the
+    /// category in general.
+    fn level_2(self) -> &'static [&'static str] {
+        match self {
+            Category::MovieDrama => &[
+                "Detective/Thriller", "Adventure/Western/War", "Science fiction/Fantasy/Horror", "Comedy",
+                "Soap/Melodrama/Folklore", "Romance", "Serious/Classical/Religious/Historical drama", "Adult drama",
+            ],
+            Category::NewsCurrentAffairs => &["News/Weather report", "News magazine", "Documentary", "Discussion/Interview/Debate"],
+            Category::ShowGameShow => &["Game show/Quiz/Contest", "Variety show", "Talk show"],
+            Category::Sports => &[
+                "Special sports events", "Sports magazine", "Football/Soccer", "Tennis/Squash", "Team sports",
+                "Athletics", "Motor sport", "Water sport", "Winter sports", "Equestrian", "Martial sports",
+            ],
+            Category::ChildrensYouth => &[
+                "Pre-school children's", "Entertainment for 6 to 14", "Entertainment for 10 to 16",
+                "Informational/Educational/School", "Cartoons/Puppets",
+            ],
+            Category::MusicBalletDance => &["Rock/Pop", "Serious/Classical music", "Folk/Traditional music", "Jazz", "Musical/Opera", "Ballet"],
+            Category::ArtsCulture => &[
+                "Performing arts", "Fine arts", "Religion", "Popular culture/Traditional arts", "Literature", "Film/Cinema",
+                "Experimental film/video", "Broadcasting/Press", "New media", "Arts/Culture magazine", "Fashion",
+            ],
+            Category::SocialPoliticalEconomics => &["Magazines/Reports/Documentary", "Economics/Social advisory", "Remarkable people"],
+            Category::EducationScienceFactual => &[
+                "Nature/Animals/Environment", "Technology/Natural sciences", "Medicine/Physiology/Psychology",
+                "Foreign countries/Expeditions", "Social/Spiritual sciences", "Further education", "Languages",
+            ],
+            Category::LeisureHobbies => &[
+                "Tourism/Travel", "Handicraft", "Motoring", "Fitness and health", "Cooking", "Advertisement/Shopping", "Gardening",
+            ],
+        }
+    }
+
+    /// The words, folded, that a category of XMLTV listings with the category has in it.
+    fn words(self) -> &'static [&'static str] {
+        match self {
+            Category::MovieDrama => &["film", "movie", "drama", "soap", "comedy", "sitcom", "thriller", "western"],
+            Category::NewsCurrentAffairs => &["news", "current affairs", "documentary", "weather", "debate"],
+            Category::ShowGameShow => &["game show", "quiz", "entertainment", "talk show", "variety", "reality"],
+            Category::Sports => &["sport", "football", "soccer", "rugby", "cricket", "tennis", "golf", "athletics", "racing"],
+            Category::ChildrensYouth => &["children", "kids", "youth", "cartoon", "animation", "pre-school"],
+            Category::MusicBalletDance => &["music", "ballet", "dance", "opera", "concert"],
+            Category::ArtsCulture => &["arts", "culture", "religio", "literature", "fashion"],
+            Category::SocialPoliticalEconomics => &["politic", "economic", "social", "business", "consumer"],
+            Category::EducationScienceFactual => &["education", "science", "nature", "factual", "history", "technology"],
+            Category::LeisureHobbies => &["leisure", "hobb", "cook", "food", "travel", "lifestyle", "gardening", "shopping", "motoring", "health"],
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The special characteristics, the level 2 nibbles 0x0 onwards of level 1 nibble 0xb,
+/// which say something of a programme rather than what it is about.
+const SPECIAL_CHARACTERISTICS: &[&str] = &["Original language", "Black and white", "Unpublished", "Live broadcast", "Plano-stereoscopic", "Local or regional"];
+
+/// The genres of the content nibbles `nibbles` of a content descriptor, level 1 in the
+/// high four bits, level 2 in the low: the category, then what within it if that is
+/// more than the category in general. A level 2 nibble that is reserved or defined by
+/// the broadcaster is the category alone, and a level 1 nibble that is reserved or
+/// undefined is "Other", so that no content descriptor is without a genre.
+pub fn content_genres(nibbles: u8) -> Vec<String> {
+    let (level_1, level_2) = (nibbles >> 4, usize::from(nibbles & 0x0f));
+    match Category::of_level_1(level_1) {
+        Some(category) => {
+            let mut genres = vec![category.name().to_string()];
+            if let Some(genre) = level_2.checked_sub(1).and_then(|i| category.level_2().get(i)) {
+                genres.push(genre.to_string());
+            }
+            genres
+        },
+        None => vec![match level_1 {
+            0xb => SPECIAL_CHARACTERISTICS.get(level_2).copied().unwrap_or("Special characteristics"),
+            0xf => BROADCASTER_DEFINED,
+            _ => OTHER,
+        }.to_string()],
+    }
+}
+
+/// The genres of all the content nibbles `content`, each once, in order.
+pub fn genres_of_content(content: &[u8]) -> Vec<String> {
+    let mut genres = Vec::<String>::new();
+    for genre in content.iter().flat_map(|nibbles| content_genres(*nibbles)) {
+        if !genres.contains(&genre) {
+            genres.push(genre);
+        }
+    }
+    genres
+}
+
+/// The category of a programme of the genres `genres`: that of the first of them that
+/// is the name of a category, or has a word of one in it, if any does.
+pub fn category_of(genres: &[String]) -> Option<Category> {
+    genres.iter().find_map(|genre| {
+        Category::ALL.iter().copied().find(|category| category.name() == genre).or_else(|| {
+            let folded = fold(genre);
+            Category::ALL.iter().copied().find(|category| category.words().iter().any(|word| folded.contains(word)))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nibbles_are_the_category_then_the_genre_within_it() {
+        assert_eq!(content_genres(0x10), vec!["Movie/Drama"]);
+        assert_eq!(content_genres(0x43), vec!["Sports", "Football/Soccer"]);
+        assert_eq!(content_genres(0x23), vec!["News/Current affairs", "Documentary"]);
+        assert_eq!(content_genres(0xa7), vec!["Leisure hobbies", "Gardening"]);
+        assert_eq!(content_genres(0xb3), vec!["Live broadcast"]);
+    }
+
+    #[test]
+    fn reserved_and_user_defined_nibbles_still_have_a_genre() {
+        assert_eq!(content_genres(0x1f), vec!["Movie/Drama"]);
+        assert_eq!(content_genres(0x3a), vec!["Show/Game show"]);
+        assert_eq!(content_genres(0x00), vec!["Other"]);
+        assert_eq!(content_genres(0xc2), vec!["Other"]);
+        assert_eq!(content_genres(0xbe), vec!["Special characteristics"]);
+        assert_eq!(content_genres(0xf4), vec!["Broadcaster defined"]);
+    }
+
+    #[test]
+    fn genres_are_each_given_once() {
+        assert_eq!(genres_of_content(&[0x41, 0x43, 0xb3]), vec!["Sports", "Special sports events", "Football/Soccer", "Live broadcast"]);
+        assert!(genres_of_content(&[]).is_empty());
+    }
+
+    #[test]
+    fn categories_of_eit_and_xmltv_genres_are_recognised() {
+        let genres = |gs: &[&str]| gs.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        assert_eq!(category_of(&content_genres(0x51)), Some(Category::ChildrensYouth));
+        assert_eq!(category_of(&genres(&["Live broadcast", "Sports"])), Some(Category::Sports));
+        assert_eq!(category_of(&genres(&["Sport"])), Some(Category::Sports));
+        assert_eq!(category_of(&genres(&["Film", "Drama"])), Some(Category::MovieDrama));
+        assert_eq!(category_of(&genres(&["Cookery"])), Some(Category::LeisureHobbies));
+        assert_eq!(category_of(&genres(&["Interests"])), None);
+        assert_eq!(category_of(&[]), None);
+    }
+}
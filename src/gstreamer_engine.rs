@@ -19,8 +19,12 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use std::cell::RefCell;
+use std::path::Path;
 use std::process::Command;
 use std::rc::Rc;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
 
 //use gio;
 //use gio::prelude::*;
@@ -37,10 +41,21 @@ use gst_mpegts;
 
 use fragile::Fragile;
 
+use log::{debug, warn};
+
+use me_tv::eit::{parse_eit_section, SectionAssembler, EIT_PID};
+use me_tv::signal_monitor::{SignalMonitor, SignalStatus};
+
+use crate::channels_data::{encode_to_mrl, select_channels_file, tuning_file_of};
 use crate::control_window_button::ControlWindowButton;
 use crate::dialogs::display_an_error_dialog;
+use crate::epg_manager::Input;
+use crate::frontend_manager::{self, FrontendId, Lease, Purpose};
 use crate::preferences;
 
+/// How often, in seconds, the signal statistics are read and shown in the header bar.
+const SIGNAL_STATUS_INTERVAL: u32 = 1;
+
 /// Is nouveau the device driver?
 ///
 /// Cannot use GL stuff on Nouveau, so it is important to know if this is running on a Nouveau
@@ -52,6 +67,33 @@ fn is_using_nouveau() -> bool {
     lsmod_output.contains("nouveau")
 }
 
+/// Parse the EIT sections in the transport stream coming out of dvbsrc, so for all the
+/// services of the multiplex, and send them to the EPG manager.
+pub fn add_eit_probe(dvbsrc: &gst::Element, to_epg_manager: mpsc::Sender<Input>) {  // Used in epg_harvester.rs.
+    let src_pad = match dvbsrc.get_static_pad("src") {
+        Some(src_pad) => src_pad,
+        None => {
+            warn!("dvbsrc has no src pad, so there will be no programme information.");
+            return;
+        },
+    };
+    let eit = Mutex::new((SectionAssembler::new(EIT_PID), to_epg_manager));
+    src_pad.add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
+        if let Some(gst::PadProbeData::Buffer(ref buffer)) = probe_info.data {
+            if let Ok(map) = buffer.map_readable() {
+                let (assembler, to_epg_manager) = &mut *eit.lock().unwrap();
+                for section in assembler.push_buffer(map.as_slice()) {
+                    if let Ok(section) = parse_eit_section(&section) {
+                        // The EPG manager only stops when the application does.
+                        let _ = to_epg_manager.send(Input::Eit(section));
+                    }
+                }
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+}
+
 /// The GStreamer elements and GTK+ widgets that are the bits that do the work of rendering the
 /// television or radio channel.
 #[derive(Debug)]
@@ -59,6 +101,10 @@ pub struct GStreamerEngine {
     playbin: gst::Element,
     video_element: gst::Element,
     pub video_widget: gtk::Widget, // FrontendWindow uses this for the overlay.
+    frontend_id: FrontendId,
+    lease: RefCell<Option<Lease>>,  // Held whilst playing.
+    signal_monitor: RefCell<Option<SignalMonitor>>,  // Running whilst playing.
+    channels_file: RefCell<Option<Box<Path>>>,  // The one the channel being played is from.
 }
 
 impl GStreamerEngine {
@@ -67,6 +113,8 @@ impl GStreamerEngine {
         let playbin = gst::ElementFactory::make("playbin", Some("playbin")).expect("Failed to create playbin element");
         playbin.connect("element-setup",  false, {
             let fei = control_window_button.frontend_id.clone();
+            // The handler must be Sync, which a Sender is not.
+            let to_epg_manager = Mutex::new(control_window_button.control_window.to_epg_manager.clone());
             move |values| {
                 // values[0] .get::<gst::Element>() is an Option on the playbin itself.
                 let element = values[1]
@@ -93,6 +141,7 @@ impl GStreamerEngine {
                         if frontend_number != fei.frontend {
                             element.set_property("frontend", &(fei.frontend as i32)).expect("Could not set frontend number of dvbsrc element");
                         }
+                        add_eit_probe(&element, to_epg_manager.lock().unwrap().clone());
                     }
                     else if element_factory.get_name() == "deinterlace" {
                         // Assumption is that we are using non-GL. Should never get here if GL
@@ -135,12 +184,12 @@ impl GStreamerEngine {
                                         if section.get_section_type() == section_type {
                                             Some(section.clone())
                                         } else {
-                                            println!("********  Element with structure tagged {:?} is not of the correct type {:?}", structure.get_name(), section_type);
+                                            warn!("Element with structure tagged {:?} is not of the correct type {:?}", structure.get_name(), section_type);
                                             None
                                         }
                                     },
                                     None => {
-                                        println!("********  Element does not have a Section.");
+                                        warn!("Element does not have a Section.");
                                         None
                                     }
                                 }
@@ -148,7 +197,7 @@ impl GStreamerEngine {
                             match structure.get_name() {
                                 "cat" => {
                                     if let Some(section) = is_element_consistent(gst_mpegts::SectionType::Cat) {
-                                        control_window_button.control_window.to_epg_manager.send(section).unwrap();
+                                        control_window_button.control_window.to_epg_manager.send(Input::Section(section)).unwrap();
                                     }
                                 },
                                 "dvb-adapter" => {
@@ -166,11 +215,11 @@ impl GStreamerEngine {
                                 "dvb-read-failure" => {
                                     // TODO What should be done on a read failure?  For now the
                                     //   read fails are simply ignored.
-                                    println!("========  Got a DVB read failure message {:?}", &structure);
+                                    warn!("Got a DVB read failure message {:?}", &structure);
                                 },
                                 "eit" => {
                                     if let Some(section) = is_element_consistent(gst_mpegts::SectionType::Eit) {
-                                        control_window_button.control_window.to_epg_manager.send(section).unwrap();
+                                        control_window_button.control_window.to_epg_manager.send(Input::Section(section)).unwrap();
                                     }
                                 },
                                 "GstNavigationMessage" => {
@@ -179,22 +228,22 @@ impl GStreamerEngine {
                                 },
                                 "nit" => {
                                     if let Some(section) = is_element_consistent(gst_mpegts::SectionType::Nit) {
-                                        control_window_button.control_window.to_epg_manager.send(section).unwrap();
+                                        control_window_button.control_window.to_epg_manager.send(Input::Section(section)).unwrap();
                                     }
                                 },
                                 "pat" => {
                                     if let Some(section) = is_element_consistent(gst_mpegts::SectionType::Pat) {
-                                        control_window_button.control_window.to_epg_manager.send(section).unwrap();
+                                        control_window_button.control_window.to_epg_manager.send(Input::Section(section)).unwrap();
                                     }
                                 },
                                 "pmt" =>{
                                     if let Some(section) = is_element_consistent(gst_mpegts::SectionType::Pmt) {
-                                        control_window_button.control_window.to_epg_manager.send(section).unwrap();
+                                        control_window_button.control_window.to_epg_manager.send(Input::Section(section)).unwrap();
                                     }
                                 },
                                 "sdt" => {
                                     if let Some(section) = is_element_consistent(gst_mpegts::SectionType::Sdt) {
-                                        control_window_button.control_window.to_epg_manager.send(section).unwrap();
+                                        control_window_button.control_window.to_epg_manager.send(Input::Section(section)).unwrap();
                                     }
                                 },
                                 "section" => {
@@ -204,18 +253,18 @@ impl GStreamerEngine {
                                 },
                                 "tdt" => {
                                     if let Some(section) = is_element_consistent(gst_mpegts::SectionType::Tdt) {
-                                        control_window_button.control_window.to_epg_manager.send(section).unwrap();
+                                        control_window_button.control_window.to_epg_manager.send(Input::Section(section)).unwrap();
                                     }
                                 },
                                 "tot" => {
                                     if let Some(section) = is_element_consistent(gst_mpegts::SectionType::Tot) {
-                                        control_window_button.control_window.to_epg_manager.send(section).unwrap();
+                                        control_window_button.control_window.to_epg_manager.send(Input::Section(section)).unwrap();
                                     }
                                 },
-                                _ => println!("************  Unknown Element type: {:?}", element),
+                                _ => debug!("Unknown Element type: {:?}", element),
                             }
                         } else {
-                            println!("************  Element has no Structure: {:?}", element);
+                            debug!("Element has no Structure: {:?}", element);
                         }
                     },
                     gst::MessageView::Eos(..) => {
@@ -342,6 +391,10 @@ impl GStreamerEngine {
                 playbin,
                 video_element: video_element.expect("'video_element' is None, this cannot happen."),
                 video_widget: video_widget.expect("'video_widget is None, this cannot happen."),
+                frontend_id: control_window_button.frontend_id.clone(),
+                lease: RefCell::new(None),
+                signal_monitor: RefCell::new(None),
+                channels_file: RefCell::new(None),
             };
             engine.video_element.set_property("force-aspect-ratio", &true).expect("Could not set 'force-aspect-ration' property");
             engine.playbin.set_property("video-sink", &engine.video_element).expect("Could not set 'video-sink' property");
@@ -350,8 +403,12 @@ impl GStreamerEngine {
         }
     }
 
-    pub fn set_mrl(&self, mrl: &str) {
+    /// Set the channel to be played, remembering the channels file to tune the listed copy
+    /// of it from as dvbbasebin only reads it when it is started.
+    pub fn set_channel(&self, channel_name: &str) {
+        let mrl = encode_to_mrl(&channel_name.to_string());
         self.playbin.set_property("uri", &mrl).expect("Could not set URI on playbin.");
+        self.channels_file.replace(Some(tuning_file_of(channel_name)));
     }
 
     pub fn pause(&self) {
@@ -363,12 +420,30 @@ impl GStreamerEngine {
     }
 
     pub fn play(&self) {
+        if self.lease.borrow().is_none() {
+            match frontend_manager::reserve(&self.frontend_id, Purpose::Viewing) {
+                Ok(lease) => { self.lease.replace(Some(lease)); },
+                Err(busy) => {
+                    display_an_error_dialog(
+                        Some(&(self.video_widget.get_toplevel().unwrap().downcast::<gtk::Window>().unwrap())),
+                        &format!("Cannot play, {}.", busy)
+                    );
+                    return;
+                },
+            }
+        }
+        if let Some(path) = &*self.channels_file.borrow() {
+            select_channels_file(path);
+        }
         if let Err(_) = self.playbin.set_state(gst::State::Playing) {
             display_an_error_dialog(
                 Some(&(self.video_widget.get_toplevel().unwrap().downcast::<gtk::Window>().unwrap())),
                 "Could not set play state, perhaps the aerial isn't connected?\n\nTry running with 'GST_DEBUG=3 me-tv' for details."
             );
         }
+        if self.signal_monitor.borrow().is_none() {
+            self.start_signal_monitor();
+        }
         /*
          * Add writing out the GStreamer pipeline to the event queue, but leave long
          * enough for the pipeline to be formed.
@@ -382,7 +457,7 @@ impl GStreamerEngine {
             let the_bin = self.playbin.clone().downcast::<gst::Bin>().unwrap();
             move || {
                 gst::debug_bin_to_dot_file(&the_bin, gst::DebugGraphDetails::all(), "pipeline");
-                println!("££££££££  Pipeline diagram drawn if GST_DEBUG_DUMP_DOT_DIR has been set.");
+                debug!("Pipeline diagram drawn if GST_DEBUG_DUMP_DOT_DIR has been set.");
                 Continue(false)
             }
         });
@@ -391,6 +466,45 @@ impl GStreamerEngine {
 
     pub fn stop(&self) {
         self.playbin.set_state(gst::State::Null).unwrap();
+        self.signal_monitor.replace(None);
+        self.lease.replace(None);
+    }
+
+    /// Show the signal statistics of the frontend as the subtitle of the header bar of
+    /// the frontend window, until the monitor is stopped. Not being able to monitor the
+    /// signal is not a reason to stop playing, so failure is only logged.
+    fn start_signal_monitor(&self) {
+        let (to_engine, from_monitor) = mpsc::channel::<SignalStatus>();
+        match SignalMonitor::start(&self.frontend_id, Duration::from_secs(SIGNAL_STATUS_INTERVAL.into()), to_engine) {
+            Ok(monitor) => { self.signal_monitor.replace(Some(monitor)); },
+            Err(e) => {
+                warn!("Cannot monitor the signal on {}, {}.", self.frontend_id, e);
+                return;
+            },
+        }
+        glib::timeout_add_seconds_local(SIGNAL_STATUS_INTERVAL, {
+            let video_widget = self.video_widget.clone();
+            move || {
+                let mut latest = None;
+                loop {
+                    match from_monitor.try_recv() {
+                        Ok(status) => latest = Some(status),
+                        Err(mpsc::TryRecvError::Empty) => break,
+                        Err(mpsc::TryRecvError::Disconnected) => return Continue(false),
+                    }
+                }
+                if let Some(status) = latest {
+                    let header_bar = video_widget.get_toplevel()
+                        .and_then(|toplevel| toplevel.downcast::<gtk::Window>().ok())
+                        .and_then(|window| window.get_titlebar())
+                        .and_then(|titlebar| titlebar.downcast::<gtk::HeaderBar>().ok());
+                    if let Some(header_bar) = header_bar {
+                        header_bar.set_subtitle(Some(&status.line()));
+                    }
+                }
+                Continue(true)
+            }
+        });
     }
 
     pub fn get_volume(&self) -> f64 {
@@ -0,0 +1,90 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Cooperation between the Me TV GUI and me-tv-record over the use of frontends.
+//!
+//! The GUI offers a service on the D-Bus session bus. A recording that needs a frontend
+//! the GUI is using asks for it with RequestFrontend and then polls IsFrontendInUse
+//! until the GUI has moved the viewing to another frontend or the user has agreed to
+//! stop watching.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dbus::blocking::Connection;
+
+/// The bus name of the GUI's handover service.
+pub const BUS_NAME: &str = "uk.org.winder.MeTV";
+
+/// The object path of the GUI's handover service.
+pub const OBJECT_PATH: &str = "/uk/org/winder/MeTV";
+
+/// The interface of the GUI's handover service.
+pub const INTERFACE: &str = "uk.org.winder.MeTV.FrontendHandover";
+
+/// How long to wait for a reply to a method call.
+const METHOD_CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often to ask the GUI whether it has released the frontend.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The result of asking the GUI to release a frontend.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Handover {
+    /// The GUI is not running, or is not using the frontend.
+    NotInUse,
+    /// The GUI was using the frontend and has released it.
+    Released,
+    /// The GUI still had the frontend at the end of the grace period.
+    TimedOut,
+}
+
+/// Ensure the GUI is not using the frontend, asking it to release the frontend and
+/// waiting up to `grace_period` if it is.
+///
+/// Without a session bus, or without a GUI on it, there is nothing to cooperate with.
+pub fn acquire_frontend_from_gui(adapter: u8, frontend: u8, grace_period: Duration) -> Handover {
+    let connection = match Connection::new_session() {
+        Ok(connection) => connection,
+        Err(_) => return Handover::NotInUse,
+    };
+    let proxy = connection.with_proxy(BUS_NAME, OBJECT_PATH, METHOD_CALL_TIMEOUT);
+    let is_in_use = || -> bool {
+        match proxy.method_call(INTERFACE, "IsFrontendInUse", (adapter, frontend)) {
+            Ok((in_use,)) => in_use,
+            Err(_) => false,
+        }
+    };
+    if !is_in_use() {
+        return Handover::NotInUse;
+    }
+    if proxy.method_call::<(), _, _, _>(INTERFACE, "RequestFrontend", (adapter, frontend)).is_err() {
+        return Handover::NotInUse;
+    }
+    let deadline = Instant::now() + grace_period;
+    while Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+        if !is_in_use() {
+            return Handover::Released;
+        }
+    }
+    Handover::TimedOut
+}
@@ -0,0 +1,111 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The GUI end of the cooperation with me-tv-record over the use of frontends, see
+//! `me_tv::handover`.
+
+use std::sync::Mutex;
+
+use dbus::blocking::Connection;
+use dbus_crossroads::{Crossroads, MethodErr};
+use lazy_static::lazy_static;
+use log::{info, warn};
+
+use me_tv::handover::{BUS_NAME, INTERFACE, OBJECT_PATH};
+
+use crate::control_window::Message;
+use crate::epg_harvester;
+use crate::frontend_manager::FrontendId;
+
+// The frontends currently being used for viewing.
+lazy_static! {
+    static ref FRONTENDS_IN_USE: Mutex<Vec<FrontendId>> = Mutex::new(vec![]);
+}
+
+/// Record whether a frontend is being used for viewing.
+pub fn set_frontend_in_use(fei: &FrontendId, in_use: bool) { // Used in control_window_button.rs
+    let mut frontends_in_use = FRONTENDS_IN_USE.lock().unwrap();
+    frontends_in_use.retain(|f| f != fei);
+    if in_use {
+        frontends_in_use.push(fei.clone());
+    }
+}
+
+/// Is the frontend being used for viewing?
+pub fn is_frontend_in_use(fei: &FrontendId) -> bool { // Used in control_window.rs
+    FRONTENDS_IN_USE.lock().unwrap().contains(fei)
+}
+
+/// The dæmon offering the handover service on the D-Bus session bus.
+///
+/// Requests for a frontend are passed to the GUI to deal with. If there is no session
+/// bus recordings cannot ask for frontends, but viewing is unaffected.
+pub fn run(to_cw: glib::Sender<Message>) {
+    let connection = match Connection::new_session() {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!("No D-Bus session bus, so no frontend handover to recordings: {}", e);
+            return;
+        },
+    };
+    if let Err(e) = connection.request_name(BUS_NAME, false, true, true) {
+        warn!("Could not acquire the D-Bus name {}, so no frontend handover to recordings: {}", BUS_NAME, e);
+        return;
+    }
+    let mut crossroads = Crossroads::new();
+    let interface = crossroads.register(INTERFACE, move |builder| {
+        builder.method("IsFrontendInUse", ("adapter", "frontend"), ("in_use",), |_, _, (adapter, frontend): (u8, u8)| {
+            let fei = FrontendId{adapter, frontend};
+            Ok((is_frontend_in_use(&fei) || epg_harvester::is_harvesting(&fei),))
+        });
+        builder.method("RequestFrontend", ("adapter", "frontend"), (), move |_, _, (adapter, frontend): (u8, u8)| {
+            info!("A recording has requested adaptor{} frontend{}.", adapter, frontend);
+            epg_harvester::yield_frontend(&FrontendId{adapter, frontend});
+            // The control window goes first when Me TV is closing, the recording can then
+            // have the frontend anyway.
+            to_cw.send(Message::FrontendRequested{fei: FrontendId{adapter, frontend}}).map_err(|e| {
+                warn!("Could not pass on the request for adaptor{} frontend{}: {}", adapter, frontend, e);
+                MethodErr::failed(&"the control window has gone")
+            })
+        });
+    });
+    crossroads.insert(OBJECT_PATH, &[interface], ());
+    if let Err(e) = crossroads.serve(&connection) {
+        warn!("The frontend handover service stopped: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frontend_in_use_is_tracked() {
+        let fei = FrontendId{adapter: 7, frontend: 3};
+        assert!(!is_frontend_in_use(&fei));
+        set_frontend_in_use(&fei, true);
+        set_frontend_in_use(&fei, true);
+        assert!(is_frontend_in_use(&fei));
+        set_frontend_in_use(&fei, false);
+        assert!(!is_frontend_in_use(&fei));
+    }
+
+}
@@ -0,0 +1,185 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Hotplugging of DVB frontends as reported by udev.
+//!
+//! udev reports the frontend devices with their adapter and frontend numbers as
+//! properties and, where its hardware database knows the device, the vendor and model
+//! names. The interpretation of the events is separate from the udev monitor, which is
+//! only built with the udev-hotplug feature, so that it can be tested without udev.
+
+#[cfg(feature = "udev-hotplug")]
+use std::io;
+#[cfg(feature = "udev-hotplug")]
+use std::os::unix::io::AsRawFd;
+#[cfg(feature = "udev-hotplug")]
+use std::time::{Duration, Instant};
+
+use crate::frontends::{FrontendHardware, FrontendId};
+
+/// A change of the frontends present.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HotplugEvent {
+    Added{fei: FrontendId, hardware: FrontendHardware},
+    Removed{fei: FrontendId},
+}
+
+/// The vendor and model names of a device from its udev properties, preferring the
+/// hardware database entries. ID_VENDOR and ID_MODEL, from the device itself, have
+/// spaces replaced by underscores.
+fn device_name(property: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    let name = |database: &str, device: &str| property(database).or_else(|| property(device).map(|value| value.replace('_', " ")));
+    match (name("ID_VENDOR_FROM_DATABASE", "ID_VENDOR"), name("ID_MODEL_FROM_DATABASE", "ID_MODEL")) {
+        (Some(vendor), Some(model)) if model.starts_with(&vendor) => Some(model),
+        (Some(vendor), Some(model)) => Some(format!("{} {}", vendor, model)),
+        (None, Some(model)) => Some(model),
+        _ => None,
+    }
+}
+
+/// Interpret a udev event for the dvb subsystem, `action` being the udev action,
+/// "add", "remove", etc., and `property` looking up the properties of the device. Only
+/// frontends being added or removed are of interest, anything else gives None. The
+/// name of the hardware from udev is preferred to that from `sysfs_hardware`.
+pub fn hotplug_event(
+    action: &str,
+    property: impl Fn(&str) -> Option<String>,
+    sysfs_hardware: impl FnOnce(&FrontendId) -> FrontendHardware,
+) -> Option<HotplugEvent> {
+    if property("DVB_DEVICE_TYPE")? != "frontend" { return None; }
+    let fei = FrontendId{
+        adapter: property("DVB_ADAPTER_NUM")?.parse().ok()?,
+        frontend: property("DVB_DEVICE_NUM")?.parse().ok()?,
+    };
+    match action {
+        "add" => {
+            let mut hardware = sysfs_hardware(&fei);
+            if let Some(name) = device_name(&property) {
+                hardware.name = Some(name);
+            }
+            Some(HotplugEvent::Added{fei, hardware})
+        },
+        "remove" => Some(HotplugEvent::Removed{fei}),
+        _ => None,
+    }
+}
+
+/// A subscription to the udev events of the dvb subsystem.
+#[cfg(feature = "udev-hotplug")]
+pub struct UdevMonitor {
+    socket: udev::MonitorSocket,
+}
+
+#[cfg(feature = "udev-hotplug")]
+impl UdevMonitor {
+    /// Subscribe, failing if udev is not available, as is the case in some containers.
+    pub fn new() -> io::Result<UdevMonitor> {
+        let socket = udev::MonitorBuilder::new()?.match_subsystem("dvb")?.listen()?;
+        Ok(UdevMonitor{socket})
+    }
+
+    /// Wait up to `timeout` for a frontend to be added or removed.
+    pub fn next_event(&mut self, timeout: Duration) -> Option<HotplugEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            while let Some(event) = self.socket.next() {
+                let action = match event.event_type() {
+                    udev::EventType::Add => "add",
+                    udev::EventType::Remove => "remove",
+                    _ => continue,
+                };
+                let property = |name: &str| event.property_value(name).and_then(|value| value.to_str()).map(String::from);
+                if let Some(hotplug_event) = hotplug_event(action, property, crate::frontends::frontend_hardware) {
+                    return Some(hotplug_event);
+                }
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) { return None; }
+            let mut poll_fd = libc::pollfd{fd: self.socket.as_raw_fd(), events: libc::POLLIN, revents: 0};
+            if unsafe { libc::poll(&mut poll_fd, 1, remaining.as_millis() as libc::c_int) } <= 0 { return None; }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn properties(entries: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let map = entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
+        move |name| map.get(name).cloned()
+    }
+
+    fn sysfs_hardware(_: &FrontendId) -> FrontendHardware {
+        FrontendHardware{name: Some("em28xx".to_string()), bus_info: Some("usb 1-2:1.0".to_string())}
+    }
+
+    const FRONTEND: [(&str, &str); 3] = [("DVB_DEVICE_TYPE", "frontend"), ("DVB_ADAPTER_NUM", "1"), ("DVB_DEVICE_NUM", "0")];
+
+    #[test]
+    fn frontend_added_is_named_from_the_hardware_database() {
+        let mut entries = FRONTEND.to_vec();
+        entries.extend(&[("ID_VENDOR_FROM_DATABASE", "Hauppauge"), ("ID_MODEL_FROM_DATABASE", "WinTV dualHD"), ("ID_VENDOR", "HCW")]);
+        assert_eq!(hotplug_event("add", properties(&entries), sysfs_hardware), Some(HotplugEvent::Added{
+            fei: FrontendId{adapter: 1, frontend: 0},
+            hardware: FrontendHardware{name: Some("Hauppauge WinTV dualHD".to_string()), bus_info: Some("usb 1-2:1.0".to_string())},
+        }));
+    }
+
+    #[test]
+    fn device_properties_are_used_without_the_hardware_database() {
+        let mut entries = FRONTEND.to_vec();
+        entries.extend(&[("ID_VENDOR", "Realtek"), ("ID_MODEL", "RTL2838UHIDIR")]);
+        match hotplug_event("add", properties(&entries), sysfs_hardware) {
+            Some(HotplugEvent::Added{hardware, ..}) => assert_eq!(hardware.name, Some("Realtek RTL2838UHIDIR".to_string())),
+            event => panic!("Unexpected {:?}", event),
+        }
+        let mut entries = FRONTEND.to_vec();
+        entries.extend(&[("ID_MODEL", "WinTV_dualHD")]);
+        match hotplug_event("add", properties(&entries), sysfs_hardware) {
+            Some(HotplugEvent::Added{hardware, ..}) => assert_eq!(hardware.name, Some("WinTV dualHD".to_string())),
+            event => panic!("Unexpected {:?}", event),
+        }
+    }
+
+    #[test]
+    fn sysfs_name_is_used_without_udev_names() {
+        match hotplug_event("add", properties(&FRONTEND), sysfs_hardware) {
+            Some(HotplugEvent::Added{hardware, ..}) => assert_eq!(hardware, sysfs_hardware(&FrontendId{adapter: 1, frontend: 0})),
+            event => panic!("Unexpected {:?}", event),
+        }
+    }
+
+    #[test]
+    fn frontend_removed() {
+        assert_eq!(hotplug_event("remove", properties(&FRONTEND), |_| panic!("No hardware needed.")), Some(HotplugEvent::Removed{fei: FrontendId{adapter: 1, frontend: 0}}));
+    }
+
+    #[test]
+    fn other_devices_and_actions_are_ignored() {
+        let demux = [("DVB_DEVICE_TYPE", "demux"), ("DVB_ADAPTER_NUM", "1"), ("DVB_DEVICE_NUM", "0")];
+        assert_eq!(hotplug_event("add", properties(&demux), sysfs_hardware), None);
+        assert_eq!(hotplug_event("change", properties(&FRONTEND), sysfs_hardware), None);
+        assert_eq!(hotplug_event("add", properties(&[("DVB_DEVICE_TYPE", "frontend")]), sysfs_hardware), None);
+    }
+}
@@ -0,0 +1,142 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! The languages of programmes. The EIT gives the language of each short and extended
+//! event descriptor as an ISO 639-2 code, ETSI EN 300 468 §6.2.15 and §6.2.37, mostly the
+//! terminology code, "cym" for Welsh, but sometimes the bibliographic one, "wel", and
+//! XMLTV listings mostly the ISO 639-1 code, "cy". Codes are compared as the terminology
+//! code of the language, so all of these are the same.
+
+/// The languages known of: the ISO 639-2 terminology code, the bibliographic code if it
+/// is different, the ISO 639-1 code, and the name.
+const LANGUAGES: &[(&str, &str, &str, &str)] = &[
+    ("ara", "", "ar", "Arabic"),
+    ("ben", "", "bn", "Bengali"),
+    ("cat", "", "ca", "Catalan"),
+    ("ces", "cze", "cs", "Czech"),
+    ("cor", "", "kw", "Cornish"),
+    ("cym", "wel", "cy", "Welsh"),
+    ("dan", "", "da", "Danish"),
+    ("deu", "ger", "de", "German"),
+    ("ell", "gre", "el", "Greek"),
+    ("eng", "", "en", "English"),
+    ("eus", "baq", "eu", "Basque"),
+    ("fin", "", "fi", "Finnish"),
+    ("fra", "fre", "fr", "French"),
+    ("gla", "", "gd", "Scottish Gaelic"),
+    ("gle", "", "ga", "Irish"),
+    ("glg", "", "gl", "Galician"),
+    ("hin", "", "hi", "Hindi"),
+    ("ita", "", "it", "Italian"),
+    ("nld", "dut", "nl", "Dutch"),
+    ("nor", "", "no", "Norwegian"),
+    ("pan", "", "pa", "Panjabi"),
+    ("pol", "", "pl", "Polish"),
+    ("por", "", "pt", "Portuguese"),
+    ("spa", "", "es", "Spanish"),
+    ("swe", "", "sv", "Swedish"),
+    ("tur", "", "tr", "Turkish"),
+    ("urd", "", "ur", "Urdu"),
+    ("zho", "chi", "zh", "Chinese"),
+];
+
+/// The entry of `LANGUAGES` of the language with the code `code`, of any kind, if it is
+/// one of them.
+fn entry(code: &str) -> Option<&'static (&'static str, &'static str, &'static str, &'static str)> {
+    let code = code.trim().to_lowercase();
+    LANGUAGES.iter().find(|(terminology, bibliographic, two_letter, _)| {
+        code == *terminology || (!bibliographic.is_empty() && code == *bibliographic) || code == *two_letter
+    })
+}
+
+/// The code `code` as codes are compared: the ISO 639-2 terminology code of the language
+/// if it is known of, otherwise the code in lower case.
+pub fn canonical_code(code: &str) -> String {
+    entry(code).map_or_else(|| code.trim().to_lowercase(), |(terminology, _, _, _)| terminology.to_string())
+}
+
+/// Are the codes `a` and `b` of the same language?
+pub fn is_same_language(a: &str, b: &str) -> bool {
+    canonical_code(a) == canonical_code(b)
+}
+
+/// The name of the language with the code `code`, the code itself if it is not known of.
+pub fn language_name(code: &str) -> String {
+    entry(code).map_or_else(|| code.to_string(), |(_, _, _, name)| name.to_string())
+}
+
+/// The code of the language with the code `code` as XMLTV has it, the ISO 639-1 code if
+/// there is one.
+pub fn xmltv_code(code: &str) -> String {
+    entry(code).map_or_else(|| canonical_code(code), |(_, _, two_letter, _)| two_letter.to_string())
+}
+
+/// The codes of the languages listed in `text`, separated by commas or spaces, each once,
+/// in order, as codes are compared.
+pub fn parse_languages(text: &str) -> Vec<String> {
+    let mut languages = Vec::<String>::new();
+    for code in text.split(|c: char| c == ',' || c.is_whitespace()).filter(|code| !code.is_empty()).map(canonical_code) {
+        if !languages.contains(&code) {
+            languages.push(code);
+        }
+    }
+    languages
+}
+
+/// The position in `languages` of the first of the `preferred` languages, in order of
+/// preference, that it has, if it has any of them.
+pub fn preferred_language<'a>(languages: impl Iterator<Item = &'a str> + Clone, preferred: &[String]) -> Option<usize> {
+    preferred.iter().find_map(|wanted| languages.clone().position(|language| is_same_language(language, wanted)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_of_all_kinds_are_of_the_same_language() {
+        assert!(is_same_language("cym", "wel"));
+        assert!(is_same_language("CY", "cym"));
+        assert!(is_same_language("eng", "en"));
+        assert!(!is_same_language("eng", "cym"));
+        assert!(is_same_language("qaa", "QAA"));
+        assert_eq!(language_name("wel"), "Welsh");
+        assert_eq!(language_name("qaa"), "qaa");
+        assert_eq!(xmltv_code("gla"), "gd");
+        assert_eq!(xmltv_code("qaa"), "qaa");
+    }
+
+    #[test]
+    fn languages_are_listed_in_order_each_once() {
+        assert_eq!(parse_languages("wel, eng cy,,  fr"), vec!["cym", "eng", "fra"]);
+        assert!(parse_languages(" ").is_empty());
+    }
+
+    #[test]
+    fn the_first_preferred_language_there_is_is_chosen() {
+        let preferred = parse_languages("gla, cym, eng");
+        assert_eq!(preferred_language(["eng", "wel"].iter().copied(), &preferred), Some(1));
+        assert_eq!(preferred_language(["eng", "fra"].iter().copied(), &preferred), Some(0));
+        assert_eq!(preferred_language(["fra"].iter().copied(), &preferred), None);
+        assert_eq!(preferred_language(["eng"].iter().copied(), &[]), None);
+    }
+}
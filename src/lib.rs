@@ -0,0 +1,62 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The parts of Me TV that are used by more than one of the executables, and which
+//! other tools may find useful.
+
+pub mod channels_file;
+pub mod chapters;
+pub mod debounce;
+pub mod desktop_notification;
+pub mod device_history;
+pub mod dvb_text;
+pub mod eit;
+pub mod epg;
+pub mod epg_bus;
+pub mod epg_cache;
+pub mod epg_grid;
+pub mod epg_harvest;
+pub mod epg_search;
+mod frontend_abi;
+pub mod frontend_event;
+pub mod frontend_info;
+pub mod frontend_lease;
+pub mod frontend_lock;
+pub mod frontends;
+pub mod genres;
+pub mod handover;
+pub mod hotplug;
+pub mod languages;
+pub mod logging;
+pub mod logos;
+pub mod m3u;
+pub mod multiplex_signals;
+pub mod name_matching;
+pub mod nit;
+pub mod recording_event;
+pub mod scan;
+pub mod schedule;
+pub mod sd_notify;
+pub mod sdt;
+pub mod sidecar;
+pub mod signal_monitor;
+pub mod thumbnail;
+pub mod xmltv;
@@ -0,0 +1,38 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The logging of Me TV and its command line programs, env_logger behind the log facade.
+
+use env_logger;
+use log;
+
+/// Initialise the logging system.
+///
+/// An explicit log level takes precedence over the verbose flag, which takes precedence
+/// over RUST_LOG. With none of them, only warnings and errors are output.
+pub fn init_logging(log_level: Option<&str>, be_verbose: bool) {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    match log_level {
+        Some(level) => { builder.filter_level(level.parse().expect("Couldn't parse the log level.")); },
+        None => if be_verbose { builder.filter_level(log::LevelFilter::Debug); },
+    }
+    builder.init();
+}
@@ -0,0 +1,117 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Finding the logo of a channel.
+//!
+//! Logos are image files in a directory the user provides, named after the channel,
+//! e.g. BBC ONE.png, or after its service id, e.g. 4164.png. Failing those the
+//! service name naming of [picons](https://github.com/picons/picons) is tried, e.g.
+//! bbcone.png, so that a set of picons made for a satellite receiver can be used as is.
+
+use std::path::{Path, PathBuf};
+
+use xdg;
+
+/// The extensions of the image files looked for, in order of preference.
+const EXTENSIONS: [&str; 4] = ["png", "svg", "jpg", "jpeg"];
+
+/// The directory logos are in if the user has not said otherwise.
+pub fn default_logos_directory() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    xdg_dirs.get_data_home().join("logos")
+}
+
+/// The picons service name of a channel: lower case with &, + and * spelt out and
+/// everything else that is not a letter or a digit left out, e.g. bbcone for BBC ONE
+/// and channel4plus1 for Channel 4+1.
+pub fn picon_name(channel_name: &str) -> String {
+    channel_name.to_lowercase()
+        .replace('&', "and")
+        .replace('+', "plus")
+        .replace('*', "star")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect()
+}
+
+/// The names, without extension, that the logo of a channel may have, in the order they
+/// are looked for.
+fn logo_names(channel_name: &str, service_id: Option<u16>) -> Vec<String> {
+    let mut names = vec![channel_name.replace('/', "_")];
+    if let Some(service_id) = service_id {
+        names.push(service_id.to_string());
+    }
+    let picon = picon_name(channel_name);
+    if !picon.is_empty() && !names.contains(&picon) {
+        names.push(picon);
+    }
+    names
+}
+
+/// The logo file of a channel in `directory`, if there is one.
+pub fn find_logo(directory: &Path, channel_name: &str, service_id: Option<u16>) -> Option<PathBuf> {
+    logo_names(channel_name, service_id).iter()
+        .flat_map(|name| EXTENSIONS.iter().map(move |extension| directory.join(format!("{}.{}", name, extension))))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile;
+
+    #[test]
+    fn picon_names_are_the_letters_and_digits_in_lower_case() {
+        assert_eq!(picon_name("BBC ONE"), "bbcone");
+        assert_eq!(picon_name("Channel 4+1"), "channel4plus1");
+        assert_eq!(picon_name("Sky Sports F1 HD"), "skysportsf1hd");
+        assert_eq!(picon_name("Film4 & More*"), "film4andmorestar");
+        assert_eq!(picon_name("5 USA"), "5usa");
+    }
+
+    #[test]
+    fn logos_are_found_by_name_then_service_id_then_picon_name() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path();
+        assert_eq!(find_logo(path, "BBC ONE", Some(4164)), None);
+        fs::write(path.join("bbcone.png"), b"").unwrap();
+        assert_eq!(find_logo(path, "BBC ONE", Some(4164)), Some(path.join("bbcone.png")));
+        fs::write(path.join("4164.svg"), b"").unwrap();
+        assert_eq!(find_logo(path, "BBC ONE", Some(4164)), Some(path.join("4164.svg")));
+        assert_eq!(find_logo(path, "BBC ONE", None), Some(path.join("bbcone.png")));
+        fs::write(path.join("BBC ONE.jpg"), b"").unwrap();
+        assert_eq!(find_logo(path, "BBC ONE", Some(4164)), Some(path.join("BBC ONE.jpg")));
+        fs::write(path.join("BBC ONE.png"), b"").unwrap();
+        assert_eq!(find_logo(path, "BBC ONE", Some(4164)), Some(path.join("BBC ONE.png")));
+    }
+
+    #[test]
+    fn a_slash_in_a_channel_name_cannot_be_in_a_file_name() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path();
+        fs::write(path.join("Dave_ja vu.png"), b"").unwrap();
+        assert_eq!(find_logo(path, "Dave/ja vu", None), Some(path.join("Dave_ja vu.png")));
+        assert_eq!(find_logo(path, "Dave ja vu", None), None);
+    }
+}
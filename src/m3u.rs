@@ -0,0 +1,128 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Extended M3U playlists of channels, for other players, and anything else that reads
+//! them, to open.
+//!
+//! Each channel is an `#EXTINF` line, giving the name of the channel after the comma
+//! and the number and group of the channel as attributes, followed by the URL to play
+//! it from.
+
+/// The group of the favourite channels.
+pub const FAVOURITES_GROUP: &str = "Favourites";
+/// The group of the radio channels that are not favourites.
+pub const RADIO_GROUP: &str = "Radio";
+/// The group of the television channels that are not favourites.
+pub const TELEVISION_GROUP: &str = "TV";
+
+/// A channel of a playlist.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlaylistEntry {
+    pub name: String,
+    pub number: u16,  // 0 if the logical channel number is not known.
+    pub group: &'static str,
+    pub url: String,
+}
+
+/// The group of a channel: favourites first, whether radio or television.
+pub fn group_of(is_favourite: bool, is_radio: bool) -> &'static str {
+    match (is_favourite, is_radio) {
+        (true, _) => FAVOURITES_GROUP,
+        (false, true) => RADIO_GROUP,
+        (false, false) => TELEVISION_GROUP,
+    }
+}
+
+/// `value` as an attribute value. Double quotes cannot be escaped in one, so they become
+/// single quotes.
+fn attribute_value(value: &str) -> String {
+    title(value).replace('"', "'")
+}
+
+/// `name` as the title of an entry, which runs to the end of the line, so without line
+/// breaks or other control characters.
+fn title(name: &str) -> String {
+    name.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+impl PlaylistEntry {
+    /// The lines of the playlist for the channel.
+    fn lines(&self) -> String {
+        let mut attributes = format!("tvg-name=\"{}\"", attribute_value(&self.name));
+        if self.number != 0 {
+            attributes.push_str(&format!(" tvg-chno=\"{}\"", self.number));
+        }
+        attributes.push_str(&format!(" group-title=\"{}\"", attribute_value(self.group)));
+        format!("#EXTINF:-1 {},{}\n{}\n", attributes, title(&self.name), title(&self.url))
+    }
+}
+
+/// The extended M3U playlist of the channels `entries`, in order.
+pub fn playlist(entries: &[PlaylistEntry]) -> String {
+    let mut playlist = "#EXTM3U\n".to_string();
+    for entry in entries {
+        playlist.push_str(&entry.lines());
+    }
+    playlist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::channels_file::dvb_uri;
+
+    fn entry(name: &str, number: u16, group: &'static str) -> PlaylistEntry {
+        PlaylistEntry{name: name.to_string(), number, group, url: dvb_uri(name)}
+    }
+
+    #[test]
+    fn channels_are_extinf_lines_followed_by_their_urls() {
+        assert_eq!(playlist(&[entry("BBC ONE Lon", 1, TELEVISION_GROUP), entry("BBC Radio 4", 0, RADIO_GROUP)]), "#EXTM3U
+#EXTINF:-1 tvg-name=\"BBC ONE Lon\" tvg-chno=\"1\" group-title=\"TV\",BBC ONE Lon
+dvb://BBC%20ONE%20Lon
+#EXTINF:-1 tvg-name=\"BBC Radio 4\" group-title=\"Radio\",BBC Radio 4
+dvb://BBC%20Radio%204
+");
+    }
+
+    #[test]
+    fn an_empty_playlist_is_just_the_header() {
+        assert_eq!(playlist(&[]), "#EXTM3U\n");
+    }
+
+    #[test]
+    fn odd_characters_in_names_do_not_break_the_lines() {
+        let lines = entry("\"Quest\", Red\nHD\t+1", 41, FAVOURITES_GROUP).lines();
+        assert_eq!(lines.lines().collect::<Vec<_>>(), vec![
+            "#EXTINF:-1 tvg-name=\"'Quest', Red HD +1\" tvg-chno=\"41\" group-title=\"Favourites\",\"Quest\", Red HD +1",
+            "dvb://%22Quest%22,%20Red%0AHD%09+1",
+        ]);
+    }
+
+    #[test]
+    fn favourites_are_grouped_together_whatever_they_are() {
+        assert_eq!(group_of(true, true), FAVOURITES_GROUP);
+        assert_eq!(group_of(true, false), FAVOURITES_GROUP);
+        assert_eq!(group_of(false, true), RADIO_GROUP);
+        assert_eq!(group_of(false, false), TELEVISION_GROUP);
+    }
+}
@@ -19,6 +19,12 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+#[cfg(not(test))]
+use std::cell::RefCell;
+#[cfg(not(test))]
+use std::io::Write;
+#[cfg(not(test))]
+use std::rc::Rc;
 #[cfg(not(test))]
 use std::thread;
 
@@ -36,21 +42,39 @@ use gst;
 #[cfg(not(test))]
 use gst_mpegts;
 
+#[cfg(not(test))]
+use me_tv::logging::init_logging;
+
 mod about;
+mod channel_editor;
+mod channel_logos;
+mod channel_order;
+mod channel_search;
 mod channels_data;
+mod channels_file_watcher;
 mod control_window;
 mod control_window_button;
+mod device_events_dialog;
 mod dialogs;
 mod dvb;
+mod epg_bus_service;
+mod epg_harvester;
 mod epg_manager;
+mod epg_search_window;
+mod epg_window;
+mod favourites;
 mod frontend_manager;
 mod frontend_window;
 mod gstreamer_engine;
+mod handover_service;
 pub mod input_event_codes; // Make this module public to avoid all the unused warnings.
 mod metvcombobox;
 mod preferences;
 mod preferences_dialog;
+mod programme_details;
+mod recent_channels;
 mod remote_control;
+mod scan_dialog;
 mod transmitter_dialog;
 
 #[cfg(not(test))]
@@ -72,37 +96,155 @@ fn main() {
         .arg(clap::Arg::with_name("no_gl")
             .long("no-gl")
             .help("Do not try to use OpenGL."))
+        .arg(clap::Arg::with_name("log_level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .help("Sets the level of logging output, overrides RUST_LOG.")
+            .takes_value(true)
+            .possible_values(&["off", "error", "warn", "info", "debug", "trace"]))
+        .arg(clap::Arg::with_name("hotplug_timeout")
+            .long("hotplug-timeout")
+            .value_name("SECONDS")
+            .help("Sets how long to wait for the frontends of a newly plugged in adapter to be usable.")
+            .takes_value(true)
+            .validator(|value| match value.parse::<f32>() {
+                Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => Ok(()),
+                _ => Err(format!("'{}' is not a number of seconds.", value)),
+            })
+            .default_value("5"))
+        .arg(clap::Arg::with_name("hotplug_debounce")
+            .long("hotplug-debounce")
+            .value_name("SECONDS")
+            .help("Sets how long an adapter must have been left alone before it being plugged in or unplugged is acted on, so that a flaky adapter does not flood the window.")
+            .takes_value(true)
+            .validator(|value| match value.parse::<f32>() {
+                Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => Ok(()),
+                _ => Err(format!("'{}' is not a number of seconds.", value)),
+            })
+            .default_value("2"))
+        .arg(clap::Arg::with_name("dvb_path")
+            .long("dvb-path")
+            .value_name("DIRECTORY")
+            .help("Sets the directory of the DVB adapter directories, overriding ME_TV_DVB_PATH, default /dev/dvb.")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("hotplug_monitor")
+            .long("hotplug-monitor")
+            .value_name("MONITOR")
+            .help("Sets how adapters being plugged in and unplugged are noticed, udev needs Me TV to have been built with the udev-hotplug feature.")
+            .takes_value(true)
+            .possible_values(&["udev", "inotify"])
+            .default_value(if cfg!(feature = "udev-hotplug") { "udev" } else { "inotify" }))
+        .arg(clap::Arg::with_name("export_m3u")
+            .long("export-m3u")
+            .value_name("PATH")
+            .help("Write the channels as an extended M3U playlist to PATH, or to standard output if PATH is -, and exit.")
+            .takes_value(true))
         .get_matches();
+    init_logging(cli_matches.value_of("log_level"), false);
+    if let Some(path) = cli_matches.value_of("export_m3u") {
+        export_m3u(path);
+    }
     if cli_matches.is_present("no_gl") {
         preferences::set_use_opengl(false, false);
     }
+    if let Some(path) = cli_matches.value_of("dvb_path") {
+        me_tv::frontends::set_dvb_devices(me_tv::frontends::DvbDevices::new(path));
+    }
+    let hotplug_timeout = std::time::Duration::from_secs_f32(cli_matches.value_of("hotplug_timeout").unwrap().parse().unwrap());
+    let hotplug_debounce = std::time::Duration::from_secs_f32(cli_matches.value_of("hotplug_debounce").unwrap().parse().unwrap());
+    let hotplug_monitor = match cli_matches.value_of("hotplug_monitor").unwrap() {
+        "udev" => frontend_manager::HotplugMonitor::Udev,
+        _ => frontend_manager::HotplugMonitor::Inotify,
+    };
     gst::init().unwrap();
     gst_mpegts::initialise();
     let application = gtk::Application::new(Some("uk.org.winder.me-tv"), gio::ApplicationFlags::empty()).expect("Application creation failed");
     glib::set_application_name("Me TV");
-    application.connect_startup(move |app| {
-        let (to_control_window, from_manager) = glib::MainContext::channel::<control_window::Message>(glib::PRIORITY_DEFAULT);
-        let (to_epg_manager, from_gstreamer) = std::sync::mpsc::channel::<gst_mpegts::Section>();
-        //  This variable is no longer used since the application menu was
-        //  removed, but the ControlWindow instance must be created at this time.
-        let _control_window = control_window::ControlWindow::new(&app, from_manager, to_epg_manager);
-        // Spawn a thread to run the frontend manager process.
-        thread::spawn({
-            let t_c_w = to_control_window.clone();
-            move ||{ frontend_manager::run(t_c_w); }
-        });
-        // Spawn a thread to run the remote control manager process.
-        thread::spawn({
-            let t_c_w = to_control_window.clone();
-            move || remote_control::run(t_c_w)
-        });
-        // Spawn a thread to run the EPG (Section packet) management process.
-        thread::spawn({
-            let t_c_w = to_control_window.clone();
-            move ||{ epg_manager::run(t_c_w, from_gstreamer); }
-        });
+    // The way to command the frontend manager and its thread, so that it can be shut down
+    // on quit.
+    let frontend_manager = Rc::new(RefCell::new(None));
+    application.connect_startup({
+        let frontend_manager = frontend_manager.clone();
+        move |app| {
+            let (to_control_window, from_manager) = glib::MainContext::channel::<control_window::Message>(glib::PRIORITY_DEFAULT);
+            let (to_epg_manager, from_gstreamer) = std::sync::mpsc::channel::<epg_manager::Input>();
+            //  This variable is no longer used since the application menu was
+            //  removed, but the ControlWindow instance must be created at this time.
+            let _control_window = control_window::ControlWindow::new(&app, from_manager, to_epg_manager.clone());
+            // Spawn a thread to run the frontend manager process.
+            // Unbounded so that the GUI never blocks telling the frontend manager something,
+            // there is no back-pressure needed as the commands are few.
+            let (to_frontend_manager, from_gui) = crossbeam_channel::unbounded::<frontend_manager::Command>();
+            let frontend_manager_thread = thread::spawn({
+                let t_c_w = to_control_window.clone();
+                move ||{ frontend_manager::run(t_c_w, from_gui, me_tv::frontends::dvb_devices(), me_tv::device_history::history_path(), hotplug_monitor, hotplug_timeout, hotplug_debounce); }
+            });
+            frontend_manager.replace(Some((to_frontend_manager, frontend_manager_thread)));
+            // Spawn a thread to run the remote control manager process.
+            thread::spawn({
+                let t_c_w = to_control_window.clone();
+                move || remote_control::run(t_c_w)
+            });
+            // Spawn a thread to reread the channels file when it changes.
+            thread::spawn({
+                let t_c_w = to_control_window.clone();
+                move || channels_file_watcher::run(t_c_w)
+            });
+            // Spawn a thread to offer frontends to recordings that need them.
+            thread::spawn({
+                let t_c_w = to_control_window.clone();
+                move || handover_service::run(t_c_w)
+            });
+            // Spawn a thread to run the EPG (Section packet) management process.
+            thread::spawn({
+                let t_c_w = to_control_window.clone();
+                move ||{ epg_manager::run(t_c_w, from_gstreamer); }
+            });
+            // Spawn a thread to harvest the EPG on frontends not otherwise in use.
+            thread::spawn({
+                let t_c_w = to_control_window.clone();
+                move || epg_harvester::run(t_c_w, to_epg_manager)
+            });
+            // Spawn a thread to offer the programme guide to other programs.
+            thread::spawn(epg_bus_service::run);
+        }
+    });
+    application.connect_shutdown(move |_| {
+        epg_manager::save_cache();
+        if let Some((to_frontend_manager, frontend_manager_thread)) = frontend_manager.borrow_mut().take() {
+            // The frontend manager may already have stopped, in which case there is no-one to tell.
+            let _ = to_frontend_manager.send(frontend_manager::Command::Shutdown);
+            if frontend_manager_thread.join().is_err() {
+                log::warn!("The frontend manager thread panicked.");
+            }
+        }
     });
     // Get a glib-gio warning if activate is not handled.
     application.connect_activate(move |_| { });
     application.run(&[]);
 }
+
+/// Write the channels as an extended M3U playlist to `path`, or to standard output if
+/// it is -, and exit.
+#[cfg(not(test))]
+fn export_m3u(path: &str) -> ! {
+    let playlist = match channels_data::channels_playlist() {
+        Some(playlist) => playlist,
+        None => {
+            log::error!("Could not read the channels file {}, so there are no channels to export.", channels_data::channels_file_path().display());
+            std::process::exit(exitcode::NOINPUT);
+        },
+    };
+    let result = if path == "-" {
+        std::io::stdout().write_all(playlist.as_bytes())
+    } else {
+        std::fs::write(path, playlist)
+    };
+    match result {
+        Ok(()) => std::process::exit(exitcode::OK),
+        Err(e) => {
+            log::error!("Could not write {}: {}", path, e);
+            std::process::exit(exitcode::CANTCREAT);
+        },
+    }
+}
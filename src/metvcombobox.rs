@@ -39,8 +39,10 @@ impl MeTVComboBoxExt for MeTVComboBox {
     ///
     /// It is assumed that the `TreeModel` is actually a `ListStore` or a
     /// `TreeModelSort` backed by a `ListStore` with the `ListStore` having
-    /// two columns (`String`, `String`) being the channel number and
-    /// the channel name.
+    /// the columns (`String`, `String`) being the channel number and
+    /// the channel name, columns 4 and 5 (`bool`, `bool`) being whether
+    /// the channel is encrypted and whether it is a radio channel, and column 9
+    /// (`Pixbuf`) the logo of the channel if it has one.
     fn new_with_model<T: IsA<gtk::TreeModel>>(model: &T) -> MeTVComboBox {
         let mut combobox = gtk::ComboBox::new();
         combobox.init_with_model(model);
@@ -51,16 +53,30 @@ impl MeTVComboBoxExt for MeTVComboBox {
     ///
     /// It is assumed that the `TreeModel` is actually a `ListStore` or a
     /// `TreeModelSort` backed by a `ListStore` with the `ListStore` having
-    /// two columns (`String`, `String`) being the channel number and
-    /// the channel name.
+    /// the columns (`String`, `String`) being the channel number and
+    /// the channel name, columns 4 and 5 (`bool`, `bool`) being whether
+    /// the channel is encrypted and whether it is a radio channel, and column 9
+    /// (`Pixbuf`) the logo of the channel if it has one.
     fn init_with_model<T: IsA<gtk::TreeModel>>(&mut self, model: &T) {
         self.set_model(Some(model));
+        // A channel without a logo just has its number and name.
+        let logo_renderer = gtk::CellRendererPixbuf::new();
+        self.pack_start(&logo_renderer, false);
+        self.add_attribute(&logo_renderer, "pixbuf", 9);
         let number_renderer = gtk::CellRendererText::new();
         self.pack_start(&number_renderer, true);
         self.add_attribute(&number_renderer, "text", 0);
         let name_renderer = gtk::CellRendererText::new();
         self.pack_start(&name_renderer, true);
         self.add_attribute(&name_renderer, "text", 1);
+        let encrypted_renderer = gtk::CellRendererPixbuf::new();
+        encrypted_renderer.set_property_icon_name(Some("channel-secure-symbolic"));
+        self.pack_start(&encrypted_renderer, false);
+        self.add_attribute(&encrypted_renderer, "visible", 4);
+        let radio_renderer = gtk::CellRendererPixbuf::new();
+        radio_renderer.set_property_icon_name(Some("audio-x-generic-symbolic"));
+        self.pack_start(&radio_renderer, false);
+        self.add_attribute(&radio_renderer, "visible", 5);
    }
 
     fn get_active_text(&self) -> Option<String> {
@@ -111,8 +127,15 @@ mod tests {
 
     use super::*;
 
+    fn create_empty_model() -> gtk::ListStore {
+        gtk::ListStore::new(&[
+            String::static_type(), String::static_type(), bool::static_type(), u32::static_type(), bool::static_type(), bool::static_type(), String::static_type(), bool::static_type(),
+            bool::static_type(), gdk_pixbuf::Pixbuf::static_type(),
+        ])
+    }
+
     fn create_test_model() -> gtk::ListStore {
-        let store = gtk::ListStore::new(&[String::static_type(), String::static_type()]);
+        let store = create_empty_model();
         store.insert_with_values(None, &[0, 1], &[&4.to_string(), &"fred"]);
         store.insert_with_values(None, &[0, 1], &[&3.to_string(), &"jane"]);
         store.insert_with_values(None, &[0, 1], &[&2.to_string(), &"jo"]);
@@ -126,7 +149,7 @@ mod tests {
             Ok(_) => (),
             Err(_) => panic!("Could not initialise GTK"),
         }
-        let store = gtk::ListStore::new(&[String::static_type(), String::static_type()]);
+        let store = create_empty_model();
         let mut thingy = MeTVComboBox::new_with_model(&store);
         thingy.set_active(Some(1)); // TODO Should this fail in some way?
         assert_eq!(thingy.get_active_text(), None);
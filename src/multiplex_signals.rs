@@ -0,0 +1,267 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The signal on each multiplex when it was last scanned.
+//!
+//! A scan samples the signal with the DVBv5 statistics whilst it is on each transponder,
+//! and keeps the figures in a file, a multiplex a line, so that the copy of a service
+//! with the strongest signal can be the one listed, and the signal of a channel can be
+//! shown. A transponder that never locked is kept as that, marginal and dead multiplexes
+//! being what show an aerial problem.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+
+use xdg;
+
+use crate::channels_file::are_same_frequency;
+use crate::scan::Transponder;
+use crate::signal_monitor::{Measurement, SignalStatus};
+
+/// The signal on a transponder whilst it was scanned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransponderSignal {
+    Locked{strength: Option<Measurement>, snr: Option<Measurement>},  // The last reading whilst the frontend had lock.
+    NoLock,
+}
+
+impl TransponderSignal {
+    /// The signal the readings taken whilst a transponder was scanned say there was, None
+    /// if there were no readings.
+    pub fn of(readings: &[SignalStatus]) -> Option<TransponderSignal> {
+        if readings.is_empty() {
+            return None;
+        }
+        Some(match readings.iter().rev().find(|reading| reading.lock) {
+            Some(reading) => TransponderSignal::Locked{strength: reading.strength, snr: reading.snr},
+            None => TransponderSignal::NoLock,
+        })
+    }
+
+    /// The signal strength from 0 to 65535, for comparing the signals of the copies of a
+    /// service, None if it is not known. Strengths in dBm are taken as being from -100 dBm
+    /// to -20 dBm.
+    pub fn strength_level(&self) -> Option<u16> {
+        let fraction = match self {
+            TransponderSignal::Locked{strength: Some(Measurement::Relative(value)), ..} => *value,
+            TransponderSignal::Locked{strength: Some(Measurement::Decibels(dbm)), ..} => (dbm + 100.0) / 80.0,
+            _ => return None,
+        };
+        Some((fraction.max(0.0).min(1.0) * 65535.0).round() as u16)
+    }
+}
+
+/// Rendered as, for example, signal 72%, SNR 25.1 dB, or no lock.
+impl fmt::Display for TransponderSignal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |m: &Option<Measurement>| m.map_or("unknown".to_string(), |m| m.to_string());
+        match self {
+            TransponderSignal::Locked{strength, snr} => write!(f, "signal {}, SNR {}", show(strength), show(snr)),
+            TransponderSignal::NoLock => f.write_str("no lock"),
+        }
+    }
+}
+
+/// The field of the signals file for a measurement: decibels with dB after them,
+/// relative measurements as a fraction.
+fn measurement_field(measurement: &Option<Measurement>) -> String {
+    match measurement {
+        Some(Measurement::Decibels(value)) => format!("{}dB", value),
+        Some(Measurement::Relative(value)) => value.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// The measurement of a field of the signals file, None if it is not known or not one.
+fn measurement_of_field(field: &str) -> Option<Measurement> {
+    match field.strip_suffix("dB") {
+        Some(value) => value.parse().ok().map(Measurement::Decibels),
+        None => field.parse().ok().map(Measurement::Relative),
+    }
+}
+
+/// The signal on a multiplex when it was scanned.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiplexSignal {
+    pub scanned: DateTime<Local>,
+    pub delivery_system: String,  // A DVBv5 name.
+    pub frequency: u32,  // As in the channels file.
+    pub polarization: Option<String>,  // For satellite multiplexes.
+    pub signal: TransponderSignal,
+}
+
+impl MultiplexSignal {
+    /// Whether this is the signal of the multiplex at `frequency` with `polarization`,
+    /// frequencies being the same as they are for channels.
+    pub fn is_of(&self, frequency: u32, polarization: Option<&str>) -> bool {
+        are_same_frequency(self.frequency, frequency) && self.polarization.as_deref() == polarization
+    }
+
+    /// The line of the signals file: the time, delivery system, frequency, polarisation,
+    /// and either the strength and SNR or no-lock, separated by tabs.
+    fn record(&self) -> String {
+        let signal = match &self.signal {
+            TransponderSignal::Locked{strength, snr} => format!("{}\t{}", measurement_field(strength), measurement_field(snr)),
+            TransponderSignal::NoLock => "no-lock".to_string(),
+        };
+        format!("{}\t{}\t{}\t{}\t{}", self.scanned.to_rfc3339(), self.delivery_system, self.frequency, self.polarization.as_deref().unwrap_or(""), signal)
+    }
+
+    /// The signal of a line of the signals file, None if it is not one.
+    fn from_record(line: &str) -> Option<MultiplexSignal> {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() < 5 {
+            return None;
+        }
+        let signal = match (fields[4], fields.get(5)) {
+            ("no-lock", None) => TransponderSignal::NoLock,
+            (strength, Some(snr)) => TransponderSignal::Locked{strength: measurement_of_field(strength), snr: measurement_of_field(snr)},
+            _ => return None,
+        };
+        Some(MultiplexSignal {
+            scanned: DateTime::parse_from_rfc3339(fields[0]).ok()?.with_timezone(&Local),
+            delivery_system: fields[1].to_string(),
+            frequency: fields[2].parse().ok()?,
+            polarization: if fields[3].is_empty() { None } else { Some(fields[3].to_string()) },
+            signal,
+        })
+    }
+}
+
+/// The signals that a scan at `scanned` of `transponders` knows the multiplexes had,
+/// `signals` being those on the transponders, as the results of the scan give them.
+pub fn scanned_signals(transponders: &[Transponder], signals: &[Option<TransponderSignal>], scanned: DateTime<Local>) -> Vec<MultiplexSignal> {
+    transponders.iter().zip(signals).filter_map(|(transponder, signal)| signal.map(|signal| MultiplexSignal {
+        scanned,
+        delivery_system: transponder.delivery_system.clone(),
+        frequency: transponder.frequency,
+        polarization: transponder.parameter("POLARIZATION").map(String::from),
+        signal,
+    })).collect()
+}
+
+/// The path of the file of the signals of the multiplexes when scanned.
+pub fn multiplex_signals_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    xdg_dirs.get_data_home().join("multiplex-signals")
+}
+
+/// Read the signals file, lines that are not signals being ignored. A file that does not
+/// exist has no signals.
+pub fn read_multiplex_signals(path: &Path) -> io::Result<Vec<MultiplexSignal>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents.lines().filter_map(MultiplexSignal::from_record).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Add the signals of the multiplexes just scanned to the signals file, replacing those
+/// of the same multiplexes, the file being replaced as a whole so that a reader never
+/// sees part of it.
+pub fn record_multiplex_signals(path: &Path, scanned: &[MultiplexSignal]) -> io::Result<()> {
+    let mut signals = read_multiplex_signals(path)?;
+    signals.retain(|signal| !scanned.iter().any(|s| s.is_of(signal.frequency, signal.polarization.as_deref())));
+    signals.extend(scanned.iter().cloned());
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let contents = signals.iter().map(|signal| signal.record() + "\n").collect::<String>();
+    let temporary = path.with_extension("new");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+/// The signal of the multiplex at `frequency` with `polarization`, if it has been scanned.
+pub fn signal_of<'a>(signals: &'a [MultiplexSignal], frequency: u32, polarization: Option<&str>) -> Option<&'a MultiplexSignal> {
+    signals.iter().rev().find(|signal| signal.is_of(frequency, polarization))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use crate::frontends::FrontendId;
+
+    fn reading(lock: bool, strength: Option<Measurement>) -> SignalStatus {
+        SignalStatus{fei: FrontendId{adapter: 0, frontend: 0}, strength, snr: Some(Measurement::Decibels(25.1)), ber: None, unc: None, lock}
+    }
+
+    fn multiplex(day: u32, frequency: u32, signal: TransponderSignal) -> MultiplexSignal {
+        MultiplexSignal{scanned: Local.ymd(2020, 10, day).and_hms(21, 0, 0), delivery_system: "DVBT".to_string(), frequency, polarization: None, signal}
+    }
+
+    #[test]
+    fn the_signal_is_the_last_reading_with_lock_or_no_lock() {
+        assert_eq!(TransponderSignal::of(&[]), None);
+        assert_eq!(TransponderSignal::of(&[reading(false, None), reading(false, None)]), Some(TransponderSignal::NoLock));
+        let signal = TransponderSignal::of(&[reading(true, Some(Measurement::Relative(0.5))), reading(true, Some(Measurement::Relative(0.72))), reading(false, None)]).unwrap();
+        assert_eq!(signal.to_string(), "signal 72%, SNR 25.1 dB");
+        assert_eq!(TransponderSignal::NoLock.to_string(), "no lock");
+    }
+
+    #[test]
+    fn strengths_are_levels_whatever_their_scale() {
+        let level = |strength| TransponderSignal::Locked{strength: Some(strength), snr: None}.strength_level();
+        assert_eq!(level(Measurement::Relative(1.0)), Some(65535));
+        assert_eq!(level(Measurement::Decibels(-60.0)), Some(32768));
+        assert_eq!(level(Measurement::Decibels(-110.0)), Some(0));
+        assert_eq!(TransponderSignal::Locked{strength: None, snr: None}.strength_level(), None);
+        assert_eq!(TransponderSignal::NoLock.strength_level(), None);
+    }
+
+    #[test]
+    fn only_the_transponders_whose_signal_is_known_have_one() {
+        let transponder = |frequency, parameters: &[(&str, &str)]| Transponder{
+            delivery_system: "DVBS2".to_string(),
+            frequency,
+            parameters: parameters.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+        };
+        let transponders = [transponder(11493750, &[("POLARIZATION", "HORIZONTAL")]), transponder(11508000, &[])];
+        let scanned = Local.ymd(2020, 10, 14).and_hms(21, 0, 0);
+        let signals = scanned_signals(&transponders, &[Some(TransponderSignal::NoLock), None], scanned);
+        assert_eq!(signals, vec![MultiplexSignal{scanned, delivery_system: "DVBS2".to_string(), frequency: 11493750, polarization: Some("HORIZONTAL".to_string()), signal: TransponderSignal::NoLock}]);
+    }
+
+    #[test]
+    fn signals_file_is_written_and_read_back_the_latest_scan_of_a_multiplex_replacing_the_earlier() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("me-tv").join("multiplex-signals");
+        assert!(read_multiplex_signals(&path).unwrap().is_empty());
+        let locked = TransponderSignal::Locked{strength: Some(Measurement::Decibels(-45.5)), snr: None};
+        let mut satellite = multiplex(14, 11493750, TransponderSignal::Locked{strength: Some(Measurement::Relative(0.25)), snr: Some(Measurement::Decibels(9.0))});
+        satellite.polarization = Some("HORIZONTAL".to_string());
+        record_multiplex_signals(&path, &[multiplex(14, 490000000, locked), multiplex(14, 522000000, TransponderSignal::NoLock), satellite.clone()]).unwrap();
+        record_multiplex_signals(&path, &[multiplex(15, 490000100, TransponderSignal::NoLock)]).unwrap();
+        let signals = read_multiplex_signals(&path).unwrap();
+        assert_eq!(signals, vec![multiplex(14, 522000000, TransponderSignal::NoLock), satellite, multiplex(15, 490000100, TransponderSignal::NoLock)]);
+        assert_eq!(signal_of(&signals, 490000000, None).map(|s| s.signal), Some(TransponderSignal::NoLock));
+        assert_eq!(signal_of(&signals, 11493750, None), None);
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "not a signal\n").unwrap();
+        assert_eq!(read_multiplex_signals(&path).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Matching what the user types against channel names, to find a channel in a long
+//! list of them. Matching ignores case and accents, so that "tele" finds Télé 5, and
+//! failing a match of the whole of what is typed the letters typed are looked for in
+//! order, so that "c4" finds Channel 4.
+
+/// How well a name matches what has been typed, best first.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Match {
+    Start,  // The name starts with what has been typed.
+    WordStart,  // A word of the name starts with what has been typed.
+    Within,  // What has been typed is somewhere in the name.
+    Letters,  // The letters and digits typed are in the name, in order.
+}
+
+/// The base letter, or letters, of an accented Latin letter, for the letters used in
+/// European languages, or `None` if it is not one.
+fn unaccented(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'ĥ' | 'ħ' => "h",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'ĵ' => "j",
+        'ķ' => "k",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => "s",
+        'ß' => "ss",
+        'ţ' | 'ť' | 'ŧ' | 'ț' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+/// The text in lower case without accents, combining accents being left out and
+/// accented letters replaced by their base letters.
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        match unaccented(c) {
+            Some(base) => folded.push_str(base),
+            None if ('\u{300}'..='\u{36f}').contains(&c) => {},
+            None => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// How well `name` matches `typed`, if it does. Everything matches nothing having been
+/// typed.
+pub fn match_name(typed: &str, name: &str) -> Option<Match> {
+    let typed = fold(typed.trim());
+    let name = fold(name);
+    if name.starts_with(&typed) {
+        return Some(Match::Start);
+    }
+    if let Some(position) = name.find(&typed) {
+        let is_word_start = name[..position].chars().last().map_or(true, |c| !c.is_alphanumeric());
+        return Some(if is_word_start { Match::WordStart } else { Match::Within });
+    }
+    let mut letters = name.chars();
+    if typed.chars().filter(|c| c.is_alphanumeric()).all(|t| letters.any(|c| c == t)) {
+        Some(Match::Letters)
+    } else {
+        None
+    }
+}
+
+/// The items whose names match `typed`, best match first, items matching equally well
+/// being in the order they were given in.
+pub fn matching<'a, T>(typed: &str, items: &'a [T], name: impl Fn(&T) -> &str) -> Vec<&'a T> {
+    let mut matches = items.iter()
+        .filter_map(|item| match_name(typed, name(item)).map(|m| (m, item)))
+        .collect::<Vec<_>>();
+    matches.sort_by_key(|(m, _)| *m);
+    matches.into_iter().map(|(_, item)| item).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_and_accents_are_folded_away() {
+        assert_eq!(fold("BBC ONE"), "bbc one");
+        assert_eq!(fold("Télé 5"), "tele 5");
+        assert_eq!(fold("ÇA VA"), "ca va");
+        assert_eq!(fold("Straße"), "strasse");
+        assert_eq!(fold("Te\u{301}le\u{301}"), "tele");
+    }
+
+    #[test]
+    fn names_match_at_the_start_then_at_words_then_within_then_by_letters() {
+        assert_eq!(match_name("bbc", "BBC ONE"), Some(Match::Start));
+        assert_eq!(match_name("one", "BBC ONE"), Some(Match::WordStart));
+        assert_eq!(match_name("ne", "BBC ONE"), Some(Match::Within));
+        assert_eq!(match_name("bbc1", "BBC ONE"), None);
+        assert_eq!(match_name("bbco", "BBC ONE"), Some(Match::Letters));
+        assert_eq!(match_name("tele", "Télé 5"), Some(Match::Start));
+        assert_eq!(match_name("TÉLÉ", "tele 5"), Some(Match::Start));
+        assert_eq!(match_name("itv", "BBC ONE"), None);
+        assert_eq!(match_name("", "BBC ONE"), Some(Match::Start));
+        assert_eq!(match_name("  ", "BBC ONE"), Some(Match::Start));
+    }
+
+    #[test]
+    fn the_best_matches_come_first_in_the_order_given() {
+        let names = ["BBC ONE", "CBBC", "BBC TWO", "Channel 4", "5 USA"];
+        assert_eq!(matching("bbc", &names, |n| *n), vec![&"BBC ONE", &"BBC TWO", &"CBBC"]);
+        assert_eq!(matching("c4", &names, |n| *n), vec![&"Channel 4"]);
+        assert_eq!(matching("", &names, |n| *n), names.iter().collect::<Vec<_>>());
+    }
+}
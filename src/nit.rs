@@ -0,0 +1,411 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Parse Network Information Table (NIT) sections, ETSI EN 300 468 §5.2.1, for the
+//! transport streams a network announces and the transponders they are on.
+//!
+//! Sections are assembled from the transport stream with the `SectionAssembler` of the
+//! EIT, a NIT being carried in sections the same way.
+
+use std::collections::BTreeMap;
+
+use crate::eit::crc32_mpeg2;
+use crate::scan::Transponder;
+
+/// The PID on which NIT sections are transmitted.
+pub const NIT_PID: u16 = 0x10;
+
+/// The table id of the NIT of the network the transport stream is of.
+pub const ACTUAL_NETWORK: u8 = 0x40;
+
+/// A transport stream the network announces, and the transponders its delivery system
+/// descriptors say it is on, none if it has no delivery system descriptor Me TV knows.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NitTransportStream {
+    pub transport_stream_id: u16,
+    pub original_network_id: u16,
+    pub transponders: Vec<Transponder>,
+}
+
+/// A NIT section.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NitSection {
+    pub table_id: u8,
+    pub network_id: u16,
+    pub version_number: u8,
+    pub section_number: u8,
+    pub last_section_number: u8,
+    pub transport_streams: Vec<NitTransportStream>,
+}
+
+/// The value of `digits` BCD digits at the start of `data`, None if they are not BCD.
+fn bcd(data: &[u8], digits: usize) -> Option<u32> {
+    (0..digits).try_fold(0u32, |value, i| {
+        let byte = *data.get(i / 2)?;
+        let digit = if i % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+        if digit > 9 { None } else { Some(value * 10 + u32::from(digit)) }
+    })
+}
+
+fn parameter(key: &str, value: &str) -> (String, String) {
+    (key.to_string(), value.to_string())
+}
+
+/// The DVBv5 name of the code rate of a terrestrial delivery system descriptor.
+fn terrestrial_code_rate(code: u8) -> Option<&'static str> {
+    ["1/2", "2/3", "3/4", "5/6", "7/8"].get(code as usize).copied()
+}
+
+/// The DVBv5 name of the inner FEC of a satellite or cable delivery system descriptor.
+fn inner_fec(code: u8) -> &'static str {
+    match code {
+        1 => "1/2",
+        2 => "2/3",
+        3 => "3/4",
+        4 => "5/6",
+        5 => "7/8",
+        6 => "8/9",
+        7 => "3/5",
+        8 => "4/5",
+        9 => "9/10",
+        15 => "NONE",
+        _ => "AUTO",
+    }
+}
+
+/// The transponder of a terrestrial delivery system descriptor, DVB-T.
+fn terrestrial_delivery(body: &[u8]) -> Option<Transponder> {
+    if body.len() < 7 {
+        return None;
+    }
+    let frequency = u32::from_be_bytes([body[0], body[1], body[2], body[3]]).checked_mul(10)?;
+    let mut parameters = Vec::new();
+    if let Some(megahertz) = [8, 7, 6, 5].get((body[4] >> 5) as usize) {
+        parameters.push(parameter("BANDWIDTH_HZ", &(megahertz * 1_000_000).to_string()));
+    }
+    if let Some(modulation) = ["QPSK", "QAM/16", "QAM/64"].get((body[5] >> 6) as usize) {
+        parameters.push(parameter("MODULATION", modulation));
+    }
+    if let Some(code_rate) = terrestrial_code_rate(body[5] & 0x07) {
+        parameters.push(parameter("CODE_RATE_HP", code_rate));
+    }
+    parameters.push(parameter("GUARD_INTERVAL", ["1/32", "1/16", "1/8", "1/4"][((body[6] >> 3) & 0x03) as usize]));
+    if let Some(mode) = ["2K", "8K", "4K"].get(((body[6] >> 1) & 0x03) as usize) {
+        parameters.push(parameter("TRANSMISSION_MODE", mode));
+    }
+    Some(Transponder{delivery_system: "DVBT".to_string(), frequency, parameters})
+}
+
+/// The parameters of a T2 delivery system descriptor, without its descriptor tag
+/// extension, and the centre frequencies of its cells.
+fn t2_delivery(body: &[u8]) -> Option<(Vec<(String, String)>, Vec<u32>)> {
+    if body.len() < 3 {
+        return None;
+    }
+    let mut parameters = vec![parameter("STREAM_ID", &body[0].to_string())];
+    let mut frequencies = Vec::new();
+    if body.len() >= 5 {
+        let bandwidth = [8_000_000, 7_000_000, 6_000_000, 5_000_000, 10_000_000, 1_712_000].get(((body[3] >> 2) & 0x0f) as usize);
+        if let Some(bandwidth) = bandwidth {
+            parameters.push(parameter("BANDWIDTH_HZ", &bandwidth.to_string()));
+        }
+        if let Some(guard_interval) = ["1/32", "1/16", "1/8", "1/4", "1/128", "19/128", "19/256"].get((body[4] >> 5) as usize) {
+            parameters.push(parameter("GUARD_INTERVAL", guard_interval));
+        }
+        if let Some(mode) = ["2K", "8K", "4K", "1K", "16K", "32K"].get(((body[4] >> 2) & 0x07) as usize) {
+            parameters.push(parameter("TRANSMISSION_MODE", mode));
+        }
+        let is_tfs = body[4] & 0x01 != 0;
+        let mut i = 5;
+        let frequency_at = |i: usize| body.get(i..i + 4).and_then(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]).checked_mul(10));
+        while i + 2 < body.len() {
+            i += 2;  // The cell id.
+            if is_tfs {
+                let length = body[i] as usize;
+                frequencies.extend((0..length / 4).filter_map(|n| frequency_at(i + 1 + 4 * n)));
+                i += 1 + length;
+            } else {
+                frequencies.extend(frequency_at(i));
+                i += 4;
+            }
+            i += 1 + body.get(i).map_or(0, |length| *length as usize);  // The subcells.
+        }
+    }
+    Some((parameters, frequencies))
+}
+
+/// The transponder of a satellite delivery system descriptor, DVB-S or DVB-S2.
+fn satellite_delivery(body: &[u8]) -> Option<Transponder> {
+    if body.len() < 11 {
+        return None;
+    }
+    let frequency = bcd(&body[0..4], 8)? * 10;
+    let is_s2 = body[6] & 0x04 != 0;
+    let mut parameters = vec![
+        parameter("POLARIZATION", ["HORIZONTAL", "VERTICAL", "LEFT", "RIGHT"][((body[6] >> 5) & 0x03) as usize]),
+        parameter("SYMBOL_RATE", &(bcd(&body[7..11], 7)? * 100).to_string()),
+        parameter("INNER_FEC", inner_fec(body[10] & 0x0f)),
+    ];
+    if is_s2 {
+        if let Some(modulation) = [None, Some("QPSK"), Some("PSK/8"), Some("APSK/16")][(body[6] & 0x03) as usize] {
+            parameters.push(parameter("MODULATION", modulation));
+        }
+        if let Some(rolloff) = ["35", "25", "20"].get(((body[6] >> 3) & 0x03) as usize) {
+            parameters.push(parameter("ROLLOFF", rolloff));
+        }
+    }
+    Some(Transponder{delivery_system: if is_s2 { "DVBS2" } else { "DVBS" }.to_string(), frequency, parameters})
+}
+
+/// The transponder of a cable delivery system descriptor, DVB-C.
+fn cable_delivery(body: &[u8]) -> Option<Transponder> {
+    if body.len() < 11 {
+        return None;
+    }
+    let frequency = bcd(&body[0..4], 8)?.checked_mul(100)?;
+    let mut parameters = vec![parameter("SYMBOL_RATE", &(bcd(&body[7..11], 7)? * 100).to_string())];
+    parameters.push(parameter("INNER_FEC", inner_fec(body[10] & 0x0f)));
+    if let Some(modulation) = ["QAM/16", "QAM/32", "QAM/64", "QAM/128", "QAM/256"].get((body[6] as usize).wrapping_sub(1)) {
+        parameters.push(parameter("MODULATION", modulation));
+    }
+    Some(Transponder{delivery_system: "DVBC/ANNEX_A".to_string(), frequency, parameters})
+}
+
+/// The transponders the transport stream descriptors `descriptors` say the transport
+/// stream is on. A DVB-T2 transport stream may have a terrestrial delivery system
+/// descriptor as well as a T2 one, which gives the frequency and bandwidth if the T2 one
+/// does not.
+fn transponders(descriptors: &[u8]) -> Vec<Transponder> {
+    let mut transponders = Vec::new();
+    let (mut terrestrial, mut t2) = (None, None);
+    let mut i = 0;
+    while i + 2 <= descriptors.len() {
+        let tag = descriptors[i];
+        let body = &descriptors[i + 2..(i + 2 + descriptors[i + 1] as usize).min(descriptors.len())];
+        match tag {
+            0x43 => transponders.extend(satellite_delivery(body)),
+            0x44 => transponders.extend(cable_delivery(body)),
+            0x5a => terrestrial = terrestrial_delivery(body),
+            0x7f if body.first() == Some(&0x04) => t2 = t2_delivery(&body[1..]),
+            _ => {},
+        }
+        i += 2 + descriptors[i + 1] as usize;
+    }
+    match (terrestrial, t2) {
+        (terrestrial, Some((mut parameters, mut frequencies))) => {
+            if let Some(terrestrial) = terrestrial {
+                if frequencies.is_empty() {
+                    frequencies.push(terrestrial.frequency);
+                }
+                let bandwidth = terrestrial.parameters.into_iter().find(|(key, _)| key == "BANDWIDTH_HZ");
+                if !parameters.iter().any(|(key, _)| key == "BANDWIDTH_HZ") {
+                    parameters.extend(bandwidth);
+                }
+            }
+            transponders.extend(frequencies.into_iter().map(|frequency| {
+                Transponder{delivery_system: "DVBT2".to_string(), frequency, parameters: parameters.clone()}
+            }));
+        },
+        (Some(terrestrial), None) => transponders.push(terrestrial),
+        (None, None) => {},
+    }
+    transponders
+}
+
+/// Parse a NIT section, checking its structure and CRC.
+pub fn parse_nit_section(section: &[u8]) -> Result<NitSection, String> {
+    if section.len() < 16 {
+        return Err(format!("NIT section too short: {} bytes.", section.len()));
+    }
+    let table_id = section[0];
+    if table_id != ACTUAL_NETWORK && table_id != 0x41 {
+        return Err(format!("Table id {:#04x} is not a NIT.", table_id));
+    }
+    let length = (((section[1] & 0x0f) as usize) << 8 | section[2] as usize) + 3;
+    if length != section.len() {
+        return Err(format!("NIT section length {} does not match the data length {}.", length, section.len()));
+    }
+    if crc32_mpeg2(section) != 0 {
+        return Err("NIT section has an incorrect CRC.".to_string());
+    }
+    let u16_at = |i: usize| (u16::from(section[i]) << 8) | u16::from(section[i + 1]);
+    let length_at = |i: usize| (u16_at(i) & 0x0fff) as usize;
+    let end = length - 4;
+    let mut i = 10 + length_at(8);
+    if i + 2 > end {
+        return Err("Network descriptors overrun the NIT section.".to_string());
+    }
+    let loop_end = i + 2 + length_at(i);
+    if loop_end > end {
+        return Err("Transport streams overrun the NIT section.".to_string());
+    }
+    i += 2;
+    let mut transport_streams = Vec::new();
+    while i + 6 <= loop_end {
+        let transport_stream_id = u16_at(i);
+        let descriptors_length = length_at(i + 4);
+        i += 6;
+        if i + descriptors_length > loop_end {
+            return Err(format!("Descriptors of transport stream {} overrun the NIT section.", transport_stream_id));
+        }
+        transport_streams.push(NitTransportStream {
+            transport_stream_id,
+            original_network_id: u16_at(i - 4),
+            transponders: transponders(&section[i..i + descriptors_length]),
+        });
+        i += descriptors_length;
+    }
+    Ok(NitSection {
+        table_id,
+        network_id: u16_at(3),
+        version_number: (section[5] >> 1) & 0x1f,
+        section_number: section[6],
+        last_section_number: section[7],
+        transport_streams,
+    })
+}
+
+/// Collects the sections of the NIT of the actual network until it has all of them.
+#[derive(Debug, Default)]
+pub struct NitCollector {
+    sections: BTreeMap<u8, NitSection>,
+}
+
+impl NitCollector {
+    /// Add a section, a new version of the table starting the collection again, returning
+    /// whether all the sections of the table have now been collected. Sections of the NIT
+    /// of other networks are ignored.
+    pub fn add(&mut self, section: NitSection) -> bool {
+        if section.table_id == ACTUAL_NETWORK {
+            let is_new_version = self.sections.values().next()
+                .map_or(false, |s| s.version_number != section.version_number || s.network_id != section.network_id);
+            if is_new_version {
+                self.sections.clear();
+            }
+            self.sections.insert(section.section_number, section);
+        }
+        self.is_complete()
+    }
+
+    /// Whether all the sections of the table have been collected.
+    pub fn is_complete(&self) -> bool {
+        match self.sections.values().next() {
+            Some(section) => (0..=section.last_section_number).all(|n| self.sections.contains_key(&n)),
+            None => false,
+        }
+    }
+
+    /// The transport streams of the sections collected.
+    pub fn transport_streams(&self) -> Vec<NitTransportStream> {
+        self.sections.values().flat_map(|section| section.transport_streams.iter().cloned()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a NIT section of the actual network from the descriptors of each of its
+    /// transport streams, given by transport stream id.
+    fn create_section(section_number: u8, last_section_number: u8, transport_streams: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let name = b"\x05Freeview";
+        let mut section = vec![ACTUAL_NETWORK, 0, 0, 0x30, 0x04, 0xc3, section_number, last_section_number, 0xf0, name.len() as u8 + 2, 0x40, name.len() as u8];
+        section.extend_from_slice(name);
+        let loop_length = transport_streams.iter().map(|(_, descriptors)| 6 + descriptors.len()).sum::<usize>();
+        section.extend_from_slice(&[0xf0 | (loop_length >> 8) as u8, loop_length as u8]);
+        for (transport_stream_id, descriptors) in transport_streams {
+            section.extend_from_slice(&transport_stream_id.to_be_bytes());
+            section.extend_from_slice(&[0x23, 0x3a, 0xf0 | (descriptors.len() >> 8) as u8, descriptors.len() as u8]);
+            section.extend_from_slice(descriptors);
+        }
+        let length = section.len() + 4 - 3;
+        section[1] = 0xf0 | (length >> 8) as u8;
+        section[2] = length as u8;
+        let crc = crc32_mpeg2(&section);
+        section.extend_from_slice(&crc.to_be_bytes());
+        section
+    }
+
+    /// A terrestrial delivery system descriptor: 490 MHz, 8 MHz, QAM/64, 2/3, 1/32, 8K.
+    const TERRESTRIAL: [u8; 13] = [0x5a, 11, 0x02, 0xeb, 0xae, 0x40, 0x1f, 0x81, 0x02, 0xff, 0xff, 0xff, 0xff];
+
+    /// A T2 delivery system descriptor: PLP 0, 8 MHz, 1/128, 32K, one cell at 474 MHz.
+    const T2: [u8; 15] = [0x7f, 13, 0x04, 0x00, 0x00, 0x01, 0x00, 0x94, 0x00, 0x14, 0x02, 0xd3, 0x44, 0x40, 0x00];
+
+    /// A satellite delivery system descriptor: DVB-S2 8PSK 11.49375 GHz horizontal, 22 Msymbol/s, 3/4.
+    const SATELLITE: [u8; 13] = [0x43, 11, 0x01, 0x14, 0x93, 0x75, 0x02, 0x82, 0x86, 0x02, 0x20, 0x00, 0x03];
+
+    fn parameters(parameters: &[(&str, &str)]) -> Vec<(String, String)> {
+        parameters.iter().map(|(key, value)| parameter(key, value)).collect()
+    }
+
+    #[test]
+    fn terrestrial_and_t2_transport_streams_are_on_their_transponders() {
+        let mut t2 = T2.to_vec();
+        t2.extend_from_slice(&TERRESTRIAL);
+        let section = parse_nit_section(&create_section(0, 0, &[(0x1004, TERRESTRIAL.to_vec()), (0x3005, t2)])).unwrap();
+        assert_eq!(section.network_id, 0x3004);
+        assert_eq!(section.version_number, 1);
+        assert_eq!(section.transport_streams.len(), 2);
+        assert_eq!(section.transport_streams[0].original_network_id, 0x233a);
+        assert_eq!(section.transport_streams[0].transponders, vec![Transponder {
+            delivery_system: "DVBT".to_string(),
+            frequency: 490000000,
+            parameters: parameters(&[("BANDWIDTH_HZ", "8000000"), ("MODULATION", "QAM/64"), ("CODE_RATE_HP", "2/3"), ("GUARD_INTERVAL", "1/32"), ("TRANSMISSION_MODE", "8K")]),
+        }]);
+        assert_eq!(section.transport_streams[1].transport_stream_id, 0x3005);
+        assert_eq!(section.transport_streams[1].transponders, vec![Transponder {
+            delivery_system: "DVBT2".to_string(),
+            frequency: 474000000,
+            parameters: parameters(&[("STREAM_ID", "0"), ("BANDWIDTH_HZ", "8000000"), ("GUARD_INTERVAL", "1/128"), ("TRANSMISSION_MODE", "32K")]),
+        }]);
+    }
+
+    #[test]
+    fn satellite_transport_streams_are_on_their_transponders() {
+        let section = parse_nit_section(&create_section(0, 0, &[(2045, SATELLITE.to_vec()), (2046, Vec::new())])).unwrap();
+        assert_eq!(section.transport_streams[0].transponders, vec![Transponder {
+            delivery_system: "DVBS2".to_string(),
+            frequency: 11493750,
+            parameters: parameters(&[("POLARIZATION", "HORIZONTAL"), ("SYMBOL_RATE", "22000000"), ("INNER_FEC", "3/4"), ("MODULATION", "PSK/8"), ("ROLLOFF", "35")]),
+        }]);
+        assert!(section.transport_streams[1].transponders.is_empty());
+    }
+
+    #[test]
+    fn corrupted_sections_are_rejected() {
+        let mut section = create_section(0, 0, &[(0x1004, TERRESTRIAL.to_vec())]);
+        section[20] ^= 0x01;
+        assert!(parse_nit_section(&section).is_err());
+        assert!(parse_nit_section(&section[..10]).is_err());
+    }
+
+    #[test]
+    fn the_table_is_complete_when_all_its_sections_are_collected() {
+        let section = |number: u8, transport_stream_id: u16| parse_nit_section(&create_section(number, 1, &[(transport_stream_id, TERRESTRIAL.to_vec())])).unwrap();
+        let mut collector = NitCollector::default();
+        assert!(!collector.add(section(1, 0x2002)));
+        assert!(!collector.add(section(1, 0x2002)));
+        assert!(collector.add(section(0, 0x1004)));
+        let ids = collector.transport_streams().iter().map(|ts| ts.transport_stream_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![0x1004, 0x2002]);
+    }
+}
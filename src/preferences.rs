@@ -20,6 +20,7 @@
  */
 
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions, create_dir_all};
 use std::io::prelude::*;
 use std::path::PathBuf;
@@ -30,7 +31,10 @@ use serde_derive::{Deserialize, Serialize};
 use serde_yaml ;
 use xdg;
 
+use crate::channel_order::ChannelOrder;
 use crate::dvb;
+use crate::favourites::{ChannelView, FavouriteChannel};
+use crate::recent_channels::RecentChannel;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 struct Preferences {
@@ -40,10 +44,102 @@ struct Preferences {
     use_last_channel: bool,
     default_channel: String,
     last_channel: String,
+    // The frontend the last channel was watched on, as adapter0:frontend0.
+    #[serde(default)]
+    last_frontend: String,
+    #[serde(default = "default_resume_last_channel")]
+    resume_last_channel: bool,
     nongl_deinterlace_method: String,
     gl_deinterlace_method: String,
+    // Defaulted so that preferences files written before these existed still load.
+    #[serde(default = "default_reconnect_after_dropout")]
+    reconnect_after_dropout: bool,
+    #[serde(default = "default_reconnect_window")]
+    reconnect_window: u32,
+    #[serde(default)]
+    favourite_channels: Vec<FavouriteChannel>,
+    #[serde(default)]
+    recent_channels: Vec<RecentChannel>,
+    #[serde(default)]
+    channel_view: ChannelView,
+    #[serde(default)]
+    channel_order: ChannelOrder,
+    #[serde(default)]
+    hide_encrypted_channels: bool,
+    #[serde(default = "default_show_radio_channels")]
+    show_radio_channels: bool,
+    #[serde(default)]
+    show_all_sources: bool,
+    // Channels files read as well as the default one, for example one for each source.
+    #[serde(default)]
+    channels_files: Vec<String>,
+    // The directory of the channel logos, the default one if empty.
+    #[serde(default)]
+    logos_directory: String,
+    // How long the channel banner is shown over the video when the channel changes, 0
+    // for it not to be shown.
+    #[serde(default = "default_banner_seconds")]
+    banner_seconds: u32,
+    // An XMLTV listings file to fill in the programme guide from, none if empty.
+    #[serde(default)]
+    xmltv_file: String,
+    // The channel each XMLTV channel id is, for those not matched by their display names,
+    // an empty name for an XMLTV channel that is none of them.
+    #[serde(default)]
+    xmltv_channels: BTreeMap<String, String>,
+    // How many minutes before a programme starts, and after it ends, a recording of it
+    // starts and stops.
+    #[serde(default = "default_recording_padding_before")]
+    recording_padding_before: u32,
+    #[serde(default = "default_recording_padding_after")]
+    recording_padding_after: u32,
+    // How many days old the cache of the programmes of the EIT can be and still be used, 0
+    // for no cache.
+    #[serde(default = "default_epg_cache_days")]
+    epg_cache_days: u32,
+    // How many days ahead the programme guide is kept for, and the most programmes it
+    // keeps of all the channels together.
+    #[serde(default = "default_epg_days_ahead")]
+    epg_days_ahead: u32,
+    #[serde(default = "default_epg_max_programmes")]
+    epg_max_programmes: u32,
+    // The languages the programme guide is shown in, as ISO 639-2 codes, the most
+    // preferred first, that of the broadcaster if empty or none of them is broadcast.
+    #[serde(default)]
+    epg_languages: Vec<String>,
+    // Whether idle frontends are tuned to each multiplex in turn to collect the programme
+    // guide, and in which hours, from the start of one to the start of the other, the
+    // same for all day.
+    #[serde(default)]
+    epg_harvesting: bool,
+    #[serde(default)]
+    epg_harvesting_from_hour: u32,
+    #[serde(default = "default_epg_harvesting_to_hour")]
+    epg_harvesting_to_hour: u32,
 }
 
+fn default_reconnect_after_dropout() -> bool { true }
+
+fn default_reconnect_window() -> u32 { 30 }
+
+fn default_show_radio_channels() -> bool { true }
+
+fn default_resume_last_channel() -> bool { true }
+
+fn default_banner_seconds() -> u32 { 5 }
+
+fn default_recording_padding_before() -> u32 { 2 }
+
+fn default_recording_padding_after() -> u32 { 5 }
+
+fn default_epg_cache_days() -> u32 { 7 }
+
+fn default_epg_days_ahead() -> u32 { 14 }
+
+fn default_epg_max_programmes() -> u32 { 50_000 }
+
+fn default_epg_harvesting_to_hour() -> u32 { 24 }
+
 // TODO Replace the Mutex with a RwLock.
 lazy_static! {
     static ref PREFERENCES: Mutex<RefCell<Preferences>> = Mutex::new(RefCell::new(Preferences{
@@ -53,8 +149,33 @@ lazy_static! {
         use_last_channel: false,
         default_channel: String::from(""),
         last_channel: String::from(""),
+        last_frontend: String::from(""),
+        resume_last_channel: default_resume_last_channel(),
         nongl_deinterlace_method: "".to_string(),
         gl_deinterlace_method: "".to_string(),
+        reconnect_after_dropout: default_reconnect_after_dropout(),
+        reconnect_window: default_reconnect_window(),
+        favourite_channels: Vec::new(),
+        recent_channels: Vec::new(),
+        channel_view: ChannelView::All,
+        channel_order: ChannelOrder::LogicalChannelNumber,
+        hide_encrypted_channels: false,
+        show_radio_channels: default_show_radio_channels(),
+        show_all_sources: false,
+        channels_files: Vec::new(),
+        logos_directory: String::from(""),
+        banner_seconds: default_banner_seconds(),
+        xmltv_file: String::from(""),
+        xmltv_channels: BTreeMap::new(),
+        recording_padding_before: default_recording_padding_before(),
+        recording_padding_after: default_recording_padding_after(),
+        epg_cache_days: default_epg_cache_days(),
+        epg_days_ahead: default_epg_days_ahead(),
+        epg_max_programmes: default_epg_max_programmes(),
+        epg_languages: Vec::new(),
+        epg_harvesting: false,
+        epg_harvesting_from_hour: 0,
+        epg_harvesting_to_hour: default_epg_harvesting_to_hour(),
     }));
 }
 
@@ -166,8 +287,82 @@ create_setter!(set_default_channel, default_channel, String);
 create_option_getter!(get_last_channel, last_channel, String, None);
 create_setter!(set_last_channel, last_channel, String);
 
+create_option_getter!(get_last_frontend, last_frontend, String, None);
+create_setter!(set_last_frontend, last_frontend, String);
+
+create_getter!(get_resume_last_channel, resume_last_channel, bool, true);
+create_setter!(set_resume_last_channel, resume_last_channel, bool);
+
 create_option_getter!(get_nongl_deinterlace_method, nongl_deinterlace_method, String, None);
 create_setter!(set_nongl_deinterlace_method, nongl_deinterlace_method, String);
 
 create_option_getter!(get_gl_deinterlace_method, gl_deinterlace_method, String, None);
 create_setter!(set_gl_deinterlace_method, gl_deinterlace_method, String);
+
+create_getter!(get_reconnect_after_dropout, reconnect_after_dropout, bool, true);
+create_setter!(set_reconnect_after_dropout, reconnect_after_dropout, bool);
+
+create_getter!(get_reconnect_window, reconnect_window, u32, 30);
+create_setter!(set_reconnect_window, reconnect_window, u32);
+
+create_option_getter!(get_favourite_channels, favourite_channels, Vec<FavouriteChannel>, None);
+create_setter!(set_favourite_channels, favourite_channels, Vec<FavouriteChannel>);
+
+create_option_getter!(get_recent_channels, recent_channels, Vec<RecentChannel>, None);
+create_setter!(set_recent_channels, recent_channels, Vec<RecentChannel>);
+
+create_getter!(get_channel_view, channel_view, ChannelView, ChannelView::All);
+create_setter!(set_channel_view, channel_view, ChannelView);
+
+create_getter!(get_channel_order, channel_order, ChannelOrder, ChannelOrder::LogicalChannelNumber);
+create_setter!(set_channel_order, channel_order, ChannelOrder);
+
+create_getter!(get_hide_encrypted_channels, hide_encrypted_channels, bool, false);
+create_setter!(set_hide_encrypted_channels, hide_encrypted_channels, bool);
+
+create_getter!(get_show_radio_channels, show_radio_channels, bool, true);
+create_setter!(set_show_radio_channels, show_radio_channels, bool);
+
+create_getter!(get_show_all_sources, show_all_sources, bool, false);
+create_setter!(set_show_all_sources, show_all_sources, bool);
+
+create_option_getter!(get_channels_files, channels_files, Vec<String>, None);
+create_setter!(set_channels_files, channels_files, Vec<String>);
+
+create_option_getter!(get_logos_directory, logos_directory, String, None);
+create_setter!(set_logos_directory, logos_directory, String);
+
+create_getter!(get_banner_seconds, banner_seconds, u32, 5);
+create_setter!(set_banner_seconds, banner_seconds, u32);
+
+create_option_getter!(get_xmltv_file, xmltv_file, String, None);
+create_setter!(set_xmltv_file, xmltv_file, String);
+
+create_option_getter!(get_xmltv_channels, xmltv_channels, BTreeMap<String, String>, None);
+
+create_getter!(get_recording_padding_before, recording_padding_before, u32, 2);
+create_setter!(set_recording_padding_before, recording_padding_before, u32);
+
+create_getter!(get_recording_padding_after, recording_padding_after, u32, 5);
+create_setter!(set_recording_padding_after, recording_padding_after, u32);
+
+create_getter!(get_epg_cache_days, epg_cache_days, u32, 7);
+create_setter!(set_epg_cache_days, epg_cache_days, u32);
+
+create_getter!(get_epg_days_ahead, epg_days_ahead, u32, 14);
+create_setter!(set_epg_days_ahead, epg_days_ahead, u32);
+
+create_getter!(get_epg_max_programmes, epg_max_programmes, u32, 50_000);
+create_setter!(set_epg_max_programmes, epg_max_programmes, u32);
+
+create_option_getter!(get_epg_languages, epg_languages, Vec<String>, None);
+create_setter!(set_epg_languages, epg_languages, Vec<String>);
+
+create_getter!(get_epg_harvesting, epg_harvesting, bool, false);
+create_setter!(set_epg_harvesting, epg_harvesting, bool);
+
+create_getter!(get_epg_harvesting_from_hour, epg_harvesting_from_hour, u32, 0);
+create_setter!(set_epg_harvesting_from_hour, epg_harvesting_from_hour, u32);
+
+create_getter!(get_epg_harvesting_to_hour, epg_harvesting_to_hour, u32, 24);
+create_setter!(set_epg_harvesting_to_hour, epg_harvesting_to_hour, u32);
@@ -20,6 +20,7 @@
  */
 
 use std::cell::Cell;
+use std::rc::Rc;
 use std::sync::Mutex;
 
 use lazy_static::lazy_static;
@@ -27,7 +28,11 @@ use lazy_static::lazy_static;
 use gtk;
 use gtk::prelude::*;
 
-use crate::control_window::ControlWindow;
+use me_tv::languages::parse_languages;
+
+use crate::channel_logos;
+use crate::channel_search::channel_search_button;
+use crate::control_window::{reload_channels_file, ControlWindow};
 use crate::dvb;
 use crate::metvcombobox::{MeTVComboBox, MeTVComboBoxExt};
 use crate::preferences;
@@ -36,7 +41,7 @@ lazy_static! {
     static ref PREFERENCES: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
 }
 
-fn create(control_window: &ControlWindow) -> gtk::Window {
+fn create(control_window: &Rc<ControlWindow>) -> gtk::Window {
     let menu_builder = gtk::Builder::from_string(include_str!("resources/preferences_dialog.glade.xml"));
     let _delivery_system_comboboxtext = {
         let comboboxtext = menu_builder.get_object::<gtk::ComboBoxText>("delivery_system").unwrap();
@@ -92,8 +97,195 @@ fn create(control_window: &ControlWindow) -> gtk::Window {
         combobox.connect_changed(
             move |selector: &MeTVComboBox| preferences::set_default_channel(selector.get_active_text().unwrap(), true)
         );
+        let search_button = channel_search_button(&control_window.channels_data_sorter, {
+            let combobox = combobox.clone();
+            move |name| { combobox.clone().set_active_text(name.to_string()); }
+        });
+        search_button.show();
+        menu_builder.get_object::<gtk::Box>("default_channel_box").unwrap().pack_start(&search_button, false, false, 0);
         combobox
     };
+    let _resume_last_channel_button = {
+        let button = menu_builder.get_object::<gtk::CheckButton>("resume_last_channel").unwrap();
+        button.set_active(preferences::get_resume_last_channel());
+        button.connect_toggled(
+            move |b| preferences::set_resume_last_channel(b.get_active(), true)
+        );
+        button
+    };
+    let _hide_encrypted_channels_button = {
+        let button = menu_builder.get_object::<gtk::CheckButton>("hide_encrypted_channels").unwrap();
+        button.set_active(preferences::get_hide_encrypted_channels());
+        button.connect_toggled({
+            let c_w = control_window.clone();
+            move |b| {
+                preferences::set_hide_encrypted_channels(b.get_active(), true);
+                c_w.refresh_channels_view();
+            }
+        });
+        button
+    };
+    let _channels_files_box = {
+        let files_box = menu_builder.get_object::<gtk::Box>("channels_files").unwrap();
+        fill_channels_files_box(&files_box, control_window);
+        let add_button = menu_builder.get_object::<gtk::Button>("add_channels_file").unwrap();
+        add_button.connect_clicked({
+            let f_b = files_box.clone();
+            let c_w = control_window.clone();
+            move |b| {
+                let chooser = gtk::FileChooserDialog::with_buttons(
+                    Some("Add a channels file"),
+                    b.get_toplevel().and_then(|w| w.downcast::<gtk::Window>().ok()).as_ref(),
+                    gtk::FileChooserAction::Open,
+                    &[("_Cancel", gtk::ResponseType::Cancel), ("_Add", gtk::ResponseType::Accept)],
+                );
+                let response = gtk::ResponseType::from(chooser.run());
+                let path = chooser.get_filename();
+                unsafe { chooser.destroy(); }
+                if let (gtk::ResponseType::Accept, Some(path)) = (response, path) {
+                    let mut files = preferences::get_channels_files().unwrap_or_default();
+                    let file = path.to_string_lossy().to_string();
+                    if !files.contains(&file) {
+                        files.push(file);
+                        set_channels_files(&f_b, &c_w, files);
+                    }
+                }
+            }
+        });
+        files_box
+    };
+    let _logos_directory_button = {
+        let button = menu_builder.get_object::<gtk::FileChooserButton>("logos_directory").unwrap();
+        button.set_filename(channel_logos::logos_directory());
+        button.connect_file_set({
+            let c_w = control_window.clone();
+            move |b| if let Some(directory) = b.get_filename() {
+                preferences::set_logos_directory(directory.to_string_lossy().to_string(), true);
+                channel_logos::forget_logos();
+                c_w.refresh_logos();
+            }
+        });
+        button
+    };
+    let _xmltv_file_button = {
+        let button = menu_builder.get_object::<gtk::FileChooserButton>("xmltv_file").unwrap();
+        if let Some(file) = preferences::get_xmltv_file().filter(|file| !file.is_empty()) {
+            button.set_filename(file);
+        }
+        // The EPG manager notices the change and reads the file.
+        button.connect_file_set(
+            move |b| if let Some(file) = b.get_filename() {
+                preferences::set_xmltv_file(file.to_string_lossy().to_string(), true);
+            }
+        );
+        button
+    };
+    let _banner_seconds_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("banner_seconds").unwrap();
+        button.set_value(preferences::get_banner_seconds() as f64);
+        button.connect_value_changed(
+            move |b| preferences::set_banner_seconds(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let _recording_padding_before_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("recording_padding_before").unwrap();
+        button.set_value(preferences::get_recording_padding_before() as f64);
+        button.connect_value_changed(
+            move |b| preferences::set_recording_padding_before(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let _recording_padding_after_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("recording_padding_after").unwrap();
+        button.set_value(preferences::get_recording_padding_after() as f64);
+        button.connect_value_changed(
+            move |b| preferences::set_recording_padding_after(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let _epg_cache_days_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("epg_cache_days").unwrap();
+        button.set_value(preferences::get_epg_cache_days() as f64);
+        button.connect_value_changed(
+            move |b| preferences::set_epg_cache_days(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let _epg_days_ahead_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("epg_days_ahead").unwrap();
+        button.set_value(preferences::get_epg_days_ahead() as f64);
+        button.connect_value_changed(
+            move |b| preferences::set_epg_days_ahead(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let _epg_max_programmes_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("epg_max_programmes").unwrap();
+        button.set_value(preferences::get_epg_max_programmes() as f64);
+        button.connect_value_changed(
+            move |b| preferences::set_epg_max_programmes(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let _epg_languages_entry = {
+        let entry = menu_builder.get_object::<gtk::Entry>("epg_languages").unwrap();
+        entry.set_text(&preferences::get_epg_languages().unwrap_or_default().join(", "));
+        entry.connect_changed(
+            move |e| preferences::set_epg_languages(parse_languages(&e.get_text()), true)
+        );
+        entry
+    };
+    let epg_harvesting_from_hour_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("epg_harvesting_from_hour").unwrap();
+        button.set_value(preferences::get_epg_harvesting_from_hour() as f64);
+        button.set_sensitive(preferences::get_epg_harvesting());
+        button.connect_value_changed(
+            move |b| preferences::set_epg_harvesting_from_hour(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let epg_harvesting_to_hour_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("epg_harvesting_to_hour").unwrap();
+        button.set_value(preferences::get_epg_harvesting_to_hour() as f64);
+        button.set_sensitive(preferences::get_epg_harvesting());
+        button.connect_value_changed(
+            move |b| preferences::set_epg_harvesting_to_hour(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let _epg_harvesting_button = {
+        let button = menu_builder.get_object::<gtk::CheckButton>("epg_harvesting").unwrap();
+        button.set_active(preferences::get_epg_harvesting());
+        button.connect_toggled(
+            move |b| {
+                preferences::set_epg_harvesting(b.get_active(), true);
+                epg_harvesting_from_hour_button.set_sensitive(b.get_active());
+                epg_harvesting_to_hour_button.set_sensitive(b.get_active());
+            }
+        );
+        button
+    };
+    let reconnect_window_button = {
+        let button = menu_builder.get_object::<gtk::SpinButton>("reconnect_window").unwrap();
+        button.set_value(preferences::get_reconnect_window() as f64);
+        button.set_sensitive(preferences::get_reconnect_after_dropout());
+        button.connect_value_changed(
+            move |b| preferences::set_reconnect_window(b.get_value_as_int() as u32, true)
+        );
+        button
+    };
+    let _reconnect_after_dropout_button = {
+        let button = menu_builder.get_object::<gtk::CheckButton>("reconnect_after_dropout").unwrap();
+        button.set_active(preferences::get_reconnect_after_dropout());
+        button.connect_toggled(
+            move |b| {
+                preferences::set_reconnect_after_dropout(b.get_active(), true);
+                reconnect_window_button.set_sensitive(b.get_active());
+            }
+        );
+        button
+    };
     let _nongl_deinterlace_method_selector = {
         let comboboxtext = menu_builder.get_object::<gtk::ComboBoxText>("nongl_deinterlace_method").unwrap();
         if let Some(method) = preferences::get_nongl_deinterlace_method() {
@@ -131,8 +323,40 @@ fn create(control_window: &ControlWindow) -> gtk::Window {
     preferences_dialog
 }
 
+/// Show the further channels files of the preferences, each with a button to stop reading it.
+fn fill_channels_files_box(files_box: &gtk::Box, control_window: &Rc<ControlWindow>) {
+    for child in files_box.get_children() {
+        files_box.remove(&child);
+    }
+    for file in preferences::get_channels_files().unwrap_or_default() {
+        let row = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let label = gtk::Label::new(Some(&file));
+        label.set_halign(gtk::Align::Start);
+        row.pack_start(&label, true, true, 0);
+        let remove_button = gtk::Button::with_label("Remove");
+        remove_button.connect_clicked({
+            let f_b = files_box.clone();
+            let c_w = control_window.clone();
+            move |_| {
+                let files = preferences::get_channels_files().unwrap_or_default().into_iter().filter(|x| *x != file).collect();
+                set_channels_files(&f_b, &c_w, files);
+            }
+        });
+        row.pack_end(&remove_button, false, false, 0);
+        files_box.pack_start(&row, false, false, 0);
+    }
+    files_box.show_all();
+}
+
+/// Change the further channels files of the preferences and reread the channels.
+fn set_channels_files(files_box: &gtk::Box, control_window: &Rc<ControlWindow>, files: Vec<String>) {
+    preferences::set_channels_files(files, true);
+    reload_channels_file(control_window);
+    fill_channels_files_box(files_box, control_window);
+}
+
 /// Display a preferences dialog in a non-modal way, but only if one is not already being displayed.
-pub fn present(control_window: &ControlWindow) {
+pub fn present(control_window: &Rc<ControlWindow>) {
     if let Ok(active) = PREFERENCES.lock() {
         if ! active.get() {
             let dialog = create(control_window);
@@ -0,0 +1,423 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The details of a programme, as the programme guide, the banner over the video, and
+//! the channel menu of a frontend show them: the title, when it is on and for how long,
+//! the genres, the parental ratings, the descriptions, in any of the languages they are
+//! broadcast in, and the choices of watching the channel now, recording the programme,
+//! and being reminded when it starts. Whatever the programme store does not have is left
+//! out.
+//!
+//! Recording adds a job to the schedule of me-tv-schedule, from a little before the
+//! programme starts to a little after it ends as the preferences say, the dæmon of
+//! me-tv-schedule recording it whether or not Me TV is still running then. A job for a
+//! programme of the EIT has the event id, me-tv-record then starting and stopping as the
+//! EIT says.
+//!
+//! The programmes to be recorded or reminded of are kept in the programme store however
+//! full it gets.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+use chrono::{Duration, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+
+use glib;
+
+use gtk;
+use gtk::prelude::*;
+
+use lazy_static::lazy_static;
+
+use log::{info, warn};
+
+use me_tv::desktop_notification::send_notification;
+use me_tv::epg::Programme;
+use me_tv::frontends::installed_frontends;
+use me_tv::languages::language_name;
+use me_tv::schedule::{conflicts, read_schedule, schedule_file_path, write_schedule, Job};
+
+use crate::control_window::{watch_channel_now, ControlWindow};
+use crate::dialogs::display_an_error_dialog;
+use crate::epg_window;
+use crate::preferences;
+
+/// How long before a programme starts the reminder of it is shown.
+const REMINDER_MINUTES: i64 = 2;
+
+const WATCH: gtk::ResponseType = gtk::ResponseType::Other(1);
+const RECORD: gtk::ResponseType = gtk::ResponseType::Other(2);
+const REMIND: gtk::ResponseType = gtk::ResponseType::Other(3);
+
+lazy_static! {
+    // The channels and starts, UTC, of the programmes to be reminded of, which the EPG
+    // manager looks at too.
+    static ref REMINDERS: Mutex<HashSet<(String, NaiveDateTime)>> = Mutex::new(HashSet::new());
+}
+
+// Dialogs can only be used in the GTK event loop thread, so there is no need of a Mutex,
+// the EPG manager having a schedule of its own.
+thread_local! {
+    // The jobs of the schedule file as it was when last modified at the time given.
+    static SCHEDULE: RefCell<Option<(Option<SystemTime>, Vec<Job>)>> = RefCell::new(None);
+}
+
+/// The job recording `programme` on the channel `channel`, starting at `local_start`,
+/// local time, to a file in `directory`, from `padding_before` minutes before it starts,
+/// to the minute, until `padding_after` minutes after it ends.
+fn recording_job(channel: &str, programme: &Programme, local_start: NaiveDateTime, directory: &Path, padding_before: u32, padding_after: u32) -> Job {
+    let start = (local_start - Duration::minutes(padding_before.into())).with_second(0).unwrap();
+    let end = local_start + Duration::seconds(programme.duration_seconds.into()) + Duration::minutes(padding_after.into());
+    Job {
+        channel: channel.to_string(),
+        start: start.format("%Y-%m-%dT%H:%M").to_string(),
+        end: None,
+        duration: Some((((end - start).num_seconds() + 59) / 60) as u32),
+        output: recording_path(directory, channel, &programme.title, local_start).to_string_lossy().to_string(),
+        adapter: None,
+        event_id: programme.event_id,
+    }
+}
+
+/// The file in `directory` to record the programme `title` on the channel `channel`
+/// starting at `local_start`, local time, to.
+fn recording_path(directory: &Path, channel: &str, title: &str, local_start: NaiveDateTime) -> PathBuf {
+    let name = format!("{} {} {}.mp4", channel, local_start.format("%Y%m%dT%H%M"), title);
+    directory.join(name.replace('/', "-"))
+}
+
+/// How long `seconds` is, in hours and minutes, for showing to the user.
+fn duration_text(seconds: u32) -> String {
+    let minutes = (seconds + 59) / 60;
+    let plural = |count: u32, unit: &str| if count == 1 { format!("1 {}", unit) } else { format!("{} {}s", count, unit) };
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => plural(minutes, "minute"),
+        (hours, 0) => plural(hours, "hour"),
+        (hours, minutes) => format!("{} {}", plural(hours, "hour"), plural(minutes, "minute")),
+    }
+}
+
+/// When `programme` is on, for how long, and on which channel, for showing to the user.
+fn when_text(channel: &str, programme: &Programme) -> String {
+    let mut parts = Vec::new();
+    if let Some(start) = programme.start {
+        parts.push(Local.from_utc_datetime(&start).format("%A %e %B").to_string());
+    }
+    if let Some(times) = programme.times_text() {
+        parts.push(times);
+    }
+    if programme.duration_seconds > 0 {
+        parts.push(format!("({})", duration_text(programme.duration_seconds)));
+    }
+    parts.push(format!("on {}", channel));
+    parts.join(" ")
+}
+
+/// A label of one of the texts of the details, wrapped if it is long.
+fn details_label(text: &str) -> gtk::Label {
+    let label = gtk::Label::new(Some(text));
+    label.set_line_wrap(true);
+    label.set_max_width_chars(60);
+    label.set_selectable(true);
+    label.set_xalign(0.0);
+    label
+}
+
+/// Show the title of `programme` as that of `dialog` and in `title_label`, and its
+/// descriptions in `descriptions_box`, in place of any there were.
+fn show_texts(dialog: &gtk::Dialog, title_label: &gtk::Label, descriptions_box: &gtk::Box, programme: &Programme) {
+    let title = if programme.title.is_empty() { "Untitled programme" } else { &programme.title };
+    dialog.set_title(title);
+    title_label.set_markup(&format!("<big><b>{}</b></big>", glib::markup_escape_text(title)));
+    for child in descriptions_box.get_children() {
+        descriptions_box.remove(&child);
+    }
+    let descriptions = [&programme.description, &programme.extended_description];
+    for description in descriptions.iter().filter(|description| !description.is_empty()) {
+        descriptions_box.pack_start(&details_label(description), false, false, 0);
+    }
+    if descriptions.iter().all(|description| description.is_empty()) {
+        let label = details_label("There is no description of this programme.");
+        label.set_sensitive(false);
+        descriptions_box.pack_start(&label, false, false, 0);
+    }
+    descriptions_box.show_all();
+}
+
+/// The seconds from now until `at`, UTC, for a timeout.
+fn seconds_until(at: NaiveDateTime) -> u32 {
+    (at - Utc::now().naive_utc()).num_seconds().max(0) as u32
+}
+
+/// When `job` starts, and on which channel, for showing to the user.
+fn job_text(job: &Job) -> String {
+    match job.start_time() {
+        Ok(start) => format!("{} from {}", job.channel, start.format("%H:%M on %A %e %B")),
+        Err(_) => format!("{} from {}", job.channel, job.start),
+    }
+}
+
+/// Ask the dæmon of me-tv-schedule, if it is running, to reread the schedule file.
+fn reload_schedule() -> bool {
+    match Command::new("pkill").args(&["-HUP", "-x", "me-tv-schedule"]).status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            warn!("Could not run pkill to signal me-tv-schedule: {}", e);
+            false
+        },
+    }
+}
+
+/// Add a job recording `programme` on `channel` to the schedule, saying first if it
+/// overlaps any other jobs.
+fn record<T: IsA<gtk::Window>>(parent: Option<&T>, channel: &str, programme: &Programme) {
+    let start = match programme.start { Some(start) => start, None => return };
+    if programme.end().map_or(true, |end| end <= Utc::now().naive_utc()) {
+        display_an_error_dialog(parent, &format!("{} has ended, so cannot be recorded.", programme.title));
+        return;
+    }
+    let directory = glib::get_user_special_dir(glib::UserDirectory::Videos)
+        .or_else(glib::get_home_dir)
+        .unwrap_or_else(|| PathBuf::from("."));
+    let job = recording_job(
+        channel, programme, Local.from_utc_datetime(&start).naive_local(), &directory,
+        preferences::get_recording_padding_before(), preferences::get_recording_padding_after(),
+    );
+    let path = schedule_file_path();
+    let mut jobs = match read_schedule(&path) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            display_an_error_dialog(parent, &format!("Cannot schedule {}:\n{}", programme.title, e));
+            return;
+        },
+    };
+    let overlapping = conflicts(&jobs, &job);
+    if !overlapping.is_empty() {
+        let message = format!(
+            "Recording {} overlaps recording:\n\n{}\n\nEach needs a frontend of its own, there are {}. Record it anyway?",
+            programme.title,
+            overlapping.into_iter().map(job_text).collect::<Vec<String>>().join("\n"),
+            installed_frontends().len(),
+        );
+        let dialog = gtk::MessageDialog::new(parent, gtk::DialogFlags::MODAL, gtk::MessageType::Warning, gtk::ButtonsType::YesNo, &message);
+        let response = dialog.run();
+        unsafe { dialog.destroy(); }
+        if response != gtk::ResponseType::Yes {
+            return;
+        }
+    }
+    let message = format!("{} will be recorded to {} by me-tv-schedule.", programme.title, job.output);
+    jobs.retain(|j| j.key() != job.key());
+    jobs.push(job);
+    if let Err(e) = write_schedule(&path, &jobs) {
+        display_an_error_dialog(parent, &format!("Cannot schedule {}:\n{}", programme.title, e));
+        return;
+    }
+    info!("Scheduled recording {} on {}.", programme.title, channel);
+    epg_window::schedule_changed();
+    let message = if reload_schedule() {
+        message
+    } else {
+        format!("{}\n\nThe me-tv-schedule dæmon is not running, it must be by then for the recording to be made.", message)
+    };
+    let dialog = gtk::MessageDialog::new(parent, gtk::DialogFlags::MODAL, gtk::MessageType::Info, gtk::ButtonsType::Ok, &message);
+    dialog.run();
+    unsafe { dialog.destroy(); }
+}
+
+/// Whether the schedule has a job recording `programme` on `channel`. The schedule file
+/// is only reread if it has been modified since it was last read.
+pub fn is_scheduled(channel: &str, programme: &Programme) -> bool {  // Used in epg_window.rs
+    let (start, end) = match (programme.start, programme.end()) {
+        (Some(start), Some(end)) => (Local.from_utc_datetime(&start).naive_local(), Local.from_utc_datetime(&end).naive_local()),
+        _ => return false,
+    };
+    let path = schedule_file_path();
+    let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+    SCHEDULE.with(|schedule| {
+        let mut schedule = schedule.borrow_mut();
+        if schedule.as_ref().map_or(true, |(read_modified, _)| *read_modified != modified) {
+            *schedule = Some((modified, read_schedule(&path).unwrap_or_default()));
+        }
+        schedule.as_ref().map_or(false, |(_, jobs)| jobs.iter().any(|job| job.records(channel, programme.event_id, start, end)))
+    })
+}
+
+/// Whether `programme` on `channel` is to be recorded or reminded of.
+pub fn is_wanted(channel: &str, programme: &Programme) -> bool {  // Used in epg_manager.rs
+    let is_reminding = programme.start.map_or(false, |start| REMINDERS.lock().unwrap().contains(&(channel.to_string(), start)));
+    is_reminding || is_scheduled(channel, programme)
+}
+
+/// Show a desktop notification a little before `programme` starts on `channel`.
+fn remind(channel: &str, programme: &Programme) {
+    let start = match programme.start { Some(start) => start, None => return };
+    let key = (channel.to_string(), start);
+    REMINDERS.lock().unwrap().insert(key.clone());
+    let summary = programme.title.clone();
+    let body = format!("Starts at {} on {}", Local.from_utc_datetime(&start).format("%H:%M"), channel);
+    glib::timeout_add_seconds_local(seconds_until(start - Duration::minutes(REMINDER_MINUTES)), move || {
+        REMINDERS.lock().unwrap().remove(&key);
+        // Talking to the notification dæmon can take a while.
+        let (summary, body) = (summary.clone(), body.clone());
+        thread::spawn(move || send_notification(&summary, &body));
+        Continue(false)
+    });
+}
+
+/// Present the details of the programme `programme` on the channel `channel` in a
+/// non-modal way.
+pub fn present<T: IsA<gtk::Window>>(parent: Option<&T>, control_window: &Rc<ControlWindow>, channel: &str, programme: &Programme) {  // Used in epg_window.rs, frontend_window.rs, and control_window_button.rs
+    let dialog = gtk::Dialog::new();
+    dialog.set_transient_for(parent);
+    dialog.set_destroy_with_parent(true);
+    let now = Utc::now().naive_utc();
+    let watch_button = dialog.add_button("_Watch this channel now", WATCH);
+    watch_button.set_tooltip_text(Some(channel));
+    let is_recording = is_scheduled(channel, programme);
+    let record_button = dialog.add_button(if is_recording { "Recording scheduled" } else { "_Record this programme" }, RECORD);
+    record_button.set_sensitive(!is_recording && programme.end().map_or(false, |end| end > now));
+    let key = programme.start.map(|start| (channel.to_string(), start));
+    let is_reminding = key.as_ref().map_or(false, |key| REMINDERS.lock().unwrap().contains(key));
+    let remind_button = dialog.add_button(if is_reminding { "Reminder set" } else { "Set a re_minder" }, REMIND);
+    remind_button.set_sensitive(!is_reminding && programme.start.map_or(false, |start| start > now));
+    dialog.add_button("_Close", gtk::ResponseType::Close);
+    let content_area = dialog.get_content_area();
+    content_area.set_spacing(10);
+    content_area.set_border_width(10);
+    let title_label = details_label("");
+    let descriptions_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    show_texts(&dialog, &title_label, &descriptions_box, programme);
+    content_area.pack_start(&title_label, false, false, 0);
+    content_area.pack_start(&details_label(&when_text(channel, programme)), false, false, 0);
+    if !programme.genres.is_empty() {
+        let genres_label = details_label("");
+        genres_label.set_markup(&format!("<i>{}</i>", glib::markup_escape_text(&programme.genres.join(", "))));
+        content_area.pack_start(&genres_label, false, false, 0);
+    }
+    if let Some(ratings) = programme.ratings_text() {
+        content_area.pack_start(&details_label(&ratings), false, false, 0);
+    }
+    if programme.texts.len() > 1 {
+        let language_selector = gtk::ComboBoxText::new();
+        for text in &programme.texts {
+            language_selector.append(Some(&text.language), &language_name(&text.language));
+        }
+        language_selector.set_active_id(Some(&programme.language));
+        language_selector.set_halign(gtk::Align::Start);
+        language_selector.set_tooltip_text(Some("The language of the title and the descriptions"));
+        language_selector.connect_changed({
+            let dialog = dialog.clone();
+            let title_label = title_label.clone();
+            let descriptions_box = descriptions_box.clone();
+            let programme = programme.clone();
+            move |selector| {
+                if let Some(language) = selector.get_active_id() {
+                    let mut shown = programme.clone();
+                    shown.show_language(&[language.to_string()]);
+                    show_texts(&dialog, &title_label, &descriptions_box, &shown);
+                }
+            }
+        });
+        content_area.pack_start(&language_selector, false, false, 0);
+    }
+    content_area.pack_start(&descriptions_box, false, false, 0);
+    dialog.connect_response({
+        let c_w = control_window.clone();
+        let channel = channel.to_string();
+        let programme = programme.clone();
+        move |d, response| {
+            if response == WATCH {
+                watch_channel_now(&c_w, &channel);
+            } else if response == RECORD {
+                record(Some(d), &channel, &programme);
+            } else if response == REMIND {
+                remind(&channel, &programme);
+            }
+            unsafe { d.destroy(); }
+        }
+    });
+    dialog.show_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 11, 2).and_hms(hour, minute, 0)
+    }
+
+    fn programme(event_id: Option<u16>, start: NaiveDateTime, minutes: u32) -> Programme {
+        Programme {
+            event_id,
+            start: Some(start),
+            duration_seconds: minutes * 60,
+            title: "Newsnight".to_string(),
+            ..Programme::default()
+        }
+    }
+
+    #[test]
+    fn durations_are_in_hours_and_minutes() {
+        assert_eq!(duration_text(30), "1 minute");
+        assert_eq!(duration_text(45 * 60), "45 minutes");
+        assert_eq!(duration_text(60 * 60), "1 hour");
+        assert_eq!(duration_text(150 * 60), "2 hours 30 minutes");
+        assert_eq!(duration_text(61 * 60), "1 hour 1 minute");
+    }
+
+    #[test]
+    fn a_recording_is_padded_and_named_after_the_programme() {
+        let job = recording_job("BBC TWO", &programme(None, at(22, 30), 50), at(22, 30), Path::new("/srv/videos"), 2, 5);
+        assert_eq!(job.start, "2020-11-02T22:28");
+        assert_eq!(job.duration, Some(57));
+        assert_eq!(job.output, "/srv/videos/BBC TWO 20201102T2230 Newsnight.mp4");
+        assert_eq!(job.event_id, None);
+    }
+
+    #[test]
+    fn a_recording_of_a_programme_of_the_eit_follows_the_event() {
+        let start = NaiveDate::from_ymd(2020, 11, 2).and_hms(22, 30, 30);
+        let job = recording_job("BBC TWO", &programme(Some(0x1234), start, 50), start, Path::new("/srv/videos"), 0, 0);
+        assert_eq!(job.start, "2020-11-02T22:30");
+        assert_eq!(job.duration, Some(51));
+        assert_eq!(job.event_id, Some(0x1234));
+        assert!(job.records("BBC TWO", Some(0x1234), start, start + Duration::minutes(50)));
+    }
+
+    #[test]
+    fn recording_files_are_named_after_the_channel_time_and_title() {
+        assert_eq!(
+            recording_path(Path::new("/srv/videos"), "BBC ONE", "News/Weather", at(18, 0)),
+            PathBuf::from("/srv/videos/BBC ONE 20201102T1800 News-Weather.mp4"),
+        );
+    }
+}
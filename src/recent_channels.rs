@@ -0,0 +1,175 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Recently watched channels: the channels most recently tuned, most recent first,
+//! kept in the preferences so as to be able to go back to them quickly.
+
+use chrono::{Local, TimeZone, Utc};
+
+use log::info;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::channels_data::get_channel_names_and_service_ids;
+use crate::preferences;
+
+/// How many recently watched channels are remembered.
+const MAX_RECENT_CHANNELS: usize = 10;
+
+/// A recently watched channel. The service id is kept as well as the name so that a
+/// channel renamed by a rescan is still remembered.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RecentChannel {
+    pub name: String,
+    pub service_id: Option<u16>,
+    pub watched: i64,  // When it was last tuned, in seconds since the Unix epoch.
+}
+
+impl RecentChannel {
+    /// When the channel was last tuned, for showing to the user.
+    pub fn watched_text(&self) -> String {
+        Local.timestamp(self.watched, 0).format("%a %e %b %H:%M").to_string()
+    }
+}
+
+/// The recently watched channels with the channel `name` watched at `now`.
+fn watched(recent: &[RecentChannel], name: &str, service_id: Option<u16>, now: i64) -> Vec<RecentChannel> {
+    let mut recent = recent.iter().filter(|channel| channel.name != name).cloned().collect::<Vec<_>>();
+    recent.insert(0, RecentChannel{name: name.to_string(), service_id, watched: now});
+    recent.truncate(MAX_RECENT_CHANNELS);
+    recent
+}
+
+/// The recently watched channels matched against the (name, service id) pairs of the
+/// channels in the channels file, and the names of those that are no longer there. A
+/// channel whose name has gone, but whose service id is that of a channel not otherwise
+/// recently watched, has been renamed and is kept with the new name.
+fn reconcile(recent: &[RecentChannel], channels: &[(String, u16)]) -> (Vec<RecentChannel>, Vec<String>) {
+    let mut kept = Vec::<RecentChannel>::new();
+    let mut dropped = Vec::new();
+    for channel in recent {
+        let present = channels.iter().find(|(name, _)| *name == channel.name)
+            .or_else(|| channels.iter().find(|(name, service_id)| {
+                Some(*service_id) == channel.service_id && !recent.iter().any(|c| c.name == *name)
+            }));
+        match present {
+            Some((name, service_id)) if !kept.iter().any(|c| c.name == *name) => {
+                kept.push(RecentChannel{name: name.clone(), service_id: Some(*service_id), watched: channel.watched});
+            },
+            Some(_) => {},
+            None => dropped.push(channel.name.clone()),
+        }
+    }
+    (kept, dropped)
+}
+
+/// The recently watched channels with the channel `name` called `new_name`, the most
+/// recently watched of the two being kept if both were watched.
+fn renamed(recent: &[RecentChannel], name: &str, new_name: &str) -> Vec<RecentChannel> {
+    let mut kept = Vec::<RecentChannel>::new();
+    for channel in recent {
+        let channel = if channel.name == name { RecentChannel{name: new_name.to_string(), ..channel.clone()} } else { channel.clone() };
+        if !kept.iter().any(|c| c.name == channel.name) {
+            kept.push(channel);
+        }
+    }
+    kept
+}
+
+/// The recently watched channels, most recent first.
+pub fn recent_channels() -> Vec<RecentChannel> {
+    preferences::get_recent_channels().unwrap_or_default()
+}
+
+/// Remember that the channel `name` has just been tuned.
+pub fn record(name: &str) {  // Used in control_window_button.rs
+    let service_id = get_channel_names_and_service_ids().unwrap_or_default().into_iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, service_id)| service_id);
+    preferences::set_recent_channels(watched(&recent_channels(), name, service_id, Utc::now().timestamp()), true);
+}
+
+/// Have a recently watched channel that has been renamed `new_name` stay recently watched.
+pub fn rename(name: &str, new_name: &str) {  // Used in channels_data.rs.
+    let recent = recent_channels();
+    let renamed = renamed(&recent, name, new_name);
+    if renamed != recent {
+        preferences::set_recent_channels(renamed, true);
+    }
+}
+
+/// Bring the recently watched channels up to date with the (name, service id) pairs of
+/// the channels now in the channels file, forgetting those that have gone.
+pub fn reconcile_with(channels: &[(String, u16)]) {  // Used in control_window.rs
+    let recent = recent_channels();
+    let (kept, dropped) = reconcile(&recent, channels);
+    for name in &dropped {
+        info!("{} is no longer in the channels file, so is no longer a recently watched channel.", name);
+    }
+    if kept != recent {
+        preferences::set_recent_channels(kept, true);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn recent(name: &str, service_id: Option<u16>, watched: i64) -> RecentChannel {
+        RecentChannel{name: name.to_string(), service_id, watched}
+    }
+
+    fn channels(channels: &[(&str, u16)]) -> Vec<(String, u16)> {
+        channels.iter().map(|(name, service_id)| (name.to_string(), *service_id)).collect()
+    }
+
+    #[test]
+    fn a_channel_watched_goes_to_the_front_once() {
+        let channels = watched(&[], "BBC TWO", Some(4287), 100);
+        let channels = watched(&channels, "ITV", Some(8261), 200);
+        assert_eq!(channels, vec![recent("ITV", Some(8261), 200), recent("BBC TWO", Some(4287), 100)]);
+        let channels = watched(&channels, "BBC TWO", Some(4287), 300);
+        assert_eq!(channels, vec![recent("BBC TWO", Some(4287), 300), recent("ITV", Some(8261), 200)]);
+    }
+
+    #[test]
+    fn only_the_most_recent_channels_are_remembered() {
+        let channels = (0..15).fold(Vec::new(), |channels, n| watched(&channels, &format!("Channel {}", n), None, n));
+        assert_eq!(channels.len(), MAX_RECENT_CHANNELS);
+        assert_eq!(channels[0], recent("Channel 14", None, 14));
+        assert_eq!(channels[MAX_RECENT_CHANNELS - 1], recent("Channel 5", None, 5));
+    }
+
+    #[test]
+    fn renamed_channels_are_still_recently_watched() {
+        let channels_watched = vec![recent("BBC TWO", Some(4287), 300), recent("BBC ONE Lon", Some(4164), 200), recent("BBC One London", Some(4164), 100)];
+        assert_eq!(renamed(&channels_watched, "BBC ONE Lon", "BBC One London"), vec![recent("BBC TWO", Some(4287), 300), recent("BBC One London", Some(4164), 200)]);
+        assert_eq!(renamed(&channels_watched, "ITV", "ITV1"), channels_watched);
+    }
+
+    #[test]
+    fn channels_no_longer_in_the_channels_file_are_dropped_and_renamed_ones_kept() {
+        let channels_watched = vec![recent("BBC FOUR", Some(4352), 300), recent("BBC ONE Lon", Some(4164), 200), recent("Dave", None, 100)];
+        let (kept, dropped) = reconcile(&channels_watched, &channels(&[("BBC One London", 4164), ("ITV", 8261)]));
+        assert_eq!(kept, vec![recent("BBC One London", Some(4164), 200)]);
+        assert_eq!(dropped, vec!["BBC FOUR", "Dave"]);
+    }
+}
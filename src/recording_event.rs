@@ -0,0 +1,128 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+
+/// The significant events during a recording by me-tv-record.
+///
+/// When me-tv-record is run with `--json` each of these is written to stdout as a single
+/// line of JSON, so that other programs can follow the progress of a recording. The
+/// variant is labelled by the `event` field.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RecordingEvent {
+    Started {
+        channel: String,
+        adapter: u8,
+        frontend: u8,
+        output: String,
+        duration_seconds: u64,
+    },
+    Tuned {
+        adapter: u8,
+        frontend: u8,
+    },
+    Progress {
+        elapsed_seconds: u64,
+        remaining_seconds: u64,
+        bytes: u64,
+        paused: bool,
+    },
+    Stats {
+        signal: i32,
+        snr: i32,
+        ber: i32,
+        unc: i32,
+        lock: bool,
+    },
+    Warning {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+    Finished {
+        output: String,
+        bytes: u64,
+        elapsed_seconds: u64,
+        captured_seconds: u64,
+        segments: Vec<String>,
+    },
+}
+
+impl RecordingEvent {
+    /// Serialise to a single line of JSON, no trailing newline.
+    pub fn to_json_line(&self) -> String {
+        serde_json::to_string(self).expect("Could not serialise a RecordingEvent.")
+    }
+
+    /// Deserialise from a line of JSON as written by `to_json_line`.
+    pub fn from_json_line(line: &str) -> Result<RecordingEvent, serde_json::Error> {
+        serde_json::from_str(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_event_has_expected_json() {
+        let event = RecordingEvent::Progress { elapsed_seconds: 60, remaining_seconds: 540, bytes: 1024, paused: false };
+        assert_eq!(
+            event.to_json_line(),
+            r#"{"event":"progress","elapsed_seconds":60,"remaining_seconds":540,"bytes":1024,"paused":false}"#
+        );
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let events = vec![
+            RecordingEvent::Started {
+                channel: "BBC NEWS".to_string(),
+                adapter: 0,
+                frontend: 1,
+                output: "/tmp/news.mp4".to_string(),
+                duration_seconds: 1800,
+            },
+            RecordingEvent::Tuned { adapter: 0, frontend: 1 },
+            RecordingEvent::Warning { message: "a \"quoted\" warning".to_string() },
+            RecordingEvent::Error { message: "no signal".to_string() },
+            RecordingEvent::Stats { signal: 52000, snr: 310, ber: 0, unc: 12, lock: true },
+            RecordingEvent::Finished {
+                output: "/tmp/news.mp4".to_string(),
+                bytes: 123456789,
+                elapsed_seconds: 1800,
+                captured_seconds: 1740,
+                segments: vec!["/tmp/news.mp4".to_string(), "/tmp/news-2.mp4".to_string()],
+            },
+        ];
+        for event in events {
+            assert_eq!(RecordingEvent::from_json_line(&event.to_json_line()).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn unknown_event_is_an_error() {
+        assert!(RecordingEvent::from_json_line(r#"{"event":"exploded"}"#).is_err());
+    }
+}
@@ -30,6 +30,7 @@ use std::time::Duration;
 use glob::glob;
 use lazy_static::lazy_static;
 use libc;
+use log::warn;
 use nix::ioctl_write_int;
 use notify::{Watcher, RecursiveMode, RawEvent, op, raw_watcher};
 use regex::Regex;
@@ -234,12 +235,12 @@ fn add_already_installed_remotes() {
             lirc_devices.iter()
                 .filter(|lirc_path| match get_sys_path_from_lirc_path(lirc_path) {
                     Ok(_) => true,
-                    Err(e) => { println!("get_sys_path_from_lirc_path failed on {:?}: {}", lirc_path, e); false },
+                    Err(e) => { warn!("get_sys_path_from_lirc_path failed on {:?}: {}", lirc_path, e); false },
                 })
                 .map(|lirc_path| {
                     let r_c = match RemoteControl::new(lirc_path) {
                         Ok(rc) => Some(rc),
-                        Err(e) => { println!("Failed to create a remote control: {:?}.\nEither the dynamic filename is wrong or maybe the user is not in group input.", e); None},
+                        Err(e) => { warn!("Failed to create a remote control: {:?}.\nEither the dynamic filename is wrong or maybe the user is not in group input.", e); None},
                     };
                     r_c
                 })
@@ -264,7 +265,7 @@ fn add_appeared_remote_control(lirc_path: PathBuf) {
             Ok(mut data) => {
                 match RemoteControl::new(&lirc_path) {
                     Ok(rc) => data.push(Arc::new(rc)),
-                    Err(e) => println!("Error adding a remote control: {}\nPerhaps the user is not in group input?", e),
+                    Err(e) => warn!("Error adding a remote control: {}\nPerhaps the user is not in group input?", e),
                 }
             },
             Err(_) => panic!("Failed to lock REMOTES for addition."),
@@ -310,8 +311,8 @@ pub fn run(to_cw: glib::Sender<Message>) {
                     _ => {},
                 }
             },
-            Ok(event) => println!("remote_control::run: broken event: {:?}", event),
-            Err(e) => println!("remote_control::run: watch error: {:?}", e),
+            Ok(event) => warn!("remote_control::run: broken event: {:?}", event),
+            Err(e) => warn!("remote_control::run: watch error: {:?}", e),
         }
     }
 }
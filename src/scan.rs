@@ -0,0 +1,807 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Scanning for channels, transponder by transponder, over as many frontends as are
+//! given.
+//!
+//! The transponders to scan are a queue that a worker for each frontend takes from, so
+//! two frontends on the same dish or aerial scan in about half the time. What scanning a
+//! transponder involves is up to a `TransponderScanner`; the one Me TV uses runs
+//! dvbv5-scan for the one transponder. A frontend that fails, rather than failing to
+//! find anything on a transponder, puts the transponder back on the queue for the
+//! other frontends and takes no more. The services found are merged, a service found
+//! more than once being only in the results once. Whilst a transponder is scanned the
+//! signal is sampled with the DVBv5 statistics of the frontend, so that the progress can
+//! show it and the results say how good the signal on each transponder was, or that it
+//! never locked.
+//!
+//! Rather than scan every transponder again, the transponders the channels already known
+//! are on can have their NIT read for the transponders the network announces, so that
+//! only those not yet known need be scanned.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gst;
+use gst::prelude::*;
+
+use log::debug;
+
+use tempfile;
+
+use crate::channels_file::{are_same_frequency, dvbv5_sections, parse_dvbv5, Channel, ParseWarning, TuningParameters};
+use crate::eit::SectionAssembler;
+use crate::frontends::{dvb_devices, FrontendId};
+use crate::multiplex_signals::TransponderSignal;
+use crate::nit::{parse_nit_section, NitCollector, NitTransportStream, NIT_PID};
+use crate::signal_monitor::{SignalMonitor, SignalStatus};
+
+/// How often a worker waiting for work, or for dvbv5-scan, looks to see whether the scan
+/// has been cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the signal is sampled whilst a transponder is scanned.
+const SIGNAL_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long to wait for the whole NIT on a transponder. A NIT is sent at least every ten
+/// seconds, and locking takes a few.
+const NIT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The parameters of a channel that are of its transponder rather than of its service.
+const TRANSPONDER_PARAMETERS: [&str; 13] = [
+    "INVERSION", "SYMBOL_RATE", "INNER_FEC", "POLARIZATION", "STREAM_ID", "CODE_RATE_HP", "CODE_RATE_LP",
+    "GUARD_INTERVAL", "TRANSMISSION_MODE", "HIERARCHY", "ROLLOFF", "PILOT", "SAT_NUMBER",
+];
+
+/// A transponder to scan, as an initial tuning file of the dtv-scan-tables gives it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Transponder {
+    pub delivery_system: String,  // A DVBv5 name.
+    pub frequency: u32,  // Hz, or kHz for satellite delivery systems.
+    pub parameters: Vec<(String, String)>,  // The others, by their DVBv5 names, e.g. BANDWIDTH_HZ, POLARIZATION.
+}
+
+impl Transponder {
+    /// The transponder `channel` is on.
+    pub fn of(channel: &Channel) -> Transponder {
+        let mut parameters = Vec::new();
+        if let Some(bandwidth_hz) = channel.tuning.bandwidth_hz {
+            parameters.push(("BANDWIDTH_HZ".to_string(), bandwidth_hz.to_string()));
+        }
+        if let Some(modulation) = &channel.tuning.modulation {
+            parameters.push(("MODULATION".to_string(), modulation.clone()));
+        }
+        parameters.extend(channel.parameters.iter()
+            .filter(|(key, _)| TRANSPONDER_PARAMETERS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone())));
+        Transponder{delivery_system: channel.tuning.delivery_system.clone(), frequency: channel.tuning.frequency, parameters}
+    }
+
+    /// A parameter of the transponder, by its DVBv5 name.
+    pub fn parameter(&self, key: &str) -> Option<&str> {
+        self.parameters.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the transponder is `other`, frequencies within 0.05% of each other being
+    /// the same as they are for channels, a satellite transponder also having to have the
+    /// same polarisation.
+    pub fn is_same_as(&self, other: &Transponder) -> bool {
+        are_same_frequency(self.frequency, other.frequency) && self.parameter("POLARIZATION") == other.parameter("POLARIZATION")
+    }
+
+    /// The tuning parameters of dvbbasebin and dvbsrc that tune to the transponder.
+    fn tuning(&self) -> TuningParameters {
+        TuningParameters {
+            delivery_system: self.delivery_system.clone(),
+            frequency: self.frequency,
+            bandwidth_hz: self.parameter("BANDWIDTH_HZ").and_then(|bandwidth_hz| bandwidth_hz.parse().ok()),
+            modulation: self.parameter("MODULATION").map(String::from),
+            service_id: 0,
+        }
+    }
+
+    /// The transponder as an entry of a DVBv5 initial tuning file.
+    pub fn initial_tuning_entry(&self) -> String {
+        let mut entry = format!("[CHANNEL]\n\tDELIVERY_SYSTEM = {}\n\tFREQUENCY = {}\n", self.delivery_system, self.frequency);
+        for (key, value) in &self.parameters {
+            entry.push_str(&format!("\t{} = {}\n", key, value));
+        }
+        entry
+    }
+}
+
+/// Rendered as, for example, DVBT2 490000000 or DVBS2 11493750 HORIZONTAL.
+impl fmt::Display for Transponder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.delivery_system, self.frequency)?;
+        match self.parameters.iter().find(|(key, _)| key == "POLARIZATION") {
+            Some((_, polarization)) => write!(f, " {}", polarization),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The transponders `channels` are on, each once, in the order of the channels.
+pub fn known_transponders(channels: &[Channel]) -> Vec<Transponder> {
+    let mut transponders = Vec::<Transponder>::new();
+    for channel in channels {
+        let transponder = Transponder::of(channel);
+        if !transponders.iter().any(|t| t.is_same_as(&transponder)) {
+            transponders.push(transponder);
+        }
+    }
+    transponders
+}
+
+/// The transponders of the transport streams `announced` that none of `channels` are on,
+/// each once. A transport stream that channels are of, by their original network and
+/// transport stream ids, is known whatever transponder it is announced on, as networks
+/// announce the frequencies other transmitters send their transport streams on.
+pub fn unknown_transponders(channels: &[Channel], announced: &[NitTransportStream]) -> Vec<Transponder> {
+    let known = known_transponders(channels);
+    let mut unknown = Vec::<Transponder>::new();
+    for stream in announced {
+        let is_known_stream = channels.iter().any(|channel| {
+            let identity = channel.identity();
+            identity.original_network_id == Some(stream.original_network_id) && identity.transport_stream_id == Some(stream.transport_stream_id)
+        });
+        if is_known_stream {
+            continue;
+        }
+        for transponder in &stream.transponders {
+            if !known.iter().chain(unknown.iter()).any(|t| t.is_same_as(transponder)) {
+                unknown.push(transponder.clone());
+            }
+        }
+    }
+    unknown
+}
+
+/// The transponders of a DVBv5 initial tuning file, and why any blocks of it were left
+/// out.
+pub fn parse_initial_tuning(contents: &str) -> (Vec<Transponder>, Vec<ParseWarning>) {
+    let (sections, mut warnings) = dvbv5_sections(contents);
+    let mut transponders = Vec::new();
+    for section in &sections {
+        let frequency = section.get("FREQUENCY").and_then(|frequency| frequency.parse::<u32>().ok());
+        match (section.get("DELIVERY_SYSTEM"), frequency) {
+            (Some(delivery_system), Some(frequency)) => transponders.push(Transponder {
+                delivery_system: delivery_system.to_string(),
+                frequency,
+                parameters: section.properties.iter().filter(|(key, _)| key != "DELIVERY_SYSTEM" && key != "FREQUENCY").cloned().collect(),
+            }),
+            _ => warnings.push(ParseWarning{line: section.line, message: format!("{} has no delivery system or frequency", section.name)}),
+        }
+    }
+    warnings.sort_by_key(|warning| warning.line);
+    (transponders, warnings)
+}
+
+/// Read the transponders of the DVBv5 initial tuning file at `path`.
+pub fn read_initial_tuning(path: &Path) -> io::Result<(Vec<Transponder>, Vec<ParseWarning>)> {
+    Ok(parse_initial_tuning(&fs::read_to_string(path)?))
+}
+
+/// Why a transponder was not scanned.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ScanError {
+    Transponder(String),  // Nothing could be found on the transponder, it may be tried on no other frontend.
+    Frontend(String),  // The frontend could not be used, the transponder may be tried on another.
+    Cancelled,
+}
+
+/// What scans a transponder on a frontend, for the services on it.
+pub trait TransponderScanner: Send + Sync {
+    /// The services on `transponder` as scanned with `fei`, giving up promptly if
+    /// `cancelled` is set.
+    fn scan(&self, fei: &FrontendId, transponder: &Transponder, cancelled: &AtomicBool) -> Result<Vec<Channel>, ScanError>;
+
+    /// Start sampling the signal on `fei` whilst `transponder` is scanned, sending the
+    /// readings to `to` until what is returned is dropped, None if the signal cannot be.
+    fn monitor_signal(&self, fei: &FrontendId, transponder: &Transponder, to: Sender<SignalStatus>) -> Option<SignalMonitor> {
+        match SignalMonitor::start(fei, SIGNAL_SAMPLE_INTERVAL, to) {
+            Ok(monitor) => Some(monitor),
+            Err(e) => {
+                debug!("The signal on {} cannot be sampled whilst {} is scanned: {}", fei, transponder, e);
+                None
+            },
+        }
+    }
+}
+
+/// What reads the NIT on a transponder for the transport streams the network announces.
+pub trait NetworkReader: Send + Sync {
+    /// The transport streams the NIT on `transponder` as tuned with `fei` announces,
+    /// giving up promptly if `cancelled` is set.
+    fn read(&self, fei: &FrontendId, transponder: &Transponder, cancelled: &AtomicBool) -> Result<Vec<NitTransportStream>, ScanError>;
+}
+
+/// What a scan is doing, for showing its progress. Transponders are given by where they
+/// are in the list of transponders to scan, or to read the NIT on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanEvent {
+    Scanning{fei: FrontendId, transponder: usize},
+    Signal{transponder: usize, status: SignalStatus},  // A reading whilst the transponder is being scanned.
+    Scanned{fei: FrontendId, transponder: usize, services: usize, signal: Option<TransponderSignal>},
+    ReadingNit{fei: FrontendId, transponder: usize},
+    ReadNit{fei: FrontendId, transponder: usize, transport_streams: usize},
+    Failed{fei: FrontendId, transponder: usize, reason: String, signal: Option<TransponderSignal>},
+    FrontendFailed{fei: FrontendId, transponder: usize, reason: String},  // The transponder goes back on the queue.
+}
+
+/// What a scan found: the services, in the order of the transponders they are on, the
+/// transponders that could not be scanned and why, and the signal on each transponder
+/// where it is known. If the scan was cancelled, or all the frontends failed, there are
+/// transponders left unscanned.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScanResults {
+    pub channels: Vec<Channel>,
+    pub failed: Vec<(usize, String)>,
+    pub unscanned: Vec<usize>,
+    pub signals: Vec<Option<TransponderSignal>>,  // By transponder.
+}
+
+/// The transponders waiting to be scanned, and how many are being scanned. A worker
+/// with nothing to do waits whilst transponders are being scanned as a frontend failing
+/// puts its transponder back.
+#[derive(Debug, Default)]
+struct Queue {
+    pending: VecDeque<usize>,
+    in_progress: usize,
+}
+
+/// What the workers share.
+struct Work {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    transponders: Vec<Transponder>,
+    found: Mutex<Vec<Option<Result<Vec<Channel>, String>>>>,  // For each transponder, when it has been scanned.
+    signals: Mutex<Vec<Option<TransponderSignal>>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Work {
+    /// The next transponder to scan, if there is one and the scan has not been cancelled.
+    fn take(&self) -> Option<usize> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(index) = queue.pending.pop_front() {
+                queue.in_progress += 1;
+                return Some(index);
+            }
+            if queue.in_progress == 0 {
+                return None;
+            }
+            queue = self.changed.wait_timeout(queue, CANCEL_POLL_INTERVAL).unwrap().0;
+        }
+    }
+
+    /// Finish with the transponder `index`, putting it back on the queue if `is_requeued`.
+    fn finish(&self, index: usize, is_requeued: bool) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.in_progress -= 1;
+        if is_requeued {
+            queue.pending.push_front(index);
+        }
+        self.changed.notify_all();
+    }
+}
+
+/// Scan the transponder `index` with `fei`, sampling the signal whilst it is scanned and
+/// sending the readings to `to`, for what was found and what the signal was. A
+/// transponder nothing could be found on without the frontend ever locking did not lock.
+fn scan_sampling_signal(scanner: &dyn TransponderScanner, fei: &FrontendId, index: usize, work: &Work, to: &Sender<ScanEvent>) -> (Result<Vec<Channel>, ScanError>, Option<TransponderSignal>) {
+    let transponder = &work.transponders[index];
+    let (to_sampler, readings) = mpsc::channel::<SignalStatus>();
+    let monitor = scanner.monitor_signal(fei, transponder, to_sampler);
+    let forwarder = thread::spawn({
+        let to = to.clone();
+        move || readings.iter().inspect(|status| {
+            let _ = to.send(ScanEvent::Signal{transponder: index, status: status.clone()});
+        }).collect::<Vec<_>>()
+    });
+    let outcome = scanner.scan(fei, transponder, &work.cancelled);
+    drop(monitor);
+    let readings = forwarder.join().unwrap_or_default();
+    let signal = match (TransponderSignal::of(&readings), &outcome) {
+        (None, Err(ScanError::Transponder(_))) => Some(TransponderSignal::NoLock),
+        (signal, _) => signal,
+    };
+    (outcome, signal)
+}
+
+/// Scan transponders with `fei` until there are none left, the frontend fails, or the
+/// scan is cancelled.
+fn work(scanner: &dyn TransponderScanner, fei: &FrontendId, work: &Work, to: &Sender<ScanEvent>) {
+    while let Some(index) = work.take() {
+        let _ = to.send(ScanEvent::Scanning{fei: fei.clone(), transponder: index});
+        let (outcome, signal) = scan_sampling_signal(scanner, fei, index, work, to);
+        let (event, found) = match outcome {
+            Ok(channels) => (ScanEvent::Scanned{fei: fei.clone(), transponder: index, services: channels.len(), signal}, Some(Ok(channels))),
+            Err(ScanError::Transponder(reason)) => (ScanEvent::Failed{fei: fei.clone(), transponder: index, reason: reason.clone(), signal}, Some(Err(reason))),
+            Err(ScanError::Frontend(reason)) => (ScanEvent::FrontendFailed{fei: fei.clone(), transponder: index, reason}, None),
+            Err(ScanError::Cancelled) => {
+                work.finish(index, true);
+                return;
+            },
+        };
+        let is_requeued = found.is_none();
+        if found.is_some() {
+            work.found.lock().unwrap()[index] = found;
+            work.signals.lock().unwrap()[index] = signal;
+        }
+        work.finish(index, is_requeued);
+        let _ = to.send(event);
+        if is_requeued {
+            debug!("{} has failed, it scans no more transponders.", fei);
+            return;
+        }
+    }
+}
+
+/// Add the channels `found` to `channels`, leaving out those that are of services
+/// already there.
+fn merge(channels: &mut Vec<Channel>, found: Vec<Channel>) {
+    for channel in found {
+        if !channels.iter().any(|c| c.is_same_service_as(&channel)) {
+            channels.push(channel);
+        }
+    }
+}
+
+/// Scan `transponders` with `scanner` on each of `frontends` at the same time, sending
+/// what is being done to `to`, until all the transponders have been scanned or
+/// `cancelled` is set.
+pub fn scan(scanner: Arc<dyn TransponderScanner>, frontends: &[FrontendId], transponders: &[Transponder], cancelled: Arc<AtomicBool>, to: Sender<ScanEvent>) -> ScanResults {
+    let work = Arc::new(Work {
+        queue: Mutex::new(Queue{pending: (0..transponders.len()).collect(), in_progress: 0}),
+        changed: Condvar::new(),
+        transponders: transponders.to_vec(),
+        found: Mutex::new(vec![None; transponders.len()]),
+        signals: Mutex::new(vec![None; transponders.len()]),
+        cancelled,
+    });
+    let workers = frontends.iter().map(|fei| {
+        let (scanner, fei, work, to) = (scanner.clone(), fei.clone(), work.clone(), to.clone());
+        thread::spawn(move || self::work(&*scanner, &fei, &work, &to))
+    }).collect::<Vec<_>>();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    let mut results = ScanResults::default();
+    let found = work.found.lock().unwrap().clone();
+    for (index, outcome) in found.into_iter().enumerate() {
+        match outcome {
+            Some(Ok(channels)) => merge(&mut results.channels, channels),
+            Some(Err(reason)) => results.failed.push((index, reason)),
+            None => results.unscanned.push(index),
+        }
+    }
+    results.signals = work.signals.lock().unwrap().clone();
+    results
+}
+
+/// Read the NIT on each of `transponders` with `reader`, sending what is being done to
+/// `to`, for the transport streams announced, until `cancelled` is set. The NITs are read
+/// one at a time with the first of `frontends`, the next being used when a frontend fails.
+pub fn read_announcements(reader: &dyn NetworkReader, frontends: &[FrontendId], transponders: &[Transponder], cancelled: &AtomicBool, to: &Sender<ScanEvent>) -> Vec<NitTransportStream> {
+    let mut announced = Vec::new();
+    let mut frontends = frontends.iter();
+    let mut fei = frontends.next();
+    let mut index = 0;
+    while let Some(current) = fei {
+        if index == transponders.len() || cancelled.load(Ordering::SeqCst) {
+            break;
+        }
+        let _ = to.send(ScanEvent::ReadingNit{fei: current.clone(), transponder: index});
+        match reader.read(current, &transponders[index], cancelled) {
+            Ok(streams) => {
+                let _ = to.send(ScanEvent::ReadNit{fei: current.clone(), transponder: index, transport_streams: streams.len()});
+                announced.extend(streams);
+                index += 1;
+            },
+            Err(ScanError::Transponder(reason)) => {
+                let _ = to.send(ScanEvent::Failed{fei: current.clone(), transponder: index, reason, signal: None});
+                index += 1;
+            },
+            Err(ScanError::Frontend(reason)) => {
+                let _ = to.send(ScanEvent::FrontendFailed{fei: current.clone(), transponder: index, reason});
+                fei = frontends.next();
+            },
+            Err(ScanError::Cancelled) => break,
+        }
+    }
+    announced
+}
+
+/// Whether what dvbv5-scan, or dvbsrc, said on failing is that the frontend could not
+/// be used, rather than that nothing was found.
+fn is_frontend_failure(stderr: &str) -> bool {
+    ["Device or resource busy", "No such file or directory", "Permission denied", "No such device", "Input/output error"]
+        .iter()
+        .any(|message| stderr.contains(message))
+}
+
+/// Scanning a transponder with dvbv5-scan, from v4l-utils, which tunes the frontend and
+/// reads the service tables, only the one transponder being scanned.
+#[derive(Clone, Debug, Default)]
+pub struct Dvbv5Scanner {
+    pub lnb: Option<String>,  // The LNB of a satellite dish, as dvbv5-scan names it, e.g. UNIVERSAL.
+}
+
+impl TransponderScanner for Dvbv5Scanner {
+    fn scan(&self, fei: &FrontendId, transponder: &Transponder, cancelled: &AtomicBool) -> Result<Vec<Channel>, ScanError> {
+        let missing = dvb_devices().missing_devices(fei);
+        if !missing.is_empty() {
+            return Err(ScanError::Frontend(format!("{} does not exist", missing[0].display())));
+        }
+        let directory = tempfile::tempdir().map_err(|e| ScanError::Frontend(format!("Cannot make a directory for dvbv5-scan: {}", e)))?;
+        let initial = directory.path().join("initial.conf");
+        let output = directory.path().join("channels.conf");
+        fs::write(&initial, transponder.initial_tuning_entry()).map_err(|e| ScanError::Frontend(format!("Cannot write {}: {}", initial.display(), e)))?;
+        let mut command = Command::new("dvbv5-scan");
+        command.arg("-a").arg(fei.adapter.to_string()).arg("-f").arg(fei.frontend.to_string());
+        if let Some(lnb) = &self.lnb {
+            command.arg("-l").arg(lnb);
+        }
+        // Only the transponder given, not those the NIT says there are, is scanned.
+        command.arg("-F").arg("-O").arg("DVBV5").arg("-o").arg(&output).arg(&initial);
+        let mut child = command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn()
+            .map_err(|e| ScanError::Frontend(format!("Cannot run dvbv5-scan: {}", e)))?;
+        // Read what dvbv5-scan says as it goes so it can never block on a full pipe.
+        let reader = child.stderr.take().map(|mut stderr| thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stderr.read_to_string(&mut text);
+            text
+        }));
+        let status = loop {
+            if cancelled.load(Ordering::SeqCst) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(ScanError::Cancelled);
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => thread::sleep(CANCEL_POLL_INTERVAL),
+                Err(e) => return Err(ScanError::Frontend(format!("Cannot wait for dvbv5-scan: {}", e))),
+            }
+        };
+        let stderr = reader.and_then(|reader| reader.join().ok()).unwrap_or_default();
+        let reason = || stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("dvbv5-scan failed").trim().to_string();
+        if !status.success() {
+            return Err(if is_frontend_failure(&stderr) { ScanError::Frontend(reason()) } else { ScanError::Transponder(reason()) });
+        }
+        match fs::read_to_string(&output) {
+            Ok(contents) => Ok(parse_dvbv5(&contents).channels),
+            Err(_) => Err(ScanError::Transponder("Nothing was found, the frontend did not lock".to_string())),
+        }
+    }
+}
+
+/// A dvbsrc GStreamer element that tunes the frontend `fei` to `transponder` and passes
+/// only the packets on the PIDs `pids`, separated by colons. None if there is no dvbsrc.
+pub fn tuned_dvbsrc(fei: &FrontendId, transponder: &Transponder, pids: &str) -> Option<gst::Element> {
+    let source = gst::ElementFactory::make("dvbsrc", None).ok()?;
+    let tuning = transponder.tuning();
+    let _ = source.set_property("adapter", &(fei.adapter as i32));
+    let _ = source.set_property("frontend", &(fei.frontend as i32));
+    source.set_property_from_str("delsys", &tuning.delsys_nick());
+    let _ = source.set_property("frequency", &tuning.frequency);
+    if let Some(bandwidth_hz) = tuning.bandwidth_hz {
+        let _ = source.set_property("bandwidth-hz", &bandwidth_hz);
+    }
+    if let Some(modulation) = tuning.modulation_nick() {
+        source.set_property_from_str("modulation", &modulation);
+    }
+    if let Some(polarity) = transponder.parameter("POLARIZATION").and_then(|polarization| polarization.get(..1)) {
+        let _ = source.set_property("polarity", &polarity.to_string());
+    }
+    if let Some(symbol_rate) = transponder.parameter("SYMBOL_RATE").and_then(|rate| rate.parse::<u32>().ok()) {
+        let _ = source.set_property("symbol-rate", &(symbol_rate / 1000));
+    }
+    if let Some(stream_id) = transponder.parameter("STREAM_ID").and_then(|id| id.parse::<i32>().ok()) {
+        let _ = source.set_property("stream-id", &stream_id);
+    }
+    let _ = source.set_property("pids", &pids.to_string());
+    Some(source)
+}
+
+/// Reading the NIT on a transponder with the dvbsrc GStreamer element, which tunes the
+/// frontend and passes only the packets on the NIT PID.
+#[derive(Clone, Debug, Default)]
+pub struct DvbsrcNetworkReader;
+
+impl NetworkReader for DvbsrcNetworkReader {
+    fn read(&self, fei: &FrontendId, transponder: &Transponder, cancelled: &AtomicBool) -> Result<Vec<NitTransportStream>, ScanError> {
+        let missing = dvb_devices().missing_devices(fei);
+        if !missing.is_empty() {
+            return Err(ScanError::Frontend(format!("{} does not exist", missing[0].display())));
+        }
+        gst::init().map_err(|e| ScanError::Frontend(format!("Cannot initialise GStreamer: {}", e)))?;
+        let source = tuned_dvbsrc(fei, transponder, &NIT_PID.to_string()).ok_or_else(|| ScanError::Frontend("There is no dvbsrc GStreamer element".to_string()))?;
+        let sink = gst::ElementFactory::make("fakesink", None).map_err(|_| ScanError::Frontend("There is no fakesink GStreamer element".to_string()))?;
+        let pipeline = gst::Pipeline::new(None);
+        pipeline.add_many(&[&source, &sink]).map_err(|e| ScanError::Frontend(e.to_string()))?;
+        source.link(&sink).map_err(|e| ScanError::Frontend(e.to_string()))?;
+        let tables = Arc::new(Mutex::new((SectionAssembler::new(NIT_PID), NitCollector::default())));
+        let src_pad = source.get_static_pad("src").ok_or_else(|| ScanError::Frontend("dvbsrc has no src pad".to_string()))?;
+        src_pad.add_probe(gst::PadProbeType::BUFFER, {
+            let tables = tables.clone();
+            move |_, probe_info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = probe_info.data {
+                    if let Ok(map) = buffer.map_readable() {
+                        let (assembler, collector) = &mut *tables.lock().unwrap();
+                        for section in assembler.push_buffer(map.as_slice()) {
+                            if let Ok(section) = parse_nit_section(&section) {
+                                collector.add(section);
+                            }
+                        }
+                    }
+                }
+                gst::PadProbeReturn::Ok
+            }
+        });
+        let started = Instant::now();
+        let outcome = match pipeline.set_state(gst::State::Playing) {
+            Err(_) => Err(ScanError::Frontend(format!("{} could not be tuned to {}", fei, transponder))),
+            Ok(_) => {
+                let bus = pipeline.get_bus().expect("A pipeline has no bus.");
+                let poll = gst::ClockTime::from_mseconds(CANCEL_POLL_INTERVAL.as_millis() as u64);
+                loop {
+                    if cancelled.load(Ordering::SeqCst) {
+                        break Err(ScanError::Cancelled);
+                    }
+                    if let Some(message) = bus.timed_pop_filtered(poll, &[gst::MessageType::Error]) {
+                        if let gst::MessageView::Error(error) = message.view() {
+                            let reason = error.get_error().to_string();
+                            let details = format!("{} {}", reason, error.get_debug().unwrap_or_default());
+                            break Err(if is_frontend_failure(&details) { ScanError::Frontend(reason) } else { ScanError::Transponder(reason) });
+                        }
+                    }
+                    let tables = tables.lock().unwrap();
+                    if tables.1.is_complete() {
+                        break Ok(tables.1.transport_streams());
+                    }
+                    if started.elapsed() > NIT_TIMEOUT {
+                        break Err(ScanError::Transponder("No NIT was received, the frontend may not have locked".to_string()));
+                    }
+                }
+            },
+        };
+        let _ = pipeline.set_state(gst::State::Null);
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::{BTreeMap, HashSet};
+    use std::sync::mpsc;
+
+    use crate::channels_file::TuningParameters;
+    use crate::signal_monitor::Measurement;
+
+    const INITIAL_TUNING_FILE: &str = "# uk-CrystalPalace
+[CHANNEL]
+\tDELIVERY_SYSTEM = DVBT
+\tFREQUENCY = 490000000
+\tBANDWIDTH_HZ = 8000000
+
+[CHANNEL]
+\tDELIVERY_SYSTEM = DVBT2
+\tFREQUENCY = 474000000
+\tBANDWIDTH_HZ = 8000000
+\tSTREAM_ID = 0
+
+[CHANNEL]
+\tDELIVERY_SYSTEM = DVBT
+";
+
+    fn transponder(frequency: u32) -> Transponder {
+        Transponder{delivery_system: "DVBT".to_string(), frequency, parameters: Vec::new()}
+    }
+
+    fn channel(name: &str, frequency: u32, service_id: u16) -> Channel {
+        Channel {
+            name: name.to_string(),
+            tuning: TuningParameters{delivery_system: "DVBT".to_string(), frequency, bandwidth_hz: None, modulation: None, service_id},
+            video_pid: None,
+            audio_pids: Vec::new(),
+            parameters: BTreeMap::new(),
+        }
+    }
+
+    /// A scanner that finds a channel on each transponder, both on 490 MHz and on what
+    /// is a rounding of it, and nothing at 522 MHz, where it does not lock. Frontends in
+    /// `failing` fail.
+    struct FakeScanner {
+        failing: HashSet<FrontendId>,
+    }
+
+    impl TransponderScanner for FakeScanner {
+        fn scan(&self, fei: &FrontendId, transponder: &Transponder, cancelled: &AtomicBool) -> Result<Vec<Channel>, ScanError> {
+            thread::sleep(Duration::from_millis(10));
+            if cancelled.load(Ordering::SeqCst) {
+                return Err(ScanError::Cancelled);
+            }
+            if self.failing.contains(fei) {
+                return Err(ScanError::Frontend("Device or resource busy".to_string()));
+            }
+            match transponder.frequency {
+                522000000 => Err(ScanError::Transponder("No lock".to_string())),
+                490000000 | 490000100 => Ok(vec![channel("BBC ONE Lon", 490000000, 4164)]),
+                frequency => Ok(vec![channel(&format!("Channel {}", frequency / 1000000), frequency, (frequency / 1000000) as u16)]),
+            }
+        }
+
+        fn monitor_signal(&self, fei: &FrontendId, transponder: &Transponder, to: Sender<SignalStatus>) -> Option<SignalMonitor> {
+            let lock = transponder.frequency != 522000000;
+            let _ = to.send(SignalStatus{fei: fei.clone(), strength: Some(Measurement::Relative(0.72)), snr: None, ber: None, unc: None, lock});
+            None
+        }
+    }
+
+    /// A reader that finds a NIT on every transponder but 522 MHz, announcing the
+    /// transport streams 0x1004 on 490 MHz and 0x2005 on 506 MHz. Frontends in `failing`
+    /// fail.
+    struct FakeReader {
+        failing: HashSet<FrontendId>,
+    }
+
+    impl NetworkReader for FakeReader {
+        fn read(&self, fei: &FrontendId, on: &Transponder, _cancelled: &AtomicBool) -> Result<Vec<NitTransportStream>, ScanError> {
+            if self.failing.contains(fei) {
+                return Err(ScanError::Frontend("Device or resource busy".to_string()));
+            }
+            if on.frequency == 522000000 {
+                return Err(ScanError::Transponder("No NIT was received".to_string()));
+            }
+            Ok(vec![
+                NitTransportStream{transport_stream_id: 0x1004, original_network_id: 0x233a, transponders: vec![transponder(490000000)]},
+                NitTransportStream{transport_stream_id: 0x2005, original_network_id: 0x233a, transponders: vec![transponder(505833330)]},
+            ])
+        }
+    }
+
+    fn frontends(count: u8) -> Vec<FrontendId> {
+        (0..count).map(|adapter| FrontendId{adapter, frontend: 0}).collect()
+    }
+
+    fn run(scanner: FakeScanner, frontends: &[FrontendId], transponders: &[Transponder], cancelled: bool) -> (ScanResults, Vec<ScanEvent>) {
+        let (to, from) = mpsc::channel();
+        let results = scan(Arc::new(scanner), frontends, transponders, Arc::new(AtomicBool::new(cancelled)), to);
+        (results, from.try_iter().collect())
+    }
+
+    #[test]
+    fn initial_tuning_files_are_read_and_written() {
+        let (transponders, warnings) = parse_initial_tuning(INITIAL_TUNING_FILE);
+        assert_eq!(transponders.len(), 2);
+        assert_eq!(transponders[1].to_string(), "DVBT2 474000000");
+        assert_eq!(transponders[1].parameters, vec![("BANDWIDTH_HZ".to_string(), "8000000".to_string()), ("STREAM_ID".to_string(), "0".to_string())]);
+        assert_eq!(warnings, vec![ParseWarning{line: 13, message: "CHANNEL has no delivery system or frequency".to_string()}]);
+        assert_eq!(parse_initial_tuning(&transponders[0].initial_tuning_entry()).0, vec![transponders[0].clone()]);
+    }
+
+    #[test]
+    fn transponders_are_shared_between_frontends_and_services_found_once() {
+        let transponders = [transponder(490000000), transponder(505833000), transponder(522000000), transponder(490000100), transponder(545833000)];
+        let (results, events) = run(FakeScanner{failing: HashSet::new()}, &frontends(2), &transponders, false);
+        let names = results.channels.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["BBC ONE Lon", "Channel 505", "Channel 545"]);
+        assert_eq!(results.failed, vec![(2, "No lock".to_string())]);
+        assert!(results.unscanned.is_empty());
+        assert_eq!(events.iter().filter(|e| matches!(e, ScanEvent::Scanning{..})).count(), 5);
+        assert_eq!(events.iter().filter(|e| matches!(e, ScanEvent::Signal{..})).count(), 5);
+        assert_eq!(results.signals[0], Some(TransponderSignal::Locked{strength: Some(Measurement::Relative(0.72)), snr: None}));
+        assert_eq!(results.signals[2], Some(TransponderSignal::NoLock));
+        assert!(events.iter().any(|e| matches!(e, ScanEvent::Failed{transponder: 2, signal: Some(TransponderSignal::NoLock), ..})));
+    }
+
+    #[test]
+    fn the_transponders_of_a_failed_frontend_are_scanned_by_the_others() {
+        let transponders = [transponder(490000000), transponder(505833000), transponder(545833000)];
+        let failing = frontends(1).into_iter().collect::<HashSet<_>>();
+        let (results, events) = run(FakeScanner{failing}, &frontends(2), &transponders, false);
+        assert_eq!(results.channels.len(), 3);
+        assert!(results.failed.is_empty() && results.unscanned.is_empty());
+        assert!(events.iter().filter(|e| matches!(e, ScanEvent::FrontendFailed{..})).count() <= 1);
+        assert!(events.iter().all(|e| !matches!(e, ScanEvent::Scanned{fei, ..} if fei.adapter == 0)));
+    }
+
+    #[test]
+    fn transponders_are_left_unscanned_when_every_frontend_fails_or_the_scan_is_cancelled() {
+        let transponders = [transponder(490000000), transponder(505833000)];
+        let failing = frontends(2).into_iter().collect::<HashSet<_>>();
+        let (results, _) = run(FakeScanner{failing}, &frontends(2), &transponders, false);
+        assert!(results.channels.is_empty());
+        assert_eq!(results.unscanned, vec![0, 1]);
+        let (results, events) = run(FakeScanner{failing: HashSet::new()}, &frontends(2), &transponders, true);
+        assert_eq!(results.unscanned, vec![0, 1]);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn the_transponders_of_channels_are_known_once_without_their_service_parameters() {
+        let mut bbc_one = channel("BBC ONE Lon", 490000000, 4164);
+        bbc_one.tuning.bandwidth_hz = Some(8000000);
+        bbc_one.parameters.insert("GUARD_INTERVAL".to_string(), "1/32".to_string());
+        bbc_one.parameters.insert("TRANSPORT_ID".to_string(), "4100".to_string());
+        let channels = [bbc_one, channel("BBC TWO", 490000000, 4287), channel("ITV", 505833000, 8261)];
+        let transponders = known_transponders(&channels);
+        assert_eq!(transponders.len(), 2);
+        assert_eq!(transponders[0].parameters, vec![("BANDWIDTH_HZ".to_string(), "8000000".to_string()), ("GUARD_INTERVAL".to_string(), "1/32".to_string())]);
+        assert!(transponders[1].is_same_as(&transponder(505833330)));
+        assert!(!transponders[1].is_same_as(&transponder(514000000)));
+    }
+
+    #[test]
+    fn only_transponders_of_transport_streams_not_known_are_unknown() {
+        let mut bbc_one = channel("BBC ONE Lon", 474000000, 4164);
+        bbc_one.parameters.insert("NETWORK_ID".to_string(), "9018".to_string());
+        bbc_one.parameters.insert("TRANSPORT_ID".to_string(), "4100".to_string());
+        let channels = [bbc_one, channel("ITV", 505833000, 8261)];
+        let announced = [
+            NitTransportStream{transport_stream_id: 0x1004, original_network_id: 0x233a, transponders: vec![transponder(490000000)]},
+            NitTransportStream{transport_stream_id: 0x2005, original_network_id: 0x233a, transponders: vec![transponder(505833330)]},
+            NitTransportStream{transport_stream_id: 0x3006, original_network_id: 0x233a, transponders: vec![transponder(529833000), transponder(545833000)]},
+            NitTransportStream{transport_stream_id: 0x3006, original_network_id: 0x233a, transponders: vec![transponder(529833000)]},
+        ];
+        let unknown = unknown_transponders(&channels, &announced);
+        assert_eq!(unknown, vec![transponder(529833000), transponder(545833000)]);
+    }
+
+    #[test]
+    fn nits_are_read_with_the_next_frontend_when_one_fails() {
+        let transponders = [transponder(490000000), transponder(522000000), transponder(505833000)];
+        let reader = FakeReader{failing: frontends(1).into_iter().collect()};
+        let (to, from) = mpsc::channel();
+        let announced = read_announcements(&reader, &frontends(2), &transponders, &AtomicBool::new(false), &to);
+        assert_eq!(announced.len(), 4);
+        let events = from.try_iter().collect::<Vec<_>>();
+        assert!(matches!(&events[1], ScanEvent::FrontendFailed{fei, transponder: 0, ..} if fei.adapter == 0));
+        assert!(matches!(&events[3], ScanEvent::ReadNit{fei, transponder: 0, transport_streams: 2} if fei.adapter == 1));
+        assert!(matches!(&events[5], ScanEvent::Failed{transponder: 1, ..}));
+        let (to, _from) = mpsc::channel();
+        assert!(read_announcements(&FakeReader{failing: frontends(2).into_iter().collect()}, &frontends(2), &transponders, &AtomicBool::new(false), &to).is_empty());
+    }
+
+    #[test]
+    fn dvbv5_scan_failing_to_open_the_frontend_is_a_frontend_failure() {
+        assert!(is_frontend_failure("ERROR    open /dev/dvb/adapter1/frontend0: Device or resource busy\n"));
+        assert!(!is_frontend_failure("ERROR    dvb_fe_get_stats failed\nWARNING  >>> tuning failed!!!\n"));
+    }
+}
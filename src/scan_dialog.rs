@@ -0,0 +1,479 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The scan dialog: the transponders of a transmitter file being scanned, using all the
+//! frontends that are free at the same time, each with what is happening on it and the
+//! signal on it, and how far the scan has got. When the scan is finished what saving the
+//! services found to the channels file would add and update is shown, and the user
+//! chooses whether to.
+//!
+//! The scan runs on threads of its own, what it does being sent back to the GTK thread
+//! on a glib channel, so that the dialog stays live, and Cancel stops the scan at once.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use glib;
+
+use gtk;
+use gtk::prelude::*;
+
+use log::{info, warn};
+
+use me_tv::channels_file::{import_channels, preview_import, Conflicts, Import};
+use me_tv::multiplex_signals::{multiplex_signals_path, record_multiplex_signals, scanned_signals, TransponderSignal};
+use me_tv::scan::{scan, Dvbv5Scanner, ScanEvent, ScanResults, Transponder};
+
+use crate::channels_data::{channels_file_path, read_channels_data};
+use crate::control_window::ControlWindow;
+use crate::dialogs::display_an_error_dialog;
+use crate::frontend_manager::{self, FrontendId, Lease};
+
+/// What is happening on a transponder of the scan.
+#[derive(Clone, Debug, PartialEq)]
+enum TransponderStatus {
+    Pending,
+    Locking{fei: FrontendId},
+    ReadingTables{fei: FrontendId},  // The frontend has lock, dvbv5-scan is reading the SI tables.
+    Done{services: usize},
+    Failed{reason: String},
+}
+
+impl fmt::Display for TransponderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransponderStatus::Pending => f.write_str("pending"),
+            TransponderStatus::Locking{fei} => write!(f, "locking on {}", fei),
+            TransponderStatus::ReadingTables{fei} => write!(f, "reading tables on {}", fei),
+            TransponderStatus::Done{services} => write!(f, "done, {} services", services),
+            TransponderStatus::Failed{reason} => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// The progress of a scan, as what it does tells it.
+#[derive(Clone, Debug, PartialEq)]
+struct ScanProgress {
+    statuses: Vec<TransponderStatus>,
+    signals: Vec<String>,  // The signal on each transponder, as far as it is known, for showing.
+    services: usize,  // Found so far, a service being counted for each transponder it is found on.
+}
+
+impl ScanProgress {
+    fn new(transponders: usize) -> ScanProgress {
+        ScanProgress{statuses: vec![TransponderStatus::Pending; transponders], signals: vec![String::new(); transponders], services: 0}
+    }
+
+    /// Follow `event`, returning the transponder it changes the row of, if it does.
+    fn follow(&mut self, event: &ScanEvent) -> Option<usize> {
+        let signal_text = |signal: &Option<TransponderSignal>| signal.map_or(String::new(), |signal| signal.to_string());
+        match event {
+            ScanEvent::Scanning{fei, transponder} => {
+                self.statuses[*transponder] = TransponderStatus::Locking{fei: fei.clone()};
+                self.signals[*transponder] = String::new();
+                Some(*transponder)
+            },
+            ScanEvent::Signal{transponder, status} => {
+                if let TransponderStatus::Locking{fei} = &self.statuses[*transponder] {
+                    if status.lock {
+                        self.statuses[*transponder] = TransponderStatus::ReadingTables{fei: fei.clone()};
+                    }
+                }
+                self.signals[*transponder] = signal_text(&TransponderSignal::of(&[status.clone()]));
+                Some(*transponder)
+            },
+            ScanEvent::Scanned{transponder, services, signal, ..} => {
+                self.statuses[*transponder] = TransponderStatus::Done{services: *services};
+                self.signals[*transponder] = signal_text(signal);
+                self.services += services;
+                Some(*transponder)
+            },
+            ScanEvent::Failed{transponder, reason, signal, ..} => {
+                self.statuses[*transponder] = TransponderStatus::Failed{reason: reason.clone()};
+                self.signals[*transponder] = signal_text(signal);
+                Some(*transponder)
+            },
+            // Another frontend will scan it.
+            ScanEvent::FrontendFailed{transponder, ..} => {
+                self.statuses[*transponder] = TransponderStatus::Pending;
+                self.signals[*transponder] = String::new();
+                Some(*transponder)
+            },
+            ScanEvent::ReadingNit{..} | ScanEvent::ReadNit{..} => None,
+        }
+    }
+
+    /// How many of the transponders have been scanned, whether or not anything was found.
+    fn scanned(&self) -> usize {
+        self.statuses.iter().filter(|status| matches!(status, TransponderStatus::Done{..} | TransponderStatus::Failed{..})).count()
+    }
+
+    /// The text of the progress bar.
+    fn text(&self) -> String {
+        format!("{} of {} transponders scanned, {} services found", self.scanned(), self.statuses.len(), self.services)
+    }
+}
+
+/// What saving the services a scan found to the channels file would do, `import` being
+/// the preview of it, and what the scan could not do.
+fn summary_text(results: &ScanResults, import: &Import) -> String {
+    let mut message = if results.channels.is_empty() {
+        "The scan found no services.".to_string()
+    } else {
+        format!(
+            "The scan found {} services: saving them adds {} channels to the channels file and updates {}, {} are already there as they are.",
+            results.channels.len(), import.added.len(), import.updated.len(), import.skipped.len(),
+        )
+    };
+    if !import.added.is_empty() {
+        message.push_str(&format!("\n\nTo be added: {}.", import.added.join(", ")));
+    }
+    if !import.updated.is_empty() {
+        message.push_str(&format!("\n\nTo be tuned as scanned: {}.", import.updated.join(", ")));
+    }
+    if !import.already_present.is_empty() {
+        message.push_str(&format!("\n\nNot to be added, other channels in the channels file have their names: {}.", import.already_present.join(", ")));
+    }
+    if !import.not_zap.is_empty() {
+        message.push_str(&format!("\n\nNot to be added, the zap format of the channels file cannot give them: {}.", import.not_zap.join(", ")));
+    }
+    if !results.failed.is_empty() {
+        message.push_str(&format!("\n\nNothing was found on {} of the transponders.", results.failed.len()));
+    }
+    if !results.unscanned.is_empty() {
+        message.push_str(&format!("\n\n{} transponders were not scanned, every frontend having failed.", results.unscanned.len()));
+    }
+    message
+}
+
+/// What the scan threads send the dialog.
+enum Update {
+    Event(ScanEvent),
+    Finished(ScanResults),
+}
+
+/// The dialog and the state of the scan.
+struct ScanDialog {
+    dialog: gtk::Dialog,
+    // The columns are the transponder, its status, and its signal.
+    store: gtk::ListStore,
+    progress_bar: gtk::ProgressBar,
+    summary_label: gtk::Label,
+    replace_button: gtk::CheckButton,
+    cancel_button: gtk::Button,
+    save_button: gtk::Button,
+    transponders: Vec<Transponder>,
+    progress: RefCell<ScanProgress>,
+    results: RefCell<Option<ScanResults>>,  // Once the scan is finished.
+    cancelled: Arc<AtomicBool>,
+    leases: RefCell<Vec<Lease>>,  // Released when the scan is finished, so that the frontends can be used again.
+    control_window: Rc<ControlWindow>,
+}
+
+impl ScanDialog {
+    /// Show what `event` says of the scan.
+    fn follow(&self, event: &ScanEvent) {
+        let mut progress = self.progress.borrow_mut();
+        if let Some(index) = progress.follow(event) {
+            if let Some(iter) = self.store.iter_nth_child(None, index as i32) {
+                self.store.set_value(&iter, 1, &progress.statuses[index].to_string().to_value());
+                self.store.set_value(&iter, 2, &progress.signals[index].to_value());
+            }
+        }
+        self.progress_bar.set_fraction(progress.scanned() as f64 / progress.statuses.len().max(1) as f64);
+        self.progress_bar.set_text(Some(&progress.text()));
+    }
+
+    /// The scan has finished: keep the signals of the multiplexes, and unless the scan was
+    /// cancelled, say what saving would do.
+    fn finish(&self, results: ScanResults) {
+        self.leases.borrow_mut().clear();
+        if self.cancelled.load(Ordering::SeqCst) {
+            info!("The scan was cancelled, the channels file is left as it was.");
+            unsafe { self.dialog.destroy(); }
+            return;
+        }
+        let path = multiplex_signals_path();
+        if let Err(e) = record_multiplex_signals(&path, &scanned_signals(&self.transponders, &results.signals, chrono::Local::now())) {
+            warn!("Could not write the multiplex signals file {}: {}", path.display(), e);
+        }
+        self.progress_bar.set_fraction(1.0);
+        *self.results.borrow_mut() = Some(results);
+        self.cancel_button.set_label("_Discard");
+        self.replace_button.show();
+        self.summary_label.show();
+        self.save_button.show();
+        self.update_summary();
+    }
+
+    /// The conflicts saving the services found is to have, as the user says.
+    fn conflicts(&self) -> Conflicts {
+        if self.replace_button.get_active() { Conflicts::Replace } else { Conflicts::Skip }
+    }
+
+    /// Say what saving the services found would do, as things are.
+    fn update_summary(&self) {
+        let results = self.results.borrow();
+        let results = match &*results {
+            Some(results) => results,
+            None => return,
+        };
+        let channels_file = channels_file_path();
+        match preview_import(&channels_file, &results.channels, self.conflicts()) {
+            Ok(import) => {
+                self.summary_label.set_text(&summary_text(results, &import));
+                self.save_button.set_sensitive(!import.added.is_empty() || !import.updated.is_empty());
+            },
+            Err(e) => {
+                self.summary_label.set_text(&format!("Could not read the channels file {}: {}", channels_file.display(), e));
+                self.save_button.set_sensitive(false);
+            },
+        }
+    }
+
+    /// Add the services found to the channels file.
+    fn save(&self) {
+        if let Some(results) = &*self.results.borrow() {
+            let channels_file = channels_file_path();
+            match import_channels(&channels_file, &results.channels, self.conflicts()) {
+                Ok(import) => if !import.added.is_empty() || !import.updated.is_empty() {
+                    read_channels_data();
+                    self.control_window.update_channels_store();
+                },
+                Err(e) => display_an_error_dialog(Some(&self.dialog), &format!("Could not write the channels file {}: {}", channels_file.display(), e)),
+            }
+        }
+    }
+
+    /// Deal with the user choosing `response`: until the scan is finished only cancelling
+    /// it, afterwards saving or discarding what it found.
+    fn respond(&self, response: gtk::ResponseType) {
+        if self.results.borrow().is_none() {
+            // The dialog goes when the scan threads have seen the cancellation and stopped.
+            self.cancelled.store(true, Ordering::SeqCst);
+            self.cancel_button.set_sensitive(false);
+            self.progress_bar.set_text(Some("Cancelling…"));
+            return;
+        }
+        if response == gtk::ResponseType::Accept {
+            self.save();
+        }
+        unsafe { self.dialog.destroy(); }
+    }
+}
+
+fn create(control_window: &Rc<ControlWindow>, transponders: Vec<Transponder>, leases: Vec<Lease>) -> Rc<ScanDialog> {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("Me TV Scan");
+    dialog.set_transient_for(Some(&control_window.window));
+    dialog.set_modal(true);
+    dialog.set_destroy_with_parent(true);
+    dialog.set_default_size(600, 500);
+    let cancel_button = dialog.add_button("_Cancel", gtk::ResponseType::Cancel).downcast::<gtk::Button>().unwrap();
+    let save_button = dialog.add_button("_Save", gtk::ResponseType::Accept).downcast::<gtk::Button>().unwrap();
+    let content_area = dialog.get_content_area();
+    content_area.set_spacing(10);
+    content_area.set_border_width(10);
+    let frontends = leases.iter().map(|lease| lease.fei().to_string()).collect::<Vec<_>>();
+    let label = gtk::Label::new(Some(&format!("Scanning {} transponders using {}.", transponders.len(), frontends.join(", "))));
+    label.set_line_wrap(true);
+    label.set_xalign(0.0);
+    content_area.pack_start(&label, false, false, 0);
+    let store = gtk::ListStore::new(&[String::static_type(), String::static_type(), String::static_type()]);
+    let progress = ScanProgress::new(transponders.len());
+    for (transponder, status) in transponders.iter().zip(&progress.statuses) {
+        store.insert_with_values(None, &[0, 1, 2], &[&transponder.to_string(), &status.to_string(), &""]);
+    }
+    let view = gtk::TreeView::with_model(&store);
+    for (column, title) in ["Multiplex", "Status", "Signal"].iter().enumerate() {
+        let renderer = gtk::CellRendererText::new();
+        let view_column = gtk::TreeViewColumn::new();
+        view_column.set_title(title);
+        view_column.pack_start(&renderer, true);
+        view_column.add_attribute(&renderer, "text", column as i32);
+        view.append_column(&view_column);
+    }
+    let scrolled_window = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    scrolled_window.add(&view);
+    content_area.pack_start(&scrolled_window, true, true, 0);
+    let progress_bar = gtk::ProgressBar::new();
+    progress_bar.set_show_text(true);
+    progress_bar.set_text(Some(&progress.text()));
+    content_area.pack_start(&progress_bar, false, false, 0);
+    let summary_label = gtk::Label::new(None);
+    summary_label.set_line_wrap(true);
+    summary_label.set_xalign(0.0);
+    summary_label.set_selectable(true);
+    content_area.pack_start(&summary_label, false, false, 0);
+    let replace_button = gtk::CheckButton::with_mnemonic("_Replace the tuning of channels already in the channels file");
+    replace_button.set_active(true);
+    content_area.pack_start(&replace_button, false, false, 0);
+    let scan_dialog = Rc::new(ScanDialog {
+        dialog: dialog.clone(),
+        store,
+        progress_bar,
+        summary_label,
+        replace_button,
+        cancel_button,
+        save_button,
+        transponders,
+        progress: RefCell::new(progress),
+        results: RefCell::new(None),
+        cancelled: Arc::new(AtomicBool::new(false)),
+        leases: RefCell::new(leases),
+        control_window: control_window.clone(),
+    });
+    scan_dialog.replace_button.connect_toggled({
+        let s_d = scan_dialog.clone();
+        move |_| s_d.update_summary()
+    });
+    dialog.connect_response({
+        let s_d = scan_dialog.clone();
+        move |_, response| s_d.respond(response)
+    });
+    // Closing the dialog whilst scanning cancels the scan, which then closes it.
+    dialog.connect_delete_event({
+        let s_d = scan_dialog.clone();
+        move |_, _| {
+            s_d.respond(gtk::ResponseType::DeleteEvent);
+            Inhibit(true)
+        }
+    });
+    dialog.show_all();
+    // Only once there is something to save.
+    scan_dialog.summary_label.hide();
+    scan_dialog.replace_button.hide();
+    scan_dialog.save_button.hide();
+    scan_dialog
+}
+
+/// Start the scan of `transponders` with the frontends of `leases`, on threads of its
+/// own, sending what it does to the dialog.
+fn start(scan_dialog: &Rc<ScanDialog>) {
+    let (to_dialog, from_scan) = glib::MainContext::channel::<Update>(glib::PRIORITY_DEFAULT);
+    from_scan.attach(None, {
+        let s_d = scan_dialog.clone();
+        move |update| match update {
+            Update::Event(event) => {
+                s_d.follow(&event);
+                Continue(true)
+            },
+            Update::Finished(results) => {
+                s_d.finish(results);
+                Continue(false)
+            },
+        }
+    });
+    let frontends = scan_dialog.leases.borrow().iter().map(|lease| lease.fei().clone()).collect::<Vec<_>>();
+    let transponders = scan_dialog.transponders.clone();
+    let cancelled = scan_dialog.cancelled.clone();
+    thread::spawn(move || {
+        let (to, from) = mpsc::channel();
+        let scanning = thread::spawn(move || scan(Arc::new(Dvbv5Scanner{lnb: None}), &frontends, &transponders, cancelled, to));
+        for event in from {
+            let _ = to_dialog.send(Update::Event(event));
+        }
+        let results = scanning.join().unwrap_or_default();
+        let _ = to_dialog.send(Update::Finished(results));
+    });
+}
+
+/// Scan `transponders` with those of `frontends` that are free, in a dialog showing how
+/// the scan goes and then offering to save the services found to the channels file.
+pub fn present(control_window: &Rc<ControlWindow>, transponders: Vec<Transponder>, frontends: &[FrontendId]) {  // Used in control_window.rs.
+    let leases = frontends.iter().filter_map(|fei| match frontend_manager::reserve_for_scanning(fei) {
+        Ok(lease) => Some(lease),
+        Err(busy) => {
+            info!("Not scanning with {}, {}.", fei, busy);
+            None
+        },
+    }).collect::<Vec<_>>();
+    if leases.is_empty() {
+        display_an_error_dialog(Some(&control_window.window), "None of the frontends is free to scan with,\nclose the channel viewers and stop the recordings using them.");
+        return;
+    }
+    let scan_dialog = create(control_window, transponders, leases);
+    start(&scan_dialog);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use me_tv::channels_file::parse_channels;
+    use me_tv::signal_monitor::{Measurement, SignalStatus};
+
+    const FEI: FrontendId = FrontendId{adapter: 0, frontend: 0};
+
+    fn reading(lock: bool) -> SignalStatus {
+        SignalStatus{fei: FEI, strength: Some(Measurement::Relative(0.72)), snr: None, ber: None, unc: None, lock}
+    }
+
+    #[test]
+    fn each_transponder_goes_from_pending_through_locking_and_reading_tables_to_done_or_failed() {
+        let mut progress = ScanProgress::new(3);
+        assert_eq!(progress.text(), "0 of 3 transponders scanned, 0 services found");
+        assert_eq!(progress.follow(&ScanEvent::Scanning{fei: FEI, transponder: 1}), Some(1));
+        assert_eq!(progress.statuses[1].to_string(), "locking on adapter0:frontend0");
+        progress.follow(&ScanEvent::Signal{transponder: 1, status: reading(false)});
+        assert_eq!(progress.statuses[1], TransponderStatus::Locking{fei: FEI});
+        assert_eq!(progress.signals[1], "no lock");
+        progress.follow(&ScanEvent::Signal{transponder: 1, status: reading(true)});
+        assert_eq!(progress.statuses[1].to_string(), "reading tables on adapter0:frontend0");
+        assert_eq!(progress.signals[1], "signal 72%, SNR unknown");
+        progress.follow(&ScanEvent::Scanned{fei: FEI, transponder: 1, services: 12, signal: Some(TransponderSignal::NoLock)});
+        assert_eq!(progress.statuses[1].to_string(), "done, 12 services");
+        progress.follow(&ScanEvent::Scanning{fei: FEI, transponder: 2});
+        progress.follow(&ScanEvent::Failed{fei: FEI, transponder: 2, reason: "Timed out".to_string(), signal: Some(TransponderSignal::NoLock)});
+        assert_eq!(progress.statuses[2].to_string(), "failed: Timed out");
+        assert_eq!(progress.signals[2], "no lock");
+        progress.follow(&ScanEvent::Scanning{fei: FEI, transponder: 0});
+        progress.follow(&ScanEvent::FrontendFailed{fei: FEI, transponder: 0, reason: "Device or resource busy".to_string()});
+        assert_eq!(progress.statuses[0], TransponderStatus::Pending);
+        assert_eq!(progress.text(), "2 of 3 transponders scanned, 12 services found");
+    }
+
+    #[test]
+    fn summary_says_what_saving_would_do_and_what_could_not_be_scanned() {
+        let channels = parse_channels("[BBC ONE Lon]
+\tSERVICE_ID = 4164
+\tDELIVERY_SYSTEM = DVBT
+\tFREQUENCY = 490000000
+[BBC TWO]
+\tSERVICE_ID = 4287
+\tDELIVERY_SYSTEM = DVBT
+\tFREQUENCY = 490000000
+").channels;
+        let results = ScanResults{channels, failed: vec![(2, "Timed out".to_string())], unscanned: vec![3], signals: Vec::new()};
+        let import = Import{added: vec!["BBC TWO".to_string()], updated: vec!["BBC ONE Lon".to_string()], ..Import::default()};
+        assert_eq!(
+            summary_text(&results, &import),
+            "The scan found 2 services: saving them adds 1 channels to the channels file and updates 1, 0 are already there as they are.\
+             \n\nTo be added: BBC TWO.\n\nTo be tuned as scanned: BBC ONE Lon.\n\nNothing was found on 1 of the transponders.\
+             \n\n1 transponders were not scanned, every frontend having failed.",
+        );
+        assert_eq!(summary_text(&ScanResults::default(), &Import::default()), "The scan found no services.");
+    }
+}
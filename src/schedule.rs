@@ -0,0 +1,363 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The recording schedule used by the me-tv-schedule dæmon.
+//!
+//! The schedule file is YAML, a sequence of jobs each giving a channel, a start
+//! date-time, either an end date-time or a duration in minutes, an output path
+//! template, and optionally the adapter to prefer and the EIT event id of the programme,
+//! for example:
+//!
+//!     - channel: BBC FOUR HD
+//!       start: 2020-10-14T21:00
+//!       duration: 60
+//!       output: /srv/recordings/{channel}_{start}.mp4
+//!       adapter: 1
+//!       event_id: 4660
+//!
+//! A job with an event id is recorded as the EIT says the programme starts and ends,
+//! within its start and end.
+//!
+//! Date-times use the same formats as the me-tv-schedule command line. The output
+//! template may use {channel} and {start}, the latter being replaced by the start
+//! date-time in YYYYMMDDThhmm form.
+//!
+//! The outcome of each job is kept in a state file so that a restart of the dæmon
+//! neither loses nor repeats jobs.
+
+use std::collections::HashMap;
+use std::fs::{File, create_dir_all};
+use std::path::Path;
+
+use chrono::{Duration, Local, NaiveDateTime, NaiveTime};
+use serde_derive::{Deserialize, Serialize};
+use serde_yaml;
+use xdg;
+
+/// Parse a date-time in one of the ISO8601 like formats accepted by me-tv-schedule.
+/// A time without a date is a time today.
+pub fn parse_to_datetime(datum: &str) -> Result<NaiveDateTime, &str> {
+    let datetime_patterns = [
+        "%Y%m%dT%H%M%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y%m%dT%H%M",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ];
+    let time_patterns= [
+        "%H%M%S",
+        "%H:%M:%S",
+        "%H%M",
+        "%H:%M",
+    ];
+    for pattern in datetime_patterns.iter() {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(datum, pattern) {
+            return Ok(datetime.into());
+        }
+    }
+    for pattern in time_patterns.iter() {
+        if let Ok(time) = NaiveTime::parse_from_str(datum, pattern) {
+            return Ok(Local::today().and_time(time).unwrap().naive_local())
+        }
+    };
+    Err(datum)
+}
+
+/// A recording job as written in the schedule file.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub channel: String,
+    pub start: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,  // Minutes.
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<u16>,
+}
+
+impl Job {
+    pub fn start_time(&self) -> Result<NaiveDateTime, String> {
+        parse_to_datetime(&self.start).map_err(|s| format!("Could not parse start time '{}'.", s))
+    }
+
+    /// Return the end time from whichever of end time and duration is given. Giving
+    /// neither, both with a conflict, or an end before the start is an error.
+    pub fn end_time(&self) -> Result<NaiveDateTime, String> {
+        let start_time = self.start_time()?;
+        let end_time = match &self.end {
+            Some(e) => Some(parse_to_datetime(e).map_err(|s| format!("Could not parse end time '{}'.", s))?),
+            None => None,
+        };
+        let end_time = match (end_time, self.duration) {
+            (Some(e), None) => e,
+            (None, Some(d)) => start_time + Duration::minutes(d.into()),
+            (Some(e), Some(d)) => {
+                if e - start_time != Duration::minutes(d.into()) {
+                    return Err("Both end time and duration were supplied but there was a conflict between them.".to_string());
+                }
+                e
+            },
+            (None, None) => return Err("Either the end time or the duration of the recording must be given.".to_string()),
+        };
+        if end_time <= start_time {
+            return Err("The end time must be after the start time.".to_string());
+        }
+        Ok(end_time)
+    }
+
+    /// Return the output path with the template placeholders replaced.
+    pub fn output_path(&self) -> Result<String, String> {
+        let start_time = self.start_time()?;
+        Ok(self.output
+            .replace("{channel}", &self.channel)
+            .replace("{start}", &start_time.format("%Y%m%dT%H%M").to_string()))
+    }
+
+    /// The identity of the job used to record its outcome in the state file.
+    pub fn key(&self) -> String {
+        format!("{}@{}", self.channel, self.start)
+    }
+
+    /// Whether this job and `other` are recording at the same time for at least a
+    /// moment. A job that is not valid overlaps nothing.
+    pub fn overlaps(&self, other: &Job) -> bool {
+        match (self.start_time(), self.end_time(), other.start_time(), other.end_time()) {
+            (Ok(start), Ok(end), Ok(other_start), Ok(other_end)) => start < other_end && other_start < end,
+            _ => false,
+        }
+    }
+
+    /// Whether this job records the programme with EIT event id `event_id` on the channel
+    /// `channel` from `start` to `end`, local times: it is one with the same event id at
+    /// about the same time, or it is recording the whole of the programme.
+    pub fn records(&self, channel: &str, event_id: Option<u16>, start: NaiveDateTime, end: NaiveDateTime) -> bool {
+        if self.channel != channel {
+            return false;
+        }
+        match (self.start_time(), self.end_time()) {
+            (Ok(job_start), Ok(job_end)) => {
+                let is_same_event = event_id.is_some() && self.event_id == event_id && job_start < end && start < job_end;
+                is_same_event || (job_start <= start && end <= job_end)
+            },
+            _ => false,
+        }
+    }
+}
+
+/// The jobs of `jobs`, other than `job` itself, recording at the same time as `job` and
+/// so needing a frontend of their own.
+pub fn conflicts<'a>(jobs: &'a [Job], job: &Job) -> Vec<&'a Job> {
+    jobs.iter().filter(|j| j.key() != job.key() && j.overlaps(job)).collect()
+}
+
+/// The outcome of a job recorded in the state file. Jobs not in the state file are pending.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum JobOutcome {
+    Running,
+    Succeeded,
+    Failed { status: Option<i32> },
+    Missed,
+    Interrupted,
+}
+
+/// Return a `Box<Path>` to the schedule file using the XDG directory structure.
+pub fn schedule_file_path() -> Box<Path> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_config_home();
+    path_buf.push("schedule.yml");
+    path_buf.into_boxed_path()
+}
+
+/// Return a `Box<Path>` to the schedule state file using the XDG directory structure.
+pub fn schedule_state_path() -> Box<Path> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_data_home();
+    path_buf.push("schedule_state.yml");
+    path_buf.into_boxed_path()
+}
+
+/// Read the jobs from the schedule file. A missing file is an empty schedule.
+pub fn read_schedule(path: &Path) -> Result<Vec<Job>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file = File::open(path).map_err(|e| format!("Could not open the schedule file {}: {}", path.display(), e))?;
+    let jobs: Option<Vec<Job>> = serde_yaml::from_reader(file).map_err(|e| format!("Could not parse the schedule file {}: {}", path.display(), e))?;
+    Ok(jobs.unwrap_or_default())
+}
+
+/// Write the jobs to the schedule file, creating the directory if needed. A dæmon
+/// already running only sees the change on being sent SIGHUP.
+pub fn write_schedule(path: &Path, jobs: &[Job]) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        create_dir_all(directory).map_err(|e| format!("Could not create {}: {}", directory.display(), e))?;
+    }
+    let file = File::create(path).map_err(|e| format!("Could not create the schedule file {}: {}", path.display(), e))?;
+    serde_yaml::to_writer(file, jobs).map_err(|e| format!("Could not write the schedule file {}: {}", path.display(), e))
+}
+
+/// Read the job outcomes from the state file. A missing or unreadable file is no outcomes.
+pub fn read_state(path: &Path) -> HashMap<String, JobOutcome> {
+    match File::open(path) {
+        Ok(file) => serde_yaml::from_reader(file).unwrap_or_default(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Write the job outcomes to the state file, creating the directory if needed.
+pub fn write_state(path: &Path, state: &HashMap<String, JobOutcome>) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        create_dir_all(directory).map_err(|e| format!("Could not create {}: {}", directory.display(), e))?;
+    }
+    let file = File::create(path).map_err(|e| format!("Could not create the state file {}: {}", path.display(), e))?;
+    serde_yaml::to_writer(file, state).map_err(|e| format!("Could not write the state file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+    use rstest::rstest;
+
+    #[rstest(
+        datum, expected,
+        case("20181229T181533", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 33)),
+        case("2018-12-29T18:15:33", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 33)),
+        case("2018-12-29 18:15:33", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 33)),
+        case("20181229T1815", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 00)),
+        case("2018-12-29T18:15", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 00)),
+        case("2018-12-29 18:15", NaiveDate::from_ymd(2018, 12, 29).and_hms(18, 15, 00)),
+        case("181513", Local::today().naive_local().and_hms(18, 15, 13)),
+        case("18:15:13", Local::today().naive_local().and_hms(18, 15, 13)),
+        case("1815", Local::today().naive_local().and_hms(18, 15, 00)),
+        case("18:15", Local::today().naive_local().and_hms(18, 15, 00)),
+    )]
+    fn parse_datetime_string(datum: &str, expected: NaiveDateTime) {
+        match parse_to_datetime(datum) {
+            Ok(result) => assert_eq!(result, expected),
+            Err(e) => assert!(false,"failed to parse: {}", e),
+        };
+    }
+
+    fn job(end: Option<&str>, duration: Option<u32>) -> Job {
+        Job {
+            channel: "BBC FOUR HD".to_string(),
+            start: "2020-10-14T21:00".to_string(),
+            end: end.map(|e| e.to_string()),
+            duration,
+            output: "/tmp/{channel}_{start}.mp4".to_string(),
+            adapter: None,
+            event_id: None,
+        }
+    }
+
+    fn job_at(channel: &str, start: &str, duration: u32, event_id: Option<u16>) -> Job {
+        Job { channel: channel.to_string(), start: start.to_string(), duration: Some(duration), event_id, ..job(None, None) }
+    }
+
+    #[test]
+    fn end_time_from_duration() {
+        assert_eq!(job(None, Some(90)).end_time(), Ok(NaiveDate::from_ymd(2020, 10, 14).and_hms(22, 30, 0)));
+    }
+
+    #[test]
+    fn end_time_from_end() {
+        assert_eq!(job(Some("2020-10-14T22:00"), None).end_time(), Ok(NaiveDate::from_ymd(2020, 10, 14).and_hms(22, 0, 0)));
+    }
+
+    #[test]
+    fn conflicting_end_and_duration_is_an_error() {
+        assert!(job(Some("2020-10-14T22:00"), Some(30)).end_time().is_err());
+    }
+
+    #[test]
+    fn neither_end_nor_duration_is_an_error() {
+        assert!(job(None, None).end_time().is_err());
+    }
+
+    #[test]
+    fn output_template_is_expanded() {
+        assert_eq!(job(None, Some(60)).output_path(), Ok("/tmp/BBC FOUR HD_20201014T2100.mp4".to_string()));
+    }
+
+    #[test]
+    fn schedule_file_is_read() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("schedule.yml");
+        std::fs::write(&path, "- channel: BBC FOUR HD
+  start: 2020-10-14T21:00
+  duration: 60
+  output: /tmp/{channel}_{start}.mp4
+").unwrap();
+        assert_eq!(read_schedule(&path), Ok(vec![job(None, Some(60))]));
+    }
+
+    #[test]
+    fn schedule_round_trips() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("me-tv").join("schedule.yml");
+        let jobs = vec![job(None, Some(60)), job_at("BBC TWO", "2020-10-14T22:28", 37, Some(0x1234))];
+        write_schedule(&path, &jobs).unwrap();
+        assert_eq!(read_schedule(&path), Ok(jobs));
+    }
+
+    #[test]
+    fn overlapping_jobs_conflict() {
+        let jobs = vec![
+            job(None, Some(60)),
+            job_at("BBC TWO", "2020-10-14T21:30", 60, None),
+            job_at("BBC THREE", "2020-10-14T22:00", 30, None),
+            job_at("BBC FOUR HD", "2020-10-14T21:00", 30, None),
+        ];
+        let new_job = job_at("BBC ONE", "2020-10-14T21:45", 15, None);
+        assert_eq!(conflicts(&jobs, &new_job), vec![&jobs[0], &jobs[1]]);
+        assert_eq!(conflicts(&jobs, &jobs[0]), vec![&jobs[1]]);
+    }
+
+    #[test]
+    fn jobs_record_the_programmes_they_cover_or_follow() {
+        let at = |hour, minute| NaiveDate::from_ymd(2020, 10, 14).and_hms(hour, minute, 0);
+        let padded = job_at("BBC FOUR HD", "2020-10-14T20:58", 67, None);
+        assert!(padded.records("BBC FOUR HD", None, at(21, 0), at(22, 0)));
+        assert!(!padded.records("BBC FOUR HD", None, at(21, 30), at(22, 30)));
+        assert!(!padded.records("BBC TWO", None, at(21, 0), at(22, 0)));
+        let following = job_at("BBC FOUR HD", "2020-10-14T20:58", 67, Some(7));
+        assert!(following.records("BBC FOUR HD", Some(7), at(21, 30), at(22, 30)));
+        assert!(!following.records("BBC FOUR HD", Some(7), at(23, 0), at(0, 0) + Duration::days(1)));
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("me-tv").join("schedule_state.yml");
+        let mut state = HashMap::new();
+        state.insert("BBC FOUR HD@2020-10-14T21:00".to_string(), JobOutcome::Failed { status: Some(75) });
+        state.insert("BBC TWO@2020-10-14T19:00".to_string(), JobOutcome::Succeeded);
+        write_state(&path, &state).unwrap();
+        assert_eq!(read_state(&path), state);
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The systemd service notification protocol, see sd_notify(3).
+//!
+//! The protocol is just datagrams of newline separated assignments sent to the
+//! socket named by $NOTIFY_SOCKET so there is no need for libsystemd. When not run
+//! by systemd the variable is not set and there is no notifier.
+
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::Duration;
+
+/// A connection to the systemd notification socket.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+}
+
+impl Notifier {
+    /// Return a notifier if $NOTIFY_SOCKET is set, i.e. if run by systemd as a
+    /// service of type notify.
+    pub fn from_environment() -> io::Result<Option<Notifier>> {
+        match env::var("NOTIFY_SOCKET") {
+            Ok(path) => Notifier::connect(&path).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Connect to the socket at `path`, a leading @ denoting an abstract socket.
+    pub fn connect(path: &str) -> io::Result<Notifier> {
+        let socket = UnixDatagram::unbound()?;
+        if let Some(name) = path.strip_prefix('@') {
+            socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?;
+        } else {
+            socket.connect(path)?;
+        }
+        Ok(Notifier { socket })
+    }
+
+    /// Send a notification, e.g. "READY=1" or "STATUS=Recording".
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket.send(state.as_bytes()).map(|_| ())
+    }
+}
+
+/// Return the watchdog interval systemd expects this process to ping within, if any.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    env::var("WATCHDOG_USEC").ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_are_sent_as_datagrams() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::connect(path.to_str().unwrap()).unwrap();
+        notifier.notify("READY=1").unwrap();
+        let mut buffer = [0u8; 64];
+        let count = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..count], b"READY=1");
+    }
+
+}
@@ -0,0 +1,100 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Parse the service descriptors of Service Description Table (SDT) sections, ETSI EN
+//! 300 468 §6.2.33, for the type of a service and the names of it and its provider.
+//!
+//! GStreamer parses the sections, but the names are decoded here, as those of the EIT
+//! are, so that they are in whichever character table the broadcaster chose.
+
+use crate::dvb_text::decode_dvb_text;
+
+/// The tag of a service descriptor.
+pub const SERVICE_DESCRIPTOR_TAG: u8 = 0x48;
+
+/// A service descriptor.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceDescriptor {
+    pub service_type: u8,
+    pub provider_name: String,
+    pub service_name: String,
+}
+
+impl ServiceDescriptor {
+    /// Whether the service is a radio one: digital radio, FM radio or advanced codec
+    /// digital radio.
+    pub fn is_radio(&self) -> bool {
+        matches!(self.service_type, 0x02 | 0x07 | 0x0a)
+    }
+}
+
+/// Parse the service descriptor `descriptor`, the tag and length then the body, None if
+/// it is not a service descriptor or is truncated.
+pub fn parse_service_descriptor(descriptor: &[u8]) -> Option<ServiceDescriptor> {
+    if descriptor.first() != Some(&SERVICE_DESCRIPTOR_TAG) {
+        return None;
+    }
+    let body = descriptor.get(2..2 + usize::from(*descriptor.get(1)?))?;
+    let service_type = *body.first()?;
+    let provider_length = usize::from(*body.get(1)?);
+    let provider_name = body.get(2..2 + provider_length)?;
+    let service_length = usize::from(*body.get(2 + provider_length)?);
+    let service_name = body.get(3 + provider_length..3 + provider_length + service_length)?;
+    Some(ServiceDescriptor {
+        service_type,
+        provider_name: decode_dvb_text(provider_name),
+        service_name: decode_dvb_text(service_name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A service descriptor of the service type, provider name and service name.
+    fn descriptor(service_type: u8, provider_name: &[u8], service_name: &[u8]) -> Vec<u8> {
+        let mut descriptor = vec![SERVICE_DESCRIPTOR_TAG, 0, service_type, provider_name.len() as u8];
+        descriptor.extend_from_slice(provider_name);
+        descriptor.push(service_name.len() as u8);
+        descriptor.extend_from_slice(service_name);
+        descriptor[1] = (descriptor.len() - 2) as u8;
+        descriptor
+    }
+
+    #[test]
+    fn names_are_decoded_in_their_character_tables() {
+        let service = parse_service_descriptor(&descriptor(0x01, b"T\xc2el\xc2e", b"\x01\xbf\xd5\xe0\xd2\xeb\xd9")).unwrap();
+        assert_eq!(service, ServiceDescriptor { service_type: 0x01, provider_name: "Télé".to_string(), service_name: "Первый".to_string() });
+        assert!(!service.is_radio());
+        let service = parse_service_descriptor(&descriptor(0x02, b"\x15BBC\xc2\xa0", b"\x15R\xc3\xa1dio 1")).unwrap();
+        assert_eq!((service.provider_name.as_str(), service.service_name.as_str()), ("BBC\u{a0}", "Rádio 1"));
+        assert!(service.is_radio());
+    }
+
+    #[test]
+    fn other_and_truncated_descriptors_are_not_parsed() {
+        assert_eq!(parse_service_descriptor(&[0x4d, 0x03, 0x01, 0x00, 0x00]), None);
+        let mut truncated = descriptor(0x01, b"BBC", b"BBC ONE");
+        truncated.truncate(9);
+        assert_eq!(parse_service_descriptor(&truncated), None);
+        assert_eq!(parse_service_descriptor(&[]), None);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_derive::Serialize;
+use serde_json;
+
+/// The formats of metadata file that can be written alongside a recording.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SidecarFormat {
+    /// A Kodi style `.nfo` file, also understood by Jellyfin.
+    Nfo,
+    Json,
+}
+
+impl SidecarFormat {
+    pub fn from_name(name: &str) -> Option<SidecarFormat> {
+        match name {
+            "nfo" => Some(SidecarFormat::Nfo),
+            "json" => Some(SidecarFormat::Json),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            SidecarFormat::Nfo => "nfo",
+            SidecarFormat::Json => "json",
+        }
+    }
+}
+
+/// What is known about a recording once it is finished.
+///
+/// Times are local times in RFC 3339 format.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RecordingMetadata {
+    pub channel: String,
+    pub start: String,
+    pub end: String,
+    pub captured_seconds: u64,
+    pub adapter: u8,
+    pub frontend: u8,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl RecordingMetadata {
+    /// A minimal Kodi movie nfo. Without a title from the EIT the channel and start time
+    /// are used so that the recording is still identifiable in a library.
+    pub fn to_nfo(&self) -> String {
+        let title = match &self.title {
+            Some(title) => title.clone(),
+            None => format!("{} {}", self.channel, self.start),
+        };
+        let mut nfo = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+        nfo.push_str(&format!("<!-- Recorded by me-tv-record on adapter {} frontend {}. -->\n", self.adapter, self.frontend));
+        nfo.push_str("<movie>\n");
+        nfo.push_str(&format!("    <title>{}</title>\n", xml_escape(&title)));
+        if let Some(description) = &self.description {
+            nfo.push_str(&format!("    <plot>{}</plot>\n", xml_escape(description)));
+        }
+        nfo.push_str(&format!("    <runtime>{}</runtime>\n", (self.captured_seconds + 30) / 60));
+        nfo.push_str(&format!("    <studio>{}</studio>\n", xml_escape(&self.channel)));
+        nfo.push_str(&format!("    <aired>{}</aired>\n", self.start.get(..10).unwrap_or(&self.start)));
+        nfo.push_str(&format!("    <dateadded>{}</dateadded>\n", self.end.get(..19).unwrap_or(&self.end).replace('T', " ")));
+        nfo.push_str("</movie>\n");
+        nfo
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Could not serialise recording metadata.")
+    }
+}
+
+/// The path of the sidecar file for a recording: the output path with its extension
+/// replaced, which is where Kodi and Jellyfin look.
+pub fn sidecar_path(output_path: &str, format: SidecarFormat) -> PathBuf {
+    Path::new(output_path).with_extension(format.extension())
+}
+
+/// Write the sidecar file for a recording, returning the path written.
+pub fn write_sidecar(output_path: &str, format: SidecarFormat, metadata: &RecordingMetadata) -> io::Result<PathBuf> {
+    let path = sidecar_path(output_path, format);
+    let contents = match format {
+        SidecarFormat::Nfo => metadata.to_nfo(),
+        SidecarFormat::Json => metadata.to_json(),
+    };
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> RecordingMetadata {
+        RecordingMetadata {
+            channel: "BBC FOUR HD".to_string(),
+            start: "2020-10-14T21:00:00+01:00".to_string(),
+            end: "2020-10-14T22:00:10+01:00".to_string(),
+            captured_seconds: 3590,
+            adapter: 0,
+            frontend: 1,
+            title: Some("Tom & Jerry <Classics>".to_string()),
+            description: Some("Cartoons.".to_string()),
+        }
+    }
+
+    #[test]
+    fn sidecar_replaces_the_extension() {
+        assert_eq!(sidecar_path("/tmp/film.mp4", SidecarFormat::Nfo), PathBuf::from("/tmp/film.nfo"));
+        assert_eq!(sidecar_path("/tmp/film.mp4", SidecarFormat::Json), PathBuf::from("/tmp/film.json"));
+    }
+
+    #[test]
+    fn nfo_has_escaped_title_and_runtime_in_minutes() {
+        let nfo = metadata().to_nfo();
+        assert!(nfo.contains("<title>Tom &amp; Jerry &lt;Classics&gt;</title>"));
+        assert!(nfo.contains("<plot>Cartoons.</plot>"));
+        assert!(nfo.contains("<runtime>60</runtime>"));
+        assert!(nfo.contains("<aired>2020-10-14</aired>"));
+        assert!(nfo.contains("<dateadded>2020-10-14 22:00:10</dateadded>"));
+    }
+
+    #[test]
+    fn nfo_without_eit_is_titled_by_channel_and_start() {
+        let metadata = RecordingMetadata { title: None, description: None, ..metadata() };
+        let nfo = metadata.to_nfo();
+        assert!(nfo.contains("<title>BBC FOUR HD 2020-10-14T21:00:00+01:00</title>"));
+        assert!(!nfo.contains("<plot>"));
+    }
+
+    #[test]
+    fn json_has_all_the_fields() {
+        let value: serde_json::Value = serde_json::from_str(&metadata().to_json()).unwrap();
+        assert_eq!(value["channel"], "BBC FOUR HD");
+        assert_eq!(value["captured_seconds"], 3590);
+        assert_eq!(value["adapter"], 0);
+        assert_eq!(value["title"], "Tom & Jerry <Classics>");
+    }
+}
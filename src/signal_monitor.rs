@@ -0,0 +1,323 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Continuous monitoring of the signal on a frontend using the DVBv5 statistics.
+//!
+//! The frontend is opened read-only and non-blocking, which the kernel allows whilst
+//! GStreamer has it open for tuning. Drivers that do not provide a statistic give None
+//! for it rather than failing, and the lock comes from FE_READ_STATUS which all drivers
+//! support.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use libc;
+
+use crate::frontend_abi::{
+    fe_read_status, get_properties, DtvProperty, DTV_STAT_CNR, DTV_STAT_ERROR_BLOCK_COUNT, DTV_STAT_POST_ERROR_BIT_COUNT,
+    DTV_STAT_POST_TOTAL_BIT_COUNT, DTV_STAT_SIGNAL_STRENGTH, FE_HAS_LOCK, FE_SCALE_COUNTER, FE_SCALE_DECIBEL, FE_SCALE_RELATIVE,
+};
+use crate::frontends::{frontend_path, FrontendId};
+
+/// The longest the monitor thread sleeps before checking whether it should stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A signal statistic, in whichever scale the driver provides.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Measurement {
+    Decibels(f64),  // dBm for signal strength, dB for signal to noise ratio.
+    Relative(f64),  // 0.0 to 1.0.
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Measurement::Decibels(value) => write!(f, "{:.1} dB", value),
+            Measurement::Relative(value) => write!(f, "{:.0}%", value * 100.0),
+        }
+    }
+}
+
+/// A reading of the signal on a frontend.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignalStatus {
+    pub fei: FrontendId,
+    pub strength: Option<Measurement>,
+    pub snr: Option<Measurement>,
+    pub ber: Option<f64>,  // Bit error rate after the inner code.
+    pub unc: Option<u64>,  // Uncorrectable blocks since tuning.
+    pub lock: bool,
+}
+
+impl SignalStatus {
+    /// A one line human readable rendering.
+    pub fn line(&self) -> String {
+        let show = |m: Option<Measurement>| m.map_or("unknown".to_string(), |m| m.to_string());
+        format!(
+            "{}: signal {}, SNR {}, BER {}, uncorrected blocks {}, {}.",
+            self.fei,
+            show(self.strength),
+            show(self.snr),
+            self.ber.map_or("unknown".to_string(), |ber| format!("{:.2e}", ber)),
+            self.unc.map_or("unknown".to_string(), |unc| unc.to_string()),
+            if self.lock { "locked" } else { "not locked" },
+        )
+    }
+}
+
+/// The readings needed of a frontend device, separated out so that the interpretation
+/// of the replies can be tested without a DVB device.
+trait StatsDevice {
+    /// The fe_status from FE_READ_STATUS.
+    fn read_status(&self) -> io::Result<u32>;
+
+    /// The replies to getting the statistics properties, in the order of `STATISTICS`.
+    fn statistics(&self) -> io::Result<Vec<DtvProperty>>;
+}
+
+/// The statistics properties read, in order.
+const STATISTICS: [u32; 5] = [
+    DTV_STAT_SIGNAL_STRENGTH,
+    DTV_STAT_CNR,
+    DTV_STAT_POST_ERROR_BIT_COUNT,
+    DTV_STAT_POST_TOTAL_BIT_COUNT,
+    DTV_STAT_ERROR_BLOCK_COUNT,
+];
+
+impl StatsDevice for File {
+    fn read_status(&self) -> io::Result<u32> {
+        let mut status = 0;
+        match unsafe { fe_read_status(self.as_raw_fd(), &mut status) } {
+            Ok(_) => Ok(status),
+            Err(_) => Err(io::Error::last_os_error()),
+        }
+    }
+
+    fn statistics(&self) -> io::Result<Vec<DtvProperty>> {
+        let mut properties = STATISTICS.iter().map(|cmd| DtvProperty::new(*cmd)).collect::<Vec<_>>();
+        get_properties(self.as_raw_fd(), &mut properties)?;
+        Ok(properties)
+    }
+}
+
+/// The measurement of a statistic, from the first, whole signal, layer.
+fn measurement(property: &DtvProperty) -> Option<Measurement> {
+    match property.stats().first() {
+        Some((FE_SCALE_DECIBEL, value)) => Some(Measurement::Decibels(*value as i64 as f64 / 1000.0)),
+        Some((FE_SCALE_RELATIVE, value)) => Some(Measurement::Relative(*value as f64 / 65535.0)),
+        _ => None,
+    }
+}
+
+/// The count of a counter statistic, from the first, whole signal, layer.
+fn count(property: &DtvProperty) -> Option<u64> {
+    match property.stats().first() {
+        Some((FE_SCALE_COUNTER, value)) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Interpret the replies of a frontend device.
+fn read_signal_status(device: &impl StatsDevice, fei: &FrontendId) -> SignalStatus {
+    let lock = device.read_status().map_or(false, |status| status & FE_HAS_LOCK != 0);
+    match device.statistics() {
+        Ok(properties) if properties.len() == STATISTICS.len() => {
+            let ber = match (count(&properties[2]), count(&properties[3])) {
+                (Some(errors), Some(total)) if total > 0 => Some(errors as f64 / total as f64),
+                _ => None,
+            };
+            SignalStatus {
+                fei: fei.clone(),
+                strength: measurement(&properties[0]),
+                snr: measurement(&properties[1]),
+                ber,
+                unc: count(&properties[4]),
+                lock,
+            }
+        },
+        _ => SignalStatus { fei: fei.clone(), strength: None, snr: None, ber: None, unc: None, lock },
+    }
+}
+
+/// Reads the signal on a frontend at an interval, sending each reading to a channel,
+/// until dropped or the receiving end of the channel is dropped.
+#[derive(Debug)]
+pub struct SignalMonitor {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SignalMonitor {
+    /// Start monitoring a frontend. Fails only if the frontend cannot be opened at all.
+    pub fn start(fei: &FrontendId, interval: Duration, to: Sender<SignalStatus>) -> io::Result<SignalMonitor> {
+        let device = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(frontend_path(fei))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let fei = fei.clone();
+            let stop = stop.clone();
+            move || {
+                while !stop.load(Ordering::SeqCst) {
+                    if to.send(read_signal_status(&device, &fei)).is_err() { break; }
+                    let next = Instant::now() + interval;
+                    while !stop.load(Ordering::SeqCst) && Instant::now() < next {
+                        thread::sleep(STOP_POLL_INTERVAL.min(interval));
+                    }
+                }
+            }
+        });
+        Ok(SignalMonitor { stop, thread: Some(thread) })
+    }
+}
+
+impl Drop for SignalMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::frontend_abi::FE_SCALE_NOT_AVAILABLE;
+
+    const FEI: FrontendId = FrontendId { adapter: 1, frontend: 0 };
+
+    struct MockFrontend {
+        status: io::Result<u32>,
+        statistics: Option<[(u8, u64); 5]>,  // None if the driver refuses.
+    }
+
+    impl StatsDevice for MockFrontend {
+        fn read_status(&self) -> io::Result<u32> {
+            match &self.status {
+                Ok(status) => Ok(*status),
+                Err(e) => Err(io::Error::from_raw_os_error(e.raw_os_error().unwrap())),
+            }
+        }
+
+        fn statistics(&self) -> io::Result<Vec<DtvProperty>> {
+            match self.statistics {
+                Some(statistics) => Ok(STATISTICS.iter().zip(statistics.iter()).map(|(cmd, stat)| {
+                    let mut property = DtvProperty::new(*cmd);
+                    property.set_stats(&[*stat]);
+                    property
+                }).collect()),
+                None => Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP)),
+            }
+        }
+    }
+
+    #[test]
+    fn decibel_statistics_are_read() {
+        let device = MockFrontend {
+            status: Ok(0x1f),
+            statistics: Some([
+                (FE_SCALE_DECIBEL, (-45_500i64) as u64),
+                (FE_SCALE_DECIBEL, 31_250),
+                (FE_SCALE_COUNTER, 20),
+                (FE_SCALE_COUNTER, 1_000_000),
+                (FE_SCALE_COUNTER, 3),
+            ]),
+        };
+        assert_eq!(read_signal_status(&device, &FEI), SignalStatus {
+            fei: FEI,
+            strength: Some(Measurement::Decibels(-45.5)),
+            snr: Some(Measurement::Decibels(31.25)),
+            ber: Some(0.00002),
+            unc: Some(3),
+            lock: true,
+        });
+    }
+
+    #[test]
+    fn relative_statistics_are_read() {
+        let device = MockFrontend {
+            status: Ok(0),
+            statistics: Some([
+                (FE_SCALE_RELATIVE, 65535),
+                (FE_SCALE_RELATIVE, 0),
+                (FE_SCALE_NOT_AVAILABLE, 0),
+                (FE_SCALE_NOT_AVAILABLE, 0),
+                (FE_SCALE_NOT_AVAILABLE, 0),
+            ]),
+        };
+        let status = read_signal_status(&device, &FEI);
+        assert_eq!(status.strength, Some(Measurement::Relative(1.0)));
+        assert_eq!(status.snr, Some(Measurement::Relative(0.0)));
+        assert_eq!(status.ber, None);
+        assert_eq!(status.unc, None);
+        assert!(!status.lock);
+    }
+
+    #[test]
+    fn no_bits_counted_gives_no_bit_error_rate() {
+        let device = MockFrontend {
+            status: Ok(FE_HAS_LOCK),
+            statistics: Some([
+                (FE_SCALE_NOT_AVAILABLE, 0),
+                (FE_SCALE_NOT_AVAILABLE, 0),
+                (FE_SCALE_COUNTER, 0),
+                (FE_SCALE_COUNTER, 0),
+                (FE_SCALE_COUNTER, 0),
+            ]),
+        };
+        assert_eq!(read_signal_status(&device, &FEI).ber, None);
+    }
+
+    #[test]
+    fn driver_refusing_statistics_still_gives_the_lock() {
+        let device = MockFrontend { status: Ok(FE_HAS_LOCK), statistics: None };
+        assert_eq!(read_signal_status(&device, &FEI), SignalStatus { fei: FEI, strength: None, snr: None, ber: None, unc: None, lock: true });
+        let device = MockFrontend { status: Err(io::Error::from_raw_os_error(libc::ENOTTY)), statistics: None };
+        assert!(!read_signal_status(&device, &FEI).lock);
+    }
+
+    #[test]
+    fn line_shows_what_is_known() {
+        let status = SignalStatus {
+            fei: FEI,
+            strength: Some(Measurement::Relative(0.61)),
+            snr: Some(Measurement::Decibels(31.3)),
+            ber: Some(0.00002),
+            unc: None,
+            lock: true,
+        };
+        assert_eq!(status.line(), "adapter1:frontend0: signal 61%, SNR 31.3 dB, BER 2.00e-5, uncorrected blocks unknown, locked.");
+    }
+
+    #[test]
+    fn monitor_cannot_start_on_a_missing_frontend() {
+        let (to, _from) = std::sync::mpsc::channel();
+        assert!(SignalMonitor::start(&FrontendId { adapter: 255, frontend: 255 }, Duration::from_secs(1), to).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Poster frames for recordings.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use gst;
+use gst::prelude::*;
+
+/// How long to wait for each step of making a thumbnail.
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The path of the thumbnail of a recording.
+pub fn thumbnail_path(output_path: &str) -> PathBuf {
+    Path::new(output_path).with_extension("jpg")
+}
+
+/// Write a JPEG of the frame `position` into the recording, or half way through if the
+/// recording is shorter than that. GStreamer must have been initialised.
+///
+/// A recording without video, e.g. of a radio channel, has no thumbnail and is an error.
+pub fn write_thumbnail(recording: &Path, thumbnail: &Path, position: Duration) -> Result<(), String> {
+    let uri = gst::filename_to_uri(recording).map_err(|e| e.to_string())?;
+    let description = format!(
+        "uridecodebin uri=\"{}\" ! videoconvert ! videoscale ! jpegenc snapshot=true ! filesink location=\"{}\"",
+        uri, thumbnail.display(),
+    );
+    let pipeline = gst::parse_launch(&description).map_err(|e| e.to_string())?;
+    let result = make_snapshot(&pipeline, position);
+    let _ = pipeline.set_state(gst::State::Null);
+    if result.is_err() {
+        let _ = fs::remove_file(thumbnail);
+    }
+    result
+}
+
+fn make_snapshot(pipeline: &gst::Element, position: Duration) -> Result<(), String> {
+    let timeout = gst::ClockTime::from_seconds(STEP_TIMEOUT.as_secs());
+    pipeline.set_state(gst::State::Paused).map_err(|_| "the recording could not be opened".to_string())?;
+    if pipeline.get_state(timeout).0.is_err() {
+        return Err("the recording could not be prerolled, it may have no video".to_string());
+    }
+    let seconds = match pipeline.query_duration::<gst::ClockTime>().and_then(|d| d.seconds()) {
+        Some(duration) if duration <= position.as_secs() => duration / 2,
+        _ => position.as_secs(),
+    };
+    pipeline
+        .seek_simple(gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT, gst::ClockTime::from_seconds(seconds))
+        .map_err(|_| format!("could not seek to {} seconds", seconds))?;
+    pipeline.set_state(gst::State::Playing).map_err(|_| "the recording could not be played".to_string())?;
+    let bus = pipeline.get_bus().expect("Pipeline without bus. Shouldn't happen!");
+    match bus.timed_pop_filtered(timeout, &[gst::MessageType::Eos, gst::MessageType::Error]) {
+        Some(msg) => match msg.view() {
+            gst::MessageView::Eos(..) => Ok(()),
+            gst::MessageView::Error(err) => Err(err.get_error().to_string()),
+            _ => unreachable!("only end of stream and error messages are popped"),
+        },
+        None => Err("timed out waiting for a frame".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile;
+
+    #[test]
+    fn thumbnail_replaces_the_extension() {
+        assert_eq!(thumbnail_path("/tmp/film.mp4"), PathBuf::from("/tmp/film.jpg"));
+    }
+
+    #[test]
+    fn missing_recording_is_an_error() {
+        gst::init().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let thumbnail = directory.path().join("film.jpg");
+        assert!(write_thumbnail(&directory.path().join("film.mp4"), &thumbnail, Duration::from_secs(60)).is_err());
+        assert!(!thumbnail.exists());
+    }
+
+    #[test]
+    fn thumbnail_of_short_recording_is_a_jpeg() {
+        gst::init().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let recording = directory.path().join("film.mp4");
+        // Skip if the needed plugins are missing.
+        let pipeline = match gst::parse_launch(&format!(
+            "videotestsrc num-buffers=100 ! x264enc ! mp4mux ! filesink location=\"{}\"", recording.display(),
+        )) {
+            Ok(pipeline) => pipeline,
+            Err(_) => return,
+        };
+        pipeline.set_state(gst::State::Playing).unwrap();
+        let bus = pipeline.get_bus().unwrap();
+        bus.timed_pop_filtered(gst::ClockTime::from_seconds(30), &[gst::MessageType::Eos, gst::MessageType::Error]);
+        pipeline.set_state(gst::State::Null).unwrap();
+        let thumbnail = directory.path().join("film.jpg");
+        write_thumbnail(&recording, &thumbnail, Duration::from_secs(60)).unwrap();
+        assert_eq!(&fs::read(&thumbnail).unwrap()[..2], &[0xff, 0xd8]);
+    }
+}
@@ -27,6 +27,7 @@ use gtk::prelude::*;
 
 use crate::dialogs::display_an_error_dialog;
 use crate::dvb;
+use crate::frontend_manager::{self, DeliverySystem};
 use crate::preferences;
 
 struct TransmitterSelector {
@@ -90,12 +91,25 @@ fn create(parent: Option<&gtk::ApplicationWindow>, transmitter_files_directory_p
     })
 }
 
+/// Warn the user if the frontends whose capabilities are known cannot receive the
+/// delivery system they have set in the preferences, since a scan would then find nothing.
+fn warn_if_delivery_system_unsupported(parent: Option<&gtk::ApplicationWindow>) {
+    let preferred = preferences::get_delivery_system();
+    if let Ok(delivery_system) = preferred.to_string().parse::<DeliverySystem>() {
+        let known = frontend_manager::delivery_systems();
+        if !known.is_empty() && !known.values().any(|delivery_systems| delivery_systems.contains(&delivery_system)) {
+            display_an_error_dialog(parent, &format!("None of the frontends appear to support {},\nthe scan may find no channels.", delivery_system));
+        }
+    }
+}
+
 /// Present a dialog to the user to allow them to select the transmitter file to
 /// use to scan to create a channels file.
 ///
 /// Returns an `Option` with a `Box<Path>` on success. If there are problems finding a
 /// transmitter file, tells the user via a message dialog and returns `None`.
 pub fn present(parent: Option<&gtk::ApplicationWindow>) -> Option<Box<path::Path>> {
+    warn_if_delivery_system_unsupported(parent);
     match dvbt_transmitter_files_directory_path() {
         Some(transmitter_files_directory_path) =>  match create(parent, transmitter_files_directory_path.as_path()) {
             Some(dialog) => {
@@ -0,0 +1,573 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! XMLTV listings, as written by the grabbers of the XMLTV project, for filling in the
+//! schedule where the EIT has little or nothing, and the matching of their channels to
+//! the channels of the channels file.
+//!
+//! An XMLTV file is a `tv` element of `channel` and `programme` elements, a simple
+//! enough use of XML that it is scanned here rather than parsed with an XML library.
+//!
+//! The programme guide can also be exported as an XMLTV file, for other programs to use.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+use crate::eit::{EventText, RunningStatus};
+use crate::epg::Programme;
+use crate::languages::xmltv_code;
+use crate::name_matching::fold;
+
+/// A channel of the listings.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmltvChannel {
+    pub id: String,
+    pub display_names: Vec<String>,
+}
+
+/// A programme of the listings.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmltvProgramme {
+    pub channel: String,  // The id of the channel.
+    pub start: NaiveDateTime,  // UTC.
+    pub stop: Option<NaiveDateTime>,  // UTC, not always given.
+    pub title: String,
+    pub description: String,
+    pub categories: Vec<String>,
+}
+
+/// The channels and programmes of an XMLTV file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Listings {
+    pub channels: Vec<XmltvChannel>,
+    pub programmes: Vec<XmltvProgramme>,
+}
+
+/// A piece of an XML document. An empty element is a start followed by an end.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Start { name: String, attributes: Vec<(String, String)> },
+    End(String),
+    Text(String),
+}
+
+/// XML text with its character and entity references replaced. A reference that is
+/// not one is left as it is.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(position) = rest.find('&') {
+        unescaped.push_str(&rest[..position]);
+        rest = &rest[position..];
+        let reference = rest.find(';').map(|end| (&rest[1..end], end));
+        let character = reference.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if name.starts_with("#x") => u32::from_str_radix(&name[2..], 16).ok().and_then(std::char::from_u32),
+            _ if name.starts_with('#') => name[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None,
+        });
+        match (character, reference) {
+            (Some(c), Some((_, end))) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            },
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Where the `>` ending the tag at the start of `text` is, one in an attribute value
+/// not counting.
+fn tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return Some(i),
+            _ => {},
+        }
+    }
+    None
+}
+
+/// The name and attributes of the inside of a start tag.
+fn parse_tag(tag: &str) -> Result<(String, Vec<(String, String)>), String> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let name = tag[..name_end].to_string();
+    let mut attributes = Vec::new();
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let equals = rest.find('=').ok_or_else(|| format!("An attribute of <{}> has no value.", name))?;
+        let attribute = rest[..equals].trim().to_string();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| format!("The {} attribute of <{}> is not quoted.", attribute, name))?;
+        let end = value[1..].find(quote).ok_or_else(|| format!("The {} attribute of <{}> is not terminated.", attribute, name))? + 1;
+        attributes.push((attribute, unescape(&value[1..end])));
+        rest = value[end + 1..].trim_start();
+    }
+    Ok((name, attributes))
+}
+
+/// The tokens of an XML document, leaving out the declarations and comments, and the
+/// text that is only white space.
+fn tokens(document: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = document;
+    while !rest.is_empty() {
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").ok_or("A comment is not terminated.")?;
+            rest = &rest[end + 3..];
+        } else if rest.starts_with("<![CDATA[") {
+            let end = rest.find("]]>").ok_or("A CDATA section is not terminated.")?;
+            tokens.push(Token::Text(rest[9..end].to_string()));
+            rest = &rest[end + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = rest.find('>').ok_or("A declaration is not terminated.")?;
+            rest = &rest[end + 1..];
+        } else if rest.starts_with("</") {
+            let end = rest.find('>').ok_or("An end tag is not terminated.")?;
+            tokens.push(Token::End(rest[2..end].trim().to_string()));
+            rest = &rest[end + 1..];
+        } else if rest.starts_with('<') {
+            let end = tag_end(rest).ok_or("A start tag is not terminated.")?;
+            let is_empty = rest[..end].ends_with('/');
+            let (name, attributes) = parse_tag(&rest[1..if is_empty { end - 1 } else { end }])?;
+            if is_empty {
+                tokens.push(Token::Start { name: name.clone(), attributes });
+                tokens.push(Token::End(name));
+            } else {
+                tokens.push(Token::Start { name, attributes });
+            }
+            rest = &rest[end + 1..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            if !rest[..end].trim().is_empty() {
+                tokens.push(Token::Text(unescape(&rest[..end])));
+            }
+            rest = &rest[end..];
+        }
+    }
+    Ok(tokens)
+}
+
+/// An XMLTV time, `YYYYMMDDhhmmss +hhmm`, the seconds and the time zone being optional,
+/// as UTC. A time with no time zone is taken to be UTC.
+fn parse_time(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim();
+    let (digits, zone) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    };
+    let number = |from: usize, to: usize| digits.get(from..to).and_then(|d| d.parse::<u32>().ok());
+    let date = NaiveDate::from_ymd_opt(number(0, 4)? as i32, number(4, 6)?, number(6, 8)?)?;
+    let seconds = if digits.len() >= 14 { number(12, 14)? } else { 0 };
+    let time = date.and_hms_opt(number(8, 10)?, number(10, 12)?, seconds)?;
+    let offset_minutes = if zone.is_empty() || zone == "UTC" || zone == "GMT" {
+        0
+    } else {
+        let sign = match zone.get(..1) {
+            Some("+") => 1,
+            Some("-") => -1,
+            _ => return None,
+        };
+        let hours = zone.get(1..3)?.parse::<i64>().ok()?;
+        let minutes = zone.get(3..5)?.parse::<i64>().ok()?;
+        sign * (hours * 60 + minutes)
+    };
+    Some(time - Duration::minutes(offset_minutes))
+}
+
+/// Parse an XMLTV file. Programmes with no start time that can be understood are left
+/// out, as are all but the first title and description of a programme. The categories
+/// of a programme are all kept, but each only once.
+pub fn parse_xmltv(document: &str) -> Result<Listings, String> {
+    let mut listings = Listings::default();
+    let mut channel: Option<XmltvChannel> = None;
+    let mut programme: Option<XmltvProgramme> = None;
+    let mut text = String::new();
+    for token in tokens(document)? {
+        match token {
+            Token::Start { name, attributes } => {
+                let attribute = |key: &str| attributes.iter().find(|(k, _)| k == key).map(|(_, value)| value.clone());
+                match name.as_str() {
+                    "channel" => channel = Some(XmltvChannel { id: attribute("id").unwrap_or_default(), display_names: Vec::new() }),
+                    "programme" => programme = attribute("start").and_then(|start| parse_time(&start)).map(|start| XmltvProgramme {
+                        channel: attribute("channel").unwrap_or_default(),
+                        start,
+                        stop: attribute("stop").and_then(|stop| parse_time(&stop)),
+                        title: String::new(),
+                        description: String::new(),
+                        categories: Vec::new(),
+                    }),
+                    _ => {},
+                }
+                text.clear();
+            },
+            Token::Text(t) => text.push_str(&t),
+            Token::End(name) => {
+                let text = text.split_off(0).trim().to_string();
+                match (name.as_str(), channel.as_mut(), programme.as_mut()) {
+                    ("display-name", Some(channel), _) => channel.display_names.push(text),
+                    ("title", _, Some(programme)) if programme.title.is_empty() => programme.title = text,
+                    ("desc", _, Some(programme)) if programme.description.is_empty() => programme.description = text,
+                    ("category", _, Some(programme)) if !text.is_empty() && !programme.categories.contains(&text) => programme.categories.push(text),
+                    ("channel", _, _) => listings.channels.extend(channel.take()),
+                    ("programme", _, _) => listings.programmes.extend(programme.take()),
+                    _ => {},
+                }
+            },
+        }
+    }
+    Ok(listings)
+}
+
+/// Read and parse the XMLTV file at `path`.
+pub fn read_xmltv(path: &Path) -> io::Result<Listings> {
+    parse_xmltv(&fs::read_to_string(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// A channel name as names are matched: folded, and only the letters and digits, so that
+/// "BBC One" matches "bbc-one" and "Télé 5" matches "TELE5".
+fn normalised(name: &str) -> String {
+    fold(name).chars().filter(|c| c.is_alphanumeric()).collect()
+}
+
+/// The service ids of the channels of the channels file the XMLTV channels are, by
+/// XMLTV channel id, given the (name, service id) pairs of those channels. An XMLTV
+/// channel with a channel name in `configured`, by XMLTV channel id, is that channel, an
+/// empty name meaning it is none of them. Otherwise it is the channel whose name is one
+/// of its display names, the first such, ignoring case, accents, spaces and punctuation.
+/// XMLTV channels that are no channel are left out.
+pub fn map_channels(xmltv_channels: &[XmltvChannel], channels: &[(String, u16)], configured: &BTreeMap<String, String>) -> HashMap<String, u16> {
+    xmltv_channels.iter()
+        .filter_map(|xmltv_channel| {
+            let service_id = match configured.get(&xmltv_channel.id) {
+                Some(name) => channels.iter().find(|(n, _)| n == name).map(|(_, service_id)| *service_id),
+                None => xmltv_channel.display_names.iter().map(|name| normalised(name)).find_map(|display_name| {
+                    channels.iter().find(|(n, _)| normalised(n) == display_name).map(|(_, service_id)| *service_id)
+                }),
+            };
+            service_id.map(|service_id| (xmltv_channel.id.clone(), service_id))
+        })
+        .collect()
+}
+
+/// The programmes of the listings on the channels of `mapping`, XMLTV channel id to
+/// service id, by service id in order of start. A programme with no stop time ends when
+/// the next programme on its channel starts, so the last such is left out.
+pub fn programmes_by_service(listings: &Listings, mapping: &HashMap<String, u16>) -> HashMap<u16, Vec<Programme>> {
+    let mut by_channel = BTreeMap::<&str, Vec<&XmltvProgramme>>::new();
+    for programme in &listings.programmes {
+        by_channel.entry(programme.channel.as_str()).or_default().push(programme);
+    }
+    let mut by_service = HashMap::<u16, Vec<Programme>>::new();
+    for (channel, mut programmes) in by_channel {
+        let service_id = match mapping.get(channel) {
+            Some(service_id) => *service_id,
+            None => continue,
+        };
+        programmes.sort_by_key(|p| p.start);
+        let service_programmes = by_service.entry(service_id).or_default();
+        for (i, programme) in programmes.iter().enumerate() {
+            let stop = programme.stop.or_else(|| programmes.get(i + 1).map(|next| next.start));
+            if let Some(stop) = stop.filter(|stop| *stop > programme.start) {
+                service_programmes.push(Programme {
+                    event_id: None,
+                    start: Some(programme.start),
+                    duration_seconds: (stop - programme.start).num_seconds() as u32,
+                    running_status: RunningStatus::Undefined,
+                    title: programme.title.clone(),
+                    description: programme.description.clone(),
+                    extended_description: String::new(),
+                    language: String::new(),
+                    texts: Vec::new(),
+                    genres: programme.categories.clone(),
+                    ratings: Vec::new(),
+                    programme_crid: None,
+                    series_crid: None,
+                });
+            }
+        }
+        service_programmes.sort_by_key(|p| p.start);
+    }
+    by_service
+}
+
+/// Text escaped for use in an element or an attribute value.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A time, UTC, as an XMLTV time.
+fn format_time(time: NaiveDateTime) -> String {
+    time.format("%Y%m%d%H%M%S +0000").to_string()
+}
+
+/// The id of the XMLTV channel of the service `service_id`.
+fn channel_id(service_id: u16) -> String {
+    format!("{}.me-tv", service_id)
+}
+
+/// The XMLTV document of the programmes `programmes`, by service id, of the channels
+/// `channels`, (name, service id) pairs, in order. A service is the first of the
+/// channels with its service id, and programmes with no start are left out. A programme
+/// has a `title` and a `desc` in each language it is in, with the language as the `lang`,
+/// the description and extended description being one `desc`, each genre is a
+/// `category`, the programme and series CRIDs are `episode-num`s of the systems "crid"
+/// and "series-crid", and each parental rating that is a minimum age is a `rating` of
+/// the country.
+pub fn export_xmltv(channels: &[(String, u16)], programmes: &HashMap<u16, Vec<Programme>>) -> String {
+    let mut document = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE tv SYSTEM \"xmltv.dtd\">\n<tv generator-info-name=\"Me TV\">\n".to_string();
+    let mut service_ids = Vec::new();
+    for (name, service_id) in channels {
+        if !service_ids.contains(service_id) {
+            service_ids.push(*service_id);
+            document.push_str(&format!("  <channel id=\"{}\">\n    <display-name>{}</display-name>\n  </channel>\n", channel_id(*service_id), escape(name)));
+        }
+    }
+    for service_id in service_ids {
+        for programme in programmes.get(&service_id).map_or(&[][..], |programmes| programmes.as_slice()) {
+            let (start, end) = match (programme.start, programme.end()) {
+                (Some(start), Some(end)) => (start, end),
+                _ => continue,
+            };
+            document.push_str(&format!("  <programme start=\"{}\" stop=\"{}\" channel=\"{}\">\n", format_time(start), format_time(end), channel_id(service_id)));
+            let texts = if programme.texts.iter().any(|text| !text.title.is_empty()) {
+                programme.texts.clone()
+            } else {
+                vec![EventText {
+                    language: programme.language.clone(),
+                    title: programme.title.clone(),
+                    description: programme.description.clone(),
+                    extended_description: programme.extended_description.clone(),
+                }]
+            };
+            let lang = |language: &str| if language.is_empty() { String::new() } else { format!(" lang=\"{}\"", escape(&xmltv_code(language))) };
+            for text in texts.iter().filter(|text| !text.title.is_empty() || texts.len() == 1) {
+                document.push_str(&format!("    <title{}>{}</title>\n", lang(&text.language), escape(&text.title)));
+            }
+            for text in &texts {
+                let description = [&text.description, &text.extended_description].iter()
+                    .filter(|text| !text.is_empty())
+                    .map(|text| text.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if !description.is_empty() {
+                    document.push_str(&format!("    <desc{}>{}</desc>\n", lang(&text.language), escape(&description)));
+                }
+            }
+            for genre in &programme.genres {
+                document.push_str(&format!("    <category>{}</category>\n", escape(genre)));
+            }
+            for (system, crid) in &[("crid", &programme.programme_crid), ("series-crid", &programme.series_crid)] {
+                if let Some(crid) = crid {
+                    document.push_str(&format!("    <episode-num system=\"{}\">{}</episode-num>\n", system, escape(crid)));
+                }
+            }
+            for rating in &programme.ratings {
+                if let Some(age) = rating.minimum_age() {
+                    document.push_str(&format!("    <rating system=\"{}\">\n      <value>{}</value>\n    </rating>\n", escape(&rating.country), age));
+                }
+            }
+            document.push_str("  </programme>\n");
+        }
+    }
+    document.push_str("</tv>\n");
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::eit::ParentalRating;
+
+    const LISTINGS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE tv SYSTEM "xmltv.dtd">
+<tv generator-info-name="tv_grab_uk_freeview">
+  <!-- BBC One, and a channel not in the channels file. -->
+  <channel id="bbc1.london.freeview.uk">
+    <display-name lang="en">BBC One Lon</display-name>
+    <display-name>101</display-name>
+  </channel>
+  <channel id="film4.freeview.uk"><display-name>Film4</display-name></channel>
+  <channel id="empty.freeview.uk"/>
+  <programme start="20201014213000 +0100" stop="20201014221500 +0100" channel="bbc1.london.freeview.uk">
+    <title lang="en">Newsnight</title>
+    <title lang="cy">Newyddion</title>
+    <desc lang="en">The stories behind the headlines &amp; more&#x2026;</desc>
+    <category lang="en">News</category>
+    <category lang="en">Current affairs</category>
+    <category lang="cy">News</category>
+  </programme>
+  <programme start="20201014221500 +0100" channel="bbc1.london.freeview.uk">
+    <title><![CDATA[Question Time & Co]]></title>
+  </programme>
+  <programme start="202010142315" channel="bbc1.london.freeview.uk"><title>Weather</title></programme>
+  <programme start="tomorrow" channel="bbc1.london.freeview.uk"><title>Unknown</title></programme>
+  <programme start='20201014210000 -0000' stop='20201014230000 -0000' channel='film4.freeview.uk'><title>Film</title></programme>
+</tv>
+"#;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 10, 14).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn listings_are_parsed() {
+        let listings = parse_xmltv(LISTINGS).unwrap();
+        assert_eq!(listings.channels, vec![
+            XmltvChannel { id: "bbc1.london.freeview.uk".to_string(), display_names: vec!["BBC One Lon".to_string(), "101".to_string()] },
+            XmltvChannel { id: "film4.freeview.uk".to_string(), display_names: vec!["Film4".to_string()] },
+            XmltvChannel { id: "empty.freeview.uk".to_string(), display_names: Vec::new() },
+        ]);
+        assert_eq!(listings.programmes.len(), 4);
+        assert_eq!(listings.programmes[0], XmltvProgramme {
+            channel: "bbc1.london.freeview.uk".to_string(),
+            start: at(20, 30),
+            stop: Some(at(21, 15)),
+            title: "Newsnight".to_string(),
+            description: "The stories behind the headlines & more…".to_string(),
+            categories: vec!["News".to_string(), "Current affairs".to_string()],
+        });
+        assert_eq!(listings.programmes[1].title, "Question Time & Co");
+        assert_eq!(listings.programmes[1].stop, None);
+        assert_eq!(listings.programmes[2].start, at(23, 15));
+        assert_eq!(listings.programmes[3].channel, "film4.freeview.uk");
+    }
+
+    #[test]
+    fn malformed_listings_are_rejected() {
+        assert!(parse_xmltv("<tv><channel id=\"x\"").is_err());
+        assert!(parse_xmltv("<tv><channel id=x></channel></tv>").is_err());
+        assert!(parse_xmltv("<tv><!-- unterminated </tv>").is_err());
+    }
+
+    #[test]
+    fn channels_are_mapped_by_configuration_then_display_name() {
+        let listings = parse_xmltv(LISTINGS).unwrap();
+        let channels = vec![("BBC ONE Lon".to_string(), 4164), ("Film 4".to_string(), 8384), ("Film4+1".to_string(), 8385)];
+        let mapping = map_channels(&listings.channels, &channels, &BTreeMap::new());
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(mapping["bbc1.london.freeview.uk"], 4164);
+        assert_eq!(mapping["film4.freeview.uk"], 8384);
+        let configured = vec![
+            ("film4.freeview.uk".to_string(), "Film4+1".to_string()),
+            ("bbc1.london.freeview.uk".to_string(), String::new()),
+            ("empty.freeview.uk".to_string(), "Dave".to_string()),
+        ].into_iter().collect();
+        let mapping = map_channels(&listings.channels, &channels, &configured);
+        assert_eq!(mapping.into_iter().collect::<Vec<_>>(), vec![("film4.freeview.uk".to_string(), 8385)]);
+    }
+
+    #[test]
+    fn programmes_without_a_stop_end_at_the_next_start() {
+        let listings = parse_xmltv(LISTINGS).unwrap();
+        let mapping = vec![("bbc1.london.freeview.uk".to_string(), 4164)].into_iter().collect();
+        let by_service = programmes_by_service(&listings, &mapping);
+        assert_eq!(by_service.len(), 1);
+        let programmes = &by_service[&4164];
+        assert_eq!(programmes.iter().map(|p| p.title.as_str()).collect::<Vec<_>>(), vec!["Newsnight", "Question Time & Co"]);
+        assert_eq!(programmes[1].start, Some(at(21, 15)));
+        assert_eq!(programmes[1].end(), Some(at(23, 15)));
+        assert_eq!(programmes[0].event_id, None);
+        assert_eq!(programmes[0].genres, vec!["News".to_string(), "Current affairs".to_string()]);
+    }
+
+    #[test]
+    fn exported_programmes_can_be_read_back() {
+        let listings = parse_xmltv(LISTINGS).unwrap();
+        let mapping = vec![("bbc1.london.freeview.uk".to_string(), 4164)].into_iter().collect();
+        let mut by_service = programmes_by_service(&listings, &mapping);
+        by_service.get_mut(&4164).unwrap()[1].genres = vec!["Show/Game show".to_string(), "Talk show".to_string()];
+        by_service.get_mut(&4164).unwrap()[1].ratings = vec![
+            ParentalRating { country: "GBR".to_string(), rating: 0x09 },
+            ParentalRating { country: "IRL".to_string(), rating: 0x12 },
+        ];
+        let channels = vec![("BBC ONE Lon".to_string(), 4164), ("BBC ONE Lon".to_string(), 4164), ("Film 4".to_string(), 8384)];
+        by_service.get_mut(&4164).unwrap()[1].series_crid = Some("/KFNVXV".to_string());
+        let document = export_xmltv(&channels, &by_service);
+        assert!(document.contains("    <episode-num system=\"series-crid\">/KFNVXV</episode-num>\n    <rating"));
+        assert!(!document.contains("system=\"crid\""));
+        assert!(document.contains("    <rating system=\"GBR\">\n      <value>12</value>\n    </rating>\n  </programme>"));
+        assert!(!document.contains("IRL"));
+        let exported = parse_xmltv(&document).unwrap();
+        assert_eq!(exported.channels, vec![
+            XmltvChannel { id: "4164.me-tv".to_string(), display_names: vec!["BBC ONE Lon".to_string()] },
+            XmltvChannel { id: "8384.me-tv".to_string(), display_names: vec!["Film 4".to_string()] },
+        ]);
+        assert_eq!(exported.programmes.len(), 2);
+        assert_eq!(exported.programmes[0], XmltvProgramme {
+            channel: "4164.me-tv".to_string(),
+            start: at(20, 30),
+            stop: Some(at(21, 15)),
+            title: "Newsnight".to_string(),
+            description: "The stories behind the headlines & more…".to_string(),
+            categories: vec!["News".to_string(), "Current affairs".to_string()],
+        });
+        assert_eq!(exported.programmes[1].title, "Question Time & Co");
+        assert_eq!(exported.programmes[1].categories, vec!["Show/Game show".to_string(), "Talk show".to_string()]);
+    }
+
+    #[test]
+    fn programmes_are_exported_in_each_language() {
+        let listings = parse_xmltv(LISTINGS).unwrap();
+        let mapping = vec![("bbc1.london.freeview.uk".to_string(), 4164)].into_iter().collect();
+        let mut by_service = programmes_by_service(&listings, &mapping);
+        let programmes = by_service.get_mut(&4164).unwrap();
+        programmes[0].language = "eng".to_string();
+        programmes[1].texts = vec![
+            EventText { language: "cym".to_string(), title: "Pawb a'i Farn".to_string(), description: "Trafodaeth.".to_string(), ..EventText::default() },
+            EventText { language: "eng".to_string(), title: "Question Time".to_string(), ..EventText::default() },
+        ];
+        let document = export_xmltv(&[("BBC ONE Lon".to_string(), 4164)], &by_service);
+        assert!(document.contains("    <title lang=\"en\">Newsnight</title>\n    <desc lang=\"en\">"));
+        assert!(document.contains("    <title lang=\"cy\">Pawb a&apos;i Farn</title>\n    <title lang=\"en\">Question Time</title>\n    <desc lang=\"cy\">Trafodaeth.</desc>\n"));
+        assert!(!document.contains("Question Time &amp; Co"));
+    }
+}